@@ -0,0 +1,116 @@
+use std::process::Command;
+
+use anyhow::anyhow;
+
+use crate::config::Config;
+use crate::repository::common_dir;
+use crate::trace;
+use crate::trace::TRACE;
+
+/// What the command line comes to once aliases are expanded.
+#[derive(Debug)]
+pub enum Expansion {
+    /// The arguments to parse, with the alias replaced by its expansion.
+    Args(Vec<String>),
+    /// A `!` alias ran in the shell and exited with this status.
+    Shell(u8),
+}
+
+/// Splits an alias into words like git does: single quotes keep
+/// everything, double quotes and backslashes work like in the shell.
+fn split_command_line(line: &str) -> Option<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut quote = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (None, c) if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                in_word = true;
+            }
+            (Some(open), c) if c == open => quote = None,
+            (None | Some('"'), '\\') => {
+                word.push(chars.next()?);
+                in_word = true;
+            }
+            (_, c) => {
+                word.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if quote.is_some() {
+        return None;
+    }
+    if in_word {
+        words.push(word);
+    }
+    Some(words)
+}
+
+/// Expands the alias in `args`, the program name first, if the command
+/// isn't built in: `alias.<name>` from config replaces it, and the rest
+/// of the arguments are kept after the expansion. A `!` alias runs in
+/// the shell with the rest as its arguments instead.
+pub fn expand(mut args: Vec<String>, is_builtin: impl Fn(&str) -> bool) -> anyhow::Result<Expansion> {
+    // Global options come before the command.
+    let Some(index) = args.iter().skip(1).position(|arg| !arg.starts_with('-')).map(|index| index + 1) else {
+        return Ok(Expansion::Args(args));
+    };
+    let config = Config::layered(&common_dir()?.join("config"))?;
+
+    let mut seen: Vec<String> = Vec::new();
+    loop {
+        let name = args[index].clone();
+        if is_builtin(&name) {
+            return Ok(Expansion::Args(args));
+        }
+        if let Some(start) = seen.iter().position(|seen| *seen == name) {
+            let mut message = format!("alias loop detected: expansion of '{}' does not terminate:", seen[0]);
+            for (i, alias) in seen.iter().enumerate() {
+                let marker = match i {
+                    i if i == start => " <==",
+                    i if i == seen.len() - 1 => " ==>",
+                    _ => "",
+                };
+                message.push_str(&format!("\n  {}{}", alias, marker));
+            }
+            return Err(anyhow!(message));
+        }
+        let Some(value) = config.get(&format!("alias.{}", name)) else {
+            return Ok(Expansion::Args(args));
+        };
+
+        if let Some(command) = value.strip_prefix('!') {
+            let rest = &args[index + 1..];
+            trace!(TRACE, "alias expansion: {} => {}", name, value);
+            trace!(TRACE, "run_command: {} {}", command, rest.join(" "));
+            let script = if rest.is_empty() { command.to_string() } else { format!("{} \"$@\"", command) };
+            let status = Command::new("sh")
+                .arg("-c")
+                .arg(script)
+                .arg(command)
+                .args(rest)
+                .env("GIT_PREFIX", "")
+                .status()
+                .map_err(|err| anyhow!("while expanding alias '{}': '{}': {}", name, command, err))?;
+            return Ok(Expansion::Shell(status.code().unwrap_or(1) as u8));
+        }
+
+        let words = split_command_line(value).ok_or(anyhow!("bad alias.{} string: unclosed quote", name))?;
+        if words.is_empty() {
+            return Err(anyhow!("empty alias for {}", name));
+        }
+        trace!(TRACE, "alias expansion: {} => {}", name, words.join(" "));
+        args.splice(index..=index, words);
+        seen.push(name);
+    }
+}
@@ -0,0 +1,154 @@
+use std::fs;
+use std::path::Path;
+
+use crate::wildmatch::wildmatch;
+
+/// The state of a single attribute for a path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AttrValue {
+    /// `attr`
+    Set,
+    /// `-attr`
+    Unset,
+    /// `attr=value`
+    Value(String),
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+    // Directory of the `.gitattributes` file the rule came from, relative to
+    // the worktree root and ending in `/` unless it is the root itself.
+    base: String,
+    pattern: String,
+    // `None` for `!attr`, which resets the attribute to unspecified.
+    attrs: Vec<(String, Option<AttrValue>)>,
+}
+
+impl Rule {
+    fn matches(&self, path: &str) -> bool {
+        let Some(relative) = path.strip_prefix(self.base.as_str()) else {
+            return false;
+        };
+
+        // Like gitignore, patterns without a slash match the basename at any
+        // depth, and patterns with one are anchored at the file's directory.
+        match self.pattern.strip_prefix('/') {
+            Some(anchored) => wildmatch(anchored, relative, true),
+            None if self.pattern.contains('/') => wildmatch(&self.pattern, relative, true),
+            None => {
+                let name = relative.rsplit('/').next().unwrap_or(relative);
+                wildmatch(&self.pattern, name, true)
+            }
+        }
+    }
+}
+
+/// The attribute rules in effect for a set of paths, see gitattributes(5).
+#[derive(Debug, Clone, Default)]
+pub struct Attributes {
+    // Ordered from lowest to highest precedence: outer directories first.
+    rules: Vec<Rule>,
+    // `$GIT_DIR/info/attributes` beats every `.gitattributes` file.
+    info: Vec<Rule>,
+}
+
+impl Attributes {
+    /// Starts with the repository-wide `info/attributes` rules.
+    pub fn load(git_dir: &Path) -> anyhow::Result<Attributes> {
+        let mut attributes = Attributes::default();
+        if let Ok(content) = fs::read_to_string(git_dir.join("info/attributes")) {
+            attributes.info = parse("", &content);
+        }
+
+        Ok(attributes)
+    }
+
+    /// Loads the `info/attributes` rules plus every `.gitattributes` file
+    /// from the worktree root down to `directory`.
+    pub fn load_for(git_dir: &Path, directory: &str) -> anyhow::Result<Attributes> {
+        let mut attributes = Attributes::load(git_dir)?;
+
+        let mut base = String::new();
+        let components = directory.split('/').filter(|component| !component.is_empty());
+        for component in std::iter::once("").chain(components) {
+            if !component.is_empty() {
+                base.push_str(component);
+                base.push('/');
+            }
+            if let Ok(content) = fs::read_to_string(Path::new(".").join(&base).join(".gitattributes")) {
+                attributes.push_file(&base, &content);
+            }
+        }
+
+        Ok(attributes)
+    }
+
+    /// Adds the rules of a `.gitattributes` file found in directory `base`,
+    /// which take precedence over those added before.
+    pub fn push_file(&mut self, base: &str, content: &str) {
+        let base = if base.is_empty() || base.ends_with('/') {
+            base.to_string()
+        } else {
+            format!("{}/", base)
+        };
+        self.rules.extend(parse(&base, content));
+    }
+
+    /// Returns the value of attribute `name` for `path`, `None` if it
+    /// is unspecified.
+    pub fn get(&self, path: &str, name: &str) -> Option<&AttrValue> {
+        self.info.iter()
+            .rev()
+            .chain(self.rules.iter().rev())
+            .filter(|rule| rule.matches(path))
+            .find_map(|rule| rule.attrs.iter().rev().find(|(attr, _)| attr == name))
+            .and_then(|(_, value)| value.as_ref())
+    }
+}
+
+fn parse(base: &str, content: &str) -> Vec<Rule> {
+    let mut rules = Vec::new();
+
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut fields = line.split_whitespace();
+        let Some(pattern) = fields.next() else {
+            continue;
+        };
+        // Macro definitions and negative patterns aren't supported.
+        if pattern.starts_with("[attr]") || pattern.starts_with('!') {
+            continue;
+        }
+        // Attributes only apply to files, so directory patterns never match.
+        if pattern.ends_with('/') {
+            continue;
+        }
+
+        let mut attrs = Vec::new();
+        for field in fields {
+            if field == "binary" {
+                // The built-in `binary` macro.
+                for attr in ["diff", "merge", "text"] {
+                    attrs.push((attr.to_string(), Some(AttrValue::Unset)));
+                }
+                attrs.push(("binary".to_string(), Some(AttrValue::Set)));
+            } else if let Some(attr) = field.strip_prefix('-') {
+                attrs.push((attr.to_string(), Some(AttrValue::Unset)));
+            } else if let Some(attr) = field.strip_prefix('!') {
+                attrs.push((attr.to_string(), None));
+            } else if let Some((attr, value)) = field.split_once('=') {
+                attrs.push((attr.to_string(), Some(AttrValue::Value(value.to_string()))));
+            } else {
+                attrs.push((field.to_string(), Some(AttrValue::Set)));
+            }
+        }
+
+        rules.push(Rule { base: base.to_string(), pattern: pattern.to_string(), attrs });
+    }
+
+    rules
+}
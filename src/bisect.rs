@@ -0,0 +1,279 @@
+use std::collections::HashSet;
+use std::fs;
+use std::process::Command;
+
+use anyhow::anyhow;
+
+use crate::info;
+use crate::object_id::ObjectId;
+use crate::objects::{ancestors, Object};
+use crate::operation::Operation;
+use crate::refs;
+use crate::repository;
+use crate::trace;
+use crate::trace::TRACE;
+use crate::worktree;
+
+/// How a commit was judged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Term {
+    Good,
+    Bad,
+    Skip,
+}
+
+/// Where bisection stands after a commit was judged.
+enum Step {
+    /// Both a good and a bad commit are needed to start narrowing down.
+    Waiting,
+    /// The next commit to judge has been checked out.
+    Testing,
+    Found,
+}
+
+/// Starts bisecting, optionally with known `bad` and `good` revisions.
+/// The branch checked out now is restored by [`reset`].
+pub fn start(bad: Option<&str>, good: &[String]) -> anyhow::Result<()> {
+    let git_dir = repository::current()?.git_dir().to_path_buf();
+    // Starting over replaces a bisect, but nothing else in progress.
+    Operation::ensure_none(&git_dir, Some(Operation::Bisect))?;
+    clear_state()?;
+
+    let original = match refs::current_branch()? {
+        Some(branch) => branch,
+        None => refs::resolve_revision("HEAD")?.to_string(),
+    };
+    fs::write(git_dir.join("BISECT_START"), format!("{}\n", original))?;
+
+    if let Some(bad) = bad {
+        record(Term::Bad, bad)?;
+    }
+    for good in good {
+        record(Term::Good, good)?;
+    }
+    next()?;
+
+    Ok(())
+}
+
+/// Judges `revisions`, HEAD by default, and checks out the next commit to
+/// test.
+pub fn mark(term: Term, revisions: &[String]) -> anyhow::Result<()> {
+    ensure_bisecting()?;
+    if revisions.is_empty() {
+        record(term, "HEAD")?;
+    }
+    for revision in revisions {
+        record(term, revision)?;
+    }
+    next()?;
+
+    Ok(())
+}
+
+/// Ends bisecting and checks out the branch or commit it started from.
+pub fn reset() -> anyhow::Result<()> {
+    let start_file = repository::current()?.git_dir().join("BISECT_START");
+    let Ok(original) = fs::read_to_string(&start_file) else {
+        println!("We are not bisecting.");
+        return Ok(());
+    };
+    let original = original.trim_end();
+
+    match refs::read_ref(&format!("refs/heads/{}", original))? {
+        Some(commit) => {
+            worktree::checkout(&commit, Some(original))?;
+            info!("Switched to branch '{}'", original);
+        }
+        None => {
+            let commit = original.parse()?;
+            worktree::checkout(&commit, None)?;
+            info!("HEAD is now at {}", &original[..7]);
+        }
+    }
+
+    clear_state()
+}
+
+/// Judges each commit by running `command` on it, until the first bad
+/// commit is found. Exit code 0 means good, 125 skip, and anything else
+/// below 128 bad.
+pub fn run(command: &[String]) -> anyhow::Result<()> {
+    ensure_bisecting()?;
+    let (program, args) = command.split_first()
+        .ok_or(anyhow!("bisect run failed: no command provided."))?;
+    let command = command.join(" ");
+
+    loop {
+        println!("running '{}'", command);
+        trace!(TRACE, "run_command: {}", command);
+        // A single argument is a shell command line, like with git.
+        let status = if args.is_empty() {
+            Command::new("sh").arg("-c").arg(program).status()?
+        } else {
+            Command::new(program).args(args).status()?
+        };
+
+        let term = match status.code() {
+            Some(0) => Term::Good,
+            Some(125) => Term::Skip,
+            Some(1..=127) => Term::Bad,
+            _ => return Err(anyhow!(
+                "bisect run failed: exit code {:?} from '{}' is < 0 or >= 128",
+                status.code(),
+                command
+            )),
+        };
+
+        record(term, "HEAD")?;
+        match next()? {
+            Step::Found => {
+                println!("bisect found first bad commit");
+                return Ok(());
+            }
+            Step::Testing => {}
+            Step::Waiting => return Err(anyhow!("bisect run cannot continue without a good and a bad commit")),
+        }
+    }
+}
+
+fn ensure_bisecting() -> anyhow::Result<()> {
+    if !repository::current()?.git_dir().join("BISECT_START").is_file() {
+        return Err(anyhow!("You need to start by \"git bisect start\""));
+    }
+
+    Ok(())
+}
+
+fn clear_state() -> anyhow::Result<()> {
+    refs::delete_refs(&refs::list_refs("refs/bisect/")?)?;
+
+    let start_file = repository::current()?.git_dir().join("BISECT_START");
+    if start_file.is_file() {
+        fs::remove_file(start_file)?;
+    }
+
+    Ok(())
+}
+
+fn record(term: Term, revision: &str) -> anyhow::Result<()> {
+    let commit = refs::resolve_revision(revision)?;
+    let name = match term {
+        Term::Bad => "refs/bisect/bad".to_string(),
+        Term::Good => format!("refs/bisect/good-{}", commit),
+        Term::Skip => format!("refs/bisect/skip-{}", commit),
+    };
+
+    refs::write_ref(&name, &commit)
+}
+
+fn marked(prefix: &str) -> anyhow::Result<Vec<ObjectId>> {
+    refs::list_refs(prefix)?
+        .into_iter()
+        .filter_map(|name| refs::read_ref(&name).transpose())
+        .collect()
+}
+
+/// Picks the commit that splits the remaining suspects most evenly and
+/// checks it out, or reports the first bad commit once it is known.
+fn next() -> anyhow::Result<Step> {
+    let bad = refs::read_ref("refs/bisect/bad")?;
+    let good = marked("refs/bisect/good-")?;
+    let Some(bad) = bad.filter(|_| !good.is_empty()) else {
+        match (bad, good.len()) {
+            (None, 0) => println!("status: waiting for both good and bad commits"),
+            (Some(_), _) => println!("status: waiting for good commit(s), bad commit known"),
+            (None, count) => println!("status: waiting for bad commit, {} good commit{} known", count, plural(count)),
+        }
+        return Ok(Step::Waiting);
+    };
+
+    let mut known_good = HashSet::new();
+    for commit in &good {
+        known_good.extend(ancestors(commit)?);
+    }
+    let suspects: HashSet<ObjectId> = ancestors(&bad)?
+        .into_iter()
+        .filter(|commit| !known_good.contains(commit))
+        .collect();
+
+    if suspects.len() <= 1 {
+        let subject = match repository::current()?.read(&bad)? {
+            Object::Commit(commit) => commit.subject(),
+            _ => String::new(),
+        };
+        println!("{} is the first bad commit", bad);
+        println!();
+        println!("    {}", subject);
+        return Ok(Step::Found);
+    }
+
+    let skipped: HashSet<ObjectId> = marked("refs/bisect/skip-")?.into_iter().collect();
+    let mut testable: Vec<&ObjectId> = suspects.iter()
+        .filter(|commit| **commit != bad && !skipped.contains(commit))
+        .collect();
+    if testable.is_empty() {
+        return Err(anyhow!("There are only 'skipped' commits left to test.\nThe first bad commit could be any of:\n{}",
+            suspects.iter().map(ObjectId::to_string).collect::<Vec<_>>().join("\n")));
+    }
+    testable.sort();
+
+    // A commit that reaches half of the suspects halves them whichever way
+    // it is judged.
+    let all = suspects.len();
+    let mut best = (*testable[0], 0);
+    for commit in testable {
+        let reaches = reach(commit, &suspects)?;
+        if reaches.min(all - reaches) > best.1.min(all - best.1) {
+            best = (*commit, reaches);
+        }
+    }
+    let (commit, reaches) = best;
+
+    let left = all - reaches - 1;
+    let steps = estimate_steps(all);
+    println!("Bisecting: {} revision{} left to test after this (roughly {} step{})",
+             left, plural(left), steps, plural(steps));
+
+    worktree::checkout(&commit, None)?;
+    let subject = match repository::current()?.read(&commit)? {
+        Object::Commit(commit) => commit.subject(),
+        _ => String::new(),
+    };
+    println!("[{}] {}", commit, subject);
+
+    Ok(Step::Testing)
+}
+
+/// Counts the suspects reachable from `commit`, itself included.
+fn reach(commit: &ObjectId, suspects: &HashSet<ObjectId>) -> anyhow::Result<usize> {
+    let repository = repository::current()?;
+    let mut seen = HashSet::new();
+    let mut pending = vec![*commit];
+
+    while let Some(sha) = pending.pop() {
+        if !suspects.contains(&sha) || !seen.insert(sha) {
+            continue;
+        }
+        if let Object::Commit(commit) = repository.read(&sha)? {
+            pending.extend(commit.parents);
+        }
+    }
+
+    Ok(seen.len())
+}
+
+/// Estimates the remaining steps like git: about log2 of the suspects.
+fn estimate_steps(all: usize) -> usize {
+    if all < 3 {
+        return 0;
+    }
+    let n = usize::BITS as usize - 1 - all.leading_zeros() as usize;
+    let e = 1 << n;
+    let x = all - e;
+    if e < 3 * x { n } else { n - 1 }
+}
+
+fn plural(count: usize) -> &'static str {
+    if count == 1 { "" } else { "s" }
+}
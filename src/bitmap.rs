@@ -0,0 +1,307 @@
+//! Writes the reachability bitmaps of a pack, `pack-<checksum>.bitmap`:
+//! for some of its commits, the set of objects reachable from each, as
+//! EWAH-compressed bitmaps over the objects in pack order, with the
+//! name-hash cache. See gitformat-bitmap(5).
+
+use std::collections::{HashMap, HashSet};
+
+use anyhow::anyhow;
+
+use crate::ident;
+use crate::object_id::ObjectId;
+use crate::objects::Object;
+use crate::repository::Repository;
+
+/// The bitmaps are of every object reachable, not just those in the pack.
+const FULL_DAG: u16 = 0x1;
+/// The name hash of every object follows the bitmaps.
+const HASH_CACHE: u16 = 0x4;
+
+/// Besides the commits refs point to, every this-many-th commit, newest
+/// first, gets a bitmap, so a walk from any commit soon reaches one.
+const SPACING: usize = 100;
+
+/// A set of positions stored as 64-bit words, the lowest bit first.
+#[derive(Debug, Default, Clone)]
+struct Bits(Vec<u64>);
+
+impl Bits {
+    fn set(&mut self, position: usize) {
+        if self.0.len() <= position / 64 {
+            self.0.resize(position / 64 + 1, 0);
+        }
+        self.0[position / 64] |= 1 << (position % 64);
+    }
+
+    fn get(&self, position: usize) -> bool {
+        self.0.get(position / 64).is_some_and(|word| word & (1 << (position % 64)) != 0)
+    }
+
+    fn or(&mut self, other: &Bits) {
+        if self.0.len() < other.0.len() {
+            self.0.resize(other.0.len(), 0);
+        }
+        for (word, other) in self.0.iter_mut().zip(&other.0) {
+            *word |= other;
+        }
+    }
+
+    /// The bits in EWAH form: the number of bits, then the words, each a
+    /// marker giving a run of all-zero or all-one words and how many
+    /// literal words follow it, then the position of the last marker.
+    fn ewah(&self) -> Vec<u8> {
+        let words = &self.0[..self.0.iter().rposition(|word| *word != 0).map_or(0, |last| last + 1)];
+        let bit_size = words.last().map_or(0, |last| (words.len() - 1) * 64 + 64 - last.leading_zeros() as usize);
+        let clean = |word: u64| word == 0 || word == u64::MAX;
+
+        let mut buffer = Vec::new();
+        let mut marker = 0;
+        let mut i = 0;
+        while i < words.len() || buffer.is_empty() {
+            let running = words.get(i) == Some(&u64::MAX);
+            let mut run = 0u64;
+            while i < words.len() && clean(words[i]) && (words[i] == u64::MAX) == running && run < u32::MAX as u64 {
+                run += 1;
+                i += 1;
+            }
+            let start = i;
+            while i < words.len() && !clean(words[i]) && i - start < 0x7fff_ffff {
+                i += 1;
+            }
+            marker = buffer.len();
+            buffer.push(running as u64 | run << 1 | ((i - start) as u64) << 33);
+            buffer.extend_from_slice(&words[start..i]);
+        }
+
+        let mut out = Vec::new();
+        out.extend_from_slice(&(bit_size as u32).to_be_bytes());
+        out.extend_from_slice(&(buffer.len() as u32).to_be_bytes());
+        for word in &buffer {
+            out.extend_from_slice(&word.to_be_bytes());
+        }
+        out.extend_from_slice(&(marker as u32).to_be_bytes());
+        out
+    }
+}
+
+/// The bitmap file of a pack holding `written`, in the order written,
+/// whose checksum is `checksum`. The pack must hold every object
+/// reachable from its commits. The commits `tips` point to, perhaps
+/// through tags, and every `SPACING`-th other commit get a bitmap;
+/// `names` gives the name hash of each object.
+pub(crate) fn write(
+    repository: &Repository,
+    written: &[(ObjectId, u64, u32)],
+    names: &HashMap<ObjectId, u32>,
+    tips: &[ObjectId],
+    checksum: &ObjectId,
+) -> anyhow::Result<Vec<u8>> {
+    let positions: HashMap<ObjectId, usize> = written.iter().enumerate().map(|(i, (sha, _, _))| (*sha, i)).collect();
+    let mut sorted: Vec<ObjectId> = written.iter().map(|(sha, _, _)| *sha).collect();
+    sorted.sort();
+
+    // Each object's type bit, and what it links to.
+    let mut types: [Bits; 4] = Default::default();
+    let mut links: HashMap<ObjectId, Vec<ObjectId>> = HashMap::new();
+    let mut commits = Vec::new();
+    for (position, (sha, _, _)) in written.iter().enumerate() {
+        let (kind, _) = repository.object_header(sha)?;
+        match kind.as_str() {
+            "commit" => {
+                let commit = repository.read_commit(sha)?;
+                commits.push((ident::identity_time(&commit.committer), *sha));
+                links.insert(*sha, std::iter::once(commit.tree).chain(commit.parents).collect());
+                types[0].set(position);
+            }
+            "tree" => {
+                let Object::Tree(tree) = repository.read(sha)? else { return Err(anyhow!("{} is not a tree", sha)) };
+                // Submodule commits live in another repository.
+                let entries = tree.entries.into_iter().filter(|entry| entry.kind() != "commit");
+                links.insert(*sha, entries.map(|entry| entry.sha).collect());
+                types[1].set(position);
+            }
+            "blob" => types[2].set(position),
+            _ => {
+                let Object::Tag(tag) = repository.read(sha)? else { return Err(anyhow!("{} is not a tag", sha)) };
+                links.insert(*sha, vec![tag.object]);
+                types[3].set(position);
+            }
+        }
+    }
+
+    commits.sort_by(|a, b| b.cmp(a));
+    // Tips that are tags count as the commits they tag.
+    let mut peeled = HashSet::new();
+    for tip in tips {
+        let mut sha = *tip;
+        while positions.get(&sha).is_some_and(|position| types[3].get(*position)) {
+            sha = links[&sha][0];
+        }
+        peeled.insert(sha);
+    }
+    let mut selected: Vec<(i64, ObjectId)> = commits.iter()
+        .enumerate()
+        .filter(|(i, (_, sha))| peeled.contains(sha) || i % SPACING == 0)
+        .map(|(_, commit)| *commit)
+        .collect();
+    // Oldest first, so that newer bitmaps can start from older ones.
+    selected.reverse();
+
+    let mut bitmaps: HashMap<ObjectId, Bits> = HashMap::new();
+    let mut entries = Vec::new();
+    for (_, commit) in selected {
+        let mut bits = Bits::default();
+        let mut pending = vec![commit];
+        while let Some(sha) = pending.pop() {
+            let position = *positions.get(&sha)
+                .ok_or(anyhow!("Object {} is reachable from {} but not in the pack", sha, commit))?;
+            if bits.get(position) {
+                continue;
+            }
+            match bitmaps.get(&sha) {
+                Some(reachable) => bits.or(reachable),
+                None => {
+                    bits.set(position);
+                    pending.extend(links.get(&sha).into_iter().flatten());
+                }
+            }
+        }
+        let index_position = sorted.binary_search(&commit).unwrap_or_else(|_| unreachable!());
+        entries.push((index_position, bits.ewah()));
+        bitmaps.insert(commit, bits);
+    }
+
+    let mut out = b"BITM".to_vec();
+    out.extend_from_slice(&1u16.to_be_bytes());
+    out.extend_from_slice(&(FULL_DAG | HASH_CACHE).to_be_bytes());
+    out.extend_from_slice(&(entries.len() as u32).to_be_bytes());
+    out.extend_from_slice(checksum.as_ref());
+    for bits in &types {
+        out.extend(bits.ewah());
+    }
+    for (index_position, bitmap) in entries {
+        out.extend_from_slice(&(index_position as u32).to_be_bytes());
+        // No XOR against an earlier bitmap, and no flags.
+        out.extend_from_slice(&[0, 0]);
+        out.extend(bitmap);
+    }
+    for sha in &sorted {
+        out.extend_from_slice(&names.get(sha).copied().unwrap_or(0).to_be_bytes());
+    }
+    let own = repository.object_format().hash(&out);
+    out.extend_from_slice(own.as_ref());
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::object_id::HashAlgorithm;
+    use crate::objects::{Commit, Tree, TreeEntry};
+    use crate::pack::{self, PackOptions};
+
+    /// The words an EWAH bitmap stands for, and its bit size.
+    fn decode(data: &[u8]) -> (Vec<u64>, u32) {
+        let u32_at = |at: usize| u32::from_be_bytes(data[at..at + 4].try_into().unwrap());
+        let bit_size = u32_at(0);
+        let buffer: Vec<u64> = (0..u32_at(4) as usize)
+            .map(|i| u64::from_be_bytes(data[8 + i * 8..16 + i * 8].try_into().unwrap()))
+            .collect();
+        assert_eq!(data.len(), 12 + buffer.len() * 8);
+        let mut words = Vec::new();
+        let mut i = 0;
+        let mut marker = 0;
+        while i < buffer.len() {
+            marker = i;
+            let run = (buffer[i] >> 1) & 0xffff_ffff;
+            let literals = (buffer[i] >> 33) as usize;
+            words.extend(std::iter::repeat_n(if buffer[i] & 1 == 1 { u64::MAX } else { 0 }, run as usize));
+            words.extend_from_slice(&buffer[i + 1..i + 1 + literals]);
+            i += 1 + literals;
+        }
+        assert_eq!(u32_at(data.len() - 4) as usize, marker);
+        (words, bit_size)
+    }
+
+    #[test]
+    fn ewah() {
+        let mut bits = Bits::default();
+        bits.set(0);
+        // A marker for no run and one literal word, then that word.
+        let words = [(1u64 << 33).to_be_bytes(), 1u64.to_be_bytes()].concat();
+        assert_eq!(bits.ewah(), [&[0, 0, 0, 1, 0, 0, 0, 2][..], &words, &[0; 4]].concat());
+        assert_eq!(Bits::default().ewah(), [0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+
+        let mut bits = Bits::default();
+        (0..128).chain([200]).for_each(|bit| bits.set(bit));
+        let encoded = bits.ewah();
+        assert_eq!(decode(&encoded), (vec![u64::MAX, u64::MAX, 0, 1 << 8], 201));
+        assert_eq!(&encoded[4..8], &[0, 0, 0, 3]);
+
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        for _ in 0..200 {
+            let mut bits = Bits::default();
+            for position in 0..2000 {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                // Long stretches of set and clear bits, and random ones.
+                if (position / 300) % 3 == 0 || ((position / 300) % 3 == 1 && state & 1 == 0) {
+                    bits.set(position);
+                }
+            }
+            let (words, bit_size) = decode(&bits.ewah());
+            let last = bits.0.iter().rposition(|word| *word != 0).map_or(0, |last| last + 1);
+            assert_eq!(words, bits.0[..last]);
+            assert!((bit_size as usize..2000).all(|position| !bits.get(position)));
+        }
+    }
+
+    #[test]
+    fn bitmaps_of_commits() {
+        let dir = std::env::temp_dir().join(format!("git-starter-rust-bitmap-{}", std::process::id()));
+        let repository = Repository::init_module(&dir, HashAlgorithm::Sha1).unwrap();
+        let mut objects = Vec::new();
+        let mut parents = Vec::new();
+        for i in 0..3 {
+            let blob = repository.write_object("blob", format!("{}", i).as_bytes(), true).unwrap();
+            let entry = TreeEntry { mode: 0o100644, name: "file".to_string(), sha: blob };
+            let tree = repository.write(&Object::Tree(Tree { entries: vec![entry] })).unwrap();
+            let commit = repository.write(&Object::Commit(Commit {
+                tree,
+                parents: parents.clone(),
+                author: format!("A <a@b> {} +0000", 1000 + i),
+                committer: format!("A <a@b> {} +0000", 1000 + i),
+                extra_headers: Vec::new(),
+                message: "c\n".to_string(),
+            }))
+            .unwrap();
+            objects.extend([(commit, 0), (tree, 0), (blob, pack::name_hash("file"))]);
+            parents = vec![commit];
+        }
+        let tip = parents[0];
+        let mut data = Vec::new();
+        let (written, checksum) = pack::write(&repository, &objects, PackOptions::WHOLE, false, &mut data).unwrap();
+        let names = objects.iter().copied().collect();
+        let bitmap = write(&repository, &written, &names, &[tip], &checksum).unwrap();
+
+        assert_eq!(&bitmap[..8], b"BITM\0\x01\0\x05");
+        // Only the tip, the newest commit, gets a bitmap, of everything.
+        assert_eq!(&bitmap[8..12], &[0, 0, 0, 1]);
+        assert_eq!(&bitmap[12..32], checksum.as_ref());
+        let (body, own) = bitmap.split_at(bitmap.len() - 20);
+        assert_eq!(HashAlgorithm::Sha1.hash(body).as_ref(), own);
+        let hashes = &body[body.len() - 36..];
+        let mut sorted: Vec<_> = objects.clone();
+        sorted.sort();
+        for (i, (_, hash)) in sorted.iter().enumerate() {
+            assert_eq!(&hashes[i * 4..i * 4 + 4], &hash.to_be_bytes());
+        }
+
+        // A pack missing an object reachable from a commit can't have one.
+        assert!(write(&repository, &written[..written.len() - 1], &names, &[tip], &checksum).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,443 @@
+use std::collections::HashSet;
+use std::fs;
+
+use anyhow::anyhow;
+
+use crate::color::{self, RESET};
+use crate::config::Config;
+use crate::error::Error;
+use crate::mailmap;
+use crate::object_id::ObjectId;
+use crate::objects::{ancestors, peel_to_tree, Object};
+use crate::operation::{self, Operation};
+use crate::refs;
+use crate::remote;
+use crate::repository::{self, common_dir};
+
+/// The branch a local branch integrates with, from
+/// `branch.<name>.remote` and `branch.<name>.merge`.
+struct Upstream {
+    /// The remote-tracking ref, or the local branch for a `.` remote.
+    tracking_ref: String,
+}
+
+impl Upstream {
+    fn short_name(&self) -> &str {
+        self.tracking_ref.strip_prefix("refs/remotes/")
+            .or_else(|| self.tracking_ref.strip_prefix("refs/heads/"))
+            .unwrap_or(&self.tracking_ref)
+    }
+}
+
+fn upstream(config: &Config, branch: &str) -> anyhow::Result<Option<Upstream>> {
+    let (Some(remote), Some(merge)) = (
+        config.get(&format!("branch.{}.remote", branch)),
+        config.get(&format!("branch.{}.merge", branch)),
+    ) else {
+        return Ok(None);
+    };
+
+    if remote == "." {
+        return Ok(Some(Upstream { tracking_ref: merge.to_string() }));
+    }
+
+    Ok(remote::fetch_refspecs(config, remote)?
+        .iter()
+        .find_map(|refspec| refspec.map_source(merge))
+        .map(|tracking_ref| Upstream { tracking_ref }))
+}
+
+/// The ref of the branch the current branch integrates with, if it has
+/// one.
+pub fn current_upstream() -> anyhow::Result<Option<String>> {
+    let Some(branch) = refs::current_branch()? else { return Ok(None) };
+    let config = Config::from_file(&common_dir()?.join("config"))?;
+    Ok(upstream(&config, &branch)?.map(|upstream| upstream.tracking_ref))
+}
+
+/// Makes `branch`, or the current branch, track `upstream`, given as a
+/// remote-tracking branch like `origin/main` or a local branch.
+pub fn set_upstream_to(upstream: &str, branch: Option<&str>) -> anyhow::Result<()> {
+    let branch = match branch {
+        Some(branch) => branch.to_string(),
+        None => refs::current_branch()?
+            .ok_or(anyhow!("Could not set upstream of HEAD to {} when it does not point to any branch", upstream))?,
+    };
+    if refs::read_ref(&format!("refs/heads/{}", branch))?.is_none() {
+        return Err(anyhow!("Branch '{}' does not exist", branch));
+    }
+
+    let config_path = common_dir()?.join("config");
+    let mut config = Config::from_file(&config_path)?;
+
+    let tracking_ref = format!("refs/remotes/{}", upstream);
+    let (remote, merge) = if refs::read_ref(&tracking_ref)?.is_some() {
+        config.subsections("remote")
+            .into_iter()
+            .find_map(|remote| {
+                let refspecs = remote::fetch_refspecs(&config, &remote).ok()?;
+                let merge = refspecs.iter().find_map(|refspec| refspec.map_destination(&tracking_ref))?;
+                Some((remote, merge))
+            })
+            .ok_or(anyhow!("The requested upstream branch '{}' is not fetched by any remote", upstream))?
+    } else if refs::read_ref(&format!("refs/heads/{}", upstream))?.is_some() {
+        (".".to_string(), format!("refs/heads/{}", upstream))
+    } else {
+        return Err(anyhow!("The requested upstream branch '{}' does not exist", upstream));
+    };
+
+    config.set(&format!("branch.{}.remote", branch), &remote)?;
+    config.set(&format!("branch.{}.merge", branch), &merge)?;
+    config.write(&config_path)?;
+
+    println!("branch '{}' set up to track '{}'.", branch, upstream);
+    Ok(())
+}
+
+/// Counts the commits reachable from `local` but not `upstream`, and the
+/// other way around.
+pub fn ahead_behind(local: &ObjectId, upstream: &ObjectId) -> anyhow::Result<(usize, usize)> {
+    let local_commits = ancestors(local)?;
+    let upstream_commits = ancestors(upstream)?;
+
+    Ok((
+        local_commits.difference(&upstream_commits).count(),
+        upstream_commits.difference(&local_commits).count(),
+    ))
+}
+
+/// Describes how a branch relates to its upstream, like `ahead 1, behind 2`
+/// or `gone`. Empty if they point at the same commit.
+fn tracking_summary(sha: &ObjectId, upstream: &Upstream) -> anyhow::Result<String> {
+    let Some(upstream_sha) = refs::read_ref(&upstream.tracking_ref)? else {
+        return Ok("gone".to_string());
+    };
+
+    Ok(match ahead_behind(sha, &upstream_sha)? {
+        (0, 0) => String::new(),
+        (ahead, 0) => format!("ahead {}", ahead),
+        (0, behind) => format!("behind {}", behind),
+        (ahead, behind) => format!("ahead {}, behind {}", ahead, behind),
+    })
+}
+
+/// Applies `lstrip=N` or `rstrip=N` to a ref name: strips `N` components
+/// from the left or right, or with a negative `N` keeps only `-N` of them.
+fn strip_components(name: &str, count: i64, from_left: bool) -> String {
+    let components: Vec<&str> = name.split('/').collect();
+    let len = components.len() as i64;
+    let strip = if count < 0 { (len + count).max(0) } else { count.min(len) } as usize;
+    let kept = if from_left { &components[strip..] } else { &components[..components.len() - strip] };
+    kept.join("/")
+}
+
+/// A ref name atom such as `refname` or `upstream` with its modifier.
+fn format_ref_name(name: &str, short: &str, modifier: Option<&str>, atom: &str) -> anyhow::Result<String> {
+    let strip = |spec: &str| spec.parse::<i64>().map_err(|_| anyhow!("unknown field name: {}", atom));
+    Ok(match modifier {
+        None => name.to_string(),
+        Some("short") => short.to_string(),
+        Some(modifier) => match modifier.split_once('=') {
+            Some(("lstrip" | "strip", count)) => strip_components(name, strip(count)?, true),
+            Some(("rstrip", count)) => strip_components(name, strip(count)?, false),
+            _ => return Err(anyhow!("unknown field name: {}", atom)),
+        },
+    })
+}
+
+/// Expands `%(atom)` placeholders for a branch, like `git branch
+/// --format`, along with `%%` and `%xx` hex escapes.
+fn format_branch(format: &str, listed: &Listed, config: &Config) -> anyhow::Result<String> {
+    let Listed { name: branch, sha, current, detached } = listed;
+    let repository = repository::current()?;
+    let object = repository.read(sha)?;
+    let commit = match &object {
+        Object::Commit(commit) => Some(commit),
+        _ => None,
+    };
+    let upstream = if *detached { None } else { upstream(config, branch)? };
+    let ident = |line: Option<&String>, part: &str| -> String {
+        let Some((name, email, _)) = line.and_then(|line| mailmap::split_ident(line)) else {
+            return String::new();
+        };
+        match part {
+            "name" => name.unwrap_or_default().to_string(),
+            "email" => format!("<{}>", email),
+            _ => email.to_string(),
+        }
+    };
+
+    let mut out = String::new();
+    let mut rest = format;
+    while let Some(percent) = rest.find('%') {
+        out.push_str(&rest[..percent]);
+        rest = &rest[percent + 1..];
+        if let Some(after) = rest.strip_prefix('%') {
+            out.push('%');
+            rest = after;
+            continue;
+        }
+        let hex = rest.get(..2).filter(|hex| hex.bytes().all(|b| b.is_ascii_hexdigit()));
+        if let Some(byte) = hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+            out.push(byte as char);
+            rest = &rest[2..];
+            continue;
+        }
+        if !rest.starts_with('(') {
+            out.push('%');
+            continue;
+        }
+        let end = rest.find(')').ok_or(anyhow!("malformed format string %{}", rest))? - 1;
+        let atom = &rest[1..end + 1];
+        rest = &rest[end + 2..];
+
+        let (name, modifier) = match atom.split_once(':') {
+            Some((name, modifier)) => (name, Some(modifier)),
+            None => (atom, None),
+        };
+        let value = match (name, modifier) {
+            ("HEAD", None) => if *current { "*" } else { " " }.to_string(),
+            ("refname", _) if *detached => branch.clone(),
+            ("refname", _) => format_ref_name(&format!("refs/heads/{}", branch), branch, modifier, atom)?,
+            ("upstream", Some(track @ ("track" | "track,nobracket" | "trackshort"))) => match &upstream {
+                Some(upstream) => {
+                    let summary = tracking_summary(sha, upstream)?;
+                    match (track, summary.as_str()) {
+                        ("trackshort", "gone") => String::new(),
+                        ("trackshort", "") => "=".to_string(),
+                        ("trackshort", summary) if summary.contains(", ") => "<>".to_string(),
+                        ("trackshort", summary) if summary.starts_with("ahead") => ">".to_string(),
+                        ("trackshort", _) => "<".to_string(),
+                        (_, "") => String::new(),
+                        ("track", summary) => format!("[{}]", summary),
+                        (_, summary) => summary.to_string(),
+                    }
+                }
+                None => String::new(),
+            },
+            ("upstream", _) => match &upstream {
+                Some(upstream) => format_ref_name(&upstream.tracking_ref, upstream.short_name(), modifier, atom)?,
+                None => String::new(),
+            },
+            ("objectname", None) => sha.to_string(),
+            ("objectname", Some("short")) => sha.to_string()[..7].to_string(),
+            ("objecttype", None) => object.kind().to_string(),
+            ("subject", None) | ("contents", Some("subject")) => {
+                commit.map(|commit| commit.subject()).unwrap_or_default()
+            }
+            ("authorname", None) => ident(commit.map(|commit| &commit.author), "name"),
+            ("authoremail", None) => ident(commit.map(|commit| &commit.author), "email"),
+            ("authoremail", Some("trim")) => ident(commit.map(|commit| &commit.author), "trim"),
+            ("committername", None) => ident(commit.map(|commit| &commit.committer), "name"),
+            ("committeremail", None) => ident(commit.map(|commit| &commit.committer), "email"),
+            ("committeremail", Some("trim")) => ident(commit.map(|commit| &commit.committer), "trim"),
+            _ => return Err(anyhow!("unknown field name: {}", atom)),
+        };
+        out.push_str(&value);
+    }
+    out.push_str(rest);
+
+    Ok(out)
+}
+
+/// Which branches `list` shows, by the commits given to `--contains`,
+/// `--no-contains`, `--merged` and `--no-merged`. Each keeps the branches
+/// that relate that way to any of its commits.
+#[derive(Debug, Default)]
+pub struct Filter {
+    /// Branches whose history includes the commit.
+    pub contains: Vec<String>,
+    pub no_contains: Vec<String>,
+    /// Branches whose tip is in the history of the commit.
+    pub merged: Vec<String>,
+    pub no_merged: Vec<String>,
+}
+
+/// Resolves `revision` to a commit, failing like git's option parsing.
+fn filter_commit(revision: &str) -> anyhow::Result<ObjectId> {
+    let usage = |message: String| {
+        eprintln!("error: {}", message);
+        anyhow::Error::from(Error::Status(129))
+    };
+    let sha = refs::resolve_revision(revision).map_err(|_| usage(format!("malformed object name {}", revision)))?;
+    match peel_to_tree(&sha) {
+        Ok((commit, _)) => Ok(commit),
+        Err(_) => Err(usage(format!("no such commit {}", revision))),
+    }
+}
+
+impl Filter {
+    fn is_empty(&self) -> bool {
+        self.contains.is_empty() && self.no_contains.is_empty() && self.merged.is_empty() && self.no_merged.is_empty()
+    }
+
+    /// The branches among `branches` that pass the filter.
+    fn apply(&self, branches: Vec<Listed>) -> anyhow::Result<Vec<Listed>> {
+        if self.is_empty() {
+            return Ok(branches);
+        }
+        let commits = |revisions: &[String]| -> anyhow::Result<Vec<ObjectId>> {
+            revisions.iter().map(|revision| filter_commit(revision)).collect()
+        };
+        let history = |revisions: &[String]| -> anyhow::Result<HashSet<ObjectId>> {
+            let mut history = HashSet::new();
+            for commit in commits(revisions)? {
+                history.extend(ancestors(&commit)?);
+            }
+            Ok(history)
+        };
+        let (contains, no_contains) = (commits(&self.contains)?, commits(&self.no_contains)?);
+        let (merged, no_merged) = (history(&self.merged)?, history(&self.no_merged)?);
+
+        let mut kept = Vec::new();
+        for branch in branches {
+            let sha = &branch.sha;
+            if !self.merged.is_empty() && !merged.contains(sha) || no_merged.contains(sha) {
+                continue;
+            }
+            if !contains.is_empty() || !no_contains.is_empty() {
+                let history = ancestors(sha)?;
+                if !contains.is_empty() && !contains.iter().any(|commit| history.contains(commit))
+                    || no_contains.iter().any(|commit| history.contains(commit))
+                {
+                    continue;
+                }
+            }
+            kept.push(branch);
+        }
+        Ok(kept)
+    }
+}
+
+/// A line of `list`: a branch, or the detached HEAD, named as git
+/// describes it.
+struct Listed {
+    name: String,
+    sha: ObjectId,
+    current: bool,
+    detached: bool,
+}
+
+/// How git describes a detached HEAD in place of a branch name: by the
+/// branch a rebase or bisect started from, or by what the last checkout
+/// detached it at, which HEAD has moved `from` if it no longer points there.
+fn detached_description(head: &ObjectId) -> anyhow::Result<String> {
+    let git_dir = repository::current()?.git_dir().to_path_buf();
+    if Operation::in_progress(&git_dir).contains(&Operation::Rebase) {
+        if let Some(branch) = operation::rebasing_branch(&git_dir) {
+            return Ok(format!("(no branch, rebasing {})", branch));
+        }
+    }
+    if let Ok(start) = fs::read_to_string(git_dir.join("BISECT_START")) {
+        let start = start.trim_end();
+        let start = match start.parse::<ObjectId>() {
+            Ok(_) => &start[..7],
+            Err(_) => start,
+        };
+        return Ok(format!("(no branch, bisect started on {})", start));
+    }
+
+    let log = fs::read_to_string(git_dir.join("logs/HEAD")).unwrap_or_default();
+    let checkout = log.lines().rev().find_map(|line| {
+        let (entry, message) = line.split_once('\t')?;
+        let (_, target) = message.strip_prefix("checkout: moving from ")?.split_once(" to ")?;
+        let new: ObjectId = entry.split(' ').nth(1)?.parse().ok()?;
+        Some((target, new))
+    });
+    let Some((target, new)) = checkout else { return Ok("(no branch)".to_string()) };
+
+    // A ref is named only if it is the one `target` means and still
+    // points where the checkout went.
+    let candidates = match target.starts_with("refs/") {
+        true => vec![target.to_string()],
+        false => ["refs/tags/", "refs/heads/", "refs/remotes/"]
+            .iter()
+            .map(|prefix| format!("{}{}", prefix, target))
+            .chain([format!("refs/remotes/{}/HEAD", target)])
+            .collect(),
+    };
+    let mut resolved = Vec::new();
+    for name in candidates {
+        if let Some(sha) = refs::read_ref(&name)? {
+            resolved.push((name, sha));
+        }
+    }
+    let from = match resolved.as_slice() {
+        [(name, sha)] if peel_to_tree(sha).is_ok_and(|(commit, _)| commit == new) => {
+            let short = name.strip_prefix("refs/tags/").or_else(|| name.strip_prefix("refs/remotes/"));
+            short.unwrap_or(name).to_string()
+        }
+        _ => new.to_string()[..7].to_string(),
+    };
+    Ok(format!("(HEAD detached {} {})", if *head == new { "at" } else { "from" }, from))
+}
+
+/// Lists the local branches that pass `filter`, marking the current one,
+/// after a detached HEAD if there is one. With `verbose`, also shows each
+/// branch's commit and how far it is from its upstream, and twice the
+/// upstream's name. A `format` replaces all of that with its `%(atom)`
+/// placeholders expanded for each branch. The names are colored as `color`
+/// or `color.branch` says, in `color.branch.current`, `.local` and
+/// `.upstream`.
+pub fn list(verbose: u8, format: Option<&str>, filter: &Filter, color: Option<color::When>) -> anyhow::Result<()> {
+    let repository = repository::current()?;
+    let config = repository.config()?;
+    let colored = color::enabled(&config, "branch", color);
+    let slot = |slot: &str, default: &str| match colored {
+        true => color::slot(&config, "branch", slot, default),
+        false => Ok(String::new()),
+    };
+    let (current_color, local_color) = (slot("current", "green")?, slot("local", "normal")?);
+    let upstream_color = slot("upstream", "blue")?;
+    let reset = if colored { RESET } else { "" };
+    let current = refs::current_branch()?;
+    let mut branches = Vec::new();
+    let head = refs::raw_ref_in(repository.git_dir(), "HEAD")?.unwrap_or_default();
+    if let (false, Some(sha)) = (head.starts_with("ref:"), refs::read_ref("HEAD")?) {
+        branches.push(Listed { name: detached_description(&sha)?, sha, current: true, detached: true });
+    }
+    for name in refs::list_refs("refs/heads/")? {
+        let Some(sha) = refs::read_ref(&name)? else { continue };
+        let name = name["refs/heads/".len()..].to_string();
+        branches.push(Listed { current: current.as_ref() == Some(&name), name, sha, detached: false });
+    }
+    let branches = filter.apply(branches)?;
+    let width = branches.iter().map(|branch| branch.name.len()).max().unwrap_or(0);
+
+    for listed in &branches {
+        if let Some(format) = format {
+            println!("{}", format_branch(format, listed, &config)?);
+            continue;
+        }
+        let Listed { name: branch, sha, current, detached } = listed;
+        let (marker, color) = if *current { ('*', &current_color) } else { (' ', &local_color) };
+        if verbose == 0 {
+            println!("{} {}{}{}", marker, color, branch, reset);
+            continue;
+        }
+
+        let subject = match repository.read(sha)? {
+            Object::Commit(commit) => commit.subject(),
+            _ => String::new(),
+        };
+
+        let upstream = if *detached { None } else { upstream(&config, branch)? };
+        let tracking = match upstream {
+            Some(upstream) => {
+                let summary = tracking_summary(sha, &upstream)?;
+                match (verbose, summary.is_empty()) {
+                    (1, true) => String::new(),
+                    (1, false) => format!("[{}] ", summary),
+                    (_, true) => format!("[{}{}{}] ", upstream_color, upstream.short_name(), reset),
+                    (_, false) => format!("[{}{}{}: {}] ", upstream_color, upstream.short_name(), reset, summary),
+                }
+            }
+            None => String::new(),
+        };
+
+        let sha = &sha.to_string()[..7];
+        println!("{} {}{:width$}{} {} {}{}", marker, color, branch, reset, sha, tracking, subject, width = width);
+    }
+
+    Ok(())
+}
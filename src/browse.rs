@@ -0,0 +1,214 @@
+use std::fmt::Write;
+
+use anyhow::anyhow;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::export_html::{commit_page, escape, page};
+use crate::object_id::ObjectId;
+use crate::objects::Object;
+use crate::refs;
+use crate::repository::{self, Repository};
+use crate::rev_list::RevisionSet;
+use crate::runtime;
+
+/// The most commits a log page lists.
+const LOG_LIMIT: usize = 200;
+
+/// A response: status line, content type and body.
+type Response = (&'static str, &'static str, Vec<u8>);
+
+fn html(body: String) -> Response {
+    ("200 OK", "text/html; charset=utf-8", body.into_bytes())
+}
+
+fn not_found(what: &str) -> Response {
+    ("404 Not Found", "text/html; charset=utf-8", page("Not found", &format!("<p>{}</p>\n", escape(what))).into_bytes())
+}
+
+/// Decodes the `%XX` escapes a browser puts in URL paths.
+fn percent_decode(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escape =
+            bytes.get(i + 1..i + 3).and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match (bytes[i], escape) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// The front page: HEAD, the branches and the tags, each linking to its
+/// log.
+fn refs_page() -> anyhow::Result<String> {
+    let mut body = String::new();
+    writeln!(body, "<h1>{}</h1>", escape(&std::env::current_dir()?.display().to_string()))?;
+    for (title, prefix) in [("Branches", "refs/heads/"), ("Tags", "refs/tags/"), ("Remote branches", "refs/remotes/")] {
+        let names = refs::list_refs(prefix)?;
+        if names.is_empty() {
+            continue;
+        }
+        writeln!(body, "<h2>{}</h2>\n<ul>", title)?;
+        for name in names {
+            writeln!(body, "<li><a href=\"/log/{}\">{}</a></li>", escape(&name), escape(&name[prefix.len()..]))?;
+        }
+        writeln!(body, "</ul>")?;
+    }
+    if refs::read_ref("HEAD")?.is_some() {
+        writeln!(body, "<p><a href=\"/log/HEAD\">HEAD</a></p>")?;
+    }
+    Ok(page("Repository", &body))
+}
+
+/// The newest commits reachable from `revision`, linking to their pages
+/// and trees.
+fn log_page(repository: &Repository, revision: &str) -> anyhow::Result<String> {
+    let commits = RevisionSet::parse(&[revision.to_string()])?.walk()?;
+    let mut body = String::new();
+    writeln!(body, "<p><a href=\"/\">Refs</a></p>\n<h1>{}</h1>\n<table>", escape(revision))?;
+    for sha in commits.iter().take(LOG_LIMIT) {
+        let commit = repository.read_commit(sha)?;
+        writeln!(
+            body,
+            "<tr><td class=\"sha\"><a href=\"/commit/{}.html\">{}</a></td><td>{}</td>\
+             <td><a href=\"/tree/{}\">tree</a></td></tr>",
+            sha,
+            &sha.to_string()[..7],
+            escape(&commit.subject()),
+            commit.tree
+        )?;
+    }
+    writeln!(body, "</table>")?;
+    if commits.len() > LOG_LIMIT {
+        writeln!(body, "<p>{} older commits not shown.</p>", commits.len() - LOG_LIMIT)?;
+    }
+    Ok(page(revision, &body))
+}
+
+fn tree_page(repository: &Repository, sha: &ObjectId) -> anyhow::Result<Option<String>> {
+    let Object::Tree(tree) = repository.read(sha)? else { return Ok(None) };
+    let mut body = String::new();
+    writeln!(body, "<p><a href=\"/\">Refs</a></p>\n<h1>tree {}</h1>\n<table>", sha)?;
+    for entry in tree.entries {
+        let link = match entry.kind() {
+            "tree" => format!("<a href=\"/tree/{}\">{}/</a>", entry.sha, escape(&entry.name)),
+            "blob" => format!("<a href=\"/blob/{}\">{}</a>", entry.sha, escape(&entry.name)),
+            // Submodule commits aren't in this repository.
+            _ => escape(&entry.name),
+        };
+        writeln!(body, "<tr><td class=\"sha\">{:06o}</td><td>{}</td></tr>", entry.mode, link)?;
+    }
+    writeln!(body, "</table>")?;
+    Ok(Some(page(&format!("tree {}", sha), &body)))
+}
+
+fn blob_page(repository: &Repository, sha: &ObjectId) -> anyhow::Result<Option<String>> {
+    let Object::Blob(content) = repository.read(sha)? else { return Ok(None) };
+    let mut body = String::new();
+    writeln!(body, "<p><a href=\"/\">Refs</a></p>\n<h1>blob {}</h1>", sha)?;
+    match std::str::from_utf8(&content) {
+        Ok(text) if !content.contains(&0) => writeln!(body, "<pre>{}</pre>", escape(text))?,
+        _ => writeln!(body, "<p>Binary file, {} bytes.</p>", content.len())?,
+    }
+    Ok(Some(page(&format!("blob {}", sha), &body)))
+}
+
+/// The most a request may send before the end of its header, so that a
+/// client can't make the server buffer without limit.
+const MAX_HEADER_SIZE: usize = 8 << 10;
+
+/// Renders the page at `path`.
+fn route(path: &str) -> anyhow::Result<Response> {
+    let repository = repository::current()?;
+    let path = percent_decode(path.split('?').next().unwrap_or_default());
+    let object = |id: &str| id.parse::<ObjectId>().ok().filter(|sha| repository.object_header(sha).is_ok());
+
+    let (kind, rest) = path.trim_start_matches('/').split_once('/').unwrap_or((path.trim_start_matches('/'), ""));
+    let page = match (kind, rest) {
+        ("" | "index.html", "") => Some(refs_page()?),
+        ("log", revision) => match refs::resolve_revision(revision) {
+            Ok(_) => Some(log_page(&repository, revision)?),
+            Err(_) => None,
+        },
+        ("commit", id) => match object(id.trim_end_matches(".html")) {
+            Some(sha) if repository.object_header(&sha)?.0 == "commit" => Some(commit_page(&repository, &sha, None)?),
+            _ => None,
+        },
+        ("tree", id) => object(id).map(|sha| tree_page(&repository, &sha)).transpose()?.flatten(),
+        ("blob", id) => object(id).map(|sha| blob_page(&repository, &sha)).transpose()?.flatten(),
+        _ => None,
+    };
+    Ok(page.map(html).unwrap_or_else(|| not_found(&format!("Nothing at {}", path))))
+}
+
+async fn handle(mut stream: TcpStream) -> anyhow::Result<()> {
+    let mut request = Vec::new();
+    let mut buffer = [0; 4096];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") && request.len() <= MAX_HEADER_SIZE {
+        let read = stream.read(&mut buffer).await?;
+        if read == 0 {
+            return Ok(());
+        }
+        request.extend_from_slice(&buffer[..read]);
+    }
+    let too_large = request.len() > MAX_HEADER_SIZE;
+    let request = String::from_utf8_lossy(&request);
+    let mut words = request.split_whitespace();
+    let (method, path) = (words.next().unwrap_or_default(), words.next().unwrap_or("/").to_string());
+
+    let (status, content_type, body) = if too_large {
+        ("431 Request Header Fields Too Large", "text/plain", b"Request header too large\n".to_vec())
+    } else if method != "GET" && method != "HEAD" {
+        ("405 Method Not Allowed", "text/plain", b"Only GET is supported\n".to_vec())
+    } else {
+        match tokio::task::spawn_blocking(move || route(&path)).await? {
+            Ok(response) => response,
+            Err(err) => ("500 Internal Server Error", "text/plain", format!("{:#}\n", err).into_bytes()),
+        }
+    };
+
+    let header = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    );
+    stream.write_all(header.as_bytes()).await?;
+    if method != "HEAD" {
+        stream.write_all(&body).await?;
+    }
+    stream.shutdown().await?;
+    Ok(())
+}
+
+/// Serves pages for the branches, logs, commits, trees and blobs of the
+/// repository in the current directory on `localhost:<port>` until
+/// interrupted.
+pub fn serve(port: u16) -> anyhow::Result<()> {
+    // Fails early outside a repository rather than on every request.
+    repository::current()?;
+    runtime::block_on(async {
+        let listener = TcpListener::bind(("127.0.0.1", port))
+            .await
+            .map_err(|err| anyhow!("Could not listen on port {}: {}", port, err))?;
+        eprintln!("Serving http://127.0.0.1:{}/", listener.local_addr()?.port());
+        loop {
+            let (stream, _) = listener.accept().await?;
+            tokio::spawn(async move {
+                if let Err(err) = handle(stream).await {
+                    eprintln!("error: {:#}", err);
+                }
+            });
+        }
+    })
+}
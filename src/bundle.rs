@@ -0,0 +1,289 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::anyhow;
+
+use crate::error::Error;
+use crate::object_id::{HashAlgorithm, ObjectId};
+use crate::objects::{peel_to_tree, Object};
+use crate::pack::{self, PackOptions};
+use crate::refs;
+use crate::repository::{self, Repository};
+use crate::rev_list::RevisionSet;
+
+/// The refs and prerequisites a bundle lists before its pack.
+#[derive(Debug, Default)]
+struct Header {
+    format: HashAlgorithm,
+    /// Commits the receiving repository must have, with their subjects.
+    prerequisites: Vec<(ObjectId, String)>,
+    refs: Vec<(ObjectId, String)>,
+}
+
+impl Header {
+    /// Reads the header at the start of `data`, returning it and the
+    /// offset of the pack after it.
+    fn parse(data: &[u8], path: &Path) -> anyhow::Result<(Header, usize)> {
+        let not_a_bundle = || anyhow!("'{}' does not look like a v2 or v3 bundle file", path.display());
+        let mut header = Header::default();
+        let mut offset = 0;
+        let mut next_line = || {
+            let end = data[offset..].iter().position(|&b| b == b'\n')?;
+            let line = std::str::from_utf8(&data[offset..offset + end]).ok();
+            offset += end + 1;
+            line
+        };
+
+        let version = next_line().ok_or_else(not_a_bundle)?;
+        if version != "# v2 git bundle" && version != "# v3 git bundle" {
+            return Err(not_a_bundle());
+        }
+        loop {
+            let line = next_line().ok_or_else(not_a_bundle)?;
+            if line.is_empty() {
+                break;
+            }
+            if let Some(capability) = line.strip_prefix('@').filter(|_| version == "# v3 git bundle") {
+                if let Some(format) = capability.strip_prefix("object-format=") {
+                    header.format = HashAlgorithm::from_name(format)?;
+                }
+                continue;
+            }
+            let (prerequisite, line) = match line.strip_prefix('-') {
+                Some(line) => (true, line),
+                None => (false, line),
+            };
+            let (sha, rest) = line.split_once(' ').unwrap_or((line, ""));
+            let sha: ObjectId = sha.parse().map_err(|_| anyhow!("unrecognized header: {}", line))?;
+            match prerequisite {
+                true => header.prerequisites.push((sha, rest.to_string())),
+                false => header.refs.push((sha, rest.to_string())),
+            }
+        }
+        Ok((header, offset))
+    }
+}
+
+/// The full names of the refs that `args` name as wanted, as git records
+/// them in a bundle: revisions that aren't refs, such as `main~1`, and
+/// excluded ones are left out.
+fn wanted_refs(args: &[String]) -> anyhow::Result<Vec<(ObjectId, String)>> {
+    let mut names = Vec::new();
+    let mut not = false;
+    for arg in args {
+        match arg.as_str() {
+            "--not" => not = !not,
+            _ if not => {}
+            "--all" => names.extend(refs::list_refs("refs/")?),
+            "--branches" => names.extend(refs::list_refs("refs/heads/")?),
+            "--tags" => names.extend(refs::list_refs("refs/tags/")?),
+            "--remotes" => names.extend(refs::list_refs("refs/remotes/")?),
+            arg if arg.starts_with('-') || arg.starts_with('^') => {}
+            arg => {
+                let sides: Vec<&str> = match (arg.split_once("..."), arg.split_once("..")) {
+                    (Some((left, right)), _) => vec![left, right],
+                    (None, Some((_, right))) => vec![right],
+                    (None, None) => vec![arg],
+                };
+                for side in sides {
+                    let side = if side.is_empty() { "HEAD" } else { side };
+                    match side {
+                        "HEAD" => names.push("HEAD".to_string()),
+                        side => names.extend(refs::expand_ref(side)?.map(|(name, _)| name)),
+                    }
+                }
+            }
+        }
+    }
+
+    let mut seen = HashSet::new();
+    let mut wanted = Vec::new();
+    for name in names {
+        if !seen.insert(name.clone()) {
+            continue;
+        }
+        if let Some(sha) = refs::read_ref(&name)? {
+            wanted.push((sha, name));
+        }
+    }
+    Ok(wanted)
+}
+
+/// Adds `tree` and everything under it to `objects`, skipping what's in
+/// `skip`.
+pub(crate) fn add_tree(
+    repository: &Repository,
+    tree: ObjectId,
+    skip: &HashSet<ObjectId>,
+    seen: &mut HashSet<ObjectId>,
+    objects: &mut Vec<ObjectId>,
+) -> anyhow::Result<()> {
+    if skip.contains(&tree) || !seen.insert(tree) {
+        return Ok(());
+    }
+    objects.push(tree);
+    let Object::Tree(entries) = repository.read(&tree)? else {
+        return Err(anyhow!("Object {} is not a tree", tree));
+    };
+    for entry in entries.entries {
+        match entry.kind() {
+            "tree" => add_tree(repository, entry.sha, skip, seen, objects)?,
+            // Submodule commits live in another repository.
+            "commit" => {}
+            _ => {
+                if !skip.contains(&entry.sha) && seen.insert(entry.sha) {
+                    objects.push(entry.sha);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Writes an undeltified pack of `objects`, which every reader takes.
+pub(crate) fn write_pack(repository: &Repository, objects: &[ObjectId], out: &mut Vec<u8>) -> anyhow::Result<()> {
+    let objects: Vec<(ObjectId, u32)> = objects.iter().map(|sha| (*sha, 0)).collect();
+    pack::write(repository, &objects, PackOptions::WHOLE, false, out)?;
+    Ok(())
+}
+
+/// `bundle create`: writes the commits selected by `args`, with their
+/// trees and blobs, and the refs `args` name to `path`. Parents of those
+/// commits that were left out become prerequisites, making the bundle
+/// incremental.
+pub fn create(path: &Path, args: &[String]) -> anyhow::Result<()> {
+    // A bundle is for another repository, which shouldn't get corruption.
+    let repository = repository::current()?.checking_objects();
+    let revisions = RevisionSet::parse(args)?;
+    let commits = revisions.walk()?;
+    let refs = wanted_refs(args)?;
+    if commits.is_empty() || refs.is_empty() {
+        return Err(anyhow!("Refusing to create empty bundle."));
+    }
+
+    let selected: HashSet<ObjectId> = commits.iter().copied().collect();
+    let mut prerequisites = Vec::new();
+    let mut seen = HashSet::new();
+    let mut trees = Vec::new();
+    for sha in &commits {
+        let commit = repository.read_commit(sha)?;
+        for parent in &commit.parents {
+            if !selected.contains(parent) && seen.insert(*parent) {
+                prerequisites.push(*parent);
+            }
+        }
+        trees.push(commit.tree);
+    }
+
+    // Whatever the prerequisites have, the receiver has too.
+    let mut skip = HashSet::new();
+    let mut skipped = Vec::new();
+    for sha in &prerequisites {
+        let (_, tree) = peel_to_tree(sha)?;
+        add_tree(&repository, tree, &HashSet::new(), &mut skip, &mut skipped)?;
+    }
+    let mut objects = commits.clone();
+    let mut seen = HashSet::new();
+    for tree in trees {
+        add_tree(&repository, tree, &skip, &mut seen, &mut objects)?;
+    }
+    // Annotated tags the refs point at, and tags they point at in turn.
+    for (sha, _) in &refs {
+        let mut sha = *sha;
+        while let Object::Tag(tag) = repository.read(&sha)? {
+            if !objects.contains(&sha) {
+                objects.push(sha);
+            }
+            sha = tag.object;
+        }
+    }
+
+    let format = repository.object_format();
+    let mut out = Vec::new();
+    match format {
+        HashAlgorithm::Sha1 => out.extend_from_slice(b"# v2 git bundle\n"),
+        format => write!(out, "# v3 git bundle\n@object-format={}\n", format.name())?,
+    }
+    for sha in &prerequisites {
+        let commit = repository.read_commit(sha)?;
+        writeln!(out, "-{} {}", sha, commit.subject())?;
+    }
+    for (sha, name) in &refs {
+        writeln!(out, "{} {}", sha, name)?;
+    }
+    writeln!(out)?;
+    write_pack(&repository, &objects, &mut out)?;
+    fs::write(path, out)?;
+    Ok(())
+}
+
+fn ref_count(count: usize) -> String {
+    match count {
+        1 => "this ref".to_string(),
+        count => format!("these {} refs", count),
+    }
+}
+
+/// `bundle verify`: checks that `path` is a bundle that the current
+/// repository has every prerequisite of and, unless `quiet`, prints what
+/// it contains. Missing prerequisites are listed and exit with status 1.
+pub fn verify(path: &Path, quiet: bool) -> anyhow::Result<()> {
+    let data = fs::read(path).map_err(|_| anyhow!("could not open '{}'", path.display()));
+    let verified = data.and_then(|data| {
+        let (header, offset) = Header::parse(&data, path)?;
+        if !data[offset..].starts_with(b"PACK") {
+            return Err(anyhow!("'{}' does not contain a pack", path.display()));
+        }
+        Ok(header)
+    });
+    let header = match verified {
+        Ok(header) => header,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            return Err(Error::Status(1).into());
+        }
+    };
+
+    let repository = repository::current()?;
+    if header.format != repository.object_format() {
+        return Err(anyhow!(
+            "The bundle uses the {} hash algorithm, but the repository uses {}",
+            header.format.name(),
+            repository.object_format().name()
+        ));
+    }
+    let missing: Vec<&ObjectId> = header
+        .prerequisites
+        .iter()
+        .map(|(sha, _)| sha)
+        .filter(|sha| !matches!(repository.object_header(sha), Ok((kind, _)) if kind == "commit"))
+        .collect();
+    if !missing.is_empty() {
+        eprintln!("error: Repository lacks these prerequisite commits:");
+        for sha in missing {
+            eprintln!("error: {} ", sha);
+        }
+        return Err(Error::Status(1).into());
+    }
+
+    eprintln!("{} is okay", path.display());
+    if quiet {
+        return Ok(());
+    }
+    println!("The bundle contains {}:", ref_count(header.refs.len()));
+    for (sha, name) in &header.refs {
+        println!("{} {}", sha, name);
+    }
+    if header.prerequisites.is_empty() {
+        println!("The bundle records a complete history.");
+    } else {
+        println!("The bundle requires {}:", ref_count(header.prerequisites.len()));
+        for (sha, _) in &header.prerequisites {
+            println!("{} ", sha);
+        }
+    }
+    println!("The bundle uses this hash algorithm: {}", header.format.name());
+    Ok(())
+}
@@ -0,0 +1,49 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::object_id::ObjectId;
+
+/// Number of inflated objects kept in memory.
+const CAPACITY: usize = 4096;
+
+/// A least-recently-used cache of inflated commits and trees, which
+/// history walks and tree traversals read over and over.
+#[derive(Debug, Default)]
+pub(crate) struct ObjectCache {
+    objects: HashMap<ObjectId, (u64, String, Vec<u8>)>,
+    // Objects by the tick they were last used at, oldest first.
+    order: BTreeMap<u64, ObjectId>,
+    tick: u64,
+}
+
+impl ObjectCache {
+    /// Whether objects of type `kind` are worth caching. Blobs are only
+    /// read once per traversal and can be large.
+    pub fn caches(kind: &str) -> bool {
+        kind == "commit" || kind == "tree"
+    }
+
+    pub fn get(&mut self, sha: &ObjectId) -> Option<(String, Vec<u8>)> {
+        self.tick += 1;
+        let (last_used, kind, data) = self.objects.get_mut(sha)?;
+        self.order.remove(last_used);
+        self.order.insert(self.tick, *sha);
+        *last_used = self.tick;
+
+        Some((kind.clone(), data.clone()))
+    }
+
+    pub fn insert(&mut self, sha: ObjectId, kind: &str, data: &[u8]) {
+        self.tick += 1;
+        if let Some((last_used, _, _)) = self.objects.insert(sha, (self.tick, kind.to_string(), data.to_vec())) {
+            self.order.remove(&last_used);
+        }
+        self.order.insert(self.tick, sha);
+
+        while self.objects.len() > CAPACITY {
+            let Some((_, oldest)) = self.order.pop_first() else {
+                break;
+            };
+            self.objects.remove(&oldest);
+        }
+    }
+}
@@ -0,0 +1,138 @@
+use std::collections::HashSet;
+use std::io::{BufRead, Write};
+
+use anyhow::anyhow;
+
+use crate::json;
+use crate::object_id::ObjectId;
+use crate::refs;
+use crate::repository::{self, Repository};
+
+/// What `cat-file --batch` prints before each object by default.
+const DEFAULT_FORMAT: &str = "%(objectname) %(objecttype) %(objectsize)";
+
+#[derive(Debug, Default)]
+pub struct BatchOptions {
+    /// Print the contents after each header (`--batch`), not only the
+    /// header (`--batch-check`).
+    pub contents: bool,
+    /// The header format, [`DEFAULT_FORMAT`] if not given.
+    pub format: Option<String>,
+    /// Read every object in the repository instead of names on stdin.
+    pub all_objects: bool,
+    /// With `all_objects`, keep the order the object store lists them in
+    /// instead of sorting by object ID.
+    pub unordered: bool,
+    /// Print each header as a JSON record.
+    pub json: bool,
+}
+
+/// Expands the `%(atom)`s of `format` for one object; `rest` is what
+/// followed the object name on the input line.
+fn expand(format: &str, sha: &ObjectId, kind: &str, size: u64, rest: &str) -> anyhow::Result<String> {
+    let mut expanded = String::new();
+    let mut remaining = format;
+    while let Some(start) = remaining.find("%(") {
+        expanded.push_str(&remaining[..start]);
+        let end = remaining[start..]
+            .find(')')
+            .ok_or(anyhow!("format element '{}' does not end in ')'", &remaining[start..]))?;
+        match &remaining[start + 2..start + end] {
+            "objectname" => expanded.push_str(&sha.to_string()),
+            "objecttype" => expanded.push_str(kind),
+            "objectsize" => expanded.push_str(&size.to_string()),
+            "rest" => expanded.push_str(rest),
+            atom => return Err(anyhow!("unknown format element: {}", atom)),
+        }
+        remaining = &remaining[start + end + 1..];
+    }
+    expanded.push_str(remaining);
+    Ok(expanded)
+}
+
+fn write_object(
+    repository: &Repository,
+    sha: &ObjectId,
+    rest: &str,
+    options: &BatchOptions,
+    threshold: u64,
+    out: &mut impl Write,
+) -> anyhow::Result<()> {
+    let (kind, size) = repository.object_header(sha)?;
+    if options.json {
+        let mut record =
+            json::Record::new().string("object", &sha.to_string()).string("type", &kind).number("size", size);
+        if !rest.is_empty() {
+            record = record.string("rest", rest);
+        }
+        writeln!(out, "{}", record)?;
+        return Ok(());
+    }
+
+    let format = options.format.as_deref().unwrap_or(DEFAULT_FORMAT);
+    writeln!(out, "{}", expand(format, sha, &kind, size, rest)?)?;
+    if options.contents {
+        if kind == "blob" && size > threshold {
+            repository.stream_object(sha, out)?;
+        } else {
+            out.write_all(&repository.find_object(sha)?.1)?;
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+/// The `--batch` and `--batch-check` modes of `cat-file`: prints a header
+/// and optionally the contents for each object named on stdin, or for
+/// every object with `all_objects`. Names that don't resolve are
+/// reported as `<name> missing`.
+pub fn batch(options: &BatchOptions) -> anyhow::Result<()> {
+    if options.json && options.contents {
+        return Err(anyhow!("--json is only supported with --batch-check"));
+    }
+    let repository = repository::current()?;
+    // Bad formats fail up front, not at the first object.
+    if let Some(format) = &options.format {
+        expand(format, &repository.object_format().null(), "", 0, "")?;
+    }
+    // Blobs above it are streamed rather than read whole.
+    let threshold = repository.big_file_threshold()?;
+    let mut out = std::io::BufWriter::new(std::io::stdout().lock());
+
+    if options.all_objects {
+        let mut objects = repository.loose_objects()?;
+        let loose: HashSet<ObjectId> = objects.iter().copied().collect();
+        objects.extend(repository.packed_objects().into_iter().filter(|sha| !loose.contains(sha)));
+        if !options.unordered {
+            objects.sort();
+        }
+        for sha in objects {
+            write_object(&repository, &sha, "", options, threshold, &mut out)?;
+        }
+        out.flush()?;
+        return Ok(());
+    }
+
+    // Names are only split from the rest of the line when the format
+    // asks for it, so that names may contain spaces otherwise.
+    let split = options.format.as_deref().is_some_and(|format| format.contains("%(rest)")) || options.json;
+    for line in std::io::stdin().lock().lines() {
+        let line = line?;
+        let (name, rest) = match line.split_once(char::is_whitespace) {
+            Some((name, rest)) if split => (name, rest.trim_start()),
+            _ => (line.as_str(), ""),
+        };
+
+        let found = refs::resolve_revision(name).ok().filter(|sha| repository.object_header(sha).is_ok());
+        match found {
+            Some(sha) => write_object(&repository, &sha, rest, options, threshold, &mut out)?,
+            None if options.json => {
+                writeln!(out, "{}", json::Record::new().string("object", name).string("status", "missing"))?
+            }
+            None => writeln!(out, "{} missing", name)?,
+        }
+        // Callers feeding names one by one wait for each answer.
+        out.flush()?;
+    }
+    Ok(())
+}
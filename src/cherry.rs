@@ -0,0 +1,36 @@
+//! `cherry`: which commits of a branch have no equivalent upstream, by
+//! comparing patch IDs.
+
+use anyhow::anyhow;
+
+use crate::branch;
+use crate::repository;
+use crate::rev_list::RevisionSet;
+
+/// Lists the commits of `head`, `HEAD` by default, that are not in
+/// `upstream`, the upstream branch by default, oldest first: `-` before
+/// those with an equivalent change upstream and `+` before the rest.
+/// Commits up to `limit` are left out.
+pub fn run(upstream: Option<&str>, head: Option<&str>, limit: Option<&str>, verbose: bool) -> anyhow::Result<()> {
+    let upstream = match upstream {
+        Some(upstream) => upstream.to_string(),
+        None => branch::current_upstream()?
+            .ok_or(anyhow!("Could not find a tracked remote branch, please specify <upstream> manually."))?,
+    };
+    let mut args = vec![format!("{}...{}", upstream, head.unwrap_or("HEAD"))];
+    args.extend(limit.map(|limit| format!("^{}", limit)));
+    let mut revisions = RevisionSet::parse(&args)?;
+    revisions.right_only = true;
+    revisions.cherry_mark = true;
+    revisions.no_merges = true;
+
+    let repository = repository::current()?;
+    for (sha, mark) in revisions.walk_marked()?.into_iter().rev() {
+        let sign = if mark == "=" { '-' } else { '+' };
+        match verbose {
+            true => println!("{} {} {}", sign, sha, repository.read_commit(&sha)?.subject()),
+            false => println!("{} {}", sign, sha),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,160 @@
+//! Colored output: whether to use it, from `--color`, `color.<command>`,
+//! `color.ui` and `NO_COLOR`, and the escape sequences of the color
+//! values, such as `bold red`, of the `color.<command>.<slot>` settings.
+
+use anyhow::anyhow;
+
+use crate::config::{self, Config};
+use crate::pager;
+
+/// Ends a colored span.
+pub const RESET: &str = "\x1b[m";
+
+/// When to color output, as `--color=<when>` or a `color.*` setting says.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum When {
+    Always,
+    Never,
+    /// Only when writing to a terminal.
+    Auto,
+}
+
+impl When {
+    /// `always`, `never` or `auto`, or a boolean, where true means `auto`
+    /// as in git.
+    pub fn parse(value: &str) -> Option<When> {
+        match value.to_lowercase().as_str() {
+            "always" => Some(When::Always),
+            "never" => Some(When::Never),
+            "auto" => Some(When::Auto),
+            value => config::parse_bool(value).map(|on| if on { When::Auto } else { When::Never }),
+        }
+    }
+}
+
+/// Whether to color the output of `command`: as `flag`, from `--color`
+/// or `--no-color`, says, else as `color.<command>` or `color.ui` does.
+/// Left to `auto`, the default, output is colored only for a terminal
+/// that isn't `TERM=dumb`, or the pager, and not at all with `NO_COLOR`
+/// set.
+pub fn enabled(config: &Config, command: &str, flag: Option<When>) -> bool {
+    let configured = || config.get(&format!("color.{}", command)).or(config.get("color.ui")).and_then(When::parse);
+    match flag.or_else(configured).unwrap_or(When::Auto) {
+        When::Always => true,
+        When::Never => false,
+        When::Auto => {
+            let no_color = std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+            let term = std::env::var("TERM").is_ok_and(|term| term != "dumb");
+            !no_color && term && pager::to_terminal()
+        }
+    }
+}
+
+/// The escape sequence of `color.<section>.<slot>`, or of `default` if
+/// it isn't set.
+pub fn slot(config: &Config, section: &str, slot: &str, default: &str) -> anyhow::Result<String> {
+    match config.get(&format!("color.{}.{}", section, slot)) {
+        Some(value) => parse(value).ok_or(anyhow!("invalid color value: {}", value)),
+        None => Ok(parse(default).expect("Default colors are valid")),
+    }
+}
+
+/// The escape sequence that sets `value`: a foreground and then a
+/// background color, each a name such as `red` or `brightblue`, a number
+/// up to 255 or `#rrggbb`, and attributes such as `bold` or `noul`.
+/// Empty for `normal`, which leaves everything as it is.
+pub fn parse(value: &str) -> Option<String> {
+    const ATTRIBUTES: &[(&str, u8, u8)] =
+        &[("bold", 1, 22), ("dim", 2, 22), ("italic", 3, 23), ("ul", 4, 24), ("blink", 5, 25), ("reverse", 7, 27),
+            ("strike", 9, 29)];
+    let mut attributes = Vec::new();
+    let mut colors: Vec<Option<String>> = Vec::new();
+    for word in value.split_whitespace().map(str::to_lowercase) {
+        let negated = word.strip_prefix("no-").or(word.strip_prefix("no"));
+        let attribute = ATTRIBUTES.iter().find_map(|(name, set, unset)| match negated {
+            Some(negated) if negated == *name => Some(*unset),
+            _ if word == *name => Some(*set),
+            _ => None,
+        });
+        match attribute {
+            Some(code) => attributes.push(code),
+            None if colors.len() < 2 => colors.push(parse_color(&word, colors.is_empty())?),
+            None => return None,
+        }
+    }
+    attributes.sort_unstable();
+    attributes.dedup();
+    let mut codes: Vec<String> = attributes.iter().map(u8::to_string).collect();
+    codes.extend(colors.into_iter().flatten());
+    match codes.is_empty() {
+        true => Some(String::new()),
+        false => Some(format!("\x1b[{}m", codes.join(";"))),
+    }
+}
+
+/// The code of one color `word`, for the foreground or the background,
+/// or `None` inside for `normal`.
+fn parse_color(word: &str, foreground: bool) -> Option<Option<String>> {
+    const NAMES: &[&str] = &["black", "red", "green", "yellow", "blue", "magenta", "cyan", "white"];
+    let (base, extended) = if foreground { (30, 38) } else { (40, 48) };
+    if word == "normal" {
+        return Some(None);
+    }
+    if word == "default" {
+        return Some(Some((base + 9).to_string()));
+    }
+    if let Some(i) = NAMES.iter().position(|name| *name == word) {
+        return Some(Some((base + i).to_string()));
+    }
+    if let Some(i) = word.strip_prefix("bright").and_then(|name| NAMES.iter().position(|other| *other == name)) {
+        return Some(Some((base + 60 + i).to_string()));
+    }
+    if let Some(hex) = word.strip_prefix('#').filter(|hex| hex.len() == 6) {
+        let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+        return Some(Some(format!("{};2;{};{};{}", extended, channel(0)?, channel(2)?, channel(4)?)));
+    }
+    match word.parse::<i32>().ok()? {
+        -1 => Some(None),
+        number @ 0..=7 => Some(Some((base + number as usize).to_string())),
+        number @ 8..=255 => Some(Some(format!("{};5;{}", extended, number))),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn color_values() {
+        assert_eq!(parse("bold red").as_deref(), Some("\x1b[1;31m"));
+        assert_eq!(parse("red bold").as_deref(), Some("\x1b[1;31m"));
+        assert_eq!(parse("ul reverse blue yellow").as_deref(), Some("\x1b[4;7;34;43m"));
+        assert_eq!(parse("brightgreen nobold").as_deref(), Some("\x1b[22;92m"));
+        assert_eq!(parse("208 #ff8000").as_deref(), Some("\x1b[38;5;208;48;2;255;128;0m"));
+        assert_eq!(parse("normal black").as_deref(), Some("\x1b[40m"));
+        assert_eq!(parse("Default").as_deref(), Some("\x1b[39m"));
+        assert_eq!(parse("normal").as_deref(), Some(""));
+        assert_eq!(parse("").as_deref(), Some(""));
+        assert_eq!(parse("red green blue"), None);
+        assert_eq!(parse("purple"), None);
+        assert_eq!(parse("256"), None);
+    }
+
+    #[test]
+    fn when_to_color() {
+        let config = Config::parse("[color]\n\tui = never\n\tbranch = always\n").unwrap();
+        assert!(enabled(&config, "branch", None));
+        assert!(!enabled(&config, "diff", None));
+        assert!(enabled(&config, "diff", Some(When::Always)));
+        assert!(!enabled(&config, "branch", Some(When::Never)));
+        assert_eq!(When::parse("true"), Some(When::Auto));
+        assert_eq!(When::parse("off"), Some(When::Never));
+        assert_eq!(When::parse("sometimes"), None);
+
+        let config = Config::parse("[color \"diff\"]\n\tmeta = yellow bold\n\tfrag = rainbow\n").unwrap();
+        assert_eq!(slot(&config, "diff", "meta", "bold").unwrap(), "\x1b[1;33m");
+        assert_eq!(slot(&config, "diff", "old", "red").unwrap(), "\x1b[31m");
+        assert!(slot(&config, "diff", "frag", "cyan").is_err());
+    }
+}
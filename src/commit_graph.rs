@@ -0,0 +1,497 @@
+//! Writes `info/commit-graph` in the object directory, which lets git walk history
+//! without parsing every commit, or a chain of such graphs in `info/commit-graphs`
+//! that grows by a layer at a time. See gitformat-commit-graph(5).
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::path::Path;
+
+use anyhow::anyhow;
+use bytes::{BufMut, BytesMut};
+
+use crate::ident::identity_time;
+use crate::lockfile;
+use crate::object_id::{HashAlgorithm, ObjectId};
+use crate::objects::{self, Object};
+use crate::refs;
+use crate::repository::{self, Repository};
+
+const NO_PARENT: u32 = 0x7000_0000;
+const OCTOPUS: u32 = 0x8000_0000;
+const LAST_EDGE: u32 = 0x8000_0000;
+
+/// Past this many changed paths a commit gets a filter that matches
+/// every path.
+const MAX_CHANGED_PATHS: usize = 512;
+const BLOOM_HASHES: u32 = 7;
+const BLOOM_BITS_PER_ENTRY: usize = 10;
+
+/// How a commit-graph is written.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct WriteOptions {
+    /// Add a layer to the chain in `info/commit-graphs` with only the
+    /// commits it lacks, rather than rewrite a single graph.
+    pub split: bool,
+    /// Record changed-path Bloom filters, which are also kept without
+    /// this if the graph being replaced or extended had them.
+    pub changed_paths: bool,
+}
+
+struct Entry {
+    tree: ObjectId,
+    parents: Vec<ObjectId>,
+    time: u64,
+    generation: u32,
+}
+
+/// A graph already on disk: its checksum, which names it in a chain,
+/// and its commits in order with their generations.
+struct Graph {
+    checksum: ObjectId,
+    commits: Vec<ObjectId>,
+    generations: Vec<u32>,
+    changed_paths: bool,
+}
+
+/// Writes the commit-graph of the current repository, as `commit-graph
+/// write --reachable` does.
+pub fn run(options: WriteOptions) -> anyhow::Result<()> {
+    write(&repository::current()?, options)?;
+    Ok(())
+}
+
+/// Writes a commit-graph covering every commit reachable from a ref and
+/// returns how many commits it holds.
+pub(crate) fn write(repository: &Repository, options: WriteOptions) -> anyhow::Result<usize> {
+    let format = repository.object_format();
+    let info = repository.object_dir().join("info");
+    let graph_path = info.join("commit-graph");
+    let chain_dir = info.join("commit-graphs");
+    let chain_path = chain_dir.join("commit-graph-chain");
+
+    let mut chain = read_chain(&chain_dir, format)?;
+    let single = match graph_path.exists() {
+        true => Some(read_graph(&graph_path, format)?),
+        false => None,
+    };
+    let changed_paths =
+        options.changed_paths || chain.iter().chain(&single).any(|graph| graph.changed_paths);
+    if !options.split {
+        chain.clear();
+    }
+
+    let mut tips = Vec::new();
+    for name in refs::list_refs("refs/")? {
+        tips.extend(refs::read_ref(&name)?);
+    }
+    tips.extend(refs::read_ref("HEAD")?);
+
+    // Commits already in a layer that is kept aren't read again.
+    let mut known: HashMap<ObjectId, u32> = chain
+        .iter()
+        .flat_map(|graph| graph.commits.iter().copied().zip(graph.generations.iter().copied()))
+        .collect();
+    let mut commits: BTreeMap<ObjectId, Entry> = BTreeMap::new();
+    collect(repository, tips, &known, &mut commits)?;
+    if options.split && commits.is_empty() && !chain.is_empty() {
+        return Ok(known.len());
+    }
+
+    // As git does by default, the new layer takes in the layers above
+    // any with more than twice its commits.
+    while chain.last().is_some_and(|top| top.commits.len() <= 2 * commits.len()) {
+        let top = chain.pop().unwrap_or_else(|| unreachable!());
+        for sha in &top.commits {
+            known.remove(sha);
+        }
+        collect(repository, top.commits, &known, &mut commits)?;
+    }
+
+    let generations = generations(&commits, &known);
+    for (sha, entry) in commits.iter_mut() {
+        entry.generation = generations[sha];
+    }
+
+    let base: Vec<ObjectId> = chain.iter().map(|graph| graph.checksum).collect();
+    let base_positions: HashMap<ObjectId, u32> =
+        chain.iter().flat_map(|graph| graph.commits.iter().copied()).zip(0..).collect();
+    let filters = match changed_paths {
+        true => Some(bloom_filters(repository, &commits)?),
+        false => None,
+    };
+    let graph = serialize(&commits, &base, &base_positions, filters, format)?;
+
+    if !options.split {
+        fs::create_dir_all(&info)?;
+        lockfile::write(&graph_path, graph)?;
+        if chain_dir.exists() {
+            fs::remove_dir_all(&chain_dir)?;
+        }
+        return Ok(commits.len());
+    }
+
+    let checksum = format.read(&graph[graph.len() - format.byte_len()..]).unwrap_or_else(|| unreachable!());
+    fs::create_dir_all(&chain_dir)?;
+    lockfile::write(&chain_dir.join(format!("graph-{}.graph", checksum)), graph)?;
+    let names: String = base.iter().chain([&checksum]).map(|sha| format!("{}\n", sha)).collect();
+    lockfile::write(&chain_path, names.into_bytes())?;
+
+    // What the new chain no longer uses, including a single graph it
+    // replaces.
+    for entry in fs::read_dir(&chain_dir)?.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let in_chain = base.iter().chain([&checksum]).any(|sha| name == format!("graph-{}.graph", sha));
+        if name.ends_with(".graph") && !in_chain {
+            fs::remove_file(entry.path())?;
+        }
+    }
+    if single.is_some() {
+        fs::remove_file(&graph_path)?;
+    }
+    Ok(base_positions.len() + commits.len())
+}
+
+/// Adds the commits reachable from `pending` to `commits`, stopping at
+/// those that are `known` from the layers below.
+fn collect(
+    repository: &Repository,
+    mut pending: Vec<ObjectId>,
+    known: &HashMap<ObjectId, u32>,
+    commits: &mut BTreeMap<ObjectId, Entry>,
+) -> anyhow::Result<()> {
+    while let Some(sha) = pending.pop() {
+        if commits.contains_key(&sha) || known.contains_key(&sha) {
+            continue;
+        }
+        match repository.read(&sha)? {
+            Object::Tag(tag) => pending.push(tag.object),
+            Object::Commit(commit) => {
+                pending.extend(commit.parents.iter().copied());
+                let time = identity_time(&commit.committer).max(0) as u64;
+                commits.insert(sha, Entry { tree: commit.tree, parents: commit.parents, time, generation: 0 });
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// The layers of the chain in `dir`, base first, or none if there is no
+/// chain.
+fn read_chain(dir: &Path, format: HashAlgorithm) -> anyhow::Result<Vec<Graph>> {
+    let Ok(names) = fs::read_to_string(dir.join("commit-graph-chain")) else { return Ok(Vec::new()) };
+    names
+        .lines()
+        .map(|name| {
+            let graph = read_graph(&dir.join(format!("graph-{}.graph", name)), format)?;
+            match graph.checksum.to_string() == name {
+                true => Ok(graph),
+                false => Err(anyhow!("Commit-graph chain names {}, but its layer hashes to {}", name, graph.checksum)),
+            }
+        })
+        .collect()
+}
+
+/// Reads what `write` needs from the graph at `path`.
+fn read_graph(path: &Path, format: HashAlgorithm) -> anyhow::Result<Graph> {
+    let data = fs::read(path)?;
+    let bad = || anyhow!("Bad commit-graph {}", path.display());
+    let hash_len = format.byte_len();
+    if data.len() < 8 + 12 + hash_len || &data[..4] != b"CGPH" || data[4] != 1 {
+        return Err(bad());
+    }
+
+    let mut chunks = HashMap::new();
+    for i in 0..data[6] as usize {
+        let entry = data.get(8 + i * 12..20 + i * 12).ok_or_else(bad)?;
+        let next = data.get(20 + i * 12..32 + i * 12).ok_or_else(bad)?;
+        let offset = |entry: &[u8]| u64::from_be_bytes(entry[4..12].try_into().unwrap_or_default()) as usize;
+        let chunk = data.get(offset(entry)..offset(next)).ok_or_else(bad)?;
+        chunks.insert(<[u8; 4]>::try_from(&entry[..4]).unwrap_or_default(), chunk);
+    }
+
+    let lookup = chunks.get(b"OIDL").ok_or_else(bad)?;
+    let commit_data = chunks.get(b"CDAT").ok_or_else(bad)?;
+    let count = lookup.len() / hash_len;
+    if commit_data.len() != count * (hash_len + 16) {
+        return Err(bad());
+    }
+    let commits = lookup.chunks(hash_len).map(|sha| format.read(sha).ok_or_else(bad)).collect::<anyhow::Result<_>>()?;
+    let generations = commit_data
+        .chunks(hash_len + 16)
+        .map(|entry| u32::from_be_bytes(entry[hash_len + 8..hash_len + 12].try_into().unwrap_or_default()) >> 2)
+        .collect();
+    let checksum = format.read(&data[data.len() - hash_len..]).ok_or_else(bad)?;
+    Ok(Graph { checksum, commits, generations, changed_paths: chunks.contains_key(b"BDAT") })
+}
+
+/// Numbers each commit one above its highest parent, starting from 1,
+/// where parents in the layers below have their `known` numbers.
+fn generations(commits: &BTreeMap<ObjectId, Entry>, known: &HashMap<ObjectId, u32>) -> HashMap<ObjectId, u32> {
+    let mut generations = known.clone();
+
+    for sha in commits.keys() {
+        let mut pending = vec![*sha];
+        while let Some(sha) = pending.last().copied() {
+            if generations.contains_key(&sha) {
+                pending.pop();
+                continue;
+            }
+            let parents = &commits[&sha].parents;
+            let missing: Vec<ObjectId> = parents.iter()
+                .filter(|parent| !generations.contains_key(*parent))
+                .copied()
+                .collect();
+            if missing.is_empty() {
+                let generation = parents.iter().map(|parent| generations[parent]).max().unwrap_or(0) + 1;
+                generations.insert(sha, generation);
+                pending.pop();
+            } else {
+                pending.extend(missing);
+            }
+        }
+    }
+
+    generations
+}
+
+/// Lays out a graph of `commits` above the layers `base`, whose commits
+/// take the first positions.
+fn serialize(
+    commits: &BTreeMap<ObjectId, Entry>,
+    base: &[ObjectId],
+    base_positions: &HashMap<ObjectId, u32>,
+    filters: Option<(BytesMut, BytesMut)>,
+    format: HashAlgorithm,
+) -> anyhow::Result<Vec<u8>> {
+    let offset = base_positions.len() as u32;
+    let positions: HashMap<&ObjectId, u32> = commits.keys().zip(offset..).collect();
+    let position = |sha: &ObjectId| positions.get(sha)
+        .or_else(|| base_positions.get(sha))
+        .copied()
+        .ok_or(anyhow!("Parent {} is missing from the commit-graph", sha));
+
+    let mut fanout = BytesMut::new();
+    let mut count = 0;
+    for byte in 0..=255u8 {
+        count += commits.keys().filter(|sha| sha.as_ref()[0] == byte).count() as u32;
+        fanout.put_u32(count);
+    }
+
+    let mut lookup = BytesMut::new();
+    for sha in commits.keys() {
+        lookup.put_slice(sha.as_ref());
+    }
+
+    let mut data = BytesMut::new();
+    let mut edges = BytesMut::new();
+    for entry in commits.values() {
+        data.put_slice(entry.tree.as_ref());
+        let first = entry.parents.first().map(&position).transpose()?.unwrap_or(NO_PARENT);
+        let second = match entry.parents.len() {
+            0 | 1 => NO_PARENT,
+            2 => position(&entry.parents[1])?,
+            _ => {
+                let edge = OCTOPUS | (edges.len() / 4) as u32;
+                for (index, parent) in entry.parents.iter().enumerate().skip(1) {
+                    let last = if index + 1 == entry.parents.len() { LAST_EDGE } else { 0 };
+                    edges.put_u32(position(parent)? | last);
+                }
+                edge
+            }
+        };
+        data.put_u32(first);
+        data.put_u32(second);
+        data.put_u32((entry.generation << 2) | ((entry.time >> 32) & 0b11) as u32);
+        data.put_u32(entry.time as u32);
+    }
+
+    let mut chunks: Vec<(&[u8; 4], &[u8])> = vec![(b"OIDF", &fanout), (b"OIDL", &lookup), (b"CDAT", &data)];
+    if !edges.is_empty() {
+        chunks.push((b"EDGE", &edges));
+    }
+    if let Some((index, data)) = &filters {
+        chunks.push((b"BIDX", index));
+        chunks.push((b"BDAT", data));
+    }
+    let base: Vec<u8> = base.iter().flat_map(|sha| sha.as_ref().to_vec()).collect();
+    if !base.is_empty() {
+        chunks.push((b"BASE", &base));
+    }
+
+    let mut graph = BytesMut::new();
+    graph.put_slice(b"CGPH");
+    graph.put_u8(1);
+    graph.put_u8(match format {
+        HashAlgorithm::Sha1 => 1,
+        HashAlgorithm::Sha256 => 2,
+    });
+    graph.put_u8(chunks.len() as u8);
+    graph.put_u8((base.len() / format.byte_len()) as u8);
+
+    let mut offset = (8 + (chunks.len() + 1) * 12) as u64;
+    for (id, chunk) in &chunks {
+        graph.put_slice(*id);
+        graph.put_u64(offset);
+        offset += chunk.len() as u64;
+    }
+    graph.put_slice(&[0; 4]);
+    graph.put_u64(offset);
+    for (_, chunk) in &chunks {
+        graph.put_slice(chunk);
+    }
+
+    let checksum = format.hash(&graph);
+    graph.put_slice(checksum.as_ref());
+    Ok(graph.to_vec())
+}
+
+/// The BIDX and BDAT chunks: for each commit, a Bloom filter of the paths
+/// it changes from its first parent and their directories, which lets
+/// `git log -- <path>` skip commits that can't touch the path.
+fn bloom_filters(repository: &Repository, commits: &BTreeMap<ObjectId, Entry>) -> anyhow::Result<(BytesMut, BytesMut)> {
+    let mut index = BytesMut::new();
+    let mut data = BytesMut::new();
+    data.put_u32(1);
+    data.put_u32(BLOOM_HASHES);
+    data.put_u32(BLOOM_BITS_PER_ENTRY as u32);
+    let mut end = 0;
+    for entry in commits.values() {
+        let old = match entry.parents.first() {
+            Some(parent) => match repository.read(parent)? {
+                Object::Commit(parent) => objects::tree_files(&parent.tree)?,
+                _ => return Err(anyhow!("Parent {} is not a commit", parent)),
+            },
+            None => BTreeMap::new(),
+        };
+        let new = objects::tree_files(&entry.tree)?;
+        let mut changed: Vec<&String> = old.keys().filter(|path| new.get(*path) != old.get(*path)).collect();
+        changed.extend(new.keys().filter(|path| !old.contains_key(*path)));
+
+        let filter = bloom_filter(&changed);
+        end += filter.len() as u32;
+        index.put_u32(end);
+        data.put_slice(&filter);
+    }
+    Ok((index, data))
+}
+
+fn bloom_filter(changed: &[&String]) -> Vec<u8> {
+    let mut paths = BTreeSet::new();
+    if changed.len() <= MAX_CHANGED_PATHS {
+        for path in changed {
+            paths.insert(path.as_str());
+            paths.extend(path.match_indices('/').map(|(slash, _)| &path[..slash]));
+        }
+    }
+    if changed.len() > MAX_CHANGED_PATHS || paths.len() > MAX_CHANGED_PATHS {
+        return vec![0xff];
+    }
+
+    let mut filter = vec![0; (paths.len() * BLOOM_BITS_PER_ENTRY).div_ceil(8).max(1)];
+    let bits = filter.len() as u64 * 8;
+    for path in paths {
+        let first = murmur3(0x293a_e76f, path.as_bytes());
+        let step = murmur3(0x7e64_6e2c, path.as_bytes());
+        for i in 0..BLOOM_HASHES {
+            let bit = first.wrapping_add(i.wrapping_mul(step)) as u64 % bits;
+            filter[(bit / 8) as usize] |= 1 << (bit % 8);
+        }
+    }
+    filter
+}
+
+/// 32-bit murmur3 as git's version 1 filters compute it, reading bytes
+/// as signed `char`s, which changes the hash of non-ASCII paths.
+fn murmur3(seed: u32, data: &[u8]) -> u32 {
+    let byte = |b: u8| b as i8 as u32;
+    let scramble = |k: u32| k.wrapping_mul(0xcc9e_2d51).rotate_left(15).wrapping_mul(0x1b87_3593);
+
+    let mut hash = seed;
+    let blocks = data.chunks_exact(4);
+    let tail = blocks.remainder();
+    for block in blocks {
+        let k = byte(block[0]) | byte(block[1]) << 8 | byte(block[2]) << 16 | byte(block[3]) << 24;
+        hash = (hash ^ scramble(k)).rotate_left(13).wrapping_mul(5).wrapping_add(0xe654_6b64);
+    }
+    if !tail.is_empty() {
+        let k = tail.iter().enumerate().fold(0, |k, (i, &b)| k ^ byte(b) << (8 * i));
+        hash ^= scramble(k);
+    }
+
+    hash ^= data.len() as u32;
+    hash ^= hash >> 16;
+    hash = hash.wrapping_mul(0x85eb_ca6b);
+    hash ^= hash >> 13;
+    hash = hash.wrapping_mul(0xc2b2_ae35);
+    hash ^ hash >> 16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn murmur3_vectors() {
+        assert_eq!(murmur3(0, b""), 0);
+        assert_eq!(murmur3(0, b"Hello world!"), 0x627b_0c2c);
+        assert_eq!(murmur3(0, b"The quick brown fox jumps over the lazy dog"), 0x2e4f_f723);
+    }
+
+    #[test]
+    fn bloom_filters_match_git() {
+        // As `git commit-graph write --changed-paths` writes it for a
+        // commit adding dir/file.txt.
+        let path = "dir/file.txt".to_string();
+        assert_eq!(bloom_filter(&[&path]), [0x87, 0x60, 0x3a]);
+        assert_eq!(bloom_filter(&[]), [0]);
+
+        let paths: Vec<String> = (0..=MAX_CHANGED_PATHS).map(|i| i.to_string()).collect();
+        assert_eq!(bloom_filter(&paths.iter().collect::<Vec<_>>()), [0xff]);
+    }
+
+    fn commit(format: HashAlgorithm, name: &str, parents: &[ObjectId]) -> (ObjectId, Entry) {
+        let entry = Entry { tree: format.hash(b"tree"), parents: parents.to_vec(), time: 1 << 33, generation: 0 };
+        (format.hash(name.as_bytes()), entry)
+    }
+
+    #[test]
+    fn graphs_round_trip() {
+        let format = HashAlgorithm::Sha1;
+        let (root, root_entry) = commit(format, "root", &[]);
+        let (a, a_entry) = commit(format, "a", &[root]);
+        let (b, b_entry) = commit(format, "b", &[root]);
+        let (c, c_entry) = commit(format, "c", &[a]);
+        let (merge, merge_entry) = commit(format, "merge", &[c, b, a]);
+        let mut commits: BTreeMap<ObjectId, Entry> =
+            [(root, root_entry), (a, a_entry), (b, b_entry), (c, c_entry), (merge, merge_entry)].into();
+
+        let generations = generations(&commits, &HashMap::new());
+        assert_eq!([root, a, b, c, merge].map(|sha| generations[&sha]), [1, 2, 2, 3, 4]);
+        for (sha, entry) in commits.iter_mut() {
+            entry.generation = generations[sha];
+        }
+
+        let data = serialize(&commits, &[], &HashMap::new(), None, format).unwrap();
+        let path = std::env::temp_dir().join(format!("git-starter-rust-commit-graph-{}", std::process::id()));
+        fs::write(&path, &data).unwrap();
+        let graph = read_graph(&path, format);
+        fs::write(&path, &data[..data.len() / 2]).unwrap();
+        let truncated = read_graph(&path, format);
+        fs::remove_file(&path).unwrap();
+
+        let graph = graph.unwrap();
+        assert_eq!(graph.checksum, format.hash(&data[..data.len() - 20]));
+        assert_eq!(graph.commits, commits.keys().copied().collect::<Vec<_>>());
+        assert_eq!(graph.generations, commits.values().map(|entry| entry.generation).collect::<Vec<_>>());
+        assert!(!graph.changed_paths);
+        assert!(truncated.is_err());
+
+        // A layer above it can only name parents it or the base has.
+        let (top, top_entry) = commit(format, "top", &[merge]);
+        let layer: BTreeMap<ObjectId, Entry> = [(top, top_entry)].into();
+        let positions: HashMap<ObjectId, u32> = graph.commits.iter().copied().zip(0..).collect();
+        assert!(serialize(&layer, &[graph.checksum], &positions, None, format).is_ok());
+        assert!(serialize(&layer, &[], &HashMap::new(), None, format).is_err());
+    }
+}
@@ -0,0 +1,168 @@
+use std::fmt::Write;
+
+use anyhow::anyhow;
+use clap::Command;
+
+use crate::refs;
+
+/// Value names of the arguments that take a branch, tag or other
+/// revision, so the scripts offer ref names for them.
+const REF_VALUE_NAMES: &[&str] =
+    &["BRANCH", "COMMIT", "COMMIT_ISH", "END", "OBJECT", "REF", "REV", "REVISION", "START", "UPSTREAM"];
+
+/// The shells [`generate`] writes completion scripts for.
+pub const SHELLS: &[&str] = &["bash", "zsh", "fish", "powershell"];
+
+/// What to offer after a command path: its subcommands and options, and
+/// whether it takes refs.
+struct Entry {
+    path: String,
+    words: Vec<String>,
+    refs: bool,
+}
+
+fn collect(command: &Command, path: &str, entries: &mut Vec<Entry>) {
+    let mut words: Vec<String> = command
+        .get_subcommands()
+        .filter(|subcommand| !subcommand.is_hide_set())
+        .map(|subcommand| subcommand.get_name().to_string())
+        .collect();
+    let mut refs = false;
+    for arg in command.get_arguments().filter(|arg| !arg.is_hide_set()) {
+        if arg.is_positional() {
+            let value_names = arg.get_value_names().unwrap_or_default();
+            refs |= value_names.iter().any(|name| REF_VALUE_NAMES.contains(&name.as_str()));
+            continue;
+        }
+        words.extend(arg.get_long().map(|long| format!("--{}", long)));
+        words.extend(arg.get_short().map(|short| format!("-{}", short)));
+    }
+    entries.push(Entry { path: path.to_string(), words, refs });
+
+    for subcommand in command.get_subcommands().filter(|subcommand| !subcommand.is_hide_set()) {
+        collect(subcommand, &format!("{}/{}", path, subcommand.get_name()), entries);
+    }
+}
+
+/// The branch and tag names the scripts offer, as `completions --refs`
+/// prints them.
+pub fn ref_names() -> anyhow::Result<Vec<String>> {
+    let mut names = Vec::new();
+    for prefix in ["refs/heads/", "refs/tags/"] {
+        names.extend(refs::list_refs(prefix)?.iter().map(|name| name[prefix.len()..].to_string()));
+    }
+    Ok(names)
+}
+
+/// Writes a completion script for `shell` that completes the
+/// subcommands and options of `command`, installed as `bin`, and asks
+/// `bin completions --refs` for ref names where a command takes them.
+pub fn generate(command: &Command, shell: &str, bin: &str) -> anyhow::Result<String> {
+    let mut entries = Vec::new();
+    collect(command, "", &mut entries);
+    let paths: Vec<&str> = entries.iter().skip(1).map(|entry| entry.path.as_str()).collect();
+    let function = format!("_{}", bin.replace(|c: char| !c.is_ascii_alphanumeric(), "_"));
+
+    let mut script = String::new();
+    match shell {
+        "bash" => {
+            writeln!(script, "{}() {{", function)?;
+            writeln!(script, "    local cur=${{COMP_WORDS[COMP_CWORD]}} path=\"\" opts=\"\" refs=0 word")?;
+            writeln!(script, "    for word in \"${{COMP_WORDS[@]:1:COMP_CWORD-1}}\"; do")?;
+            writeln!(script, "        case \"$path/$word\" in")?;
+            writeln!(script, "            {}) path=\"$path/$word\" ;;", paths.join("|"))?;
+            writeln!(script, "        esac\n    done")?;
+            writeln!(script, "    case \"$path\" in")?;
+            for entry in &entries {
+                writeln!(
+                    script,
+                    "        \"{}\") opts=\"{}\"; refs={} ;;",
+                    entry.path,
+                    entry.words.join(" "),
+                    entry.refs as u8
+                )?;
+            }
+            writeln!(script, "    esac")?;
+            writeln!(script, "    if [[ $refs == 1 && $cur != -* ]]; then")?;
+            writeln!(script, "        opts=\"$opts $({} completions --refs 2>/dev/null)\"", bin)?;
+            writeln!(script, "    fi")?;
+            writeln!(script, "    COMPREPLY=($(compgen -W \"$opts\" -- \"$cur\"))")?;
+            writeln!(script, "}}")?;
+            writeln!(script, "complete -F {} -o bashdefault -o default {}", function, bin)?;
+        }
+        "zsh" => {
+            writeln!(script, "#compdef {}\n", bin)?;
+            writeln!(script, "{}() {{", function)?;
+            writeln!(script, "    local path=\"\" opts=\"\" refs=0 word")?;
+            writeln!(script, "    for word in ${{words[2,CURRENT-1]}}; do")?;
+            writeln!(script, "        case \"$path/$word\" in")?;
+            writeln!(script, "            ({}) path=\"$path/$word\" ;;", paths.join("|"))?;
+            writeln!(script, "        esac\n    done")?;
+            writeln!(script, "    case \"$path\" in")?;
+            for entry in &entries {
+                writeln!(
+                    script,
+                    "        (\"{}\") opts=\"{}\"; refs={} ;;",
+                    entry.path,
+                    entry.words.join(" "),
+                    entry.refs as u8
+                )?;
+            }
+            writeln!(script, "    esac")?;
+            writeln!(script, "    local -a candidates\n    candidates=(${{=opts}})")?;
+            writeln!(script, "    if (( refs )) && [[ $PREFIX != -* ]]; then")?;
+            writeln!(script, "        candidates+=(${{(f)\"$({} completions --refs 2>/dev/null)\"}})", bin)?;
+            writeln!(script, "    fi")?;
+            writeln!(script, "    compadd -a candidates\n    _files")?;
+            writeln!(script, "}}\n")?;
+            writeln!(script, "compdef {} {}", function, bin)?;
+        }
+        "fish" => {
+            writeln!(script, "function _{}_complete", function)?;
+            writeln!(script, "    set -l path \"\"")?;
+            writeln!(script, "    for word in (commandline -opc)[2..-1]")?;
+            writeln!(script, "        switch \"$path/$word\"")?;
+            writeln!(script, "            case {}", paths.join(" "))?;
+            writeln!(script, "                set path \"$path/$word\"")?;
+            writeln!(script, "        end\n    end")?;
+            writeln!(script, "    switch \"$path\"")?;
+            for entry in &entries {
+                let path = if entry.path.is_empty() { "''" } else { entry.path.as_str() };
+                writeln!(script, "        case {}", path)?;
+                writeln!(script, "            printf '%s\\n' {}", entry.words.join(" "))?;
+                if entry.refs {
+                    writeln!(script, "            {} completions --refs 2>/dev/null", bin)?;
+                }
+            }
+            writeln!(script, "    end\nend")?;
+            writeln!(script, "complete -c {} -a '(_{}_complete)'", bin, function)?;
+        }
+        "powershell" => {
+            writeln!(script, "Register-ArgumentCompleter -Native -CommandName '{}' -ScriptBlock {{", bin)?;
+            writeln!(script, "    param($wordToComplete, $commandAst, $cursorPosition)")?;
+            let quoted: Vec<String> = paths.iter().map(|path| format!("'{}'", path)).collect();
+            writeln!(script, "    $paths = @({})", quoted.join(", "))?;
+            writeln!(script, "    $path = ''")?;
+            writeln!(script, "    foreach ($element in $commandAst.CommandElements | Select-Object -Skip 1) {{")?;
+            writeln!(script, "        $word = $element.ToString()")?;
+            writeln!(script, "        if ($element.Extent.StartOffset -ge $cursorPosition) {{ break }}")?;
+            writeln!(script, "        if ($paths -contains \"$path/$word\") {{ $path = \"$path/$word\" }}")?;
+            writeln!(script, "    }}")?;
+            writeln!(script, "    $opts = switch ($path) {{")?;
+            for entry in &entries {
+                let words: Vec<String> = entry.words.iter().map(|word| format!("'{}'", word)).collect();
+                let refs =
+                    if entry.refs { format!(" + @(& '{}' completions --refs 2>$null)", bin) } else { String::new() };
+                writeln!(script, "        '{}' {{ @({}){} }}", entry.path, words.join(", "), refs)?;
+            }
+            writeln!(script, "    }}")?;
+            writeln!(script, "    $opts | Where-Object {{ $_ -like \"$wordToComplete*\" }} | ForEach-Object {{")?;
+            writeln!(script, "        [System.Management.Automation.CompletionResult]::new($_)")?;
+            writeln!(script, "    }}")?;
+            writeln!(script, "}}")?;
+        }
+        shell => return Err(anyhow!("Unsupported shell: {}", shell)),
+    }
+
+    Ok(script)
+}
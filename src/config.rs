@@ -0,0 +1,713 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::anyhow;
+
+use crate::error::Error;
+use crate::ident;
+use crate::lockfile;
+use crate::repository;
+
+/// A git config file, kept line by line so that rewriting it preserves
+/// comments and formatting of everything that wasn't touched.
+#[derive(Debug, Default, Clone)]
+pub struct Config {
+    lines: Vec<Line>,
+}
+
+#[derive(Debug, Clone)]
+struct Line {
+    raw: String,
+    kind: LineKind,
+}
+
+#[derive(Debug, Clone)]
+enum LineKind {
+    Section(SectionName),
+    Entry { section: SectionName, key: String, value: Option<String> },
+    Other,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct SectionName {
+    // Section names are case-insensitive and stored lowercased,
+    // subsection names are case-sensitive.
+    name: String,
+    subsection: Option<String>,
+}
+
+impl SectionName {
+    fn header(&self) -> String {
+        match &self.subsection {
+            Some(subsection) => {
+                let escaped = subsection.replace('\\', "\\\\").replace('"', "\\\"");
+                format!("[{} \"{}\"]", self.name, escaped)
+            }
+            None => format!("[{}]", self.name),
+        }
+    }
+}
+
+/// The system and global config files in the order git reads them.
+/// `GIT_CONFIG_NOSYSTEM` skips the system file.
+fn global_paths() -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    if std::env::var_os("GIT_CONFIG_NOSYSTEM").is_none() {
+        paths.push(system_path());
+    }
+    paths.extend(user_paths());
+
+    paths
+}
+
+/// `GIT_CONFIG_SYSTEM`, or `/etc/gitconfig`.
+fn system_path() -> PathBuf {
+    std::env::var_os("GIT_CONFIG_SYSTEM").map_or_else(|| PathBuf::from("/etc/gitconfig"), PathBuf::from)
+}
+
+/// The global config files in the order git reads them: the XDG one,
+/// then `~/.gitconfig`. `GIT_CONFIG_GLOBAL` replaces both.
+fn user_paths() -> Vec<PathBuf> {
+    if let Some(global) = std::env::var_os("GIT_CONFIG_GLOBAL") {
+        return vec![PathBuf::from(global)];
+    }
+    let mut paths = Vec::new();
+    let home = std::env::var_os("HOME").map(PathBuf::from);
+    match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(xdg) => paths.push(PathBuf::from(xdg).join("git/config")),
+        None => paths.extend(home.as_ref().map(|home| home.join(".config/git/config"))),
+    }
+    paths.extend(home.map(|home| home.join(".gitconfig")));
+
+    paths
+}
+
+/// One of the config files git reads, as chosen with `--system`,
+/// `--global` or `--local`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    System,
+    Global,
+    Local,
+}
+
+impl Scope {
+    /// The files read for this scope, in order.
+    pub fn read_paths(self) -> anyhow::Result<Vec<PathBuf>> {
+        match self {
+            Scope::System => Ok(vec![system_path()]),
+            Scope::Global => Ok(user_paths()),
+            Scope::Local => Ok(vec![local_path()?]),
+        }
+    }
+
+    /// The file written for this scope. Global writes go to
+    /// `~/.gitconfig`, unless only the XDG file exists.
+    pub fn write_path(self) -> anyhow::Result<PathBuf> {
+        match self {
+            Scope::Global => {
+                let paths = user_paths();
+                let path = match paths.as_slice() {
+                    [xdg, home] if xdg.exists() && !home.exists() => xdg,
+                    [.., home] => home,
+                    [] => return Err(anyhow!("$HOME not set")),
+                };
+                Ok(path.clone())
+            }
+            scope => Ok(scope.read_paths()?.remove(0)),
+        }
+    }
+}
+
+/// The config file of the repository in the current directory.
+fn local_path() -> anyhow::Result<PathBuf> {
+    let git_dir = repository::git_dir()?;
+    if !git_dir.is_dir() {
+        return Err(anyhow!("--local can only be used inside a git repository"));
+    }
+    Ok(repository::common_dir_of(&git_dir)?.join("config"))
+}
+
+/// Splits `section.subsection.key` into its parts, lowercasing the
+/// case-insensitive ones.
+fn split_name(name: &str) -> anyhow::Result<(SectionName, String)> {
+    let (section, rest) = name.split_once('.')
+        .ok_or(anyhow!("Key does not contain a section: {}", name))?;
+    let (subsection, key) = match rest.rsplit_once('.') {
+        Some((subsection, key)) => (Some(subsection.to_string()), key),
+        None => (None, rest),
+    };
+    if section.is_empty() || key.is_empty() {
+        return Err(anyhow!("Invalid key: {}", name));
+    }
+
+    Ok((
+        SectionName { name: section.to_lowercase(), subsection },
+        key.to_lowercase(),
+    ))
+}
+
+impl Config {
+    /// Reads a config file, treating a missing file as empty.
+    pub fn from_file(path: &Path) -> anyhow::Result<Config> {
+        match fs::read_to_string(path) {
+            Ok(content) => Config::parse(&content),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Reads the system and global config files followed by
+    /// `repository_config`, so that lookups see the value git would use.
+    /// The result is for reading only; write to a single file instead.
+    pub fn layered(repository_config: &Path) -> anyhow::Result<Config> {
+        let mut config = Config::default();
+        for path in global_paths().iter().map(PathBuf::as_path).chain([repository_config]) {
+            config.lines.extend(Config::from_file(path)?.lines);
+        }
+
+        Ok(config)
+    }
+
+    /// The system and global config files alone, for outside a repository.
+    pub fn global() -> anyhow::Result<Config> {
+        let mut config = Config::default();
+        for path in global_paths() {
+            config.lines.extend(Config::from_file(&path)?.lines);
+        }
+
+        Ok(config)
+    }
+
+    pub fn parse(content: &str) -> anyhow::Result<Config> {
+        let mut lines = Vec::new();
+        let mut section: Option<SectionName> = None;
+
+        for (number, raw) in content.lines().enumerate() {
+            let trimmed = raw.trim();
+            let kind = if trimmed.is_empty() || trimmed.starts_with('#') || trimmed.starts_with(';') {
+                LineKind::Other
+            } else if trimmed.starts_with('[') {
+                let name = parse_section_header(trimmed)
+                    .ok_or(anyhow!("Bad config line {}: {}", number + 1, raw))?;
+                section = Some(name.clone());
+                LineKind::Section(name)
+            } else {
+                let section = section.clone()
+                    .ok_or(anyhow!("Bad config line {}: {}", number + 1, raw))?;
+                let (key, value) = match trimmed.split_once('=') {
+                    Some((key, value)) => (key.trim(), Some(parse_value(value))),
+                    None => (trimmed, None),
+                };
+                if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+                    return Err(anyhow!("Bad config line {}: {}", number + 1, raw));
+                }
+                LineKind::Entry { section, key: key.to_lowercase(), value }
+            };
+
+            lines.push(Line { raw: raw.to_string(), kind });
+        }
+
+        Ok(Config { lines })
+    }
+
+    pub fn write(&self, path: &Path) -> anyhow::Result<()> {
+        lockfile::write(path, self.to_string())
+    }
+
+    /// Returns the last value set for `name`, with a bare `key` (no `=`)
+    /// reported as `"true"` like git does.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        let (section_name, key_name) = split_name(name).ok()?;
+
+        self.lines.iter()
+            .rev()
+            .find_map(|line| match &line.kind {
+                LineKind::Entry { section, key, value }
+                if *section == section_name && *key == key_name => {
+                    Some(value.as_deref().unwrap_or("true"))
+                }
+                _ => None,
+            })
+    }
+
+    /// Returns every value set for a multi-valued `name` in file order.
+    pub fn get_all(&self, name: &str) -> Vec<&str> {
+        let Ok((section_name, key_name)) = split_name(name) else {
+            return Vec::new();
+        };
+
+        self.lines.iter()
+            .filter_map(|line| match &line.kind {
+                LineKind::Entry { section, key, value }
+                if *section == section_name && *key == key_name => {
+                    Some(value.as_deref().unwrap_or("true"))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Returns the distinct subsection names of `section` in file order,
+    /// e.g. the submodule names for `submodule`.
+    pub fn subsections(&self, section: &str) -> Vec<String> {
+        let section = section.to_lowercase();
+        let mut subsections = Vec::new();
+
+        for line in &self.lines {
+            if let LineKind::Section(SectionName { name, subsection: Some(subsection) }) = &line.kind {
+                if *name == section && !subsections.contains(subsection) {
+                    subsections.push(subsection.clone());
+                }
+            }
+        }
+
+        subsections
+    }
+
+    /// Returns every entry in file order as its full `section.key` name
+    /// and value, `None` for a bare key.
+    pub fn entries(&self) -> Vec<(String, Option<&str>)> {
+        self.lines.iter()
+            .filter_map(|line| match &line.kind {
+                LineKind::Entry { section, key, value } => {
+                    let name = match &section.subsection {
+                        Some(subsection) => format!("{}.{}.{}", section.name, subsection, key),
+                        None => format!("{}.{}", section.name, key),
+                    };
+                    Some((name, value.as_deref()))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Sets `name` to `value`, replacing the last existing occurrence or
+    /// appending to (or creating) its section.
+    pub fn set(&mut self, name: &str, value: &str) -> anyhow::Result<()> {
+        let (section_name, key_name) = split_name(name)?;
+        let raw = format!("\t{} = {}", key_name, format_value(value));
+
+        let existing = self.lines.iter()
+            .rposition(|line| matches!(&line.kind,
+                LineKind::Entry { section, key, .. } if *section == section_name && *key == key_name));
+        let kind = LineKind::Entry {
+            section: section_name.clone(),
+            key: key_name,
+            value: Some(value.to_string()),
+        };
+
+        if let Some(index) = existing {
+            self.lines[index] = Line { raw, kind };
+            return Ok(());
+        }
+
+        self.append(section_name, Line { raw, kind });
+        Ok(())
+    }
+
+    /// Adds another value for a multi-valued `name`, after its existing ones.
+    pub fn add(&mut self, name: &str, value: &str) -> anyhow::Result<()> {
+        let (section_name, key_name) = split_name(name)?;
+        let raw = format!("\t{} = {}", key_name, format_value(value));
+        let kind = LineKind::Entry {
+            section: section_name.clone(),
+            key: key_name,
+            value: Some(value.to_string()),
+        };
+
+        self.append(section_name, Line { raw, kind });
+        Ok(())
+    }
+
+    /// Removes every value of `name`.
+    pub fn unset(&mut self, name: &str) -> anyhow::Result<()> {
+        let (section_name, key_name) = split_name(name)?;
+        self.lines.retain(|line| !matches!(&line.kind,
+            LineKind::Entry { section, key, .. } if *section == section_name && *key == key_name));
+
+        Ok(())
+    }
+
+    /// Removes every `[section "subsection"]` block, given as
+    /// `section.subsection`, along with the comments inside it.
+    pub fn remove_section(&mut self, name: &str) {
+        let section_name = match name.split_once('.') {
+            Some((section, subsection)) => SectionName {
+                name: section.to_lowercase(),
+                subsection: Some(subsection.to_string()),
+            },
+            None => SectionName { name: name.to_lowercase(), subsection: None },
+        };
+
+        let mut inside = false;
+        self.lines.retain(|line| {
+            if let LineKind::Section(section) = &line.kind {
+                inside = *section == section_name;
+            }
+            !inside
+        });
+    }
+}
+
+impl Config {
+    /// Inserts `line` after the last line of `section_name`, creating the
+    /// section at the end of the file if it doesn't exist yet.
+    fn append(&mut self, section_name: SectionName, line: Line) {
+        let section_end = self.lines.iter()
+            .rposition(|line| match &line.kind {
+                LineKind::Section(section) | LineKind::Entry { section, .. } => *section == section_name,
+                LineKind::Other => false,
+            });
+        match section_end {
+            Some(index) => self.lines.insert(index + 1, line),
+            None => {
+                self.lines.push(Line { raw: section_name.header(), kind: LineKind::Section(section_name) });
+                self.lines.push(line);
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Config {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for line in &self.lines {
+            writeln!(f, "{}", line.raw)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Where the `config` command reads and writes: every file git reads
+/// (writing the repository's), one scope, or a file given with `--file`.
+#[derive(Debug, Clone)]
+pub enum Location {
+    All,
+    Scope(Scope),
+    File(PathBuf),
+}
+
+impl Location {
+    fn read_paths(&self) -> anyhow::Result<Vec<PathBuf>> {
+        Ok(match self {
+            Location::All => {
+                let mut paths = global_paths();
+                let git_dir = repository::git_dir()?;
+                if git_dir.is_dir() {
+                    paths.push(repository::common_dir_of(&git_dir)?.join("config"));
+                }
+                paths
+            }
+            Location::Scope(scope) => scope.read_paths()?,
+            Location::File(path) => vec![path.clone()],
+        })
+    }
+
+    fn read(&self) -> anyhow::Result<Config> {
+        let mut config = Config::default();
+        for path in self.read_paths()? {
+            config.lines.extend(Config::from_file(&path)?.lines);
+        }
+        Ok(config)
+    }
+
+    fn write_path(&self) -> anyhow::Result<PathBuf> {
+        match self {
+            Location::All => local_path().map_err(|_| anyhow!("not in a git directory")),
+            Location::Scope(scope) => scope.write_path(),
+            Location::File(path) => Ok(path.clone()),
+        }
+    }
+}
+
+/// What the `config` command does.
+#[derive(Debug, Clone, Copy)]
+pub enum Action<'a> {
+    Get { name: &'a str, all: bool },
+    Set { name: &'a str, value: &'a str },
+    Add { name: &'a str, value: &'a str },
+    Unset { name: &'a str, all: bool },
+    List,
+    Edit,
+}
+
+/// Runs the `config` command. Like git, it fails with status 1 when a
+/// key to get isn't set, and with 5 when a key to unset or replace
+/// isn't there or has several values.
+pub fn run(location: &Location, action: Action) -> anyhow::Result<()> {
+    match action {
+        Action::Get { name, all } => {
+            split_name(name)?;
+            let config = location.read()?;
+            let values = config.get_all(name);
+            if values.is_empty() {
+                return Err(Error::Status(1).into());
+            }
+            let values = if all { &values[..] } else { &values[values.len() - 1..] };
+            for value in values {
+                println!("{}", value);
+            }
+        }
+        Action::Set { name, value } => {
+            let path = location.write_path()?;
+            let mut config = Config::from_file(&path)?;
+            if config.get_all(name).len() > 1 {
+                eprintln!("warning: {} has multiple values", name);
+                eprintln!("error: cannot overwrite multiple values with a single value");
+                eprintln!("       Use a regexp, --add or --replace-all to change {}.", name);
+                return Err(Error::Status(5).into());
+            }
+            config.set(name, value)?;
+            config.write(&path)?;
+        }
+        Action::Add { name, value } => {
+            let path = location.write_path()?;
+            let mut config = Config::from_file(&path)?;
+            config.add(name, value)?;
+            config.write(&path)?;
+        }
+        Action::Unset { name, all } => {
+            let path = location.write_path()?;
+            let mut config = Config::from_file(&path)?;
+            match config.get_all(name).len() {
+                0 => return Err(Error::Status(5).into()),
+                1 => {}
+                _ if all => {}
+                _ => {
+                    eprintln!("warning: {} has multiple values", name);
+                    return Err(Error::Status(5).into());
+                }
+            }
+            config.unset(name)?;
+            config.write(&path)?;
+        }
+        Action::List => {
+            // Unlike lookups, listing a file that was asked for insists on it.
+            let paths = location.read_paths()?;
+            if !matches!(location, Location::All) && !paths.iter().any(|path| path.exists()) {
+                let path = paths.last().map(|path| path.display().to_string()).unwrap_or_default();
+                return Err(anyhow!("unable to read config file '{}': No such file or directory", path));
+            }
+            let config = location.read()?;
+            for (name, value) in config.entries() {
+                match value {
+                    Some(value) => println!("{}={}", name, value),
+                    None => println!("{}", name),
+                }
+            }
+        }
+        Action::Edit => edit(location)?,
+    }
+    Ok(())
+}
+
+/// Opens the file `location` writes in the editor, starting a new
+/// global file with a commented-out identity like git does.
+fn edit(location: &Location) -> anyhow::Result<()> {
+    let path = location.write_path()?;
+    if matches!(location, Location::Scope(Scope::Global)) && !path.exists() {
+        let identity = ident::resolve(ident::Role::Committer, &Config::default())?;
+        let template = format!(
+            "# This is Git's per-user configuration file.\n\
+             [user]\n\
+             # Please adapt and uncomment the following lines:\n\
+             #\tname = {}\n\
+             #\temail = {}\n",
+            identity.name, identity.email
+        );
+        lockfile::write(&path, template)?;
+    }
+
+    let editor = ident::editor(&Location::All.read()?);
+    let status = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$@\"", editor))
+        .arg(&editor)
+        .arg(&path)
+        .status()
+        .map_err(|err| anyhow!("Unable to start editor '{}': {}", editor, err))?;
+    if !status.success() {
+        return Err(anyhow!("There was a problem with the editor '{}'.", editor));
+    }
+    Ok(())
+}
+
+/// Parses a size such as `512`, `10K`, `5M` or `1G`.
+pub fn parse_size(size: &str) -> anyhow::Result<u64> {
+    let (number, unit) = match size.char_indices().last() {
+        Some((index, 'k' | 'K')) => (&size[..index], 1 << 10),
+        Some((index, 'm' | 'M')) => (&size[..index], 1 << 20),
+        Some((index, 'g' | 'G')) => (&size[..index], 1 << 30),
+        _ => (size, 1),
+    };
+    let number: u64 = number.parse().map_err(|_| anyhow!("Invalid size: {}", size))?;
+
+    number.checked_mul(unit).ok_or(anyhow!("Invalid size: {}", size))
+}
+
+pub fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_lowercase().as_str() {
+        "true" | "yes" | "on" | "1" => Some(true),
+        "false" | "no" | "off" | "0" | "" => Some(false),
+        _ => None,
+    }
+}
+
+fn parse_section_header(line: &str) -> Option<SectionName> {
+    let inner = line.strip_prefix('[')?;
+    let end = inner.rfind(']')?;
+    let rest = inner[end + 1..].trim();
+    if !rest.is_empty() && !rest.starts_with('#') && !rest.starts_with(';') {
+        return None;
+    }
+    let inner = inner[..end].trim();
+
+    match inner.split_once(char::is_whitespace) {
+        Some((name, subsection)) => {
+            let subsection = subsection.trim()
+                .strip_prefix('"')?
+                .strip_suffix('"')?;
+            let mut unescaped = String::new();
+            let mut chars = subsection.chars();
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' => unescaped.push(chars.next()?),
+                    c => unescaped.push(c),
+                }
+            }
+            Some(SectionName { name: name.to_lowercase(), subsection: Some(unescaped) })
+        }
+        // Deprecated `[section.subsection]` syntax.
+        None => match inner.split_once('.') {
+            Some((name, subsection)) => Some(SectionName {
+                name: name.to_lowercase(),
+                subsection: Some(subsection.to_lowercase()),
+            }),
+            None => Some(SectionName { name: inner.to_lowercase(), subsection: None }),
+        },
+    }
+}
+
+fn parse_value(value: &str) -> String {
+    let mut result = String::new();
+    let mut in_quotes = false;
+    // Whitespace is only kept when followed by more value characters.
+    let mut pending_space = String::new();
+    let mut chars = value.trim_start().chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                result.push_str(&pending_space);
+                pending_space.clear();
+                in_quotes = !in_quotes;
+            }
+            '#' | ';' if !in_quotes => break,
+            c if c.is_whitespace() && !in_quotes => pending_space.push(c),
+            '\\' => {
+                result.push_str(&pending_space);
+                pending_space.clear();
+                match chars.next() {
+                    Some('n') => result.push('\n'),
+                    Some('t') => result.push('\t'),
+                    Some('b') => { result.pop(); }
+                    Some(c) => result.push(c),
+                    None => {}
+                }
+            }
+            c => {
+                result.push_str(&pending_space);
+                pending_space.clear();
+                result.push(c);
+            }
+        }
+    }
+
+    result
+}
+
+fn format_value(value: &str) -> String {
+    let needs_quotes = value.starts_with(char::is_whitespace)
+        || value.ends_with(char::is_whitespace)
+        || value.contains(['#', ';']);
+
+    let mut result = String::new();
+    for c in value.chars() {
+        match c {
+            '\\' => result.push_str("\\\\"),
+            '"' => result.push_str("\\\""),
+            '\n' => result.push_str("\\n"),
+            '\t' => result.push_str("\\t"),
+            c => result.push(c),
+        }
+    }
+
+    if needs_quotes {
+        format!("\"{}\"", result)
+    } else {
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = "\
+# Written by hand.
+[core]
+\tbare = false
+\tFileMode
+[remote \"Origin\"]
+\turl = \"/srv/a b\" ; the mirror
+\tfetch = +refs/heads/*:refs/remotes/origin/*
+\tfetch = +refs/tags/*:refs/tags/*
+[Branch.main]
+\tremote = Origin
+";
+
+    #[test]
+    fn parse_and_get() {
+        let config = Config::parse(CONFIG).unwrap();
+        assert_eq!(config.get("core.bare"), Some("false"));
+        // Section and key names are case-insensitive, and a bare key is true.
+        assert_eq!(config.get("CORE.filemode"), Some("true"));
+        assert_eq!(config.get("remote.Origin.url"), Some("/srv/a b"));
+        // Subsection names are case-sensitive.
+        assert_eq!(config.get("remote.origin.url"), None);
+        assert_eq!(
+            config.get_all("remote.Origin.fetch"),
+            ["+refs/heads/*:refs/remotes/origin/*", "+refs/tags/*:refs/tags/*"]
+        );
+        assert_eq!(config.get("branch.main.remote"), Some("Origin"));
+        assert_eq!(config.subsections("remote"), ["Origin"]);
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(Config::parse("bare = true\n").is_err());
+        assert!(Config::parse("[core\n").is_err());
+        assert!(Config::parse("[core]\n\tbad_key = 1\n").is_err());
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut config = Config::parse(CONFIG).unwrap();
+        assert_eq!(config.to_string(), CONFIG);
+
+        config.set("core.bare", "true").unwrap();
+        config.set("user.name", " spaced # out").unwrap();
+        config.add("remote.Origin.fetch", "+refs/notes/*:refs/notes/*").unwrap();
+        config.unset("branch.main.remote").unwrap();
+        let written = config.to_string();
+        assert!(written.starts_with("# Written by hand.\n[core]\n\tbare = true\n\tFileMode\n"));
+        assert!(written.ends_with("[user]\n\tname = \" spaced # out\"\n"));
+
+        let reread = Config::parse(&written).unwrap();
+        assert_eq!(reread.get("core.bare"), Some("true"));
+        assert_eq!(reread.get("user.name"), Some(" spaced # out"));
+        assert_eq!(reread.get_all("remote.Origin.fetch").len(), 3);
+        assert_eq!(reread.get("branch.main.remote"), None);
+    }
+}
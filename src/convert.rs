@@ -0,0 +1,442 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io::{BufReader, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::Mutex;
+
+use anyhow::anyhow;
+
+use crate::attributes::{AttrValue, Attributes};
+use crate::config::{parse_bool, Config};
+use crate::pktline;
+use crate::trace;
+use crate::trace::TRACE;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AutoCrlf {
+    False,
+    True,
+    Input,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Eol {
+    Lf,
+    Crlf,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TextState {
+    Binary,
+    Text,
+    Auto,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FilterKind {
+    Clean,
+    Smudge,
+}
+
+impl FilterKind {
+    fn name(self) -> &'static str {
+        match self {
+            FilterKind::Clean => "clean",
+            FilterKind::Smudge => "smudge",
+        }
+    }
+}
+
+/// A `filter.<driver>` section of the config.
+#[derive(Debug, Clone)]
+struct FilterDriver {
+    clean: Option<String>,
+    smudge: Option<String>,
+    process: Option<String>,
+    required: bool,
+}
+
+/// Content conversion between the worktree and the object database: the
+/// clean/smudge filter drivers selected by the `filter` attribute, then
+/// line-ending conversion driven by `core.autocrlf`, `core.eol` and the
+/// `text`/`eol` attributes.
+#[derive(Debug)]
+pub struct Convert {
+    autocrlf: AutoCrlf,
+    eol: Eol,
+    drivers: HashMap<String, FilterDriver>,
+    // Long-running `filter.<driver>.process` filters, started on first use
+    // and kept alive for the rest of the command.
+    processes: Mutex<HashMap<String, FilterProcess>>,
+}
+
+impl Convert {
+    pub fn from_config(config: &Config) -> anyhow::Result<Convert> {
+        let autocrlf = match config.get("core.autocrlf") {
+            None => AutoCrlf::False,
+            Some(value) if value.eq_ignore_ascii_case("input") => AutoCrlf::Input,
+            Some(value) => match parse_bool(value) {
+                Some(true) => AutoCrlf::True,
+                Some(false) => AutoCrlf::False,
+                None => return Err(anyhow!("Bad core.autocrlf value: {}", value)),
+            },
+        };
+        let eol = match config.get("core.eol").map(str::to_lowercase).as_deref() {
+            Some("crlf") => Eol::Crlf,
+            Some("lf") => Eol::Lf,
+            None | Some("native") => native_eol(),
+            Some(value) => return Err(anyhow!("Bad core.eol value: {}", value)),
+        };
+
+        let mut drivers = HashMap::new();
+        for name in config.subsections("filter") {
+            let get = |key: &str| config.get(&format!("filter.{}.{}", name, key)).map(str::to_string);
+            let required = match get("required") {
+                Some(value) => parse_bool(&value)
+                    .ok_or(anyhow!("Bad filter.{}.required value: {}", name, value))?,
+                None => false,
+            };
+
+            drivers.insert(name.clone(), FilterDriver {
+                clean: get("clean"),
+                smudge: get("smudge"),
+                process: get("process"),
+                required,
+            });
+        }
+
+        Ok(Convert { autocrlf, eol, drivers, processes: Mutex::new(HashMap::new()) })
+    }
+
+    /// Runs the filter driver selected by the `filter` attribute, if any.
+    /// Failures of drivers that aren't `required` only produce a warning
+    /// and leave the content unfiltered, like in git.
+    fn filter<'a>(&self, kind: FilterKind, path: &str, attributes: &Attributes, content: &'a [u8]) -> anyhow::Result<Cow<'a, [u8]>> {
+        let Some(AttrValue::Value(name)) = attributes.get(path, "filter") else {
+            return Ok(Cow::Borrowed(content));
+        };
+        let Some(driver) = self.drivers.get(name) else {
+            return Ok(Cow::Borrowed(content));
+        };
+
+        let result = match (&driver.process, kind) {
+            (Some(process), _) => self.run_process(name, process, kind, path, content),
+            (None, FilterKind::Clean) => driver.clean.as_ref()
+                .map(|command| run_command(command, path, content))
+                .transpose(),
+            (None, FilterKind::Smudge) => driver.smudge.as_ref()
+                .map(|command| run_command(command, path, content))
+                .transpose(),
+        };
+
+        match result {
+            Ok(Some(filtered)) => Ok(Cow::Owned(filtered)),
+            Ok(None) if driver.required => {
+                Err(anyhow!("{}: {} filter '{}' is required but not defined", path, kind.name(), name))
+            }
+            Ok(None) => Ok(Cow::Borrowed(content)),
+            Err(err) if driver.required => Err(err.context(format!("{}: {} filter '{}' failed", path, kind.name(), name))),
+            Err(err) => {
+                eprintln!("warning: {}: {} filter '{}' failed: {}", path, kind.name(), name, err);
+                Ok(Cow::Borrowed(content))
+            }
+        }
+    }
+
+    fn run_process(&self, name: &str, command: &str, kind: FilterKind, path: &str, content: &[u8]) -> anyhow::Result<Option<Vec<u8>>> {
+        let mut processes = self.processes.lock()
+            .map_err(|_| anyhow!("Filter process lock poisoned"))?;
+
+        if !processes.contains_key(name) {
+            processes.insert(name.to_string(), FilterProcess::start(command)?);
+        }
+        let process = processes.get_mut(name).expect("Filter process was just started");
+
+        process.filter(kind, path, content)
+    }
+
+    fn text(&self, path: &str, attributes: &Attributes) -> TextState {
+        match attributes.get(path, "text") {
+            Some(AttrValue::Set) => TextState::Text,
+            Some(AttrValue::Unset) => TextState::Binary,
+            Some(AttrValue::Value(value)) if value == "auto" => TextState::Auto,
+            // Setting `eol` implies `text`.
+            _ if matches!(attributes.get(path, "eol"), Some(AttrValue::Value(_))) => TextState::Text,
+            _ if self.autocrlf != AutoCrlf::False => TextState::Auto,
+            _ => TextState::Binary,
+        }
+    }
+
+    fn output_eol(&self, path: &str, attributes: &Attributes) -> Eol {
+        match attributes.get(path, "eol") {
+            Some(AttrValue::Value(value)) if value == "crlf" => return Eol::Crlf,
+            Some(AttrValue::Value(value)) if value == "lf" => return Eol::Lf,
+            _ => {}
+        }
+
+        match self.autocrlf {
+            AutoCrlf::True => Eol::Crlf,
+            AutoCrlf::Input => Eol::Lf,
+            AutoCrlf::False => self.eol,
+        }
+    }
+
+    /// Cleans `content` for storage when a file is staged.
+    pub fn to_git<'a>(&self, path: &str, attributes: &Attributes, content: &'a [u8]) -> anyhow::Result<Cow<'a, [u8]>> {
+        let filtered = self.filter(FilterKind::Clean, path, attributes, content)?;
+
+        match self.crlf_to_lf(path, attributes, &filtered) {
+            Some(converted) => Ok(Cow::Owned(converted)),
+            None => Ok(filtered),
+        }
+    }
+
+    /// Smudges `content` for the worktree when a file is checked out.
+    pub fn to_worktree<'a>(&self, path: &str, attributes: &Attributes, content: &'a [u8]) -> anyhow::Result<Cow<'a, [u8]>> {
+        let converted = match self.lf_to_crlf(path, attributes, content) {
+            Some(converted) => Cow::Owned(converted),
+            None => Cow::Borrowed(content),
+        };
+
+        let filtered = match self.filter(FilterKind::Smudge, path, attributes, &converted)? {
+            Cow::Owned(filtered) => Some(filtered),
+            Cow::Borrowed(_) => None,
+        };
+
+        Ok(filtered.map(Cow::Owned).unwrap_or(converted))
+    }
+
+    /// Normalizes CRLF line endings to LF, returning `None` if nothing
+    /// needs converting.
+    fn crlf_to_lf(&self, path: &str, attributes: &Attributes, content: &[u8]) -> Option<Vec<u8>> {
+        let stats = Stats::new(content);
+        let convert = match self.text(path, attributes) {
+            TextState::Binary => false,
+            TextState::Text => stats.crlf > 0,
+            TextState::Auto => stats.crlf > 0 && !stats.is_binary(),
+        };
+        if !convert {
+            return None;
+        }
+
+        let mut result = Vec::with_capacity(content.len());
+        for (i, &byte) in content.iter().enumerate() {
+            if byte == b'\r' && content.get(i + 1) == Some(&b'\n') {
+                continue;
+            }
+            result.push(byte);
+        }
+
+        Some(result)
+    }
+
+    /// Expands LF to CRLF if configured, returning `None` if nothing needs
+    /// converting.
+    fn lf_to_crlf(&self, path: &str, attributes: &Attributes, content: &[u8]) -> Option<Vec<u8>> {
+        let text = self.text(path, attributes);
+        if text == TextState::Binary || self.output_eol(path, attributes) == Eol::Lf {
+            return None;
+        }
+
+        let stats = Stats::new(content);
+        if stats.lone_lf == 0 {
+            return None;
+        }
+        // Files that already contain CRLF or stray CRs are left alone, as
+        // they wouldn't round-trip.
+        if text == TextState::Auto && (stats.is_binary() || stats.crlf > 0) {
+            return None;
+        }
+
+        let mut result = Vec::with_capacity(content.len() + stats.lone_lf);
+        for (i, &byte) in content.iter().enumerate() {
+            if byte == b'\n' && (i == 0 || content[i - 1] != b'\r') {
+                result.push(b'\r');
+            }
+            result.push(byte);
+        }
+
+        Some(result)
+    }
+}
+
+/// Runs a one-shot `clean`/`smudge` command through the shell, feeding
+/// it the content on stdin. `%f` is replaced by the quoted path.
+fn run_command(command: &str, path: &str, content: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let quoted = format!("'{}'", path.replace('\'', "'\\''"));
+    let command = command.replace("%f", &quoted);
+    trace!(TRACE, "run_command: {}", command);
+
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(&command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    // Feed stdin from another thread so a filter that writes before it has
+    // read all of its input can't deadlock us.
+    let mut stdin = child.stdin.take().expect("Filter stdin is piped");
+    let output = std::thread::scope(|scope| {
+        let writer = scope.spawn(move || stdin.write_all(content));
+        let output = child.wait_with_output();
+        // A filter may legitimately exit without reading everything.
+        let _ = writer.join();
+        output
+    })?;
+
+    if !output.status.success() {
+        return Err(anyhow!("External filter '{}' failed: {}", command, output.status));
+    }
+
+    Ok(output.stdout)
+}
+
+/// A filter speaking git's long-running process protocol (version 2) over
+/// pkt-lines on its stdin/stdout.
+#[derive(Debug)]
+struct FilterProcess {
+    child: Child,
+    stdin: Option<ChildStdin>,
+    stdout: BufReader<ChildStdout>,
+    capabilities: Vec<String>,
+}
+
+impl FilterProcess {
+    fn start(command: &str) -> anyhow::Result<FilterProcess> {
+        trace!(TRACE, "run_command: {}", command);
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let mut stdin = child.stdin.take().expect("Filter stdin is piped");
+        let mut stdout = BufReader::new(child.stdout.take().expect("Filter stdout is piped"));
+
+        pktline::write_text(&mut stdin, "git-filter-client")?;
+        pktline::write_text(&mut stdin, "version=2")?;
+        pktline::write_flush(&mut stdin)?;
+        stdin.flush()?;
+
+        let welcome = pktline::read_text_list(&mut stdout)?;
+        if welcome.first().map(String::as_str) != Some("git-filter-server")
+            || !welcome.iter().any(|line| line == "version=2") {
+            return Err(anyhow!("Filter process '{}' does not speak protocol version 2", command));
+        }
+
+        pktline::write_text(&mut stdin, "capability=clean")?;
+        pktline::write_text(&mut stdin, "capability=smudge")?;
+        pktline::write_flush(&mut stdin)?;
+        stdin.flush()?;
+
+        let capabilities = pktline::read_text_list(&mut stdout)?
+            .into_iter()
+            .filter_map(|line| line.strip_prefix("capability=").map(str::to_string))
+            .collect();
+
+        Ok(FilterProcess { child, stdin: Some(stdin), stdout, capabilities })
+    }
+
+    /// Filters one file, returning `None` if the process doesn't support
+    /// `kind` or asked to be skipped from now on.
+    fn filter(&mut self, kind: FilterKind, path: &str, content: &[u8]) -> anyhow::Result<Option<Vec<u8>>> {
+        if !self.capabilities.iter().any(|capability| capability == kind.name()) {
+            return Ok(None);
+        }
+        let stdin = self.stdin.as_mut().ok_or(anyhow!("Filter process is closed"))?;
+
+        pktline::write_text(stdin, &format!("command={}", kind.name()))?;
+        pktline::write_text(stdin, &format!("pathname={}", path))?;
+        pktline::write_flush(stdin)?;
+        pktline::write_data(stdin, content)?;
+        pktline::write_flush(stdin)?;
+        stdin.flush()?;
+
+        match status(&pktline::read_text_list(&mut self.stdout)?) {
+            Some("success") => {}
+            Some("abort") => {
+                self.capabilities.retain(|capability| capability != kind.name());
+                return Ok(None);
+            }
+            status => return Err(anyhow!("Filter process reported status {}", status.unwrap_or("<none>"))),
+        }
+
+        let filtered = pktline::read_data(&mut self.stdout)?;
+        // A trailing status list may revoke the success reported up front,
+        // an empty one keeps it.
+        match status(&pktline::read_text_list(&mut self.stdout)?) {
+            None | Some("success") => Ok(Some(filtered)),
+            Some(status) => Err(anyhow!("Filter process reported status {}", status)),
+        }
+    }
+}
+
+impl Drop for FilterProcess {
+    fn drop(&mut self) {
+        // Closing stdin tells the filter to exit.
+        self.stdin.take();
+        let _ = self.child.wait();
+    }
+}
+
+fn status(lines: &[String]) -> Option<&str> {
+    lines.iter()
+        .rev()
+        .find_map(|line| line.strip_prefix("status="))
+}
+
+#[cfg(windows)]
+fn native_eol() -> Eol {
+    Eol::Crlf
+}
+
+#[cfg(not(windows))]
+fn native_eol() -> Eol {
+    Eol::Lf
+}
+
+/// Character statistics used by git to tell text from binary content.
+struct Stats {
+    nul: usize,
+    lone_cr: usize,
+    lone_lf: usize,
+    crlf: usize,
+    printable: usize,
+    non_printable: usize,
+}
+
+impl Stats {
+    fn new(content: &[u8]) -> Stats {
+        let mut stats = Stats { nul: 0, lone_cr: 0, lone_lf: 0, crlf: 0, printable: 0, non_printable: 0 };
+
+        for (i, &byte) in content.iter().enumerate() {
+            match byte {
+                b'\r' if content.get(i + 1) == Some(&b'\n') => {
+                    stats.crlf += 1;
+                    stats.printable += 1;
+                }
+                b'\r' => stats.lone_cr += 1,
+                b'\n' if i > 0 && content[i - 1] == b'\r' => {}
+                b'\n' => stats.lone_lf += 1,
+                0 => stats.nul += 1,
+                // Backspace, tab, escape and form feed count as printable.
+                8 | 9 | 27 | 12 => stats.printable += 1,
+                127 => stats.non_printable += 1,
+                byte if byte < 32 => stats.non_printable += 1,
+                _ => stats.printable += 1,
+            }
+        }
+
+        // A trailing EOF character (^Z) from DOS is ignored.
+        if content.last() == Some(&26) {
+            stats.non_printable = stats.non_printable.saturating_sub(1);
+        }
+
+        stats
+    }
+
+    fn is_binary(&self) -> bool {
+        self.lone_cr > 0 || self.nul > 0 || (self.printable >> 7) < self.non_printable
+    }
+}
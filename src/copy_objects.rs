@@ -0,0 +1,127 @@
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::anyhow;
+
+use crate::info;
+use crate::maintenance;
+use crate::pack::{self, PackIndex};
+use crate::progress::Progress;
+use crate::repository::{self, create_temp_file, Repository};
+use crate::verbose;
+
+/// Copies the objects of the repository at `from` that this one lacks,
+/// keeping them compressed as stored, with a progress counter if
+/// `progress`. Loose objects are copied one by one and packs whole.
+pub fn run(from: &Path, progress: bool) -> anyhow::Result<()> {
+    let repository = repository::current()?;
+    let source = Repository::open(from)
+        .ok()
+        .filter(|source| source.git_dir().is_dir())
+        .ok_or(anyhow!("'{}' does not appear to be a git repository", from.display()))?;
+    if source.object_format() != repository.object_format() {
+        return Err(anyhow!(
+            "Cannot copy {} objects into a {} repository",
+            source.object_format().name(),
+            repository.object_format().name()
+        ));
+    }
+
+    let objects = source.loose_objects()?;
+    let progress = Progress::new("Copying objects", objects.len(), progress);
+    let mut copied = 0;
+    let mut present = 0;
+    repository.batch(|| {
+        for sha in &objects {
+            if repository.copy_loose_from(&source, sha)? {
+                verbose!(1, "Copied {}", sha);
+                copied += 1;
+            } else {
+                present += 1;
+            }
+            progress.tick();
+        }
+        Ok(())
+    })?;
+    progress.finish();
+
+    // Packs go over whole rather than object by object.
+    for name in source_packs(&source)? {
+        let (new, old) = copy_pack(&repository, &source, &name)?;
+        if new > 0 {
+            verbose!(1, "Copied {}.pack", name);
+        }
+        copied += new;
+        present += old;
+    }
+    repository.reload_packs();
+
+    info!("Copied {} objects, {} already present", copied, present);
+    maintenance::run_auto(false)
+}
+
+/// The names of the packs of `source` that have an index, without the
+/// extension.
+fn source_packs(source: &Repository) -> anyhow::Result<Vec<String>> {
+    let mut packs = Vec::new();
+    let Ok(entries) = fs::read_dir(source.object_dir().join("pack")) else {
+        return Ok(packs);
+    };
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_none_or(|ext| ext != "pack") || !path.with_extension("idx").is_file() {
+            continue;
+        }
+        if let Some(name) = path.file_stem().and_then(|name| name.to_str()) {
+            packs.push(name.to_string());
+        }
+    }
+    packs.sort();
+    Ok(packs)
+}
+
+/// Copies the pack `name` of `source` and its index into the object
+/// directory, after checking that this can read the pack, that it isn't
+/// corrupt and that the index is the one made for it. The index goes
+/// last, as git ignores a pack until it has one. Returns how many of its
+/// objects were new here and how many were present already.
+fn copy_pack(repository: &Repository, source: &Repository, name: &str) -> anyhow::Result<(usize, usize)> {
+    let from = source.object_dir().join("pack").join(name);
+    let format = repository.object_format();
+    let index = fs::read(from.with_extension("idx"))?;
+    let parsed = PackIndex::parse(&index, format).map_err(|err| anyhow!("{}.idx: {}", name, err))?;
+    let new = parsed.objects.iter().filter(|(sha, _)| !repository.has_object(sha)).count();
+    let directory = repository.object_dir().join("pack");
+    if new == 0 || directory.join(format!("{}.idx", name)).is_file() {
+        return Ok((0, parsed.objects.len()));
+    }
+
+    let pack = fs::read(from.with_extension("pack"))?;
+    let (_, count) = pack::parse_header(&pack).map_err(|err| anyhow!("{}.pack: {}", name, err))?;
+    let (data, checksum) = pack.split_at(pack.len().saturating_sub(format.byte_len()));
+    if format.hash(data).as_ref() != checksum {
+        return Err(anyhow!("{}.pack is corrupt: checksum mismatch", name));
+    }
+    if parsed.pack_checksum.as_ref() != checksum {
+        return Err(anyhow!("{}.idx does not match {}.pack", name, name));
+    }
+    if count as usize != parsed.objects.len() {
+        return Err(anyhow!("{}.pack holds {} objects, but {}.idx lists {}", name, count, name, parsed.objects.len()));
+    }
+
+    fs::create_dir_all(&directory)?;
+    for (extension, data) in [("pack", &pack), ("idx", &index)] {
+        let (temp_path, mut file) = create_temp_file(&directory, "tmp_pack_")?;
+        let result = (|| -> anyhow::Result<()> {
+            file.write_all(data)?;
+            file.sync_all()?;
+            Ok(fs::rename(&temp_path, directory.join(format!("{}.{}", name, extension)))?)
+        })();
+        if result.is_err() {
+            let _ = fs::remove_file(&temp_path);
+        }
+        result?;
+    }
+    Ok((new, parsed.objects.len() - new))
+}
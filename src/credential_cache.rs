@@ -0,0 +1,173 @@
+use std::fs;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+use anyhow::anyhow;
+
+use crate::credential::Credential;
+
+/// How long the cache keeps a credential by default, in seconds.
+pub const DEFAULT_TIMEOUT: u64 = 900;
+
+/// How long a new daemon waits for its first credential.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long the daemon waits on a client, so that one that stops
+/// talking can't keep it from serving the others.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// `~/.git-credential-cache/socket` if that directory exists, as older
+/// versions used it, otherwise `$XDG_CACHE_HOME/git/credential/socket`.
+pub fn default_socket() -> anyhow::Result<PathBuf> {
+    let home = PathBuf::from(std::env::var_os("HOME").ok_or(anyhow!("HOME is not set"))?);
+    let old = home.join(".git-credential-cache");
+    if old.is_dir() {
+        return Ok(old.join("socket"));
+    }
+    let cache = match std::env::var_os("XDG_CACHE_HOME") {
+        Some(cache) => PathBuf::from(cache),
+        None => home.join(".cache"),
+    };
+    Ok(cache.join("git/credential/socket"))
+}
+
+/// Starts the daemon on `socket` and waits until it listens.
+fn spawn_daemon(socket: &Path) -> anyhow::Result<()> {
+    let mut daemon = Command::new(std::env::current_exe()?)
+        .arg("credential-cache--daemon")
+        .arg(socket)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|err| anyhow!("Unable to start cache daemon: {}", err))?;
+
+    let mut line = String::new();
+    BufReader::new(daemon.stdout.take().expect("Stdout is piped")).read_line(&mut line)?;
+    if line != "ok\n" {
+        return Err(anyhow!("Cache daemon did not start: {}", line.trim_end()));
+    }
+    Ok(())
+}
+
+/// The `credential-cache` helper: relays `action` and the credential on
+/// stdin to the daemon listening on `socket`, starting it to store.
+pub fn run(action: &str, timeout: u64, socket: &Path) -> anyhow::Result<()> {
+    let mut request = format!("action={}\ntimeout={}\n", action, timeout).into_bytes();
+    match action {
+        "get" | "store" | "erase" => {
+            std::io::stdin().lock().read_to_end(&mut request)?;
+        }
+        "exit" => {}
+        // Unknown actions are ignored, as git may add new ones.
+        _ => return Ok(()),
+    }
+
+    let mut stream = match UnixStream::connect(socket) {
+        Ok(stream) => stream,
+        Err(_) if action == "store" => {
+            spawn_daemon(socket)?;
+            UnixStream::connect(socket).map_err(|err| anyhow!("Unable to connect to cache daemon: {}", err))?
+        }
+        // Nothing is cached without a daemon.
+        Err(_) => return Ok(()),
+    };
+    stream.write_all(&request)?;
+    stream.shutdown(std::net::Shutdown::Write)?;
+
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response)?;
+    std::io::stdout().lock().write_all(&response)?;
+    Ok(())
+}
+
+struct Entry {
+    credential: Credential,
+    expires: Instant,
+}
+
+/// Handles one request and returns whether the daemon should exit.
+fn serve(stream: &mut UnixStream, entries: &mut Vec<Entry>) -> anyhow::Result<bool> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut action = String::new();
+    let mut timeout = String::new();
+    reader.read_line(&mut action)?;
+    reader.read_line(&mut timeout)?;
+    let action = action.trim_end().strip_prefix("action=").ok_or(anyhow!("Cache request without an action"))?;
+    let timeout: u64 = timeout
+        .trim_end()
+        .strip_prefix("timeout=")
+        .and_then(|timeout| timeout.parse().ok())
+        .ok_or(anyhow!("Cache request without a timeout"))?;
+    let credential = Credential::read(&mut reader)?;
+
+    match action {
+        "get" => {
+            if let Some(entry) = entries.iter().find(|entry| credential.matches(&entry.credential, false)) {
+                entry.credential.write_secret(stream)?;
+            }
+        }
+        "store" => {
+            if credential.protocol.is_none() || credential.username.is_none() || credential.password.is_none() {
+                return Ok(false);
+            }
+            entries.retain(|entry| !credential.matches(&entry.credential, false));
+            entries.push(Entry { credential, expires: Instant::now() + Duration::from_secs(timeout) });
+        }
+        "erase" => entries.retain(|entry| !credential.matches(&entry.credential, true)),
+        "exit" => return Ok(true),
+        _ => {}
+    }
+    Ok(false)
+}
+
+/// The daemon behind `credential-cache`: keeps credentials in memory
+/// until they expire, and exits once none are left.
+pub fn daemon(socket: &Path) -> anyhow::Result<()> {
+    if let Some(directory) = socket.parent() {
+        fs::create_dir_all(directory)?;
+        fs::set_permissions(directory, fs::Permissions::from_mode(0o700))?;
+    }
+    let _ = fs::remove_file(socket);
+    let listener =
+        UnixListener::bind(socket).map_err(|err| anyhow!("Unable to bind to '{}': {}", socket.display(), err))?;
+
+    // The client waits for this before it connects.
+    println!("ok");
+    std::io::stdout().flush()?;
+
+    let started = Instant::now();
+    let mut entries: Vec<Entry> = Vec::new();
+    loop {
+        let now = Instant::now();
+        entries.retain(|entry| entry.expires > now);
+        let wake = match entries.iter().map(|entry| entry.expires).min() {
+            Some(expires) => expires,
+            None if now < started + IDLE_TIMEOUT => started + IDLE_TIMEOUT,
+            None => break,
+        };
+
+        let mut poll = libc::pollfd { fd: listener.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+        let wait = wake.saturating_duration_since(now).as_millis().min(i32::MAX as u128 - 1) as libc::c_int;
+        // SAFETY: `poll` reads and writes only the one `pollfd` it is given,
+        // which lives until it returns, and the listener keeps the fd open.
+        if unsafe { libc::poll(&mut poll, 1, wait + 1) } <= 0 {
+            continue;
+        }
+
+        let (mut stream, _) = listener.accept()?;
+        // A broken request shouldn't bring down the cache.
+        if let Ok(true) = serve(&mut stream, &mut entries) {
+            break;
+        }
+    }
+
+    let _ = fs::remove_file(socket);
+    Ok(())
+}
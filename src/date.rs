@@ -0,0 +1,397 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::anyhow;
+
+const MONTHS: [&str; 12] = ["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+
+/// The current time and the local time zone's offset in minutes.
+pub fn now() -> (i64, i32) {
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs() as i64)
+        .unwrap_or(0);
+
+    (time, local_offset(time))
+}
+
+/// Parses a date as accepted in `GIT_AUTHOR_DATE`: git's internal
+/// `<epoch> <offset>` (optionally `@`-prefixed), RFC 2822 such as
+/// `Thu, 07 Apr 2005 22:13:13 +0200`, asctime or `ctime` such as
+/// `Thu Apr 7 22:13:13 2005 +0200`, or ISO 8601 such as
+/// `2005-04-07T22:13:13+02:00`. Returns seconds since the epoch and the
+/// offset in minutes; dates without a zone are taken as local time.
+pub fn parse(date: &str) -> anyhow::Result<(i64, i32)> {
+    let date = date.trim();
+    parse_internal(date)
+        .or_else(|| parse_rfc2822(date))
+        .or_else(|| parse_asctime(date))
+        .or_else(|| parse_iso8601(date))
+        // Commits can't record times before the epoch.
+        .filter(|(time, _)| *time >= 0)
+        .ok_or(anyhow!("Invalid date format: {}", date))
+}
+
+/// Parses an approximate date as `--since` and friends accept: `now`,
+/// `yesterday`, `2 weeks ago`, `3.days.ago`, `last month`, a bare day
+/// such as `2024-03-01` (its start), or any date [`parse`] accepts.
+/// Returns seconds since the epoch.
+pub fn approxidate(date: &str) -> anyhow::Result<i64> {
+    let date = date.trim();
+    let invalid = || anyhow!("Invalid date: {}", date);
+    let ago = |count: i64, unit: &str| -> anyhow::Result<i64> {
+        let seconds = match unit.trim_end_matches('s') {
+            "second" => 1,
+            "minute" => 60,
+            "hour" => 60 * 60,
+            "day" => 24 * 60 * 60,
+            "week" => 7 * 24 * 60 * 60,
+            "month" => 30 * 24 * 60 * 60,
+            "year" => 365 * 24 * 60 * 60,
+            _ => return Err(invalid()),
+        };
+        Ok(now().0.saturating_sub(count.saturating_mul(seconds)))
+    };
+
+    match date {
+        "now" | "today" => return Ok(now().0),
+        "yesterday" => return ago(1, "day"),
+        _ => {}
+    }
+    if let Some(unit) = date.strip_prefix("last").map(|unit| unit.trim_start_matches(['.', ' '])) {
+        return ago(1, unit);
+    }
+    let relative = date.strip_suffix("ago").map(|rest| rest.trim_end_matches(['.', ' ']));
+    if let Some(relative) = relative {
+        let (count, unit) = relative.split_once(['.', ' ']).ok_or_else(invalid)?;
+        return ago(count.parse().map_err(|_| invalid())?, unit.trim_start_matches(['.', ' ']));
+    }
+
+    parse(date)
+        .or_else(|_| parse(&format!("{} 00:00:00", date)))
+        .map(|(time, _)| time)
+        .map_err(|_| invalid())
+}
+
+/// Parses an expiry cutoff such as `90.days.ago`, `never` or `now`, or
+/// any date [`approxidate`] accepts, into seconds since the epoch.
+/// Entries older than the cutoff expire: `never` is `i64::MIN` and `all`
+/// or `now` is `i64::MAX`.
+pub fn parse_expiry(date: &str) -> anyhow::Result<i64> {
+    match date {
+        "never" | "false" => Ok(i64::MIN),
+        "all" | "now" => Ok(i64::MAX),
+        _ => approxidate(date).map_err(|_| anyhow!("Invalid expiry date: {}", date)),
+    }
+}
+
+fn parse_internal(date: &str) -> Option<(i64, i32)> {
+    let (time, offset) = match date.split_once(' ') {
+        Some((time, offset)) => (time, Some(offset)),
+        None => (date, None),
+    };
+    let explicit = time.starts_with('@');
+    let time = time.trim_start_matches('@');
+    if !time.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    // Like git, a bare number is only a timestamp if it can't be a date.
+    if !explicit && offset.is_none() && time.len() < 9 {
+        return None;
+    }
+    let time = time.parse().ok()?;
+
+    Some((time, offset.map(parse_offset).unwrap_or(Some(0))?))
+}
+
+/// `[Day, ]DD Mon YYYY HH:MM[:SS] [zone]`
+fn parse_rfc2822(date: &str) -> Option<(i64, i32)> {
+    let date = date.split_once(", ").map(|(_, rest)| rest).unwrap_or(date);
+    let mut fields = date.split_whitespace();
+
+    let day: u32 = fields.next()?.parse().ok()?;
+    let month = fields.next()?.to_lowercase();
+    let month = MONTHS.iter().position(|name| month.starts_with(name))? as u32 + 1;
+    let year: i64 = fields.next()?.parse().ok()?;
+    let (hour, minute, second) = parse_clock(fields.next()?)?;
+    let offset = match fields.next() {
+        Some(zone) => Some(parse_zone(zone)?),
+        None => None,
+    };
+    if fields.next().is_some() {
+        return None;
+    }
+
+    to_epoch(year, month, day, hour, minute, second, offset)
+}
+
+/// `[Day ]Mon DD HH:MM[:SS] YYYY [zone]`
+fn parse_asctime(date: &str) -> Option<(i64, i32)> {
+    let mut fields = date.split_whitespace().peekable();
+    let first = fields.peek()?.to_lowercase();
+    if !MONTHS.iter().any(|name| first.starts_with(name)) {
+        // The day of the week.
+        fields.next();
+    }
+
+    let month = fields.next()?.to_lowercase();
+    let month = MONTHS.iter().position(|name| month.starts_with(name))? as u32 + 1;
+    let day: u32 = fields.next()?.parse().ok()?;
+    let (hour, minute, second) = parse_clock(fields.next()?)?;
+    let year: i64 = fields.next()?.parse().ok()?;
+    let offset = match fields.next() {
+        Some(zone) => Some(parse_zone(zone)?),
+        None => None,
+    };
+    if fields.next().is_some() {
+        return None;
+    }
+
+    to_epoch(year, month, day, hour, minute, second, offset)
+}
+
+/// `YYYY-MM-DD[T ]HH:MM[:SS[.frac]][ ][Z|+HH:MM|+HHMM]`
+fn parse_iso8601(date: &str) -> Option<(i64, i32)> {
+    let (day_part, rest) = date.split_once(['T', ' '])?;
+    let mut day_fields = day_part.split('-');
+    let year: i64 = day_fields.next()?.parse().ok()?;
+    let month: u32 = day_fields.next()?.parse().ok()?;
+    let day: u32 = day_fields.next()?.parse().ok()?;
+    if day_fields.next().is_some() {
+        return None;
+    }
+
+    let rest = rest.trim_start();
+    let zone_start = rest.find(['Z', 'z', '+', '-', ' ']).unwrap_or(rest.len());
+    let (clock, zone) = rest.split_at(zone_start);
+    // Fractional seconds are dropped, like git does.
+    let clock = clock.split('.').next()?;
+    let (hour, minute, second) = parse_clock(clock)?;
+
+    let offset = match zone.trim() {
+        "" => None,
+        zone => Some(parse_zone(zone)?),
+    };
+
+    to_epoch(year, month, day, hour, minute, second, offset)
+}
+
+/// `HH:MM` or `HH:MM:SS`
+fn parse_clock(clock: &str) -> Option<(u32, u32, u32)> {
+    let mut fields = clock.split(':');
+    let hour = fields.next()?.parse().ok()?;
+    let minute = fields.next()?.parse().ok()?;
+    let second = fields.next().map(str::parse).unwrap_or(Ok(0)).ok()?;
+    if fields.next().is_some() || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+
+    Some((hour, minute, second))
+}
+
+/// Parses a zone such as `+0200`, `-05:30`, `Z` or `GMT` into minutes.
+fn parse_zone(zone: &str) -> Option<i32> {
+    match zone.to_uppercase().as_str() {
+        "Z" | "UT" | "UTC" | "GMT" => Some(0),
+        zone => parse_offset(&zone.replace(':', "")),
+    }
+}
+
+/// Parses `+0200` or `-0530` into minutes.
+fn parse_offset(offset: &str) -> Option<i32> {
+    let (sign, digits) = match offset.as_bytes().first()? {
+        b'+' => (1, &offset[1..]),
+        b'-' => (-1, &offset[1..]),
+        _ => return None,
+    };
+    if digits.len() != 4 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let hours: i32 = digits[..2].parse().ok()?;
+    let minutes: i32 = digits[2..].parse().ok()?;
+
+    Some(sign * (hours * 60 + minutes))
+}
+
+/// Converts a wall-clock time at `offset` minutes from UTC, or local time
+/// if `None`, to seconds since the epoch.
+fn to_epoch(year: i64, month: u32, day: u32, hour: u32, minute: u32, second: u32, offset: Option<i32>) -> Option<(i64, i32)> {
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    let wall_clock = days_from_civil(year, month, day) * 86400
+        + i64::from(hour) * 3600
+        + i64::from(minute) * 60
+        + i64::from(second);
+    let offset = offset.unwrap_or_else(|| local_offset(wall_clock));
+
+    Some((wall_clock - i64::from(offset) * 60, offset))
+}
+
+/// Days between 1970-01-01 and the given date in the proleptic Gregorian
+/// calendar.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = i64::from(month);
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146097 + day_of_era - 719468
+}
+
+/// The date `days` after 1970-01-01 as year, month and day; the inverse of
+/// [`days_from_civil`].
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month + 2) / 5 + 1) as u32;
+    let month = if month < 10 { month + 3 } else { month - 9 } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    (year, month, day)
+}
+
+/// Formats a time `offset` minutes from UTC like git's `--date=iso`:
+/// `2005-04-07 22:13:13 +0200`.
+pub fn format_iso(time: i64, offset: i32) -> String {
+    let wall_clock = time + i64::from(offset) * 60;
+    let (year, month, day) = civil_from_days(wall_clock.div_euclid(86400));
+    let seconds = wall_clock.rem_euclid(86400);
+    let sign = if offset < 0 { '-' } else { '+' };
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} {}{:02}{:02}",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60,
+        sign,
+        offset.abs() / 60,
+        offset.abs() % 60
+    )
+}
+
+/// Formats a time `offset` minutes from UTC the way `git log` shows it by
+/// default: `Thu Apr 7 22:13:13 2005 +0200`.
+pub fn format_default(time: i64, offset: i32) -> String {
+    const DAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+    const NAMES: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+    let wall_clock = time + i64::from(offset) * 60;
+    let days = wall_clock.div_euclid(86400);
+    let (year, month, day) = civil_from_days(days);
+    let seconds = wall_clock.rem_euclid(86400);
+    let sign = if offset < 0 { '-' } else { '+' };
+
+    format!(
+        "{} {} {} {:02}:{:02}:{:02} {} {}{:02}{:02}",
+        // 1970-01-01 was a Thursday.
+        DAYS[(days + 4).rem_euclid(7) as usize],
+        NAMES[month as usize - 1],
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60,
+        year,
+        sign,
+        offset.abs() / 60,
+        offset.abs() % 60
+    )
+}
+
+#[cfg(unix)]
+fn local_offset(time: i64) -> i32 {
+    let time = time as libc::time_t;
+    // SAFETY: `localtime_r` only writes to the `tm` we own.
+    unsafe {
+        let mut tm: libc::tm = std::mem::zeroed();
+        if libc::localtime_r(&time, &mut tm).is_null() {
+            return 0;
+        }
+        (tm.tm_gmtoff / 60) as i32
+    }
+}
+
+#[cfg(not(unix))]
+fn local_offset(_time: i64) -> i32 {
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2005-04-07 22:13:13 +0200, the date of git's first commit.
+    const FIRST_COMMIT: (i64, i32) = (1112904793, 120);
+
+    #[test]
+    fn internal_format() {
+        assert_eq!(parse("1112904793 +0200").unwrap(), FIRST_COMMIT);
+        assert_eq!(parse("@1112904793 +0200").unwrap(), FIRST_COMMIT);
+        assert_eq!(parse("@0").unwrap(), (0, 0));
+        // A short bare number could be a date, so it isn't a timestamp.
+        assert!(parse("12345").is_err());
+        assert!(parse("1112904793 +02").is_err());
+    }
+
+    #[test]
+    fn rfc2822() {
+        assert_eq!(parse("Thu, 07 Apr 2005 22:13:13 +0200").unwrap(), FIRST_COMMIT);
+        assert_eq!(parse("7 Apr 2005 22:13:13 +0200").unwrap(), FIRST_COMMIT);
+        assert_eq!(parse("Thu, 07 Apr 2005 20:13:13 GMT").unwrap(), (FIRST_COMMIT.0, 0));
+        assert!(parse("Thu, 07 Foo 2005 22:13:13 +0200").is_err());
+    }
+
+    #[test]
+    fn asctime() {
+        assert_eq!(parse("Thu Apr 7 22:13:13 2005 +0200").unwrap(), FIRST_COMMIT);
+        assert_eq!(parse("Apr  7 22:13:13 2005 +0200").unwrap(), FIRST_COMMIT);
+        // Without a zone, as `ctime` prints it, the date is local time.
+        assert_eq!(parse("Thu Apr  7 22:13:13 2005").unwrap(), to_epoch(2005, 4, 7, 22, 13, 13, None).unwrap());
+        assert!(parse("Thu Apr 7 22:13:13 +0200").is_err());
+        assert!(parse("Thu Apr 7 22:13:13 2005 +0200 extra").is_err());
+    }
+
+    #[test]
+    fn iso8601() {
+        assert_eq!(parse("2005-04-07T22:13:13+02:00").unwrap(), FIRST_COMMIT);
+        assert_eq!(parse("2005-04-07 22:13:13 +0200").unwrap(), FIRST_COMMIT);
+        assert_eq!(parse("2005-04-07T20:13:13.250Z").unwrap(), (FIRST_COMMIT.0, 0));
+        assert!(parse("2005-13-07T22:13:13Z").is_err());
+        assert!(parse("2005-04-07T24:13:13Z").is_err());
+        assert!(parse("1969-12-31T23:59:59Z").is_err());
+    }
+
+    #[test]
+    fn approximate_dates() {
+        let (now, _) = now();
+        assert!((now - approxidate("2 weeks ago").unwrap() - 14 * 86400).abs() <= 1);
+        assert!((now - approxidate("3.days.ago").unwrap() - 3 * 86400).abs() <= 1);
+        assert!((now - approxidate("yesterday").unwrap() - 86400).abs() <= 1);
+        assert!((now - approxidate("last month").unwrap() - 30 * 86400).abs() <= 1);
+        assert_eq!(approxidate("2005-04-07").unwrap(), to_epoch(2005, 4, 7, 0, 0, 0, None).unwrap().0);
+        assert!(approxidate("2 fortnights ago").is_err());
+        assert_eq!(parse_expiry("never").unwrap(), i64::MIN);
+        assert_eq!(parse_expiry("now").unwrap(), i64::MAX);
+    }
+
+    #[test]
+    fn civil_dates_round_trip() {
+        for days in [-719468, -1, 0, 59, 11016, 2932896] {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+        assert_eq!(format_iso(FIRST_COMMIT.0, FIRST_COMMIT.1), "2005-04-07 22:13:13 +0200");
+        assert_eq!(format_iso(0, -330), "1969-12-31 18:30:00 -0530");
+        assert_eq!(format_default(FIRST_COMMIT.0, FIRST_COMMIT.1), "Thu Apr 7 22:13:13 2005 +0200");
+        assert_eq!(format_default(0, -330), "Wed Dec 31 18:30:00 1969 -0530");
+    }
+}
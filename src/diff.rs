@@ -0,0 +1,1940 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use sha1::{Digest, Sha1};
+
+use crate::color::{self, RESET};
+use crate::config::Config;
+use crate::object_id::{HashAlgorithm, ObjectId};
+use crate::objects::Object;
+use crate::quote;
+use crate::regex::Regex;
+use crate::repository::{self, Repository, DEFAULT_BIG_FILE_THRESHOLD};
+use crate::userdiff::{Driver, Drivers, FuncName};
+
+/// Lines of context git shows around each change by default.
+pub const DEFAULT_CONTEXT: usize = 3;
+
+/// How much unchanged text a patch shows around changes.
+#[derive(Debug, Clone, Copy)]
+pub struct Context {
+    /// Lines around each change, `-U`.
+    pub lines: usize,
+    /// Unchanged lines between two hunks, beyond the context of both,
+    /// that may still join them into one, `--inter-hunk-context`.
+    pub inter_hunk: usize,
+    /// Whether to show the whole function around each change, `-W`.
+    pub function: bool,
+}
+
+impl Default for Context {
+    fn default() -> Context {
+        Context { lines: DEFAULT_CONTEXT, inter_hunk: 0, function: false }
+    }
+}
+
+/// The colors of the parts of a patch, from `color.diff.<slot>`.
+#[derive(Debug, Clone)]
+pub struct Colors {
+    /// The header of each file.
+    pub meta: String,
+    /// The line numbers of hunk headers.
+    pub frag: String,
+    /// The function name of hunk headers.
+    pub func: String,
+    pub context: String,
+    pub old: String,
+    pub new: String,
+    /// Whitespace errors in added lines.
+    pub whitespace: String,
+    /// The header of each commit, for `log`.
+    pub commit: String,
+}
+
+impl Colors {
+    pub fn load(config: &Config) -> anyhow::Result<Colors> {
+        let slot = |slot: &str, default: &str| color::slot(config, "diff", slot, default);
+        let context = match config.get("color.diff.context") {
+            Some(_) => slot("context", "normal")?,
+            None => slot("plain", "normal")?,
+        };
+        Ok(Colors {
+            meta: slot("meta", "bold")?,
+            frag: slot("frag", "cyan")?,
+            func: slot("func", "normal")?,
+            context,
+            old: slot("old", "red")?,
+            new: slot("new", "green")?,
+            whitespace: slot("whitespace", "normal red")?,
+            commit: slot("commit", "yellow")?,
+        })
+    }
+}
+
+/// Git only looks this far into a file when deciding if it's binary.
+const BINARY_CHECK_LENGTH: usize = 8000;
+
+/// Splits `content` into lines, each keeping its `\n`.
+fn split_lines(content: &[u8]) -> Vec<&[u8]> {
+    content.split_inclusive(|&b| b == b'\n').collect()
+}
+
+// Tuning from git's xdiff, so diffs come out the same as git's.
+const MAX_COST_MIN: isize = 256;
+const HEURISTIC_MIN_COST: isize = 256;
+const SNAKE_COUNT: isize = 20;
+const HEURISTIC_FACTOR: isize = 4;
+const MAX_EQUAL_LIMIT: usize = 1024;
+const SIMILAR_SCAN_WINDOW: usize = 100;
+const KEEP_DISCARDED_RUN: usize = 4;
+
+/// xdiff's cheap square root, rounded to a power of two.
+fn bogo_sqrt(mut n: usize) -> usize {
+    let mut root = 1;
+    while n > 0 {
+        root <<= 1;
+        n >>= 2;
+    }
+    root
+}
+
+/// Which lines of `old` and `new` changed, found the way git's default
+/// Myers diff finds them: lines with no match on the other side are set
+/// aside first, then the rest are compared by divide and conquer, trading
+/// minimality for speed when the diff gets expensive.
+fn changed_lines(old: &[&[u8]], new: &[&[u8]]) -> (Vec<bool>, Vec<bool>) {
+    // Equal lines share a class, and each class counts its lines per side.
+    let mut ids: HashMap<&[u8], usize> = HashMap::new();
+    let mut counts: Vec<[usize; 2]> = Vec::new();
+    let mut classes: [Vec<usize>; 2] = [Vec::new(), Vec::new()];
+    for (side, lines) in [old, new].into_iter().enumerate() {
+        for line in lines {
+            let next = ids.len();
+            let class = *ids.entry(line).or_insert(next);
+            if class == counts.len() {
+                counts.push([0, 0]);
+            }
+            counts[class][side] += 1;
+            classes[side].push(class);
+        }
+    }
+
+    let prefix = classes[0].iter().zip(&classes[1]).take_while(|(a, b)| a == b).count();
+    let suffix = classes[0][prefix..].iter().rev()
+        .zip(classes[1][prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let mut changes = [vec![false; old.len()], vec![false; new.len()]];
+    let mut kept: [Vec<usize>; 2] = [Vec::new(), Vec::new()];
+    for side in 0..2 {
+        let lines = &classes[side];
+        let end = lines.len() - suffix;
+        let limit = bogo_sqrt(lines.len()).min(MAX_EQUAL_LIMIT);
+        // 0 for lines with no match on the other side, 2 for lines with
+        // many, 1 otherwise.
+        let discard: Vec<u8> = lines.iter()
+            .map(|&class| match counts[class][1 - side] {
+                0 => 0,
+                matches if matches >= limit => 2,
+                _ => 1,
+            })
+            .collect();
+        for i in prefix..end {
+            if discard[i] == 1 || (discard[i] == 2 && !is_discarded_run(&discard, i, prefix, end - 1)) {
+                kept[side].push(i);
+            } else {
+                changes[side][i] = true;
+            }
+        }
+    }
+
+    let hashes = [
+        kept[0].iter().map(|&i| classes[0][i]).collect::<Vec<_>>(),
+        kept[1].iter().map(|&i| classes[1][i]).collect::<Vec<_>>(),
+    ];
+    let diagonals = hashes[0].len() + hashes[1].len() + 3;
+    let mut myers = Myers {
+        old: &hashes[0],
+        new: &hashes[1],
+        forward: vec![0; diagonals],
+        backward: vec![0; diagonals],
+        offset: hashes[1].len() as isize + 1,
+        max_cost: (bogo_sqrt(diagonals) as isize).max(MAX_COST_MIN),
+        old_changes: vec![false; hashes[0].len()],
+        new_changes: vec![false; hashes[1].len()],
+    };
+    myers.compare(0, hashes[0].len() as isize, 0, hashes[1].len() as isize, false);
+
+    for (side, side_changes) in [myers.old_changes, myers.new_changes].into_iter().enumerate() {
+        for (&line, changed) in kept[side].iter().zip(side_changes) {
+            changes[side][line] = changed;
+        }
+    }
+    let [old_changes, new_changes] = changes;
+
+    (old_changes, new_changes)
+}
+
+/// Whether a line with many matches sits among lines with none, where
+/// keeping it would only make for a patchy diff.
+fn is_discarded_run(discard: &[u8], i: usize, start: usize, end: usize) -> bool {
+    let start = start.max(i.saturating_sub(SIMILAR_SCAN_WINDOW));
+    let end = end.min(i + SIMILAR_SCAN_WINDOW);
+
+    let scan = |lines: &mut dyn Iterator<Item = usize>| {
+        let (mut unmatched, mut multiple) = (0, 1);
+        for line in lines {
+            match discard[line] {
+                0 => unmatched += 1,
+                2 => multiple += 1,
+                _ => break,
+            }
+        }
+        (unmatched, multiple)
+    };
+    let (before, before_multiple) = scan(&mut (start..i).rev());
+    if before == 0 {
+        return false;
+    }
+    let (after, after_multiple) = scan(&mut (i + 1..=end));
+    if after == 0 {
+        return false;
+    }
+
+    let multiple = before_multiple + after_multiple;
+    multiple * KEEP_DISCARDED_RUN < multiple + before + after
+}
+
+/// The state of xdiff's divide and conquer Myers search. Diagonals are
+/// indexed from `offset` so they can go negative.
+struct Myers<'a> {
+    old: &'a [usize],
+    new: &'a [usize],
+    forward: Vec<isize>,
+    backward: Vec<isize>,
+    offset: isize,
+    max_cost: isize,
+    old_changes: Vec<bool>,
+    new_changes: Vec<bool>,
+}
+
+/// Where [`Myers::split`] divides a box, and whether each half must be
+/// diffed minimally.
+struct Split {
+    old: isize,
+    new: isize,
+    minimal_low: bool,
+    minimal_high: bool,
+}
+
+impl Myers<'_> {
+    fn f(&self, d: isize) -> isize {
+        self.forward[(d + self.offset) as usize]
+    }
+
+    fn set_f(&mut self, d: isize, value: isize) {
+        self.forward[(d + self.offset) as usize] = value;
+    }
+
+    fn b(&self, d: isize) -> isize {
+        self.backward[(d + self.offset) as usize]
+    }
+
+    fn set_b(&mut self, d: isize, value: isize) {
+        self.backward[(d + self.offset) as usize] = value;
+    }
+
+    fn same(&self, i1: isize, i2: isize) -> bool {
+        self.old[i1 as usize] == self.new[i2 as usize]
+    }
+
+    /// Marks the changes between `old[off1..lim1]` and `new[off2..lim2]`.
+    fn compare(&mut self, mut off1: isize, mut lim1: isize, mut off2: isize, mut lim2: isize, minimal: bool) {
+        while off1 < lim1 && off2 < lim2 && self.same(off1, off2) {
+            off1 += 1;
+            off2 += 1;
+        }
+        while off1 < lim1 && off2 < lim2 && self.same(lim1 - 1, lim2 - 1) {
+            lim1 -= 1;
+            lim2 -= 1;
+        }
+
+        if off1 == lim1 {
+            self.new_changes[off2 as usize..lim2 as usize].fill(true);
+        } else if off2 == lim2 {
+            self.old_changes[off1 as usize..lim1 as usize].fill(true);
+        } else {
+            let split = self.split(off1, lim1, off2, lim2, minimal);
+            self.compare(off1, split.old, off2, split.new, split.minimal_low);
+            self.compare(split.old, lim1, split.new, lim2, split.minimal_high);
+        }
+    }
+
+    /// Finds a point on an optimal path through the box, searching from
+    /// both corners until the paths meet, or a good enough point once the
+    /// search gets expensive unless `minimal`.
+    fn split(&mut self, off1: isize, lim1: isize, off2: isize, lim2: isize, minimal: bool) -> Split {
+        let (dmin, dmax) = (off1 - lim2, lim1 - off2);
+        let (fmid, bmid) = (off1 - off2, lim1 - lim2);
+        let odd = (fmid - bmid) & 1 != 0;
+        let (mut fmin, mut fmax) = (fmid, fmid);
+        let (mut bmin, mut bmax) = (bmid, bmid);
+
+        self.set_f(fmid, off1);
+        self.set_b(bmid, lim1);
+
+        for cost in 1.. {
+            let mut got_snake = false;
+
+            // Widen the diagonals searched by one, staying in the box.
+            if fmin > dmin {
+                fmin -= 1;
+                self.set_f(fmin - 1, -1);
+            } else {
+                fmin += 1;
+            }
+            if fmax < dmax {
+                fmax += 1;
+                self.set_f(fmax + 1, -1);
+            } else {
+                fmax -= 1;
+            }
+
+            for d in (fmin..=fmax).rev().step_by(2) {
+                let mut i1 = if self.f(d - 1) >= self.f(d + 1) { self.f(d - 1) + 1 } else { self.f(d + 1) };
+                let start = i1;
+                let mut i2 = i1 - d;
+                while i1 < lim1 && i2 < lim2 && self.same(i1, i2) {
+                    i1 += 1;
+                    i2 += 1;
+                }
+                got_snake |= i1 - start > SNAKE_COUNT;
+                self.set_f(d, i1);
+                if odd && bmin <= d && d <= bmax && self.b(d) <= i1 {
+                    return Split { old: i1, new: i2, minimal_low: true, minimal_high: true };
+                }
+            }
+
+            if bmin > dmin {
+                bmin -= 1;
+                self.set_b(bmin - 1, isize::MAX);
+            } else {
+                bmin += 1;
+            }
+            if bmax < dmax {
+                bmax += 1;
+                self.set_b(bmax + 1, isize::MAX);
+            } else {
+                bmax -= 1;
+            }
+
+            for d in (bmin..=bmax).rev().step_by(2) {
+                let mut i1 = if self.b(d - 1) < self.b(d + 1) { self.b(d - 1) } else { self.b(d + 1) - 1 };
+                let start = i1;
+                let mut i2 = i1 - d;
+                while i1 > off1 && i2 > off2 && self.same(i1 - 1, i2 - 1) {
+                    i1 -= 1;
+                    i2 -= 1;
+                }
+                got_snake |= start - i1 > SNAKE_COUNT;
+                self.set_b(d, i1);
+                if !odd && fmin <= d && d <= fmax && i1 <= self.f(d) {
+                    return Split { old: i1, new: i2, minimal_low: true, minimal_high: true };
+                }
+            }
+
+            if minimal {
+                continue;
+            }
+
+            // Past some cost, settle for a diagonal that has come far
+            // along a long run of matching lines.
+            if got_snake && cost > HEURISTIC_MIN_COST {
+                let mut best = 0;
+                let mut split = None;
+                for d in (fmin..=fmax).rev().step_by(2) {
+                    let i1 = self.f(d);
+                    let i2 = i1 - d;
+                    let value = (i1 - off1) + (i2 - off2) - (d - fmid).abs();
+                    if value > HEURISTIC_FACTOR * cost
+                        && value > best
+                        && off1 + SNAKE_COUNT <= i1
+                        && i1 < lim1
+                        && off2 + SNAKE_COUNT <= i2
+                        && i2 < lim2
+                        && (1..=SNAKE_COUNT).all(|k| self.same(i1 - k, i2 - k))
+                    {
+                        best = value;
+                        split = Some((i1, i2));
+                    }
+                }
+                if let Some((old, new)) = split {
+                    return Split { old, new, minimal_low: true, minimal_high: false };
+                }
+
+                let mut best = 0;
+                for d in (bmin..=bmax).rev().step_by(2) {
+                    let i1 = self.b(d);
+                    let i2 = i1 - d;
+                    let value = (lim1 - i1) + (lim2 - i2) - (d - bmid).abs();
+                    if value > HEURISTIC_FACTOR * cost
+                        && value > best
+                        && off1 < i1
+                        && i1 <= lim1 - SNAKE_COUNT
+                        && off2 < i2
+                        && i2 <= lim2 - SNAKE_COUNT
+                        && (0..SNAKE_COUNT).all(|k| self.same(i1 + k, i2 + k))
+                    {
+                        best = value;
+                        split = Some((i1, i2));
+                    }
+                }
+                if let Some((old, new)) = split {
+                    return Split { old, new, minimal_low: false, minimal_high: true };
+                }
+            }
+
+            // Enough is enough: take whichever path got furthest.
+            if cost >= self.max_cost {
+                let (mut forward_best, mut forward_i1) = (-1, -1);
+                for d in (fmin..=fmax).rev().step_by(2) {
+                    let mut i1 = self.f(d).min(lim1);
+                    let mut i2 = i1 - d;
+                    if lim2 < i2 {
+                        i1 = lim2 + d;
+                        i2 = lim2;
+                    }
+                    if forward_best < i1 + i2 {
+                        forward_best = i1 + i2;
+                        forward_i1 = i1;
+                    }
+                }
+
+                let (mut backward_best, mut backward_i1) = (isize::MAX, isize::MAX);
+                for d in (bmin..=bmax).rev().step_by(2) {
+                    let mut i1 = self.b(d).max(off1);
+                    let mut i2 = i1 - d;
+                    if i2 < off2 {
+                        i1 = off2 + d;
+                        i2 = off2;
+                    }
+                    if i1 + i2 < backward_best {
+                        backward_best = i1 + i2;
+                        backward_i1 = i1;
+                    }
+                }
+
+                return if (lim1 + lim2) - backward_best < forward_best - (off1 + off2) {
+                    Split { old: forward_i1, new: forward_best - forward_i1, minimal_low: true, minimal_high: false }
+                } else {
+                    Split { old: backward_i1, new: backward_best - backward_i1, minimal_low: false, minimal_high: true }
+                };
+            }
+        }
+
+        unreachable!("The search always ends")
+    }
+}
+
+/// A run of changed lines `start..end` on one side; empty between runs.
+#[derive(Clone, Copy)]
+struct Group {
+    start: usize,
+    end: usize,
+}
+
+/// One side of a diff being tidied up by [`compact`].
+struct Side<'a> {
+    lines: &'a [&'a [u8]],
+    changes: &'a mut [bool],
+}
+
+impl Side<'_> {
+    fn changed(&self, line: usize) -> bool {
+        self.changes.get(line).copied().unwrap_or(false)
+    }
+
+    fn first_group(&self) -> Group {
+        let end = (0..).find(|&line| !self.changed(line)).unwrap();
+        Group { start: 0, end }
+    }
+
+    fn next_group(&self, group: &mut Group) -> bool {
+        if group.end == self.lines.len() {
+            return false;
+        }
+        group.start = group.end + 1;
+        group.end = (group.start..).find(|&line| !self.changed(line)).unwrap();
+        true
+    }
+
+    fn previous_group(&self, group: &mut Group) -> bool {
+        if group.start == 0 {
+            return false;
+        }
+        group.end = group.start - 1;
+        group.start = (0..group.end).rev().find(|&line| !self.changed(line)).map_or(0, |line| line + 1);
+        true
+    }
+
+    /// Moves the group down a line if its first line equals the line
+    /// after it, merging it with any group it then touches.
+    fn slide_down(&mut self, group: &mut Group) -> bool {
+        if group.end >= self.lines.len() || self.lines[group.start] != self.lines[group.end] {
+            return false;
+        }
+        self.changes[group.start] = false;
+        self.changes[group.end] = true;
+        group.start += 1;
+        group.end = (group.end..).find(|&line| !self.changed(line)).unwrap();
+        true
+    }
+
+    fn slide_up(&mut self, group: &mut Group) -> bool {
+        if group.start == 0 || self.lines[group.start - 1] != self.lines[group.end - 1] {
+            return false;
+        }
+        group.start -= 1;
+        group.end -= 1;
+        self.changes[group.start] = true;
+        self.changes[group.end] = false;
+        group.start = (0..group.start).rev().find(|&line| !self.changed(line)).map_or(0, |line| line + 1);
+        true
+    }
+}
+
+/// Slides each group of changes in `side` to where it reads best, the way
+/// git does: lined up with a change on the `other` side if it can be, or
+/// else where the indent heuristic puts it.
+fn compact(side: &mut Side, other: &mut Side) {
+    let mut group = side.first_group();
+    let mut other_group = other.first_group();
+
+    loop {
+        if group.end != group.start {
+            let mut end_matching_other;
+            let mut earliest_end;
+            let mut size;
+            loop {
+                size = group.end - group.start;
+                end_matching_other = None;
+
+                while side.slide_up(&mut group) {
+                    other.previous_group(&mut other_group);
+                }
+                earliest_end = group.end;
+                if other_group.end > other_group.start {
+                    end_matching_other = Some(group.end);
+                }
+
+                while side.slide_down(&mut group) {
+                    other.next_group(&mut other_group);
+                    if other_group.end > other_group.start {
+                        end_matching_other = Some(group.end);
+                    }
+                }
+
+                // Sliding can merge groups; go again until it doesn't.
+                if size == group.end - group.start {
+                    break;
+                }
+            }
+
+            if group.end == earliest_end {
+                // It can't move.
+            } else if end_matching_other.is_some() {
+                while other_group.end == other_group.start {
+                    side.slide_up(&mut group);
+                    other.previous_group(&mut other_group);
+                }
+            } else {
+                let best = best_shift(side.lines, group, size, earliest_end);
+                while group.end > best {
+                    side.slide_up(&mut group);
+                    other.previous_group(&mut other_group);
+                }
+            }
+        }
+
+        if !side.next_group(&mut group) {
+            break;
+        }
+        other.next_group(&mut other_group);
+    }
+}
+
+/// The indent of a line with tabs as 8 columns, or `None` if it's blank.
+fn indent(line: &[u8]) -> Option<i32> {
+    const MAX_INDENT: i32 = 200;
+
+    let mut indent = 0;
+    for &b in line {
+        match b {
+            b' ' => indent += 1,
+            b'\t' => indent += 8 - indent % 8,
+            b'\n' | b'\r' | 0x0b | 0x0c => {}
+            _ => return Some(indent),
+        }
+        if indent >= MAX_INDENT {
+            return Some(MAX_INDENT);
+        }
+    }
+
+    None
+}
+
+/// How badly splitting before line `split` reads, from the indents and
+/// blank lines around it: git's indent heuristic.
+#[derive(Default)]
+struct SplitScore {
+    effective_indent: i32,
+    penalty: i32,
+}
+
+impl SplitScore {
+    fn add(&mut self, lines: &[&[u8]], split: usize) {
+        const MAX_BLANKS: i32 = 20;
+
+        let end_of_file = split >= lines.len();
+        let line_indent = lines.get(split).and_then(|line| indent(line));
+
+        let mut pre_blank = 0;
+        let mut pre_indent = None;
+        for line in lines[..split.min(lines.len())].iter().rev() {
+            pre_indent = indent(line);
+            if pre_indent.is_some() {
+                break;
+            }
+            pre_blank += 1;
+            if pre_blank == MAX_BLANKS {
+                pre_indent = Some(0);
+                break;
+            }
+        }
+
+        let mut post_blank = 0;
+        let mut post_indent = None;
+        for line in lines.iter().skip(split + 1) {
+            post_indent = indent(line);
+            if post_indent.is_some() {
+                break;
+            }
+            post_blank += 1;
+            if post_blank == MAX_BLANKS {
+                post_indent = Some(0);
+                break;
+            }
+        }
+
+        if pre_indent.is_none() && pre_blank == 0 {
+            self.penalty += 1;
+        }
+        if end_of_file {
+            self.penalty += 21;
+        }
+        let post_blank = if line_indent.is_none() { 1 + post_blank } else { 0 };
+        let total_blank = pre_blank + post_blank;
+        self.penalty += -30 * total_blank + 6 * post_blank;
+
+        let any_blanks = total_blank != 0;
+        let indent = line_indent.or(post_indent);
+        self.effective_indent += indent.unwrap_or(-1);
+        match (indent, pre_indent) {
+            (Some(indent), Some(pre_indent)) if indent > pre_indent => {
+                self.penalty += if any_blanks { 10 } else { -4 };
+            }
+            (Some(indent), Some(pre_indent)) if indent < pre_indent => {
+                self.penalty += match (post_indent.is_some_and(|post| post > indent), any_blanks) {
+                    (true, true) => 17,
+                    (true, false) => 24,
+                    (false, true) => 17,
+                    (false, false) => 23,
+                };
+            }
+            _ => {}
+        }
+    }
+
+    fn cmp(&self, other: &SplitScore) -> i32 {
+        let indents = match self.effective_indent.cmp(&other.effective_indent) {
+            Ordering::Less => -1,
+            Ordering::Equal => 0,
+            Ordering::Greater => 1,
+        };
+        60 * indents + self.penalty - other.penalty
+    }
+}
+
+/// The end position between `earliest_end` and the group's lowest one at
+/// which it reads best.
+fn best_shift(lines: &[&[u8]], group: Group, size: usize, earliest_end: usize) -> usize {
+    const MAX_SLIDING: usize = 100;
+
+    let lowest = earliest_end.max(group.end.saturating_sub(size + 1)).max(group.end.saturating_sub(MAX_SLIDING));
+    let mut best: Option<(usize, SplitScore)> = None;
+    for shift in lowest..=group.end {
+        let mut score = SplitScore::default();
+        score.add(lines, shift);
+        score.add(lines, shift - size);
+        match &best {
+            Some((_, best)) if score.cmp(best) > 0 => {}
+            _ => best = Some((shift, score)),
+        }
+    }
+
+    best.map_or(group.end, |(shift, _)| shift)
+}
+
+/// A change: `old_len` lines from `old_start` replaced by `new_len`
+/// lines from `new_start`.
+struct Change {
+    old_start: usize,
+    old_len: usize,
+    new_start: usize,
+    new_len: usize,
+}
+
+fn changes(old_changes: &[bool], new_changes: &[bool]) -> Vec<Change> {
+    let mut changes = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old_changes.len() || j < new_changes.len() {
+        let old_len = old_changes[i..].iter().take_while(|&&changed| changed).count();
+        let new_len = new_changes[j..].iter().take_while(|&&changed| changed).count();
+        if old_len + new_len > 0 {
+            changes.push(Change { old_start: i, old_len, new_start: j, new_len });
+        }
+        i += old_len + 1;
+        j += new_len + 1;
+    }
+
+    changes
+}
+
+/// The part of `line` to show in a hunk header if it starts a function:
+/// what `funcname` picks out or, without one, the line if it starts with
+/// a letter, `_` or `$`, like a function definition.
+fn function_line<'a>(line: &'a [u8], funcname: Option<&FuncName>) -> Option<&'a [u8]> {
+    match funcname {
+        Some(funcname) => funcname.find(line),
+        None => line.first().is_some_and(|&b| b.is_ascii_alphabetic() || b == b'_' || b == b'$').then_some(line),
+    }
+}
+
+/// The first line from `start` towards `limit`, which is left out, that
+/// starts a function.
+fn find_function(lines: &[&[u8]], funcname: Option<&FuncName>, start: isize, limit: isize) -> Option<isize> {
+    let step = if start > limit { -1 } else { 1 };
+    let mut i = start;
+    while i != limit && 0 <= i && i < lines.len() as isize {
+        if function_line(lines[i as usize], funcname).is_some() {
+            return Some(i);
+        }
+        i += step;
+    }
+
+    None
+}
+
+fn is_blank(line: &[u8]) -> bool {
+    line.iter().all(u8::is_ascii_whitespace)
+}
+
+/// The text git shows after a hunk header: the nearest line above the
+/// hunk that starts a function.
+fn function_name<'a>(lines: &[&'a [u8]], before: usize, funcname: Option<&FuncName>) -> &'a [u8] {
+    lines[..before]
+        .iter()
+        .rev()
+        .find_map(|line| function_line(line, funcname))
+        .map(|line| {
+            let line = &line[..line.len().min(80)];
+            let end = line.iter().rposition(|b| !b.is_ascii_whitespace()).map_or(0, |end| end + 1);
+            &line[..end]
+        })
+        .unwrap_or_default()
+}
+
+/// A hunk header range: `start,len`, with `len` left out if it's 1 and
+/// `start` naming the line before if the range is empty.
+fn range(start: usize, len: usize) -> String {
+    match len {
+        0 => format!("{},0", start),
+        1 => format!("{}", start + 1),
+        len => format!("{},{}", start + 1, len),
+    }
+}
+
+/// Writes the hunks of a unified diff from `old` to `new`, with `context`
+/// around each change and hunk headers naming the function each starts
+/// in, found with `funcname` if given. Hunk boundaries are placed the
+/// way git's default Myers diff with the indent heuristic places them.
+pub(crate) fn unified(old: &[u8], new: &[u8], context: &Context, funcname: Option<&FuncName>, out: &mut Vec<u8>) {
+    let old_lines = split_lines(old);
+    let new_lines = split_lines(new);
+    let (mut old_changes, mut new_changes) = changed_lines(&old_lines, &new_lines);
+    {
+        let mut old_side = Side { lines: &old_lines, changes: &mut old_changes };
+        let mut new_side = Side { lines: &new_lines, changes: &mut new_changes };
+        compact(&mut old_side, &mut new_side);
+        compact(&mut new_side, &mut old_side);
+    }
+
+    let changes = changes(&old_changes, &new_changes);
+    let mut next = 0;
+    while next < changes.len() {
+        // Changes close enough to share context go in one hunk.
+        let joined = 2 * context.lines + context.inter_hunk;
+        let mut last = next;
+        while changes.get(last + 1).is_some_and(|change| {
+            change.old_start - (changes[last].old_start + changes[last].old_len) <= joined
+        }) {
+            last += 1;
+        }
+        let (old_start, new_start) = hunk_start(&old_lines, &new_lines, &changes[next], context, funcname);
+        let (old_end, new_end) = hunk_end(&old_lines, &new_lines, &changes, &mut last, context, funcname);
+        next = last + 1;
+
+        out.extend_from_slice(
+            format!("@@ -{} +{} @@", range(old_start, old_end - old_start), range(new_start, new_end - new_start))
+                .as_bytes(),
+        );
+        let function = function_name(&old_lines, old_start, funcname);
+        if !function.is_empty() {
+            out.push(b' ');
+            out.extend_from_slice(function);
+        }
+        out.push(b'\n');
+
+        let (mut i, mut j) = (old_start, new_start);
+        while i < old_end || j < new_end {
+            let (marker, line) = if i < old_end && old_changes[i] {
+                i += 1;
+                (b'-', old_lines[i - 1])
+            } else if j < new_end && new_changes[j] {
+                j += 1;
+                (b'+', new_lines[j - 1])
+            } else {
+                i += 1;
+                j += 1;
+                (b' ', old_lines[i - 1])
+            };
+            out.push(marker);
+            out.extend_from_slice(line);
+            if !line.ends_with(b"\n") {
+                out.extend_from_slice(b"\n\\ No newline at end of file\n");
+            }
+        }
+    }
+}
+
+/// Where a hunk starting with `first` starts on each side: `context`
+/// lines before it or, with function context, at the function it is in
+/// and the comments just above that.
+fn hunk_start(
+    old_lines: &[&[u8]],
+    new_lines: &[&[u8]],
+    first: &Change,
+    context: &Context,
+    funcname: Option<&FuncName>,
+) -> (usize, usize) {
+    let old_start = first.old_start.saturating_sub(context.lines);
+    let new_start = first.new_start.saturating_sub(context.lines);
+    if !context.function {
+        return (old_start, new_start);
+    }
+
+    let mut start = first.old_start as isize;
+    if first.old_start >= old_lines.len() {
+        // Lines added at the end need no more if they hold a whole
+        // function, and otherwise the function at the end of the file.
+        if (first.new_start..new_lines.len()).any(|i| function_line(new_lines[i], funcname).is_some()) {
+            return (old_start, new_start);
+        }
+        start = old_lines.len() as isize - 1;
+    }
+    let mut function = find_function(old_lines, funcname, start, -1).unwrap_or(-1);
+    while function > 0
+        && !is_blank(old_lines[function as usize - 1])
+        && function_line(old_lines[function as usize - 1], funcname).is_none()
+    {
+        function -= 1;
+    }
+    let function = function.max(0) as usize;
+    match function < old_start {
+        true => (function, new_start.saturating_sub(old_start - function)),
+        false => (old_start, new_start),
+    }
+}
+
+/// Where the hunk ending with `changes[last]` ends on each side: `context`
+/// lines after it or, with function context, at the end of the function
+/// it is in, joining any later change in the same function into the hunk.
+fn hunk_end(
+    old_lines: &[&[u8]],
+    new_lines: &[&[u8]],
+    changes: &[Change],
+    last: &mut usize,
+    context: &Context,
+    funcname: Option<&FuncName>,
+) -> (usize, usize) {
+    loop {
+        let change = &changes[*last];
+        let (old_end, new_end) = (change.old_start + change.old_len, change.new_start + change.new_len);
+        let lines = context.lines.min(old_lines.len() - old_end).min(new_lines.len() - new_end);
+        let (mut old_end, mut new_end) = (old_end + lines, new_end + lines);
+        if !context.function {
+            return (old_end, new_end);
+        }
+
+        let start = (change.old_start + change.old_len) as isize;
+        let mut function = find_function(old_lines, funcname, start, old_lines.len() as isize).unwrap_or(-1);
+        while function > 0 && is_blank(old_lines[function as usize - 1]) {
+            function -= 1;
+        }
+        let function = if function < 0 { old_lines.len() } else { function as usize };
+        if function > old_end {
+            new_end = (new_end + function - old_end).min(new_lines.len());
+            old_end = function;
+        }
+
+        // A later change in the same function, or close to its end, is
+        // in this hunk too.
+        if let Some(next) = changes.get(*last + 1) {
+            let line = next.old_start.min(old_lines.len() - 1) as isize;
+            if line - context.lines as isize <= old_end as isize
+                || find_function(old_lines, funcname, line, old_end as isize).is_none()
+            {
+                *last += 1;
+                continue;
+            }
+        }
+        return (old_end, new_end);
+    }
+}
+
+fn is_binary(content: &[u8]) -> bool {
+    content[..content.len().min(BINARY_CHECK_LENGTH)].contains(&0)
+}
+
+/// A file as compared by `diff --no-index` or between trees.
+struct File {
+    /// The path as shown, without a leading `/`.
+    name: String,
+    mode: u32,
+    /// Empty for a big file, which is never read whole.
+    content: Vec<u8>,
+    size: usize,
+    /// Larger than `core.bigFileThreshold`, so compared as binary by ID.
+    big: bool,
+    /// The blob's ID, when it comes from a repository or is big.
+    id: Option<ObjectId>,
+    /// Where the file is, for `diff --no-index`.
+    path: Option<PathBuf>,
+    /// What its `diff` attribute selects.
+    driver: Option<Arc<Driver>>,
+}
+
+impl File {
+    fn read(path: &Path, threshold: u64, drivers: &Drivers) -> anyhow::Result<File> {
+        let metadata = fs::symlink_metadata(path)
+            .map_err(|err| anyhow!("Could not access '{}': {}", path.display(), err))?;
+        let name = path.to_string_lossy().trim_start_matches('/').to_string();
+        let driver = drivers.get(&name)?;
+        let file = |mode, content: Vec<u8>, size, big, id| {
+            let path = Some(path.to_path_buf());
+            File { name: name.clone(), mode, content, size, big, id, path, driver: driver.clone() }
+        };
+        #[cfg(unix)]
+        let executable = {
+            use std::os::unix::fs::PermissionsExt;
+
+            metadata.permissions().mode() & 0o100 != 0
+        };
+        // Other systems have no executable bit to report.
+        #[cfg(not(unix))]
+        let executable = false;
+        let mode = if executable { 0o100755 } else { 0o100644 };
+        if metadata.is_file() && metadata.len() > threshold {
+            // Without a repository there's no object format to follow.
+            let mut hasher = Sha1::new();
+            hasher.update(format!("blob {}\0", metadata.len()));
+            std::io::copy(&mut fs::File::open(path)?, &mut hasher)?;
+            let id = ObjectId::Sha1(hasher.finalize().into());
+            return Ok(file(mode, Vec::new(), metadata.len() as usize, true, Some(id)));
+        }
+
+        let (mode, content) = if metadata.file_type().is_symlink() {
+            (0o120000, fs::read_link(path)?.into_os_string().into_encoded_bytes())
+        } else {
+            (mode, fs::read(path)?)
+        };
+
+        let size = content.len();
+        Ok(file(mode, content, size, false, None))
+    }
+
+    fn from_tree(
+        repository: &Repository,
+        name: &str,
+        mode: u32,
+        id: &ObjectId,
+        threshold: u64,
+        drivers: &Drivers,
+    ) -> anyhow::Result<File> {
+        let driver = drivers.get(name)?;
+        let (kind, size) = repository.object_header(id)?;
+        if kind == "blob" && size > threshold {
+            let (name, content, size) = (name.to_string(), Vec::new(), size as usize);
+            return Ok(File { name, mode, content, size, big: true, id: Some(*id), path: None, driver });
+        }
+        let content = match repository.read(id)? {
+            Object::Blob(content) => content,
+            object => return Err(anyhow!("Object {} is a {}, not a blob", id, object.kind())),
+        };
+
+        let (name, size) = (name.to_string(), content.len());
+        Ok(File { name, mode, size, content, big: false, id: Some(*id), path: None, driver })
+    }
+
+    /// Whether the file is shown as binary: if it is big, if its driver
+    /// says so, or else if it has a NUL near the start.
+    fn is_binary(&self) -> bool {
+        self.big || self.driver.as_ref().and_then(|driver| driver.binary).unwrap_or_else(|| is_binary(&self.content))
+    }
+
+    fn funcname(&self) -> Option<&FuncName> {
+        self.driver.as_ref().and_then(|driver| driver.funcname.as_ref())
+    }
+
+    /// Whether `old` and `new` have the same content; big files, whose
+    /// content isn't read, by their IDs.
+    fn same_content(old: Option<&File>, new: Option<&File>) -> bool {
+        match (old, new) {
+            (Some(old), Some(new)) if old.big || new.big => old.id.is_some() && old.id == new.id,
+            (Some(old), Some(new)) => old.content == new.content,
+            _ => false,
+        }
+    }
+
+    fn abbreviated_id(file: Option<&File>) -> String {
+        // Without a repository there's no object format to follow.
+        let id = match file {
+            Some(File { id: Some(id), .. }) => *id,
+            Some(file) => {
+                let header = format!("blob {}\0", file.content.len());
+                HashAlgorithm::Sha1.hash(&[header.as_bytes(), &file.content].concat())
+            }
+            None => HashAlgorithm::Sha1.null(),
+        };
+        id.to_string()[..7].to_string()
+    }
+}
+
+/// The `index` line of a patch: both abbreviated IDs, and the mode if it
+/// didn't change.
+fn index_line(old: Option<&File>, new: Option<&File>) -> String {
+    let ids = format!("index {}..{}", File::abbreviated_id(old), File::abbreviated_id(new));
+    match (old, new) {
+        (Some(old), Some(new)) if old.mode == new.mode => format!("{} {:06o}\n", ids, old.mode),
+        _ => format!("{}\n", ids),
+    }
+}
+
+/// Writes a git-style patch from `old` to `new`, where a missing side is
+/// an added or deleted file and `similarity`, a percentage, says `new`
+/// was moved from `old`. Returns whether they differ.
+fn write_patch(
+    old: Option<&File>,
+    new: Option<&File>,
+    similarity: Option<usize>,
+    context: &Context,
+    colors: Option<&Colors>,
+    out: &mut Vec<u8>,
+) -> bool {
+    let (old_name, new_name) = match (old, new) {
+        (Some(old), Some(new)) => (&old.name, &new.name),
+        (Some(file), None) | (None, Some(file)) => (&file.name, &file.name),
+        (None, None) => return false,
+    };
+    let empty = Vec::new();
+    let old_content = old.map_or(&empty, |file| &file.content);
+    let new_content = new.map_or(&empty, |file| &file.content);
+    let same_content = File::same_content(old, new);
+    if same_content && old.map(|file| file.mode) == new.map(|file| file.mode) && similarity.is_none() {
+        return false;
+    }
+
+    let mut header = Vec::new();
+    header.extend_from_slice(format!("diff --git a/{} b/{}\n", old_name, new_name).as_bytes());
+    match (old, new) {
+        (None, Some(new)) => header.extend_from_slice(format!("new file mode {:06o}\n", new.mode).as_bytes()),
+        (Some(old), None) => header.extend_from_slice(format!("deleted file mode {:06o}\n", old.mode).as_bytes()),
+        (Some(old), Some(new)) if old.mode != new.mode => {
+            header.extend_from_slice(format!("old mode {:06o}\nnew mode {:06o}\n", old.mode, new.mode).as_bytes());
+        }
+        _ => {}
+    }
+    if let Some(similarity) = similarity {
+        header.extend_from_slice(
+            format!("similarity index {}%\nrename from {}\nrename to {}\n", similarity, old_name, new_name).as_bytes(),
+        );
+    }
+    if !same_content {
+        header.extend_from_slice(index_line(old, new).as_bytes());
+    }
+
+    let old_label = old.map_or("/dev/null".to_string(), |file| format!("a/{}", file.name));
+    let new_label = new.map_or("/dev/null".to_string(), |file| format!("b/{}", file.name));
+    let binary = old.is_some_and(File::is_binary) || new.is_some_and(File::is_binary);
+    let hunks = !same_content && !binary && (!old_content.is_empty() || !new_content.is_empty());
+    if hunks {
+        header.extend_from_slice(format!("--- {}\n+++ {}\n", old_label, new_label).as_bytes());
+    }
+    match colors {
+        Some(colors) => paint_lines(&header, &colors.meta, out),
+        None => out.extend_from_slice(&header),
+    }
+
+    if !same_content && binary {
+        out.extend_from_slice(format!("Binary files {} and {} differ\n", old_label, new_label).as_bytes());
+    } else if hunks {
+        // Like git, the old file's driver comes first.
+        let funcname = old.and_then(File::funcname).or_else(|| new.and_then(File::funcname));
+        match colors {
+            Some(colors) => {
+                let mut plain = Vec::new();
+                unified(old_content, new_content, context, funcname, &mut plain);
+                paint_hunks(&plain, old_content, new_content, colors, out);
+            }
+            None => unified(old_content, new_content, context, funcname, out),
+        }
+    }
+
+    true
+}
+
+/// Writes each line of `text` in `color`.
+fn paint_lines(text: &[u8], color: &str, out: &mut Vec<u8>) {
+    for line in text.split_inclusive(|&b| b == b'\n') {
+        out.extend_from_slice(color.as_bytes());
+        out.extend_from_slice(line.strip_suffix(b"\n").unwrap_or(line));
+        out.extend_from_slice(RESET.as_bytes());
+        out.push(b'\n');
+    }
+}
+
+/// How many whitespace-only lines `content` ends with.
+fn trailing_blank_lines(content: &[u8]) -> usize {
+    let Some(content) = content.strip_suffix(b"\n") else { return 0 };
+    content.rsplit(|&b| b == b'\n').take_while(|line| line.iter().all(u8::is_ascii_whitespace)).count()
+}
+
+/// Writes the `hunks` of a patch from `old` to `new` in `colors` the way
+/// git does: the lines that are removed, added or unchanged, hunk headers
+/// and function names, and in added lines the whitespace errors git
+/// looks for by default. Those are whitespace at the end of a line,
+/// spaces before a tab in the indentation, and blank lines added at the
+/// end of the file.
+fn paint_hunks(hunks: &[u8], old: &[u8], new: &[u8], colors: &Colors, out: &mut Vec<u8>) {
+    let line_count = |content: &[u8]| split_lines(content).len();
+    // The first line of the blank lines each side ends with, counting
+    // from 1, if the new side has more of them.
+    let (old_blank, new_blank) = (trailing_blank_lines(old), trailing_blank_lines(new));
+    let blank_at_eof = (new_blank > old_blank)
+        .then(|| (line_count(old) - old_blank + 1, line_count(new) - new_blank + 1));
+    let (mut old_line, mut new_line) = (0, 0);
+    for line in hunks.split_inclusive(|&b| b == b'\n') {
+        let line = line.strip_suffix(b"\n").unwrap_or(line);
+        let (marker, text) = line.split_first().map_or((b' ', &[][..]), |(marker, text)| (*marker, text));
+        let paint = |color: &str, text: &[u8], out: &mut Vec<u8>| {
+            out.extend_from_slice(color.as_bytes());
+            out.extend_from_slice(text);
+            out.extend_from_slice(RESET.as_bytes());
+        };
+        match marker {
+            b'@' => {
+                let end = line[2..].windows(2).position(|window| window == b"@@").map_or(line.len(), |end| end + 4);
+                let (range, function) = line.split_at(end);
+                let start = |side: u8| -> Option<usize> {
+                    let at = range.iter().position(|&b| b == side)? + 1;
+                    let digits = range[at..].iter().take_while(|b| b.is_ascii_digit()).count();
+                    std::str::from_utf8(&range[at..at + digits]).ok()?.parse().ok()
+                };
+                (old_line, new_line) = (start(b'-').unwrap_or(0), start(b'+').unwrap_or(0));
+                paint(&colors.frag, range, out);
+                let name = function.trim_ascii_start();
+                if name.len() < function.len() {
+                    paint(&colors.context, &function[..function.len() - name.len()], out);
+                }
+                if !name.is_empty() {
+                    paint(&colors.func, name, out);
+                }
+            }
+            b'-' => {
+                paint(&colors.old, line, out);
+                old_line += 1;
+            }
+            b'+' => {
+                let blank = text.iter().all(u8::is_ascii_whitespace);
+                let at_eof =
+                    blank_at_eof.is_some_and(|(old_start, new_start)| old_start <= old_line && new_start <= new_line);
+                if colors.whitespace.is_empty() {
+                    paint(&colors.new, line, out);
+                } else if at_eof && blank {
+                    paint(&colors.whitespace, line, out);
+                } else {
+                    paint(&colors.new, b"+", out);
+                    paint_whitespace_errors(text, colors, out);
+                }
+                new_line += 1;
+            }
+            _ => {
+                paint(&colors.context, line, out);
+                if marker == b' ' {
+                    old_line += 1;
+                    new_line += 1;
+                }
+            }
+        }
+        out.push(b'\n');
+    }
+}
+
+/// Writes the `text` of an added line with the whitespace at its end and
+/// the spaces before a tab in its indentation in the whitespace color.
+fn paint_whitespace_errors(text: &[u8], colors: &Colors, out: &mut Vec<u8>) {
+    let trailing = text.iter().rposition(|b| !b.is_ascii_whitespace()).map_or(0, |end| end + 1);
+    let mut written = 0;
+    for (i, &b) in text[..trailing].iter().enumerate() {
+        match b {
+            b' ' => continue,
+            b'\t' if written < i => {
+                out.extend_from_slice(colors.whitespace.as_bytes());
+                out.extend_from_slice(&text[written..i]);
+                out.extend_from_slice(RESET.as_bytes());
+                out.push(b'\t');
+            }
+            b'\t' => out.extend_from_slice(&text[written..=i]),
+            _ => break,
+        }
+        written = i + 1;
+    }
+    for (color, part) in [(&colors.new, &text[written..trailing]), (&colors.whitespace, &text[trailing..])] {
+        if !part.is_empty() {
+            out.extend_from_slice(color.as_bytes());
+            out.extend_from_slice(part);
+            out.extend_from_slice(RESET.as_bytes());
+        }
+    }
+}
+
+/// The sorted names in a directory.
+fn directory_entries(path: &Path) -> anyhow::Result<Vec<String>> {
+    let mut names = fs::read_dir(path)?
+        .map(|entry| Ok(entry?.file_name().to_string_lossy().to_string()))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    names.sort();
+
+    Ok(names)
+}
+
+/// What `diff --no-index` compares every file with.
+struct Settings {
+    context: Context,
+    threshold: u64,
+    drivers: Drivers,
+    colors: Option<Colors>,
+}
+
+/// Compares `old` and `new`, either of which may be missing, recursing
+/// into directories. Returns whether anything differs.
+fn compare(old: Option<&Path>, new: Option<&Path>, settings: &Settings, out: &mut Vec<u8>) -> anyhow::Result<bool> {
+    let is_dir = |path: Option<&Path>| path.is_some_and(|path| fs::symlink_metadata(path).is_ok_and(|m| m.is_dir()));
+
+    if !is_dir(old) && !is_dir(new) {
+        let old = old.map(|path| File::read(path, settings.threshold, &settings.drivers)).transpose()?;
+        let new = new.map(|path| File::read(path, settings.threshold, &settings.drivers)).transpose()?;
+        let driver = old.as_ref().or(new.as_ref()).and_then(|file| file.driver.as_deref());
+        if let Some(program) = settings.drivers.external(driver) {
+            let mode = |file: &Option<File>| file.as_ref().map(|file| file.mode);
+            if File::same_content(old.as_ref(), new.as_ref()) && mode(&old) == mode(&new) {
+                return Ok(false);
+            }
+            run_external(program, old.as_ref(), new.as_ref(), out)?;
+            return Ok(true);
+        }
+        return Ok(write_patch(old.as_ref(), new.as_ref(), None, &settings.context, settings.colors.as_ref(), out));
+    }
+
+    // A file facing a directory is deleted or added alongside its contents.
+    let mut differs = false;
+    if !is_dir(old) && old.is_some() {
+        differs |= compare(old, None, settings, out)?;
+    }
+    if !is_dir(new) && new.is_some() {
+        differs |= compare(None, new, settings, out)?;
+    }
+
+    let entries = |path: Option<&Path>| -> anyhow::Result<Vec<String>> {
+        if is_dir(path) {
+            directory_entries(path.unwrap())
+        } else {
+            Ok(Vec::new())
+        }
+    };
+    let (old_entries, new_entries) = (entries(old)?, entries(new)?);
+    let (old, new) = (old.filter(|path| is_dir(Some(path))), new.filter(|path| is_dir(Some(path))));
+
+    let mut names: Vec<&String> = old_entries.iter().chain(&new_entries).collect();
+    names.sort();
+    names.dedup();
+    for name in names {
+        let old_path = old.filter(|_| old_entries.contains(name)).map(|dir| dir.join(name));
+        let new_path = new.filter(|_| new_entries.contains(name)).map(|dir| dir.join(name));
+        differs |= compare(old_path.as_deref(), new_path.as_deref(), settings, out)?;
+    }
+
+    Ok(differs)
+}
+
+/// Shows the changes to a file with `program`, the way git runs
+/// `GIT_EXTERNAL_DIFF`: through the shell, with the name, then the path,
+/// ID and mode of each side, `/dev/null` standing in for a missing one.
+/// If the new name differs, it follows, with the patch's `index` line.
+fn run_external(program: &str, old: Option<&File>, new: Option<&File>, out: &mut Vec<u8>) -> anyhow::Result<()> {
+    let name = old.map_or("/dev/null", |file| &file.name);
+    let new_name = new.map_or("/dev/null", |file| &file.name);
+    let mut command = Command::new("sh");
+    command.arg("-c").arg(format!("{} \"$@\"", program)).arg(program).arg(name);
+    for file in [old, new] {
+        match file {
+            Some(file) => {
+                let path = file.path.as_deref().map_or(PathBuf::from(&file.name), Path::to_path_buf);
+                // Files outside the object store have no ID yet, like
+                // worktree files in git.
+                let id = file.id.filter(|_| file.path.is_none()).unwrap_or(HashAlgorithm::Sha1.null());
+                command.arg(path).arg(id.to_string()).arg(format!("{:06o}", file.mode));
+            }
+            None => {
+                command.args(["/dev/null", ".", "."]);
+            }
+        }
+    }
+    if new_name != name {
+        let index = match File::same_content(old, new) {
+            true => String::new(),
+            false => index_line(old, new),
+        };
+        command.arg(new_name).arg(index);
+    }
+    let output = command
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|err| anyhow!("external diff died, stopping at {}: {}", name, err))?;
+    out.extend_from_slice(&output.stdout);
+    if !output.status.success() {
+        std::io::stdout().lock().write_all(out)?;
+        return Err(anyhow!("external diff died, stopping at {}", name));
+    }
+
+    Ok(())
+}
+
+#[derive(Debug)]
+pub struct DiffOptions {
+    /// What to show around changes.
+    pub context: Context,
+    /// Only report whether there are differences, through the result.
+    pub quiet: bool,
+    /// Run `GIT_EXTERNAL_DIFF`, `diff.external` or a driver's command
+    /// instead of showing a patch, if one is set.
+    pub ext_diff: bool,
+    /// `--color` or `--no-color`, else `color.diff` decides.
+    pub color: Option<color::When>,
+}
+
+impl Default for DiffOptions {
+    fn default() -> DiffOptions {
+        DiffOptions { context: Context::default(), quiet: false, ext_diff: true, color: None }
+    }
+}
+
+/// Compares two files or directories outside of any repository, like
+/// `git diff --no-index`, and prints a patch. A file compared with a
+/// directory is compared with the file of the same name in it. Returns
+/// whether they differ.
+pub fn no_index(old: &Path, new: &Path, options: &DiffOptions) -> anyhow::Result<bool> {
+    let resolve = |path: &Path, other: &Path| -> PathBuf {
+        match other.file_name() {
+            Some(name) if path.is_dir() && !other.is_dir() => path.join(name),
+            _ => path.to_path_buf(),
+        }
+    };
+    let (old, new) = (resolve(old, new), resolve(new, old));
+
+    // Outside a repository only the default threshold and the global
+    // config apply, and there are no attributes.
+    let ext_diff = options.ext_diff && !options.quiet;
+    let (threshold, config, drivers) = match repository::current() {
+        Ok(repository) => {
+            let config = repository.config()?;
+            let drivers = Drivers::new(config.clone(), Some(repository.git_dir()), ext_diff);
+            (repository.big_file_threshold()?, config, drivers)
+        }
+        Err(_) => {
+            let config = Config::global()?;
+            (DEFAULT_BIG_FILE_THRESHOLD, config.clone(), Drivers::new(config, None, ext_diff))
+        }
+    };
+    let colors = color::enabled(&config, "diff", options.color).then(|| Colors::load(&config)).transpose()?;
+    let settings = Settings { context: options.context, threshold, drivers, colors };
+    let mut out = Vec::new();
+    let differs = compare(Some(&old), Some(&new), &settings, &mut out)?;
+    if !options.quiet {
+        std::io::stdout().lock().write_all(&out)?;
+    }
+
+    Ok(differs)
+}
+
+/// A file that differs between two trees.
+struct FilePair {
+    old: Option<File>,
+    new: Option<File>,
+    /// For a rename, how much of `new` came from `old`, as a percentage.
+    similarity: Option<usize>,
+}
+
+impl FilePair {
+    fn name(&self) -> &str {
+        match (&self.old, &self.new) {
+            (_, Some(file)) | (Some(file), None) => &file.name,
+            (None, None) => "",
+        }
+    }
+
+    fn is_binary(&self) -> bool {
+        [&self.old, &self.new].into_iter().flatten().any(File::is_binary)
+    }
+
+    /// Lines added and deleted or, for a binary file, its size after and
+    /// before.
+    fn counts(&self) -> (usize, usize) {
+        let empty = Vec::new();
+        let old = self.old.as_ref().map_or(&empty, |file| &file.content);
+        let new = self.new.as_ref().map_or(&empty, |file| &file.content);
+        let size = |file: &Option<File>| file.as_ref().map_or(0, |file| file.size);
+        if File::same_content(self.old.as_ref(), self.new.as_ref()) {
+            (0, 0)
+        } else if self.is_binary() {
+            (size(&self.new), size(&self.old))
+        } else {
+            let (old_changes, new_changes) = changed_lines(&split_lines(old), &split_lines(new));
+            let count = |changes: Vec<bool>| changes.into_iter().filter(|&changed| changed).count();
+            (count(new_changes), count(old_changes))
+        }
+    }
+
+    /// The name a diffstat shows: `old => new` for a rename, with what
+    /// their paths share pulled out, as in `dir/{a => b}`.
+    fn display_name(&self) -> String {
+        match (&self.old, &self.new) {
+            (Some(old), Some(new)) if self.similarity.is_some() => rename_name(&old.name, &new.name),
+            _ => quote::quote_path(self.name(), true).into_owned(),
+        }
+    }
+}
+
+/// git's `pprint_rename`: the directories leading up to and following
+/// the part of the path that changed are only shown once.
+fn rename_name(old: &str, new: &str) -> String {
+    let quoted = (quote::quote_path(old, true), quote::quote_path(new, true));
+    if quoted.0 != old || quoted.1 != new {
+        return format!("{} => {}", quoted.0, quoted.1);
+    }
+
+    let (a, b) = (old.as_bytes(), new.as_bytes());
+    let mut prefix = 0;
+    for (i, (x, y)) in a.iter().zip(b).enumerate() {
+        if x != y {
+            break;
+        }
+        if *x == b'/' {
+            prefix = i + 1;
+        }
+    }
+
+    // The suffix may reach back into the prefix's final slash, but no
+    // further.
+    let floor = prefix.saturating_sub(1);
+    let mut suffix = 0;
+    let (mut i, mut j) = (a.len(), b.len());
+    while i >= floor && j >= floor && a.get(i) == b.get(j) {
+        if a.get(i) == Some(&b'/') {
+            suffix = a.len() - i;
+        }
+        if i == 0 || j == 0 {
+            break;
+        }
+        i -= 1;
+        j -= 1;
+    }
+
+    let old_middle = &old[prefix..a.len().saturating_sub(suffix).max(prefix)];
+    let new_middle = &new[prefix..b.len().saturating_sub(suffix).max(prefix)];
+    if prefix + suffix == 0 {
+        format!("{} => {}", old_middle, new_middle)
+    } else {
+        format!("{}{{{} => {}}}{}", &old[..prefix], old_middle, new_middle, &old[a.len() - suffix..])
+    }
+}
+
+/// Similarity scores run up to this, as in git.
+const MAX_SCORE: usize = 60000;
+
+/// The least similar files taken for a rename: half.
+const MINIMUM_SCORE: usize = 30000;
+
+/// How many of the best sources each added file keeps as rename
+/// candidates.
+const CANDIDATES_PER_FILE: usize = 4;
+
+/// The chunks git compares to estimate how similar two files are, lines
+/// or runs up to 64 bytes, as the number of bytes per chunk hash.
+fn span_hashes(content: &[u8]) -> HashMap<u32, usize> {
+    const HASH_BASE: u32 = 107927;
+
+    let is_text = !is_binary(content);
+    let mut hashes = HashMap::new();
+    let (mut accum1, mut accum2, mut n) = (0u32, 0u32, 0);
+    for (i, &c) in content.iter().enumerate() {
+        // Line ends compare the same with or without a CR.
+        if is_text && c == b'\r' && content.get(i + 1) == Some(&b'\n') {
+            continue;
+        }
+        let old = accum1;
+        accum1 = ((accum1 << 7) ^ (accum2 >> 25)).wrapping_add(u32::from(c));
+        accum2 = (accum2 << 7) ^ (old >> 25);
+        n += 1;
+        if n < 64 && c != b'\n' {
+            continue;
+        }
+        *hashes.entry(accum1.wrapping_add(accum2.wrapping_mul(0x61)) % HASH_BASE).or_default() += n;
+        (accum1, accum2, n) = (0, 0, 0);
+    }
+    if n > 0 {
+        *hashes.entry(accum1.wrapping_add(accum2.wrapping_mul(0x61)) % HASH_BASE).or_default() += n;
+    }
+
+    hashes
+}
+
+/// How much of `new` comes from `old`, up to [`MAX_SCORE`], estimated
+/// the way git's rename detection does. Files whose sizes are too far
+/// apart to reach [`MINIMUM_SCORE`] score 0 without a closer look.
+fn similarity(old: &File, new: &File) -> usize {
+    let is_regular = |file: &File| file.mode & 0o170000 == 0o100000;
+    if !is_regular(old) || !is_regular(new) || old.big || new.big || new.content.is_empty() {
+        return 0;
+    }
+    let max_size = old.content.len().max(new.content.len());
+    let base_size = old.content.len().min(new.content.len());
+    if max_size * (MAX_SCORE - MINIMUM_SCORE) < (max_size - base_size) * MAX_SCORE {
+        return 0;
+    }
+
+    let new_hashes = span_hashes(&new.content);
+    let copied: usize = span_hashes(&old.content)
+        .into_iter()
+        .map(|(hash, count)| count.min(new_hashes.get(&hash).copied().unwrap_or(0)))
+        .sum();
+    copied * MAX_SCORE / max_size
+}
+
+/// Pairs deleted files with added ones they were renamed to: first those
+/// with the same content, then those similar enough, best matches first.
+/// Each pair takes the added file's place.
+fn detect_renames_in(files: &mut Vec<FilePair>) {
+    let deleted: Vec<usize> = (0..files.len()).filter(|&i| files[i].new.is_none()).collect();
+    let added: Vec<usize> = (0..files.len()).filter(|&i| files[i].old.is_none()).collect();
+    let mut renames: HashMap<usize, (usize, usize)> = HashMap::new();
+
+    for &source in &deleted {
+        let id = files[source].old.as_ref().and_then(|file| file.id);
+        let target = added.iter().copied().find(|&target| {
+            !renames.contains_key(&target) && files[target].new.as_ref().is_some_and(|file| file.id == id)
+        });
+        if let Some(target) = target {
+            renames.insert(target, (source, MAX_SCORE));
+        }
+    }
+
+    let used: Vec<usize> = renames.values().map(|&(source, _)| source).collect();
+    let mut candidates = Vec::new();
+    for &target in added.iter().filter(|target| !renames.contains_key(target)) {
+        let new = files[target].new.as_ref().expect("Added files have a new side");
+        let mut best: Vec<(usize, bool, usize, usize)> = Vec::new();
+        for &source in deleted.iter().filter(|source| !used.contains(source)) {
+            let old = files[source].old.as_ref().expect("Deleted files have an old side");
+            let score = similarity(old, new);
+            if score >= MINIMUM_SCORE {
+                let basename = |name: &str| name.rsplit('/').next().unwrap_or_default().to_string();
+                best.push((score, basename(&old.name) == basename(&new.name), target, source));
+            }
+        }
+        best.sort_by_key(|&(score, ..)| std::cmp::Reverse(score));
+        best.truncate(CANDIDATES_PER_FILE);
+        candidates.extend(best);
+    }
+    candidates.sort_by(|a, b| b.0.cmp(&a.0).then(b.1.cmp(&a.1)));
+
+    let mut used_sources: Vec<usize> = used;
+    for (score, _, target, source) in candidates {
+        if renames.contains_key(&target) || used_sources.contains(&source) {
+            continue;
+        }
+        renames.insert(target, (source, score));
+        used_sources.push(source);
+    }
+
+    let mut sources: HashMap<usize, File> = HashMap::new();
+    for &(source, _) in renames.values() {
+        if let Some(old) = files[source].old.take() {
+            sources.insert(source, old);
+        }
+    }
+    for (target, (source, score)) in renames {
+        files[target].old = sources.remove(&source);
+        files[target].similarity = Some(score * 100 / MAX_SCORE);
+    }
+    files.retain(|pair| pair.old.is_some() || pair.new.is_some());
+}
+
+/// What `log -S` and `-G` look for in the changes to a file.
+pub(crate) enum Pickaxe {
+    /// A change in how many times a string occurs.
+    Occurrences(Vec<u8>),
+    /// A change in how many times a regular expression matches.
+    Matches(Regex),
+    /// An added or removed line that a regular expression matches.
+    Lines(Regex),
+}
+
+impl Pickaxe {
+    /// Whether the change from `old` to `new` has what `self` looks for.
+    /// Binary files never do.
+    fn finds(&self, old: Option<&File>, new: Option<&File>) -> bool {
+        if old.is_some_and(File::is_binary) || new.is_some_and(File::is_binary) {
+            return false;
+        }
+        let old = old.map_or(&[][..], |file| &file.content[..]);
+        let new = new.map_or(&[][..], |file| &file.content[..]);
+        match self {
+            Pickaxe::Occurrences(needle) => {
+                let count = |text: &[u8]| match needle.is_empty() {
+                    true => 0,
+                    false => {
+                        let (mut count, mut rest) = (0, text);
+                        while let Some(at) = rest.windows(needle.len()).position(|window| window == &needle[..]) {
+                            count += 1;
+                            rest = &rest[at + needle.len()..];
+                        }
+                        count
+                    }
+                };
+                count(old) != count(new)
+            }
+            Pickaxe::Matches(regex) => {
+                let count = |text: &[u8]| {
+                    let mut count = 0;
+                    for line in split_lines(text) {
+                        let mut rest = line.strip_suffix(b"\n").unwrap_or(line);
+                        while let Some(Some((start, end))) = regex.captures(rest).map(|captures| captures[0]) {
+                            count += 1;
+                            if end >= rest.len() {
+                                break;
+                            }
+                            rest = &rest[end.max(start + 1)..];
+                        }
+                    }
+                    count
+                };
+                count(old) != count(new)
+            }
+            Pickaxe::Lines(regex) => {
+                let mut patch = Vec::new();
+                unified(old, new, &Context { lines: 0, ..Context::default() }, None, &mut patch);
+                patch.split(|&b| b == b'\n')
+                    .filter(|line| line.starts_with(b"+") || line.starts_with(b"-"))
+                    .any(|line| regex.is_match(&line[1..]))
+            }
+        }
+    }
+}
+
+/// The files that differ between two trees, in path order.
+pub struct TreeDiff {
+    files: Vec<FilePair>,
+}
+
+impl TreeDiff {
+    /// Compares the files below trees `old` and `new`. With
+    /// `detect_renames`, a deleted file whose content reappears, perhaps
+    /// edited, under another name is shown as renamed.
+    pub fn new(repository: &Repository, old: &ObjectId, new: &ObjectId, detect_renames: bool) -> anyhow::Result<TreeDiff> {
+        TreeDiff::between(repository, repository.tree_files(old)?, repository.tree_files(new)?, detect_renames)
+    }
+
+    /// Shows every file below `tree` as added, as for a root commit.
+    pub fn root(repository: &Repository, tree: &ObjectId) -> anyhow::Result<TreeDiff> {
+        TreeDiff::between(repository, BTreeMap::new(), repository.tree_files(tree)?, false)
+    }
+
+    fn between(
+        repository: &Repository,
+        old_files: BTreeMap<String, (u32, ObjectId)>,
+        new_files: BTreeMap<String, (u32, ObjectId)>,
+        detect_renames: bool,
+    ) -> anyhow::Result<TreeDiff> {
+        let threshold = repository.big_file_threshold()?;
+        // An in-memory repository has no attributes.
+        let git_dir = (!repository.is_in_memory()).then(|| repository.git_dir());
+        let drivers = Drivers::new(repository.config()?, git_dir, false);
+        let mut names: Vec<&String> = old_files.keys().chain(new_files.keys()).collect();
+        names.sort();
+        names.dedup();
+        let mut files = Vec::new();
+        for name in names {
+            let (old, new) = (old_files.get(name), new_files.get(name));
+            if old == new {
+                continue;
+            }
+            let file = |side: Option<&(u32, ObjectId)>| {
+                side.map(|(mode, id)| File::from_tree(repository, name, *mode, id, threshold, &drivers)).transpose()
+            };
+            files.push(FilePair { old: file(old)?, new: file(new)?, similarity: None });
+        }
+
+        if detect_renames {
+            detect_renames_in(&mut files);
+        }
+
+        Ok(TreeDiff { files })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// The patch ID of the changes, as git computes it to find commits
+    /// that make the same change: a hash of their patch with the hunk
+    /// headers, index lines and all whitespace left out, so that it
+    /// doesn't depend on where in the files or the history the change is.
+    pub fn patch_id(&self, format: HashAlgorithm) -> ObjectId {
+        let stripped = |text: &[u8]| -> Vec<u8> {
+            text.iter().copied().filter(|b| !matches!(b, b' ' | b'\t' | b'\n' | 0x0b | 0x0c | b'\r')).collect()
+        };
+        let mut data = Vec::new();
+        for pair in &self.files {
+            let (old, new) = (pair.old.as_ref(), pair.new.as_ref());
+            let old_name = stripped(old.or(new).map_or("", |file| &file.name).as_bytes());
+            let new_name = stripped(new.or(old).map_or("", |file| &file.name).as_bytes());
+            data.extend_from_slice(b"diff--gita/");
+            data.extend_from_slice(&old_name);
+            data.extend_from_slice(b"b/");
+            data.extend_from_slice(&new_name);
+            match (old, new) {
+                (None, Some(new)) => data.extend_from_slice(format!("newfilemode{:06o}", new.mode).as_bytes()),
+                (Some(old), None) => data.extend_from_slice(format!("deletedfilemode{:06o}", old.mode).as_bytes()),
+                (Some(old), Some(new)) if old.mode != new.mode => {
+                    data.extend_from_slice(format!("oldmode{:06o}newmode{:06o}", old.mode, new.mode).as_bytes());
+                }
+                _ => {}
+            }
+
+            if old.is_some_and(File::is_binary) || new.is_some_and(File::is_binary) {
+                let id = |file: Option<&File>| file.and_then(|file| file.id).unwrap_or(format.null()).to_string();
+                data.extend_from_slice(id(old).as_bytes());
+                data.extend_from_slice(id(new).as_bytes());
+                continue;
+            }
+            match old {
+                Some(_) => data.extend_from_slice(&[&b"---a/"[..], &old_name].concat()),
+                None => data.extend_from_slice(b"---/dev/null"),
+            }
+            match new {
+                Some(_) => data.extend_from_slice(&[&b"+++b/"[..], &new_name].concat()),
+                None => data.extend_from_slice(b"+++/dev/null"),
+            }
+            let mut patch = Vec::new();
+            let old_content = old.map_or(&[][..], |file| &file.content[..]);
+            let new_content = new.map_or(&[][..], |file| &file.content[..]);
+            unified(old_content, new_content, &Context::default(), None, &mut patch);
+            for line in patch.split(|&b| b == b'\n') {
+                if !line.starts_with(b"@@") && !line.starts_with(b"\\ ") {
+                    data.extend(stripped(line));
+                }
+            }
+        }
+        format.hash(&data)
+    }
+
+    /// Keeps the files whose changes have what `pickaxe` looks for, or
+    /// with `all` every file if any has it. Returns whether any has.
+    pub(crate) fn pickaxe(&mut self, pickaxe: &Pickaxe, all: bool) -> bool {
+        let found: Vec<bool> =
+            self.files.iter().map(|pair| pickaxe.finds(pair.old.as_ref(), pair.new.as_ref())).collect();
+        let any = found.contains(&true);
+        if !all {
+            let mut found = found.into_iter();
+            self.files.retain(|_| found.next().unwrap_or(false));
+        }
+        any
+    }
+
+    /// Writes a diffstat `width` columns wide, like `git diff --stat`: a
+    /// line per file with its number of changed lines and a bar of `+`
+    /// and `-`, scaled to fit, then the totals. Nothing if no file differs.
+    pub fn write_stat(&self, width: usize, out: &mut Vec<u8>) {
+        if self.files.is_empty() {
+            return;
+        }
+        let decimal_width = |n: usize| n.to_string().len();
+        let counts: Vec<(usize, usize)> = self.files.iter().map(FilePair::counts).collect();
+        let names: Vec<String> = self.files.iter().map(FilePair::display_name).collect();
+
+        let mut max_change = 0;
+        let mut bin_width = 0;
+        let mut number_width = 0;
+        for (pair, &(added, deleted)) in self.files.iter().zip(&counts) {
+            if pair.is_binary() {
+                bin_width = bin_width.max(14 + decimal_width(added) + decimal_width(deleted));
+                // Counts line up with "Bin".
+                number_width = 3;
+            } else {
+                max_change = max_change.max(added + deleted);
+            }
+        }
+        let max_len = names.iter().map(|name| name.chars().count()).max().unwrap_or(0);
+        number_width = number_width.max(decimal_width(max_change));
+
+        // Leave at least a little room for the name and the graph.
+        let width = width.max(16 + 6 + number_width);
+        let mut graph_width = if max_change + 4 > bin_width { max_change } else { bin_width - 4 };
+        let mut name_width = max_len;
+        if name_width + number_width + 6 + graph_width > width {
+            if graph_width + number_width + 6 > width * 3 / 8 {
+                graph_width = (width * 3 / 8).saturating_sub(number_width + 6).max(6);
+            }
+            if name_width > width - number_width - 6 - graph_width {
+                name_width = width - number_width - 6 - graph_width;
+            } else {
+                graph_width = width - number_width - 6 - name_width;
+            }
+        }
+
+        let (mut total_added, mut total_deleted) = (0, 0);
+        for ((pair, name), &(added, deleted)) in self.files.iter().zip(&names).zip(&counts) {
+            // Names too long lose their start, up to a directory if possible.
+            let mut prefix = "";
+            let mut name: &str = name;
+            let mut len = name_width;
+            if name.chars().count() > name_width {
+                prefix = "...";
+                len = name_width.saturating_sub(3);
+                let skip = name.chars().count() - len;
+                name = &name[name.char_indices().nth(skip).map_or(name.len(), |(i, _)| i)..];
+                if let Some(slash) = name.find('/') {
+                    name = &name[slash..];
+                }
+            }
+            let padding = len.saturating_sub(name.chars().count());
+
+            out.extend_from_slice(format!(" {}{}{} | ", prefix, name, " ".repeat(padding)).as_bytes());
+            if pair.is_binary() {
+                out.extend_from_slice(format!("{:>width$}", "Bin", width = number_width).as_bytes());
+                if added != 0 || deleted != 0 {
+                    out.extend_from_slice(format!(" {} -> {} bytes", deleted, added).as_bytes());
+                }
+                out.push(b'\n');
+                continue;
+            }
+
+            let (mut plus, mut minus) = (added, deleted);
+            if graph_width <= max_change {
+                let scale = |n: usize| if n == 0 { 0 } else { 1 + n * (graph_width - 1) / max_change };
+                let mut total = scale(added + deleted);
+                if total < 2 && added > 0 && deleted > 0 {
+                    total = 2;
+                }
+                if added < deleted {
+                    plus = scale(added);
+                    minus = total - plus;
+                } else {
+                    minus = scale(deleted);
+                    plus = total - minus;
+                }
+            }
+            out.extend_from_slice(format!("{:>width$}", added + deleted, width = number_width).as_bytes());
+            if added + deleted > 0 {
+                out.push(b' ');
+            }
+            out.extend_from_slice(format!("{}{}\n", "+".repeat(plus), "-".repeat(minus)).as_bytes());
+            total_added += added;
+            total_deleted += deleted;
+        }
+
+        let plural = |n: usize| if n == 1 { "" } else { "s" };
+        let files = self.files.len();
+        out.extend_from_slice(format!(" {} file{} changed", files, plural(files)).as_bytes());
+        if total_added > 0 || total_deleted == 0 {
+            out.extend_from_slice(format!(", {} insertion{}(+)", total_added, plural(total_added)).as_bytes());
+        }
+        if total_deleted > 0 || total_added == 0 {
+            out.extend_from_slice(format!(", {} deletion{}(-)", total_deleted, plural(total_deleted)).as_bytes());
+        }
+        out.push(b'\n');
+    }
+
+    /// Writes the created, deleted and renamed files and the mode changes,
+    /// like `git diff --summary`.
+    pub fn write_summary(&self, out: &mut Vec<u8>) {
+        for pair in &self.files {
+            let name = quote::quote_path(pair.name(), true);
+            match (&pair.old, &pair.new) {
+                (None, Some(new)) => out.extend_from_slice(format!(" create mode {:06o} {}\n", new.mode, name).as_bytes()),
+                (Some(old), None) => out.extend_from_slice(format!(" delete mode {:06o} {}\n", old.mode, name).as_bytes()),
+                (Some(old), Some(new)) => {
+                    if let Some(similarity) = pair.similarity {
+                        out.extend_from_slice(format!(" rename {} ({}%)\n", pair.display_name(), similarity).as_bytes());
+                    }
+                    if old.mode != new.mode {
+                        out.extend_from_slice(format!(" mode change {:06o} => {:06o}", old.mode, new.mode).as_bytes());
+                        if pair.similarity.is_none() {
+                            out.extend_from_slice(format!(" {}", name).as_bytes());
+                        }
+                        out.push(b'\n');
+                    }
+                }
+                (None, None) => {}
+            }
+        }
+    }
+
+    /// Writes a patch for every file, with `context` lines around changes,
+    /// in `colors` if given.
+    pub fn write_patch(&self, context: usize, colors: Option<&Colors>, out: &mut Vec<u8>) {
+        let context = Context { lines: context, ..Context::default() };
+        for pair in &self.files {
+            write_patch(pair.old.as_ref(), pair.new.as_ref(), pair.similarity, &context, colors, out);
+        }
+    }
+}
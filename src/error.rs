@@ -0,0 +1,46 @@
+use std::process::ExitCode;
+
+/// Failures that map to a specific git exit code. Any other error is
+/// reported as `fatal:` and exits with 128, like git's `die()`.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// Invalid command line usage, exit code 129.
+    #[error("{0}")]
+    Usage(String),
+    /// A hook rejected the operation, exit code 1.
+    #[error("hook '{name}' failed: {status}")]
+    HookFailed { name: String, status: std::process::ExitStatus },
+    /// A check that answers through its exit code alone, such as
+    /// `check-ref-format`. Nothing is printed.
+    #[error("exit code {0}")]
+    Status(u8),
+}
+
+impl Error {
+    fn exit_code(&self) -> u8 {
+        match self {
+            Error::Usage(_) => 129,
+            Error::HookFailed { .. } => 1,
+            Error::Status(code) => *code,
+        }
+    }
+}
+
+/// Prints `err` to stderr and returns the exit code git would use for it.
+pub fn report(err: &anyhow::Error) -> ExitCode {
+    match err.downcast_ref::<Error>() {
+        Some(Error::Usage(message)) => {
+            eprintln!("usage: {}", message);
+            ExitCode::from(Error::Usage(String::new()).exit_code())
+        }
+        Some(error @ Error::Status(_)) => ExitCode::from(error.exit_code()),
+        Some(error) => {
+            eprintln!("error: {:#}", err);
+            ExitCode::from(error.exit_code())
+        }
+        None => {
+            eprintln!("fatal: {:#}", err);
+            ExitCode::from(128)
+        }
+    }
+}
@@ -0,0 +1,160 @@
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+use crate::date;
+use crate::diff::{self, TreeDiff};
+use crate::info;
+use crate::mailmap::split_ident;
+use crate::object_id::ObjectId;
+use crate::repository::{self, Repository};
+use crate::rev_list::RevisionSet;
+
+const STYLE: &str = "body { font-family: sans-serif; margin: 2em; }
+table { border-collapse: collapse; }
+td { padding: 0.2em 1em 0.2em 0; vertical-align: top; }
+pre { background: #f6f8fa; padding: 1em; overflow-x: auto; }
+.sha { font-family: monospace; }
+.add { color: #22863a; }
+.del { color: #b31d28; }
+.hunk { color: #6f42c1; }
+.meta { font-weight: bold; }
+";
+
+/// `text` with the characters HTML gives meaning to escaped.
+pub(crate) fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// A complete HTML page with the shared style sheet.
+pub(crate) fn page(title: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>\n{}</style>\n\
+         </head>\n<body>\n{}</body>\n</html>\n",
+        escape(title),
+        STYLE,
+        body
+    )
+}
+
+/// The name and date of an identity line, the date as `--date=iso`.
+fn identity(line: &str) -> (String, String) {
+    let Some((name, email, time)) = split_ident(line) else { return (line.to_string(), String::new()) };
+    let date = date::parse(time).map(|(time, offset)| date::format_iso(time, offset)).unwrap_or_default();
+    (format!("{} <{}>", name.unwrap_or_default(), email), date)
+}
+
+/// `patch` with each line wrapped in a span classed by what it is.
+fn highlight(patch: &str) -> String {
+    let mut html = String::new();
+    for line in patch.lines() {
+        let class = match line {
+            _ if line.starts_with("diff --git") => "meta",
+            _ if line.starts_with("+++ ") || line.starts_with("--- ") => "meta",
+            _ if line.starts_with("@@") => "hunk",
+            _ if line.starts_with('+') => "add",
+            _ if line.starts_with('-') => "del",
+            _ => "",
+        };
+        match class {
+            "" => html.push_str(&escape(line)),
+            class => html.push_str(&format!("<span class=\"{}\">{}</span>", class, escape(line))),
+        }
+        html.push('\n');
+    }
+    html
+}
+
+
+/// The page of one commit: its headers, message, and diff against its
+/// first parent. Parents that are in `exported`, or all of them without
+/// it, are linked.
+pub(crate) fn commit_page(
+    repository: &Repository,
+    sha: &ObjectId,
+    exported: Option<&HashSet<ObjectId>>,
+) -> anyhow::Result<String> {
+    let commit = repository.read_commit(sha)?;
+    let (author, author_date) = identity(&commit.author);
+    let (committer, commit_date) = identity(&commit.committer);
+
+    let mut body = String::new();
+    writeln!(body, "<p><a href=\"../index.html\">All commits</a></p>")?;
+    writeln!(body, "<h1>{}</h1>", escape(&commit.subject()))?;
+    writeln!(body, "<table>")?;
+    writeln!(body, "<tr><td>commit</td><td class=\"sha\">{}</td></tr>", sha)?;
+    for parent in &commit.parents {
+        match exported.is_none_or(|exported| exported.contains(parent)) {
+            true => {
+                writeln!(body, "<tr><td>parent</td><td class=\"sha\"><a href=\"{0}.html\">{0}</a></td></tr>", parent)?
+            }
+            false => writeln!(body, "<tr><td>parent</td><td class=\"sha\">{}</td></tr>", parent)?,
+        }
+    }
+    writeln!(body, "<tr><td>author</td><td>{}</td><td>{}</td></tr>", escape(&author), author_date)?;
+    writeln!(body, "<tr><td>committer</td><td>{}</td><td>{}</td></tr>", escape(&committer), commit_date)?;
+    writeln!(body, "</table>")?;
+    writeln!(body, "<pre>{}</pre>", escape(commit.message.trim_end()))?;
+
+    let changes = match commit.parents.first() {
+        Some(parent) => TreeDiff::new(repository, &repository.read_commit(parent)?.tree, &commit.tree, true)?,
+        None => TreeDiff::root(repository, &commit.tree)?,
+    };
+    if !changes.is_empty() {
+        let mut stat = Vec::new();
+        changes.write_stat(80, &mut stat);
+        changes.write_summary(&mut stat);
+        let mut patch = Vec::new();
+        changes.write_patch(diff::DEFAULT_CONTEXT, None, &mut patch);
+        writeln!(body, "<pre>{}</pre>", escape(&String::from_utf8_lossy(&stat)))?;
+        writeln!(body, "<pre>{}</pre>", highlight(&String::from_utf8_lossy(&patch)))?;
+    }
+
+    Ok(page(&format!("{} - {}", commit.subject(), sha), &body))
+}
+
+/// Writes the commits `range` selects as static HTML under `directory`:
+/// `index.html` lists them newest first and links to a page per commit,
+/// `commit/<id>.html`, showing its message and diff.
+pub fn export(range: &[String], directory: &Path) -> anyhow::Result<()> {
+    let repository = repository::current()?;
+    let commits = RevisionSet::parse(range)?.walk()?;
+    let exported: HashSet<ObjectId> = commits.iter().copied().collect();
+    fs::create_dir_all(directory.join("commit"))?;
+
+    let mut body = String::new();
+    writeln!(body, "<h1>{}</h1>", escape(&range.join(" ")))?;
+    writeln!(body, "<table>")?;
+    for sha in &commits {
+        let commit = repository.read_commit(sha)?;
+        let (author, date) = identity(&commit.author);
+        let short = &sha.to_string()[..7];
+        writeln!(
+            body,
+            "<tr><td class=\"sha\"><a href=\"commit/{}.html\">{}</a></td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            sha,
+            short,
+            escape(&commit.subject()),
+            escape(&author),
+            date
+        )?;
+        fs::write(directory.join("commit").join(format!("{}.html", sha)), commit_page(&repository, sha, Some(&exported))?)?;
+    }
+    writeln!(body, "</table>")?;
+    fs::write(directory.join("index.html"), page(&range.join(" "), &body))?;
+
+    info!("Exported {} commits to {}", commits.len(), directory.display());
+    Ok(())
+}
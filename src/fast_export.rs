@@ -0,0 +1,397 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::anyhow;
+
+use crate::object_id::ObjectId;
+use crate::objects::{tree_files, Object};
+use crate::quote::quote_path;
+use crate::refs;
+use crate::repository::{self, Repository};
+
+/// Files of a commit by path, as seen through the path filter.
+type Files = BTreeMap<String, (u32, ObjectId)>;
+
+/// What to do with tags that carry a signature, which can't survive the
+/// export unchanged once any object is rewritten by the importer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignedTags {
+    Abort,
+    Verbatim,
+    Warn,
+    Strip,
+    WarnStrip,
+}
+
+impl FromStr for SignedTags {
+    type Err = anyhow::Error;
+
+    fn from_str(mode: &str) -> anyhow::Result<SignedTags> {
+        match mode {
+            "abort" => Ok(SignedTags::Abort),
+            "verbatim" => Ok(SignedTags::Verbatim),
+            "warn" => Ok(SignedTags::Warn),
+            "strip" => Ok(SignedTags::Strip),
+            "warn-strip" => Ok(SignedTags::WarnStrip),
+            _ => Err(anyhow!("Unknown signed-tags mode: {}", mode)),
+        }
+    }
+}
+
+pub struct ExportOptions<'a> {
+    pub signed_tags: SignedTags,
+    /// Only changes to these paths (or below these directories) are
+    /// exported, and commits that change none of them are left out.
+    pub paths: Vec<String>,
+    pub import_marks: Option<&'a Path>,
+    pub export_marks: Option<&'a Path>,
+}
+
+/// Writes a fast-import stream to `out` recreating the history of `refs`,
+/// or of every ref with `all`. Objects listed in the imported marks are assumed to be on the other
+/// side already and are referred to by mark instead of being exported.
+pub fn run(refs: &[String], all: bool, options: &ExportOptions, out: &mut impl Write) -> anyhow::Result<()> {
+    let mut exporter = Exporter {
+        repository: repository::current()?,
+        options,
+        marks: HashMap::new(),
+        next_mark: 1,
+        rewritten: HashMap::new(),
+        files: HashMap::new(),
+    };
+    if let Some(path) = options.import_marks {
+        exporter.import_marks(path)?;
+    }
+
+    let tips = tips(refs, all)?;
+    let mut named = HashMap::new();
+    let mut order = Vec::new();
+    for (name, sha) in &tips {
+        let commit = exporter.peel(sha)?;
+        if let Some(commit) = commit {
+            exporter.walk(commit, name, &mut named, &mut order)?;
+        }
+    }
+
+    let mut last = HashMap::new();
+    for sha in order {
+        let name = &named[&sha];
+        if exporter.commit(&sha, name, out)? {
+            last.insert(name.clone(), sha);
+        }
+    }
+
+    for (name, sha) in &tips {
+        match exporter.repository.read(sha)? {
+            Object::Tag(_) if name.starts_with("refs/tags/") => exporter.tag(name, sha, out)?,
+            _ => {
+                let Some(commit) = exporter.peel(sha)? else { continue };
+                let Some(target) = exporter.rewritten.get(&commit).copied().flatten() else { continue };
+                if last.get(name) != Some(&target) {
+                    writeln!(out, "reset {}\nfrom {}\n", name, exporter.reference(&target))?;
+                }
+            }
+        }
+    }
+
+    if let Some(path) = options.export_marks {
+        let marks: BTreeMap<_, _> = exporter.marks.iter().map(|(sha, mark)| (mark, sha)).collect();
+        let content: String = marks.into_iter()
+            .map(|(mark, sha)| format!(":{} {}\n", mark, sha))
+            .collect();
+        fs::write(path, content)?;
+    }
+
+    Ok(())
+}
+
+/// Expands the requested refs to full names, in order and without
+/// duplicates.
+fn tips(names: &[String], all: bool) -> anyhow::Result<Vec<(String, ObjectId)>> {
+    let mut tips: Vec<(String, ObjectId)> = Vec::new();
+
+    if all {
+        for name in refs::list_refs("refs/")? {
+            if let Some(sha) = refs::read_ref(&name)? {
+                tips.push((name, sha));
+            }
+        }
+    }
+    for name in names {
+        let expanded = if name == "HEAD" {
+            let branch = refs::current_branch()?.map(|branch| format!("refs/heads/{}", branch));
+            vec![(branch.unwrap_or_else(|| "HEAD".to_string()), refs::resolve_revision("HEAD")?)]
+        } else {
+            let tip = refs::expand_ref(name)?.ok_or(anyhow!("Not a valid ref name: {}", name))?;
+            vec![tip]
+        };
+
+        for tip in expanded {
+            if !tips.iter().any(|(name, _)| *name == tip.0) {
+                tips.push(tip);
+            }
+        }
+    }
+
+    Ok(tips)
+}
+
+struct Exporter<'a> {
+    repository: Repository,
+    options: &'a ExportOptions<'a>,
+    marks: HashMap<ObjectId, u64>,
+    next_mark: u64,
+    /// The commit each walked commit became once filtered: itself if it
+    /// was exported, its nearest exported ancestor if left out, or `None`
+    /// if no ancestor is left.
+    rewritten: HashMap<ObjectId, Option<ObjectId>>,
+    files: HashMap<ObjectId, Files>,
+}
+
+impl Exporter<'_> {
+    /// Follows tags down to a commit. Refs to trees or blobs have no
+    /// history to export.
+    fn peel(&self, sha: &ObjectId) -> anyhow::Result<Option<ObjectId>> {
+        let mut sha = *sha;
+        loop {
+            match self.repository.read(&sha)? {
+                Object::Tag(tag) => sha = tag.object,
+                Object::Commit(_) => return Ok(Some(sha)),
+                _ => return Ok(None),
+            }
+        }
+    }
+
+    /// Appends the commits reachable from `tip` to `order`, parents first,
+    /// naming each after the first ref it was reached from. Commits with
+    /// an imported mark end the walk.
+    fn walk(
+        &mut self,
+        tip: ObjectId,
+        name: &str,
+        named: &mut HashMap<ObjectId, String>,
+        order: &mut Vec<ObjectId>,
+    ) -> anyhow::Result<()> {
+        let mut pending = vec![(tip, false)];
+
+        while let Some((sha, parents_done)) = pending.pop() {
+            if parents_done {
+                order.push(sha);
+                continue;
+            }
+            if named.contains_key(&sha) {
+                continue;
+            }
+            if self.marks.contains_key(&sha) {
+                self.rewritten.insert(sha, Some(sha));
+                continue;
+            }
+
+            named.insert(sha, name.to_string());
+            pending.push((sha, true));
+            let commit = self.repository.read_commit(&sha)?;
+            pending.extend(commit.parents.iter().rev().map(|parent| (*parent, false)));
+        }
+
+        Ok(())
+    }
+
+    /// The files of `commit` that pass the path filter.
+    fn files(&mut self, commit: &ObjectId) -> anyhow::Result<&Files> {
+        if !self.files.contains_key(commit) {
+            let tree = self.repository.read_commit(commit)?.tree;
+            let paths = &self.options.paths;
+            let files = tree_files(&tree)?
+                .into_iter()
+                .filter(|(file, _)| paths.is_empty() || paths.iter().any(|path| in_path(file, path)))
+                .collect();
+            self.files.insert(*commit, files);
+        }
+
+        Ok(&self.files[commit])
+    }
+
+    /// Writes `sha` as a commit on `name`, along with the blobs it adds.
+    /// Returns `false` if the path filter left it out.
+    fn commit(&mut self, sha: &ObjectId, name: &str, out: &mut impl Write) -> anyhow::Result<bool> {
+        let commit = self.repository.read_commit(sha)?;
+
+        let mut parents: Vec<ObjectId> = Vec::new();
+        for parent in &commit.parents {
+            if let Some(parent) = self.rewritten[parent] {
+                if !parents.contains(&parent) {
+                    parents.push(parent);
+                }
+            }
+        }
+
+        let before = match parents.first() {
+            Some(parent) => self.files(parent)?.clone(),
+            None => Files::new(),
+        };
+        let after = self.files(sha)?.clone();
+        if !self.options.paths.is_empty() && parents.len() < 2 && before == after {
+            self.rewritten.insert(*sha, parents.first().copied());
+            return Ok(false);
+        }
+
+        let paths: BTreeSet<&String> = before.keys().chain(after.keys()).collect();
+        let mut changes = String::new();
+        for path in paths {
+            match (before.get(path), after.get(path)) {
+                (_, None) => changes.push_str(&format!("D {}\n", quote(path))),
+                (old, Some(new)) if old != Some(new) => {
+                    let (mode, object) = *new;
+                    let dataref = if mode == 0o160000 { object.to_string() } else { self.blob(&object, out)? };
+                    changes.push_str(&format!("M {:o} {} {}\n", mode, dataref, quote(path)));
+                }
+                _ => {}
+            }
+        }
+
+        if parents.is_empty() {
+            writeln!(out, "reset {}", name)?;
+        }
+        let mark = self.mark(sha);
+        writeln!(out, "commit {}\nmark :{}", name, mark)?;
+        writeln!(out, "author {}\ncommitter {}", commit.author, commit.committer)?;
+        if let Some((_, encoding)) = commit.extra_headers.iter().find(|(header, _)| header == "encoding") {
+            writeln!(out, "encoding {}", encoding)?;
+        }
+        write!(out, "data {}\n{}", commit.message.len(), commit.message)?;
+        for (index, parent) in parents.iter().enumerate() {
+            let command = if index == 0 { "from" } else { "merge" };
+            writeln!(out, "{} {}", command, self.reference(parent))?;
+        }
+        writeln!(out, "{}", changes)?;
+
+        self.rewritten.insert(*sha, Some(*sha));
+        Ok(true)
+    }
+
+    /// Writes the blob unless it already has a mark, returning the mark.
+    fn blob(&mut self, sha: &ObjectId, out: &mut impl Write) -> anyhow::Result<String> {
+        if !self.marks.contains_key(sha) {
+            let data = match self.repository.read(sha)? {
+                Object::Blob(data) => data,
+                object => return Err(anyhow!("Object {} is a {}, not a blob", sha, object.kind())),
+            };
+            let mark = self.mark(sha);
+            write!(out, "blob\nmark :{}\ndata {}\n", mark, data.len())?;
+            out.write_all(&data)?;
+            writeln!(out)?;
+        }
+
+        Ok(self.reference(sha))
+    }
+
+    fn tag(&mut self, name: &str, sha: &ObjectId, out: &mut impl Write) -> anyhow::Result<()> {
+        let Object::Tag(tag) = self.repository.read(sha)? else {
+            return Err(anyhow!("Object {} is not a tag", sha));
+        };
+        let short_name = name.strip_prefix("refs/tags/").unwrap_or(name);
+
+        let target = match tag.kind.as_str() {
+            "commit" => match self.rewritten.get(&tag.object).copied().flatten() {
+                Some(commit) => self.reference(&commit),
+                // The path filter left out the tagged commit and its history.
+                None => return Ok(()),
+            },
+            "blob" => self.blob(&tag.object, out)?,
+            kind => return Err(anyhow!("Tag {} points to a {}, which can't be exported", short_name, kind)),
+        };
+
+        let mut message = Cow::Borrowed(tag.message.as_str());
+        if let Some(signature) = signature_start(&tag.message) {
+            match self.options.signed_tags {
+                SignedTags::Abort => {
+                    return Err(anyhow!(
+                        "Encountered signed tag {}; use --signed-tags=<mode> to handle it",
+                        short_name
+                    ))
+                }
+                SignedTags::Verbatim => {}
+                SignedTags::Warn => eprintln!("warning: exporting signed tag {}", short_name),
+                SignedTags::Strip => message = Cow::Owned(tag.message[..signature].to_string()),
+                SignedTags::WarnStrip => {
+                    eprintln!("warning: stripping signature from tag {}", short_name);
+                    message = Cow::Owned(tag.message[..signature].to_string());
+                }
+            }
+        }
+
+        writeln!(out, "tag {}\nfrom {}", short_name, target)?;
+        if let Some(tagger) = &tag.tagger {
+            writeln!(out, "tagger {}", tagger)?;
+        }
+        writeln!(out, "data {}\n{}", message.len(), message)?;
+
+        Ok(())
+    }
+
+    fn mark(&mut self, sha: &ObjectId) -> u64 {
+        let mark = self.next_mark;
+        self.next_mark += 1;
+        self.marks.insert(*sha, mark);
+        mark
+    }
+
+    /// Refers to an object by its mark if it has one.
+    fn reference(&self, sha: &ObjectId) -> String {
+        match self.marks.get(sha) {
+            Some(mark) => format!(":{}", mark),
+            None => sha.to_string(),
+        }
+    }
+
+    fn import_marks(&mut self, path: &Path) -> anyhow::Result<()> {
+        for line in fs::read_to_string(path)?.lines() {
+            let (mark, sha) = line.strip_prefix(':')
+                .and_then(|line| line.split_once(' '))
+                .ok_or(anyhow!("Corrupt mark line: {}", line))?;
+            let mark: u64 = mark.parse().map_err(|_| anyhow!("Corrupt mark line: {}", line))?;
+            self.marks.insert(sha.parse()?, mark);
+            self.next_mark = self.next_mark.max(mark + 1);
+        }
+
+        Ok(())
+    }
+}
+
+/// Whether `file` is `path` or inside the directory `path`.
+fn in_path(file: &str, path: &str) -> bool {
+    let path = path.trim_end_matches('/');
+    path.is_empty() || file == path || file.strip_prefix(path).is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// Quotes paths fast-import would otherwise misread, including ones with
+/// spaces, which would be ambiguous in `R` and `C` commands.
+fn quote(path: &str) -> Cow<'_, str> {
+    match quote_path(path, false) {
+        Cow::Borrowed(path) if path.contains(' ') => Cow::Owned(format!("\"{}\"", path)),
+        quoted => quoted,
+    }
+}
+
+/// The offset of the signature appended to a tag message, if any.
+pub(crate) fn signature_start(message: &str) -> Option<usize> {
+    const SIGNATURE_HEADERS: [&str; 3] = [
+        "-----BEGIN PGP SIGNATURE-----",
+        "-----BEGIN PGP MESSAGE-----",
+        "-----BEGIN SSH SIGNATURE-----",
+    ];
+
+    let mut offset = 0;
+    for line in message.split_inclusive('\n') {
+        if SIGNATURE_HEADERS.iter().any(|header| line.starts_with(header)) {
+            return Some(offset);
+        }
+        offset += line.len();
+    }
+
+    None
+}
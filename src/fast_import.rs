@@ -0,0 +1,471 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::BufRead;
+use std::path::Path;
+
+use anyhow::anyhow;
+
+use crate::object_id::ObjectId;
+use crate::objects::{peel_to_tree, tree_files, write_tree_files, Commit, Object, Tag};
+use crate::quote::unquote_path;
+use crate::refs;
+use crate::repository::{self, Repository};
+
+/// The files of a commit being assembled, by path.
+type Files = BTreeMap<String, (u32, ObjectId)>;
+
+/// Reads a fast-import stream from `input`, writing its objects and
+/// updating the refs it names once the whole stream has been read.
+pub fn run(input: impl BufRead, import_marks: Option<&Path>, export_marks: Option<&Path>) -> anyhow::Result<()> {
+    let mut importer = Importer {
+        input,
+        repository: repository::current()?,
+        peeked: None,
+        marks: HashMap::new(),
+        refs: BTreeMap::new(),
+    };
+    if let Some(path) = import_marks {
+        importer.import_marks(path)?;
+    }
+
+    // Objects are made durable together, before any ref points at them.
+    let repository = importer.repository.clone();
+    repository.batch(|| importer.run())?;
+
+    for (name, sha) in &importer.refs {
+        refs::write_ref(name, sha)?;
+    }
+    if let Some(path) = export_marks {
+        let marks: BTreeMap<_, _> = importer.marks.iter().collect();
+        let content: String = marks.into_iter()
+            .map(|(mark, sha)| format!(":{} {}\n", mark, sha))
+            .collect();
+        fs::write(path, content)?;
+    }
+
+    Ok(())
+}
+
+struct Importer<R> {
+    input: R,
+    repository: Repository,
+    /// A line read ahead that belongs to the next command.
+    peeked: Option<String>,
+    marks: HashMap<u64, ObjectId>,
+    /// Refs updated by the stream so far, written at the end.
+    refs: BTreeMap<String, ObjectId>,
+}
+
+impl<R: BufRead> Importer<R> {
+    fn run(&mut self) -> anyhow::Result<()> {
+        while let Some(line) = self.next_line()? {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if line == "blob" {
+                self.blob()?;
+            } else if let Some(name) = line.strip_prefix("commit ") {
+                self.commit(name)?;
+            } else if let Some(name) = line.strip_prefix("tag ") {
+                self.tag(name)?;
+            } else if let Some(name) = line.strip_prefix("reset ") {
+                self.reset(name)?;
+            } else if let Some(message) = line.strip_prefix("progress ") {
+                println!("progress {}", message);
+            } else if let Some(feature) = line.strip_prefix("feature ") {
+                check_feature(feature)?;
+            } else if line == "done" {
+                break;
+            } else if line != "checkpoint" && !line.starts_with("option ") {
+                return Err(anyhow!("Unsupported command: {}", line));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn next_line(&mut self) -> anyhow::Result<Option<String>> {
+        if let Some(line) = self.peeked.take() {
+            return Ok(Some(line));
+        }
+
+        let mut line = Vec::new();
+        if self.input.read_until(b'\n', &mut line)? == 0 {
+            return Ok(None);
+        }
+        if line.last() == Some(&b'\n') {
+            line.pop();
+        }
+
+        Ok(Some(String::from_utf8(line).map_err(|_| anyhow!("Command is not valid UTF-8"))?))
+    }
+
+    /// Returns the rest of the next line if it starts with `prefix`,
+    /// leaving the line for the next command otherwise.
+    fn optional(&mut self, prefix: &str) -> anyhow::Result<Option<String>> {
+        match self.next_line()? {
+            Some(line) => match line.strip_prefix(prefix) {
+                Some(rest) => Ok(Some(rest.to_string())),
+                None => {
+                    self.peeked = Some(line);
+                    Ok(None)
+                }
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn required(&mut self, prefix: &str) -> anyhow::Result<String> {
+        self.optional(prefix)?
+            .ok_or(anyhow!("Expected '{}' command", prefix.trim_end()))
+    }
+
+    /// Reads `data <count>` followed by that many bytes, or
+    /// `data <<<delimiter>` followed by lines up to the delimiter.
+    fn data(&mut self) -> anyhow::Result<Vec<u8>> {
+        let header = self.required("data ")?;
+
+        let data = match header.strip_prefix("<<") {
+            Some(delimiter) => {
+                let mut data = Vec::new();
+                loop {
+                    let line = self.next_line()?
+                        .ok_or(anyhow!("EOF in data (terminator '{}' not found)", delimiter))?;
+                    if line == delimiter {
+                        break data;
+                    }
+                    data.extend_from_slice(line.as_bytes());
+                    data.push(b'\n');
+                }
+            }
+            None => {
+                let count: usize = header.parse()
+                    .map_err(|_| anyhow!("Invalid data length: {}", header))?;
+                let mut data = vec![0; count];
+                self.input.read_exact(&mut data)?;
+                data
+            }
+        };
+
+        // The newline after the data is optional.
+        if let Some(line) = self.next_line()? {
+            if !line.is_empty() {
+                self.peeked = Some(line);
+            }
+        }
+
+        Ok(data)
+    }
+
+    fn mark(&mut self) -> anyhow::Result<Option<u64>> {
+        let mark = self.optional("mark :")?
+            .map(|mark| mark.parse().map_err(|_| anyhow!("Invalid mark: :{}", mark)))
+            .transpose()?;
+        // The ID in the source repository only matters to the exporter.
+        self.optional("original-oid ")?;
+
+        Ok(mark)
+    }
+
+    fn blob(&mut self) -> anyhow::Result<()> {
+        let mark = self.mark()?;
+        let data = self.data()?;
+        let sha = self.repository.write(&Object::Blob(data))?;
+        if let Some(mark) = mark {
+            self.marks.insert(mark, sha);
+        }
+
+        Ok(())
+    }
+
+    fn commit(&mut self, name: &str) -> anyhow::Result<()> {
+        let mark = self.mark()?;
+        let author = self.optional("author ")?;
+        let committer = self.required("committer ")?;
+        let encoding = self.optional("encoding ")?;
+        let message = String::from_utf8(self.data()?)
+            .map_err(|_| anyhow!("Commit message for {} is not valid UTF-8", name))?;
+
+        // Without `from`, a commit continues the branch it is made on.
+        let parent = match self.optional("from ")? {
+            Some(from) => Some(self.resolve(&from)?),
+            None => match self.refs.get(name) {
+                Some(tip) => Some(*tip),
+                None => refs::read_ref(name)?,
+            },
+        };
+        let mut parents: Vec<ObjectId> = parent.into_iter().collect();
+        while let Some(merge) = self.optional("merge ")? {
+            parents.push(self.resolve(&merge)?);
+        }
+
+        let mut files = match parent {
+            Some(parent) => tree_files(&peel_to_tree(&parent)?.1)?,
+            None => Files::new(),
+        };
+        while let Some(line) = self.next_line()? {
+            if line.is_empty() {
+                break;
+            }
+            if !self.file_change(&line, &mut files)? {
+                self.peeked = Some(line);
+                break;
+            }
+        }
+
+        let commit = Commit {
+            tree: write_tree_files(&files)?,
+            parents,
+            author: author.unwrap_or_else(|| committer.clone()),
+            committer,
+            extra_headers: encoding.map(|encoding| ("encoding".to_string(), encoding)).into_iter().collect(),
+            message,
+        };
+        let sha = self.repository.write(&Object::Commit(commit))?;
+        if let Some(mark) = mark {
+            self.marks.insert(mark, sha);
+        }
+        self.refs.insert(name.to_string(), sha);
+
+        Ok(())
+    }
+
+    /// Applies an `M`, `D`, `R`, `C` or `deleteall` line to `files`.
+    /// Returns `false` if `line` is not a file change.
+    fn file_change(&mut self, line: &str, files: &mut Files) -> anyhow::Result<bool> {
+        if line == "deleteall" {
+            files.clear();
+        } else if let Some(rest) = line.strip_prefix("M ") {
+            let (mode, rest) = rest.split_once(' ').ok_or(anyhow!("Missing dataref: {}", line))?;
+            let (dataref, path) = rest.split_once(' ').ok_or(anyhow!("Missing path: {}", line))?;
+            let (path, _) = parse_path(path, true)?;
+            let mode = parse_mode(mode)?;
+            let sha = match dataref {
+                "inline" => {
+                    let data = self.data()?;
+                    self.repository.write(&Object::Blob(data))?
+                }
+                dataref => self.resolve(dataref)?,
+            };
+
+            remove_path(files, &path);
+            if mode == 0o40000 {
+                for (file, entry) in tree_files(&sha)? {
+                    files.insert(join_path(&path, &file), entry);
+                }
+            } else {
+                files.insert(path, (mode, sha));
+            }
+        } else if let Some(path) = line.strip_prefix("D ") {
+            remove_path(files, &parse_path(path, true)?.0);
+        } else if let Some((rest, rename)) = line.strip_prefix("R ").map(|rest| (rest, true))
+            .or_else(|| line.strip_prefix("C ").map(|rest| (rest, false))) {
+            let (source, rest) = parse_path(rest, false)?;
+            let (destination, _) = parse_path(rest.trim_start(), true)?;
+
+            let moved: Vec<(String, (u32, ObjectId))> = files.iter()
+                .filter_map(|(file, entry)| {
+                    let relative = if *file == source { "" } else { file.strip_prefix(&format!("{}/", source))? };
+                    Some((join_path(&destination, relative), *entry))
+                })
+                .collect();
+            if moved.is_empty() {
+                return Err(anyhow!("Path {} not in branch", source));
+            }
+            if rename {
+                remove_path(files, &source);
+            }
+            remove_path(files, &destination);
+            files.extend(moved);
+        } else if line.starts_with("N ") {
+            return Err(anyhow!("Notes are not supported: {}", line));
+        } else {
+            return Ok(false);
+        }
+
+        Ok(true)
+    }
+
+    fn tag(&mut self, name: &str) -> anyhow::Result<()> {
+        let mark = self.mark()?;
+        let from = self.required("from ")?;
+        let object = self.resolve(&from)?;
+        self.optional("original-oid ")?;
+        let tagger = self.optional("tagger ")?;
+        let message = String::from_utf8(self.data()?)
+            .map_err(|_| anyhow!("Tag message for {} is not valid UTF-8", name))?;
+
+        let (kind, _) = self.repository.find_object(&object)?;
+        let tag = Tag { object, kind, tag: name.to_string(), tagger, extra_headers: Vec::new(), message };
+        let sha = self.repository.write(&Object::Tag(tag))?;
+        if let Some(mark) = mark {
+            self.marks.insert(mark, sha);
+        }
+        self.refs.insert(format!("refs/tags/{}", name), sha);
+
+        Ok(())
+    }
+
+    fn reset(&mut self, name: &str) -> anyhow::Result<()> {
+        match self.optional("from ")? {
+            Some(from) => {
+                let sha = self.resolve(&from)?;
+                self.refs.insert(name.to_string(), sha);
+            }
+            None => {
+                self.refs.remove(name);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Resolves a mark such as `:4`, a full object ID, or a ref.
+    fn resolve(&self, reference: &str) -> anyhow::Result<ObjectId> {
+        if let Some(mark) = reference.strip_prefix(':') {
+            let mark: u64 = mark.parse().map_err(|_| anyhow!("Invalid mark: {}", reference))?;
+            return self.marks.get(&mark)
+                .copied()
+                .ok_or(anyhow!("Mark :{} not declared", mark));
+        }
+        if let Some(sha) = self.refs.get(reference) {
+            return Ok(*sha);
+        }
+
+        refs::resolve_revision(reference)
+    }
+
+    fn import_marks(&mut self, path: &Path) -> anyhow::Result<()> {
+        for line in fs::read_to_string(path)?.lines() {
+            let (mark, sha) = line.strip_prefix(':')
+                .and_then(|line| line.split_once(' '))
+                .ok_or(anyhow!("Corrupt mark line: {}", line))?;
+            let mark = mark.parse().map_err(|_| anyhow!("Corrupt mark line: {}", line))?;
+            self.marks.insert(mark, sha.parse()?);
+        }
+
+        Ok(())
+    }
+}
+
+fn check_feature(feature: &str) -> anyhow::Result<()> {
+    match feature.split_once('=').map(|(name, _)| name).unwrap_or(feature) {
+        "date-format" | "done" | "force" | "import-marks" | "export-marks" => Ok(()),
+        _ => Err(anyhow!("This version of fast-import does not support feature {}", feature)),
+    }
+}
+
+/// Parses a possibly quoted path. Unquoted paths run to the end of the
+/// line if `last`, or to the next space otherwise.
+fn parse_path(text: &str, last: bool) -> anyhow::Result<(String, &str)> {
+    if text.starts_with('"') {
+        return unquote_path(text).ok_or(anyhow!("Invalid quoted path: {}", text));
+    }
+
+    let (path, rest) = match text.split_once(' ') {
+        Some((path, rest)) if !last => (path, rest),
+        _ => (text, ""),
+    };
+    Ok((path.trim_end_matches('/').to_string(), rest))
+}
+
+fn parse_mode(mode: &str) -> anyhow::Result<u32> {
+    match mode {
+        "644" | "100644" => Ok(0o100644),
+        "755" | "100755" => Ok(0o100755),
+        "120000" => Ok(0o120000),
+        "160000" => Ok(0o160000),
+        "40000" | "040000" => Ok(0o40000),
+        mode => Err(anyhow!("Corrupt mode: {}", mode)),
+    }
+}
+
+fn join_path(directory: &str, path: &str) -> String {
+    match (directory, path) {
+        ("", path) => path.to_string(),
+        (directory, "") => directory.to_string(),
+        (directory, path) => format!("{}/{}", directory, path),
+    }
+}
+
+/// Removes the file at `path` or everything below the directory `path`.
+fn remove_path(files: &mut Files, path: &str) {
+    if path.is_empty() {
+        files.clear();
+        return;
+    }
+    let prefix = format!("{}/", path);
+    files.retain(|file, _| file != path && !file.starts_with(&prefix));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_id::HashAlgorithm;
+
+    fn importer<'a>(repository: &Repository, input: &'a [u8]) -> Importer<&'a [u8]> {
+        Importer { input, repository: repository.clone(), peeked: None, marks: HashMap::new(), refs: BTreeMap::new() }
+    }
+
+    #[test]
+    fn data_and_marks() {
+        let dir = std::env::temp_dir().join(format!("git-starter-rust-fast-import-{}", std::process::id()));
+        let repository = Repository::init_module(&dir, HashAlgorithm::Sha1).unwrap();
+
+        let stream = b"blob\nmark :1\ndata 5\nhello\nblob\nmark :2\ndata <<EOF\nline\nEOF\n\nblob\ndata 9\nshort";
+        let mut blobs = importer(&repository, stream);
+        let first = blobs.next_line().unwrap().unwrap();
+        let blob = blobs.blob();
+        let second = blobs.next_line().unwrap().unwrap();
+        let delimited = blobs.blob();
+        blobs.next_line().unwrap();
+        let truncated = blobs.blob();
+        let unknown = importer(&repository, b"frobnicate\n").run();
+        let unterminated = importer(&repository, b"blob\ndata <<EOF\nline\n").run();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!((first.as_str(), second.as_str()), ("blob", "blob"));
+        blob.unwrap();
+        delimited.unwrap();
+        assert_eq!(blobs.marks[&1], HashAlgorithm::Sha1.hash(b"blob 5\0hello"));
+        assert_eq!(blobs.marks[&2], HashAlgorithm::Sha1.hash(b"blob 5\0line\n"));
+        assert!(truncated.is_err());
+        assert!(unknown.is_err());
+        assert!(unterminated.is_err());
+    }
+
+    #[test]
+    fn file_changes() {
+        let dir = std::env::temp_dir().join(format!("git-starter-rust-file-changes-{}", std::process::id()));
+        let repository = Repository::init_module(&dir, HashAlgorithm::Sha1).unwrap();
+        let mut changes = importer(&repository, b"");
+        fs::remove_dir_all(&dir).unwrap();
+
+        let blob = HashAlgorithm::Sha1.hash(b"blob");
+        changes.marks.insert(1, blob);
+        let mut files = Files::new();
+        for line in ["M 644 :1 a/b", "M 755 :1 \"a/c d\"", "C a e", "R e/b f", "D e", "M 120000 :1 link"] {
+            assert!(changes.file_change(line, &mut files).unwrap(), "{}", line);
+        }
+        let paths: Vec<(&str, u32)> = files.iter().map(|(path, (mode, _))| (path.as_str(), *mode)).collect();
+        assert_eq!(paths, [("a/b", 0o100644), ("a/c d", 0o100755), ("f", 0o100644), ("link", 0o120000)]);
+
+        assert!(!changes.file_change("from :1", &mut files).unwrap());
+        assert!(changes.file_change("M 644 :2 x", &mut files).is_err());
+        assert!(changes.file_change("M 600 :1 x", &mut files).is_err());
+        assert!(changes.file_change("R missing x", &mut files).is_err());
+        assert!(changes.file_change("deleteall", &mut files).unwrap());
+        assert!(files.is_empty());
+    }
+
+    #[test]
+    fn paths() {
+        assert_eq!(parse_path("a b", true).unwrap(), ("a b".to_string(), ""));
+        assert_eq!(parse_path("a/ b", false).unwrap(), ("a".to_string(), "b"));
+        assert_eq!(parse_path("\"a\\tb\" c", false).unwrap(), ("a\tb".to_string(), " c"));
+        assert_eq!(join_path("", "a"), "a");
+        assert_eq!(join_path("a", ""), "a");
+        assert_eq!(join_path("a", "b"), "a/b");
+    }
+}
@@ -0,0 +1,242 @@
+use std::collections::HashSet;
+use std::fmt::Display;
+
+use crate::object_id::HashAlgorithm;
+use crate::refs;
+use crate::verify_path::{is_hfs_dot, is_ntfs_dotgit};
+
+/// Something wrong with an object, with the ID git's fsck reports it by.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    pub id: &'static str,
+    pub message: String,
+}
+
+impl Problem {
+    fn new(id: &'static str, message: impl Into<String>) -> Problem {
+        Problem { id, message: message.into() }
+    }
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.id, self.message)
+    }
+}
+
+/// Checks that `data` is well formed as an object of type `kind`, the way
+/// git's fsck does with every warning treated as an error. Returns what
+/// is wrong, which for a valid object is nothing.
+pub fn check(kind: &str, data: &[u8], format: HashAlgorithm) -> Vec<Problem> {
+    match kind {
+        "tree" => check_tree(data, format),
+        "commit" => check_commit(data, format).err().into_iter().collect(),
+        "tag" => check_tag(data, format).err().into_iter().collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn check_tree(data: &[u8], format: HashAlgorithm) -> Vec<Problem> {
+    let mut found = HashSet::new();
+    let mut seen = HashSet::new();
+    let mut previous: Option<Vec<u8>> = None;
+    let mut rest = data;
+    while !rest.is_empty() {
+        let Some((mode, name, id)) = parse_tree_entry(rest, format) else {
+            return vec![Problem::new("badTree", "cannot be parsed as a tree")];
+        };
+        rest = &rest[mode.len() + name.len() + 2 + id.len()..];
+
+        if id.iter().all(|&b| b == 0) {
+            found.insert("nullSha1");
+        }
+        if name.contains(&b'/') {
+            found.insert("fullPathname");
+        }
+        found.extend(match name {
+            b"" => Some("emptyName"),
+            b"." => Some("hasDot"),
+            b".." => Some("hasDotdot"),
+            _ => is_dotgit(&String::from_utf8_lossy(name)).then_some("hasDotgit"),
+        });
+        let unpadded = &mode[mode.iter().take_while(|&&b| b == b'0').count()..];
+        if unpadded.len() < mode.len() {
+            found.insert("zeroPaddedFilemode");
+        }
+        let is_dir = unpadded == b"40000";
+        if !matches!(unpadded, b"100644" | b"100755" | b"40000" | b"120000" | b"160000") {
+            found.insert("badFilemode");
+        }
+
+        // Entries sort as if directories ended in `/`.
+        let mut key = name.to_vec();
+        if is_dir {
+            key.push(b'/');
+        }
+        if !seen.insert(name.to_vec()) {
+            found.insert("duplicateEntries");
+        } else if previous.as_ref().is_some_and(|previous| *previous > key) {
+            found.insert("treeNotSorted");
+        }
+        previous = Some(key);
+    }
+
+    [
+        ("nullSha1", "contains entries pointing to null sha1"),
+        ("fullPathname", "contains full pathnames"),
+        ("emptyName", "contains empty pathname"),
+        ("hasDot", "contains '.'"),
+        ("hasDotdot", "contains '..'"),
+        ("hasDotgit", "contains '.git'"),
+        ("zeroPaddedFilemode", "contains zero-padded file modes"),
+        ("badFilemode", "contains bad file modes"),
+        ("duplicateEntries", "contains duplicate file entries"),
+        ("treeNotSorted", "not properly sorted"),
+    ]
+    .into_iter()
+    .filter(|(id, _)| found.contains(id))
+    .map(|(id, message)| Problem::new(id, message))
+    .collect()
+}
+
+/// Whether any filesystem opens `name` as `.git`.
+fn is_dotgit(name: &str) -> bool {
+    name.eq_ignore_ascii_case(".git") || is_hfs_dot(name, "git") || is_ntfs_dotgit(name)
+}
+
+/// Splits the tree entry at the start of `data` into its mode, name and
+/// raw ID.
+fn parse_tree_entry(data: &[u8], format: HashAlgorithm) -> Option<(&[u8], &[u8], &[u8])> {
+    let space = data.iter().position(|&b| b == b' ')?;
+    let nul = data.iter().position(|&b| b == 0)?;
+    let mode = &data[..space];
+    if nul < space || mode.is_empty() || !mode.iter().all(|b| (b'0'..=b'7').contains(b)) {
+        return None;
+    }
+    let id = data.get(nul + 1..nul + 1 + format.byte_len())?;
+    Some((mode, &data[space + 1..nul], id))
+}
+
+/// The headers of a commit or tag, up to the blank line before the
+/// message, which must all end in a newline.
+fn headers(data: &[u8]) -> Result<&[u8], Problem> {
+    let end = data.windows(2).position(|pair| pair == b"\n\n").map(|end| end + 1);
+    let headers = match end {
+        Some(end) => &data[..end],
+        None if data.ends_with(b"\n") => data,
+        None => return Err(Problem::new("unterminatedHeader", "unterminated header")),
+    };
+    match headers.iter().position(|&b| b == 0) {
+        Some(offset) => Err(Problem::new("nulInHeader", format!("unterminated header: NUL at offset {}", offset))),
+        None => Ok(headers),
+    }
+}
+
+/// Takes the `name` header line from the start of `headers`, returning
+/// its value and what follows.
+fn header<'a>(headers: &'a [u8], name: &str) -> Option<(&'a [u8], &'a [u8])> {
+    let rest = headers.strip_prefix(name.as_bytes())?.strip_prefix(b" ")?;
+    let end = rest.iter().position(|&b| b == b'\n')?;
+    Some((&rest[..end], &rest[end + 1..]))
+}
+
+fn is_hex_id(value: &[u8], format: HashAlgorithm) -> bool {
+    value.len() == format.byte_len() * 2 && value.iter().all(u8::is_ascii_hexdigit)
+}
+
+fn check_commit(data: &[u8], format: HashAlgorithm) -> Result<(), Problem> {
+    let mut rest = headers(data)?;
+
+    let (tree, after) =
+        header(rest, "tree").ok_or(Problem::new("missingTree", "invalid format - expected 'tree' line"))?;
+    if !is_hex_id(tree, format) {
+        return Err(Problem::new("badTreeSha1", "invalid 'tree' line format - bad sha1"));
+    }
+    rest = after;
+    while let Some((parent, after)) = header(rest, "parent") {
+        if !is_hex_id(parent, format) {
+            return Err(Problem::new("badParentSha1", "invalid 'parent' line format - bad sha1"));
+        }
+        rest = after;
+    }
+
+    let (author, after) =
+        header(rest, "author").ok_or(Problem::new("missingAuthor", "invalid format - expected 'author' line"))?;
+    check_ident(author)?;
+    rest = after;
+    if header(rest, "author").is_some() {
+        return Err(Problem::new("multipleAuthors", "invalid format - multiple 'author' lines"));
+    }
+
+    let (committer, _) = header(rest, "committer")
+        .ok_or(Problem::new("missingCommitter", "invalid format - expected 'committer' line"))?;
+    check_ident(committer)
+}
+
+fn check_tag(data: &[u8], format: HashAlgorithm) -> Result<(), Problem> {
+    let rest = headers(data)?;
+
+    let (object, rest) =
+        header(rest, "object").ok_or(Problem::new("missingObject", "invalid format - expected 'object' line"))?;
+    if !is_hex_id(object, format) {
+        return Err(Problem::new("badObjectSha1", "invalid 'object' line format - bad sha1"));
+    }
+
+    let (kind, rest) =
+        header(rest, "type").ok_or(Problem::new("missingTypeEntry", "invalid format - expected 'type' line"))?;
+    if !matches!(kind, b"blob" | b"tree" | b"commit" | b"tag") {
+        return Err(Problem::new("badType", "invalid 'type' value"));
+    }
+
+    let (name, rest) =
+        header(rest, "tag").ok_or(Problem::new("missingTagEntry", "invalid format - expected 'tag' line"))?;
+    let name = String::from_utf8_lossy(name);
+    if !refs::is_valid_ref_name(&format!("refs/tags/{}", name), false, false) {
+        return Err(Problem::new("badTagName", format!("invalid 'tag' name: {}", name)));
+    }
+
+    let (tagger, _) =
+        header(rest, "tagger").ok_or(Problem::new("missingTaggerEntry", "invalid format - expected 'tagger' line"))?;
+    check_ident(tagger)
+}
+
+/// Checks an identity such as `Name <email> 1709990458 +0200`.
+fn check_ident(ident: &[u8]) -> Result<(), Problem> {
+    let bad = |id: &'static str, what: &str| Err(Problem::new(id, format!("invalid author/committer line - {}", what)));
+
+    if ident.first() == Some(&b'<') {
+        return bad("missingNameBeforeEmail", "missing space before email");
+    }
+    let open = ident.iter().position(|&b| b == b'<' || b == b'>');
+    match open.map(|i| ident[i]) {
+        Some(b'>') => return bad("badName", "bad name"),
+        None => return bad("missingEmail", "missing email"),
+        _ => {}
+    }
+    let open = open.unwrap_or_default();
+    if ident[open - 1] != b' ' {
+        return bad("missingSpaceBeforeEmail", "missing space before email");
+    }
+    let rest = &ident[open + 1..];
+    let Some(close) = rest.iter().position(|&b| b == b'<' || b == b'>').filter(|&i| rest[i] == b'>') else {
+        return bad("badEmail", "bad email");
+    };
+    let Some(rest) = rest[close + 1..].strip_prefix(b" ") else {
+        return bad("missingSpaceBeforeDate", "missing space before date");
+    };
+
+    let digits = rest.iter().take_while(|b| b.is_ascii_digit()).count();
+    if rest.first() == Some(&b'0') && rest.get(1) != Some(&b' ') {
+        return bad("zeroPaddedDate", "zero-padded date");
+    }
+    if digits > 0 && std::str::from_utf8(&rest[..digits]).ok().and_then(|date| date.parse::<i64>().ok()).is_none() {
+        return bad("badDateOverflow", "date causes integer overflow");
+    }
+    let Some(zone) = rest[digits..].strip_prefix(b" ").filter(|_| digits > 0) else {
+        return bad("badDate", "bad date");
+    };
+    match zone {
+        [b'+' | b'-', digits @ ..] if digits.len() == 4 && digits.iter().all(u8::is_ascii_digit) => Ok(()),
+        _ => bad("badTimezone", "bad time zone"),
+    }
+}
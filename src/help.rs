@@ -0,0 +1,563 @@
+use std::fmt::Write;
+
+use anyhow::anyhow;
+use clap::{Arg, ArgAction, Command};
+
+/// What `help <command>` shows beyond the one-line `about`: a longer
+/// description and a few examples, given without the program name.
+struct Page {
+    command: &'static str,
+    description: &'static str,
+    examples: &'static [(&'static str, &'static str)],
+}
+
+const PAGES: &[Page] = &[
+    Page {
+        command: "init",
+        description: "Creates an empty repository in DIRECTORY, or the current directory: the object store, \
+            the refs and a config file. Running it in an existing repository is safe and only picks up what \
+            is missing.",
+        examples: &[
+            ("init", "Start a repository in the current directory."),
+            ("init --bare -b trunk project.git", "Create a bare repository whose first branch is trunk."),
+            ("init --template=/etc/git-templates", "Start with the hooks and info/exclude of a shared template."),
+            ("init --ref-format=reftable", "Keep refs in reftables, which scale to many more refs than files."),
+        ],
+    },
+    Page {
+        command: "cat-file",
+        description: "Shows an object from the object store. Blobs are printed as stored, trees are listed \
+            like ls-tree.",
+        examples: &[("cat-file -p OBJECT", "Show the object with the ID OBJECT, whatever its type.")],
+    },
+    Page {
+        command: "hash-object",
+        description: "Computes the object ID a file would have as a blob, and with -w writes the blob to the \
+            object store. Attributes and filters apply as they would when adding the file.",
+        examples: &[("hash-object -w README.md", "Store README.md as a blob and print its ID.")],
+    },
+    Page {
+        command: "ls-files",
+        description: "Lists the files in the index, and with the options the modified, deleted, untracked or \
+            ignored files of the working tree compared to it.",
+        examples: &[
+            ("ls-files -m", "List the files changed in the working tree."),
+            ("ls-files --others --exclude-standard", "List the untracked files that aren't ignored."),
+            ("ls-files -u", "List the stages of each path a merge left in conflict."),
+        ],
+    },
+    Page {
+        command: "diff",
+        description: "Shows the changes between two files or directories on disk as a unified diff. A diff \
+            attribute picks a driver, whose diff.<driver>.xfuncname chooses the hunk headers and whose \
+            diff.<driver>.command, like diff.external, shows the changes instead. On a terminal the patch is \
+            colored, as color.diff or color.ui says and in the colors of color.diff.<slot>, unless NO_COLOR is \
+            set, and shown through the pager as for log.",
+        examples: &[
+            ("diff old.txt new.txt", "Compare two versions of a file."),
+            ("diff --color=always old.txt new.txt | less -R", "Keep the colors when piping the patch."),
+            ("diff -W old.rs new.rs", "Show every changed function whole."),
+            ("config pager.diff 'less -S'", "Page diffs without wrapping their lines."),
+            ("diff --no-ext-diff old.rs new.rs", "Show a patch even if an external diff program is set."),
+        ],
+    },
+    Page {
+        command: "rev-list",
+        description: "Lists the commits reachable from the given revisions, newest first, leaving out those \
+            reachable from a revision prefixed with ^. For A...B, --cherry-pick leaves out the commits whose \
+            patch is also on the other side and --cherry-mark marks them with =.",
+        examples: &[
+            ("rev-list main ^origin/main", "List the commits on main that origin/main doesn't have."),
+            ("rev-list --cherry origin/main...topic", "Mark the commits of topic that were applied upstream."),
+        ],
+    },
+    Page {
+        command: "range-diff",
+        description: "Compares two versions of a series of commits, such as a branch before and after a rebase. \
+            Each commit is paired with its counterpart in the other version by how similar their patches are; \
+            = marks pairs with the same patch, ! the changed ones, whose changes follow, and < and > the commits \
+            only in the old or the new version.",
+        examples: &[
+            ("range-diff main topic-v1 topic", "Show how topic changed since the copy topic-v1 was made."),
+            ("range-diff -s origin/topic...topic", "List how the commits of two versions of topic pair up."),
+        ],
+    },
+    Page {
+        command: "cherry",
+        description: "Lists the commits of a branch that are not in its upstream, oldest first, with - before \
+            those whose patch upstream already has and + before the rest.",
+        examples: &[("cherry -v origin/main topic", "Show which commits of topic still need to go upstream.")],
+    },
+    Page {
+        command: "log",
+        description: "Shows the commits rev-list would list, each with its author, date and message, or on one \
+            line with --oneline. With -p each commit's patch follows. -S <string> shows only the commits that \
+            change how often the string occurs in a file, a regular expression with --pickaxe-regex, and \
+            -G <regex> only those that add or remove a line matching it. --decorate names the refs that point \
+            at each commit, as log.decorate does on a terminal, and --color colors the commit headers, refs and \
+            patches as color.diff does. On a terminal the log is shown through the pager: GIT_PAGER, \
+            pager.log, core.pager, PAGER or less, in that order.",
+        examples: &[
+            ("log --left-right --cherry-pick --oneline main...topic", "Compare two branches, patches not equal."),
+            ("log -S parse_config --oneline", "Find the commits that added or removed calls to parse_config."),
+            ("log -G 'TODO|FIXME' -p", "Show the changes to lines with TODO or FIXME in them."),
+            ("--no-pager log --oneline -5", "Print the last five commits straight to the terminal."),
+        ],
+    },
+    Page {
+        command: "update-index",
+        description: "Changes the index entries of the given paths: adds or refreshes them, or sets flags such \
+            as assume-unchanged and skip-worktree.",
+        examples: &[("update-index --assume-unchanged config.local", "Stop checking config.local for changes.")],
+    },
+    Page {
+        command: "update-ref",
+        description: "Points a ref at an object, optionally only if it currently points at another, or deletes \
+            it. With --stdin, reads update, create, delete and verify commands and applies them together, so \
+            that either every ref changes or none does.",
+        examples: &[
+            ("update-ref refs/heads/topic NEW OLD", "Move topic to NEW if it is still at OLD."),
+            ("update-ref -d refs/heads/topic", "Delete the topic branch."),
+            ("update-ref --stdin <commands.txt", "Apply the commands in commands.txt in one transaction."),
+        ],
+    },
+    Page {
+        command: "ls-tree",
+        description: "Lists the entries of a tree object: their modes, types, object IDs and names.",
+        examples: &[("ls-tree --name-only TREE", "List the names in the tree with the ID TREE.")],
+    },
+    Page {
+        command: "write-tree",
+        description: "Writes the tree objects for the current index and prints the ID of the top-level tree.",
+        examples: &[("write-tree", "Snapshot the index as a tree.")],
+    },
+    Page {
+        command: "commit-tree",
+        description: "Creates a commit object for a tree, with the given parents and message, and prints its \
+            ID. No ref is updated.",
+        examples: &[
+            ("commit-tree -p HEAD -m 'Fix typo' TREE", "Commit TREE on top of HEAD."),
+            ("commit-tree -p main -p topic -m 'Merge topic' TREE", "Record TREE as a merge of two branches."),
+        ],
+    },
+    Page {
+        command: "check-mailmap",
+        description: "Prints the canonical name and email address of each contact, as .mailmap maps them.",
+        examples: &[("check-mailmap 'Jane <jane@old.example>'", "Look up Jane's current address.")],
+    },
+    Page {
+        command: "worktree",
+        description: "Manages the working trees attached to the repository: each has its own HEAD and index \
+            but shares the objects and refs.",
+        examples: &[
+            ("worktree add -b hotfix ../hotfix", "Check out a new branch hotfix in ../hotfix."),
+            ("worktree list", "Show every working tree and what it has checked out."),
+        ],
+    },
+    Page {
+        command: "bisect",
+        description: "Finds the commit that introduced a bug by binary search: mark a bad and a good revision, \
+            then test the commits it checks out until one is left.",
+        examples: &[
+            ("bisect start HEAD v1.0", "Bisect between v1.0, which was good, and HEAD."),
+            ("bisect run make test", "Let the exit code of make test judge each commit."),
+        ],
+    },
+    Page {
+        command: "branch",
+        description: "Lists the local branches, marking the current one, or with the options shows their \
+            upstreams and configures them. On a terminal the names are colored, as color.branch or color.ui says \
+            and in the colors of color.branch.<slot>.",
+        examples: &[
+            ("branch -vv", "Show each branch with its upstream and how far ahead or behind it is."),
+            ("branch -u origin/main", "Make origin/main the upstream of the current branch."),
+            ("branch --format '%(refname:short) %(objectname:short)'", "List branches in a custom format."),
+            ("branch --merged main", "List the branches already merged into main, which are safe to delete."),
+            ("branch --contains abc1234", "List the branches that include commit abc1234."),
+        ],
+    },
+    Page {
+        command: "show-branch",
+        description: "Shows branches side by side with the commits on them, marking which branches each \
+            commit is on, down to where they meet.",
+        examples: &[("show-branch main topic", "Compare main and topic.")],
+    },
+    Page {
+        command: "check-ref-format",
+        description: "Checks that a ref name follows git's rules, exiting with status 1 if it doesn't.",
+        examples: &[("check-ref-format --branch 'feature/x'", "Check a branch name.")],
+    },
+    Page {
+        command: "reflog",
+        description: "Manages the logs of where each ref has pointed.",
+        examples: &[("reflog expire --expire=30.days.ago --all", "Drop the entries older than a month.")],
+    },
+    Page {
+        command: "maintenance",
+        description: "Runs the tasks that keep the repository fast, such as packing loose objects and \
+            writing the commit-graph.",
+        examples: &[
+            ("maintenance run --task commit-graph", "Write the commit-graph now."),
+            ("maintenance run --auto", "Run maintenance only if gc.auto or gc.autoPackLimit is exceeded."),
+        ],
+    },
+    Page {
+        command: "commit-graph",
+        description: "Writes the commit-graph, which lets history be walked without parsing every commit. \
+            With --split, new commits go into a layer of their own, merged with the smaller layers below.",
+        examples: &[
+            ("commit-graph write --reachable", "Rewrite the whole commit-graph."),
+            ("commit-graph write --split --changed-paths", "Add a layer with Bloom filters for path-limited logs."),
+        ],
+    },
+    Page {
+        command: "rewrite-history",
+        description: "Rewrites every commit: drops or keeps paths, strips large blobs and \
+            edits messages, then moves the refs to the new commits.",
+        examples: &[("rewrite-history --strip-blobs-bigger-than 10M", "Remove large files from the whole history.")],
+    },
+    Page {
+        command: "fast-export",
+        description: "Writes the history of the given refs as a fast-import stream, for moving it to another \
+            repository or tool.",
+        examples: &[("fast-export --all > repo.stream", "Export every ref.")],
+    },
+    Page {
+        command: "fast-import",
+        description: "Reads a fast-import stream on stdin and writes the objects and refs it describes.",
+        examples: &[("fast-import < repo.stream", "Import a stream written by fast-export.")],
+    },
+    Page {
+        command: "request-pull",
+        description: "Summarizes the changes between START and END for asking a maintainer to pull them from \
+            URL: where they are, a shortlog and a diffstat. It warns if URL doesn't have END.",
+        examples: &[("request-pull v1.0 https://example.com/repo.git main", "Ask to pull main, based on v1.0.")],
+    },
+    Page {
+        command: "mailsplit",
+        description: "Splits an mbox into one numbered file per mail in a directory, for mailinfo to read.",
+        examples: &[("mailsplit -o patches series.mbox", "Split series.mbox into patches/0001 and on.")],
+    },
+    Page {
+        command: "mailinfo",
+        description: "Reads a mail on stdin, writes its message and patch to the given files and prints the \
+            author, email, date and subject.",
+        examples: &[("mailinfo msg patch < patches/0001", "Take apart the first mail of a series.")],
+    },
+    Page {
+        command: "credential-store",
+        description: "A credential helper that keeps credentials unencrypted in ~/.git-credentials, one URL \
+            per line.",
+        examples: &[("config --global credential.helper store", "Use it for every repository.")],
+    },
+    Page {
+        command: "credential-cache",
+        description: "A credential helper that keeps credentials in the memory of a daemon for a while, 15 \
+            minutes by default.",
+        examples: &[("config --global credential.helper 'cache --timeout 3600'", "Cache credentials for an hour.")],
+    },
+    Page {
+        command: "config",
+        description: "Reads and writes config options. Lookups see every file git reads; writes go to the \
+            repository's config unless --global, --system or --file choose another. Comments and formatting \
+            of rewritten files are kept.",
+        examples: &[
+            ("config --global user.name 'Jane Doe'", "Set your name for every repository."),
+            ("config --get-all remote.origin.fetch", "Show every fetch refspec of origin."),
+            ("config --global --edit", "Open the global config in the editor."),
+        ],
+    },
+    Page {
+        command: "completions",
+        description: "Prints a script that completes commands, options and ref names in the given shell.",
+        examples: &[("completions bash > ~/.local/share/bash-completion/completions/git", "Install for bash.")],
+    },
+    Page {
+        command: "browse",
+        description: "Serves a read-only web view of the repository on localhost: the branches and tags, their \
+            logs, each commit with its diff, and the trees and files of any commit. It runs until interrupted.",
+        examples: &[("browse --port 8080", "Browse the repository at http://127.0.0.1:8080/.")],
+    },
+    Page {
+        command: "bundle",
+        description: "Writes commits, the objects they need and refs to a single file that another repository \
+            can fetch from, for moving history without a network connection. Commits the receiver already \
+            has can be left out with ^<revision>; the bundle then lists them as prerequisites, which verify \
+            checks for.",
+        examples: &[
+            ("bundle create repo.bundle --all", "Bundle every ref and its complete history."),
+            ("bundle create new.bundle main ^origin/main", "Bundle only what main has beyond origin/main."),
+            ("bundle verify new.bundle", "Check that this repository can fetch from the bundle."),
+        ],
+    },
+    Page {
+        command: "export-html",
+        description: "Writes static HTML pages for the commits a range selects: an index listing them newest \
+            first, and a page per commit with its message, diffstat and patch against its first parent. The \
+            pages need no server, so they can be copied anywhere.",
+        examples: &[("export-html v1.0..main site", "Publish what changed since v1.0 to site/index.html.")],
+    },
+    Page {
+        command: "lfs",
+        description: "Keeps the content of tracked files in .git/lfs and commits small pointer files in their \
+            place. The lfs filter driver stores content when files are added and restores it on checkout, \
+            downloading it from the remote's LFS server through the batch API when it isn't stored locally. \
+            The server is lfs.url, remote.<name>.lfsurl, or info/lfs under the remote's URL. When it asks for \
+            a password, the credential.helper helpers are asked and told whether it worked, then the \
+            GIT_ASKPASS or core.askPass program or the terminal if they don't know it; a login in ~/.netrc for \
+            the server's host is tried first. Requests honor http.proxy, http.sslCAInfo and http.sslVerify, \
+            and the http_proxy, https_proxy and no_proxy environment variables.",
+        examples: &[
+            ("lfs install --local", "Set up the filter driver for this repository."),
+            ("lfs track '*.psd'", "Store Photoshop files with LFS."),
+            ("lfs push origin main", "Upload the objects main needs before pushing it."),
+        ],
+    },
+    Page {
+        command: "stats",
+        description: "Walks every object reachable from the refs and reports counts and sizes by type, both \
+            uncompressed and on disk, the largest blobs and deepest trees with their paths, how deep delta \
+            chains go, and storage by file extension. Objects only reachable from the reflogs aren't counted.",
+        examples: &[("stats --top 20", "Find the 20 largest files in history before cleaning up.")],
+    },
+    Page {
+        command: "verify-objects",
+        description: "Checks every loose object faster than a full fsck: each is decompressed and re-hashed \
+            in parallel, and any that is truncated, corrupt or stored under the wrong name is reported. \
+            Connectivity isn't checked. Useful after restoring a backup.",
+        examples: &[("verify-objects --threads 4", "Verify the object store on four threads.")],
+    },
+    Page {
+        command: "copy-objects",
+        description: "Copies every object of another repository on this machine that this one doesn't have, \
+            checking each and keeping it compressed as stored. No refs change. Packs the other \
+            repository has and this one lacks are copied whole, with their indexes.",
+        examples: &[("copy-objects --from ../upstream", "Seed this repository with the objects of ../upstream.")],
+    },
+    Page {
+        command: "pack-objects",
+        description: "Writes the objects listed on stdin, as rev-list --objects prints them, into a pack. \
+            Each is stored as a delta against one of the --window objects of the same type and a \
+            similar path before it, where that is smaller, in chains at most --depth long.",
+        examples: &[
+            ("pack-objects .git/objects/pack/pack < objects", "Pack the objects listed in the file objects."),
+            ("pack-objects --stdout --window=0 < objects > out.pack", "Write a pack without deltas to out.pack."),
+        ],
+    },
+    Page {
+        command: "repack",
+        description: "Packs the reachable loose objects into a new pack. With -a every reachable object goes \
+            into it but those in packs kept with a .keep file, and -d removes what that makes redundant. \
+            --geometric rolls up only the smallest packs, until each pack left has at least FACTOR times \
+            the objects of the next smaller one. With -b, the new pack gets reachability bitmaps that \
+            let git count the objects to send without walking history.",
+        examples: &[
+            ("repack -d", "Pack loose objects and remove them."),
+            ("repack --geometric=2 -d --write-midx", "Merge the small packs and index every pack in one file."),
+            ("repack -a -d -b", "Pack everything into one pack with bitmaps for serving clones."),
+            ("repack -a -d --window=250 --depth=50", "Rewrite everything into one tightly packed pack."),
+        ],
+    },
+    Page {
+        command: "checkout-index",
+        description: "Copies files from the index to the working tree, leaving existing files alone unless \
+            forced.",
+        examples: &[("checkout-index -a -f", "Restore every file to its staged content.")],
+    },
+    Page {
+        command: "var",
+        description: "Prints the author or committer identity, or the editor, that git would use.",
+        examples: &[("var GIT_AUTHOR_IDENT", "Show the identity new commits would get.")],
+    },
+    Page {
+        command: "interpret-trailers",
+        description: "Adds trailers such as Signed-off-by to commit messages, or parses out the ones they \
+            have, following the trailer.* config.",
+        examples: &[("interpret-trailers --trailer 'Acked-by: Jane' msg.txt", "Add an Acked-by trailer.")],
+    },
+    Page {
+        command: "name-rev",
+        description: "Finds a name for each commit relative to a ref, such as main~2, for reading object IDs \
+            more easily.",
+        examples: &[("name-rev --tags HEAD", "Name HEAD after the nearest tag.")],
+    },
+    Page {
+        command: "notes",
+        description: "Attaches notes to objects without changing them, kept on refs/notes/commits.",
+        examples: &[("notes add -m 'Tested on ARM' HEAD", "Annotate the current commit.")],
+    },
+    Page {
+        command: "replace",
+        description: "Makes every command read another object in place of an existing one, via refs/replace/. \
+            --no-replace-objects turns it off.",
+        examples: &[("replace OLD NEW", "Read NEW wherever OLD is referenced.")],
+    },
+    Page {
+        command: "remote",
+        description: "Manages the remote repositories whose branches are tracked.",
+        examples: &[
+            ("remote add origin https://example.com/repo.git", "Add a remote named origin."),
+            ("remote -v", "List the remotes with their URLs."),
+        ],
+    },
+    Page {
+        command: "submodule",
+        description: "Inspects, initializes and checks out the submodules listed in .gitmodules. Update \
+            clones each registered submodule into .git/modules/<name> from its URL, which must be a \
+            repository on this machine, and leaves its HEAD detached at the recorded commit.",
+        examples: &[
+            ("submodule status", "Show the commit recorded for each submodule."),
+            ("submodule init && submodule update", "Check out every submodule at its recorded commit."),
+        ],
+    },
+    Page {
+        command: "sparse-checkout",
+        description: "Reduces the working tree to the given directories, or to patterns with --no-cone, \
+            while the index keeps every file.",
+        examples: &[("sparse-checkout set docs src/core", "Check out only docs and src/core.")],
+    },
+];
+
+/// Appends `text` wrapped to `width` columns, each line indented by
+/// `indent` spaces.
+fn wrap(out: &mut String, text: &str, indent: usize, width: usize) {
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        if !line.is_empty() && indent + line.len() + 1 + word.len() > width {
+            let _ = writeln!(out, "{:indent$}{}", "", line, indent = indent);
+            line.clear();
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(word);
+    }
+    if !line.is_empty() {
+        let _ = writeln!(out, "{:indent$}{}", "", line, indent = indent);
+    }
+}
+
+/// How an argument is shown in the OPTIONS section: `-v, --verbose` or
+/// `--format <FORMAT>`.
+fn arg_heading(arg: &Arg) -> String {
+    let mut heading =
+        [arg.get_short().map(|short| format!("-{}", short)), arg.get_long().map(|long| format!("--{}", long))]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>()
+            .join(", ");
+    if matches!(arg.get_action(), ArgAction::Set | ArgAction::Append) {
+        let names = arg.get_value_names().map(|names| names.join("> <")).unwrap_or_else(|| arg.get_id().to_string());
+        heading.push_str(&format!(" <{}>", names));
+    }
+    heading
+}
+
+fn render_page(command: &mut Command, path: &str, out: &mut String) {
+    let about = command.get_about().map(|about| about.to_string()).unwrap_or_default();
+    let page = PAGES.iter().find(|page| page.command == path.split(' ').nth(1).unwrap_or_default());
+    let bin = path.split(' ').next().unwrap_or_default();
+
+    out.push_str("NAME\n");
+    wrap(out, &format!("{} - {}", path, about), 4, 80);
+    out.push_str("\nSYNOPSIS\n");
+    let usage = command.render_usage().to_string();
+    wrap(out, usage.trim().strip_prefix("Usage:").unwrap_or(&usage), 4, 80);
+
+    // Only the top-level page of a command has the longer description;
+    // the NAME line already gives the short one.
+    let description = match page {
+        Some(page) if path.split(' ').count() == 2 => page.description.to_string(),
+        _ => command.get_long_about().map(|about| about.to_string()).unwrap_or_default(),
+    };
+    if !description.is_empty() {
+        out.push_str("\nDESCRIPTION\n");
+        wrap(out, &description, 4, 80);
+    }
+
+    let arguments: Vec<&Arg> = command.get_arguments().filter(|arg| !arg.is_hide_set()).collect();
+    for (title, positional) in [("OPTIONS", false), ("ARGUMENTS", true)] {
+        let arguments: Vec<&&Arg> = arguments.iter().filter(|arg| arg.is_positional() == positional).collect();
+        if arguments.is_empty() {
+            continue;
+        }
+        let _ = write!(out, "\n{}\n", title);
+        for arg in arguments {
+            let heading = if positional {
+                let names =
+                    arg.get_value_names().map(|names| names.join(" ")).unwrap_or_else(|| arg.get_id().to_string());
+                if arg.is_required_set() {
+                    format!("<{}>", names)
+                } else {
+                    format!("[{}]", names)
+                }
+            } else {
+                arg_heading(arg)
+            };
+            let _ = writeln!(out, "    {}", heading);
+            let help = arg.get_long_help().or(arg.get_help()).map(|help| help.to_string()).unwrap_or_default();
+            let mut help = help;
+            let possible: Vec<String> =
+                arg.get_possible_values().iter().map(|value| value.get_name().to_string()).collect();
+            if !possible.is_empty() {
+                help.push_str(&format!(" One of: {}.", possible.join(", ")));
+            }
+            wrap(out, &help, 8, 80);
+        }
+    }
+
+    let subcommands: Vec<&Command> = command.get_subcommands().filter(|subcommand| !subcommand.is_hide_set()).collect();
+    if !subcommands.is_empty() {
+        out.push_str("\nCOMMANDS\n");
+        for subcommand in subcommands {
+            let _ = writeln!(out, "    {}", subcommand.get_name());
+            wrap(out, &subcommand.get_about().map(|about| about.to_string()).unwrap_or_default(), 8, 80);
+        }
+    }
+
+    if let Some(page) = page.filter(|_| path.split(' ').count() == 2) {
+        out.push_str("\nEXAMPLES\n");
+        for (example, explanation) in page.examples {
+            let _ = writeln!(out, "    {} {}", bin, example);
+            wrap(out, explanation, 8, 80);
+        }
+    }
+}
+
+/// The manual page for the command at `path` under `command`, installed
+/// as `bin`: synopsis, description, every option and argument, and
+/// examples.
+pub fn render(mut command: Command, bin: &str, path: &[&str]) -> anyhow::Result<String> {
+    command = command.bin_name(bin.to_string());
+    command.build();
+    let mut current = &mut command;
+    for name in path {
+        current = current
+            .find_subcommand_mut(name)
+            .filter(|subcommand| !subcommand.is_hide_set())
+            .ok_or(anyhow!("No manual entry for {}", path.join(" ")))?;
+    }
+
+    let mut out = String::new();
+    render_page(current, &std::iter::once(bin).chain(path.iter().copied()).collect::<Vec<_>>().join(" "), &mut out);
+    Ok(out)
+}
+
+fn render_tree(command: &mut Command, path: &str, out: &mut String) {
+    for subcommand in command.get_subcommands_mut().filter(|subcommand| !subcommand.is_hide_set()) {
+        let path = format!("{} {}", path, subcommand.get_name());
+        out.push_str(&format!("{}\n\n", "=".repeat(80)));
+        render_page(subcommand, &path, out);
+        out.push('\n');
+        render_tree(subcommand, &path, out);
+    }
+}
+
+/// The manual pages of every command and subcommand, for `--help-all`.
+pub fn render_all(mut command: Command, bin: &str) -> String {
+    command = command.bin_name(bin.to_string());
+    command.build();
+    let mut out = String::new();
+    render_tree(&mut command, bin, &mut out);
+    out
+}
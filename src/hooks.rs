@@ -0,0 +1,88 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+
+use anyhow::anyhow;
+
+use crate::error::Error;
+use crate::repository::{common_dir, repo_config};
+use crate::trace;
+use crate::trace::TRACE;
+
+/// Finds the hook `name` in `core.hooksPath` or `$GIT_COMMON_DIR/hooks`,
+/// ignoring files that aren't executable like git does.
+fn find_hook(name: &str, worktree: &Path) -> anyhow::Result<Option<PathBuf>> {
+    let hooks_dir = match repo_config()?.get("core.hookspath") {
+        // Relative hook paths are relative to where hooks run.
+        Some(path) => worktree.join(path),
+        None => common_dir()?.join("hooks"),
+    };
+
+    let hook = hooks_dir.join(name);
+    if !hook.is_file() || !is_executable(&hook)? {
+        return Ok(None);
+    }
+
+    // The hook runs from the worktree, so it needs an absolute path.
+    Ok(Some(std::fs::canonicalize(hook)?))
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> anyhow::Result<bool> {
+    use std::os::unix::fs::PermissionsExt;
+
+    Ok(std::fs::metadata(path)?.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(_path: &Path) -> anyhow::Result<bool> {
+    Ok(true)
+}
+
+/// Runs hook `name` from the top of `worktree` with `GIT_DIR` set to
+/// `git_dir`, returning `None` if the hook isn't installed.
+pub(crate) fn run(
+    name: &str,
+    args: &[&str],
+    stdin: Option<&[u8]>,
+    worktree: &Path,
+    git_dir: &Path,
+) -> anyhow::Result<Option<ExitStatus>> {
+    let Some(hook) = find_hook(name, worktree)? else {
+        return Ok(None);
+    };
+
+    trace!(TRACE, "run_command: {} {}", hook.display(), args.join(" "));
+    let mut child = Command::new(&hook)
+        .args(args)
+        .current_dir(worktree)
+        .env("GIT_DIR", git_dir)
+        .stdin(if stdin.is_some() { Stdio::piped() } else { Stdio::null() })
+        .spawn()
+        .map_err(|err| anyhow!("Cannot run hook '{}': {}", hook.display(), err))?;
+
+    if let (Some(input), Some(mut pipe)) = (stdin, child.stdin.take()) {
+        // Hooks are free to ignore their input and exit early.
+        match pipe.write_all(input) {
+            Err(err) if err.kind() != std::io::ErrorKind::BrokenPipe => return Err(err.into()),
+            _ => {}
+        }
+    }
+
+    Ok(Some(child.wait()?))
+}
+
+/// Like [`run`], but fails if the hook exits with a non-zero status, which
+/// aborts the operation that invoked it.
+pub(crate) fn run_checked(
+    name: &str,
+    args: &[&str],
+    stdin: Option<&[u8]>,
+    worktree: &Path,
+    git_dir: &Path,
+) -> anyhow::Result<()> {
+    match run(name, args, stdin, worktree, git_dir)? {
+        Some(status) if !status.success() => Err(Error::HookFailed { name: name.to_string(), status }.into()),
+        _ => Ok(()),
+    }
+}
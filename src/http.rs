@@ -0,0 +1,278 @@
+//! The HTTP client for servers such as the LFS API, set up by the
+//! `http.*` settings for proxies and certificates, and asking the
+//! credential helpers for a username and password when a server wants
+//! them.
+
+use std::fs;
+use std::path::Path;
+
+use anyhow::anyhow;
+use reqwest::{Certificate, Proxy, StatusCode};
+
+use crate::config::{self, Config};
+use crate::credential::{self, Credential};
+use crate::runtime::block_on;
+
+/// A client for the server at one URL that remembers the credential it
+/// authenticated with.
+pub(crate) struct Client {
+    client: reqwest::Client,
+    config: Config,
+    url: String,
+    credential: Option<Credential>,
+    /// Whether `credential` came from the helpers, so that they hear if it
+    /// worked.
+    filled: bool,
+    approved: bool,
+}
+
+impl Client {
+    /// A client for `url` as `config` sets it up: through `http.proxy`
+    /// if that is set, else the proxy `http_proxy`/`https_proxy` name for
+    /// hosts not in `no_proxy`; trusting only the certificates in
+    /// `http.sslCAInfo` or `GIT_SSL_CAINFO` if one is set; and not
+    /// checking certificates at all with `http.sslVerify` false or
+    /// `GIT_SSL_NO_VERIFY` set. Without a username in `url` the login in
+    /// `~/.netrc` for its host is used, as curl does.
+    pub(crate) fn new(config: &Config, url: &str) -> anyhow::Result<Client> {
+        let mut builder = reqwest::Client::builder();
+        match setting(config, url, "proxy") {
+            Some("") => builder = builder.no_proxy(),
+            Some(proxy) => {
+                let proxy = match proxy.contains("://") {
+                    true => proxy.to_string(),
+                    false => format!("http://{}", proxy),
+                };
+                reqwest::Url::parse(&proxy).map_err(|err| anyhow!("Invalid http.proxy '{}': {}", proxy, err))?;
+                let exceptions = std::env::var("no_proxy").or(std::env::var("NO_PROXY")).unwrap_or_default();
+                builder = builder.proxy(Proxy::custom(move |target| {
+                    let bypass = target.host_str().is_some_and(|host| bypasses_proxy(host, &exceptions));
+                    (!bypass).then(|| proxy.clone())
+                }));
+            }
+            // reqwest honors the proxy environment variables by default.
+            None => {}
+        }
+
+        let ca_info = std::env::var("GIT_SSL_CAINFO").ok().or(setting(config, url, "sslcainfo").map(str::to_string));
+        if let Some(path) = ca_info {
+            let pem = fs::read(&path).map_err(|err| anyhow!("Could not read CA bundle {}: {}", path, err))?;
+            builder = builder.tls_built_in_root_certs(false);
+            for certificate in certificates(&pem) {
+                let invalid = |err| anyhow!("Invalid certificate in {}: {}", path, err);
+                builder = builder.add_root_certificate(Certificate::from_pem(certificate).map_err(invalid)?);
+            }
+        }
+        let verify = std::env::var_os("GIT_SSL_NO_VERIFY").is_none()
+            && setting(config, url, "sslverify").and_then(config::parse_bool) != Some(false);
+        builder = builder.danger_accept_invalid_certs(!verify);
+
+        let credential = Credential::from_url(url)
+            .filter(|credential| credential.username.is_some())
+            .or_else(|| netrc(reqwest::Url::parse(url).ok()?.host_str()?));
+        Ok(Client {
+            client: builder.build()?,
+            config: config.clone(),
+            url: url.to_string(),
+            credential,
+            filled: false,
+            approved: false,
+        })
+    }
+
+    /// The underlying client, for requests that bring their own
+    /// authentication, such as the LFS actions.
+    pub(crate) fn inner(&self) -> &reqwest::Client {
+        &self.client
+    }
+
+    /// Sends the request that `build` makes with the credential for the
+    /// URL. When the server answers 401 it is asked for again and the
+    /// request retried, from the helpers or the user; if the server then
+    /// still refuses, the helpers are told to forget it.
+    pub(crate) fn send<F>(&mut self, build: F) -> anyhow::Result<reqwest::Response>
+    where
+        F: Fn(&reqwest::Client) -> reqwest::RequestBuilder,
+    {
+        loop {
+            let mut request = build(&self.client);
+            if let Some(Credential { username: Some(username), password, .. }) = &self.credential {
+                request = request.basic_auth(username, password.as_ref());
+            }
+            let response =
+                block_on(request.send()).map_err(|err| anyhow!("Request to {} failed: {}", self.url, err))?;
+
+            if response.status() != StatusCode::UNAUTHORIZED {
+                if self.filled && !self.approved && response.status().is_success() {
+                    credential::approve(&self.config, self.credential.as_ref().expect("Filled credentials are set"));
+                    self.approved = true;
+                }
+                return Ok(response);
+            }
+            if self.filled {
+                credential::reject(&self.config, self.credential.as_ref().expect("Filled credentials are set"));
+                return Err(anyhow!("Authentication failed for '{}'", self.url));
+            }
+
+            let mut query = credential::query(&self.config, &self.url).ok_or(anyhow!("Invalid URL '{}'", self.url))?;
+            if let Some(Credential { username: Some(username), .. }) = &self.credential {
+                query.username = Some(username.clone());
+            }
+            credential::fill(&self.config, &mut query)?;
+            self.credential = Some(query);
+            self.filled = true;
+        }
+    }
+}
+
+/// The value of `http.<key>` for `url`: from the `http.<url>.<key>` whose
+/// URL matches the most of it, else the plain setting, the last one set
+/// winning among equals.
+fn setting<'a>(config: &'a Config, url: &str, key: &str) -> Option<&'a str> {
+    let target = Credential::from_url(url)?;
+    let mut best = None;
+    for (name, value) in config.entries() {
+        let specificity = match name.strip_prefix("http.").and_then(|name| name.strip_suffix(key)) {
+            Some("") => 0,
+            Some(pattern) => match pattern.strip_suffix('.') {
+                Some(pattern) if target.is_matched_by(pattern) => pattern.len(),
+                _ => continue,
+            },
+            None => continue,
+        };
+        if best.is_none_or(|(most, _)| specificity >= most) {
+            best = Some((specificity, value.unwrap_or("true")));
+        }
+    }
+    best.map(|(_, value)| value)
+}
+
+/// The login `~/.netrc`, or `_netrc` on Windows, has for `host`.
+fn netrc(host: &str) -> Option<Credential> {
+    let home = std::env::var_os("HOME").or(std::env::var_os("USERPROFILE"))?;
+    let name = if cfg!(windows) { "_netrc" } else { ".netrc" };
+    let content = fs::read_to_string(Path::new(&home).join(name)).ok()?;
+    parse_netrc(&content, host).filter(|credential| credential.username.is_some())
+}
+
+/// The `login` and `password` of the `machine` entry for `host` in the
+/// netrc `content`, or of the `default` entry after them. `macdef` macros
+/// are skipped up to the blank line that ends them.
+fn parse_netrc(content: &str, host: &str) -> Option<Credential> {
+    let mut entry: Option<(bool, Credential)> = None;
+    let mut in_macro = false;
+    for line in content.lines() {
+        if in_macro {
+            in_macro = !line.trim().is_empty();
+            continue;
+        }
+        let mut tokens = line.split_whitespace();
+        while let Some(token) = tokens.next() {
+            match token {
+                "machine" | "default" => {
+                    if let Some((true, credential)) = entry.take() {
+                        return Some(credential);
+                    }
+                    let matches =
+                        token == "default" || tokens.next().is_some_and(|name| name.eq_ignore_ascii_case(host));
+                    entry = Some((matches, Credential::default()));
+                }
+                "login" | "password" | "account" => {
+                    let value = tokens.next().map(str::to_string);
+                    match (&mut entry, token) {
+                        (Some((_, credential)), "login") => credential.username = value,
+                        (Some((_, credential)), "password") => credential.password = value,
+                        _ => {}
+                    }
+                }
+                "macdef" => {
+                    in_macro = true;
+                    break;
+                }
+                _ => {}
+            }
+        }
+    }
+    entry.filter(|(matches, _)| *matches).map(|(_, credential)| credential)
+}
+
+/// Whether `no_proxy`, a comma-separated list of hosts and domains, or
+/// `*` for all of them, says to reach `host` directly.
+fn bypasses_proxy(host: &str, no_proxy: &str) -> bool {
+    let host = host.trim_start_matches('[').trim_end_matches(']').to_lowercase();
+    no_proxy.split(',').map(str::trim).filter(|entry| !entry.is_empty()).any(|entry| {
+        let domain = entry.trim_start_matches("*.").trim_start_matches('.').to_lowercase();
+        entry == "*" || host == domain || host.ends_with(&format!(".{}", domain))
+    })
+}
+
+/// The PEM blocks of the certificates in a bundle.
+fn certificates(pem: &[u8]) -> Vec<&[u8]> {
+    const END: &[u8] = b"-----END CERTIFICATE-----";
+    let mut certificates = Vec::new();
+    let mut rest = pem;
+    while let Some(end) = rest.windows(END.len()).position(|window| window == END) {
+        certificates.push(&rest[..end + END.len()]);
+        rest = &rest[end + END.len()..];
+    }
+    certificates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings_for_urls() {
+        let config = Config::parse(concat!(
+            "[http]\n\tproxy = plain:1\n\tsslVerify\n",
+            "[http \"https://example.com/team\"]\n\tproxy = team:1\n",
+            "[http \"https://example.com\"]\n\tproxy = site:1\n\tsslVerify = false\n",
+        ))
+        .unwrap();
+        assert_eq!(setting(&config, "https://example.com/team/repo.git", "proxy"), Some("team:1"));
+        assert_eq!(setting(&config, "https://example.com/other.git", "proxy"), Some("site:1"));
+        assert_eq!(setting(&config, "http://example.com/team", "proxy"), Some("plain:1"));
+        assert_eq!(setting(&config, "https://example.com/team", "sslverify"), Some("false"));
+        assert_eq!(setting(&config, "https://example.org", "sslverify"), Some("true"));
+        assert_eq!(setting(&config, "https://example.org", "sslcainfo"), None);
+    }
+
+    #[test]
+    fn netrc_entries() {
+        let content = concat!(
+            "machine other.com login nobody password nothing\n",
+            "macdef init\nmachine example.com login wrong\n\n",
+            "machine Example.com\n\tlogin me\n\tpassword secret\n",
+            "default login anonymous password guest\n",
+        );
+        let found = parse_netrc(content, "example.com").unwrap();
+        assert_eq!((found.username.as_deref(), found.password.as_deref()), (Some("me"), Some("secret")));
+        let found = parse_netrc(content, "elsewhere.com").unwrap();
+        assert_eq!(found.username.as_deref(), Some("anonymous"));
+        assert_eq!(parse_netrc("machine other.com login nobody\n", "example.com"), None);
+    }
+
+    #[test]
+    fn proxy_exceptions() {
+        let no_proxy = "localhost, .internal.example.com,10.0.0.1";
+        assert!(bypasses_proxy("localhost", no_proxy));
+        assert!(bypasses_proxy("git.internal.example.com", no_proxy));
+        assert!(bypasses_proxy("internal.example.com", no_proxy));
+        assert!(bypasses_proxy("10.0.0.1", no_proxy));
+        assert!(!bypasses_proxy("example.com", no_proxy));
+        assert!(!bypasses_proxy("notlocalhost", no_proxy));
+        assert!(bypasses_proxy("anything", "*"));
+        assert!(!bypasses_proxy("anything", ""));
+    }
+
+    #[test]
+    fn certificates_of_bundles() {
+        let one = "-----BEGIN CERTIFICATE-----\nAAAA\n-----END CERTIFICATE-----";
+        let bundle = format!("# first\n{}\n{}\n", one, one.replace("AAAA", "BBBB"));
+        let found = certificates(bundle.as_bytes());
+        assert_eq!(found.len(), 2);
+        assert!(found[0].ends_with(b"AAAA\n-----END CERTIFICATE-----"));
+        assert!(found[1].starts_with(b"\n-----BEGIN"));
+    }
+}
@@ -0,0 +1,118 @@
+use std::fmt::{self, Display, Formatter};
+use crate::config::Config;
+use crate::date;
+
+/// Whose identity is asked for; each can be overridden separately.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Author,
+    Committer,
+}
+
+impl Role {
+    fn env_prefix(self) -> &'static str {
+        match self {
+            Role::Author => "GIT_AUTHOR",
+            Role::Committer => "GIT_COMMITTER",
+        }
+    }
+
+    fn config_section(self) -> &'static str {
+        match self {
+            Role::Author => "author",
+            Role::Committer => "committer",
+        }
+    }
+}
+
+/// A name, email and time as recorded in commits and tags.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Identity {
+    pub name: String,
+    pub email: String,
+    /// Seconds since the epoch.
+    pub time: i64,
+    /// The time zone as an offset from UTC in minutes.
+    pub offset: i32,
+}
+
+/// Formats the identity like git stores it: `Name <email> 1709990458 +0200`.
+impl Display for Identity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let sign = if self.offset < 0 { '-' } else { '+' };
+        let offset = self.offset.abs();
+        write!(f, "{} <{}> {} {}{:02}{:02}", self.name, self.email, self.time, sign, offset / 60, offset % 60)
+    }
+}
+
+/// Resolves the identity for `role` like git: `GIT_AUTHOR_NAME` and
+/// friends, then `author.name`, then `user.name`, falling back to the
+/// login name and host. The date comes from `GIT_AUTHOR_DATE`, or now.
+pub fn resolve(role: Role, config: &Config) -> anyhow::Result<Identity> {
+    let lookup = |field: &str| {
+        std::env::var(format!("{}_{}", role.env_prefix(), field.to_uppercase()))
+            .ok()
+            .or_else(|| config.get(&format!("{}.{}", role.config_section(), field)).map(str::to_string))
+            .or_else(|| config.get(&format!("user.{}", field)).map(str::to_string))
+    };
+
+    let user = std::env::var("USER")
+        .or_else(|_| std::env::var("LOGNAME"))
+        .unwrap_or_else(|_| "unknown".to_string());
+    let name = lookup("name").unwrap_or_else(|| user.clone());
+    let email = lookup("email")
+        .or_else(|| std::env::var("EMAIL").ok())
+        .unwrap_or_else(|| format!("{}@{}", user, host_name()));
+
+    let (time, offset) = match std::env::var(format!("{}_DATE", role.env_prefix())) {
+        Ok(date) => date::parse(&date)?,
+        Err(_) => date::now(),
+    };
+
+    Ok(Identity { name: sanitize(&name), email: sanitize(&email), time, offset })
+}
+
+/// Strips what would break the identity line: surrounding whitespace and
+/// the `<`, `>` and newlines git drops too.
+fn sanitize(value: &str) -> String {
+    value.chars()
+        .filter(|c| !matches!(c, '<' | '>' | '\n'))
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
+#[cfg(unix)]
+fn host_name() -> String {
+    let mut buf = [0u8; 256];
+    // SAFETY: the buffer is valid for its whole length.
+    if unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) } != 0 {
+        return "(none)".to_string();
+    }
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
+
+#[cfg(not(unix))]
+fn host_name() -> String {
+    std::env::var("COMPUTERNAME").unwrap_or_else(|_| "(none)".to_string())
+}
+
+/// The editor for commit messages and the like: `GIT_EDITOR`,
+/// `core.editor`, `VISUAL`, `EDITOR`, then `vi`.
+pub fn editor(config: &Config) -> String {
+    std::env::var("GIT_EDITOR")
+        .ok()
+        .or_else(|| config.get("core.editor").map(str::to_string))
+        .or_else(|| std::env::var("VISUAL").ok())
+        .or_else(|| std::env::var("EDITOR").ok())
+        .unwrap_or_else(|| "vi".to_string())
+}
+
+/// The timestamp in an identity line such as `Name <email> 1709990458 +0200`.
+pub fn identity_time(identity: &str) -> i64 {
+    identity.rsplit(' ')
+        .nth(1)
+        .and_then(|time| time.parse().ok())
+        .unwrap_or(0)
+}
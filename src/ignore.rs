@@ -0,0 +1,133 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::{self, Config};
+use crate::wildmatch::wildmatch;
+
+#[derive(Debug, Clone)]
+struct Rule {
+    // Directory of the file the rule came from, relative to the worktree
+    // root and ending in `/` unless it is the root itself.
+    base: String,
+    pattern: String,
+    // `!pattern` re-includes what an earlier rule excluded.
+    negated: bool,
+    // `pattern/` only matches directories.
+    dir_only: bool,
+}
+
+impl Rule {
+    fn matches(&self, path: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        let Some(relative) = path.strip_prefix(self.base.as_str()) else {
+            return false;
+        };
+
+        match self.pattern.strip_prefix('/') {
+            Some(anchored) => wildmatch(anchored, relative, true),
+            None if self.pattern.contains('/') => wildmatch(&self.pattern, relative, true),
+            None => {
+                let name = relative.rsplit('/').next().unwrap_or(relative);
+                wildmatch(&self.pattern, name, true)
+            }
+        }
+    }
+}
+
+/// The ignore rules in effect for a set of paths, see gitignore(5).
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Ignore {
+    // Ordered from lowest to highest precedence: `core.excludesFile`,
+    // `info/exclude`, then `.gitignore` files from the root inwards.
+    rules: Vec<Rule>,
+    /// `core.ignoreCase`: rules and paths are compared case-insensitively.
+    ignore_case: bool,
+}
+
+impl Ignore {
+    /// Starts with the rules of `core.excludesFile` and `info/exclude`.
+    pub(crate) fn load(git_dir: &Path, config: &Config) -> Ignore {
+        let ignore_case = config.get("core.ignorecase").and_then(config::parse_bool).unwrap_or(false);
+        let excludes_file = match config.get("core.excludesfile") {
+            Some(path) => Some(expand_home(path)),
+            None => match std::env::var_os("XDG_CONFIG_HOME") {
+                Some(xdg) => Some(PathBuf::from(xdg).join("git/ignore")),
+                None => std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config/git/ignore")),
+            },
+        };
+
+        let mut ignore = Ignore { ignore_case, ..Ignore::default() };
+        for path in excludes_file.into_iter().chain([git_dir.join("info/exclude")]) {
+            if let Ok(content) = fs::read_to_string(path) {
+                ignore.push_file("", &content);
+            }
+        }
+
+        ignore
+    }
+
+    /// Adds the rules of a `.gitignore` file found in directory `base`,
+    /// which take precedence over those added before.
+    pub(crate) fn push_file(&mut self, base: &str, content: &str) {
+        let base = if base.is_empty() || base.ends_with('/') {
+            base.to_string()
+        } else {
+            format!("{}/", base)
+        };
+        match self.ignore_case {
+            true => self.rules.extend(parse(&base.to_lowercase(), &content.to_lowercase())),
+            false => self.rules.extend(parse(&base, content)),
+        }
+    }
+
+    /// Whether `path` is ignored, the last matching rule deciding. Paths
+    /// inside an ignored directory are the caller's to skip.
+    pub(crate) fn is_ignored(&self, path: &str, is_dir: bool) -> bool {
+        let path = if self.ignore_case { path.to_lowercase() } else { path.to_string() };
+        self.rules.iter()
+            .rev()
+            .find(|rule| rule.matches(&path, is_dir))
+            .is_some_and(|rule| !rule.negated)
+    }
+}
+
+fn parse(base: &str, content: &str) -> Vec<Rule> {
+    let mut rules = Vec::new();
+
+    for line in content.lines() {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        // Trailing spaces are dropped unless escaped with a backslash.
+        let trimmed = line.trim_end_matches(' ');
+        let line = match trimmed.strip_suffix('\\') {
+            Some(escaped) if trimmed.len() < line.len() => format!("{} ", escaped),
+            _ => trimmed.to_string(),
+        };
+
+        let (negated, pattern) = match line.strip_prefix('!') {
+            Some(pattern) => (true, pattern),
+            None => (false, line.strip_prefix('\\').unwrap_or(&line)),
+        };
+        let (pattern, dir_only) = match pattern.strip_suffix('/') {
+            Some(pattern) => (pattern, true),
+            None => (pattern, false),
+        };
+        if pattern.is_empty() {
+            continue;
+        }
+
+        rules.push(Rule { base: base.to_string(), pattern: pattern.to_string(), negated, dir_only });
+    }
+
+    rules
+}
+
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), std::env::var_os("HOME")) {
+        (Some(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => PathBuf::from(path),
+    }
+}
@@ -0,0 +1,567 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::anyhow;
+use bytes::{Buf, BufMut, BytesMut};
+
+use crate::lockfile;
+use crate::object_id::{HashAlgorithm, ObjectId};
+
+const INDEX_SIGNATURE: &[u8; 4] = b"DIRC";
+const TREE_SIGNATURE: &[u8; 4] = b"TREE";
+const MAX_NAME_LENGTH: usize = 0xfff;
+const FLAG_EXTENDED: u16 = 0x4000;
+/// Flag for entries marked assume-unchanged, whose files are taken to
+/// match the index without looking.
+pub(crate) const ASSUME_VALID: u16 = 0x8000;
+/// Extended flag for entries left out of a sparse checkout.
+pub(crate) const SKIP_WORKTREE: u16 = 0x4000;
+// The assume-valid bit and the merge stage, kept as they are.
+const FLAG_PRESERVED: u16 = 0xb000;
+// The fixed fields before the object ID, and the flags after it.
+const ENTRY_STAT_LENGTH: usize = 40;
+const ENTRY_FLAGS_LENGTH: usize = 2;
+
+/// A single staged file, see `Documentation/gitformat-index.txt`.
+#[derive(Debug, Clone)]
+pub(crate) struct IndexEntry {
+    pub ctime: (u32, u32),
+    pub mtime: (u32, u32),
+    pub dev: u32,
+    pub ino: u32,
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u32,
+    pub sha: ObjectId,
+    /// The assume-valid and stage bits of the flags field.
+    pub flags: u16,
+    /// Version 3 flags such as skip-worktree and intent-to-add.
+    pub extended_flags: u16,
+    pub path: String,
+}
+
+impl IndexEntry {
+    /// Builds an entry without stat data, for a file that isn't on disk.
+    pub fn new(path: String, mode: u32, sha: ObjectId) -> IndexEntry {
+        IndexEntry {
+            ctime: (0, 0),
+            mtime: (0, 0),
+            dev: 0,
+            ino: 0,
+            mode,
+            uid: 0,
+            gid: 0,
+            size: 0,
+            sha,
+            flags: 0,
+            extended_flags: 0,
+            path,
+        }
+    }
+
+    /// Whether the stat data of two entries for the same file agree, so
+    /// that the file can be taken to be unchanged.
+    pub fn same_stat(&self, other: &IndexEntry) -> bool {
+        (self.ctime, self.mtime, self.dev, self.ino, self.uid, self.gid, self.size)
+            == (other.ctime, other.mtime, other.dev, other.ino, other.uid, other.gid, other.size)
+    }
+
+    /// The merge stage: 0 for a resolved path, or for a conflict 1 for the
+    /// common ancestor's version, 2 for ours and 3 for theirs.
+    pub fn stage(&self) -> u16 {
+        (self.flags >> 12) & 0b11
+    }
+
+    /// Builds an entry for a file that was just written to `file`, taking
+    /// the stat data from the filesystem so git sees it as up to date.
+    pub fn from_file(file: &Path, path: String, mode: u32, sha: ObjectId) -> anyhow::Result<IndexEntry> {
+        let metadata = fs::symlink_metadata(file)?;
+
+        #[cfg(unix)]
+        let entry = {
+            use std::os::unix::fs::MetadataExt;
+
+            // The index stores truncated 32-bit stat fields.
+            IndexEntry {
+                ctime: (metadata.ctime() as u32, metadata.ctime_nsec() as u32),
+                mtime: (metadata.mtime() as u32, metadata.mtime_nsec() as u32),
+                dev: metadata.dev() as u32,
+                ino: metadata.ino() as u32,
+                mode,
+                uid: metadata.uid(),
+                gid: metadata.gid(),
+                size: metadata.size() as u32,
+                sha,
+                flags: 0,
+                extended_flags: 0,
+                path,
+            }
+        };
+
+        #[cfg(not(unix))]
+        let entry = {
+            let mtime = metadata.modified()?
+                .duration_since(std::time::UNIX_EPOCH)?;
+            let mtime = (mtime.as_secs() as u32, mtime.subsec_nanos());
+
+            IndexEntry {
+                ctime: mtime,
+                mtime,
+                dev: 0,
+                ino: 0,
+                mode,
+                uid: 0,
+                gid: 0,
+                size: metadata.len() as u32,
+                sha,
+                flags: 0,
+                extended_flags: 0,
+                path,
+            }
+        };
+
+        Ok(entry)
+    }
+}
+
+/// The `TREE` extension: the tree object IDs of directories whose
+/// entries haven't changed since the last write-tree.
+#[derive(Debug, Clone)]
+pub(crate) struct CacheTree {
+    /// The directory's name within its parent, empty for the root.
+    pub name: String,
+    /// Number of index entries below this directory, or `None` if it
+    /// has been invalidated.
+    pub entry_count: Option<usize>,
+    pub sha: Option<ObjectId>,
+    pub subtrees: Vec<CacheTree>,
+}
+
+impl CacheTree {
+    /// Reads the directory at `depth` below the root and its subtrees.
+    fn read(buf: &mut &[u8], format: HashAlgorithm, depth: usize) -> anyhow::Result<CacheTree> {
+        // Each level is a path component, so a deeper tree can't match
+        // any path the index holds.
+        if depth > MAX_NAME_LENGTH {
+            return Err(anyhow!("Invalid TREE extension"));
+        }
+        let name_end = buf.iter()
+            .position(|&b| b == 0)
+            .ok_or(anyhow!("Invalid TREE extension"))?;
+        let name = String::from_utf8(buf[..name_end].to_vec())?;
+        buf.advance(name_end + 1);
+
+        let line_end = buf.iter()
+            .position(|&b| b == b'\n')
+            .ok_or(anyhow!("Invalid TREE extension"))?;
+        let (entry_count, subtree_count) = std::str::from_utf8(&buf[..line_end])?
+            .split_once(' ')
+            .ok_or(anyhow!("Invalid TREE extension"))?;
+        let entry_count: i64 = entry_count.parse()?;
+        let subtree_count: usize = subtree_count.parse()?;
+        buf.advance(line_end + 1);
+
+        // Invalidated directories have no object ID.
+        let (entry_count, sha) = if entry_count < 0 {
+            (None, None)
+        } else {
+            let sha = format.read(buf).ok_or(anyhow!("Invalid TREE extension"))?;
+            buf.advance(format.byte_len());
+            (Some(entry_count as usize), Some(sha))
+        };
+
+        let subtrees = (0..subtree_count)
+            .map(|_| CacheTree::read(buf, format, depth + 1))
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(CacheTree { name, entry_count, sha, subtrees })
+    }
+
+    fn write(&self, buf: &mut BytesMut) {
+        buf.put_slice(self.name.as_bytes());
+        buf.put_u8(0);
+        match (self.entry_count, &self.sha) {
+            (Some(entry_count), Some(sha)) => {
+                buf.put_slice(format!("{} {}\n", entry_count, self.subtrees.len()).as_bytes());
+                buf.put_slice(sha.as_ref());
+            }
+            _ => buf.put_slice(format!("-1 {}\n", self.subtrees.len()).as_bytes()),
+        }
+        for subtree in &self.subtrees {
+            subtree.write(buf);
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Index {
+    /// The on-disk format version; 0 picks the oldest that fits.
+    pub version: u32,
+    pub entries: Vec<IndexEntry>,
+    pub cache_tree: Option<CacheTree>,
+}
+
+impl Index {
+    /// Reads the index file, treating a missing file as an empty index.
+    /// Object IDs and the checksum use the repository's hash `format`.
+    pub fn read(path: &Path, format: HashAlgorithm) -> anyhow::Result<Index> {
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Index::default()),
+            Err(err) => return Err(err.into()),
+        };
+        Index::parse(&data, format)
+    }
+
+    /// Parses the contents of an index file.
+    pub fn parse(data: &[u8], format: HashAlgorithm) -> anyhow::Result<Index> {
+        if data.len() < 12 + format.byte_len() {
+            return Err(anyhow!("Index file is too short"));
+        }
+
+        let (content, checksum) = data.split_at(data.len() - format.byte_len());
+        if format.hash(content).as_ref() != checksum {
+            return Err(anyhow!("Index file is corrupt: bad checksum"));
+        }
+
+        let mut buf = content;
+        if &buf[..4] != INDEX_SIGNATURE {
+            return Err(anyhow!("Index file has a bad signature"));
+        }
+        buf.advance(4);
+        let version = buf.get_u32();
+        if !(2..=4).contains(&version) {
+            return Err(anyhow!("Unsupported index version: {}", version));
+        }
+        let count = buf.get_u32() as usize;
+
+        // The count is only trusted as far as the entries could fit in
+        // what is left, each fixed part followed by at least a NUL.
+        let fixed_length = ENTRY_STAT_LENGTH + format.byte_len() + ENTRY_FLAGS_LENGTH;
+        let mut entries: Vec<IndexEntry> = Vec::with_capacity(count.min(buf.remaining() / (fixed_length + 1)));
+        for _ in 0..count {
+            let start = buf.remaining();
+            if start < fixed_length {
+                return Err(anyhow!("Index file is truncated"));
+            }
+
+            let ctime = (buf.get_u32(), buf.get_u32());
+            let mtime = (buf.get_u32(), buf.get_u32());
+            let dev = buf.get_u32();
+            let ino = buf.get_u32();
+            let mode = buf.get_u32();
+            let uid = buf.get_u32();
+            let gid = buf.get_u32();
+            let size = buf.get_u32();
+            let sha = format.read(buf).ok_or(anyhow!("Index file is truncated"))?;
+            buf.advance(format.byte_len());
+            let flags = buf.get_u16();
+            let extended_flags = if flags & FLAG_EXTENDED != 0 {
+                if version < 3 || buf.remaining() < 2 {
+                    return Err(anyhow!("Index file is corrupt: bad extended flags"));
+                }
+                buf.get_u16()
+            } else {
+                0
+            };
+
+            // Version 4 stores each path as the number of bytes to drop from
+            // the end of the previous one plus the new suffix, without padding.
+            let mut path = Vec::new();
+            if version == 4 {
+                let strip = read_varint(&mut buf)?;
+                let previous = entries.last().map(|entry| entry.path.as_bytes()).unwrap_or(b"");
+                let keep = previous.len().checked_sub(strip)
+                    .ok_or(anyhow!("Index file is corrupt: bad path prefix"))?;
+                path.extend_from_slice(&previous[..keep]);
+            }
+
+            let path_length = buf.iter()
+                .position(|&b| b == 0)
+                .ok_or(anyhow!("Index file is truncated"))?;
+            path.extend_from_slice(&buf[..path_length]);
+            let path = String::from_utf8(path)?;
+
+            let padding = if version == 4 {
+                1
+            } else {
+                let entry_length = start - buf.remaining() + path_length;
+                8 - entry_length % 8
+            };
+            if buf.remaining() < path_length + padding {
+                return Err(anyhow!("Index file is truncated"));
+            }
+            buf.advance(path_length + padding);
+
+            entries.push(IndexEntry {
+                ctime,
+                mtime,
+                dev,
+                ino,
+                mode,
+                uid,
+                gid,
+                size,
+                sha,
+                flags: flags & FLAG_PRESERVED,
+                extended_flags,
+                path,
+            });
+        }
+
+        let mut cache_tree = None;
+        while buf.has_remaining() {
+            if buf.remaining() < 8 {
+                return Err(anyhow!("Index file is truncated"));
+            }
+            let signature: [u8; 4] = buf[..4].try_into().expect("Slice has four bytes");
+            buf.advance(4);
+            let size = buf.get_u32() as usize;
+            if buf.remaining() < size {
+                return Err(anyhow!("Index file is truncated"));
+            }
+            let (mut data, rest) = buf.split_at(size);
+            buf = rest;
+
+            // Extensions starting with an uppercase letter are optional
+            // caches that can be dropped; others change how the index must
+            // be read.
+            if &signature == TREE_SIGNATURE {
+                cache_tree = Some(CacheTree::read(&mut data, format, 0)?);
+            } else if !signature[0].is_ascii_uppercase() {
+                return Err(anyhow!(
+                    "Index uses the unsupported {} extension",
+                    String::from_utf8_lossy(&signature),
+                ));
+            }
+        }
+
+        Ok(Index { version, entries, cache_tree })
+    }
+
+    /// Writes the index as version 2, or 3 if an entry has extended flags,
+    /// unless it was read as version 4. Unknown extensions are dropped.
+    pub fn write(&self, path: &Path, format: HashAlgorithm) -> anyhow::Result<()> {
+        lockfile::write(path, self.serialize(format))
+    }
+
+    /// The contents of the index file, as `write` stores them.
+    pub fn serialize(&self, format: HashAlgorithm) -> BytesMut {
+        let mut entries: Vec<&IndexEntry> = self.entries.iter().collect();
+        entries.sort_by(|a, b| a.path.as_bytes().cmp(b.path.as_bytes()));
+
+        let version = match self.version {
+            4 => 4,
+            _ if entries.iter().any(|entry| entry.extended_flags != 0) => 3,
+            _ => 2,
+        };
+
+        let mut buf = BytesMut::new();
+        buf.put_slice(INDEX_SIGNATURE);
+        buf.put_u32(version);
+        buf.put_u32(entries.len() as u32);
+
+        let mut previous: &str = "";
+        for entry in entries {
+            let start = buf.len();
+            buf.put_u32(entry.ctime.0);
+            buf.put_u32(entry.ctime.1);
+            buf.put_u32(entry.mtime.0);
+            buf.put_u32(entry.mtime.1);
+            buf.put_u32(entry.dev);
+            buf.put_u32(entry.ino);
+            buf.put_u32(entry.mode);
+            buf.put_u32(entry.uid);
+            buf.put_u32(entry.gid);
+            buf.put_u32(entry.size);
+            buf.put_slice(entry.sha.as_ref());
+
+            let mut flags = entry.flags & FLAG_PRESERVED | entry.path.len().min(MAX_NAME_LENGTH) as u16;
+            if entry.extended_flags != 0 {
+                flags |= FLAG_EXTENDED;
+            }
+            buf.put_u16(flags);
+            if entry.extended_flags != 0 {
+                buf.put_u16(entry.extended_flags);
+            }
+
+            if version == 4 {
+                let common = previous.bytes()
+                    .zip(entry.path.bytes())
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                write_varint(&mut buf, previous.len() - common);
+                buf.put_slice(&entry.path.as_bytes()[common..]);
+                buf.put_u8(0);
+                previous = &entry.path;
+            } else {
+                buf.put_slice(entry.path.as_bytes());
+
+                // Entries are NUL-padded to a multiple of eight bytes, with at
+                // least one NUL terminating the path.
+                let padding = 8 - (buf.len() - start) % 8;
+                buf.put_bytes(0, padding);
+            }
+        }
+
+        if let Some(cache_tree) = &self.cache_tree {
+            let mut data = BytesMut::new();
+            cache_tree.write(&mut data);
+            buf.put_slice(TREE_SIGNATURE);
+            buf.put_u32(data.len() as u32);
+            buf.put_slice(&data);
+        }
+
+        let checksum = format.hash(&buf);
+        buf.put_slice(checksum.as_ref());
+        buf
+    }
+}
+
+/// Reads git's offset varint, where each continuation adds one before
+/// shifting so that every value has a single encoding.
+fn read_varint(buf: &mut &[u8]) -> anyhow::Result<usize> {
+    let mut next = || {
+        buf.has_remaining()
+            .then(|| buf.get_u8())
+            .ok_or(anyhow!("Index file is truncated"))
+    };
+
+    let mut byte = next()?;
+    let mut value = (byte & 0x7f) as usize;
+    while byte & 0x80 != 0 {
+        byte = next()?;
+        value = value.checked_add(1)
+            .and_then(|value| value.checked_mul(0x80))
+            .ok_or(anyhow!("Index file is corrupt: bad path prefix"))?
+            | (byte & 0x7f) as usize;
+    }
+
+    Ok(value)
+}
+
+fn write_varint(buf: &mut BytesMut, mut value: usize) {
+    let mut bytes = vec![(value & 0x7f) as u8];
+    while value >= 0x80 {
+        value = (value >> 7) - 1;
+        bytes.push(0x80 | (value & 0x7f) as u8);
+    }
+    bytes.reverse();
+    buf.put_slice(&bytes);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FORMAT: HashAlgorithm = HashAlgorithm::Sha1;
+
+    fn index(version: u32, paths: &[&str]) -> Index {
+        let entries = paths.iter()
+            .map(|path| IndexEntry::new(path.to_string(), 0o100644, FORMAT.hash(path.as_bytes())))
+            .collect();
+        Index { version, entries, cache_tree: None }
+    }
+
+    /// `content` with the checksum that makes it a well-formed file.
+    fn with_checksum(mut content: Vec<u8>) -> Vec<u8> {
+        let checksum = FORMAT.hash(&content);
+        content.extend_from_slice(checksum.as_ref());
+        content
+    }
+
+    fn paths(index: &Index) -> Vec<&str> {
+        index.entries.iter().map(|entry| entry.path.as_str()).collect()
+    }
+
+    #[test]
+    fn versions_round_trip() {
+        let paths_in = ["a", "dir/file", "dir/file2", "dir/sub/deep", "z"];
+
+        let parsed = Index::parse(&index(0, &paths_in).serialize(FORMAT), FORMAT).unwrap();
+        assert_eq!(parsed.version, 2);
+        assert_eq!(paths(&parsed), paths_in);
+
+        let mut extended = index(0, &paths_in);
+        extended.entries[1].extended_flags = SKIP_WORKTREE;
+        let parsed = Index::parse(&extended.serialize(FORMAT), FORMAT).unwrap();
+        assert_eq!(parsed.version, 3);
+        assert_eq!(parsed.entries[1].extended_flags, SKIP_WORKTREE);
+        assert_eq!(parsed.entries[0].extended_flags, 0);
+
+        let compressed = index(4, &paths_in);
+        let data = compressed.serialize(FORMAT);
+        let parsed = Index::parse(&data, FORMAT).unwrap();
+        assert_eq!(parsed.version, 4);
+        assert_eq!(paths(&parsed), paths_in);
+        assert!(data.len() < index(2, &paths_in).serialize(FORMAT).len());
+    }
+
+    #[test]
+    fn cache_tree_round_trip() {
+        let mut index = index(0, &["a", "dir/file"]);
+        index.cache_tree = Some(CacheTree {
+            name: String::new(),
+            entry_count: Some(2),
+            sha: Some(FORMAT.hash(b"root")),
+            subtrees: vec![CacheTree { name: "dir".to_string(), entry_count: None, sha: None, subtrees: Vec::new() }],
+        });
+        let cache_tree = Index::parse(&index.serialize(FORMAT), FORMAT).unwrap().cache_tree.unwrap();
+        assert_eq!(cache_tree.entry_count, Some(2));
+        assert_eq!(cache_tree.sha, Some(FORMAT.hash(b"root")));
+        assert_eq!(cache_tree.subtrees[0].name, "dir");
+        assert_eq!(cache_tree.subtrees[0].entry_count, None);
+    }
+
+    #[test]
+    fn truncated_files_are_errors() {
+        let data = index(4, &["a", "dir/file"]).serialize(FORMAT);
+        let content = &data[..data.len() - FORMAT.byte_len()];
+        for length in 0..content.len() {
+            assert!(Index::parse(&with_checksum(content[..length].to_vec()), FORMAT).is_err(), "{}", length);
+        }
+        let mut corrupt = data.to_vec();
+        corrupt[20] ^= 1;
+        assert!(Index::parse(&corrupt, FORMAT).is_err());
+    }
+
+    #[test]
+    fn entry_count_is_not_trusted() {
+        let mut content = b"DIRC".to_vec();
+        content.extend_from_slice(&2u32.to_be_bytes());
+        content.extend_from_slice(&u32::MAX.to_be_bytes());
+        assert_eq!(Index::parse(&with_checksum(content), FORMAT).unwrap_err().to_string(), "Index file is truncated");
+    }
+
+    #[test]
+    fn bad_path_prefixes_are_errors() {
+        let data = index(4, &["ab"]).serialize(FORMAT);
+        let header = 12 + ENTRY_STAT_LENGTH + FORMAT.byte_len() + ENTRY_FLAGS_LENGTH;
+
+        // A prefix varint far longer than any usize.
+        let mut content = data[..header].to_vec();
+        content.extend_from_slice(&[0xff; 20]);
+        content.extend_from_slice(b"\0ab\0");
+        let error = Index::parse(&with_checksum(content), FORMAT).unwrap_err();
+        assert_eq!(error.to_string(), "Index file is corrupt: bad path prefix");
+
+        // Stripping more than the previous path holds.
+        let mut content = data[..header].to_vec();
+        content.extend_from_slice(&[3]);
+        content.extend_from_slice(b"ab\0");
+        let error = Index::parse(&with_checksum(content), FORMAT).unwrap_err();
+        assert_eq!(error.to_string(), "Index file is corrupt: bad path prefix");
+    }
+
+    #[test]
+    fn varints_round_trip() {
+        for value in [0, 1, 0x7f, 0x80, 0x3fff, 0x4000, 0x4080, usize::MAX] {
+            let mut buf = BytesMut::new();
+            write_varint(&mut buf, value);
+            let mut data: &[u8] = &buf;
+            assert_eq!(read_varint(&mut data).unwrap(), value);
+            assert!(data.is_empty());
+        }
+    }
+}
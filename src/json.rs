@@ -0,0 +1,57 @@
+use std::fmt::{self, Display, Formatter};
+
+/// A flat JSON object built one field at a time, written on one line
+/// for the `--json` output of commands.
+#[derive(Debug, Default, Clone)]
+pub struct Record {
+    fields: Vec<(&'static str, String)>,
+}
+
+impl Record {
+    pub fn new() -> Record {
+        Record::default()
+    }
+
+    pub fn string(mut self, key: &'static str, value: &str) -> Record {
+        self.fields.push((key, quote(value)));
+        self
+    }
+
+    pub fn number(mut self, key: &'static str, value: u64) -> Record {
+        self.fields.push((key, value.to_string()));
+        self
+    }
+}
+
+impl Display for Record {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{{")?;
+        for (i, (key, value)) in self.fields.iter().enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            write!(f, "{}:{}", quote(key), value)?;
+        }
+        write!(f, "}}")
+    }
+}
+
+/// `text` as a JSON string, escaping quotes, backslashes and control
+/// characters.
+pub fn quote(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
@@ -0,0 +1,354 @@
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::anyhow;
+use serde_json::{json, Value};
+
+use crate::config::{self, Config};
+use crate::http;
+use crate::object_id::ObjectId;
+use crate::objects::{self, Object};
+use crate::refs;
+use crate::repository::{self, Repository};
+use crate::rev_list::RevisionSet;
+use crate::runtime::block_on;
+use crate::sha256;
+
+const SPEC_URL: &str = "https://git-lfs.github.com/spec/v1";
+
+/// Pointers are small; anything larger is real content.
+const MAX_POINTER_SIZE: usize = 1024;
+
+const MEDIA_TYPE: &str = "application/vnd.git-lfs+json";
+
+/// What git stores in place of a large file tracked by LFS.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pointer {
+    /// The SHA-256 of the content, in hex.
+    pub oid: String,
+    pub size: u64,
+}
+
+impl Pointer {
+    /// Parses `content` if it is a pointer file.
+    pub fn parse(content: &[u8]) -> Option<Pointer> {
+        if content.len() > MAX_POINTER_SIZE {
+            return None;
+        }
+        let text = std::str::from_utf8(content).ok()?;
+        let mut lines = text.lines();
+        let version = lines.next()?.strip_prefix("version ")?;
+        if version != SPEC_URL && version != "https://hawser.github.com/spec/v1" {
+            return None;
+        }
+
+        let (mut oid, mut size) = (None, None);
+        for line in lines {
+            match line.split_once(' ')? {
+                ("oid", value) => oid = Some(value.strip_prefix("sha256:")?.to_string()),
+                ("size", value) => size = Some(value.parse().ok()?),
+                _ => {}
+            }
+        }
+        let oid = oid.filter(|oid| oid.len() == 64 && oid.bytes().all(|b| b.is_ascii_hexdigit()))?;
+        Some(Pointer { oid, size: size? })
+    }
+
+    fn for_content(content: &[u8]) -> Pointer {
+        Pointer { oid: hex::encode(sha256::digest(content)), size: content.len() as u64 }
+    }
+}
+
+impl std::fmt::Display for Pointer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "version {}\noid sha256:{}\nsize {}\n", SPEC_URL, self.oid, self.size)
+    }
+}
+
+/// Where the content of `oid` is kept: `lfs/objects/<aa>/<bb>/<oid>` in
+/// the git directory.
+fn object_path(repository: &Repository, oid: &str) -> PathBuf {
+    repository.common_dir().join("lfs/objects").join(&oid[..2]).join(&oid[2..4]).join(oid)
+}
+
+fn store(repository: &Repository, pointer: &Pointer, content: &[u8]) -> anyhow::Result<()> {
+    let path = object_path(repository, &pointer.oid);
+    if path.exists() {
+        return Ok(());
+    }
+    let directory = path.parent().expect("Object paths have a directory");
+    fs::create_dir_all(directory)?;
+    let temporary = directory.join(format!("{}.tmp", pointer.oid));
+    fs::write(&temporary, content)?;
+    fs::rename(temporary, path)?;
+    Ok(())
+}
+
+/// The LFS server for `remote`: `lfs.url`, `remote.<remote>.lfsurl`, or
+/// `info/lfs` under the remote's URL.
+fn endpoint(config: &Config, remote: &str) -> anyhow::Result<String> {
+    if let Some(url) = config.get("lfs.url").or(config.get(&format!("remote.{}.lfsurl", remote))) {
+        return Ok(url.trim_end_matches('/').to_string());
+    }
+    let url = config.get(&format!("remote.{}.url", remote)).unwrap_or(remote).trim_end_matches('/');
+    if !url.starts_with("http://") && !url.starts_with("https://") {
+        return Err(anyhow!("LFS needs an http(s) remote, not '{}'; set lfs.url", url));
+    }
+    match url.ends_with(".git") {
+        true => Ok(format!("{}/info/lfs", url)),
+        false => Ok(format!("{}.git/info/lfs", url)),
+    }
+}
+
+fn with_headers(mut request: reqwest::RequestBuilder, action: &Value) -> reqwest::RequestBuilder {
+    if let Some(headers) = action["header"].as_object() {
+        for (name, value) in headers {
+            request = request.header(name.as_str(), value.as_str().unwrap_or_default());
+        }
+    }
+    request
+}
+
+/// Asks the server at `endpoint` how to `operation` ("download" or
+/// "upload") the objects of `pointers`, returning each object's entry of
+/// the response.
+fn batch(
+    client: &mut http::Client,
+    endpoint: &str,
+    operation: &str,
+    pointers: &[Pointer],
+) -> anyhow::Result<Vec<Value>> {
+    let objects: Vec<Value> =
+        pointers.iter().map(|pointer| json!({"oid": pointer.oid, "size": pointer.size})).collect();
+    let body = json!({"operation": operation, "transfers": ["basic"], "objects": objects, "hash_algo": "sha256"});
+    let response = client
+        .send(|client| {
+            client
+                .post(format!("{}/objects/batch", endpoint))
+                .header("Accept", MEDIA_TYPE)
+                .header("Content-Type", MEDIA_TYPE)
+                .body(body.to_string())
+        })
+        .map_err(|err| anyhow!("LFS batch request failed: {}", err))?;
+    let status = response.status();
+    let text = block_on(response.text())?;
+    if !status.is_success() {
+        let message: Option<String> =
+            serde_json::from_str::<Value>(&text).ok().and_then(|value| value["message"].as_str().map(str::to_string));
+        return Err(anyhow!("LFS server returned {}: {}", status, message.unwrap_or(text)));
+    }
+
+    let response: Value = serde_json::from_str(&text).map_err(|err| anyhow!("Invalid LFS batch response: {}", err))?;
+    let objects = response["objects"].as_array().ok_or(anyhow!("Invalid LFS batch response: no objects"))?;
+    for object in objects {
+        if let Some(error) = object.get("error") {
+            return Err(anyhow!(
+                "LFS object {}: {}",
+                object["oid"].as_str().unwrap_or_default(),
+                error["message"].as_str().unwrap_or("error")
+            ));
+        }
+    }
+    Ok(objects.clone())
+}
+
+/// Fetches the content of `pointer` from the server at `endpoint`.
+fn download(config: &Config, endpoint: &str, pointer: &Pointer) -> anyhow::Result<Vec<u8>> {
+    let mut client = http::Client::new(config, endpoint)?;
+    let objects = batch(&mut client, endpoint, "download", std::slice::from_ref(pointer))?;
+    let action = objects
+        .iter()
+        .find(|object| object["oid"] == pointer.oid.as_str())
+        .map(|object| &object["actions"]["download"])
+        .filter(|action| action.is_object())
+        .ok_or(anyhow!("LFS server has no download for {}", pointer.oid))?;
+    let href = action["href"].as_str().ok_or(anyhow!("LFS download for {} has no href", pointer.oid))?;
+
+    let response = block_on(with_headers(client.inner().get(href), action).send())?;
+    if !response.status().is_success() {
+        return Err(anyhow!("LFS download of {} failed: {}", pointer.oid, response.status()));
+    }
+    let content = block_on(response.bytes())?.to_vec();
+    if Pointer::for_content(&content) != *pointer {
+        return Err(anyhow!("LFS object {} is corrupt", pointer.oid));
+    }
+    Ok(content)
+}
+
+/// The `clean` filter: stores the content from `input` and writes its
+/// pointer to `output`. Content that already is a pointer passes through.
+pub fn clean(input: &mut impl Read, output: &mut impl Write) -> anyhow::Result<()> {
+    let mut content = Vec::new();
+    input.read_to_end(&mut content)?;
+    if Pointer::parse(&content).is_some() {
+        output.write_all(&content)?;
+        return Ok(());
+    }
+
+    let repository = repository::current()?;
+    let pointer = Pointer::for_content(&content);
+    store(&repository, &pointer, &content)?;
+    write!(output, "{}", pointer)?;
+    Ok(())
+}
+
+/// The `smudge` filter: writes the content of the pointer on `input`,
+/// downloading it from the `origin` LFS server if it isn't stored yet.
+/// Anything that isn't a pointer passes through, and so do pointers with
+/// `GIT_LFS_SKIP_SMUDGE` set.
+pub fn smudge(input: &mut impl Read, output: &mut impl Write) -> anyhow::Result<()> {
+    let mut content = Vec::new();
+    input.read_to_end(&mut content)?;
+    let skip = std::env::var("GIT_LFS_SKIP_SMUDGE").is_ok_and(|value| config::parse_bool(&value) == Some(true));
+    let Some(pointer) = Pointer::parse(&content).filter(|_| !skip) else {
+        output.write_all(&content)?;
+        return Ok(());
+    };
+
+    let repository = repository::current()?;
+    let path = object_path(&repository, &pointer.oid);
+    let content = match fs::read(&path) {
+        Ok(content) => content,
+        Err(_) => {
+            let config = repository.config()?;
+            let content = download(&config, &endpoint(&config, "origin")?, &pointer)?;
+            store(&repository, &pointer, &content)?;
+            content
+        }
+    };
+    output.write_all(&content)?;
+    Ok(())
+}
+
+/// The pointers in the trees of the commits reachable from `include`
+/// but not from `exclude`.
+fn pointers_between(
+    repository: &Repository,
+    include: Vec<ObjectId>,
+    exclude: Vec<ObjectId>,
+) -> anyhow::Result<Vec<Pointer>> {
+    let commits = RevisionSet { include, exclude, ..RevisionSet::default() }.walk()?;
+    let mut seen = std::collections::HashSet::new();
+    let mut pointers = Vec::new();
+    for commit in commits {
+        let Object::Commit(commit) = repository.read(&commit)? else { continue };
+        for (_, id) in objects::tree_files(&commit.tree)?.into_values() {
+            if !seen.insert(id) {
+                continue;
+            }
+            let (kind, size) = repository.object_header(&id)?;
+            if kind != "blob" || size as usize > MAX_POINTER_SIZE {
+                continue;
+            }
+            if let Object::Blob(content) = repository.read(&id)? {
+                pointers.extend(Pointer::parse(&content).filter(|pointer| !pointers.contains(pointer)));
+            }
+        }
+    }
+    Ok(pointers)
+}
+
+/// Uploads the LFS objects that `refs` need and `remote` doesn't have
+/// yet, judging by its remote-tracking branches, as a pre-push hook
+/// would.
+pub fn push(remote: &str, revisions: &[String]) -> anyhow::Result<()> {
+    let repository = repository::current()?;
+    let config = repository.config()?;
+    let endpoint = endpoint(&config, remote)?;
+    let include = revisions.iter().map(|revision| refs::resolve_revision(revision)).collect::<anyhow::Result<_>>()?;
+    let exclude = refs::list_refs(&format!("refs/remotes/{}/", remote))?
+        .iter()
+        .filter_map(|name| refs::read_ref(name).transpose())
+        .collect::<anyhow::Result<_>>()?;
+
+    let pointers: Vec<Pointer> = pointers_between(&repository, include, exclude)?
+        .into_iter()
+        .filter(|pointer| object_path(&repository, &pointer.oid).exists())
+        .collect();
+    if pointers.is_empty() {
+        return Ok(());
+    }
+
+    let mut client = http::Client::new(&config, &endpoint)?;
+    let mut uploaded = 0;
+    let objects = batch(&mut client, &endpoint, "upload", &pointers)?;
+    let client = client.inner();
+    for object in objects {
+        // Without an upload action the server already has the object.
+        let actions = &object["actions"];
+        let Some(href) = actions["upload"]["href"].as_str() else { continue };
+        let oid = object["oid"].as_str().unwrap_or_default();
+        let pointer =
+            pointers.iter().find(|pointer| pointer.oid == oid).ok_or(anyhow!("Unexpected LFS object {}", oid))?;
+
+        let content = fs::read(object_path(&repository, oid))?;
+        let request =
+            with_headers(client.put(href), &actions["upload"]).header("Content-Type", "application/octet-stream");
+        let response = block_on(request.body(content).send())?;
+        if !response.status().is_success() {
+            return Err(anyhow!("LFS upload of {} failed: {}", oid, response.status()));
+        }
+        if let Some(href) = actions["verify"]["href"].as_str() {
+            let body = json!({"oid": pointer.oid, "size": pointer.size}).to_string();
+            let request = with_headers(client.post(href), &actions["verify"]).header("Content-Type", MEDIA_TYPE);
+            let response = block_on(request.body(body).send())?;
+            if !response.status().is_success() {
+                return Err(anyhow!("LFS verify of {} failed: {}", oid, response.status()));
+            }
+        }
+        uploaded += 1;
+    }
+    eprintln!("Uploading LFS objects: {} of {} uploaded", uploaded, pointers.len());
+    Ok(())
+}
+
+/// Makes the filter driver `lfs` run `bin lfs clean` and `bin lfs
+/// smudge`, in the global config or `location`.
+pub fn install(bin: &str, location: &config::Location) -> anyhow::Result<()> {
+    let settings = [
+        ("filter.lfs.clean", format!("{} lfs clean -- %f", bin)),
+        ("filter.lfs.smudge", format!("{} lfs smudge -- %f", bin)),
+        ("filter.lfs.required", "true".to_string()),
+    ];
+    for (name, value) in &settings {
+        config::run(location, config::Action::Set { name, value })?;
+    }
+    println!("Git LFS initialized.");
+    Ok(())
+}
+
+/// Tracks `patterns` with LFS in `.gitattributes`, or lists the tracked
+/// patterns if there are none.
+pub fn track(patterns: &[String]) -> anyhow::Result<()> {
+    let path = Path::new(".gitattributes");
+    let mut content = fs::read_to_string(path).unwrap_or_default();
+    let tracked: Vec<&str> = content
+        .lines()
+        .filter(|line| line.split_whitespace().skip(1).any(|attribute| attribute == "filter=lfs"))
+        .filter_map(|line| line.split_whitespace().next())
+        .collect();
+
+    if patterns.is_empty() {
+        println!("Listing tracked patterns");
+        for pattern in tracked {
+            println!("    {} (.gitattributes)", pattern);
+        }
+        return Ok(());
+    }
+
+    let tracked: Vec<String> = tracked.into_iter().map(str::to_string).collect();
+    for pattern in patterns {
+        if tracked.contains(pattern) {
+            println!("\"{}\" already supported", pattern);
+            continue;
+        }
+        if !content.is_empty() && !content.ends_with('\n') {
+            content.push('\n');
+        }
+        content.push_str(&format!("{} filter=lfs diff=lfs merge=lfs -text\n", pattern));
+        println!("Tracking \"{}\"", pattern);
+    }
+    fs::write(path, content)?;
+    Ok(())
+}
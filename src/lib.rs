@@ -0,0 +1,92 @@
+//! A small git implementation. [`Repository`] is the entry point for
+//! reading and writing objects; the `git-starter-rust` binary is a thin
+//! command line wrapper around these modules.
+
+pub mod alias;
+pub mod attributes;
+pub mod bisect;
+mod bitmap;
+pub mod branch;
+pub mod bundle;
+pub mod browse;
+mod cache;
+pub mod cherry;
+pub mod color;
+pub mod cat_file;
+pub mod commit_graph;
+pub mod completion;
+pub mod config;
+pub mod convert;
+pub mod copy_objects;
+pub mod credential;
+#[cfg(unix)]
+pub mod credential_cache;
+pub mod date;
+pub mod diff;
+pub mod error;
+pub mod export_html;
+pub mod fast_export;
+pub mod fast_import;
+mod fsck;
+pub mod help;
+mod hooks;
+mod http;
+pub mod ident;
+mod ignore;
+mod index;
+pub mod json;
+pub mod lfs;
+pub mod log;
+mod lockfile;
+pub mod ls_files;
+pub mod mailinfo;
+pub mod mailmap;
+pub mod mailsplit;
+pub mod maintenance;
+mod midx;
+pub mod name_rev;
+pub mod notes;
+pub mod object_id;
+pub mod objects;
+pub mod operation;
+pub mod pager;
+mod pack;
+pub mod pathspec;
+mod pktline;
+mod precompose;
+pub mod profile;
+mod progress;
+pub mod quote;
+pub mod range_diff;
+pub mod reflog;
+pub mod refs;
+pub mod refspec;
+mod reftable;
+mod regex;
+pub mod remote;
+pub mod repack;
+pub mod replace;
+pub mod repository;
+pub mod request_pull;
+pub mod rev_list;
+pub mod rewrite_history;
+mod runtime;
+pub mod show_branch;
+mod sha256;
+pub mod sparse;
+pub mod stats;
+pub mod submodule;
+pub mod trace;
+pub mod trailers;
+pub mod update_index;
+pub mod update_ref;
+pub mod upload_pack;
+mod userdiff;
+pub mod verify_objects;
+pub mod verbosity;
+mod verify_path;
+mod wildmatch;
+pub mod worktree;
+
+pub use object_id::ObjectId;
+pub use repository::Repository;
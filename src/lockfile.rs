@@ -0,0 +1,85 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::anyhow;
+
+/// Exclusive access to a file while it is rewritten, following git's
+/// protocol: the new contents go to `<path>.lock`, created only if it
+/// doesn't exist yet, which is renamed over `path` on [`commit`]. Other
+/// processes fail to take the lock rather than interleave their writes.
+/// Dropping the lock without committing leaves `path` untouched.
+///
+/// [`commit`]: LockFile::commit
+pub(crate) struct LockFile {
+    path: PathBuf,
+    lock_path: PathBuf,
+    file: Option<File>,
+}
+
+impl LockFile {
+    pub(crate) fn acquire(path: &Path) -> anyhow::Result<LockFile> {
+        let mut lock_path = path.as_os_str().to_owned();
+        lock_path.push(".lock");
+        let lock_path = PathBuf::from(lock_path);
+
+        let file = match OpenOptions::new().write(true).create_new(true).open(&lock_path) {
+            Ok(file) => file,
+            Err(error) if error.kind() == io::ErrorKind::AlreadyExists => {
+                return Err(anyhow!(
+                    "Unable to create '{}': File exists.\n\n\
+                     Another git process seems to be running in this repository.\n\
+                     If it still fails, a git process may have crashed earlier:\n\
+                     remove the file manually to continue.",
+                    lock_path.display()
+                ))
+            }
+            Err(error) => return Err(anyhow!("Unable to create '{}': {}", lock_path.display(), error)),
+        };
+
+        Ok(LockFile { path: path.to_path_buf(), lock_path, file: Some(file) })
+    }
+
+    pub(crate) fn write(&mut self, data: &[u8]) -> anyhow::Result<()> {
+        self.file.as_mut().expect("Lock is held until committed").write_all(data)?;
+        Ok(())
+    }
+
+    /// Flushes the new contents to disk and moves them into place. If
+    /// either fails, the lock is removed as on drop.
+    pub(crate) fn commit(mut self) -> anyhow::Result<()> {
+        self.flush()?;
+        self.rename()
+    }
+
+    /// Flushes the new contents to disk, all of committing that can fail
+    /// for want of space, so that several locks can get that far before
+    /// any of them is moved into place.
+    pub(crate) fn flush(&mut self) -> anyhow::Result<()> {
+        self.file.as_ref().expect("Lock is held until committed").sync_all()?;
+        Ok(())
+    }
+
+    /// Moves the flushed contents into place.
+    pub(crate) fn rename(mut self) -> anyhow::Result<()> {
+        fs::rename(&self.lock_path, &self.path)?;
+        // Only now is the lock file gone, leaving nothing for drop to do.
+        self.file = None;
+        Ok(())
+    }
+}
+
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        if self.file.take().is_some() {
+            let _ = fs::remove_file(&self.lock_path);
+        }
+    }
+}
+
+/// Replaces the contents of `path` under its lock.
+pub(crate) fn write(path: &Path, data: impl AsRef<[u8]>) -> anyhow::Result<()> {
+    let mut lock = LockFile::acquire(path)?;
+    lock.write(data.as_ref())?;
+    lock.commit()
+}
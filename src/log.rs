@@ -0,0 +1,459 @@
+//! `log`: the commits rev-list selects, with their authors, dates and
+//! messages and optionally their patches, newest first.
+
+use std::collections::HashMap;
+use std::io::Write;
+
+use anyhow::anyhow;
+
+use crate::color::{self, RESET};
+use crate::config::{self, Config};
+use crate::date;
+use crate::diff::{self, Pickaxe, TreeDiff};
+use crate::mailmap::{split_ident, Mailmap};
+use crate::object_id::ObjectId;
+use crate::objects::{Commit, Object};
+use crate::pager;
+use crate::refs;
+use crate::regex::Regex;
+use crate::repository::{self, Repository};
+use crate::rev_list::RevisionSet;
+
+/// How `log` shows commits, and which of those rev-list selects.
+#[derive(Debug, Default)]
+pub struct LogOptions {
+    /// One line per commit: its abbreviated ID and subject.
+    pub oneline: bool,
+    /// Show the patch of each commit against its first parent.
+    pub patch: bool,
+    pub max_count: Option<usize>,
+    /// `-S`: only commits that change how often this occurs in a file.
+    pub occurrences: Option<String>,
+    /// `-G`: only commits that add or remove a line matching this
+    /// extended regular expression.
+    pub lines: Option<String>,
+    /// Take the `-S` string as an extended regular expression.
+    pub pickaxe_regex: bool,
+    /// Show every file of a commit the pickaxe selects, not only the ones
+    /// it found something in.
+    pub pickaxe_all: bool,
+    /// `--decorate` or `--no-decorate`, else `log.decorate` decides.
+    pub decorate: Option<Decorate>,
+    /// `--color` or `--no-color`, else `color.diff` decides.
+    pub color: Option<color::When>,
+}
+
+/// How to show the refs that point at each commit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decorate {
+    No,
+    /// Without `refs/heads/`, `refs/remotes/` and `refs/tags/`.
+    Short,
+    Full,
+}
+
+impl Decorate {
+    /// `short`, `full` or `no`, or a boolean for short or no.
+    fn parse(value: &str) -> Option<Decorate> {
+        match value {
+            "short" => Some(Decorate::Short),
+            "full" => Some(Decorate::Full),
+            "no" => Some(Decorate::No),
+            value => config::parse_bool(value).map(|on| if on { Decorate::Short } else { Decorate::No }),
+        }
+    }
+
+    /// As `log.decorate` says, by default short refs only for a terminal or
+    /// the pager.
+    fn configured(config: &Config) -> anyhow::Result<Decorate> {
+        match config.get("log.decorate") {
+            Some("auto") | None => Ok(if pager::to_terminal() { Decorate::Short } else { Decorate::No }),
+            Some(value) => Decorate::parse(value).ok_or(anyhow!("Invalid log.decorate: {}", value)),
+        }
+    }
+}
+
+impl LogOptions {
+    /// Takes the options of `log` out of `args`: `--oneline`,
+    /// `-p`/`-u`/`--patch`, `-n <n>`/`-<n>`/`--max-count=<n>`, `-S`, `-G`,
+    /// `--pickaxe-regex`, `--pickaxe-all`, `--decorate[=<style>]`,
+    /// `--no-decorate`, `--color[=<when>]` and `--no-color`. The rest is
+    /// left for [`RevisionSet::parse`].
+    pub fn parse(args: &[String]) -> anyhow::Result<(LogOptions, Vec<String>)> {
+        let mut options = LogOptions::default();
+        let mut rest = Vec::new();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = |option: &str| -> anyhow::Result<String> {
+                match &arg[option.len()..] {
+                    "" => args.next().cloned().ok_or(anyhow!("Option {} requires a value", option)),
+                    value => Ok(value.trim_start_matches('=').to_string()),
+                }
+            };
+            match arg.as_str() {
+                "--oneline" => options.oneline = true,
+                "-p" | "-u" | "--patch" => options.patch = true,
+                "--pickaxe-regex" => options.pickaxe_regex = true,
+                "--pickaxe-all" => options.pickaxe_all = true,
+                "--decorate" => options.decorate = Some(Decorate::Short),
+                "--no-decorate" => options.decorate = Some(Decorate::No),
+                "--color" => options.color = Some(color::When::Always),
+                "--no-color" => options.color = Some(color::When::Never),
+                arg if arg.starts_with("--decorate=") => {
+                    let style = &arg["--decorate=".len()..];
+                    options.decorate = Some(Decorate::parse(style).ok_or(anyhow!("Invalid --decorate: {}", style))?);
+                }
+                arg if arg.starts_with("--color=") => {
+                    let when = &arg["--color=".len()..];
+                    options.color = Some(color::When::parse(when).ok_or(anyhow!("Invalid --color: {}", when))?);
+                }
+                arg if arg.starts_with("-S") => options.occurrences = Some(value("-S")?),
+                arg if arg.starts_with("-G") => options.lines = Some(value("-G")?),
+                arg if arg.starts_with("-n") || arg.starts_with("--max-count") => {
+                    let option = if arg.starts_with("-n") { "-n" } else { "--max-count" };
+                    let count = value(option)?;
+                    options.max_count = Some(count.parse().map_err(|_| anyhow!("Invalid count: {}", count))?);
+                }
+                arg if arg.len() > 1 && arg[1..].bytes().all(|b| b.is_ascii_digit()) && arg.starts_with('-') => {
+                    options.max_count = Some(arg[1..].parse()?);
+                }
+                _ => rest.push(arg.clone()),
+            }
+        }
+        Ok((options, rest))
+    }
+
+    fn pickaxe(&self) -> anyhow::Result<Option<Pickaxe>> {
+        let regex = |pattern: &str| Regex::new(pattern, true, false).ok_or(anyhow!("Invalid regex: {}", pattern));
+        match (&self.occurrences, &self.lines) {
+            (Some(_), Some(_)) => Err(anyhow!("-G and -S are mutually exclusive")),
+            (Some(needle), None) if self.pickaxe_regex => Ok(Some(Pickaxe::Matches(regex(needle)?))),
+            (Some(needle), None) => Ok(Some(Pickaxe::Occurrences(needle.as_bytes().to_vec()))),
+            (None, Some(pattern)) => Ok(Some(Pickaxe::Lines(regex(pattern)?))),
+            (None, None) => Ok(None),
+        }
+    }
+}
+
+/// What kind of ref a decoration is, which picks its color.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RefKind {
+    Head,
+    Branch,
+    RemoteBranch,
+    Tag,
+    Stash,
+}
+
+/// The colors of the log: those of its patches and commit headers, and
+/// those of the decorations, from `color.decorate.<slot>`.
+struct Colors {
+    diff: diff::Colors,
+    head: String,
+    branch: String,
+    remote_branch: String,
+    tag: String,
+    stash: String,
+}
+
+impl Colors {
+    fn load(config: &Config) -> anyhow::Result<Colors> {
+        let slot = |slot: &str, default: &str| color::slot(config, "decorate", slot, default);
+        Ok(Colors {
+            diff: diff::Colors::load(config)?,
+            head: slot("head", "bold cyan")?,
+            branch: slot("branch", "bold green")?,
+            remote_branch: slot("remotebranch", "bold red")?,
+            tag: slot("tag", "bold yellow")?,
+            stash: slot("stash", "bold magenta")?,
+        })
+    }
+
+    fn of(&self, kind: RefKind) -> &str {
+        match kind {
+            RefKind::Head => &self.head,
+            RefKind::Branch => &self.branch,
+            RefKind::RemoteBranch => &self.remote_branch,
+            RefKind::Tag => &self.tag,
+            RefKind::Stash => &self.stash,
+        }
+    }
+}
+
+/// The refs that point at each commit, through any tags, in the order
+/// git shows them: `HEAD` first, then the others last name first.
+fn decorations(repository: &Repository) -> anyhow::Result<HashMap<ObjectId, Vec<(RefKind, String)>>> {
+    let mut names = Vec::new();
+    for prefix in ["refs/heads/", "refs/remotes/", "refs/tags/"] {
+        names.extend(refs::list_refs(prefix)?);
+    }
+    names.extend(refs::read_ref("refs/stash")?.map(|_| "refs/stash".to_string()));
+    names.sort();
+    names.push("HEAD".to_string());
+
+    let mut decorations: HashMap<ObjectId, Vec<(RefKind, String)>> = HashMap::new();
+    for name in names.into_iter().rev() {
+        let Some(mut sha) = refs::read_ref(&name)? else { continue };
+        let kind = match name.as_str() {
+            "HEAD" => RefKind::Head,
+            "refs/stash" => RefKind::Stash,
+            name if name.starts_with("refs/heads/") => RefKind::Branch,
+            name if name.starts_with("refs/remotes/") => RefKind::RemoteBranch,
+            _ => RefKind::Tag,
+        };
+        loop {
+            decorations.entry(sha).or_default().push((kind, name.clone()));
+            match repository.read(&sha)? {
+                Object::Tag(tag) => sha = tag.object,
+                _ => break,
+            }
+        }
+    }
+    Ok(decorations)
+}
+
+/// Writes the refs that point at a commit the way `--decorate` shows
+/// them after its ID, such as ` (HEAD -> main, tag: v1.0, origin/main)`
+/// where `head` is `refs/heads/main`, with full ref names with `full`.
+fn write_decorations(
+    refs: &[(RefKind, String)],
+    head: Option<&str>,
+    full: bool,
+    colors: Option<&Colors>,
+    out: &mut String,
+) {
+    let (commit, reset) = match colors {
+        Some(colors) => (colors.diff.commit.as_str(), RESET),
+        None => ("", ""),
+    };
+    let color = |kind| colors.map_or("", |colors| colors.of(kind));
+    let shown = |name: &str| -> String {
+        let short = ["refs/heads/", "refs/remotes/", "refs/tags/"].iter().find_map(|prefix| name.strip_prefix(prefix));
+        match full {
+            true => name.to_string(),
+            false => short.unwrap_or(name).to_string(),
+        }
+    };
+    // With HEAD at its branch, the branch is shown with HEAD instead of
+    // on its own.
+    let current = match head {
+        Some(head) if refs.iter().any(|(kind, _)| *kind == RefKind::Head) => refs.iter().find(|(_, name)| name == head),
+        _ => None,
+    };
+    let mut prefix = " (";
+    for decoration @ (kind, name) in refs {
+        if Some(decoration) == current {
+            continue;
+        }
+        out.push_str(&format!("{}{}{}{}", commit, prefix, reset, color(*kind)));
+        if *kind == RefKind::Tag {
+            out.push_str("tag: ");
+        }
+        out.push_str(&shown(name));
+        if let (Some((current_kind, current)), RefKind::Head) = (current, kind) {
+            out.push_str(&format!(" -> {}{}{}", reset, color(*current_kind), shown(current)));
+        }
+        out.push_str(reset);
+        prefix = ", ";
+    }
+    if !refs.is_empty() {
+        out.push_str(&format!("{}){}", commit, reset));
+    }
+}
+
+/// The changes of `commit` against its first parent, or all of its files
+/// for a root commit. Merges have none, as in git by default.
+pub(crate) fn changes(repository: &Repository, commit: &Commit) -> anyhow::Result<Option<TreeDiff>> {
+    match commit.parents[..] {
+        [] => Ok(Some(TreeDiff::root(repository, &commit.tree)?)),
+        [parent] => Ok(Some(TreeDiff::new(repository, &repository.read_commit(&parent)?.tree, &commit.tree, true)?)),
+        _ => Ok(None),
+    }
+}
+
+/// Writes `commit` the way `git log` does by default: the `header` line
+/// with its ID, any parents of a merge, its author as the mailmap has it,
+/// the author date and the message indented by four spaces.
+fn write_medium(
+    sha: &ObjectId,
+    header: &str,
+    commit: &Commit,
+    mailmap: &Mailmap,
+    out: &mut Vec<u8>,
+) -> anyhow::Result<()> {
+    writeln!(out, "{}", header)?;
+    if commit.parents.len() > 1 {
+        let parents: Vec<String> = commit.parents.iter().map(|parent| parent.to_string()[..7].to_string()).collect();
+        writeln!(out, "Merge: {}", parents.join(" "))?;
+    }
+    let (name, email, time) = split_ident(&commit.author).ok_or(anyhow!("Invalid author in commit {}", sha))?;
+    let (name, email) = mailmap.lookup(name.unwrap_or_default(), email);
+    writeln!(out, "Author: {} <{}>", name, email)?;
+    if let Ok((time, offset)) = date::parse(time) {
+        writeln!(out, "Date:   {}", date::format_default(time, offset))?;
+    }
+    writeln!(out)?;
+    for line in commit.message.lines() {
+        writeln!(out, "    {}", line)?;
+    }
+    Ok(())
+}
+
+/// Shows the commits that `args` select, as `git log` does.
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    let (options, args) = LogOptions::parse(args)?;
+    let pickaxe = options.pickaxe()?;
+    let repository = repository::current()?;
+    let mut revisions = RevisionSet::parse(&args)?;
+    if revisions.include.is_empty() && revisions.exclude.is_empty() {
+        revisions.include.push(refs::resolve_revision("HEAD")?);
+    }
+    let mailmap = Mailmap::load(None)?;
+    let config = repository.config()?;
+    let colors = color::enabled(&config, "diff", options.color).then(|| Colors::load(&config)).transpose()?;
+    let decorate = options.decorate.map_or_else(|| Decorate::configured(&config), Ok)?;
+    let decorations = match decorate {
+        Decorate::No => HashMap::new(),
+        _ => decorations(&repository)?,
+    };
+    let head = refs::raw_ref("HEAD")?;
+    let head = head.as_deref().and_then(|head| head.strip_prefix("ref:")).map(str::trim);
+
+    let mut stdout = std::io::stdout().lock();
+    let mut shown = 0;
+    for (sha, mark) in revisions.walk_marked()? {
+        if options.max_count.is_some_and(|max| shown >= max) {
+            break;
+        }
+        let commit = repository.read_commit(&sha)?;
+        let mut changes = match options.patch || pickaxe.is_some() {
+            true => changes(&repository, &commit)?,
+            false => None,
+        };
+        if let Some(pickaxe) = &pickaxe {
+            if !changes.as_mut().is_some_and(|changes| changes.pickaxe(pickaxe, options.pickaxe_all)) {
+                continue;
+            }
+        }
+
+        let mut header = match options.oneline {
+            true => sha.to_string()[..7].to_string(),
+            false => format!("commit {}", sha),
+        };
+        if !mark.is_empty() {
+            let at = if options.oneline { 0 } else { "commit ".len() };
+            header.insert_str(at, &format!("{} ", mark));
+        }
+        if let Some(colors) = &colors {
+            header = format!("{}{}{}", colors.diff.commit, header, RESET);
+        }
+        if let Some(refs) = decorations.get(&sha) {
+            write_decorations(refs, head, decorate == Decorate::Full, colors.as_ref(), &mut header);
+        }
+
+        let mut out = Vec::new();
+        match options.oneline {
+            true => writeln!(out, "{} {}", header, commit.subject())?,
+            false => {
+                if shown > 0 {
+                    writeln!(out)?;
+                }
+                write_medium(&sha, &header, &commit, &mailmap, &mut out)?;
+            }
+        }
+        if let Some(changes) = changes.filter(|changes| options.patch && !changes.is_empty()) {
+            if !options.oneline {
+                writeln!(out)?;
+            }
+            changes.write_patch(diff::DEFAULT_CONTEXT, colors.as_ref().map(|colors| &colors.diff), &mut out);
+        }
+        match stdout.write_all(&out) {
+            // Whoever reads the log may stop early.
+            Err(err) if err.kind() == std::io::ErrorKind::BrokenPipe => return Ok(()),
+            result => result?,
+        }
+        shown += 1;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::objects::{Tree, TreeEntry};
+
+    #[test]
+    fn options() {
+        let args: Vec<String> =
+            ["-Sneedle", "main", "-n", "3", "--oneline", "-G", "a|b", "^old", "-p"].map(String::from).to_vec();
+        let (options, rest) = LogOptions::parse(&args).unwrap();
+        assert_eq!(options.occurrences.as_deref(), Some("needle"));
+        assert_eq!(options.lines.as_deref(), Some("a|b"));
+        assert_eq!(options.max_count, Some(3));
+        assert!(options.oneline && options.patch);
+        assert_eq!(rest, ["main", "^old"]);
+        assert!(options.pickaxe().is_err());
+
+        let (options, _) = LogOptions::parse(&["-7".to_string(), "--max-count=2".to_string()]).unwrap();
+        assert_eq!(options.max_count, Some(2));
+        assert!(LogOptions::parse(&["-S".to_string()]).is_err());
+
+        let args = ["--decorate=full", "--color", "--no-color"].map(String::from);
+        let (options, _) = LogOptions::parse(&args).unwrap();
+        assert_eq!((options.decorate, options.color), (Some(Decorate::Full), Some(color::When::Never)));
+        assert!(LogOptions::parse(&["--decorate=long".to_string()]).is_err());
+    }
+
+    #[test]
+    fn changes_of_commits() {
+        let repository = Repository::in_memory();
+        let tree = |files: &[(&str, &str)]| {
+            let entries = files.iter().map(|(name, content)| {
+                let sha = repository.write(&Object::Blob(content.as_bytes().to_vec())).unwrap();
+                TreeEntry { mode: 0o100644, name: name.to_string(), sha }
+            });
+            repository.write(&Object::Tree(Tree { entries: entries.collect() })).unwrap()
+        };
+        let first = repository.commit(&tree(&[("a.txt", "one\n")]), &[], "First").unwrap();
+        let second = tree(&[("a.txt", "one\ntwo\n"), ("b.txt", "new\n")]);
+        let second = repository.commit(&second, &[first], "Second").unwrap();
+        let merge = repository.commit(&tree(&[("a.txt", "one\n")]), &[second, first], "Merge").unwrap();
+        let patch = |sha: &ObjectId| {
+            let changes = changes(&repository, &repository.read_commit(sha).unwrap()).unwrap()?;
+            let mut out = Vec::new();
+            changes.write_patch(diff::DEFAULT_CONTEXT, None, &mut out);
+            Some(String::from_utf8(out).unwrap())
+        };
+
+        let root = patch(&first).unwrap();
+        assert!(root.starts_with("diff --git a/a.txt b/a.txt\nnew file mode 100644\n"), "{}", root);
+        assert!(root.ends_with("@@ -0,0 +1 @@\n+one\n"), "{}", root);
+        let second = patch(&second).unwrap();
+        assert!(second.contains("@@ -1 +1,2 @@\n one\n+two\n"), "{}", second);
+        assert!(second.contains("diff --git a/b.txt b/b.txt\nnew file mode 100644\n"), "{}", second);
+        assert_eq!(patch(&merge), None);
+    }
+
+    #[test]
+    fn decorations() {
+        let refs = [
+            (RefKind::Head, "HEAD"),
+            (RefKind::RemoteBranch, "refs/remotes/origin/main"),
+            (RefKind::Tag, "refs/tags/v1.0"),
+            (RefKind::Branch, "refs/heads/main"),
+        ]
+        .map(|(kind, name)| (kind, name.to_string()));
+        let decorated = |head, full| {
+            let mut out = String::new();
+            write_decorations(&refs, head, full, None, &mut out);
+            out
+        };
+        assert_eq!(decorated(Some("refs/heads/main"), false), " (HEAD -> main, origin/main, tag: v1.0)");
+        assert_eq!(decorated(None, false), " (HEAD, origin/main, tag: v1.0, main)");
+        assert_eq!(
+            decorated(Some("refs/heads/main"), true),
+            " (HEAD -> refs/heads/main, refs/remotes/origin/main, tag: refs/tags/v1.0)"
+        );
+        assert_eq!(decorated(None, false).len(), decorated(Some("refs/heads/other"), false).len());
+    }
+}
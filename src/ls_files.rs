@@ -0,0 +1,213 @@
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+use anyhow::anyhow;
+
+use crate::attributes::Attributes;
+use crate::config::{self, Config};
+use crate::convert::Convert;
+use crate::ignore::Ignore;
+use crate::index::{Index, ASSUME_VALID, SKIP_WORKTREE};
+use crate::pathspec::Pathspec;
+use crate::precompose::{self, precompose};
+use crate::quote::quote_path;
+use crate::repository;
+use crate::worktree::is_entry_modified;
+
+/// Which files `ls-files` shows. With none of `cached`, `others`,
+/// `modified` and `deleted`, it shows the cached ones.
+#[derive(Debug, Default)]
+pub struct ListOptions {
+    pub cached: bool,
+    /// Untracked files in the worktree.
+    pub others: bool,
+    /// Files that differ from the index, including deleted ones.
+    pub modified: bool,
+    pub deleted: bool,
+    /// Leave out untracked files matched by `.gitignore`, `info/exclude`
+    /// and `core.excludesFile`.
+    pub exclude_standard: bool,
+    /// Terminate paths with NUL and don't quote them.
+    pub null_terminated: bool,
+    /// Prefix each path with a tag for its status, such as `H` for cached
+    /// or `S` for skip-worktree.
+    pub tags: bool,
+    /// Like `tags`, but lowercase for assume-unchanged entries.
+    pub verbose: bool,
+    /// Show the mode, object ID and merge stage of each index entry, as
+    /// well as the cached ones.
+    pub stage: bool,
+    /// Like `stage`, but only list the cached entries of unmerged paths.
+    pub unmerged: bool,
+}
+
+/// Prints the files selected by `options`, limited to those the pathspec
+/// `paths` matches if any are given: untracked files first, then each index
+/// entry as often as it is cached, deleted and modified. Skip-worktree
+/// entries are never modified or deleted, and assume-unchanged ones are
+/// only checked for deletion. Each stage of an unmerged path is an entry
+/// of its own.
+pub fn list(options: &ListOptions, paths: &[String]) -> anyhow::Result<()> {
+    let repository = repository::current()?;
+    let git_dir = repository.git_dir();
+    let config = repository.config()?;
+    let index = Index::read(&git_dir.join("index"), repository.object_format())?;
+    let quote_non_ascii = match config.get("core.quotepath") {
+        Some(value) => config::parse_bool(value).ok_or(anyhow!("Bad core.quotePath value: {}", value))?,
+        None => true,
+    };
+
+    let pathspec = Pathspec::parse(paths)?;
+    let selected = |path: &str| pathspec.matches(path);
+    let show = |tag: char, details: &str, path: &str| {
+        let tag = if options.tags || options.verbose { format!("{} ", tag) } else { String::new() };
+        if options.null_terminated {
+            print!("{}{}{}\0", tag, details, path);
+        } else {
+            println!("{}{}{}", tag, details, quote_path(path, quote_non_ascii));
+        }
+    };
+
+    if options.others {
+        let ignore = if options.exclude_standard { Some(Ignore::load(git_dir, &config)) } else { None };
+        let others = untracked_files(Path::new("."), &index, &config, ignore)?;
+        for path in others.iter().filter(|path| selected(path.trim_end_matches('/'))) {
+            show('?', "", path);
+        }
+    }
+
+    let with_stage = options.stage || options.unmerged;
+    let cached = options.cached || with_stage || !(options.others || options.modified || options.deleted);
+    let convert = Convert::from_config(&config)?;
+    let mut attributes: HashMap<&str, Attributes> = HashMap::new();
+    for entry in index.entries.iter().filter(|entry| selected(&entry.path)) {
+        let assume_valid = entry.flags & ASSUME_VALID != 0;
+        let skip_worktree = entry.extended_flags & SKIP_WORKTREE != 0;
+        let tag = |tag: char| if options.verbose && assume_valid { tag.to_ascii_lowercase() } else { tag };
+        let stage = entry.stage();
+        let details = match with_stage {
+            true => format!("{:06o} {} {}\t", entry.mode, entry.sha, stage),
+            false => String::new(),
+        };
+
+        if cached && (!options.unmerged || stage != 0) {
+            let status = match (stage, skip_worktree) {
+                (1.., _) => 'M',
+                (_, true) => 'S',
+                (_, false) => 'H',
+            };
+            show(tag(status), &details, &entry.path);
+        }
+        if !(options.modified || options.deleted) || skip_worktree {
+            continue;
+        }
+
+        let file = Path::new(".").join(&entry.path);
+        let deleted = fs::symlink_metadata(&file).is_err();
+        if options.deleted && deleted {
+            show(tag('R'), &details, &entry.path);
+        }
+        if !options.modified || (assume_valid && !deleted) {
+            continue;
+        }
+        let modified = deleted || {
+            let directory = entry.path.rsplit_once('/').map(|(directory, _)| directory).unwrap_or("");
+            if !attributes.contains_key(directory) {
+                attributes.insert(directory, Attributes::load_for(git_dir, directory)?);
+            }
+            is_entry_modified(&file, &entry.path, entry.mode, &entry.sha, &attributes[directory], &convert)?
+        };
+        if modified {
+            show(tag('C'), &details, &entry.path);
+        }
+    }
+
+    Ok(())
+}
+
+/// The files below the worktree at `root` that `index` doesn't track and
+/// `ignore`, if given, doesn't ignore, sorted. Untracked repositories are
+/// listed as `dir/`.
+pub(crate) fn untracked_files(
+    root: &Path,
+    index: &Index,
+    config: &Config,
+    ignore: Option<Ignore>,
+) -> anyhow::Result<Vec<String>> {
+    // On case-insensitive filesystems `File` is the tracked `file`.
+    let ignore_case = config.get("core.ignorecase").and_then(config::parse_bool).unwrap_or(false);
+    let tracked: HashSet<Cow<str>> = index
+        .entries
+        .iter()
+        .map(|entry| match ignore_case {
+            true => Cow::Owned(entry.path.to_lowercase()),
+            false => Cow::Borrowed(entry.path.as_str()),
+        })
+        .collect();
+
+    let names = Names { ignore_case, precompose: precompose::enabled(config) };
+    let mut others = Vec::new();
+    untracked(root, "", &tracked, names, ignore.map(Cow::Owned), &mut others)?;
+    others.sort();
+    Ok(others)
+}
+
+/// How names read from a directory are compared with tracked paths.
+#[derive(Debug, Clone, Copy)]
+struct Names {
+    /// `tracked` holds lowercased paths.
+    ignore_case: bool,
+    /// Names are precomposed before they are compared.
+    precompose: bool,
+}
+
+/// Collects the untracked files below `dir`, whose path relative to the
+/// worktree is `base`. Untracked repositories are listed as `dir/`
+/// rather than descended into.
+fn untracked(
+    dir: &Path,
+    base: &str,
+    tracked: &HashSet<Cow<str>>,
+    names: Names,
+    mut ignore: Option<Cow<Ignore>>,
+    others: &mut Vec<String>,
+) -> anyhow::Result<()> {
+    if let Some(ignore) = &mut ignore {
+        if let Ok(content) = fs::read_to_string(dir.join(".gitignore")) {
+            ignore.to_mut().push_file(base, &content);
+        }
+    }
+
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let Some(name) = name.to_str() else { continue };
+        if name == ".git" {
+            continue;
+        }
+        let name = if names.precompose { precompose(name) } else { Cow::Borrowed(name) };
+
+        let path = format!("{}{}", base, name);
+        let is_dir = entry.file_type()?.is_dir();
+        if ignore.as_ref().is_some_and(|ignore| ignore.is_ignored(&path, is_dir)) {
+            continue;
+        }
+
+        let key = if names.ignore_case { Cow::Owned(path.to_lowercase()) } else { Cow::Borrowed(path.as_str()) };
+        if !is_dir {
+            if !tracked.contains(key.as_ref()) {
+                others.push(path);
+            }
+        } else if entry.path().join(".git").exists() {
+            if !tracked.contains(key.as_ref()) {
+                others.push(format!("{}/", path));
+            }
+        } else {
+            untracked(&entry.path(), &format!("{}/", path), tracked, names, ignore.clone(), others)?;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,624 @@
+use std::fs;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use anyhow::anyhow;
+
+/// How much of the subject to keep.
+#[derive(Debug, Default)]
+pub struct InfoOptions {
+    /// Keep the subject as it is (`-k`).
+    pub keep_subject: bool,
+    /// Only strip brackets containing `PATCH` (`-b`).
+    pub keep_non_patch_brackets: bool,
+    /// Append the `Message-Id` header to the message (`-m`).
+    pub message_id: bool,
+}
+
+/// What an emailed patch says about itself: the author and subject from
+/// its headers, or from headers at the top of the body, the commit
+/// message, and the patch after it.
+#[derive(Debug, Default)]
+pub struct MailInfo {
+    pub author: Option<String>,
+    pub email: Option<String>,
+    pub subject: Option<String>,
+    pub date: Option<String>,
+    pub message: Vec<u8>,
+    pub patch: Vec<u8>,
+}
+
+/// The headers mailinfo reports, from the mail or from the body.
+#[derive(Debug, Default)]
+struct Headers {
+    from: Option<String>,
+    subject: Option<String>,
+    date: Option<String>,
+}
+
+impl Headers {
+    /// Records `line` if it's one of the reported headers.
+    fn set(&mut self, line: &str) -> bool {
+        let Some((name, value)) = line.split_once(':') else {
+            return false;
+        };
+        let value = decode_words(value.trim_start());
+        let header = match name.to_ascii_lowercase().as_str() {
+            "from" => &mut self.from,
+            "subject" => &mut self.subject,
+            "date" => &mut self.date,
+            _ => return false,
+        };
+        *header = Some(value);
+        true
+    }
+}
+
+/// Where it is in the lines of a mail's body.
+#[derive(PartialEq)]
+enum Stage {
+    /// Before the message, where `From:`, `Subject:` and `Date:` lines
+    /// override the mail's headers.
+    Headers,
+    Message,
+    Patch,
+}
+
+/// Splits an email into its author, subject, message and patch, like
+/// `git mailinfo`.
+pub fn parse(mail: &[u8], options: &InfoOptions) -> anyhow::Result<MailInfo> {
+    let mail = &mail[mail.iter().position(|b| !b.is_ascii_whitespace()).unwrap_or(mail.len())..];
+    if mail.is_empty() {
+        return Err(anyhow!("Empty patch"));
+    }
+
+    let (headers, body) = split_headers(mail);
+    let mut mail_headers = Headers::default();
+    for line in &headers {
+        mail_headers.set(line);
+    }
+    let message_id = header(&headers, "message-id");
+    let body = decode_body(&headers, body);
+
+    let mut info = MailInfo::default();
+    let mut body_headers = Headers::default();
+    let mut pending = String::new();
+    let mut stage = Stage::Headers;
+    for line in body.split_inclusive(|&b| b == b'\n') {
+        if stage == Stage::Patch {
+            info.patch.extend_from_slice(line);
+            continue;
+        }
+
+        let text = String::from_utf8_lossy(line);
+        if stage == Stage::Headers {
+            if text.trim_end_matches('\n').is_empty() {
+                if !pending.is_empty() {
+                    body_headers.set(&std::mem::take(&mut pending));
+                    stage = Stage::Message;
+                }
+                continue;
+            }
+            if !pending.is_empty() && text.starts_with([' ', '\t']) {
+                pending.pop();
+                pending.push_str(&text);
+                continue;
+            }
+            if !pending.is_empty() {
+                body_headers.set(&std::mem::take(&mut pending));
+            }
+            if text.starts_with("[PATCH]") && text[7..].starts_with(char::is_whitespace) {
+                body_headers.subject = Some(text.to_string());
+                continue;
+            }
+            if is_body_header(&text) {
+                pending = text.to_string();
+                continue;
+            }
+            stage = Stage::Message;
+        }
+
+        if is_patch_break(line) {
+            if let (true, Some(id)) = (options.message_id, &message_id) {
+                info.message.extend_from_slice(format!("Message-Id: {}\n", id).as_bytes());
+            }
+            info.patch.extend_from_slice(line);
+            stage = Stage::Patch;
+            continue;
+        }
+        info.message.extend_from_slice(line);
+    }
+
+    // Headers in the body only count if there's a patch after them.
+    let use_body = !info.patch.is_empty();
+    let pick =
+        |body: &Option<String>, mail: &Option<String>| body.as_ref().filter(|_| use_body).or(mail.as_ref()).cloned();
+
+    if let Some(from) = pick(&body_headers.from, &mail_headers.from) {
+        let (name, email) = parse_from(&collapse_space(&from));
+        info.author = Some(name);
+        info.email = Some(email);
+    }
+    info.subject = pick(&body_headers.subject, &mail_headers.subject).map(|subject| {
+        if options.keep_subject {
+            subject
+        } else {
+            collapse_space(&clean_subject(&subject, options.keep_non_patch_brackets))
+        }
+    });
+    info.date = pick(&body_headers.date, &mail_headers.date).map(|date| collapse_space(&date));
+
+    Ok(info)
+}
+
+/// Splits the header lines, unfolded, from the body. The `From ` line of
+/// an mbox isn't a header.
+fn split_headers(mail: &[u8]) -> (Vec<String>, &[u8]) {
+    let mut headers: Vec<String> = Vec::new();
+    let mut rest = mail;
+    if rest.starts_with(b"From ") {
+        rest = rest.iter().position(|&b| b == b'\n').map_or(&[][..], |end| &rest[end + 1..]);
+    }
+
+    while !rest.is_empty() {
+        let end = rest.iter().position(|&b| b == b'\n').map_or(rest.len(), |end| end + 1);
+        let line = String::from_utf8_lossy(&rest[..end]);
+        let line = line.trim_end_matches(['\n', '\r']);
+        if line.is_empty() {
+            return (headers, &rest[end..]);
+        }
+        match headers.last_mut() {
+            Some(last) if line.starts_with([' ', '\t']) => last.push_str(line),
+            _ if is_header(line) => headers.push(line.to_string()),
+            // Not a header: the body starts here.
+            _ => return (headers, rest),
+        }
+        rest = &rest[end..];
+    }
+
+    (headers, rest)
+}
+
+fn is_header(line: &str) -> bool {
+    match line.split_once(':') {
+        Some((name, _)) => name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-'),
+        None => false,
+    }
+}
+
+fn is_body_header(line: &str) -> bool {
+    ["from:", "subject:", "date:"]
+        .iter()
+        .any(|name| line.get(..name.len()).is_some_and(|start| start.eq_ignore_ascii_case(name)))
+}
+
+/// The value of the first header called `name`.
+fn header(headers: &[String], name: &str) -> Option<String> {
+    headers.iter().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        key.eq_ignore_ascii_case(name).then(|| value.trim().to_string())
+    })
+}
+
+/// A parameter such as `charset` from a `Content-Type` value.
+fn parameter(value: &str, name: &str) -> Option<String> {
+    value.split(';').skip(1).find_map(|parameter| {
+        let (key, value) = parameter.split_once('=')?;
+        key.trim().eq_ignore_ascii_case(name).then(|| value.trim().trim_matches('"').to_string())
+    })
+}
+
+/// The body decoded from its transfer encoding and charset, with the
+/// parts of a multipart message one after another.
+fn decode_body(headers: &[String], body: &[u8]) -> Vec<u8> {
+    let content_type = header(headers, "content-type").unwrap_or_default();
+    let encoding = header(headers, "content-transfer-encoding").unwrap_or_default().to_ascii_lowercase();
+
+    if content_type.to_ascii_lowercase().starts_with("multipart/") {
+        if let Some(boundary) = parameter(&content_type, "boundary") {
+            let delimiter = format!("--{}", boundary);
+            let mut decoded = Vec::new();
+            let mut part: Option<Vec<u8>> = None;
+            for line in body.split_inclusive(|&b| b == b'\n') {
+                let text = String::from_utf8_lossy(line);
+                let text = text.trim_end();
+                if text == delimiter || text == format!("{}--", delimiter) {
+                    if let Some(part) = part.take() {
+                        let (part_headers, part_body) = split_headers(&part);
+                        decoded.extend(decode_body(&part_headers, part_body));
+                    }
+                    if text == delimiter {
+                        part = Some(Vec::new());
+                    } else {
+                        // Like git, the closing boundary ends with a line break of its own.
+                        decoded.push(b'\n');
+                    }
+                    continue;
+                }
+                // The preamble before the first part is left out.
+                if let Some(part) = &mut part {
+                    part.extend_from_slice(line);
+                }
+            }
+            return decoded;
+        }
+    }
+
+    let decoded = match encoding.as_str() {
+        "quoted-printable" => decode_quoted_printable(body, false),
+        "base64" => decode_base64(body),
+        _ => body.to_vec(),
+    };
+    to_utf8(&decoded, parameter(&content_type, "charset").as_deref())
+}
+
+fn to_utf8(bytes: &[u8], charset: Option<&str>) -> Vec<u8> {
+    match charset.map(str::to_ascii_lowercase).as_deref() {
+        Some("iso-8859-1" | "latin1" | "latin-1") => bytes.iter().map(|&b| b as char).collect::<String>().into_bytes(),
+        _ => bytes.to_vec(),
+    }
+}
+
+fn decode_quoted_printable(text: &[u8], in_header: bool) -> Vec<u8> {
+    let mut decoded = Vec::new();
+    let mut i = 0;
+    while i < text.len() {
+        match text[i] {
+            b'=' => {
+                let hex = text.get(i + 1..i + 3).and_then(|hex| std::str::from_utf8(hex).ok());
+                if let Some(byte) = hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                    decoded.push(byte);
+                    i += 3;
+                    continue;
+                }
+                // A soft line break joins the next line on.
+                let rest = &text[i + 1..];
+                let spaces = rest.iter().take_while(|&&b| b == b' ' || b == b'\t' || b == b'\r').count();
+                if rest.get(spaces) == Some(&b'\n') {
+                    i += spaces + 2;
+                    continue;
+                }
+                decoded.push(b'=');
+            }
+            b'_' if in_header => decoded.push(b' '),
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+
+    decoded
+}
+
+fn decode_base64(text: &[u8]) -> Vec<u8> {
+    let value = |b: u8| match b {
+        b'A'..=b'Z' => Some(b - b'A'),
+        b'a'..=b'z' => Some(b - b'a' + 26),
+        b'0'..=b'9' => Some(b - b'0' + 52),
+        b'+' => Some(62),
+        b'/' => Some(63),
+        _ => None,
+    };
+
+    let mut decoded = Vec::new();
+    let (mut bits, mut count) = (0u32, 0);
+    for sixbits in text.iter().take_while(|&&b| b != b'=').filter_map(|&b| value(b)) {
+        bits = bits << 6 | sixbits as u32;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            decoded.push((bits >> count) as u8);
+        }
+    }
+
+    decoded
+}
+
+/// Decodes RFC 2047 encoded words such as `=?UTF-8?q?caf=C3=A9?=`,
+/// dropping the whitespace between adjacent ones.
+fn decode_words(value: &str) -> String {
+    let mut decoded = String::new();
+    let mut rest = value;
+    let mut after_word = false;
+
+    while let Some(start) = rest.find("=?") {
+        let word = rest[start + 2..].splitn(3, '?').collect::<Vec<_>>();
+        let [charset, encoding, text] = word[..] else {
+            break;
+        };
+        let Some(end) = text.find("?=") else {
+            break;
+        };
+        let bytes = match encoding {
+            "q" | "Q" => decode_quoted_printable(&text.as_bytes()[..end], true),
+            "b" | "B" => decode_base64(&text.as_bytes()[..end]),
+            _ => break,
+        };
+
+        let between = &rest[..start];
+        if !(after_word && between.trim().is_empty()) {
+            decoded.push_str(between);
+        }
+        decoded.push_str(&String::from_utf8_lossy(&to_utf8(&bytes, Some(charset))));
+        after_word = true;
+        let consumed = start + 2 + charset.len() + 1 + encoding.len() + 1 + end + 2;
+        rest = &rest[consumed..];
+    }
+    decoded.push_str(rest);
+
+    decoded
+}
+
+/// Whether the patch starts at `line`: a diff header, or a `---` line.
+fn is_patch_break(line: &[u8]) -> bool {
+    if line.starts_with(b"diff -") || line.starts_with(b"Index: ") {
+        return true;
+    }
+    if line.len() < 4 || !line.starts_with(b"---") {
+        return false;
+    }
+    // `--- <file>` starts a bare patch, and `---` alone separates one.
+    if line[3] == b' ' && !line[4].is_ascii_whitespace() {
+        return true;
+    }
+    line[3..].iter().take_while(|&&b| b != b'\n').all(|b| b.is_ascii_whitespace())
+}
+
+/// Turns each run of whitespace into a single space.
+fn collapse_space(value: &str) -> String {
+    let mut collapsed = String::new();
+    for c in value.chars() {
+        if c.is_ascii_whitespace() {
+            if !collapsed.ends_with(' ') {
+                collapsed.push(' ');
+            }
+        } else {
+            collapsed.push(c);
+        }
+    }
+    collapsed
+}
+
+/// Strips `Re:` and `[PATCH ...]` style prefixes from a subject, or with
+/// `keep_non_patch_brackets` only brackets mentioning `PATCH`.
+fn clean_subject(subject: &str, keep_non_patch_brackets: bool) -> String {
+    let mut subject = subject.to_string();
+    let mut at = 0;
+
+    loop {
+        let rest = &subject[at..];
+        if rest.len() > 3 && rest[..3].eq_ignore_ascii_case("re:") {
+            subject.replace_range(at..at + 3, "");
+        } else if rest.starts_with([' ', '\t', ':']) {
+            subject.replace_range(at..at + 1, "");
+        } else if rest.starts_with('[') {
+            let Some(end) = rest.find(']') else {
+                break;
+            };
+            let bracket = &rest[..=end];
+            if !keep_non_patch_brackets || (bracket.len() >= 7 && bracket.contains("PATCH")) {
+                subject.replace_range(at..=at + end, "");
+            } else {
+                at += end + 1;
+                if subject[at..].starts_with(char::is_whitespace) {
+                    at += 1;
+                }
+            }
+        } else {
+            break;
+        }
+    }
+
+    subject.trim().to_string()
+}
+
+/// Removes the quotes and backslash escapes of quoted strings in a
+/// `From` header, keeping comments in their parentheses.
+fn unquote(value: &str) -> String {
+    let mut unquoted = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' => unquoted.extend(chars.next()),
+                        c => unquoted.push(c),
+                    }
+                }
+            }
+            '(' => {
+                unquoted.push('(');
+                let mut depth = 1;
+                while let Some(c) = chars.next() {
+                    match c {
+                        '\\' => {
+                            unquoted.extend(chars.next());
+                            continue;
+                        }
+                        '(' => depth += 1,
+                        ')' => {
+                            depth -= 1;
+                            if depth == 0 {
+                                break;
+                            }
+                        }
+                        _ => {}
+                    }
+                    unquoted.push(c);
+                }
+                unquoted.push(')');
+            }
+            c => unquoted.push(c),
+        }
+    }
+    unquoted
+}
+
+/// Splits a `From` value such as `A U Thor <author@example.com>` or
+/// `author@example.com (A U Thor)` into a name and email. A name that
+/// looks wrong is replaced with the email.
+fn parse_from(from: &str) -> (String, String) {
+    let sane_name = |name: &str, email: &str| {
+        if name.is_empty() || name.len() > 60 || name.contains(['@', '<', '>']) {
+            email.to_string()
+        } else {
+            name.to_string()
+        }
+    };
+
+    let mut value = unquote(from);
+    let Some(at) = value.find('@') else {
+        // `A U Thor <author>`, without a domain.
+        return match value.find('<').and_then(|start| Some((start, start + value[start..].find('>')?))) {
+            Some((start, end)) => {
+                let email = value[start + 1..end].to_string();
+                (sane_name(value[..start].trim(), &email), email)
+            }
+            None => (String::new(), String::new()),
+        };
+    };
+
+    let mut start = at;
+    while start > 0 {
+        let c = value.as_bytes()[start - 1];
+        if c.is_ascii_whitespace() {
+            break;
+        }
+        if c == b'<' {
+            value.replace_range(start - 1..start, " ");
+            break;
+        }
+        start -= 1;
+    }
+    let length = value[start..].find([' ', '\n', '\t', '\r', '\x0b', '\x0c', '>']).unwrap_or(value.len() - start);
+    let email = value[start..start + length].to_string();
+    let end = (start + length + 1).min(value.len());
+    value.replace_range(start..end, "");
+
+    let name = collapse_space(&value);
+    let mut name = name.trim();
+    if name.len() >= 2 && name.starts_with('(') && name.ends_with(')') {
+        name = &name[1..name.len() - 1];
+    }
+
+    (sane_name(name, &email), email)
+}
+
+/// Reads a mail from stdin, writes its message to `message_path` and its
+/// patch to `patch_path`, and prints its author, subject and date.
+pub fn run(message_path: &Path, patch_path: &Path, options: &InfoOptions) -> anyhow::Result<()> {
+    let mut mail = Vec::new();
+    std::io::stdin().lock().read_to_end(&mut mail)?;
+    let info = parse(&mail, options)?;
+
+    fs::write(message_path, &info.message)?;
+    fs::write(patch_path, &info.patch)?;
+
+    let mut out = std::io::stdout().lock();
+    if let (Some(author), Some(email)) = (&info.author, &info.email) {
+        writeln!(out, "Author: {}", author)?;
+        writeln!(out, "Email: {}", email)?;
+    }
+    // A subject kept as it is can span lines.
+    if let Some(subject) = &info.subject {
+        for line in subject.split('\n') {
+            writeln!(out, "Subject: {}", line)?;
+        }
+    }
+    if let Some(date) = &info.date {
+        writeln!(out, "Date: {}", date)?;
+    }
+    writeln!(out)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAIL: &[u8] = b"From 1234 Mon Sep 17 00:00:00 2001
+From: =?UTF-8?q?Ren=C3=A9?= Thor <rene@example.com>
+Date: Thu, 7 Apr 2005 15:13:13 -0700
+Subject: [PATCH 1/2] Re: fix the
+ frobnicator
+Message-Id: <123@example.com>
+
+Body text here.
+
+---
+ file | 1 +
+diff --git a/file b/file
+";
+
+    #[test]
+    fn headers_message_and_patch() {
+        let info = parse(MAIL, &InfoOptions::default()).unwrap();
+        assert_eq!(info.author.as_deref(), Some("René Thor"));
+        assert_eq!(info.email.as_deref(), Some("rene@example.com"));
+        assert_eq!(info.subject.as_deref(), Some("fix the frobnicator"));
+        assert_eq!(info.date.as_deref(), Some("Thu, 7 Apr 2005 15:13:13 -0700"));
+        assert_eq!(info.message, b"Body text here.\n\n");
+        assert_eq!(info.patch, b"---\n file | 1 +\ndiff --git a/file b/file\n");
+
+        let options = InfoOptions { message_id: true, ..Default::default() };
+        let info = parse(MAIL, &options).unwrap();
+        assert_eq!(info.message, b"Body text here.\n\nMessage-Id: <123@example.com>\n");
+
+        let options = InfoOptions { keep_subject: true, ..Default::default() };
+        let info = parse(MAIL, &options).unwrap();
+        assert_eq!(info.subject.as_deref(), Some("[PATCH 1/2] Re: fix the frobnicator"));
+
+        assert!(parse(b" \n\n", &InfoOptions::default()).is_err());
+    }
+
+    #[test]
+    fn body_headers_and_quoted_printable() {
+        let mail = b"From: a@b.c
+Subject: [RFC] [PATCH] x
+Content-Transfer-Encoding: quoted-printable
+
+From: Real Author <real@example.com>
+Subject: Real subject
+
+caf=C3=A9 =
+line
+---
+diff
+";
+        let info = parse(mail, &InfoOptions::default()).unwrap();
+        assert_eq!(info.author.as_deref(), Some("Real Author"));
+        assert_eq!(info.email.as_deref(), Some("real@example.com"));
+        assert_eq!(info.subject.as_deref(), Some("Real subject"));
+        assert_eq!(String::from_utf8_lossy(&info.message), "café line\n");
+        assert_eq!(info.patch, b"---\ndiff\n");
+
+        // Without a patch, the headers in the body are just message.
+        let info = parse(b"From: a@b.c\nSubject: s\n\nFrom: x@y.z\n\nbody\n", &InfoOptions::default()).unwrap();
+        assert_eq!(info.email.as_deref(), Some("a@b.c"));
+    }
+
+    #[test]
+    fn subjects() {
+        assert_eq!(clean_subject("Re: [PATCH v2 3/4] [RFC] x", false), "x");
+        assert_eq!(clean_subject("[RFC] [PATCH] x", true), "[RFC] x");
+        assert_eq!(collapse_space("a \t b\n c"), "a b c");
+    }
+
+    #[test]
+    fn authors() {
+        assert_eq!(parse_from("A U Thor <author@example.com>"), ("A U Thor".into(), "author@example.com".into()));
+        assert_eq!(parse_from("j@d.e (J Doe)"), ("J Doe".into(), "j@d.e".into()));
+        assert_eq!(parse_from("\"Doe, J\\\"r\" <j@d.e>"), ("Doe, J\"r".into(), "j@d.e".into()));
+        assert_eq!(parse_from("a@b.c"), ("a@b.c".into(), "a@b.c".into()));
+    }
+
+    #[test]
+    fn encodings() {
+        assert_eq!(decode_words("=?UTF-8?q?caf=C3=A9?= =?UTF-8?B?w6k=?= x"), "caféé x");
+        assert_eq!(decode_quoted_printable(b"a=3Db=\nc", false), b"a=bc");
+        assert_eq!(decode_quoted_printable(b"a_b", true), b"a b");
+        assert_eq!(decode_base64(b"aGVs\nbG8="), b"hello");
+    }
+}
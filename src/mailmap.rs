@@ -0,0 +1,98 @@
+use std::fs;
+use std::path::Path;
+
+/// One line of a `.mailmap` file, see gitmailmap(5).
+#[derive(Debug)]
+struct Entry {
+    proper_name: Option<String>,
+    proper_email: Option<String>,
+    commit_name: Option<String>,
+    commit_email: String,
+}
+
+/// Maps the identities recorded in commits to canonical ones.
+#[derive(Debug, Default)]
+pub struct Mailmap {
+    entries: Vec<Entry>,
+}
+
+impl Mailmap {
+    /// Loads `.mailmap` from the worktree root plus the file named by
+    /// `mailmap.file`, whose entries take precedence.
+    pub fn load(mailmap_file: Option<&str>) -> anyhow::Result<Mailmap> {
+        let mut mailmap = Mailmap::default();
+        for path in std::iter::once(".mailmap").chain(mailmap_file) {
+            match fs::read_to_string(Path::new(path)) {
+                Ok(content) => mailmap.add(&content),
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        Ok(mailmap)
+    }
+
+    pub fn add(&mut self, content: &str) {
+        for line in content.lines() {
+            let line = line.split('#').next().unwrap_or("");
+            if let Some(entry) = parse_line(line) {
+                self.entries.push(entry);
+            }
+        }
+    }
+
+    /// Returns the canonical name and email for an identity. Entries that
+    /// also match the commit name win over email-only ones, later entries
+    /// win over earlier ones.
+    pub fn lookup<'a>(&'a self, name: &'a str, email: &'a str) -> (&'a str, &'a str) {
+        let matching = |entry: &&Entry| entry.commit_email.eq_ignore_ascii_case(email);
+        let entry = self.entries.iter()
+            .rev()
+            .filter(matching)
+            .find(|entry| entry.commit_name.as_ref().is_some_and(|commit_name| commit_name.eq_ignore_ascii_case(name)))
+            .or_else(|| self.entries.iter().rev().filter(matching).find(|entry| entry.commit_name.is_none()));
+
+        match entry {
+            Some(entry) => (
+                entry.proper_name.as_deref().unwrap_or(name),
+                entry.proper_email.as_deref().unwrap_or(email),
+            ),
+            None => (name, email),
+        }
+    }
+}
+
+/// Splits `Name <email>` at its first angle-bracketed email, returning the
+/// trimmed name (if any), the email and the rest of the line.
+pub fn split_ident(text: &str) -> Option<(Option<&str>, &str, &str)> {
+    let start = text.find('<')?;
+    let end = start + text[start..].find('>')?;
+    let name = text[..start].trim();
+
+    Some((
+        if name.is_empty() { None } else { Some(name) },
+        &text[start + 1..end],
+        &text[end + 1..],
+    ))
+}
+
+fn parse_line(line: &str) -> Option<Entry> {
+    let (proper_name, first_email, rest) = split_ident(line)?;
+
+    match split_ident(rest) {
+        // `Proper Name <proper@email> [Commit Name] <commit@email>`
+        Some((commit_name, commit_email, _)) => Some(Entry {
+            proper_name: proper_name.map(str::to_string),
+            proper_email: Some(first_email.to_string()),
+            commit_name: commit_name.map(str::to_string),
+            commit_email: commit_email.to_string(),
+        }),
+        // `Proper Name <commit@email>`
+        None => Some(Entry {
+            proper_name: Some(proper_name?.to_string()),
+            proper_email: None,
+            commit_name: None,
+            commit_email: first_email.to_string(),
+        }),
+    }
+}
@@ -0,0 +1,132 @@
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+use anyhow::anyhow;
+
+#[derive(Debug, Clone)]
+pub struct SplitOptions {
+    /// Accept a mailbox that doesn't start with a `From ` line, as one
+    /// mail (`-b`).
+    pub allow_bare: bool,
+    /// Digits in the names of the files written.
+    pub precision: usize,
+    /// The number before the first file's.
+    pub skip: usize,
+    /// Keep `\r` before line ends.
+    pub keep_cr: bool,
+    /// Unescape `>From ` lines, for mailboxes in mboxrd format.
+    pub mboxrd: bool,
+}
+
+impl Default for SplitOptions {
+    fn default() -> SplitOptions {
+        SplitOptions { allow_bare: false, precision: 4, skip: 0, keep_cr: false, mboxrd: false }
+    }
+}
+
+/// Whether `line` separates mails in an mbox: `From ` followed by an
+/// address and something that looks like a date with a time.
+fn is_from_line(line: &[u8]) -> bool {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    if line.len() < 19 || !line.starts_with(b"From ") {
+        return false;
+    }
+    let Some(colon) = line[5..line.len() - 1].iter().rposition(|&b| b == b':').map(|colon| colon + 5) else {
+        return false;
+    };
+    let digit = |i: usize| line.get(i).is_some_and(u8::is_ascii_digit);
+    if colon < 4
+        || !digit(colon - 4)
+        || !digit(colon - 2)
+        || !digit(colon - 1)
+        || !digit(colon + 1)
+        || !digit(colon + 2)
+    {
+        return false;
+    }
+
+    // After the minutes (and maybe seconds) comes a year.
+    let year: String = line[colon + 3..]
+        .iter()
+        .map(|&b| b as char)
+        .skip_while(|c| c.is_ascii_whitespace())
+        .take_while(char::is_ascii_digit)
+        .collect();
+    year.parse::<u32>().is_ok_and(|year| year > 90)
+}
+
+/// `>From `, `>>From ` and so on, escaped by mboxrd writers.
+fn is_escaped_from(line: &[u8]) -> bool {
+    let quotes = line.iter().take_while(|&&b| b == b'>').count();
+    quotes > 0 && line[quotes..].starts_with(b"From ")
+}
+
+/// Splits the mailbox `mbox` into one file per mail in `directory`, named
+/// by number from `skip + 1`, and returns how many were written.
+pub fn split(mbox: &[u8], directory: &Path, options: &SplitOptions) -> anyhow::Result<usize> {
+    let start = mbox.iter().position(|b| !b.is_ascii_whitespace()).ok_or(anyhow!("Empty mbox"))?;
+    let mut lines = mbox[start..].split_inclusive(|&b| b == b'\n').peekable();
+
+    let mut count = 0;
+    while let Some(first) = lines.next() {
+        let bare = !is_from_line(first);
+        if bare && !options.allow_bare {
+            return Err(anyhow!("Corrupt mailbox"));
+        }
+
+        let mut mail = Vec::new();
+        let mut line = first;
+        loop {
+            let unix_line;
+            let mut text = line;
+            if !options.keep_cr && text.ends_with(b"\r\n") {
+                unix_line = [&text[..text.len() - 2], b"\n"].concat();
+                text = &unix_line;
+            }
+            if options.mboxrd && is_escaped_from(text) {
+                text = &text[1..];
+            }
+            mail.extend_from_slice(text);
+
+            match lines.peek() {
+                // A bare mail runs to the end.
+                Some(next) if bare || !is_from_line(next) => line = lines.next().unwrap(),
+                _ => break,
+            }
+        }
+
+        count += 1;
+        let name = format!("{:0width$}", options.skip + count, width = options.precision);
+        fs::write(directory.join(name), mail)?;
+    }
+
+    Ok(count)
+}
+
+/// Splits each mailbox in `paths`, or stdin if there are none, into
+/// `directory` and prints how many mails there were.
+pub fn run(paths: &[String], directory: &Path, options: &SplitOptions) -> anyhow::Result<()> {
+    let mut options = options.clone();
+    let mut total = 0;
+
+    let mut mailboxes = Vec::new();
+    if paths.is_empty() || paths == ["-"] {
+        let mut mbox = Vec::new();
+        std::io::stdin().lock().read_to_end(&mut mbox)?;
+        mailboxes.push(mbox);
+    } else {
+        for path in paths {
+            mailboxes.push(fs::read(path).map_err(|err| anyhow!("Could not read {}: {}", path, err))?);
+        }
+    }
+
+    for mbox in mailboxes {
+        let count = split(&mbox, directory, &options)?;
+        options.skip += count;
+        total += count;
+    }
+    println!("{}", total);
+
+    Ok(())
+}
@@ -1,233 +1,1086 @@
-extern crate core;
-
-use std::fmt::Write as _;
-use std::fs;
-use std::io::{BufRead, BufReader, Read, Write};
-use std::path::PathBuf;
+use std::borrow::Cow;
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 
 use anyhow::anyhow;
-use bytes::{BufMut, BytesMut};
 use clap::{Arg, ArgAction, ArgMatches, Command};
-use flate2::read::ZlibDecoder;
-use flate2::write::ZlibEncoder;
-
-use crate::sha1hash::Sha1Hash;
 
-mod sha1hash;
+use git_starter_rust::attributes::Attributes;
+use git_starter_rust::convert::Convert;
+use git_starter_rust::error::{self, Error};
+use git_starter_rust::mailmap::{self, Mailmap};
+use git_starter_rust::object_id::HashAlgorithm;
+use git_starter_rust::objects::{self, Object, TreeOptions};
+use git_starter_rust::repository::{self, worktree_path};
+use git_starter_rust::trace::TRACE;
+use git_starter_rust::upload_pack::UploadPack;
+use git_starter_rust::{
+    alias, bisect, branch, browse, bundle, cat_file, cherry, color, commit_graph, completion, config, copy_objects,
+    credential, date, diff, export_html, fast_export, fast_import, help, ident, json, lfs, log, ls_files, mailinfo,
+    mailsplit, maintenance, name_rev, notes, pager, profile, quote, range_diff, reflog, refs, remote, repack, replace,
+    request_pull, rev_list, rewrite_history, show_branch, sparse, stats, submodule, trace, trailers, update_index,
+    update_ref, verbosity, verify_objects, worktree, ObjectId, Repository,
+};
+#[cfg(unix)]
+use git_starter_rust::credential_cache;
 
 #[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    match get_matches().subcommand() {
-        Some(("init", _)) => {
-            fs::create_dir(".git")?;
-            fs::create_dir(".git/objects")?;
-            fs::create_dir(".git/refs")?;
-            fs::write(".git/HEAD", "ref: refs/heads/main\n")?;
-            println!("Initialized git directory")
+async fn main() -> ExitCode {
+    trace!(TRACE, "built-in: git {}", std::env::args().skip(1).collect::<Vec<_>>().join(" "));
+
+    let cli = cli();
+    let is_builtin = |name: &str| name == "help" || cli.find_subcommand(name).is_some();
+    let args = match alias::expand(std::env::args().collect(), is_builtin) {
+        Ok(alias::Expansion::Args(args)) => args,
+        Ok(alias::Expansion::Shell(status)) => return ExitCode::from(status),
+        Err(err) => return error::report(&err),
+    };
+
+    let matches = match cli.try_get_matches_from(args) {
+        Ok(matches) => matches,
+        Err(err) => {
+            // `--help` and `--version` are reported as errors by clap too.
+            let _ = err.print();
+            return if err.use_stderr() { ExitCode::from(129) } else { ExitCode::SUCCESS };
+        }
+    };
+
+    let result = run(&matches);
+    profile::report();
+    let code = match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => error::report(&err),
+    };
+    pager::finish();
+    code
+}
+
+fn run(matches: &ArgMatches) -> anyhow::Result<()> {
+    if matches.get_flag("help-all") {
+        print!("{}", help::render_all(cli(), &bin_name()?));
+        return Ok(());
+    }
+    let (quiet, verbose) = verbosity(matches);
+    verbosity::set(quiet, verbose);
+    if matches.get_flag("profile") {
+        profile::enable();
+    }
+    // Like git, pass the option on to everything we run, hooks included.
+    if matches.get_flag("no-replace-objects") {
+        std::env::set_var("GIT_NO_REPLACE_OBJECTS", "1");
+    }
+    start_pager(matches)?;
+
+    match matches.subcommand() {
+        Some(("init", init_matches)) => {
+            let directory = init_matches.get_one::<String>("directory")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| PathBuf::from("."));
+            let bare = init_matches.get_flag("bare");
+            let initial_branch = init_matches.get_one::<String>("initial-branch")
+                .map(String::as_str);
+            let format = init_matches.get_one::<String>("object-format")
+                .map(|name| HashAlgorithm::from_name(name))
+                .transpose()?;
+            let ref_format = init_matches.get_one::<String>("ref-format")
+                .map(|name| refs::RefFormat::from_name(name))
+                .transpose()?;
+
+            let template = init_matches.get_one::<String>("template").map(Path::new);
+            let (_, reinit) = Repository::init(&directory, bare, initial_branch, format, ref_format, template)?;
+            if verbosity::quiet() {
+            } else if reinit {
+                println!("Reinitialized existing git directory");
+            } else {
+                println!("Initialized git directory");
+            }
         }
         Some(("cat-file", cat_file_matches)) => {
-            let blob_sha: Sha1Hash = cat_file_matches.get_one::<String>("blob_sha")
+            let batch = cat_file_matches.get_one::<String>("batch");
+            let batch_check = cat_file_matches.get_one::<String>("batch-check");
+            if batch.is_some() || batch_check.is_some() {
+                let options = cat_file::BatchOptions {
+                    contents: batch.is_some(),
+                    format: batch.or(batch_check).filter(|format| !format.is_empty()).cloned(),
+                    all_objects: cat_file_matches.get_flag("batch-all-objects"),
+                    unordered: cat_file_matches.get_flag("unordered"),
+                    json: matches.get_flag("json"),
+                };
+                return cat_file::batch(&options);
+            }
+            if cat_file_matches.get_flag("batch-all-objects") {
+                let usage = "git cat-file --batch-all-objects needs --batch or --batch-check";
+                return Err(Error::Usage(usage.to_string()).into());
+            }
+
+            let sha: ObjectId = cat_file_matches.get_one::<String>("blob_sha")
                 .expect("Blob SHA is required")
                 .parse()?;
-            let filename = filename_from_sha(&blob_sha)?;
-            let file = fs::File::open(filename)?;
-            let decoder = ZlibDecoder::new(file);
-            let mut reader = BufReader::new(decoder);
-
-            let mut buf = Vec::new();
-            reader.read_until(0, &mut buf)?;
 
-            let text = std::str::from_utf8(&buf[..buf.len() - 1])?;
-            let text = text.strip_prefix("blob ").ok_or(anyhow!("Invalid blob"))?;
-            let size: usize = text.parse()?;
-            buf.resize(size, 0);
-            reader.read_exact(&mut buf)?;
+            let repository = repository::current()?;
+            let (kind, size) = repository.object_header(&sha)?;
+            if kind == "blob" && size > repository.big_file_threshold()? {
+                repository.stream_object(&sha, &mut std::io::stdout().lock())?;
+                return Ok(());
+            }
 
-            let content = std::str::from_utf8(&buf)?;
-            print!("{}", content);
+            match repository.read(&sha)? {
+                Object::Tree(tree) => {
+                    for entry in tree.entries {
+                        println!("{:06o} {} {}\t{}", entry.mode, entry.kind(), entry.sha, entry.name);
+                    }
+                }
+                object => std::io::stdout().write_all(&object.serialize())?,
+            }
         }
         Some(("hash-object", hash_object_matches)) => {
             let filename = hash_object_matches.get_one::<String>("file")
                 .expect("File argument is required")
                 .as_str();
             let write_to_file = hash_object_matches.get_flag("write");
+            let kind = hash_object_matches.get_one::<String>("type").map_or("blob", String::as_str);
+            let literally = hash_object_matches.get_flag("literally");
+            if kind != "blob" || literally {
+                println!("{}", objects::hash_typed_object(&filename.into(), kind, write_to_file, literally)?);
+                return Ok(());
+            }
+
+            let path = worktree_path(Path::new(filename));
+            let directory = path.rsplit_once('/').map(|(directory, _)| directory).unwrap_or("");
+            let repository = repository::current()?;
+            let attributes = Attributes::load_for(repository.git_dir(), directory)?;
+            let convert = Convert::from_config(&repository.config()?)?;
 
-            let blob_sha = hash_object(&filename.into(), write_to_file)?;
+            let blob_sha = objects::hash_object(&filename.into(), write_to_file, &attributes, &convert)?;
             println!("{}", blob_sha);
         }
+        Some(("diff", diff_matches)) => {
+            let paths: Vec<&String> = diff_matches.get_many::<String>("paths").unwrap_or_default().collect();
+            // Like git, outside a repository there is nothing else to compare.
+            if !diff_matches.get_flag("no-index") && repository::current().is_ok() {
+                return Err(anyhow!("Only diff --no-index is supported"));
+            }
+            let [old, new] = paths[..] else {
+                return Err(Error::Usage("git diff --no-index [<options>] <path> <path>".to_string()).into());
+            };
+            let options = diff::DiffOptions {
+                context: diff::Context {
+                    lines: diff_matches.get_one::<usize>("unified").copied().unwrap_or(diff::DEFAULT_CONTEXT),
+                    inter_hunk: diff_matches.get_one::<usize>("inter-hunk-context").copied().unwrap_or(0),
+                    function: diff_matches.get_flag("function-context"),
+                },
+                quiet: diff_matches.get_flag("quiet"),
+                ext_diff: !diff_matches.get_flag("no-ext-diff"),
+                color: color_when(diff_matches),
+            };
+
+            if diff::no_index(Path::new(old), Path::new(new), &options)? {
+                return Err(Error::Status(1).into());
+            }
+        }
+        Some(("rev-list", rev_list_matches)) => {
+            let args: Vec<String> = rev_list_matches.get_many::<String>("args").unwrap_or_default().cloned().collect();
+            rev_list::run(&args)?;
+        }
+        Some(("range-diff", range_diff_matches)) => {
+            let ranges: Vec<String> =
+                range_diff_matches.get_many::<String>("ranges").unwrap_or_default().cloned().collect();
+            let creation_factor = range_diff_matches.get_one::<usize>("creation-factor").copied();
+            let no_patch = range_diff_matches.get_flag("no-patch");
+            range_diff::run(&ranges, creation_factor.unwrap_or(range_diff::DEFAULT_CREATION_FACTOR), no_patch)?;
+        }
+        Some(("cherry", cherry_matches)) => {
+            let arg = |name: &str| cherry_matches.get_one::<String>(name).map(String::as_str);
+            cherry::run(arg("upstream"), arg("head"), arg("limit"), cherry_matches.get_flag("verbose"))?;
+        }
+        Some(("log", log_matches)) => {
+            let args: Vec<String> = log_matches.get_many::<String>("args").unwrap_or_default().cloned().collect();
+            log::run(&args)?;
+        }
+        Some(("update-index", update_matches)) => {
+            let flag = |set: &str, unset: &str| {
+                if update_matches.get_flag(set) {
+                    Some(true)
+                } else if update_matches.get_flag(unset) {
+                    Some(false)
+                } else {
+                    None
+                }
+            };
+            let paths: Vec<String> = update_matches.get_many::<String>("paths").unwrap_or_default().cloned().collect();
+
+            update_index::mark(
+                &paths,
+                flag("assume-unchanged", "no-assume-unchanged"),
+                flag("skip-worktree", "no-skip-worktree"),
+            )?;
+        }
+        Some(("ls-files", ls_files_matches)) => {
+            let options = ls_files::ListOptions {
+                cached: ls_files_matches.get_flag("cached"),
+                others: ls_files_matches.get_flag("others"),
+                modified: ls_files_matches.get_flag("modified"),
+                deleted: ls_files_matches.get_flag("deleted"),
+                exclude_standard: ls_files_matches.get_flag("exclude-standard"),
+                null_terminated: ls_files_matches.get_flag("z"),
+                tags: ls_files_matches.get_flag("t"),
+                verbose: ls_files_matches.get_flag("v"),
+                stage: ls_files_matches.get_flag("stage"),
+                unmerged: ls_files_matches.get_flag("unmerged"),
+            };
+            let paths: Vec<String> = ls_files_matches.get_many::<String>("paths").unwrap_or_default().cloned().collect();
+
+            ls_files::list(&options, &paths)?;
+        }
         Some(("ls-tree", ls_tree_matches)) => {
-            let tree_sha: Sha1Hash = ls_tree_matches.get_one::<String>("tree_sha")
+            let tree_sha: ObjectId = ls_tree_matches.get_one::<String>("tree_sha")
                 .expect("Tree SHA is required")
                 .parse()?;
             let name_only = ls_tree_matches.get_flag("name-only");
+            let null_terminated = ls_tree_matches.get_flag("z");
+            let repository = repository::current()?;
+            let quote_non_ascii = match repository.config()?.get("core.quotepath") {
+                Some(value) => config::parse_bool(value)
+                    .ok_or(anyhow!("Bad core.quotePath value: {}", value))?,
+                None => true,
+            };
 
-            let filename = filename_from_sha(&tree_sha)?;
-            let file = fs::File::open(filename)?;
-            let decoder = ZlibDecoder::new(file);
-            let mut buf_reader = BufReader::new(decoder);
-
-            let mut buf = Vec::new();
-            let read = buf_reader.read_until(0, &mut buf)?;
-            let str = std::str::from_utf8(&buf[..read - 1])?;
-            let str = str.strip_prefix("tree ").ok_or(anyhow!("Invalid tree"))?;
-            let size: usize = str.parse()?;
-
-            let mut left = size;
-            while left > 0 {
-                buf.clear();
-                let read = buf_reader.read_until(0, &mut buf)?;
-                let (mode, name) = std::str::from_utf8(&buf[..read - 1])?
-                    .split_once(' ')
-                    .ok_or(anyhow!("Invalid tree entry"))?;
-                let (mode, name) = (u32::from_str_radix(mode, 8)?, name.to_string());
-
-                buf.resize(20, 0);
-                buf_reader.read_exact(&mut buf)?;
-                let sha = hex::encode(&buf);
-
-                if name_only {
-                    println!("{}", name);
+            let Object::Tree(tree) = repository.read(&tree_sha)? else {
+                return Err(anyhow!("Not a tree object: {}", tree_sha));
+            };
+
+            for entry in tree.entries {
+                // Paths are only quoted in line-based output, `-z` emits
+                // them verbatim.
+                let (name, terminator) = if null_terminated {
+                    (Cow::Borrowed(entry.name.as_str()), '\0')
                 } else {
-                    if mode == 0o40000 {
-                        println!("{:06o} tree {} {}", mode, name, sha);
-                    } else {
-                        println!("{:06o} blob {} {}", mode, name, sha);
+                    (quote::quote_path(&entry.name, quote_non_ascii), '\n')
+                };
+
+                if matches.get_flag("json") {
+                    let mut record = json::Record::new();
+                    if !name_only {
+                        record = record.string("mode", &format!("{:06o}", entry.mode))
+                            .string("type", entry.kind())
+                            .string("object", &entry.sha.to_string());
                     }
+                    println!("{}", record.string("path", &entry.name));
+                } else if name_only {
+                    print!("{}{}", name, terminator);
+                } else {
+                    print!("{:06o} {} {}\t{}{}", entry.mode, entry.kind(), entry.sha, name, terminator);
                 }
-
-                left -= read + 20;
             }
         }
-        Some(("write-tree", _)) => {
-            let sha1 = write_tree(&".".into())?;
+        Some(("write-tree", write_tree_matches)) => {
+            let git_dir = repository::current()?.git_dir().to_path_buf();
+            let options = TreeOptions::load()?;
+
+            // The attributes of the directories above the prefix still apply,
+            // `write_tree` adds the ones inside it.
+            let (path, attributes) = match write_tree_matches.get_one::<String>("prefix") {
+                Some(prefix) => {
+                    let prefix = prefix.trim_end_matches('/');
+                    let path = PathBuf::from(prefix);
+                    if !path.is_dir() {
+                        return Err(anyhow!("git-write-tree: prefix {}/ not found", prefix));
+                    }
+                    let parent = prefix.rsplit_once('/').map(|(parent, _)| parent).unwrap_or("");
+                    (path, Attributes::load_for(&git_dir, parent)?)
+                }
+                None => (PathBuf::from("."), Attributes::load(&git_dir)?),
+            };
+
+            let sha1 = repository::current()?.batch(|| objects::write_tree(&path, &attributes, &options))?;
             println!("{}", sha1);
         },
         Some(("commit-tree", commit_tree_matches)) => {
-            let tree_sha: Sha1Hash = commit_tree_matches.get_one::<String>("tree_sha")
+            let tree_sha: ObjectId = commit_tree_matches.get_one::<String>("tree_sha")
                 .expect("Tree SHA is required")
                 .parse()?;
-            let parent: Option<Sha1Hash> = commit_tree_matches.get_one::<String>("parent")
-                .map(|s| s.parse().map(Some))
-                .transpose()
-                .map(|opt| opt.flatten())?;
+            let mut parents: Vec<ObjectId> = Vec::new();
+            for parent in commit_tree_matches.get_many::<String>("parent").unwrap_or_default() {
+                let parent = refs::resolve_revision(parent)?;
+                // Like git, a parent given twice is only recorded once.
+                if parents.contains(&parent) {
+                    eprintln!("error: duplicate parent {} ignored", parent);
+                    continue;
+                }
+                parents.push(parent);
+            }
             let message = commit_tree_matches.get_one::<String>("message")
                 .expect("Message is required");
 
-            // println!("tree: {}, parent: {:?}, message: {}", tree_sha, parent, message);
-            let mut commit_buf = String::new();
-            writeln!(commit_buf, "tree {}", tree_sha)?;
-            if let Some(parent) = parent {
-                writeln!(commit_buf, "parent {}", parent)?;
-            }
-            writeln!(commit_buf, "author Noname <noreply@noname.com> 1709990458 +0200")?;
-            writeln!(commit_buf, "committer Noname <noreply@noname.com> 1709990458 +0200")?;
-            writeln!(commit_buf, "")?;
-            writeln!(commit_buf, "{}", message)?;
-
-            let sha1 = write_object("commit", commit_buf.as_bytes(), true)?;
+            let sha1 = repository::current()?.commit(&tree_sha, &parents, message)?;
             println!("{}", sha1);
         }
+        Some(("check-mailmap", check_mailmap_matches)) => {
+            let config = repository::current()?.config()?;
+            let mailmap = Mailmap::load(config.get("mailmap.file"))?;
 
-        _ => {
-            eprintln!("Invalid command, use --help.");
+            for contact in check_mailmap_matches.get_many::<String>("contact").into_iter().flatten() {
+                let (name, email, _) = mailmap::split_ident(contact)
+                    .ok_or(anyhow!("Unable to parse contact: {}", contact))?;
+                match mailmap.lookup(name.unwrap_or(""), email) {
+                    ("", email) => println!("<{}>", email),
+                    (name, email) => println!("{} <{}>", name, email),
+                }
+            }
         }
-    }
+        Some(("worktree", worktree_matches)) => match worktree_matches.subcommand() {
+            Some(("add", add_matches)) => {
+                let path = add_matches.get_one::<String>("path")
+                    .expect("Path is required");
+                let commitish = add_matches.get_one::<String>("commit-ish")
+                    .map(String::as_str);
+                let new_branch = add_matches.get_one::<String>("branch")
+                    .map(String::as_str);
+                let quiet = add_matches.get_flag("quiet") || verbosity::quiet();
 
-    Ok(())
-}
+                worktree::add(
+                    Path::new(path),
+                    commitish,
+                    new_branch,
+                    add_matches.get_flag("detach"),
+                    add_matches.get_flag("force"),
+                    quiet,
+                    add_matches.get_flag("progress") || (!quiet && std::io::stderr().is_terminal()),
+                )?;
+            }
+            Some(("list", _)) => worktree::list()?,
+            Some(("remove", remove_matches)) => {
+                let worktree = remove_matches.get_one::<String>("worktree")
+                    .expect("Worktree is required");
 
-fn hash_object(filename: &PathBuf, write_to_file: bool) -> anyhow::Result<Sha1Hash> {
-    let buf = fs::read(filename)?;
-    let sha = write_object("blob", &buf, write_to_file)?;
+                worktree::remove(worktree, remove_matches.get_count("force"))?;
+            }
+            _ => return Err(Error::Usage("Invalid worktree command, use --help.".to_string()).into()),
+        },
+        Some(("bisect", bisect_matches)) => {
+            let (command, command_matches) = bisect_matches.subcommand()
+                .expect("A bisect command is required");
+            let revisions: Vec<String> = command_matches.try_get_many::<String>("revisions")
+                .ok()
+                .flatten()
+                .into_iter()
+                .flatten()
+                .cloned()
+                .collect();
 
-    Ok(sha)
-}
+            match command {
+                "start" => match revisions.split_first() {
+                    Some((bad, good)) => bisect::start(Some(bad), good)?,
+                    None => bisect::start(None, &[])?,
+                },
+                "good" => bisect::mark(bisect::Term::Good, &revisions)?,
+                "bad" => bisect::mark(bisect::Term::Bad, &revisions)?,
+                "skip" => bisect::mark(bisect::Term::Skip, &revisions)?,
+                "reset" => bisect::reset()?,
+                "run" => bisect::run(&revisions)?,
+                _ => return Err(Error::Usage("Invalid bisect command, use --help.".to_string()).into()),
+            }
+        }
+        Some(("branch", branch_matches)) => {
+            let name = branch_matches.get_one::<String>("branch").map(String::as_str);
+            match branch_matches.get_one::<String>("set-upstream-to") {
+                Some(upstream) => branch::set_upstream_to(upstream, name)?,
+                None => {
+                    let revisions = |name: &str| -> Vec<String> {
+                        branch_matches.get_many::<String>(name).unwrap_or_default().cloned().collect()
+                    };
+                    let filter = branch::Filter {
+                        contains: revisions("contains"),
+                        no_contains: revisions("no-contains"),
+                        merged: revisions("merged"),
+                        no_merged: revisions("no-merged"),
+                    };
+                    branch::list(
+                        branch_matches.get_count("verbose"),
+                        branch_matches.get_one::<String>("format").map(String::as_str),
+                        &filter,
+                        color_when(branch_matches),
+                    )?
+                }
+            }
+        }
+        Some(("show-branch", show_matches)) => {
+            let revisions: Vec<String> = show_matches.get_many::<String>("revisions").unwrap_or_default().cloned().collect();
+            let options = show_branch::ShowOptions {
+                remotes: show_matches.get_flag("remotes"),
+                all: show_matches.get_flag("all"),
+                more: if show_matches.get_flag("list") {
+                    -1
+                } else {
+                    show_matches.get_one::<i64>("more").copied().unwrap_or(0)
+                },
+                sha1_name: show_matches.get_flag("sha1-name"),
+                no_name: show_matches.get_flag("no-name"),
+                topics: show_matches.get_flag("topics"),
+                sparse: show_matches.get_flag("sparse"),
+            };
+            show_branch::run(&revisions, &options)?;
+        }
+        Some(("check-ref-format", check_matches)) => {
+            let name = check_matches.get_one::<String>("refname").expect("Refname is required");
+
+            if check_matches.get_flag("branch") {
+                let full_name = format!("refs/heads/{}", name);
+                if name.starts_with('-') || !refs::is_valid_ref_name(&full_name, false, false) {
+                    return Err(anyhow!("'{}' is not a valid branch name", name));
+                }
+                println!("{}", name);
+                return Ok(());
+            }
+
+            let normalize = check_matches.get_flag("normalize");
+            let name = if normalize { refs::normalize_ref_name(name) } else { name.clone() };
+            if !refs::is_valid_ref_name(&name, check_matches.get_flag("allow-onelevel"), check_matches.get_flag("refspec-pattern")) {
+                return Err(Error::Status(1).into());
+            }
+            if normalize {
+                println!("{}", name);
+            }
+        }
+        Some(("reflog", reflog_matches)) => match reflog_matches.subcommand() {
+            Some(("expire", expire_matches)) => {
+                let mut expiry = reflog::Expiry::from_config(&repository::current()?)?;
+                if let Some(expire) = expire_matches.get_one::<String>("expire") {
+                    expiry.expire = date::parse_expiry(expire)?;
+                }
+                if let Some(expire) = expire_matches.get_one::<String>("expire-unreachable") {
+                    expiry.expire_unreachable = date::parse_expiry(expire)?;
+                }
+                let refs: Vec<String> = expire_matches.get_many::<String>("refs").unwrap_or_default().cloned().collect();
 
-fn write_tree(path: &PathBuf) -> anyhow::Result<Sha1Hash> {
-    let dir_entries = fs::read_dir(path)?;
-    let mut entries = Vec::new();
+                reflog::expire(
+                    &refs,
+                    expire_matches.get_flag("all"),
+                    &expiry,
+                    expire_matches.get_flag("dry-run"),
+                    expire_matches.get_flag("verbose"),
+                )?;
+            }
+            _ => unreachable!(),
+        },
+        Some(("maintenance", maintenance_matches)) => match maintenance_matches.subcommand() {
+            Some(("run", run_matches)) => {
+                let tasks: Vec<maintenance::Task> = run_matches.get_many::<maintenance::Task>("task")
+                    .unwrap_or_default()
+                    .copied()
+                    .collect();
+
+                let options = maintenance::Options {
+                    quiet: run_matches.get_flag("quiet") || verbosity::quiet(),
+                    auto: run_matches.get_flag("auto"),
+                    detach: match (run_matches.get_flag("detach"), run_matches.get_flag("no-detach")) {
+                        (true, _) => Some(true),
+                        (_, true) => Some(false),
+                        _ => None,
+                    },
+                    detached_child: run_matches.get_flag("detached-child"),
+                };
+                maintenance::run(&tasks, options)?;
+            }
+            _ => unreachable!(),
+        },
+        Some(("rewrite-history", rewrite_matches)) => {
+            let renames = rewrite_matches.get_many::<String>("path-rename")
+                .unwrap_or_default()
+                .map(|rename| {
+                    rename.split_once(':')
+                        .map(|(old, new)| (old.to_string(), new.to_string()))
+                        .ok_or(anyhow!("Expected OLD:NEW for --path-rename: {}", rename))
+                })
+                .collect::<anyhow::Result<_>>()?;
+            let options = rewrite_history::RewriteOptions {
+                paths: rewrite_matches.get_many::<String>("path").unwrap_or_default().cloned().collect(),
+                renames,
+                max_blob_size: rewrite_matches.get_one::<String>("strip-blobs-bigger-than")
+                    .map(|size| config::parse_size(size))
+                    .transpose()?,
+                message_callback: rewrite_matches.get_one::<String>("message-callback").cloned(),
+            };
 
-    for entry in dir_entries {
-        let entry = entry?;
-        let name = entry.path();
+            rewrite_history::run(&options)?;
+        }
+        Some(("fast-export", export_matches)) => {
+            let refs: Vec<String> = export_matches.get_many::<String>("refs").unwrap_or_default().cloned().collect();
+            let options = fast_export::ExportOptions {
+                signed_tags: *export_matches.get_one::<fast_export::SignedTags>("signed-tags").unwrap(),
+                paths: export_matches.get_many::<String>("paths").unwrap_or_default().cloned().collect(),
+                import_marks: export_matches.get_one::<String>("import-marks").map(Path::new),
+                export_marks: export_matches.get_one::<String>("export-marks").map(Path::new),
+            };
 
-        let last_name = name.file_name()
-            .ok_or(anyhow!("Invalid file name"))?
-            .to_str()
-            .ok_or(anyhow!("Invalid file name"))?
-            .to_string();
-        if last_name.starts_with(".") {
-            continue;
+            let mut out = std::io::BufWriter::new(std::io::stdout().lock());
+            fast_export::run(&refs, export_matches.get_flag("all"), &options, &mut out)?;
+            out.flush()?;
         }
+        Some(("fast-import", import_matches)) => {
+            let import_marks = import_matches.get_one::<String>("import-marks").map(Path::new);
+            let export_marks = import_matches.get_one::<String>("export-marks").map(Path::new);
 
-        let metadata = entry.metadata()?;
-        let mode: u32 = if metadata.is_dir() { 0o40000 } else { 0o100644 };
+            fast_import::run(std::io::stdin().lock(), import_marks, export_marks)?;
+        }
+        Some(("request-pull", request_matches)) => {
+            let start = request_matches.get_one::<String>("start").expect("Start is required");
+            let url = request_matches.get_one::<String>("url").expect("URL is required");
+            let end = request_matches.get_one::<String>("end").map(String::as_str);
+            let options = request_pull::RequestOptions { patch: request_matches.get_flag("patch") };
 
-        let sha = if metadata.is_dir() {
-            write_tree(&entry.path())?
-        } else {
-            hash_object(&entry.path(), true)?
-        };
+            request_pull::run(start, url, end, &options)?;
+        }
+        Some(("mailsplit", split_matches)) => {
+            let directory = split_matches.get_one::<String>("output").expect("Output directory is required");
+            let paths: Vec<String> = split_matches.get_many::<String>("mbox").unwrap_or_default().cloned().collect();
+            let precision = split_matches.get_one::<usize>("precision").copied().unwrap_or(4);
+            if !(3..10).contains(&precision) {
+                return Err(Error::Usage(
+                    "git mailsplit [-d<prec>] [-f<n>] [-b] [--keep-cr] -o<directory> [(<mbox>|<Maildir>)...]".to_string(),
+                )
+                .into());
+            }
+            let options = mailsplit::SplitOptions {
+                allow_bare: split_matches.get_flag("bare"),
+                precision,
+                skip: split_matches.get_one::<usize>("skip").copied().unwrap_or(0),
+                keep_cr: split_matches.get_flag("keep-cr"),
+                mboxrd: split_matches.get_flag("mboxrd"),
+            };
 
-        entries.push((mode, last_name, sha));
-    }
+            mailsplit::run(&paths, Path::new(directory), &options)?;
+        }
+        Some(("mailinfo", info_matches)) => {
+            let message = info_matches.get_one::<String>("msg").expect("Message file is required");
+            let patch = info_matches.get_one::<String>("patch").expect("Patch file is required");
+            let options = mailinfo::InfoOptions {
+                keep_subject: info_matches.get_flag("keep-subject"),
+                keep_non_patch_brackets: info_matches.get_flag("keep-non-patch"),
+                message_id: info_matches.get_flag("message-id"),
+            };
 
-    entries.sort_by(|a, b| a.1.cmp(&b.1));
+            mailinfo::run(Path::new(message), Path::new(patch), &options)?;
+        }
+        Some(("credential-store", store_matches)) => {
+            let action = store_matches.get_one::<String>("action").expect("Action is required");
+            credential::store(action, store_matches.get_one::<String>("file").map(Path::new))?;
+        }
+        #[cfg(unix)]
+        Some(("credential-cache", cache_matches)) => {
+            let action = cache_matches.get_one::<String>("action").expect("Action is required");
+            let timeout = cache_matches.get_one::<u64>("timeout").copied().unwrap_or(credential_cache::DEFAULT_TIMEOUT);
+            let socket = match cache_matches.get_one::<String>("socket") {
+                Some(socket) => PathBuf::from(socket),
+                None => credential_cache::default_socket()?,
+            };
 
-    let mut buf = BytesMut::new();
-    for (mode, name, sha) in entries {
-        buf.write_fmt(format_args!("{:o} {}", mode, name))?;
-        buf.put_u8(0);
-        buf.put_slice(sha.as_ref());
-    }
-    let buf = buf.freeze();
+            credential_cache::run(action, timeout, &socket)?;
+        }
+        #[cfg(unix)]
+        Some(("credential-cache--daemon", daemon_matches)) => {
+            let socket = daemon_matches.get_one::<String>("socket").expect("Socket is required");
+            credential_cache::daemon(Path::new(socket))?;
+        }
+        Some(("config", config_matches)) => {
+            let location = match config_matches.get_one::<String>("file") {
+                Some(file) => config::Location::File(PathBuf::from(file)),
+                None if config_matches.get_flag("system") => config::Location::Scope(config::Scope::System),
+                None if config_matches.get_flag("global") => config::Location::Scope(config::Scope::Global),
+                None if config_matches.get_flag("local") => config::Location::Scope(config::Scope::Local),
+                None => config::Location::All,
+            };
+            let args: Vec<&str> = config_matches.get_many::<String>("args")
+                .into_iter()
+                .flatten()
+                .map(String::as_str)
+                .collect();
+            let flag = |name| config_matches.get_flag(name);
+            let action = match args.as_slice() {
+                [] if flag("list") => config::Action::List,
+                [] if flag("edit") => config::Action::Edit,
+                [name] if flag("get") || flag("get-all") => config::Action::Get { name, all: flag("get-all") },
+                [name] if flag("unset") || flag("unset-all") => config::Action::Unset { name, all: flag("unset-all") },
+                [name, value] if flag("add") => config::Action::Add { name, value },
+                [name] if !flag("add") => config::Action::Get { name, all: false },
+                [name, value] if !flag("add") => config::Action::Set { name, value },
+                _ => return Err(Error::Usage("git config [<options>]".to_string()).into()),
+            };
 
-    let sha1 = write_object("tree", &buf, true)?;
-    Ok(sha1)
-}
+            config::run(&location, action)?;
+        }
+        Some(("completions", completions_matches)) => {
+            if completions_matches.get_flag("refs") {
+                for name in completion::ref_names()? {
+                    println!("{}", name);
+                }
+                return Ok(());
+            }
+            let shell = completions_matches.get_one::<String>("shell").expect("Shell is required");
+            print!("{}", completion::generate(&cli(), shell, &bin_name()?)?);
+        }
+        Some(("help", help_matches)) => {
+            let path: Vec<&str> = help_matches.get_many::<String>("command")
+                .into_iter()
+                .flatten()
+                .map(String::as_str)
+                .collect();
+            if path.is_empty() {
+                cli().bin_name(bin_name()?).print_help()?;
+            } else {
+                print!("{}", help::render(cli(), &bin_name()?, &path)?);
+            }
+        }
+        Some(("lfs", lfs_matches)) => match lfs_matches.subcommand() {
+            Some(("install", install_matches)) => {
+                let scope = match install_matches.get_flag("local") {
+                    true => config::Scope::Local,
+                    false => config::Scope::Global,
+                };
+                lfs::install(&bin_name()?, &config::Location::Scope(scope))?;
+            }
+            Some(("track", track_matches)) => {
+                let patterns: Vec<String> = track_matches.get_many::<String>("patterns")
+                    .into_iter()
+                    .flatten()
+                    .cloned()
+                    .collect();
+                lfs::track(&patterns)?;
+            }
+            Some(("clean", _)) => {
+                lfs::clean(&mut std::io::stdin().lock(), &mut std::io::stdout().lock())?;
+            }
+            Some(("smudge", _)) => {
+                lfs::smudge(&mut std::io::stdin().lock(), &mut std::io::stdout().lock())?;
+            }
+            Some(("push", push_matches)) => {
+                let remote = push_matches.get_one::<String>("remote").expect("Remote is required");
+                let revisions: Vec<String> = push_matches.get_many::<String>("revisions")
+                    .expect("Revisions are required")
+                    .cloned()
+                    .collect();
+                lfs::push(remote, &revisions)?;
+            }
+            _ => unreachable!(),
+        },
+        Some(("bundle", bundle_matches)) => match bundle_matches.subcommand() {
+            Some(("create", create_matches)) => {
+                let file = create_matches.get_one::<PathBuf>("file").expect("File is required");
+                let args: Vec<String> = create_matches.get_many::<String>("args")
+                    .expect("Revisions are required")
+                    .cloned()
+                    .collect();
+                bundle::create(file, &args)?;
+            }
+            Some(("verify", verify_matches)) => {
+                let file = verify_matches.get_one::<PathBuf>("file").expect("File is required");
+                bundle::verify(file, verify_matches.get_flag("quiet") || verbosity::quiet())?;
+            }
+            _ => unreachable!(),
+        },
+        Some(("export-html", export_matches)) => {
+            let range = export_matches.get_one::<String>("range").expect("Range is required");
+            let directory = export_matches.get_one::<PathBuf>("directory").expect("Directory is required");
+            export_html::export(std::slice::from_ref(range), directory)?;
+        }
+        Some(("browse", browse_matches)) => {
+            browse::serve(*browse_matches.get_one::<u16>("port").expect("Port has a default"))?;
+        }
+        Some(("stats", stats_matches)) => {
+            stats::run(*stats_matches.get_one::<usize>("top").expect("Top has a default"))?;
+        }
+        Some(("verify-objects", verify_matches)) => {
+            let progress = verify_matches.get_flag("progress")
+                || (!verify_matches.get_flag("no-progress") && std::io::stderr().is_terminal());
+            verify_objects::run(verify_matches.get_one::<usize>("threads").copied(), progress)?;
+        }
+        Some(("copy-objects", copy_matches)) => {
+            let from = copy_matches.get_one::<PathBuf>("from").expect("Source is required");
+            let progress = copy_matches.get_flag("progress")
+                || (!copy_matches.get_flag("no-progress") && std::io::stderr().is_terminal());
+            copy_objects::run(from, progress)?;
+        }
+        Some(("pack-objects", pack_matches)) => {
+            let progress = pack_matches.get_flag("progress")
+                || (!pack_matches.get_flag("no-progress") && std::io::stderr().is_terminal());
+            let base_name = pack_matches.get_one::<PathBuf>("base-name").map(PathBuf::as_path);
+            repack::pack_objects(std::io::stdin().lock(), base_name, tuning(pack_matches), progress)?;
+        }
+        Some(("repack", repack_matches)) => {
+            repack::run(repack::Options {
+                all: repack_matches.get_flag("all"),
+                geometric: repack_matches.get_one::<usize>("geometric").copied(),
+                delete: repack_matches.get_flag("delete"),
+                write_midx: repack_matches.get_flag("write-midx"),
+                write_bitmaps: match (
+                    repack_matches.get_flag("write-bitmap-index"),
+                    repack_matches.get_flag("no-write-bitmap-index"),
+                ) {
+                    (true, _) => Some(true),
+                    (_, true) => Some(false),
+                    _ => None,
+                },
+                tuning: tuning(repack_matches),
+                progress: std::io::stderr().is_terminal(),
+            })?;
+        }
+        Some(("commit-graph", graph_matches)) => match graph_matches.subcommand() {
+            Some(("write", write_matches)) => {
+                commit_graph::run(commit_graph::WriteOptions {
+                    split: write_matches.get_flag("split"),
+                    changed_paths: write_matches.get_flag("changed-paths"),
+                })?;
+            }
+            _ => unreachable!(),
+        },
+        Some(("update-ref", update_matches)) => {
+            let no_deref = update_matches.get_flag("no-deref");
+            let values: Vec<&String> = update_matches.get_many::<String>("values").unwrap_or_default().collect();
+            let usage = "git update-ref [<options>] -d <refname> [<old-val>]\n   \
+                         or: git update-ref [<options>]    <refname> <new-val> [<old-val>]\n   \
+                         or: git update-ref [<options>] --stdin [-z]";
+            let usage = || Err(Error::Usage(usage.to_string()).into());
+            if update_matches.get_flag("stdin") {
+                if !values.is_empty() {
+                    return usage();
+                }
+                update_ref::run_stdin(update_matches.get_flag("z"))?;
+            } else if update_matches.get_flag("z") {
+                return usage();
+            } else if update_matches.get_flag("delete") {
+                match values[..] {
+                    [name] => update_ref::delete(name, None, no_deref)?,
+                    [name, old] => update_ref::delete(name, Some(old), no_deref)?,
+                    _ => return usage(),
+                }
+            } else {
+                match values[..] {
+                    [name, new] => update_ref::update(name, new, None, no_deref)?,
+                    [name, new, old] => update_ref::update(name, new, Some(old), no_deref)?,
+                    _ => return usage(),
+                }
+            }
+        }
+        Some(("upload-pack", upload_matches)) => {
+            let directory = Path::new(upload_matches.get_one::<String>("directory").expect("Directory is required"));
+            let repository = Repository::open(directory)
+                .ok()
+                .filter(|repository| repository.git_dir().is_dir())
+                .ok_or(anyhow!("'{}' does not appear to be a git repository", directory.display()))?;
+            let upload_pack = UploadPack::new(repository, upload_matches.get_flag("stateless-rpc"));
+            let mut response = std::io::stdout().lock();
+            if !upload_matches.get_flag("stateless-rpc") || upload_matches.get_flag("advertise-refs") {
+                upload_pack.advertise_refs(&mut response)?;
+            }
+            if !upload_matches.get_flag("advertise-refs") {
+                upload_pack.handle(&mut std::io::stdin().lock(), &mut response)?;
+            }
+        }
+        Some(("checkout-index", checkout_matches)) => {
+            let paths: Vec<String> = checkout_matches.get_many::<String>("paths")
+                .into_iter()
+                .flatten()
+                .cloned()
+                .collect();
+            let prefix = checkout_matches.get_one::<String>("prefix").map(String::as_str).unwrap_or("");
 
-fn write_object(kind: &str, content: &[u8], write_to_file: bool) -> anyhow::Result<Sha1Hash> {
-    let mut buf = BytesMut::new();
-    buf.write_fmt(format_args!("{} {}", kind, content.len()))?;
-    buf.put_u8(0);
-    buf.put_slice(content);
+            worktree::checkout_index(&paths, checkout_matches.get_flag("all"), prefix, checkout_matches.get_flag("force"))?;
+        }
+        Some(("var", var_matches)) => {
+            let config = repository::current()?.config()?;
+            let value = match var_matches.get_one::<String>("variable").expect("Variable is required").as_str() {
+                "GIT_AUTHOR_IDENT" => ident::resolve(ident::Role::Author, &config)?.to_string(),
+                "GIT_COMMITTER_IDENT" => ident::resolve(ident::Role::Committer, &config)?.to_string(),
+                "GIT_EDITOR" => ident::editor(&config),
+                variable => return Err(Error::Usage(format!("Unknown variable {}, use --help.", variable)).into()),
+            };
+            println!("{}", value);
+        }
+        Some(("interpret-trailers", trailers_matches)) => {
+            let trailers: Vec<trailers::Trailer> = trailers_matches.get_many::<String>("trailer")
+                .into_iter()
+                .flatten()
+                .map(|trailer| trailer.parse())
+                .collect::<anyhow::Result<_>>()?;
+            let files: Vec<&String> = trailers_matches.get_many::<String>("files")
+                .into_iter()
+                .flatten()
+                .collect();
 
-    let sha1 = Sha1Hash::hash(&buf);
+            let inputs = if files.is_empty() {
+                let mut message = String::new();
+                std::io::Read::read_to_string(&mut std::io::stdin(), &mut message)?;
+                vec![(None, message)]
+            } else {
+                files.into_iter()
+                    .map(|file| Ok((Some(file), std::fs::read_to_string(file)?)))
+                    .collect::<anyhow::Result<_>>()?
+            };
+
+            for (file, message) in inputs {
+                let output = if trailers_matches.get_flag("parse") {
+                    trailers::parse(&message)
+                        .iter()
+                        .map(|trailer| format!("{}\n", trailer))
+                        .collect()
+                } else {
+                    trailers::add(&message, &trailers)
+                };
 
-    if write_to_file {
-        let directory = directory_from_sha(&sha1)?;
-        fs::create_dir_all(&directory)?;
+                match file {
+                    Some(file) if trailers_matches.get_flag("in-place") => std::fs::write(file, output)?,
+                    _ => print!("{}", output),
+                }
+            }
+        }
+        Some(("name-rev", name_rev_matches)) => {
+            let tags_only = name_rev_matches.get_flag("tags");
+            let name_only = name_rev_matches.get_flag("name-only");
+            let revisions: Vec<String> = name_rev_matches.get_many::<String>("revisions")
+                .into_iter()
+                .flatten()
+                .cloned()
+                .collect();
 
-        let filename = filename_from_sha(&sha1)?;
-        let file = fs::OpenOptions::new()
-            .write(true)
-            .create(true)
-            .truncate(true)
-            .open(filename)?;
-        let file = ZlibEncoder::new(file, flate2::Compression::default());
+            if name_rev_matches.get_flag("annotate-stdin") {
+                name_rev::annotate_stdin(tags_only, name_only)?;
+            } else if name_rev_matches.get_flag("all") {
+                name_rev::name_all(tags_only, name_only)?;
+            } else {
+                name_rev::name_revisions(&revisions, tags_only, name_only)?;
+            }
+        }
+        Some(("notes", notes_matches)) => match notes_matches.subcommand() {
+            Some(("add", add_matches)) => {
+                let object = add_matches.get_one::<String>("object").expect("Object has a default");
+                let message = add_matches.get_one::<String>("message").expect("Message is required");
+                notes::add(object, message, add_matches.get_flag("force"))?;
+            }
+            Some(("show", show_matches)) => {
+                notes::show(show_matches.get_one::<String>("object").expect("Object has a default"))?;
+            }
+            Some(("list", list_matches)) => {
+                notes::list(list_matches.get_one::<String>("object").map(String::as_str))?;
+            }
+            Some(_) => return Err(Error::Usage("Invalid notes command, use --help.".to_string()).into()),
+            None => notes::list(None)?,
+        },
+        Some(("replace", replace_matches)) => {
+            let objects: Vec<String> = replace_matches.get_many::<String>("objects")
+                .into_iter()
+                .flatten()
+                .cloned()
+                .collect();
+            if replace_matches.get_flag("delete") {
+                replace::delete(&objects)?;
+            } else {
+                match objects.as_slice() {
+                    [] => replace::list()?,
+                    [object, replacement] => replace::create(object, replacement, replace_matches.get_flag("force"))?,
+                    _ => return Err(Error::Usage("Expected an object and its replacement, use --help.".to_string()).into()),
+                }
+            }
+        }
+        Some(("remote", remote_matches)) => match remote_matches.subcommand() {
+            Some(("add", add_matches)) => {
+                let name = add_matches.get_one::<String>("name").expect("Name is required");
+                let url = add_matches.get_one::<String>("url").expect("Url is required");
+                remote::add(name, url)?;
+            }
+            Some(("remove", remove_matches)) => {
+                let name = remove_matches.get_one::<String>("name").expect("Name is required");
+                remote::remove(name)?;
+            }
+            Some(("set-url", set_url_matches)) => {
+                let name = set_url_matches.get_one::<String>("name").expect("Name is required");
+                let url = set_url_matches.get_one::<String>("url").expect("Url is required");
+                remote::set_url(name, url)?;
+            }
+            Some(_) => return Err(Error::Usage("Invalid remote command, use --help.".to_string()).into()),
+            None => remote::list(remote_matches.get_flag("verbose"))?,
+        },
+        Some(("submodule", submodule_matches)) => match submodule_matches.subcommand() {
+            Some(("init", _)) => submodule::init()?,
+            Some(("status", _)) => submodule::status()?,
+            Some(("update", _)) => submodule::update()?,
+            _ => return Err(Error::Usage("Invalid submodule command, use --help.".to_string()).into()),
+        },
+        Some(("submodule--checkout", checkout_matches)) => {
+            let commit = checkout_matches.get_one::<String>("commit").expect("Commit is required");
+            submodule::checkout(&refs::resolve_revision(commit)?)?;
+        }
+        Some(("sparse-checkout", sparse_matches)) => match sparse_matches.subcommand() {
+            Some(("init", init_matches)) => sparse::init(!init_matches.get_flag("no-cone"))?,
+            Some(("set", set_matches)) => {
+                let patterns: Vec<String> = set_matches.get_many::<String>("patterns")
+                    .into_iter()
+                    .flatten()
+                    .cloned()
+                    .collect();
+                sparse::set(&patterns, !set_matches.get_flag("no-cone"))?;
+            }
+            Some(("list", _)) => sparse::list()?,
+            _ => return Err(Error::Usage("Invalid sparse-checkout command, use --help.".to_string()).into()),
+        },
 
-        let mut file = file;
-        file.write(&buf)?;
+        _ => return Err(Error::Usage("Invalid command, use --help.".to_string()).into()),
     }
 
-    Ok(sha1)
+    Ok(())
+}
+
+/// The name the program was installed as, for usage and scripts.
+fn bin_name() -> anyhow::Result<String> {
+    let exe = std::env::current_exe()?;
+    Ok(exe.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default())
 }
 
-fn get_matches() -> ArgMatches {
-    Command::new("Rust Git")
+fn cli() -> Command {
+    let cli = Command::new("Rust Git")
         .version("0.1.0")
         .author("xxorza")
         .about("A simple git implementation in Rust")
-        .subcommand(Command::new("init").about("Initialize a new git repository"))
+        .arg(
+            Arg::new("no-replace-objects")
+                .long("no-replace-objects")
+                .action(ArgAction::SetTrue)
+                .help("Ignore the replacements in refs/replace/"),
+        )
+        .arg(
+            Arg::new("profile")
+                .long("profile")
+                .action(ArgAction::SetTrue)
+                .help("Report time, object reads and file counts on stderr at the end"),
+        )
+        .arg(
+            Arg::new("quiet")
+                .short('q')
+                .long("quiet")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("verbose")
+                .help("Only report results and errors, not what a command is doing"),
+        )
+        .arg(
+            Arg::new("verbose")
+                .short('v')
+                .long("verbose")
+                .action(ArgAction::Count)
+                .help("Report more of what a command is doing, twice for even more"),
+        )
+        .arg(
+            Arg::new("paginate")
+                .short('p')
+                .long("paginate")
+                .action(ArgAction::SetTrue)
+                .overrides_with("no-pager")
+                .help("Page the output of any command when it is for a terminal"),
+        )
+        .arg(
+            Arg::new("no-pager")
+                .long("no-pager")
+                .action(ArgAction::SetTrue)
+                .overrides_with("paginate")
+                .help("Don't page the output of log, diff and the like"),
+        )
+        .arg(
+            Arg::new("help-all")
+                .long("help-all")
+                .action(ArgAction::SetTrue)
+                .help("Print the manual pages of every command"),
+        )
+        .arg(
+            Arg::new("json")
+                .long("json")
+                .action(ArgAction::SetTrue)
+                .global(true)
+                .help("Print one JSON record per line where a command supports it"),
+        )
+        .disable_help_subcommand(true)
+        .subcommand(
+            Command::new("init")
+                .about("Initialize a new git repository")
+                .arg(
+                    Arg::new("bare")
+                        .long("bare")
+                        .action(ArgAction::SetTrue)
+                        .help("Create a bare repository without a working tree"),
+                )
+                .arg(
+                    Arg::new("initial-branch")
+                        .short('b')
+                        .long("initial-branch")
+                        .value_name("BRANCH_NAME")
+                        .help("The name of the initial branch, defaults to main"),
+                )
+                .arg(
+                    Arg::new("object-format")
+                        .long("object-format")
+                        .value_name("FORMAT")
+                        .value_parser(["sha1", "sha256"])
+                        .help("The hash algorithm to name objects with, defaults to sha1"),
+                )
+                .arg(
+                    Arg::new("ref-format")
+                        .long("ref-format")
+                        .value_name("FORMAT")
+                        .value_parser(["files", "reftable"])
+                        .help("How to store refs, defaults to files"),
+                )
+                .arg(
+                    Arg::new("template")
+                        .long("template")
+                        .value_name("TEMPLATE_DIRECTORY")
+                        .help("The directory to copy hooks and other files from, defaults to init.templateDir"),
+                )
+                .arg(
+                    Arg::new("directory")
+                        .value_name("DIRECTORY")
+                        .help("The directory to create the repository in"),
+                ),
+        )
         .subcommand(
             Command::new("cat-file")
                 .about("Prints the contents of a git object")
                 .arg(
                     Arg::new("blob_sha")
                         .short('p')
-                        .required(true)
+                        .required_unless_present_any(["batch", "batch-check"])
                         .value_name("BLOB_SHA")
                         .help("The SHA of the blob to print"),
+                )
+                .arg(
+                    Arg::new("batch")
+                        .long("batch")
+                        .value_name("FORMAT")
+                        .num_args(0..=1)
+                        .require_equals(true)
+                        .default_missing_value("")
+                        .conflicts_with_all(["blob_sha", "batch-check"])
+                        .help("Print the header and contents of each object named on stdin"),
+                )
+                .arg(
+                    Arg::new("batch-check")
+                        .long("batch-check")
+                        .value_name("FORMAT")
+                        .num_args(0..=1)
+                        .require_equals(true)
+                        .default_missing_value("")
+                        .conflicts_with("blob_sha")
+                        .help("Print only the header of each object named on stdin"),
+                )
+                .arg(
+                    Arg::new("batch-all-objects")
+                        .long("batch-all-objects")
+                        .action(ArgAction::SetTrue)
+                        .help("Print every object in the repository instead of reading names"),
+                )
+                .arg(
+                    Arg::new("unordered")
+                        .long("unordered")
+                        .action(ArgAction::SetTrue)
+                        .requires("batch-all-objects")
+                        .help("List the objects in storage order instead of sorting them"),
                 ),
         )
         .subcommand(
@@ -239,6 +1092,18 @@ fn get_matches() -> ArgMatches {
                         .action(ArgAction::SetTrue)
                         .help("Write the object into the object database"),
                 )
+                .arg(
+                    Arg::new("type")
+                        .short('t')
+                        .value_name("TYPE")
+                        .help("Type of the object to create (default: blob)"),
+                )
+                .arg(
+                    Arg::new("literally")
+                        .long("literally")
+                        .action(ArgAction::SetTrue)
+                        .help("Allow any type and content, without checking that the object is valid"),
+                )
                 .arg(
                     Arg::new("file")
                         .value_name("FILE")
@@ -246,57 +1111,1670 @@ fn get_matches() -> ArgMatches {
                 ),
         )
         .subcommand(
-            Command::new("ls-tree")
-                .about("List the contents of a tree object")
+            Command::new("ls-files")
+                .about("Show information about files in the index and the working tree")
                 .arg(
-                    Arg::new("name-only")
-                        .long("name-only")
+                    Arg::new("cached")
+                        .short('c')
+                        .long("cached")
                         .action(ArgAction::SetTrue)
-                        .help("Only show names of tree entries"),
+                        .help("Show cached files (the default)"),
                 )
                 .arg(
-                    Arg::new("tree_sha")
-                        .value_name("TREE_SHA")
-                        .required(true)
-                        .help("The SHA of the tree to list"),
-                ),
-        )
-        .subcommand(
-            Command::new("write-tree")
-                .about("Write a tree object from the current index")
+                    Arg::new("others")
+                        .short('o')
+                        .long("others")
+                        .action(ArgAction::SetTrue)
+                        .help("Show untracked files"),
+                )
+                .arg(
+                    Arg::new("modified")
+                        .short('m')
+                        .long("modified")
+                        .action(ArgAction::SetTrue)
+                        .help("Show files that differ from the index"),
+                )
+                .arg(
+                    Arg::new("deleted")
+                        .short('d')
+                        .long("deleted")
+                        .action(ArgAction::SetTrue)
+                        .help("Show files deleted from the working tree"),
+                )
+                .arg(
+                    Arg::new("stage")
+                        .short('s')
+                        .long("stage")
+                        .action(ArgAction::SetTrue)
+                        .help("Show the mode, object ID and merge stage of each entry"),
+                )
+                .arg(
+                    Arg::new("unmerged")
+                        .short('u')
+                        .long("unmerged")
+                        .action(ArgAction::SetTrue)
+                        .help("Show only the stages of unmerged paths, implying --stage"),
+                )
+                .arg(
+                    Arg::new("exclude-standard")
+                        .long("exclude-standard")
+                        .action(ArgAction::SetTrue)
+                        .help("Skip untracked files matched by the standard ignore rules"),
+                )
+                .arg(
+                    Arg::new("t")
+                        .short('t')
+                        .action(ArgAction::SetTrue)
+                        .help("Show a status tag before each file"),
+                )
+                .arg(
+                    Arg::new("v")
+                        .short('v')
+                        .action(ArgAction::SetTrue)
+                        .help("Like -t, with lowercase tags for assume-unchanged files"),
+                )
+                .arg(
+                    Arg::new("z")
+                        .short('z')
+                        .action(ArgAction::SetTrue)
+                        .help("Terminate entries with NUL and don't quote paths"),
+                )
+                .arg(Arg::new("paths").value_name("PATH").num_args(0..).help("Only show the files these pathspecs match")),
         )
         .subcommand(
-            Command::new("commit-tree")
-                .about("Create a new commit object")
+            Command::new("diff")
+                .about("Show changes between two files or directories")
                 .arg(
-                    Arg::new("tree_sha")
-                        .value_name("TREE_SHA")
-                        .required(true)
-                        .help("The SHA of the tree to commit"),
+                    Arg::new("no-index")
+                        .long("no-index")
+                        .action(ArgAction::SetTrue)
+                        .help("Compare paths outside of any repository, exiting with 1 if they differ"),
                 )
                 .arg(
-                    Arg::new("parent")
-                        .short('p')
-                        .value_name("PARENT")
-                        .help("The SHA of the parent commit"),
+                    Arg::new("unified")
+                        .short('U')
+                        .long("unified")
+                        .value_name("N")
+                        .value_parser(clap::value_parser!(usize))
+                        .help("Show N lines of context"),
                 )
                 .arg(
-                    Arg::new("message")
-                        .short('m')
-                        .value_name("MESSAGE")
-                        .required(true)
-                        .help("The commit message"),
-                ),
-        )
-        .get_matches()
-}
-
-fn filename_from_sha(sha: &Sha1Hash) -> anyhow::Result<String> {
-    let str = sha.to_string();
-    Ok(format!(".git/objects/{}/{}", &str[..2], &str[2..]))
-}
-
-fn directory_from_sha(sha: &Sha1Hash) -> anyhow::Result<String> {
-    Ok(format!(".git/objects/{}", &sha.to_string()[..2]))
+                    Arg::new("inter-hunk-context")
+                        .long("inter-hunk-context")
+                        .value_name("N")
+                        .value_parser(clap::value_parser!(usize))
+                        .help("Join hunks with up to N unchanged lines between them"),
+                )
+                .arg(
+                    Arg::new("function-context")
+                        .short('W')
+                        .long("function-context")
+                        .action(ArgAction::SetTrue)
+                        .overrides_with("no-function-context")
+                        .help("Show the whole function around each change"),
+                )
+                .arg(
+                    Arg::new("no-function-context")
+                        .long("no-function-context")
+                        .action(ArgAction::SetTrue)
+                        .overrides_with("function-context")
+                        .hide(true),
+                )
+                .arg(
+                    Arg::new("quiet")
+                        .long("quiet")
+                        .action(ArgAction::SetTrue)
+                        .help("Only report differences through the exit code"),
+                )
+                .arg(
+                    Arg::new("ext-diff")
+                        .long("ext-diff")
+                        .action(ArgAction::SetTrue)
+                        .overrides_with("no-ext-diff")
+                        .help("Let an external diff program show the changes (the default)"),
+                )
+                .arg(
+                    Arg::new("no-ext-diff")
+                        .long("no-ext-diff")
+                        .action(ArgAction::SetTrue)
+                        .overrides_with("ext-diff")
+                        .help("Show a patch even if an external diff program is configured"),
+                )
+                .args(color_args())
+                .arg(
+                    Arg::new("exit-code")
+                        .long("exit-code")
+                        .action(ArgAction::SetTrue)
+                        .help("Exit with 1 if there are differences, always on with --no-index"),
+                )
+                .arg(Arg::new("paths").value_name("PATH").num_args(0..).help("The two paths to compare")),
+        )
+        .subcommand(
+            Command::new("rev-list")
+                .about("List commits reachable from some revisions but not others")
+                .arg(
+                    Arg::new("args")
+                        .value_name("REVISION")
+                        .num_args(0..)
+                        .allow_hyphen_values(true)
+                        .trailing_var_arg(true)
+                        .help("Revisions, ranges such as A..B and A...B, and --not, --all, --branches, --tags, \
+                               --remotes, --left-right, --left-only, --right-only, --cherry-pick, --cherry-mark, \
+                               --cherry, --no-merges, --count, --since, --until, --author, --grep or -i"),
+                ),
+        )
+        .subcommand(
+            Command::new("range-diff")
+                .about("Compare two commit ranges, such as two versions of a branch")
+                .arg(
+                    Arg::new("creation-factor")
+                        .long("creation-factor")
+                        .value_name("PERCENT")
+                        .value_parser(clap::value_parser!(usize))
+                        .help("How much a patch may change and still be paired with its old version"),
+                )
+                .arg(
+                    Arg::new("no-patch")
+                        .short('s')
+                        .long("no-patch")
+                        .action(ArgAction::SetTrue)
+                        .help("Only list the pairs of commits, not how their patches changed"),
+                )
+                .arg(
+                    Arg::new("ranges")
+                        .value_name("RANGE")
+                        .num_args(1..=3)
+                        .required(true)
+                        .help("A..B C..D, A...B, or <base> <old> <new>"),
+                ),
+        )
+        .subcommand(
+            Command::new("cherry")
+                .about("Find commits not yet applied upstream")
+                .arg(Arg::new("verbose").short('v').action(ArgAction::SetTrue).help("Show the commit subjects"))
+                .arg(Arg::new("upstream").value_name("UPSTREAM").help("Where to look for equivalent commits"))
+                .arg(Arg::new("head").value_name("HEAD").help("The branch whose commits to check, HEAD by default"))
+                .arg(Arg::new("limit").value_name("LIMIT").help("Don't report commits up to and including this one")),
+        )
+        .subcommand(
+            Command::new("log")
+                .about("Show commit logs")
+                .arg(
+                    Arg::new("args")
+                        .value_name("ARG")
+                        .num_args(0..)
+                        .allow_hyphen_values(true)
+                        .trailing_var_arg(true)
+                        .help("Revisions and filters as for rev-list, and --oneline, -p, -n <n>, -S <string>, \
+                               -G <regex>, --pickaxe-regex or --pickaxe-all"),
+                ),
+        )
+        .subcommand(
+            Command::new("update-index")
+                .about("Change the flags of index entries")
+                .arg(
+                    Arg::new("assume-unchanged")
+                        .long("assume-unchanged")
+                        .action(ArgAction::SetTrue)
+                        .overrides_with("no-assume-unchanged")
+                        .help("Treat the files as unchanged without checking them"),
+                )
+                .arg(
+                    Arg::new("no-assume-unchanged")
+                        .long("no-assume-unchanged")
+                        .action(ArgAction::SetTrue)
+                        .overrides_with("assume-unchanged")
+                        .help("Check the files for changes again"),
+                )
+                .arg(
+                    Arg::new("skip-worktree")
+                        .long("skip-worktree")
+                        .action(ArgAction::SetTrue)
+                        .overrides_with("no-skip-worktree")
+                        .help("Leave the files out of the working tree"),
+                )
+                .arg(
+                    Arg::new("no-skip-worktree")
+                        .long("no-skip-worktree")
+                        .action(ArgAction::SetTrue)
+                        .overrides_with("skip-worktree")
+                        .help("Track the files in the working tree again"),
+                )
+                .arg(Arg::new("paths").value_name("FILE").num_args(1..).required(true).help("Files to change")),
+        )
+        .subcommand(
+            Command::new("ls-tree")
+                .about("List the contents of a tree object")
+                .arg(
+                    Arg::new("name-only")
+                        .long("name-only")
+                        .action(ArgAction::SetTrue)
+                        .help("Only show names of tree entries"),
+                )
+                .arg(
+                    Arg::new("z")
+                        .short('z')
+                        .action(ArgAction::SetTrue)
+                        .help("Terminate entries with NUL and don't quote paths"),
+                )
+                .arg(
+                    Arg::new("tree_sha")
+                        .value_name("TREE_SHA")
+                        .required(true)
+                        .help("The SHA of the tree to list"),
+                ),
+        )
+        .subcommand(
+            Command::new("write-tree")
+                .about("Write a tree object from the current index")
+                .arg(
+                    Arg::new("prefix")
+                        .long("prefix")
+                        .value_name("DIR")
+                        .help("Write the tree of this subdirectory only"),
+                ),
+        )
+        .subcommand(
+            Command::new("commit-tree")
+                .about("Create a new commit object")
+                .arg(
+                    Arg::new("tree_sha")
+                        .value_name("TREE_SHA")
+                        .required(true)
+                        .help("The SHA of the tree to commit"),
+                )
+                .arg(
+                    Arg::new("parent")
+                        .short('p')
+                        .value_name("PARENT")
+                        .action(ArgAction::Append)
+                        .help("The SHA of a parent commit, given once for each parent"),
+                )
+                .arg(
+                    Arg::new("message")
+                        .short('m')
+                        .value_name("MESSAGE")
+                        .required(true)
+                        .help("The commit message"),
+                ),
+        )
+        .subcommand(
+            Command::new("check-mailmap")
+                .about("Show canonical names and email addresses for contacts")
+                .arg(
+                    Arg::new("contact")
+                        .value_name("CONTACT")
+                        .num_args(1..)
+                        .required(true)
+                        .help("A contact of the form 'Name <email>' or '<email>'"),
+                ),
+        )
+        .subcommand(
+            Command::new("worktree")
+                .about("Manage multiple working trees")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("add")
+                        .about("Create a new working tree at the given path")
+                        .arg(
+                            Arg::new("branch")
+                                .short('b')
+                                .value_name("NEW_BRANCH")
+                                .help("Create a new branch and check it out in the new working tree"),
+                        )
+                        .arg(
+                            Arg::new("detach")
+                                .long("detach")
+                                .action(ArgAction::SetTrue)
+                                .help("Detach HEAD in the new working tree"),
+                        )
+                        .arg(
+                            Arg::new("force")
+                                .short('f')
+                                .long("force")
+                                .action(ArgAction::SetTrue)
+                                .help("Check out a branch even if it is checked out in another working tree"),
+                        )
+                        .arg(
+                            Arg::new("quiet")
+                                .short('q')
+                                .long("quiet")
+                                .action(ArgAction::SetTrue)
+                                .help("Suppress feedback messages"),
+                        )
+                        .arg(
+                            Arg::new("progress")
+                                .long("progress")
+                                .action(ArgAction::SetTrue)
+                                .help("Report progress even if stderr is not a terminal"),
+                        )
+                        .arg(
+                            Arg::new("path")
+                                .value_name("PATH")
+                                .required(true)
+                                .help("The directory of the new working tree"),
+                        )
+                        .arg(
+                            Arg::new("commit-ish")
+                                .value_name("COMMIT_ISH")
+                                .help("The branch or commit to check out"),
+                        ),
+                )
+                .subcommand(Command::new("list").about("List all working trees"))
+                .subcommand(
+                    Command::new("remove")
+                        .about("Remove a working tree")
+                        .arg(
+                            Arg::new("force")
+                                .short('f')
+                                .long("force")
+                                .action(ArgAction::Count)
+                                .help("Remove a working tree with local changes, twice to remove a locked one"),
+                        )
+                        .arg(
+                            Arg::new("worktree")
+                                .value_name("WORKTREE")
+                                .required(true)
+                                .help("The path or name of the working tree to remove"),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("bisect")
+                .about("Find the commit that introduced a bug by binary search")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("start")
+                        .about("Start bisecting, optionally with a bad and some good revisions")
+                        .arg(revisions_arg("The bad revision followed by good ones")),
+                )
+                .subcommand(Command::new("good").about("Mark revisions as good").arg(revisions_arg("Defaults to HEAD")))
+                .subcommand(Command::new("bad").about("Mark a revision as bad").arg(revisions_arg("Defaults to HEAD")))
+                .subcommand(Command::new("skip").about("Skip untestable revisions").arg(revisions_arg("Defaults to HEAD")))
+                .subcommand(Command::new("reset").about("Stop bisecting and return to the original branch"))
+                .subcommand(
+                    Command::new("run")
+                        .about("Judge each commit by the exit code of a command")
+                        .arg(
+                            Arg::new("revisions")
+                                .value_name("COMMAND")
+                                .num_args(1..)
+                                .required(true)
+                                .trailing_var_arg(true)
+                                .allow_hyphen_values(true)
+                                .help("0 for good, 125 to skip, other codes below 128 for bad"),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("branch")
+                .about("List branches or configure their upstream")
+                .arg(
+                    Arg::new("verbose")
+                        .short('v')
+                        .long("verbose")
+                        .action(ArgAction::Count)
+                        .help("Show the commit and upstream status, twice to also name the upstream"),
+                )
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .value_name("FORMAT")
+                        .help("Show each branch as FORMAT, with placeholders such as %(refname:short) and %(upstream:track)"),
+                )
+                .arg(
+                    Arg::new("contains")
+                        .long("contains")
+                        .value_name("COMMIT")
+                        .num_args(0..=1)
+                        .default_missing_value("HEAD")
+                        .action(ArgAction::Append)
+                        .help("Only list branches which contain COMMIT (HEAD if not given)"),
+                )
+                .arg(
+                    Arg::new("no-contains")
+                        .long("no-contains")
+                        .value_name("COMMIT")
+                        .num_args(0..=1)
+                        .default_missing_value("HEAD")
+                        .action(ArgAction::Append)
+                        .help("Only list branches which don't contain COMMIT (HEAD if not given)"),
+                )
+                .arg(
+                    Arg::new("merged")
+                        .long("merged")
+                        .value_name("COMMIT")
+                        .num_args(0..=1)
+                        .default_missing_value("HEAD")
+                        .action(ArgAction::Append)
+                        .help("Only list branches whose tips are reachable from COMMIT (HEAD if not given)"),
+                )
+                .arg(
+                    Arg::new("no-merged")
+                        .long("no-merged")
+                        .value_name("COMMIT")
+                        .num_args(0..=1)
+                        .default_missing_value("HEAD")
+                        .action(ArgAction::Append)
+                        .help("Only list branches whose tips are not reachable from COMMIT (HEAD if not given)"),
+                )
+                .arg(
+                    Arg::new("set-upstream-to")
+                        .short('u')
+                        .long("set-upstream-to")
+                        .value_name("UPSTREAM")
+                        .help("Make the branch track UPSTREAM"),
+                )
+                .args(color_args())
+                .arg(
+                    Arg::new("branch")
+                        .value_name("BRANCH")
+                        .requires("set-upstream-to")
+                        .help("The branch to configure, defaults to the current one"),
+                ),
+        )
+        .subcommand(
+            Command::new("show-branch")
+                .about("Show branches and the commits on them")
+                .arg(
+                    Arg::new("remotes")
+                        .short('r')
+                        .long("remotes")
+                        .action(ArgAction::SetTrue)
+                        .help("Show remote-tracking branches"),
+                )
+                .arg(
+                    Arg::new("all")
+                        .short('a')
+                        .long("all")
+                        .action(ArgAction::SetTrue)
+                        .help("Show both local and remote-tracking branches"),
+                )
+                .arg(
+                    Arg::new("more")
+                        .long("more")
+                        .value_name("N")
+                        .num_args(0..=1)
+                        .require_equals(true)
+                        .default_missing_value("1")
+                        .value_parser(clap::value_parser!(i64))
+                        .help("Show N more commits past the common ancestor"),
+                )
+                .arg(
+                    Arg::new("list")
+                        .long("list")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with("more")
+                        .help("Only show the branches"),
+                )
+                .arg(
+                    Arg::new("sha1-name")
+                        .long("sha1-name")
+                        .action(ArgAction::SetTrue)
+                        .help("Name commits by abbreviated ID"),
+                )
+                .arg(
+                    Arg::new("no-name")
+                        .long("no-name")
+                        .action(ArgAction::SetTrue)
+                        .help("Don't name commits"),
+                )
+                .arg(
+                    Arg::new("topics")
+                        .long("topics")
+                        .action(ArgAction::SetTrue)
+                        .help("Only show commits not on the first branch"),
+                )
+                .arg(
+                    Arg::new("sparse")
+                        .long("sparse")
+                        .action(ArgAction::SetTrue)
+                        .help("Also show merges reachable from only one branch"),
+                )
+                .arg(Arg::new("revisions").value_name("REV").num_args(0..).help("Defaults to all local branches")),
+        )
+        .subcommand(
+            Command::new("check-ref-format")
+                .about("Check that a ref name is valid, exiting with 1 if not")
+                .arg(
+                    Arg::new("allow-onelevel")
+                        .long("allow-onelevel")
+                        .action(ArgAction::SetTrue)
+                        .help("Accept names without a slash"),
+                )
+                .arg(
+                    Arg::new("refspec-pattern")
+                        .long("refspec-pattern")
+                        .action(ArgAction::SetTrue)
+                        .help("Accept a single `*` as in refspec patterns"),
+                )
+                .arg(
+                    Arg::new("normalize")
+                        .long("normalize")
+                        .action(ArgAction::SetTrue)
+                        .help("Collapse slashes and print the normalized name"),
+                )
+                .arg(
+                    Arg::new("branch")
+                        .long("branch")
+                        .action(ArgAction::SetTrue)
+                        .help("Check a branch name and print it"),
+                )
+                .arg(Arg::new("refname").value_name("REFNAME").required(true).help("The name to check")),
+        )
+        .subcommand(
+            Command::new("reflog")
+                .about("Manage reflog information")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("expire")
+                        .about("Prune old reflog entries")
+                        .arg(Arg::new("refs").value_name("REF").num_args(0..).help("Refs whose reflogs to prune"))
+                        .arg(
+                            Arg::new("all")
+                                .long("all")
+                                .action(ArgAction::SetTrue)
+                                .help("Prune the reflogs of all refs"),
+                        )
+                        .arg(
+                            Arg::new("expire")
+                                .long("expire")
+                                .value_name("TIME")
+                                .help("Prune entries older than TIME; defaults to gc.reflogExpire or 90 days"),
+                        )
+                        .arg(
+                            Arg::new("expire-unreachable")
+                                .long("expire-unreachable")
+                                .value_name("TIME")
+                                .help("Prune entries older than TIME that the ref no longer reaches; defaults to gc.reflogExpireUnreachable or 30 days"),
+                        )
+                        .arg(
+                            Arg::new("dry-run")
+                                .short('n')
+                                .long("dry-run")
+                                .action(ArgAction::SetTrue)
+                                .help("Only show what would be pruned"),
+                        )
+                        .arg(
+                            Arg::new("verbose")
+                                .long("verbose")
+                                .action(ArgAction::SetTrue)
+                                .help("Show each pruned entry"),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("maintenance")
+                .about("Run tasks that keep the repository fast")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("run")
+                        .about("Run maintenance tasks")
+                        .arg(
+                            Arg::new("task")
+                                .long("task")
+                                .value_name("TASK")
+                                .action(ArgAction::Append)
+                                .value_parser(clap::value_parser!(maintenance::Task))
+                                .help("commit-graph, loose-objects, incremental-repack or pack-refs; can be repeated"),
+                        )
+                        .arg(
+                            Arg::new("auto")
+                                .long("auto")
+                                .action(ArgAction::SetTrue)
+                                .help("Only run if there are more loose objects or packs than gc.auto and gc.autoPackLimit allow"),
+                        )
+                        .arg(
+                            Arg::new("detach")
+                                .long("detach")
+                                .action(ArgAction::SetTrue)
+                                .overrides_with("no-detach")
+                                .help("Run in the background, logging problems to .git/gc.log (the default with --auto)"),
+                        )
+                        .arg(
+                            Arg::new("no-detach")
+                                .long("no-detach")
+                                .action(ArgAction::SetTrue)
+                                .overrides_with("detach")
+                                .help("Run in the foreground"),
+                        )
+                        .arg(Arg::new("detached-child").long("detached-child").action(ArgAction::SetTrue).hide(true))
+                        .arg(
+                            Arg::new("quiet")
+                                .long("quiet")
+                                .action(ArgAction::SetTrue)
+                                .help("Don't report progress"),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("rewrite-history")
+                .about("Rewrite every commit, filtering paths, large blobs and messages")
+                .arg(
+                    Arg::new("path")
+                        .long("path")
+                        .value_name("PATH")
+                        .action(ArgAction::Append)
+                        .help("Keep only this file or directory; can be repeated"),
+                )
+                .arg(
+                    Arg::new("path-rename")
+                        .long("path-rename")
+                        .value_name("OLD:NEW")
+                        .action(ArgAction::Append)
+                        .help("Move files from OLD to NEW; can be repeated"),
+                )
+                .arg(
+                    Arg::new("strip-blobs-bigger-than")
+                        .long("strip-blobs-bigger-than")
+                        .value_name("SIZE")
+                        .help("Remove files larger than SIZE, which may end in K, M or G"),
+                )
+                .arg(
+                    Arg::new("message-callback")
+                        .long("message-callback")
+                        .value_name("COMMAND")
+                        .help("Shell command that reads each commit message and prints its replacement"),
+                ),
+        )
+        .subcommand(
+            Command::new("fast-export")
+                .about("Write the history of refs as a fast-import stream")
+                .arg(Arg::new("refs").value_name("REF").num_args(0..).help("Refs to export"))
+                .arg(
+                    Arg::new("all")
+                        .long("all")
+                        .action(ArgAction::SetTrue)
+                        .help("Export every ref"),
+                )
+                .arg(
+                    Arg::new("signed-tags")
+                        .long("signed-tags")
+                        .value_name("MODE")
+                        .value_parser(clap::value_parser!(fast_export::SignedTags))
+                        .default_value("abort")
+                        .help("What to do with signed tags: abort, verbatim, warn, strip or warn-strip"),
+                )
+                .arg(
+                    Arg::new("import-marks")
+                        .long("import-marks")
+                        .value_name("FILE")
+                        .help("Skip objects marked by an earlier export"),
+                )
+                .arg(
+                    Arg::new("export-marks")
+                        .long("export-marks")
+                        .value_name("FILE")
+                        .help("Save the marks to FILE once the export is done"),
+                )
+                .arg(
+                    Arg::new("paths")
+                        .value_name("PATH")
+                        .num_args(0..)
+                        .last(true)
+                        .help("Only export changes to these paths"),
+                ),
+        )
+        .subcommand(
+            Command::new("fast-import")
+                .about("Write the objects and refs described by a fast-import stream on stdin")
+                .arg(
+                    Arg::new("import-marks")
+                        .long("import-marks")
+                        .value_name("FILE")
+                        .help("Load marks saved by an earlier import"),
+                )
+                .arg(
+                    Arg::new("export-marks")
+                        .long("export-marks")
+                        .value_name("FILE")
+                        .help("Save the marks to FILE once the import is done"),
+                ),
+        )
+        .subcommand(
+            Command::new("request-pull")
+                .about("Summarize the changes to pull from a repository, for mailing to its maintainer")
+                .arg(
+                    Arg::new("patch")
+                        .short('p')
+                        .action(ArgAction::SetTrue)
+                        .help("Include the patch"),
+                )
+                .arg(Arg::new("start").value_name("START").required(true).help("The commit the changes are based on"))
+                .arg(Arg::new("url").value_name("URL").required(true).help("The repository to pull from"))
+                .arg(
+                    Arg::new("end")
+                        .value_name("END")
+                        .help("The commit to pull up to, as <local>[:<remote>], HEAD by default"),
+                ),
+        )
+        .subcommand(
+            Command::new("mailsplit")
+                .about("Split an mbox into one file per mail")
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .value_name("DIRECTORY")
+                        .required(true)
+                        .help("Where to write the mails"),
+                )
+                .arg(
+                    Arg::new("bare")
+                        .short('b')
+                        .action(ArgAction::SetTrue)
+                        .help("Treat a mailbox without a From line as a single mail"),
+                )
+                .arg(
+                    Arg::new("precision")
+                        .short('d')
+                        .value_name("DIGITS")
+                        .value_parser(clap::value_parser!(usize))
+                        .help("Digits in the file names, from 3 to 9, 4 by default"),
+                )
+                .arg(
+                    Arg::new("skip")
+                        .short('f')
+                        .value_name("N")
+                        .value_parser(clap::value_parser!(usize))
+                        .help("Start numbering after N"),
+                )
+                .arg(
+                    Arg::new("keep-cr")
+                        .long("keep-cr")
+                        .action(ArgAction::SetTrue)
+                        .help("Keep carriage returns before line ends"),
+                )
+                .arg(
+                    Arg::new("mboxrd")
+                        .long("mboxrd")
+                        .action(ArgAction::SetTrue)
+                        .help("Unescape >From lines"),
+                )
+                .arg(Arg::new("mbox").value_name("MBOX").num_args(0..).help("Defaults to stdin")),
+        )
+        .subcommand(
+            Command::new("mailinfo")
+                .about("Extract the author, message and patch from a mail on stdin")
+                .arg(
+                    Arg::new("keep-subject")
+                        .short('k')
+                        .action(ArgAction::SetTrue)
+                        .help("Keep the subject as it is"),
+                )
+                .arg(
+                    Arg::new("keep-non-patch")
+                        .short('b')
+                        .action(ArgAction::SetTrue)
+                        .help("Only strip brackets containing PATCH from the subject"),
+                )
+                .arg(
+                    Arg::new("message-id")
+                        .short('m')
+                        .long("message-id")
+                        .action(ArgAction::SetTrue)
+                        .help("Add the Message-Id header to the message"),
+                )
+                .arg(Arg::new("msg").value_name("MSG").required(true).help("Where to write the message"))
+                .arg(Arg::new("patch").value_name("PATCH").required(true).help("Where to write the patch")),
+        )
+        .subcommand(
+            Command::new("credential-store")
+                .about("Keep credentials in plain text in a file")
+                .arg(
+                    Arg::new("file")
+                        .long("file")
+                        .value_name("PATH")
+                        .help("Use PATH instead of ~/.git-credentials"),
+                )
+                .arg(Arg::new("action").value_name("ACTION").required(true).help("get, store or erase")),
+        )
+        .subcommand(
+            Command::new("credential-cache")
+                .about("Keep credentials in memory for a while")
+                .arg(
+                    Arg::new("timeout")
+                        .long("timeout")
+                        .value_name("SECONDS")
+                        .value_parser(clap::value_parser!(u64))
+                        .help("Forget stored credentials after SECONDS, 900 by default"),
+                )
+                .arg(
+                    Arg::new("socket")
+                        .long("socket")
+                        .value_name("PATH")
+                        .help("Talk to the daemon listening on PATH"),
+                )
+                .arg(Arg::new("action").value_name("ACTION").required(true).help("get, store, erase or exit")),
+        )
+        .subcommand(
+            Command::new("credential-cache--daemon")
+                .about("Serve the credentials cached by credential-cache")
+                .hide(true)
+                .arg(Arg::new("socket").value_name("SOCKET").required(true)),
+        )
+        .subcommand(
+            Command::new("config")
+                .about("Get and set repository or global options")
+                .arg(
+                    Arg::new("global")
+                        .long("global")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with_all(["system", "local", "file"])
+                        .help("Use the global config file"),
+                )
+                .arg(
+                    Arg::new("system")
+                        .long("system")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with_all(["local", "file"])
+                        .help("Use the system config file"),
+                )
+                .arg(
+                    Arg::new("local")
+                        .long("local")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with("file")
+                        .help("Use the repository config file"),
+                )
+                .arg(
+                    Arg::new("file")
+                        .short('f')
+                        .long("file")
+                        .value_name("FILE")
+                        .help("Use the given config file"),
+                )
+                .arg(Arg::new("get").long("get").action(ArgAction::SetTrue).help("Get the last value of a key"))
+                .arg(Arg::new("get-all").long("get-all").action(ArgAction::SetTrue).help("Get every value of a key"))
+                .arg(Arg::new("add").long("add").action(ArgAction::SetTrue).help("Add another value for a key"))
+                .arg(Arg::new("unset").long("unset").action(ArgAction::SetTrue).help("Remove a key"))
+                .arg(
+                    Arg::new("unset-all")
+                        .long("unset-all")
+                        .action(ArgAction::SetTrue)
+                        .help("Remove every value of a key"),
+                )
+                .arg(
+                    Arg::new("list")
+                        .short('l')
+                        .long("list")
+                        .action(ArgAction::SetTrue)
+                        .help("List every variable with its value"),
+                )
+                .arg(
+                    Arg::new("edit")
+                        .short('e')
+                        .long("edit")
+                        .action(ArgAction::SetTrue)
+                        .help("Open the config file in the editor"),
+                )
+                .group(
+                    clap::ArgGroup::new("action").args(["get", "get-all", "add", "unset", "unset-all", "list", "edit"]),
+                )
+                .arg(Arg::new("args").value_name("ARGS").num_args(0..=2).help("The key, and the value to set")),
+        )
+        .subcommand(
+            Command::new("completions")
+                .about("Print a shell completion script")
+                .arg(
+                    Arg::new("refs")
+                        .long("refs")
+                        .action(ArgAction::SetTrue)
+                        .hide(true)
+                        .help("List the ref names to complete"),
+                )
+                .arg(
+                    Arg::new("shell")
+                        .value_name("SHELL")
+                        .value_parser(completion::SHELLS.to_vec())
+                        .required_unless_present("refs")
+                        .help("The shell to complete for"),
+                ),
+        )
+        .subcommand(
+            Command::new("help")
+                .about("Show the manual page of a command")
+                .arg(Arg::new("command").value_name("COMMAND").num_args(0..).help("The command, and its subcommand")),
+        )
+        .subcommand(
+            Command::new("lfs")
+                .about("Store large files outside the repository with Git LFS")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("install")
+                        .about("Set up the lfs filter driver")
+                        .arg(
+                            Arg::new("local")
+                                .long("local")
+                                .action(ArgAction::SetTrue)
+                                .help("Configure the repository instead of the global config"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("track")
+                        .about("Track files with LFS in .gitattributes, or list the tracked patterns")
+                        .arg(Arg::new("patterns").value_name("PATTERN").num_args(0..).help("The patterns to track")),
+                )
+                .subcommand(
+                    Command::new("clean")
+                        .about("Store the content on stdin and print its pointer")
+                        .arg(Arg::new("file").value_name("FILE").help("The path of the file, for filter drivers")),
+                )
+                .subcommand(
+                    Command::new("smudge")
+                        .about("Print the content of the pointer on stdin, downloading it if needed")
+                        .arg(Arg::new("file").value_name("FILE").help("The path of the file, for filter drivers")),
+                )
+                .subcommand(
+                    Command::new("push")
+                        .about("Upload the LFS objects that the revisions need to a remote")
+                        .arg(Arg::new("remote").value_name("REMOTE").required(true).help("The remote to upload to"))
+                        .arg(
+                            Arg::new("revisions")
+                                .value_name("REVISION")
+                                .num_args(1..)
+                                .required(true)
+                                .help("The revisions being pushed"),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("bundle")
+                .about("Move commits between repositories as files")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("create")
+                        .about("Write the commits and refs that some revisions select to a bundle")
+                        .arg(
+                            Arg::new("file")
+                                .value_name("FILE")
+                                .value_parser(clap::value_parser!(PathBuf))
+                                .required(true)
+                                .help("The bundle to write"),
+                        )
+                        .arg(
+                            Arg::new("args")
+                                .value_name("REVISION")
+                                .num_args(1..)
+                                .required(true)
+                                .allow_hyphen_values(true)
+                                .trailing_var_arg(true)
+                                .help("The refs to bundle, and revisions such as ^origin/main the receiver has"),
+                        ),
+                )
+                .subcommand(
+                    Command::new("verify")
+                        .about("Check that a bundle is valid and the repository has its prerequisites")
+                        .arg(
+                            Arg::new("quiet")
+                                .short('q')
+                                .long("quiet")
+                                .action(ArgAction::SetTrue)
+                                .help("Only report whether the bundle is okay"),
+                        )
+                        .arg(
+                            Arg::new("file")
+                                .value_name("FILE")
+                                .value_parser(clap::value_parser!(PathBuf))
+                                .required(true)
+                                .help("The bundle to check"),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("export-html")
+                .about("Write commits and their diffs as static HTML pages")
+                .arg(
+                    Arg::new("range")
+                        .value_name("REVISION_RANGE")
+                        .required(true)
+                        .allow_hyphen_values(true)
+                        .help("The commits to export, such as main or v1.0..main"),
+                )
+                .arg(
+                    Arg::new("directory")
+                        .value_name("DIRECTORY")
+                        .value_parser(clap::value_parser!(PathBuf))
+                        .required(true)
+                        .help("Where to write the pages"),
+                ),
+        )
+        .subcommand(
+            Command::new("browse")
+                .about("Browse the repository in a web browser")
+                .arg(
+                    Arg::new("port")
+                        .short('p')
+                        .long("port")
+                        .value_name("PORT")
+                        .value_parser(clap::value_parser!(u16))
+                        .default_value("1234")
+                        .help("The port to serve on, 0 for any free one"),
+                ),
+        )
+        .subcommand(
+            Command::new("stats")
+                .about("Report what the objects of the repository take up")
+                .arg(
+                    Arg::new("top")
+                        .long("top")
+                        .value_name("N")
+                        .value_parser(clap::value_parser!(usize))
+                        .default_value("10")
+                        .help("How many blobs, trees and extensions to list"),
+                ),
+        )
+        .subcommand(
+            Command::new("verify-objects")
+                .about("Re-hash every object to check the object store for corruption")
+                .arg(
+                    Arg::new("threads")
+                        .long("threads")
+                        .value_name("N")
+                        .value_parser(clap::value_parser!(usize))
+                        .help("How many threads to hash on, one per CPU by default"),
+                )
+                .arg(
+                    Arg::new("progress")
+                        .long("progress")
+                        .action(ArgAction::SetTrue)
+                        .help("Report progress even if stderr is not a terminal"),
+                )
+                .arg(
+                    Arg::new("no-progress")
+                        .long("no-progress")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with("progress")
+                        .help("Don't report progress"),
+                ),
+        )
+        .subcommand(
+            Command::new("copy-objects")
+                .about("Copy the objects this repository lacks from another local repository")
+                .arg(
+                    Arg::new("from")
+                        .long("from")
+                        .value_name("REPOSITORY")
+                        .value_parser(clap::value_parser!(PathBuf))
+                        .required(true)
+                        .help("The repository to copy from"),
+                )
+                .arg(
+                    Arg::new("progress")
+                        .long("progress")
+                        .action(ArgAction::SetTrue)
+                        .help("Report progress even if stderr is not a terminal"),
+                )
+                .arg(
+                    Arg::new("no-progress")
+                        .long("no-progress")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with("progress")
+                        .help("Don't report progress"),
+                ),
+        )
+        .subcommand(
+            Command::new("pack-objects")
+                .about("Write the objects listed on stdin into a pack")
+                .arg(
+                    Arg::new("base-name")
+                        .value_name("BASE_NAME")
+                        .value_parser(clap::value_parser!(PathBuf))
+                        .required_unless_present("stdout")
+                        .help("Store the pack and its index as <BASE_NAME>-<checksum>.pack and .idx"),
+                )
+                .arg(
+                    Arg::new("stdout")
+                        .long("stdout")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with("base-name")
+                        .help("Write the pack to stdout instead"),
+                )
+                .args(tuning_args())
+                .arg(
+                    Arg::new("progress")
+                        .long("progress")
+                        .action(ArgAction::SetTrue)
+                        .help("Report progress even if stderr is not a terminal"),
+                )
+                .arg(
+                    Arg::new("no-progress")
+                        .long("no-progress")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with("progress")
+                        .help("Don't report progress"),
+                ),
+        )
+        .subcommand(
+            Command::new("repack")
+                .about("Pack the loose objects of the repository")
+                .arg(
+                    Arg::new("all")
+                        .short('a')
+                        .action(ArgAction::SetTrue)
+                        .help("Pack every reachable object into one pack"),
+                )
+                .arg(
+                    Arg::new("geometric")
+                        .short('g')
+                        .long("geometric")
+                        .value_name("FACTOR")
+                        .value_parser(clap::value_parser!(usize))
+                        .conflicts_with("all")
+                        .help("Roll up the smallest packs so that each pack has FACTOR times the objects of the last"),
+                )
+                .arg(
+                    Arg::new("delete")
+                        .short('d')
+                        .action(ArgAction::SetTrue)
+                        .help("Remove the packs and loose objects the new pack makes redundant"),
+                )
+                .arg(
+                    Arg::new("write-midx")
+                        .short('m')
+                        .long("write-midx")
+                        .action(ArgAction::SetTrue)
+                        .help("Write a multi-pack-index of the packs afterwards"),
+                )
+                .arg(
+                    Arg::new("write-bitmap-index")
+                        .short('b')
+                        .long("write-bitmap-index")
+                        .action(ArgAction::SetTrue)
+                        .overrides_with("no-write-bitmap-index")
+                        .help("Write reachability bitmaps for the new pack, with -a"),
+                )
+                .arg(
+                    Arg::new("no-write-bitmap-index")
+                        .long("no-write-bitmap-index")
+                        .action(ArgAction::SetTrue)
+                        .overrides_with("write-bitmap-index")
+                        .help("Don't write bitmaps, whatever repack.writeBitmaps says"),
+                )
+                .args(tuning_args()),
+        )
+        .subcommand(
+            Command::new("commit-graph")
+                .about("Write a commit-graph file that speeds up history walks")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("write")
+                        .about("Write a commit-graph of every commit reachable from a ref")
+                        .arg(
+                            Arg::new("reachable")
+                                .long("reachable")
+                                .action(ArgAction::SetTrue)
+                                .help("Start from every ref (the only mode supported)"),
+                        )
+                        .arg(
+                            Arg::new("split")
+                                .long("split")
+                                .action(ArgAction::SetTrue)
+                                .help("Add a layer to the chain in info/commit-graphs instead of rewriting the graph"),
+                        )
+                        .arg(
+                            Arg::new("changed-paths")
+                                .long("changed-paths")
+                                .action(ArgAction::SetTrue)
+                                .help("Record Bloom filters of the paths each commit changes"),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("update-ref")
+                .about("Update, create or delete refs, several at once with --stdin")
+                .arg(Arg::new("delete").short('d').action(ArgAction::SetTrue).help("Delete the ref"))
+                .arg(
+                    Arg::new("no-deref")
+                        .long("no-deref")
+                        .action(ArgAction::SetTrue)
+                        .help("Change a symbolic ref itself, not the ref it points to"),
+                )
+                .arg(
+                    Arg::new("stdin")
+                        .long("stdin")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with("delete")
+                        .help("Read commands from stdin and apply them in transactions"),
+                )
+                .arg(Arg::new("z").short('z').action(ArgAction::SetTrue).help("Read NUL-terminated fields from stdin"))
+                .arg(
+                    Arg::new("values")
+                        .value_name("ARGS")
+                        .num_args(0..)
+                        .help("The ref, its new value unless deleting, and the value it must have first"),
+                ),
+        )
+        .subcommand(
+            Command::new("upload-pack")
+                .about("Send the objects a fetch or clone asks for, speaking the pack protocol on stdin and stdout")
+                .arg(
+                    Arg::new("stateless-rpc")
+                        .long("stateless-rpc")
+                        .action(ArgAction::SetTrue)
+                        .help("Answer a single request, as over smart HTTP"),
+                )
+                .arg(
+                    Arg::new("advertise-refs")
+                        .long("advertise-refs")
+                        .action(ArgAction::SetTrue)
+                        .help("Only list the refs and capabilities, then exit"),
+                )
+                .arg(Arg::new("directory").required(true).help("The repository to serve")),
+        )
+        .subcommand(
+            Command::new("checkout-index")
+                .about("Copy files from the index to the working tree")
+                .arg(
+                    Arg::new("all")
+                        .short('a')
+                        .long("all")
+                        .action(ArgAction::SetTrue)
+                        .help("Check out every file in the index"),
+                )
+                .arg(
+                    Arg::new("force")
+                        .short('f')
+                        .long("force")
+                        .action(ArgAction::SetTrue)
+                        .help("Overwrite files that differ from the index"),
+                )
+                .arg(
+                    Arg::new("prefix")
+                        .long("prefix")
+                        .value_name("STRING")
+                        .help("Prepend STRING to each path, such as `export/` to write elsewhere"),
+                )
+                .arg(Arg::new("paths").value_name("FILE").num_args(0..).help("The files to check out")),
+        )
+        .subcommand(
+            Command::new("var")
+                .about("Show the identity or editor git would use")
+                .arg(
+                    Arg::new("variable")
+                        .value_name("VARIABLE")
+                        .required(true)
+                        .value_parser(["GIT_AUTHOR_IDENT", "GIT_COMMITTER_IDENT", "GIT_EDITOR"])
+                        .help("The variable to print"),
+                ),
+        )
+        .subcommand(
+            Command::new("interpret-trailers")
+                .about("Add or parse trailers such as Signed-off-by in commit messages")
+                .arg(
+                    Arg::new("trailer")
+                        .long("trailer")
+                        .value_name("KEY=VALUE")
+                        .action(ArgAction::Append)
+                        .help("A trailer to add, as `Key: value` or `Key=value`"),
+                )
+                .arg(
+                    Arg::new("parse")
+                        .long("parse")
+                        .visible_alias("only-trailers")
+                        .action(ArgAction::SetTrue)
+                        .help("Print only the existing trailers, unfolded"),
+                )
+                .arg(
+                    Arg::new("in-place")
+                        .long("in-place")
+                        .action(ArgAction::SetTrue)
+                        .help("Edit the files instead of printing the result"),
+                )
+                .arg(
+                    Arg::new("files")
+                        .value_name("FILE")
+                        .num_args(0..)
+                        .help("The messages to process, stdin if none"),
+                ),
+        )
+        .subcommand(
+            Command::new("name-rev")
+                .about("Find symbolic names for commits, such as main~2")
+                .arg(
+                    Arg::new("tags")
+                        .long("tags")
+                        .action(ArgAction::SetTrue)
+                        .help("Only use tags to name the commits"),
+                )
+                .arg(
+                    Arg::new("name-only")
+                        .long("name-only")
+                        .action(ArgAction::SetTrue)
+                        .help("Print only the names, not the commits"),
+                )
+                .arg(
+                    Arg::new("all")
+                        .long("all")
+                        .action(ArgAction::SetTrue)
+                        .help("Name every commit reachable from a ref"),
+                )
+                .arg(
+                    Arg::new("annotate-stdin")
+                        .long("annotate-stdin")
+                        .visible_alias("stdin")
+                        .action(ArgAction::SetTrue)
+                        .help("Append the names to the full commit IDs found in stdin"),
+                )
+                .arg(
+                    Arg::new("revisions")
+                        .value_name("COMMIT")
+                        .num_args(0..)
+                        .help("The commits to name"),
+                ),
+        )
+        .subcommand(
+            Command::new("notes")
+                .about("Attach notes to objects without changing them")
+                .subcommand(
+                    Command::new("add")
+                        .about("Add a note to an object")
+                        .arg(
+                            Arg::new("message")
+                                .short('m')
+                                .long("message")
+                                .value_name("MESSAGE")
+                                .required(true)
+                                .help("The note's content"),
+                        )
+                        .arg(
+                            Arg::new("force")
+                                .short('f')
+                                .long("force")
+                                .action(ArgAction::SetTrue)
+                                .help("Replace an existing note"),
+                        )
+                        .arg(Arg::new("object").value_name("OBJECT").default_value("HEAD").help("The object to annotate")),
+                )
+                .subcommand(
+                    Command::new("show")
+                        .about("Show the note attached to an object")
+                        .arg(Arg::new("object").value_name("OBJECT").default_value("HEAD").help("The annotated object")),
+                )
+                .subcommand(
+                    Command::new("list")
+                        .about("List the notes and the objects they annotate")
+                        .arg(Arg::new("object").value_name("OBJECT").help("Only show the note of this object")),
+                ),
+        )
+        .subcommand(
+            Command::new("replace")
+                .about("Read other objects in place of existing ones")
+                .arg(
+                    Arg::new("force")
+                        .short('f')
+                        .long("force")
+                        .action(ArgAction::SetTrue)
+                        .help("Overwrite an existing replacement"),
+                )
+                .arg(
+                    Arg::new("delete")
+                        .short('d')
+                        .long("delete")
+                        .action(ArgAction::SetTrue)
+                        .help("Remove the replacements of the given objects"),
+                )
+                .arg(
+                    Arg::new("objects")
+                        .value_name("OBJECT")
+                        .num_args(0..)
+                        .help("The object and its replacement, or the objects to stop replacing"),
+                ),
+        )
+        .subcommand(
+            Command::new("remote")
+                .about("Manage the tracked remote repositories")
+                .arg(
+                    Arg::new("verbose")
+                        .short('v')
+                        .long("verbose")
+                        .action(ArgAction::SetTrue)
+                        .help("Show the URL after each remote name"),
+                )
+                .subcommand(
+                    Command::new("add")
+                        .about("Add a remote")
+                        .arg(Arg::new("name").value_name("NAME").required(true).help("The name of the remote"))
+                        .arg(Arg::new("url").value_name("URL").required(true).help("The URL to fetch from")),
+                )
+                .subcommand(
+                    Command::new("remove")
+                        .visible_alias("rm")
+                        .about("Remove a remote and its remote-tracking branches")
+                        .arg(Arg::new("name").value_name("NAME").required(true).help("The name of the remote")),
+                )
+                .subcommand(
+                    Command::new("set-url")
+                        .about("Change the URL of a remote")
+                        .arg(Arg::new("name").value_name("NAME").required(true).help("The name of the remote"))
+                        .arg(Arg::new("url").value_name("URL").required(true).help("The new URL")),
+                ),
+        )
+        .subcommand(
+            Command::new("submodule")
+                .about("Inspect, initialize and check out submodules")
+                .subcommand_required(true)
+                .subcommand(Command::new("init").about("Register the submodules from .gitmodules in the config"))
+                .subcommand(Command::new("status").about("Show the commits recorded for each submodule"))
+                .subcommand(
+                    Command::new("update")
+                        .about("Clone the registered submodules from local repositories and check out their commits"),
+                ),
+        )
+        .subcommand(
+            Command::new("submodule--checkout")
+                .about("Check out a commit inside a submodule for submodule update")
+                .hide(true)
+                .arg(Arg::new("commit").value_name("COMMIT").required(true)),
+        )
+        .subcommand(
+            Command::new("sparse-checkout")
+                .about("Reduce the worktree to a subset of the tracked files")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("init")
+                        .about("Enable sparse checkout with only the top-level files")
+                        .args(cone_args()),
+                )
+                .subcommand(
+                    Command::new("set")
+                        .about("Set the directories, or patterns with --no-cone, to check out")
+                        .args(cone_args())
+                        .arg(
+                            Arg::new("patterns")
+                                .value_name("PATTERN")
+                                .num_args(0..)
+                                .help("Directories in cone mode, gitignore-style patterns otherwise"),
+                        ),
+                )
+                .subcommand(Command::new("list").about("List the sparse directories or patterns")),
+        );
+    with_verbosity(cli)
 }
 
+/// Gives every subcommand that has no `-q` or `-v` of its own the
+/// top-level ones, so that they can follow the command name too, as in
+/// `init -q`. Subcommands such as `ls-files -v` keep their own meaning.
+fn with_verbosity(mut command: Command) -> Command {
+    for subcommand in command.get_subcommands_mut() {
+        let free = |short: char, long: &str| {
+            !subcommand.get_arguments().any(|arg| arg.get_short() == Some(short) || arg.get_long() == Some(long))
+        };
+        let (quiet, verbose) = (free('q', "quiet"), free('v', "verbose"));
+
+        let mut taken = std::mem::take(subcommand);
+        if quiet {
+            taken = taken.arg(
+                Arg::new("command-quiet")
+                    .short('q')
+                    .long("quiet")
+                    .action(ArgAction::SetTrue)
+                    .help("Only report results and errors, not what a command is doing"),
+            );
+        }
+        if verbose {
+            taken = taken.arg(
+                Arg::new("command-verbose")
+                    .short('v')
+                    .long("verbose")
+                    .action(ArgAction::Count)
+                    .help("Report more of what a command is doing, twice for even more"),
+            );
+        }
+        if quiet && verbose {
+            taken = taken.mut_arg("command-quiet", |arg| arg.conflicts_with("command-verbose"));
+        }
+        *subcommand = with_verbosity(taken);
+    }
+    command
+}
+
+/// The `-q` and `-v` given before the command or after it, as added by
+/// `with_verbosity`.
+fn verbosity(matches: &ArgMatches) -> (bool, u8) {
+    let (mut quiet, mut verbose) = (matches.get_flag("quiet"), matches.get_count("verbose"));
+    let mut level = matches;
+    while let Some((_, subcommand)) = level.subcommand() {
+        quiet |= subcommand.try_get_one::<bool>("command-quiet").ok().flatten().copied().unwrap_or(false);
+        verbose += subcommand.try_get_one::<u8>("command-verbose").ok().flatten().copied().unwrap_or(0);
+        level = subcommand;
+    }
+    (quiet, verbose)
+}
+
+fn revisions_arg(help: &'static str) -> Arg {
+    Arg::new("revisions")
+        .value_name("REVISION")
+        .num_args(0..)
+        .help(help)
+}
+
+/// `--window`, `--depth` and `--threads` of `pack-objects` and `repack`.
+fn tuning_args() -> [Arg; 3] {
+    [
+        Arg::new("window")
+            .long("window")
+            .value_name("N")
+            .value_parser(clap::value_parser!(usize))
+            .help("How many objects to try each one as a delta against, pack.window or 10 by default"),
+        Arg::new("depth")
+            .long("depth")
+            .value_name("N")
+            .value_parser(clap::value_parser!(usize))
+            .help("The longest delta chain to write, pack.depth or 50 by default"),
+        Arg::new("threads")
+            .long("threads")
+            .value_name("N")
+            .value_parser(clap::value_parser!(usize))
+            .help("How many threads to search for deltas on, pack.threads or one per CPU by default"),
+    ]
+}
+
+fn tuning(matches: &ArgMatches) -> repack::Tuning {
+    repack::Tuning {
+        window: matches.get_one::<usize>("window").copied(),
+        depth: matches.get_one::<usize>("depth").copied(),
+        threads: matches.get_one::<usize>("threads").copied(),
+    }
+}
+
+/// `--color[=<when>]` and `--no-color`.
+fn color_args() -> [Arg; 2] {
+    [
+        Arg::new("color")
+            .long("color")
+            .value_name("WHEN")
+            .num_args(0..=1)
+            .require_equals(true)
+            .default_missing_value("always")
+            .value_parser(["always", "never", "auto"])
+            .help("Color the output always, never, or only on a terminal"),
+        Arg::new("no-color")
+            .long("no-color")
+            .action(ArgAction::SetTrue)
+            .overrides_with("color")
+            .help("Don't color the output"),
+    ]
+}
+
+fn color_when(matches: &ArgMatches) -> Option<color::When> {
+    match matches.get_flag("no-color") {
+        true => Some(color::When::Never),
+        false => matches.get_one::<String>("color").and_then(|when| color::When::parse(when)),
+    }
+}
+
+/// Sends the output of the command `matches` run through the pager, if
+/// it is for a terminal and `--paginate`, `--no-pager`, `pager.<command>`
+/// or the command's own default say to page it.
+fn start_pager(matches: &ArgMatches) -> anyhow::Result<()> {
+    let flag = match (matches.get_flag("paginate"), matches.get_flag("no-pager")) {
+        (true, _) => Some(true),
+        (_, true) => Some(false),
+        _ => None,
+    };
+    let Some((command, command_matches)) = matches.subcommand() else {
+        return Ok(());
+    };
+    if flag == Some(false) || !std::io::stdout().is_terminal() {
+        return Ok(());
+    }
+    // Like git, page what is for reading rather than what changes things.
+    let by_default = match command {
+        "log" | "diff" | "range-diff" => true,
+        "branch" => !command_matches.contains_id("set-upstream-to"),
+        _ => false,
+    };
+    let config = match repository::current() {
+        Ok(repository) => repository.config()?,
+        Err(_) => config::Config::global()?,
+    };
+    match pager::program(&config, command) {
+        Some(program) if pager::wanted(&config, command, flag, by_default) => pager::start(&program),
+        _ => Ok(()),
+    }
+}
+
+fn cone_args() -> [Arg; 2] {
+    [
+        Arg::new("cone")
+            .long("cone")
+            .action(ArgAction::SetTrue)
+            .overrides_with("no-cone")
+            .help("Match whole directories, the default"),
+        Arg::new("no-cone")
+            .long("no-cone")
+            .action(ArgAction::SetTrue)
+            .overrides_with("cone")
+            .help("Use full gitignore-style patterns"),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cli_is_consistent() {
+        cli().debug_assert();
+    }
+
+    #[test]
+    fn verbosity_follows_the_command() {
+        let parse = |args: &[&str]| verbosity(&cli().try_get_matches_from(args).unwrap());
+        assert_eq!(parse(&["git", "init", "-q"]), (true, 0));
+        assert_eq!(parse(&["git", "-q", "init"]), (true, 0));
+        assert_eq!(parse(&["git", "-v", "copy-objects", "-v", "--from", "x"]), (false, 2));
+        assert_eq!(parse(&["git", "worktree", "list", "-v"]), (false, 1));
+        // Commands with a `-v` of their own keep it.
+        assert_eq!(parse(&["git", "ls-files", "-v"]), (false, 0));
+        assert!(cli().try_get_matches_from(["git", "init", "-q", "-v"]).is_err());
+    }
+
+    #[test]
+    fn pager_flags() {
+        let matches = cli().try_get_matches_from(["git", "-p", "--no-pager", "log"]).unwrap();
+        assert!(matches.get_flag("no-pager") && !matches.get_flag("paginate"));
+        let matches = cli().try_get_matches_from(["git", "--no-pager", "--paginate", "branch"]).unwrap();
+        assert!(matches.get_flag("paginate") && !matches.get_flag("no-pager"));
+    }
+}
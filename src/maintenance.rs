@@ -0,0 +1,322 @@
+use std::fs;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::time::UNIX_EPOCH;
+
+use anyhow::anyhow;
+
+use crate::commit_graph::{self, WriteOptions};
+use crate::config::{self, Config};
+use crate::date;
+use crate::refs;
+use crate::repack;
+use crate::repository::{self, Repository};
+use crate::verbose;
+
+/// The tasks `maintenance run` knows about, in the order they run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Task {
+    CommitGraph,
+    LooseObjects,
+    IncrementalRepack,
+    PackRefs,
+}
+
+const TASKS: [Task; 4] = [Task::CommitGraph, Task::LooseObjects, Task::IncrementalRepack, Task::PackRefs];
+
+impl Task {
+    pub fn name(self) -> &'static str {
+        match self {
+            Task::CommitGraph => "commit-graph",
+            Task::LooseObjects => "loose-objects",
+            Task::IncrementalRepack => "incremental-repack",
+            Task::PackRefs => "pack-refs",
+        }
+    }
+}
+
+impl FromStr for Task {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> anyhow::Result<Task> {
+        TASKS.into_iter()
+            .find(|task| task.name() == name)
+            .ok_or(anyhow!("'{}' is not a valid task", name))
+    }
+}
+
+/// Reads an integer `gc.*` setting, or `default` if it isn't set.
+fn gc_limit(config: &Config, name: &str, default: i64) -> anyhow::Result<i64> {
+    match config.get(&format!("gc.{}", name.to_lowercase())) {
+        Some(value) => value.parse().map_err(|_| anyhow!("Bad gc.{} value: {}", name, value)),
+        None => Ok(default),
+    }
+}
+
+/// Whether there are enough loose objects or packs for `--auto` to run
+/// maintenance: more than `gc.auto` loose objects, 6700 by default, or
+/// more than `gc.autoPackLimit` packs, 50 by default. Like git, loose
+/// objects are estimated from the `17/` directory alone, and `gc.auto=0`
+/// turns both checks off.
+fn needs_gc(repository: &Repository, config: &Config) -> anyhow::Result<bool> {
+    let auto = gc_limit(config, "auto", 6700)?;
+    if auto <= 0 {
+        return Ok(false);
+    }
+
+    let threshold = (auto as usize).div_ceil(256);
+    let loose = fs::read_dir(repository.object_dir().join("17"))
+        .map(|entries| {
+            entries.flatten()
+                .filter(|entry| {
+                    let name = entry.file_name();
+                    let name = name.to_string_lossy();
+                    name.len() == repository.object_format().byte_len() * 2 - 2
+                        && name.bytes().all(|b| b.is_ascii_hexdigit())
+                })
+                .count()
+        })
+        .unwrap_or(0);
+    if loose > threshold {
+        return Ok(true);
+    }
+
+    let pack_limit = gc_limit(config, "autoPackLimit", 50)?;
+    if pack_limit <= 0 {
+        return Ok(false);
+    }
+    let packs = fs::read_dir(repository.object_dir().join("pack"))
+        .map(|entries| {
+            entries.flatten()
+                .map(|entry| entry.path())
+                // Packs marked to be kept are never repacked.
+                .filter(|path| path.extension().is_some_and(|ext| ext == "pack"))
+                .filter(|path| !path.with_extension("keep").exists())
+                .count()
+        })
+        .unwrap_or(0);
+    Ok(packs as i64 > pack_limit)
+}
+
+/// Runs maintenance with `--auto` after a command that wrote many
+/// objects, unless `maintenance.auto` is false.
+pub fn run_auto(quiet: bool) -> anyhow::Result<()> {
+    let config = repository::current()?.config()?;
+    let enabled = match config.get("maintenance.auto") {
+        Some(value) => config::parse_bool(value).ok_or(anyhow!("Bad maintenance.auto value: {}", value))?,
+        None => true,
+    };
+    if enabled {
+        run(&[], Options { quiet, auto: true, ..Options::default() })?;
+    }
+    Ok(())
+}
+
+/// How `maintenance run` runs.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Options {
+    pub quiet: bool,
+    /// Only run past the `gc.auto` or `gc.autoPackLimit` thresholds.
+    pub auto: bool,
+    /// Run in the background, by default with `auto` unless
+    /// `maintenance.autoDetach` or `gc.autoDetach` is false.
+    pub detach: Option<bool>,
+    /// This is the background process a detached run started, whose
+    /// stderr is `gc.log.lock`.
+    pub detached_child: bool,
+}
+
+/// Runs `tasks`, or if none are given, those enabled with
+/// `maintenance.<task>.enabled`. Without any such setting, the tasks
+/// standing in for `gc` run: commit-graph and pack-refs.
+pub fn run(tasks: &[Task], options: Options) -> anyhow::Result<()> {
+    let repository = repository::current()?;
+    let config = repository.config()?;
+    if options.auto && !needs_gc(&repository, &config)? {
+        return Ok(());
+    }
+
+    if options.detached_child {
+        let result = run_tasks(&repository, &config, tasks, true);
+        return finish_log(&repository, result);
+    }
+    let detach = match options.detach {
+        Some(detach) => detach,
+        None => options.auto && auto_detach(&config)?,
+    };
+    // Like git, a detached automatic run is skipped while the last one's
+    // complaints are still waiting to be read.
+    if detach && options.auto && report_last_error(&repository, &config)? {
+        return Ok(());
+    }
+    if options.auto && !options.quiet {
+        match detach {
+            true => eprintln!("Auto packing the repository in background for optimum performance."),
+            false => eprintln!("Auto packing the repository for optimum performance."),
+        }
+        eprintln!("See \"git help maintenance\" for manual housekeeping.");
+    }
+
+    match detach {
+        true => spawn_detached(&repository, tasks, options.auto),
+        false => run_tasks(&repository, &config, tasks, options.quiet),
+    }
+}
+
+fn auto_detach(config: &Config) -> anyhow::Result<bool> {
+    for name in ["maintenance.autoDetach", "gc.autoDetach"] {
+        if let Some(value) = config.get(&name.to_lowercase()) {
+            return config::parse_bool(value).ok_or(anyhow!("Bad {} value: {}", name, value));
+        }
+    }
+    Ok(true)
+}
+
+/// Prints what the last detached run logged to `gc.log`, unless the log
+/// is older than `gc.logExpiry`, one day by default. Returns whether it
+/// did.
+fn report_last_error(repository: &Repository, config: &Config) -> anyhow::Result<bool> {
+    let path = repository.git_dir().join("gc.log");
+    let Ok(log) = fs::read_to_string(&path) else { return Ok(false) };
+    let expiry = date::approxidate(config.get("gc.logexpiry").unwrap_or("1.day.ago"))?;
+    let modified = fs::metadata(&path)?.modified()?.duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs() as i64);
+    if log.is_empty() || modified < expiry {
+        return Ok(false);
+    }
+
+    eprintln!(
+        "warning: The last gc run reported the following. Please correct the root cause\n\
+         and remove {}\n\
+         Automatic cleanup will not be performed until the file is removed.\n\n{}",
+        path.display(),
+        log
+    );
+    Ok(true)
+}
+
+/// Starts this command again in the background with its output going to
+/// `gc.log.lock`, unless a detached run already holds it.
+fn spawn_detached(repository: &Repository, tasks: &[Task], auto: bool) -> anyhow::Result<()> {
+    let lock = repository.git_dir().join("gc.log.lock");
+    let log = match fs::OpenOptions::new().write(true).create_new(true).open(&lock) {
+        Ok(log) => log,
+        Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => return Ok(()),
+        Err(err) => return Err(anyhow!("Unable to create '{}': {}", lock.display(), err)),
+    };
+
+    let mut command = Command::new(std::env::current_exe()?);
+    command.args(["maintenance", "run", "--detached-child"]);
+    if auto {
+        command.arg("--auto");
+    }
+    for task in tasks {
+        command.args(["--task", task.name()]);
+    }
+    command.stdin(Stdio::null()).stdout(Stdio::null()).stderr(log);
+    detach_from_terminal(&mut command);
+    if let Err(err) = command.spawn() {
+        fs::remove_file(&lock)?;
+        return Err(anyhow!("Unable to start maintenance in the background: {}", err));
+    }
+    Ok(())
+}
+
+/// Lets the background process outlive the terminal it was started from.
+#[cfg(unix)]
+fn detach_from_terminal(command: &mut Command) {
+    use std::os::unix::process::CommandExt;
+
+    // SAFETY: `setsid` is async-signal-safe and touches no memory of ours.
+    unsafe {
+        command.pre_exec(|| {
+            libc::setsid();
+            Ok(())
+        });
+    }
+}
+
+#[cfg(windows)]
+fn detach_from_terminal(command: &mut Command) {
+    use std::os::windows::process::CommandExt;
+
+    const DETACHED_PROCESS: u32 = 0x8;
+    const CREATE_NEW_PROCESS_GROUP: u32 = 0x200;
+    command.creation_flags(DETACHED_PROCESS | CREATE_NEW_PROCESS_GROUP);
+}
+
+#[cfg(not(any(unix, windows)))]
+fn detach_from_terminal(_command: &mut Command) {}
+
+/// In the background process, keeps what it printed, along with how it
+/// failed, as `gc.log` for the next automatic run to report, or removes
+/// the log if there was nothing to say.
+fn finish_log(repository: &Repository, result: anyhow::Result<()>) -> anyhow::Result<()> {
+    if let Err(err) = &result {
+        eprintln!("error: {:#}", err);
+    }
+    let lock = repository.git_dir().join("gc.log.lock");
+    let log = repository.git_dir().join("gc.log");
+    if fs::metadata(&lock).map_or(0, |metadata| metadata.len()) == 0 {
+        fs::remove_file(&lock)?;
+        if log.exists() {
+            fs::remove_file(&log)?;
+        }
+    } else {
+        fs::rename(&lock, &log)?;
+    }
+    Ok(())
+}
+
+fn run_tasks(repository: &Repository, config: &Config, tasks: &[Task], quiet: bool) -> anyhow::Result<()> {
+    let mut tasks = tasks.to_vec();
+    let mut gc = false;
+    if tasks.is_empty() {
+        let mut configured = false;
+        for task in TASKS {
+            let Some(value) = config.get(&format!("maintenance.{}.enabled", task.name())) else { continue };
+            configured = true;
+            let enabled = config::parse_bool(value)
+                .ok_or(anyhow!("Bad maintenance.{}.enabled value: {}", task.name(), value))?;
+            if enabled {
+                tasks.push(task);
+            }
+        }
+        if !configured {
+            tasks = vec![Task::CommitGraph, Task::PackRefs];
+            gc = true;
+        }
+    }
+    // gc rewrites the whole graph, where the task adds a layer.
+    let split = !gc;
+    tasks.sort();
+    tasks.dedup();
+
+    for task in tasks {
+        verbose!(1, "Running task '{}'", task.name());
+        match task {
+            Task::CommitGraph => {
+                let count = commit_graph::write(repository, WriteOptions { split, ..WriteOptions::default() })?;
+                if !quiet {
+                    eprintln!("Wrote a commit-graph with {} commits", count);
+                }
+            }
+            Task::LooseObjects => {
+                let count = repack::pack_loose(repository, config)?;
+                if !quiet && count > 0 {
+                    eprintln!("Packed {} loose objects", count);
+                }
+            }
+            Task::PackRefs => refs::pack_refs()?,
+            Task::IncrementalRepack => {
+                match config.get("core.multipackindex").and_then(config::parse_bool) {
+                    Some(false) => {
+                        eprintln!("warning: skipping incremental-repack task because core.multiPackIndex is disabled")
+                    }
+                    _ => repack::incremental(repository, config)?,
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,216 @@
+//! Writes the multi-pack-index, `objects/pack/multi-pack-index`, which
+//! lists the objects of every pack in one place so that lookups need not
+//! search each pack's index in turn. See gitformat-pack(5).
+
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::time::SystemTime;
+
+use anyhow::anyhow;
+
+use crate::object_id::{HashAlgorithm, ObjectId};
+use crate::pack::Pack;
+use crate::repository::{create_temp_file, Repository};
+
+/// An object of the multi-pack-index: its ID, the position of the pack it
+/// is taken from among the packs sorted by name, and its offset there.
+pub(crate) type Entry = (ObjectId, usize, u64);
+
+/// Those of `packs` in the object directory of `repository`, sorted by
+/// the names of their indexes as the multi-pack-index lists them.
+pub(crate) fn local_packs<'a>(repository: &Repository, packs: &'a [Pack]) -> Vec<&'a Pack> {
+    let mut packs: Vec<&Pack> = packs
+        .iter()
+        .filter(|pack| pack.path().parent() == Some(repository.object_dir().join("pack").as_path()))
+        .collect();
+    packs.sort_by_key(|pack| pack.path().with_extension("idx"));
+    packs
+}
+
+/// Picks the copy of each object of `packs` that the multi-pack-index
+/// points to: the one in the `preferred` pack, or else in the most
+/// recently modified pack. Returns them sorted by ID.
+pub(crate) fn select(packs: &[&Pack], preferred: Option<&Path>) -> anyhow::Result<Vec<Entry>> {
+    let mut candidates = Vec::new();
+    for (position, pack) in packs.iter().enumerate() {
+        let modified = fs::metadata(pack.path())?.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+        let rank = (Some(pack.path()) != preferred, std::cmp::Reverse(modified), position);
+        candidates.extend(pack.index().objects.iter().map(|(sha, offset)| (*sha, rank, *offset)));
+    }
+    candidates.sort_unstable();
+    candidates.dedup_by_key(|(sha, _, _)| *sha);
+    Ok(candidates.into_iter().map(|(sha, (_, _, position), offset)| (sha, position, offset)).collect())
+}
+
+/// Writes the multi-pack-index of the packs in the object directory,
+/// preferring the copies of objects in the `preferred` pack. Returns how
+/// many objects it lists.
+pub(crate) fn write(repository: &Repository, preferred: Option<&Path>) -> anyhow::Result<usize> {
+    let all = repository.packs();
+    let packs = local_packs(repository, &all);
+    let entries = select(&packs, preferred)?;
+    let names: Vec<String> = packs.iter()
+        .map(|pack| {
+            let index = pack.path().with_extension("idx");
+            let name = index.file_name().ok_or(anyhow!("Bad pack path {}", index.display()))?;
+            Ok(name.to_string_lossy().into_owned())
+        })
+        .collect::<anyhow::Result<_>>()?;
+    let data = serialize(&names, &entries, repository.object_format());
+
+    let pack_dir = repository.object_dir().join("pack");
+    let (temp_path, mut file) = create_temp_file(&pack_dir, "tmp_midx_")?;
+    let result = (|| -> anyhow::Result<()> {
+        file.write_all(&data)?;
+        file.sync_all()?;
+        // The bitmap and reverse index of an older index don't fit this one.
+        clear(repository)?;
+        Ok(fs::rename(&temp_path, pack_dir.join("multi-pack-index"))?)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result?;
+    Ok(entries.len())
+}
+
+/// Removes the multi-pack-index, and its bitmap and reverse index, as
+/// when a pack it lists is deleted.
+pub(crate) fn clear(repository: &Repository) -> anyhow::Result<()> {
+    let pack_dir = repository.object_dir().join("pack");
+    let Ok(entries) = fs::read_dir(&pack_dir) else { return Ok(()) };
+    for entry in entries {
+        let name = entry?.file_name();
+        let name = name.to_string_lossy();
+        let companion = name.starts_with("multi-pack-index-") && (name.ends_with(".bitmap") || name.ends_with(".rev"));
+        if name == "multi-pack-index" || companion {
+            match fs::remove_file(pack_dir.join(&*name)) {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+                _ => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The bytes of a version 1 multi-pack-index of the packs whose indexes
+/// are `names`, sorted, listing `entries`.
+fn serialize(names: &[String], entries: &[Entry], format: HashAlgorithm) -> Vec<u8> {
+    let mut pack_names = Vec::new();
+    for name in names {
+        pack_names.extend_from_slice(name.as_bytes());
+        pack_names.push(0);
+    }
+    pack_names.resize(pack_names.len().next_multiple_of(4), 0);
+
+    let mut fanout = Vec::new();
+    let mut count = 0;
+    for first in 0..=255u8 {
+        count += entries[count..].iter().take_while(|(sha, _, _)| sha.as_ref()[0] == first).count();
+        fanout.extend_from_slice(&(count as u32).to_be_bytes());
+    }
+    let lookup: Vec<u8> = entries.iter().flat_map(|(sha, _, _)| sha.as_ref().to_vec()).collect();
+
+    // Offsets past 31 bits move to the large offsets chunk only if some
+    // offset needs more than 32, as in git.
+    let large_needed = entries.iter().any(|(_, _, offset)| *offset > u32::MAX as u64);
+    let mut offsets = Vec::new();
+    let mut large = Vec::new();
+    for (_, position, offset) in entries {
+        offsets.extend_from_slice(&(*position as u32).to_be_bytes());
+        let offset = match large_needed && *offset >> 31 != 0 {
+            true => {
+                large.extend_from_slice(&offset.to_be_bytes());
+                0x8000_0000 | (large.len() / 8 - 1) as u32
+            }
+            false => *offset as u32,
+        };
+        offsets.extend_from_slice(&offset.to_be_bytes());
+    }
+
+    let mut chunks = vec![(*b"PNAM", pack_names), (*b"OIDF", fanout), (*b"OIDL", lookup), (*b"OOFF", offsets)];
+    if large_needed {
+        chunks.push((*b"LOFF", large));
+    }
+
+    let mut data = b"MIDX".to_vec();
+    let hash_version = match format {
+        HashAlgorithm::Sha1 => 1,
+        HashAlgorithm::Sha256 => 2,
+    };
+    data.extend_from_slice(&[1, hash_version, chunks.len() as u8, 0]);
+    data.extend_from_slice(&(names.len() as u32).to_be_bytes());
+    let mut offset = (data.len() + (chunks.len() + 1) * 12) as u64;
+    for (id, chunk) in &chunks {
+        data.extend_from_slice(id);
+        data.extend_from_slice(&offset.to_be_bytes());
+        offset += chunk.len() as u64;
+    }
+    data.extend_from_slice(&[0; 4]);
+    data.extend_from_slice(&offset.to_be_bytes());
+    for (_, chunk) in chunks {
+        data.extend(chunk);
+    }
+    let checksum = format.hash(&data);
+    data.extend_from_slice(checksum.as_ref());
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pack::{self, PackOptions};
+
+    const FORMAT: HashAlgorithm = HashAlgorithm::Sha1;
+
+    #[test]
+    fn indexes_of_packs() {
+        let dir = std::env::temp_dir().join(format!("git-starter-rust-midx-{}", std::process::id()));
+        let repository = Repository::init_module(&dir, FORMAT).unwrap();
+        let shared = repository.write_object("blob", b"shared", true).unwrap();
+        let mut paths = Vec::new();
+        for name in ["one", "two"] {
+            let own = repository.write_object("blob", name.as_bytes(), true).unwrap();
+            let mut data = Vec::new();
+            let objects = [(shared, 0), (own, 0)];
+            let (written, checksum) = pack::write(&repository, &objects, PackOptions::WHOLE, false, &mut data).unwrap();
+            let index = pack::write_index(&written, &checksum, FORMAT);
+            paths.push(pack::store(&dir.join("objects/pack/pack"), &data, &index, None, &checksum).unwrap());
+        }
+        repository.reload_packs();
+
+        let all = repository.packs();
+        let packs = local_packs(&repository, &all);
+        assert_eq!(packs.len(), 2);
+        for preferred in &paths {
+            let entries = select(&packs, Some(preferred)).unwrap();
+            assert_eq!(entries.len(), 3);
+            let (_, position, _) = entries.iter().find(|(sha, _, _)| *sha == shared).unwrap();
+            assert_eq!(packs[*position].path(), preferred.as_path());
+        }
+
+        assert_eq!(write(&repository, Some(&paths[0])).unwrap(), 3);
+        let data = fs::read(dir.join("objects/pack/multi-pack-index")).unwrap();
+        assert_eq!(&data[..12], b"MIDX\x01\x01\x04\x00\x00\x00\x00\x02");
+        let ids: Vec<&[u8]> = (0..5).map(|i| &data[12 + i * 12..16 + i * 12]).collect();
+        assert_eq!(ids, [&b"PNAM"[..], b"OIDF", b"OIDL", b"OOFF", b"\0\0\0\0"]);
+        let (body, checksum) = data.split_at(data.len() - 20);
+        assert_eq!(FORMAT.hash(body).as_ref(), checksum);
+
+        clear(&repository).unwrap();
+        assert!(!dir.join("objects/pack/multi-pack-index").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn large_offsets() {
+        let sha = FORMAT.hash(b"x");
+        let names = ["pack-a.idx".to_string()];
+        let small = serialize(&names, &[(sha, 0, 0x8000_0000)], FORMAT);
+        assert!(!small.windows(4).any(|id| id == b"LOFF"));
+        let large = serialize(&names, &[(sha, 0, 0x8000_0000), (FORMAT.hash(b"y"), 0, 1 << 32)], FORMAT);
+        assert!(large.windows(4).any(|id| id == b"LOFF"));
+        assert_eq!(large[6], 5);
+    }
+}
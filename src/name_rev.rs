@@ -0,0 +1,228 @@
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+
+use crate::object_id::ObjectId;
+use crate::ident::identity_time;
+use crate::objects::Object;
+use crate::refs;
+use crate::repository::{self, Repository};
+
+/// Merge parents are named from their own `^N` and count as far away, so
+/// first-parent chains win.
+const MERGE_TRAVERSAL_WEIGHT: usize = 65535;
+
+/// A name such as `tags/v1.0~2^2~1`: a tip ref, the merge hops taken from
+/// it, and the first-parent steps after the last hop.
+#[derive(Debug, Clone)]
+struct RevName {
+    tip: String,
+    /// When the tip was tagged, or committed for other refs.
+    tagger_date: i64,
+    generation: usize,
+    distance: usize,
+    from_tag: bool,
+}
+
+impl RevName {
+    fn is_worse_than(&self, other: &RevName) -> bool {
+        // Names based on older tags are preferred, even if farther away.
+        if self.from_tag && other.from_tag {
+            return self.tagger_date > other.tagger_date
+                || (self.tagger_date == other.tagger_date && self.distance > other.distance);
+        }
+        if self.from_tag != other.from_tag {
+            return other.from_tag;
+        }
+        if self.distance != other.distance {
+            return self.distance > other.distance;
+        }
+        self.tagger_date > other.tagger_date
+    }
+
+    fn name(&self) -> String {
+        match self.generation {
+            0 => self.tip.clone(),
+            generation => format!("{}~{}", self.tip.strip_suffix("^0").unwrap_or(&self.tip), generation),
+        }
+    }
+}
+
+/// Symbolic names for commits, found by walking back from refs.
+pub struct NameRev {
+    names: HashMap<ObjectId, RevName>,
+}
+
+impl NameRev {
+    /// Names every commit reachable from a ref, or only from tags if
+    /// `tags_only`.
+    pub fn load(tags_only: bool) -> anyhow::Result<NameRev> {
+        let repository = repository::current()?;
+        let mut name_rev = NameRev { names: HashMap::new() };
+
+        for ref_name in refs::list_refs("refs/")? {
+            let from_tag = ref_name.starts_with("refs/tags/");
+            if tags_only && !from_tag {
+                continue;
+            }
+            let Some(mut sha) = refs::read_ref(&ref_name)? else {
+                continue;
+            };
+
+            let mut tip = shorten(&ref_name);
+            let mut tagger_date = None;
+            let commit = loop {
+                match repository.read(&sha)? {
+                    Object::Tag(tag) => {
+                        tagger_date = tagger_date.or(tag.tagger.as_deref().map(identity_time));
+                        sha = tag.object;
+                        if !tip.ends_with("^0") {
+                            tip.push_str("^0");
+                        }
+                    }
+                    Object::Commit(commit) => break Some(commit),
+                    _ => break None,
+                }
+            };
+            // Tags of trees and blobs name no commits.
+            let Some(commit) = commit else {
+                continue;
+            };
+
+            let name = RevName {
+                tip,
+                tagger_date: tagger_date.unwrap_or_else(|| identity_time(&commit.committer)),
+                generation: 0,
+                distance: 0,
+                from_tag,
+            };
+            name_rev.walk(&repository, sha, name)?;
+        }
+
+        Ok(name_rev)
+    }
+
+    fn walk(&mut self, repository: &Repository, tip: ObjectId, name: RevName) -> anyhow::Result<()> {
+        let mut pending = vec![(tip, name)];
+
+        while let Some((sha, name)) = pending.pop() {
+            if self.names.get(&sha).is_some_and(|existing| !existing.is_worse_than(&name)) {
+                continue;
+            }
+            let Object::Commit(commit) = repository.read(&sha)? else {
+                continue;
+            };
+
+            // Pushed in reverse so the first parent is named first.
+            for (number, parent) in commit.parents.iter().enumerate().rev() {
+                let parent_name = match number {
+                    0 => RevName { generation: name.generation + 1, distance: name.distance + 1, ..name.clone() },
+                    _ => RevName {
+                        tip: match name.generation {
+                            0 => format!("{}^{}", name.tip.strip_suffix("^0").unwrap_or(&name.tip), number + 1),
+                            _ => format!("{}^{}", name.name(), number + 1),
+                        },
+                        generation: 0,
+                        distance: name.distance + MERGE_TRAVERSAL_WEIGHT,
+                        ..name.clone()
+                    },
+                };
+                pending.push((*parent, parent_name));
+            }
+            self.names.insert(sha, name);
+        }
+
+        Ok(())
+    }
+
+    /// The name of `commit`, or `None` if no ref reaches it.
+    pub fn name(&self, commit: &ObjectId) -> Option<String> {
+        self.names.get(commit).map(RevName::name)
+    }
+
+    /// Every named commit with its name, ordered by ID.
+    pub fn all(&self) -> Vec<(ObjectId, String)> {
+        let mut all: Vec<(ObjectId, String)> = self.names.iter()
+            .map(|(sha, name)| (*sha, name.name()))
+            .collect();
+        all.sort();
+        all
+    }
+}
+
+/// Strips `refs/heads/` and `refs/` from a ref name, like git does for
+/// names it shows.
+fn shorten(ref_name: &str) -> String {
+    ref_name.strip_prefix("refs/heads/")
+        .or_else(|| ref_name.strip_prefix("refs/"))
+        .unwrap_or(ref_name)
+        .to_string()
+}
+
+/// Prints `<revision> <name>` for each revision, or just the name if
+/// `name_only`.
+pub fn name_revisions(revisions: &[String], tags_only: bool, name_only: bool) -> anyhow::Result<()> {
+    let name_rev = NameRev::load(tags_only)?;
+
+    for revision in revisions {
+        let Ok(commit) = refs::resolve_revision(revision) else {
+            eprintln!("Could not get sha1 for {}. Skipping.", revision);
+            continue;
+        };
+        let name = name_rev.name(&commit).unwrap_or_else(|| "undefined".to_string());
+        if name_only {
+            println!("{}", name);
+        } else {
+            println!("{} {}", revision, name);
+        }
+    }
+
+    Ok(())
+}
+
+/// Prints every commit reachable from a ref with its name.
+pub fn name_all(tags_only: bool, name_only: bool) -> anyhow::Result<()> {
+    for (sha, name) in NameRev::load(tags_only)?.all() {
+        if name_only {
+            println!("{}", name);
+        } else {
+            println!("{} {}", sha, name);
+        }
+    }
+
+    Ok(())
+}
+
+/// Copies stdin to stdout, following each full commit ID with its name in
+/// parentheses, or replacing it with the name if `name_only`.
+pub fn annotate_stdin(tags_only: bool, name_only: bool) -> anyhow::Result<()> {
+    let name_rev = NameRev::load(tags_only)?;
+    let hex_len = repository::current()?.object_format().byte_len() * 2;
+    let mut stdout = std::io::stdout().lock();
+
+    for line in std::io::stdin().lock().lines() {
+        let line = line?;
+        let mut output = String::new();
+        let mut rest = line.as_str();
+
+        while let Some(start) = rest.find(|c: char| c.is_ascii_hexdigit()) {
+            let run = rest[start..].find(|c: char| !c.is_ascii_hexdigit()).unwrap_or(rest.len() - start);
+            let word = &rest[start..start + run];
+            output.push_str(&rest[..start]);
+
+            let name = (word.len() == hex_len)
+                .then(|| word.parse().ok())
+                .flatten()
+                .and_then(|sha| name_rev.name(&sha));
+            match name {
+                Some(name) if name_only => output.push_str(&name),
+                Some(name) => output.push_str(&format!("{} ({})", word, name)),
+                None => output.push_str(word),
+            }
+            rest = &rest[start + run..];
+        }
+        output.push_str(rest);
+        writeln!(stdout, "{}", output)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,103 @@
+use std::collections::BTreeMap;
+
+use anyhow::anyhow;
+
+use crate::info;
+use crate::object_id::ObjectId;
+use crate::objects::{peel_to_tree, read_tree, Object, Tree, TreeEntry};
+use crate::refs;
+use crate::repository;
+
+const NOTES_REF: &str = "refs/notes/commits";
+
+/// Reads the notes tree into a map from annotated object to note blob.
+/// Fanout directories such as `ab/cdef…` are flattened.
+fn read_notes() -> anyhow::Result<BTreeMap<ObjectId, ObjectId>> {
+    let mut notes = BTreeMap::new();
+    if let Some(commit) = refs::read_ref(NOTES_REF)? {
+        let (_, tree) = peel_to_tree(&commit)?;
+        collect_notes(&tree, "", &mut notes)?;
+    }
+
+    Ok(notes)
+}
+
+fn collect_notes(tree: &ObjectId, prefix: &str, notes: &mut BTreeMap<ObjectId, ObjectId>) -> anyhow::Result<()> {
+    for entry in read_tree(tree)?.entries {
+        let name = format!("{}{}", prefix, entry.name);
+        if entry.mode == 0o40000 {
+            collect_notes(&entry.sha, &name, notes)?;
+        } else if let Ok(object) = name.parse() {
+            notes.insert(object, entry.sha);
+        }
+    }
+
+    Ok(())
+}
+
+/// Attaches `message` as a note to `object`, refusing to replace an
+/// existing note unless `force`.
+pub fn add(object: &str, message: &str, force: bool) -> anyhow::Result<()> {
+    let repository = repository::current()?;
+    let object = refs::resolve_revision(object)?;
+
+    let mut notes = read_notes()?;
+    if notes.contains_key(&object) && !force {
+        return Err(anyhow!(
+            "Cannot add notes. Found existing notes for object {}. Use '-f' to overwrite existing notes",
+            object
+        ));
+    }
+    if notes.contains_key(&object) {
+        info!("Overwriting existing notes for object {}", object);
+    }
+
+    let blob = repository.write(&Object::Blob(format!("{}\n", message.trim_end()).into_bytes()))?;
+    notes.insert(object, blob);
+
+    let entries = notes.into_iter()
+        .map(|(object, blob)| TreeEntry { mode: 0o100644, name: object.to_string(), sha: blob })
+        .collect();
+    let tree = repository.write(&Object::Tree(Tree { entries }))?;
+    let parents: Vec<ObjectId> = refs::read_ref(NOTES_REF)?.into_iter().collect();
+    let commit = repository.commit(&tree, &parents, "Notes added by 'git notes add'")?;
+
+    refs::write_ref(NOTES_REF, &commit)
+}
+
+/// Prints the note attached to `object`.
+pub fn show(object: &str) -> anyhow::Result<()> {
+    let object = refs::resolve_revision(object)?;
+    let note = read_notes()?
+        .remove(&object)
+        .ok_or(anyhow!("No note found for object {}.", object))?;
+
+    match repository::current()?.read(&note)? {
+        Object::Blob(content) => print!("{}", String::from_utf8_lossy(&content)),
+        object => return Err(anyhow!("Note {} is a {}, not a blob", note, object.kind())),
+    }
+
+    Ok(())
+}
+
+/// Prints a `<note> <object>` line for every note, or just the note
+/// attached to `object`.
+pub fn list(object: Option<&str>) -> anyhow::Result<()> {
+    let notes = read_notes()?;
+
+    match object {
+        Some(object) => {
+            let object = refs::resolve_revision(object)?;
+            let note = notes.get(&object)
+                .ok_or(anyhow!("No note found for object {}.", object))?;
+            println!("{}", note);
+        }
+        None => {
+            for (object, note) in notes {
+                println!("{} {}", note, object);
+            }
+        }
+    }
+
+    Ok(())
+}
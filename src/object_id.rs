@@ -0,0 +1,170 @@
+use std::fmt::Display;
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use sha1::{Digest, Sha1};
+
+use crate::sha256;
+
+/// The hash function a repository names its objects with, set by
+/// `extensions.objectFormat`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HashAlgorithm {
+    #[default]
+    Sha1,
+    Sha256,
+}
+
+impl HashAlgorithm {
+    pub fn from_name(name: &str) -> anyhow::Result<HashAlgorithm> {
+        match name.to_lowercase().as_str() {
+            "sha1" => Ok(HashAlgorithm::Sha1),
+            "sha256" => Ok(HashAlgorithm::Sha256),
+            _ => Err(anyhow!("Unknown object format: {}", name)),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            HashAlgorithm::Sha1 => "sha1",
+            HashAlgorithm::Sha256 => "sha256",
+        }
+    }
+
+    /// Length of a raw object ID in bytes.
+    pub fn byte_len(self) -> usize {
+        match self {
+            HashAlgorithm::Sha1 => 20,
+            HashAlgorithm::Sha256 => 32,
+        }
+    }
+
+    pub fn hash(self, data: &[u8]) -> ObjectId {
+        match self {
+            HashAlgorithm::Sha1 => {
+                let mut hasher = Sha1::new();
+                hasher.update(data);
+                ObjectId::Sha1(hasher.finalize().into())
+            }
+            HashAlgorithm::Sha256 => ObjectId::Sha256(sha256::digest(data)),
+        }
+    }
+
+    /// The all-zero ID git uses for "no object", e.g. in hook arguments.
+    pub fn null(self) -> ObjectId {
+        match self {
+            HashAlgorithm::Sha1 => ObjectId::from_bytes([0; 20]),
+            HashAlgorithm::Sha256 => ObjectId::Sha256([0; 32]),
+        }
+    }
+
+    /// Reads a raw object ID from the start of `bytes`.
+    pub fn read(self, bytes: &[u8]) -> Option<ObjectId> {
+        let bytes = bytes.get(..self.byte_len())?;
+        Some(match self {
+            HashAlgorithm::Sha1 => ObjectId::Sha1(bytes.try_into().ok()?),
+            HashAlgorithm::Sha256 => ObjectId::Sha256(bytes.try_into().ok()?),
+        })
+    }
+}
+
+/// The name of an object: a SHA-1 or SHA-256 digest of its contents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ObjectId {
+    Sha1([u8; 20]),
+    Sha256([u8; 32]),
+}
+
+impl ObjectId {
+    /// A SHA-1 ID from its raw bytes, usable in constants.
+    pub const fn from_bytes(bytes: [u8; 20]) -> ObjectId {
+        ObjectId::Sha1(bytes)
+    }
+
+    pub fn algorithm(&self) -> HashAlgorithm {
+        match self {
+            ObjectId::Sha1(_) => HashAlgorithm::Sha1,
+            ObjectId::Sha256(_) => HashAlgorithm::Sha256,
+        }
+    }
+}
+
+impl Display for ObjectId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&hex::encode(self.as_ref()))?;
+
+        Ok(())
+    }
+}
+
+impl AsRef<[u8]> for ObjectId {
+    fn as_ref(&self) -> &[u8] {
+        match self {
+            ObjectId::Sha1(bytes) => bytes,
+            ObjectId::Sha256(bytes) => bytes,
+        }
+    }
+}
+
+/// Why a string or byte slice isn't a valid object ID.
+#[derive(Debug, thiserror::Error)]
+pub enum ParseObjectIdError {
+    #[error("Invalid object ID length: {0}")]
+    Length(usize),
+    #[error("Invalid object ID: {0}")]
+    Hex(#[from] hex::FromHexError),
+}
+
+impl FromStr for ObjectId {
+    type Err = ParseObjectIdError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Check the length first so a wrong-length input reports that
+        // rather than a hex error for an odd number of digits.
+        if s.len() != 40 && s.len() != 64 {
+            return Err(ParseObjectIdError::Length(s.len()));
+        }
+
+        ObjectId::try_from(hex::decode(s)?.as_slice())
+    }
+}
+
+impl TryFrom<&[u8]> for ObjectId {
+    type Error = ParseObjectIdError;
+
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        if let Ok(bytes) = bytes.try_into() {
+            return Ok(ObjectId::Sha1(bytes));
+        }
+        if let Ok(bytes) = bytes.try_into() {
+            return Ok(ObjectId::Sha256(bytes));
+        }
+
+        Err(ParseObjectIdError::Length(bytes.len()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sha256_ids() {
+        // `git hash-object --object-format=sha256` of a file holding "hello\n".
+        let id = HashAlgorithm::Sha256.hash(b"blob 6\0hello\n");
+        assert_eq!(id.to_string(), "2cf8d83d9ee29543b34a87727421fdecb7e3f3a183d337639025de576db9ebb4");
+        assert_eq!(id.algorithm(), HashAlgorithm::Sha256);
+        assert_eq!(id.to_string().parse::<ObjectId>().unwrap(), id);
+        assert_eq!(HashAlgorithm::Sha256.read(id.as_ref()), Some(id));
+        assert_eq!(HashAlgorithm::Sha256.null().to_string(), "0".repeat(64));
+    }
+
+    #[test]
+    fn lengths_pick_the_algorithm() {
+        assert_eq!("0".repeat(40).parse::<ObjectId>().unwrap().algorithm(), HashAlgorithm::Sha1);
+        assert_eq!("0".repeat(64).parse::<ObjectId>().unwrap().algorithm(), HashAlgorithm::Sha256);
+        assert!(matches!("0".repeat(41).parse::<ObjectId>(), Err(ParseObjectIdError::Length(41))));
+        assert!(matches!("g".repeat(40).parse::<ObjectId>(), Err(ParseObjectIdError::Hex(_))));
+        assert_eq!(HashAlgorithm::Sha256.read(&[0; 31]), None);
+    }
+}
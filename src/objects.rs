@@ -0,0 +1,692 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use anyhow::anyhow;
+use bytes::{BufMut, BytesMut};
+
+use crate::attributes::Attributes;
+use crate::config;
+use crate::convert::Convert;
+use crate::fsck;
+use crate::index::{CacheTree, Index, IndexEntry};
+use crate::object_id::{HashAlgorithm, ObjectId};
+use crate::precompose::{self, precompose};
+use crate::refs;
+use crate::repository::{self, git_dir_of, worktree_path, Repository};
+
+/// Settings used while hashing the files of a worktree.
+pub struct TreeOptions {
+    convert: Convert,
+    // With `core.fileMode=false` the executable bit on disk is ignored and
+    // the mode recorded in the index is kept instead.
+    file_mode: bool,
+    index_modes: HashMap<String, u32>,
+    precompose_unicode: bool,
+    cached: CachedTrees,
+}
+
+/// The index as it was when writing started: directories whose cache
+/// tree node is still valid and whose files all match their entries'
+/// stat data reuse the recorded tree instead of being hashed again.
+#[derive(Default)]
+struct CachedTrees {
+    entries: HashMap<String, IndexEntry>,
+    cache_tree: Option<CacheTree>,
+    // Files changed in the same second as the index was written may differ
+    // without their stat data showing it, so they are never trusted.
+    written: Option<SystemTime>,
+}
+
+impl TreeOptions {
+    pub fn load() -> anyhow::Result<TreeOptions> {
+        let repository = repository::current()?;
+        let config = repository.config()?;
+        let file_mode = match config.get("core.filemode") {
+            Some(value) => config::parse_bool(value)
+                .ok_or(anyhow!("Bad core.filemode value: {}", value))?,
+            None => true,
+        };
+        let index_path = repository.git_dir().join("index");
+        let index = Index::read(&index_path, repository.object_format())?;
+        let index_modes = if file_mode {
+            HashMap::new()
+        } else {
+            index.entries.iter().map(|entry| (entry.path.clone(), entry.mode)).collect()
+        };
+        let cached = CachedTrees {
+            entries: index.entries.into_iter()
+                .filter(|entry| entry.stage() == 0)
+                .map(|entry| (entry.path.clone(), entry))
+                .collect(),
+            cache_tree: index.cache_tree,
+            written: fs::metadata(&index_path).and_then(|metadata| metadata.modified()).ok(),
+        };
+
+        Ok(TreeOptions {
+            convert: Convert::from_config(&config)?,
+            file_mode,
+            index_modes,
+            precompose_unicode: precompose::enabled(&config),
+            cached,
+        })
+    }
+
+    /// The tree the index recorded for the directory `path`, if it is
+    /// still valid and every file below it is unchanged.
+    fn cached_tree(&self, path: &Path) -> Option<ObjectId> {
+        let name = self.index_path(path);
+        let mut node = self.cached.cache_tree.as_ref()?;
+        for component in name.split('/').filter(|component| !component.is_empty()) {
+            node = node.subtrees.iter().find(|subtree| subtree.name == component)?;
+        }
+        let (entry_count, sha) = (node.entry_count?, node.sha?);
+
+        let mut unchanged = 0;
+        if !self.unchanged_files(path, entry_count, &mut unchanged)? || unchanged != entry_count {
+            return None;
+        }
+        repository::current().ok()?.object_header(&sha).ok()?;
+        Some(sha)
+    }
+
+    /// Counts the files below `path` into `unchanged`, returning `None`
+    /// or `false` as soon as one of them differs from its index entry or
+    /// there are more than `limit`.
+    fn unchanged_files(&self, path: &Path, limit: usize, unchanged: &mut usize) -> Option<bool> {
+        for entry in fs::read_dir(path).ok()? {
+            let entry = entry.ok()?;
+            if entry.file_name() == ".git" {
+                continue;
+            }
+            let metadata = entry.metadata().ok()?;
+            let file = entry.path();
+            if metadata.is_dir() && !file.join(".git").exists() {
+                if !self.unchanged_files(&file, limit, unchanged)? {
+                    return Some(false);
+                }
+                continue;
+            }
+
+            *unchanged += 1;
+            let index_entry = self.cached.entries.get(&self.index_path(&file))?;
+            let clean = if metadata.is_dir() {
+                index_entry.mode == 0o160000
+                    && refs::read_ref_in(&git_dir_of(&file).ok()?, "HEAD").ok()? == Some(index_entry.sha)
+            } else {
+                let current = IndexEntry::from_file(&file, String::new(), index_entry.mode, index_entry.sha).ok()?;
+                let racy = self.cached.written.is_none_or(|written| {
+                    let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(index_entry.mtime.0.into());
+                    modified + Duration::from_secs(1) > written
+                });
+                !racy
+                    && self.file_mode(&self.index_path(&file), &metadata) == index_entry.mode
+                    && current.same_stat(index_entry)
+            };
+            if !clean || *unchanged > limit {
+                return Some(false);
+            }
+        }
+        Some(true)
+    }
+
+    /// The index path of a file or directory below the worktree root.
+    fn index_path(&self, path: &Path) -> String {
+        let path = worktree_path(path);
+        if self.precompose_unicode { precompose(&path).into_owned() } else { path }
+    }
+
+    fn file_mode(&self, path: &str, metadata: &fs::Metadata) -> u32 {
+        let path = if self.precompose_unicode { precompose(path) } else { Cow::Borrowed(path) };
+        #[cfg(unix)]
+        if self.file_mode {
+            use std::os::unix::fs::PermissionsExt;
+
+            return if metadata.permissions().mode() & 0o100 != 0 { 0o100755 } else { 0o100644 };
+        }
+        #[cfg(not(unix))]
+        let _ = metadata;
+
+        match self.index_modes.get(path.as_ref()) {
+            Some(&0o100755) => 0o100755,
+            _ => 0o100644,
+        }
+    }
+}
+
+pub fn hash_object(
+    filename: &PathBuf,
+    write_to_file: bool,
+    attributes: &Attributes,
+    convert: &Convert,
+) -> anyhow::Result<ObjectId> {
+    let buf = fs::read(filename)?;
+    let buf = convert.to_git(&worktree_path(filename), attributes, &buf)?;
+    let sha = write_object("blob", &buf, write_to_file)?;
+
+    Ok(sha)
+}
+
+/// Hashes the content of `filename` as an object of type `kind`, without
+/// any conversion. Unless `literally`, the type must be known and the
+/// content a well formed object of it.
+pub fn hash_typed_object(filename: &PathBuf, kind: &str, write_to_file: bool, literally: bool) -> anyhow::Result<ObjectId> {
+    let buf = fs::read(filename)?;
+    if !literally {
+        if !matches!(kind, "blob" | "tree" | "commit" | "tag") {
+            return Err(anyhow!("invalid object type \"{}\"", kind));
+        }
+        let problems = fsck::check(kind, &buf, repository::current()?.object_format());
+        if !problems.is_empty() {
+            for problem in problems {
+                eprintln!("error: object fails fsck: {}", problem);
+            }
+            return Err(anyhow!("refusing to create malformed object"));
+        }
+    }
+
+    write_object(kind, &buf, write_to_file)
+}
+
+pub fn write_tree(path: &PathBuf, attributes: &Attributes, options: &TreeOptions) -> anyhow::Result<ObjectId> {
+    if let Some(sha) = options.cached_tree(path) {
+        return Ok(sha);
+    }
+
+    let dir_entries = fs::read_dir(path)?;
+    let mut entries = Vec::new();
+    let mut files = Vec::new();
+
+    let mut attributes = Cow::Borrowed(attributes);
+    if let Ok(content) = fs::read_to_string(path.join(".gitattributes")) {
+        attributes.to_mut().push_file(&worktree_path(path), &content);
+    }
+
+    for entry in dir_entries {
+        let entry = entry?;
+        let name = entry.path();
+
+        let mut last_name = name.file_name()
+            .ok_or(anyhow!("Invalid file name"))?
+            .to_str()
+            .ok_or(anyhow!("Invalid file name"))?
+            .to_string();
+        // Only the repository itself is left out; `.gitmodules`,
+        // `.gitattributes` and other dotfiles are content like any file.
+        if last_name == ".git" {
+            continue;
+        }
+        if options.precompose_unicode {
+            last_name = precompose(&last_name).into_owned();
+        }
+
+        let metadata = entry.metadata()?;
+        // A directory with its own `.git` is a submodule and is recorded
+        // as a gitlink to its checked out commit.
+        let is_submodule = metadata.is_dir() && entry.path().join(".git").exists();
+        let mode: u32 = if is_submodule {
+            0o160000
+        } else if metadata.is_dir() {
+            0o40000
+        } else {
+            options.file_mode(&worktree_path(&entry.path()), &metadata)
+        };
+
+        let sha = if is_submodule {
+            Some(refs::read_ref_in(&git_dir_of(&entry.path())?, "HEAD")?
+                .ok_or(anyhow!("'{}' does not have a commit checked out", entry.path().display()))?)
+        } else if metadata.is_dir() {
+            Some(write_tree(&entry.path(), &attributes, options)?)
+        } else {
+            files.push(entry.path());
+            None
+        };
+
+        entries.push((mode, last_name, sha));
+    }
+
+    // Files are hashed last, all at once, and their IDs handed back out in
+    // the order they were queued.
+    let mut blobs = hash_files(&files, &attributes, &options.convert)?.into_iter();
+    let entries: Vec<TreeEntry> = entries.into_iter()
+        .map(|(mode, name, sha)| {
+            let sha = sha.or_else(|| blobs.next()).expect("Every file was hashed");
+            TreeEntry { mode, name, sha }
+        })
+        .collect();
+    let mut tree = Tree { entries };
+    tree.sort();
+
+    let sha1 = write_object("tree", &tree.serialize(), true)?;
+    Ok(sha1)
+}
+
+/// Hashes and stores `files` as blobs on up to one thread per CPU,
+/// returning their IDs in the same order.
+fn hash_files(files: &[PathBuf], attributes: &Attributes, convert: &Convert) -> anyhow::Result<Vec<ObjectId>> {
+    let threads = std::thread::available_parallelism().map_or(1, |threads| threads.get());
+    let chunk_size = files.len().div_ceil(threads).max(1);
+
+    std::thread::scope(|scope| {
+        let workers: Vec<_> = files.chunks(chunk_size)
+            .map(|chunk| scope.spawn(move || {
+                chunk.iter()
+                    .map(|file| hash_object(file, true, attributes, convert))
+                    .collect::<anyhow::Result<Vec<_>>>()
+            }))
+            .collect();
+
+        let mut shas = Vec::with_capacity(files.len());
+        for worker in workers {
+            shas.extend(worker.join().expect("Hashing thread panicked")?);
+        }
+
+        Ok(shas)
+    })
+}
+
+/// Hashes an object into the repository in the current directory.
+pub fn write_object(kind: &str, content: &[u8], write_to_file: bool) -> anyhow::Result<ObjectId> {
+    repository::current()?.write_object(kind, content, write_to_file)
+}
+
+/// Reads an object from the repository in the current directory.
+pub fn read_object(sha: &ObjectId) -> anyhow::Result<(String, Vec<u8>)> {
+    repository::current()?.find_object(sha)
+}
+
+/// Reads a tree from the repository in the current directory.
+pub fn read_tree(sha: &ObjectId) -> anyhow::Result<Tree> {
+    repository::current()?.read_tree(sha)
+}
+
+/// Maps the path of every file below `tree` to its mode and ID.
+pub fn tree_files(tree: &ObjectId) -> anyhow::Result<BTreeMap<String, (u32, ObjectId)>> {
+    repository::current()?.tree_files(tree)
+}
+
+/// Files below a directory being written, relative to it.
+type Entries<'a> = Vec<(&'a str, (u32, ObjectId))>;
+
+/// Writes the nested trees holding `files`, keyed by path as returned by
+/// [`tree_files`], and returns the root tree.
+pub fn write_tree_files(files: &BTreeMap<String, (u32, ObjectId)>) -> anyhow::Result<ObjectId> {
+    fn write(repository: &Repository, files: Entries) -> anyhow::Result<ObjectId> {
+        let mut tree = Tree::default();
+        let mut directories: BTreeMap<&str, Entries> = BTreeMap::new();
+
+        for (path, (mode, sha)) in files {
+            match path.split_once('/') {
+                Some((directory, rest)) => directories.entry(directory).or_default().push((rest, (mode, sha))),
+                None => tree.entries.push(TreeEntry { mode, name: path.to_string(), sha }),
+            }
+        }
+        for (name, files) in directories {
+            let sha = write(repository, files)?;
+            tree.entries.push(TreeEntry { mode: 0o40000, name: name.to_string(), sha });
+        }
+
+        tree.sort();
+        repository.write(&Object::Tree(tree))
+    }
+
+    let repository = repository::current()?;
+    write(&repository, files.iter().map(|(path, entry)| (path.as_str(), *entry)).collect())
+}
+
+/// A parsed object. Serializing it reproduces the stored bytes exactly.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Object {
+    Blob(Vec<u8>),
+    Tree(Tree),
+    Commit(Commit),
+    Tag(Tag),
+}
+
+impl Object {
+    pub fn parse(kind: &str, data: &[u8], format: HashAlgorithm) -> anyhow::Result<Object> {
+        Ok(match kind {
+            "blob" => Object::Blob(data.to_vec()),
+            "tree" => Object::Tree(Tree::parse(data, format)?),
+            "commit" => Object::Commit(Commit::parse(data)?),
+            "tag" => Object::Tag(Tag::parse(data)?),
+            kind => return Err(anyhow!("Unknown object type: {}", kind)),
+        })
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Object::Blob(_) => "blob",
+            Object::Tree(_) => "tree",
+            Object::Commit(_) => "commit",
+            Object::Tag(_) => "tag",
+        }
+    }
+
+    pub fn serialize(&self) -> Cow<'_, [u8]> {
+        match self {
+            Object::Blob(data) => Cow::Borrowed(data),
+            Object::Tree(tree) => Cow::Owned(tree.serialize()),
+            Object::Commit(commit) => Cow::Owned(commit.serialize().into_bytes()),
+            Object::Tag(tag) => Cow::Owned(tag.serialize().into_bytes()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TreeEntry {
+    pub mode: u32,
+    pub name: String,
+    pub sha: ObjectId,
+}
+
+impl TreeEntry {
+    pub fn kind(&self) -> &'static str {
+        match self.mode {
+            0o40000 => "tree",
+            0o160000 => "commit",
+            _ => "blob",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Tree {
+    /// Entries in stored order, which git keeps sorted by name.
+    pub entries: Vec<TreeEntry>,
+}
+
+impl Tree {
+    /// Parses a tree whose entries hold raw IDs of the given format.
+    pub fn parse(data: &[u8], format: HashAlgorithm) -> anyhow::Result<Tree> {
+        let mut entries = Vec::new();
+        let mut rest = data;
+
+        while !rest.is_empty() {
+            let name_end = rest.iter()
+                .position(|&b| b == 0)
+                .ok_or(anyhow!("Invalid tree entry"))?;
+            let (mode, name) = std::str::from_utf8(&rest[..name_end])?
+                .split_once(' ')
+                .ok_or(anyhow!("Invalid tree entry"))?;
+            // Modes are stored without leading zeros, so `040000` can't
+            // round-trip and is rejected like git's fsck does.
+            if mode.starts_with('0') {
+                return Err(anyhow!("Invalid tree entry mode: {}", mode));
+            }
+            let mode = u32::from_str_radix(mode, 8)?;
+
+            let sha = format.read(&rest[name_end + 1..])
+                .ok_or(anyhow!("Invalid tree entry"))?;
+
+            entries.push(TreeEntry { mode, name: name.to_string(), sha });
+            rest = &rest[name_end + 1 + format.byte_len()..];
+        }
+
+        Ok(Tree { entries })
+    }
+
+    /// Sorts the entries the way git stores them, comparing directories as
+    /// if their names ended in `/`.
+    pub fn sort(&mut self) {
+        fn key(entry: &TreeEntry) -> Vec<u8> {
+            let mut key = entry.name.as_bytes().to_vec();
+            if entry.mode == 0o40000 {
+                key.push(b'/');
+            }
+            key
+        }
+
+        self.entries.sort_by_cached_key(key);
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut buf = BytesMut::new();
+        for entry in &self.entries {
+            // Writing to a `BytesMut` can't fail.
+            let _ = buf.write_fmt(format_args!("{:o} {}", entry.mode, entry.name));
+            buf.put_u8(0);
+            buf.put_slice(entry.sha.as_ref());
+        }
+
+        buf.to_vec()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Commit {
+    pub tree: ObjectId,
+    pub parents: Vec<ObjectId>,
+    /// Identity lines such as `Name <email> 1709990458 +0200`.
+    pub author: String,
+    pub committer: String,
+    /// Headers after `committer`, such as `encoding` or `gpgsig`, in
+    /// stored order. Continuation lines are joined with `\n`.
+    pub extra_headers: Vec<(String, String)>,
+    pub message: String,
+}
+
+impl Commit {
+    pub fn parse(data: &[u8]) -> anyhow::Result<Commit> {
+        let (headers, message) = split_headers(data)?;
+        let mut headers = headers.into_iter().peekable();
+
+        let tree = match headers.next() {
+            Some((name, value)) if name == "tree" => value.parse()?,
+            _ => return Err(anyhow!("Invalid commit: missing tree")),
+        };
+        let mut parents = Vec::new();
+        while let Some((_, value)) = headers.next_if(|(name, _)| name == "parent") {
+            parents.push(value.parse()?);
+        }
+        let author = match headers.next() {
+            Some((name, value)) if name == "author" => value,
+            _ => return Err(anyhow!("Invalid commit: missing author")),
+        };
+        let committer = match headers.next() {
+            Some((name, value)) if name == "committer" => value,
+            _ => return Err(anyhow!("Invalid commit: missing committer")),
+        };
+
+        Ok(Commit { tree, parents, author, committer, extra_headers: headers.collect(), message })
+    }
+
+    pub fn serialize(&self) -> String {
+        let mut headers = vec![("tree".to_string(), self.tree.to_string())];
+        headers.extend(self.parents.iter().map(|parent| ("parent".to_string(), parent.to_string())));
+        headers.push(("author".to_string(), self.author.clone()));
+        headers.push(("committer".to_string(), self.committer.clone()));
+        headers.extend(self.extra_headers.iter().cloned());
+
+        join_headers(&headers, &self.message)
+    }
+
+    /// The first paragraph of the message on one line, the way git
+    /// shows it with `%s`: blank lines before it skipped and its lines
+    /// joined with spaces once trailing whitespace is trimmed.
+    pub fn subject(&self) -> String {
+        let lines = self.message.lines().map(str::trim_end).skip_while(|line| line.is_empty());
+        lines.take_while(|line| !line.is_empty()).collect::<Vec<_>>().join(" ")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tag {
+    pub object: ObjectId,
+    pub kind: String,
+    pub tag: String,
+    /// Missing in some very old tags.
+    pub tagger: Option<String>,
+    pub extra_headers: Vec<(String, String)>,
+    pub message: String,
+}
+
+impl Tag {
+    pub fn parse(data: &[u8]) -> anyhow::Result<Tag> {
+        let (headers, message) = split_headers(data)?;
+        let mut headers = headers.into_iter().peekable();
+
+        let mut next = |expected: &str| match headers.next() {
+            Some((name, value)) if name == expected => Ok(value),
+            _ => Err(anyhow!("Invalid tag: missing {}", expected)),
+        };
+        let object = next("object")?.parse()?;
+        let kind = next("type")?;
+        let tag = next("tag")?;
+        let tagger = headers.next_if(|(name, _)| name == "tagger").map(|(_, value)| value);
+
+        Ok(Tag { object, kind, tag, tagger, extra_headers: headers.collect(), message })
+    }
+
+    pub fn serialize(&self) -> String {
+        let mut headers = vec![
+            ("object".to_string(), self.object.to_string()),
+            ("type".to_string(), self.kind.clone()),
+            ("tag".to_string(), self.tag.clone()),
+        ];
+        if let Some(tagger) = &self.tagger {
+            headers.push(("tagger".to_string(), tagger.clone()));
+        }
+        headers.extend(self.extra_headers.iter().cloned());
+
+        join_headers(&headers, &self.message)
+    }
+}
+
+/// Splits a commit or tag into its headers and message. A header value
+/// continues on following lines that start with a space.
+fn split_headers(data: &[u8]) -> anyhow::Result<(Vec<(String, String)>, String)> {
+    let text = std::str::from_utf8(data)?;
+    let (header_text, message) = text.split_once("\n\n")
+        .ok_or(anyhow!("Invalid object: missing message separator"))?;
+
+    let mut headers: Vec<(String, String)> = Vec::new();
+    for line in header_text.split('\n') {
+        if let Some(continuation) = line.strip_prefix(' ') {
+            let (_, value) = headers.last_mut().ok_or(anyhow!("Invalid object header: {}", line))?;
+            value.push('\n');
+            value.push_str(continuation);
+        } else {
+            let (name, value) = line.split_once(' ')
+                .ok_or(anyhow!("Invalid object header: {}", line))?;
+            headers.push((name.to_string(), value.to_string()));
+        }
+    }
+
+    Ok((headers, message.to_string()))
+}
+
+fn join_headers(headers: &[(String, String)], message: &str) -> String {
+    let mut text = String::new();
+    for (name, value) in headers {
+        text.push_str(name);
+        text.push(' ');
+        text.push_str(&value.replace('\n', "\n "));
+        text.push('\n');
+    }
+    text.push('\n');
+    text.push_str(message);
+
+    text
+}
+
+/// Collects `commit` and every commit reachable from it through parents.
+pub fn ancestors(commit: &ObjectId) -> anyhow::Result<HashSet<ObjectId>> {
+    let repository = repository::current()?;
+    let mut seen = HashSet::new();
+    let mut pending = vec![*commit];
+
+    while let Some(sha) = pending.pop() {
+        if !seen.insert(sha) {
+            continue;
+        }
+        pending.extend(repository.read_commit(&sha)?.parents);
+    }
+
+    Ok(seen)
+}
+
+/// Follows tags down to a commit and returns the commit and its tree.
+pub fn peel_to_tree(sha: &ObjectId) -> anyhow::Result<(ObjectId, ObjectId)> {
+    let repository = repository::current()?;
+    let mut sha = *sha;
+
+    loop {
+        match repository.read(&sha)? {
+            Object::Tag(tag) => sha = tag.object,
+            Object::Commit(commit) => return Ok((sha, commit.tree)),
+            object => return Err(anyhow!("Object {} is a {}, not a commit", sha, object.kind())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(mode: u32, name: &str) -> TreeEntry {
+        TreeEntry { mode, name: name.to_string(), sha: HashAlgorithm::Sha1.hash(name.as_bytes()) }
+    }
+
+    #[test]
+    fn tree_sorts_directories_with_trailing_slash() {
+        let mut tree = Tree {
+            entries: vec![
+                entry(0o100644, "foo0"),
+                entry(0o40000, "foo"),
+                entry(0o100644, "foo.c"),
+                entry(0o40000, "fo"),
+                entry(0o100755, "foo-bar"),
+                entry(0o120000, "foo bar"),
+            ],
+        };
+        tree.sort();
+        // The order `git write-tree` stores the same names in.
+        let names: Vec<&str> = tree.entries.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(names, ["fo", "foo bar", "foo-bar", "foo.c", "foo", "foo0"]);
+
+        let parsed = Tree::parse(&tree.serialize(), HashAlgorithm::Sha1).unwrap();
+        assert_eq!(parsed.entries, tree.entries);
+    }
+
+    #[test]
+    fn commit_subject_is_first_paragraph() {
+        let commit = |message: &str| Commit {
+            tree: HashAlgorithm::Sha1.null(),
+            parents: Vec::new(),
+            author: String::new(),
+            committer: String::new(),
+            extra_headers: Vec::new(),
+            message: message.to_string(),
+        };
+        assert_eq!(commit("Subject\n\nBody\n").subject(), "Subject");
+        assert_eq!(commit("\n\nFirst line  \nsecond line\n\nBody\n").subject(), "First line second line");
+        assert_eq!(commit("").subject(), "");
+    }
+
+    #[test]
+    fn garbage_objects_are_errors_not_panics() {
+        // Mostly the bytes objects are made of, some with the high bit set.
+        let bytes = b"0123456789 \n\0<>abtreparnco+-";
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        for len in 0..3000 {
+            let data: Vec<u8> = (0..len % 97)
+                .map(|_| {
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    bytes[state as usize % bytes.len()] ^ ((state >> 40) as u8 & 0x80)
+                })
+                .collect();
+            for kind in ["tree", "commit", "tag", "blob"] {
+                let _ = Object::parse(kind, &data, HashAlgorithm::Sha1);
+            }
+        }
+        assert!(Tree::parse(b"100644 a", HashAlgorithm::Sha1).is_err());
+        assert!(Tree::parse(b"100644 a\0short", HashAlgorithm::Sha1).is_err());
+        assert!(Commit::parse(b"tree 123\n").is_err());
+    }
+}
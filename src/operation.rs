@@ -0,0 +1,96 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::anyhow;
+
+/// An operation that leaves its state in a worktree's git directory until
+/// it is finished or aborted. Git's own commands may have started any
+/// of these; only bisect is done here.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    Merge,
+    Rebase,
+    Am,
+    CherryPick,
+    Revert,
+    Bisect,
+}
+
+impl Operation {
+    /// The operations in progress in the worktree whose git directory is
+    /// `git_dir`, found from the files git keeps for them, in the order
+    /// `git status` checks them.
+    pub fn in_progress(git_dir: &Path) -> Vec<Operation> {
+        let mut operations = Vec::new();
+        if git_dir.join("MERGE_HEAD").is_file() {
+            operations.push(Operation::Merge);
+        }
+        // `git am` applies patches in rebase-apply too, marked by the
+        // `applying` file.
+        if git_dir.join("rebase-apply").is_dir() {
+            match git_dir.join("rebase-apply/applying").is_file() {
+                true => operations.push(Operation::Am),
+                false => operations.push(Operation::Rebase),
+            }
+        } else if git_dir.join("rebase-merge").is_dir() {
+            operations.push(Operation::Rebase);
+        }
+        if git_dir.join("CHERRY_PICK_HEAD").is_file() {
+            operations.push(Operation::CherryPick);
+        }
+        if git_dir.join("REVERT_HEAD").is_file() {
+            operations.push(Operation::Revert);
+        }
+        if git_dir.join("BISECT_START").is_file() {
+            operations.push(Operation::Bisect);
+        }
+
+        operations
+    }
+
+    /// Fails if an operation other than `starting`, if any, is in
+    /// progress, so that operations are not stacked on top of each other.
+    pub fn ensure_none(git_dir: &Path, starting: Option<Operation>) -> anyhow::Result<()> {
+        match Operation::in_progress(git_dir).into_iter().find(|operation| Some(*operation) != starting) {
+            Some(operation) => Err(anyhow!(
+                "You are in the middle of {}; finish it or run \"git {}\" first",
+                operation.description(),
+                operation.abort_command()
+            )),
+            None => Ok(()),
+        }
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            Operation::Merge => "a merge",
+            Operation::Rebase => "a rebase",
+            Operation::Am => "an am session",
+            Operation::CherryPick => "a cherry-pick",
+            Operation::Revert => "a revert",
+            Operation::Bisect => "a bisect",
+        }
+    }
+
+    /// What abandons the operation, going back to where it started.
+    fn abort_command(self) -> &'static str {
+        match self {
+            Operation::Merge => "merge --abort",
+            Operation::Rebase => "rebase --abort",
+            Operation::Am => "am --abort",
+            Operation::CherryPick => "cherry-pick --abort",
+            Operation::Revert => "revert --abort",
+            Operation::Bisect => "bisect reset",
+        }
+    }
+}
+
+/// The branch a rebase in progress in the worktree at `git_dir` started
+/// from, as it shows in place of the detached HEAD, or `None` if there
+/// is no rebase or it started from a detached HEAD.
+pub fn rebasing_branch(git_dir: &Path) -> Option<String> {
+    let head_name = ["rebase-merge/head-name", "rebase-apply/head-name"]
+        .iter()
+        .find_map(|name| fs::read_to_string(git_dir.join(name)).ok())?;
+    head_name.trim_end().strip_prefix("refs/heads/").map(String::from)
+}
@@ -0,0 +1,1138 @@
+//! Reads and writes pack files, `objects/pack/pack-<checksum>.pack`,
+//! through the version 2 `.idx` next to each, which lists the objects in
+//! the pack with where each one starts. See gitformat-pack(5).
+
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::io::{BufReader, Read, Seek, SeekFrom, Take, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+
+use anyhow::anyhow;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+
+use crate::config::Config;
+use crate::object_id::{HashAlgorithm, ObjectId};
+use crate::profile;
+use crate::progress::Progress;
+use crate::repository::{create_temp_file, Repository};
+
+const INDEX_MAGIC: &[u8] = b"\xfftOc";
+
+/// Past this many deltas on deltas, the deepest git's pack-objects
+/// makes, an object is taken to be corrupt. This also ends chains of ref
+/// deltas that loop.
+pub(crate) const MAX_DELTA_DEPTH: usize = 4095;
+
+const OFS_DELTA: u8 = 6;
+const REF_DELTA: u8 = 7;
+
+/// The object type a pack entry's type code stands for.
+pub(crate) fn kind_name(code: u8) -> Option<&'static str> {
+    match code {
+        1 => Some("commit"),
+        2 => Some("tree"),
+        3 => Some("blob"),
+        4 => Some("tag"),
+        _ => None,
+    }
+}
+
+/// The version and object count from the header of a pack, failing for
+/// versions other than the 2 and 3 git reads.
+pub(crate) fn parse_header(header: &[u8]) -> anyhow::Result<(u32, u32)> {
+    if header.len() < 12 || &header[..4] != b"PACK" {
+        return Err(anyhow!("Not a pack"));
+    }
+    let version = u32::from_be_bytes(header[4..8].try_into().expect("Four bytes"));
+    if !matches!(version, 2 | 3) {
+        return Err(anyhow!("Unsupported pack version {}", version));
+    }
+    Ok((version, u32::from_be_bytes(header[8..12].try_into().expect("Four bytes"))))
+}
+
+/// A pack's `.idx`: the objects of the pack in order, with the offset of
+/// each, and the checksum of the pack it was made for.
+#[derive(Debug)]
+pub(crate) struct PackIndex {
+    pub(crate) objects: Vec<(ObjectId, u64)>,
+    pub(crate) pack_checksum: ObjectId,
+}
+
+impl PackIndex {
+    /// Parses a version 2 index of `format` objects, checking its
+    /// checksum, its order and that every offset it gives is readable.
+    pub(crate) fn parse(data: &[u8], format: HashAlgorithm) -> anyhow::Result<PackIndex> {
+        let bad = || anyhow!("Bad pack index");
+        let hash_len = format.byte_len();
+        let word = |at: usize| u32::from_be_bytes(data[at..at + 4].try_into().expect("Four bytes"));
+        if data.len() < 8 + 256 * 4 + 2 * hash_len || &data[..4] != INDEX_MAGIC {
+            return Err(bad());
+        }
+        if word(4) != 2 {
+            return Err(anyhow!("Unsupported pack index version {}", word(4)));
+        }
+        let (body, checksum) = data.split_at(data.len() - hash_len);
+        if format.hash(body).as_ref() != checksum {
+            return Err(anyhow!("Pack index checksum mismatch"));
+        }
+
+        let count = word(8 + 255 * 4) as usize;
+        let names = 8 + 256 * 4;
+        let offsets = count.checked_mul(hash_len + 4).and_then(|len| len.checked_add(names)).ok_or_else(bad)?;
+        let large = offsets.checked_add(count * 4).ok_or_else(bad)?;
+        let trailer = data.len() - 2 * hash_len;
+        if large > trailer || !(trailer - large).is_multiple_of(8) {
+            return Err(bad());
+        }
+
+        let mut objects: Vec<(ObjectId, u64)> = Vec::with_capacity(count);
+        let mut previous = 0;
+        for first in 0..256 {
+            let end = word(8 + first * 4) as usize;
+            if end < previous || end > count {
+                return Err(bad());
+            }
+            for i in previous..end {
+                let sha = format.read(&data[names + i * hash_len..names + (i + 1) * hash_len]).ok_or_else(bad)?;
+                if sha.as_ref()[0] as usize != first || objects.last().is_some_and(|(last, _)| *last >= sha) {
+                    return Err(bad());
+                }
+                let offset = match word(offsets + i * 4) {
+                    offset if offset & 0x8000_0000 == 0 => offset as u64,
+                    offset => {
+                        let at = large + (offset & 0x7fff_ffff) as usize * 8;
+                        let bytes = data.get(at..at + 8).filter(|_| at + 8 <= trailer).ok_or_else(bad)?;
+                        u64::from_be_bytes(bytes.try_into().expect("Eight bytes"))
+                    }
+                };
+                objects.push((sha, offset));
+            }
+            previous = end;
+        }
+
+        let pack_checksum = format.read(&data[trailer..trailer + hash_len]).ok_or_else(bad)?;
+        Ok(PackIndex { objects, pack_checksum })
+    }
+
+    /// Where `sha` starts in the pack, if the pack has it.
+    pub(crate) fn find(&self, sha: &ObjectId) -> Option<u64> {
+        let position = self.objects.binary_search_by(|(name, _)| name.cmp(sha)).ok()?;
+        Some(self.objects[position].1)
+    }
+}
+
+/// A pack and its index.
+#[derive(Debug)]
+pub(crate) struct Pack {
+    path: PathBuf,
+    index: PackIndex,
+    format: HashAlgorithm,
+    /// Each offset in the pack followed by the next, or by where the
+    /// trailer starts, which bounds the entry at it.
+    ends: Vec<(u64, u64)>,
+}
+
+/// The start of an entry in a pack, with the decoder of its data.
+struct Entry {
+    code: u8,
+    size: u64,
+    /// Where the base of a delta is in the pack.
+    base: Option<u64>,
+    decoder: ZlibDecoder<Take<BufReader<fs::File>>>,
+}
+
+impl Pack {
+    /// Opens the pack at `path` through its `.idx`, which must be for
+    /// this pack and list as many objects as it holds.
+    pub(crate) fn open(path: &Path, format: HashAlgorithm) -> anyhow::Result<Pack> {
+        let name = path.display();
+        let index = PackIndex::parse(&fs::read(path.with_extension("idx"))?, format)
+            .map_err(|err| anyhow!("{}: {}", path.with_extension("idx").display(), err))?;
+
+        let mut file = fs::File::open(path)?;
+        let len = file.metadata()?.len();
+        let mut header = [0; 12];
+        file.read_exact(&mut header).map_err(|_| anyhow!("{} is too short to be a pack", name))?;
+        let (_, count) = parse_header(&header).map_err(|err| anyhow!("{}: {}", name, err))?;
+        let mut checksum = vec![0; format.byte_len()];
+        let trailer = len.checked_sub(checksum.len() as u64).filter(|&trailer| trailer >= 12)
+            .ok_or(anyhow!("{} is too short to be a pack", name))?;
+        file.seek(SeekFrom::Start(trailer))?;
+        file.read_exact(&mut checksum)?;
+        if checksum.as_slice() != index.pack_checksum.as_ref() {
+            return Err(anyhow!("{} does not match its index", name));
+        }
+        if count as usize != index.objects.len() {
+            return Err(anyhow!("{} holds {} objects, but its index lists {}", name, count, index.objects.len()));
+        }
+
+        let mut offsets: Vec<u64> = index.objects.iter().map(|(_, offset)| *offset).collect();
+        offsets.sort_unstable();
+        offsets.dedup();
+        if offsets.first().is_some_and(|&first| first < 12) || offsets.last().is_some_and(|&last| last >= trailer) {
+            return Err(anyhow!("{} has an index with offsets outside it", name));
+        }
+        let ends = offsets.iter().zip(offsets.iter().skip(1).chain([&trailer])).map(|(&a, &b)| (a, b)).collect();
+        Ok(Pack { path: path.to_path_buf(), index, format, ends })
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub(crate) fn index(&self) -> &PackIndex {
+        &self.index
+    }
+
+    pub(crate) fn contains(&self, sha: &ObjectId) -> bool {
+        self.index.find(sha).is_some()
+    }
+
+    /// How many bytes the entry at `offset` takes up in the pack.
+    fn entry_len(&self, offset: u64) -> Option<u64> {
+        let position = self.ends.binary_search_by(|(start, _)| start.cmp(&offset)).ok()?;
+        let (start, end) = self.ends[position];
+        Some(end - start)
+    }
+
+    /// How many bytes `sha` takes up in the pack, compressed and perhaps
+    /// as a delta, if the pack has it.
+    pub(crate) fn stored_size(&self, sha: &ObjectId) -> Option<u64> {
+        self.entry_len(self.index.find(sha)?)
+    }
+
+    /// How many deltas lead from `sha` to a whole object, if the pack
+    /// has it.
+    pub(crate) fn delta_depth(&self, sha: &ObjectId) -> anyhow::Result<Option<usize>> {
+        let Some(mut offset) = self.index.find(sha) else { return Ok(None) };
+        for depth in 0..=MAX_DELTA_DEPTH {
+            match self.entry(offset)?.base {
+                Some(base) => offset = base,
+                None => return Ok(Some(depth)),
+            }
+        }
+        Err(anyhow!("Delta chain of {} is too deep", sha))
+    }
+
+    fn entry(&self, offset: u64) -> anyhow::Result<Entry> {
+        let len = self.entry_len(offset).ok_or(anyhow!("No object at offset {} of {}", offset, self.path.display()))?;
+        let mut file = fs::File::open(&self.path)?;
+        profile::FILES_OPENED.add(1);
+        file.seek(SeekFrom::Start(offset))?;
+        let mut reader = BufReader::new(file).take(len);
+
+        let (code, size) = parse_entry_header(&mut reader)?;
+        let base = match code {
+            OFS_DELTA => {
+                let distance = parse_base_distance(&mut reader)?;
+                Some(offset.checked_sub(distance).filter(|&base| base >= 12 && distance > 0)
+                    .ok_or(anyhow!("Delta base offset out of bounds at offset {}", offset))?)
+            }
+            REF_DELTA => {
+                let mut sha = vec![0; self.format.byte_len()];
+                reader.read_exact(&mut sha).map_err(|_| anyhow!("Truncated pack entry at offset {}", offset))?;
+                let sha = self.format.read(&sha).unwrap_or_else(|| unreachable!());
+                // Bases outside the pack only appear in thin packs, which
+                // are never stored.
+                Some(self.index.find(&sha).ok_or(anyhow!("Delta base {} is not in the pack", sha))?)
+            }
+            code if kind_name(code).is_some() => None,
+            code => return Err(anyhow!("Unknown pack entry type {} at offset {}", code, offset)),
+        };
+        Ok(Entry { code, size, base, decoder: ZlibDecoder::new(reader) })
+    }
+
+    /// Reads `sha`, applying its deltas, if the pack has it.
+    pub(crate) fn read(&self, sha: &ObjectId) -> anyhow::Result<Option<(&'static str, Vec<u8>)>> {
+        let Some(mut offset) = self.index.find(sha) else { return Ok(None) };
+        let mut deltas = Vec::new();
+        let (kind, mut content) = loop {
+            let entry = self.entry(offset)?;
+            let data = inflate(entry.decoder, entry.size)?;
+            match entry.base {
+                Some(base) if deltas.len() < MAX_DELTA_DEPTH => {
+                    deltas.push(data);
+                    offset = base;
+                }
+                Some(_) => return Err(anyhow!("Delta chain of {} is too deep", sha)),
+                None => break (kind_name(entry.code).unwrap_or_else(|| unreachable!()), data),
+            }
+        };
+        for delta in deltas.iter().rev() {
+            content = apply_delta(&content, delta)?;
+        }
+        profile::OBJECT_READS.add(1);
+        Ok(Some((kind, content)))
+    }
+
+    /// The type and size of `sha` without reading it whole, if the pack
+    /// has it. A delta's size is at the start of its data.
+    pub(crate) fn header(&self, sha: &ObjectId) -> anyhow::Result<Option<(&'static str, u64)>> {
+        let Some(mut offset) = self.index.find(sha) else { return Ok(None) };
+        let mut size = None;
+        for _ in 0..=MAX_DELTA_DEPTH {
+            let mut entry = self.entry(offset)?;
+            match entry.base {
+                Some(base) => {
+                    if size.is_none() {
+                        let mut start = Vec::new();
+                        (&mut entry.decoder).take(20).read_to_end(&mut start)?;
+                        let mut input = &start[..];
+                        delta_size(&mut input).ok_or(anyhow!("Corrupt delta"))?;
+                        size = Some(delta_size(&mut input).ok_or(anyhow!("Corrupt delta"))?);
+                    }
+                    offset = base;
+                }
+                None => return Ok(Some((kind_name(entry.code).unwrap_or_else(|| unreachable!()), size.unwrap_or(entry.size)))),
+            }
+        }
+        Err(anyhow!("Delta chain of {} is too deep", sha))
+    }
+}
+
+/// Reads the type code and size that start a pack entry: four bits of
+/// size in the first byte and seven more in each byte after it.
+fn parse_entry_header(reader: &mut impl Read) -> anyhow::Result<(u8, u64)> {
+    let mut next = || -> anyhow::Result<u8> {
+        let mut byte = [0];
+        reader.read_exact(&mut byte).map_err(|_| anyhow!("Truncated pack entry header"))?;
+        Ok(byte[0])
+    };
+    let mut byte = next()?;
+    let code = (byte >> 4) & 0x07;
+    let mut size = (byte & 0x0f) as u64;
+    let mut shift = 4;
+    while byte & 0x80 != 0 {
+        byte = next()?;
+        let bits = (byte & 0x7f) as u64;
+        if shift > 63 || (bits << shift) >> shift != bits {
+            return Err(anyhow!("Pack entry size overflows 64 bits"));
+        }
+        size |= bits << shift;
+        shift += 7;
+    }
+    Ok((code, size))
+}
+
+/// Reads how far back the base of an offset delta is, a big-endian
+/// number of seven bits per byte where each byte after the first also
+/// adds one.
+fn parse_base_distance(reader: &mut impl Read) -> anyhow::Result<u64> {
+    let mut next = || -> anyhow::Result<u8> {
+        let mut byte = [0];
+        reader.read_exact(&mut byte).map_err(|_| anyhow!("Truncated delta base offset"))?;
+        Ok(byte[0])
+    };
+    let mut byte = next()?;
+    let mut distance = (byte & 0x7f) as u64;
+    while byte & 0x80 != 0 {
+        byte = next()?;
+        distance = distance.checked_add(1)
+            .and_then(|distance| distance.checked_mul(128))
+            .ok_or(anyhow!("Delta base offset overflows 64 bits"))?
+            | (byte & 0x7f) as u64;
+    }
+    Ok(distance)
+}
+
+/// Inflates the `size` bytes of an entry's data, failing rather than
+/// inflating any further if there is more, like a loose object's content.
+fn inflate(decoder: impl Read, size: u64) -> anyhow::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    decoder.take(size.saturating_add(1))
+        .read_to_end(&mut data)
+        .map_err(|err| anyhow!("Corrupt pack entry: {}", err))?;
+    profile::BYTES_INFLATED.add(data.len() as u64);
+    if data.len() as u64 != size {
+        return Err(anyhow!("Pack entry inflates to {} bytes, but its header says {}", data.len(), size));
+    }
+    Ok(data)
+}
+
+/// Reads one of the sizes at the start of a delta, seven bits per byte,
+/// least significant first.
+fn delta_size(input: &mut &[u8]) -> Option<u64> {
+    let mut size = 0u64;
+    let mut shift = 0;
+    loop {
+        let (&byte, rest) = input.split_first()?;
+        *input = rest;
+        let bits = (byte & 0x7f) as u64;
+        if shift > 63 || (bits << shift) >> shift != bits {
+            return None;
+        }
+        size |= bits << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return Some(size);
+        }
+    }
+}
+
+/// Rebuilds an object from its delta against `base`: the sizes of the
+/// base and of the result, then instructions that copy a range of the
+/// base or insert the bytes that follow them. The result grows as the
+/// instructions run, never past the size the delta states, rather than
+/// being allocated up front from it.
+pub(crate) fn apply_delta(base: &[u8], delta: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let truncated = || anyhow!("Truncated delta");
+    let mut input = delta;
+    let base_size = delta_size(&mut input).ok_or_else(truncated)?;
+    let result_size = delta_size(&mut input).ok_or_else(truncated)?;
+    if base_size != base.len() as u64 {
+        return Err(anyhow!("Delta is for a base of {} bytes, not {}", base_size, base.len()));
+    }
+
+    let mut result = Vec::with_capacity(result_size.min((base.len() + delta.len()) as u64) as usize);
+    while let Some((&op, rest)) = input.split_first() {
+        input = rest;
+        let chunk = if op & 0x80 != 0 {
+            // Which bytes of the offset and size follow is in the bits of
+            // the instruction.
+            let mut next = |bit: u8| -> anyhow::Result<u64> {
+                if op & bit == 0 {
+                    return Ok(0);
+                }
+                let (&byte, rest) = input.split_first().ok_or_else(truncated)?;
+                input = rest;
+                Ok(byte as u64)
+            };
+            let offset = next(0x01)? | next(0x02)? << 8 | next(0x04)? << 16 | next(0x08)? << 24;
+            let size = match next(0x10)? | next(0x20)? << 8 | next(0x40)? << 16 {
+                0 => 0x10000,
+                size => size,
+            };
+            let end = offset + size;
+            if end > base.len() as u64 {
+                return Err(anyhow!("Delta copies bytes {}..{} of a {} byte base", offset, end, base.len()));
+            }
+            &base[offset as usize..end as usize]
+        } else if op != 0 {
+            let (data, rest) = input.split_at_checked(op as usize).ok_or_else(truncated)?;
+            input = rest;
+            data
+        } else {
+            return Err(anyhow!("Delta has the reserved instruction 0"));
+        };
+        if (result.len() + chunk.len()) as u64 > result_size {
+            return Err(anyhow!("Delta makes more than the {} bytes it states", result_size));
+        }
+        result.extend_from_slice(chunk);
+    }
+
+    if result.len() as u64 != result_size {
+        return Err(anyhow!("Delta makes {} bytes, but states {}", result.len(), result_size));
+    }
+    Ok(result)
+}
+
+/// How a pack is written: how many of the objects before each one it is
+/// tried as a delta against, how long chains of deltas may grow, and how
+/// many threads search for them.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct PackOptions {
+    /// With 0, every object is written whole.
+    pub(crate) window: usize,
+    pub(crate) depth: usize,
+    pub(crate) threads: usize,
+}
+
+impl PackOptions {
+    /// Every object whole, as in packs for readers that might not take
+    /// offset deltas.
+    pub(crate) const WHOLE: PackOptions = PackOptions { window: 0, depth: 0, threads: 1 };
+
+    /// `pack.window`, `pack.depth` and `pack.threads`, by default 10, 50
+    /// and one thread per CPU, which `pack.threads` of 0 also means.
+    /// Like git, depths past 4095 are cut down to it.
+    pub(crate) fn from_config(config: &Config) -> anyhow::Result<PackOptions> {
+        let get = |name: &str, default: usize| match config.get(name) {
+            Some(value) => value.parse::<usize>().map_err(|_| anyhow!("Bad {} value: {}", name, value)),
+            None => Ok(default),
+        };
+        Ok(PackOptions {
+            window: get("pack.window", 10)?,
+            depth: get("pack.depth", 50)?.min(MAX_DELTA_DEPTH),
+            threads: get("pack.threads", 0)?,
+        })
+    }
+}
+
+/// An object of a pack being written, before it is laid out.
+struct Prepared {
+    code: u8,
+    /// How big the object is, or its delta.
+    size: usize,
+    /// The object the delta is against, as a position in the input.
+    base: Option<usize>,
+    deflated: Vec<u8>,
+}
+
+/// The objects of a written pack, each with its offset and the CRC-32 of
+/// its entry.
+pub(crate) type Written = Vec<(ObjectId, u64, u32)>;
+
+/// The hash git sorts objects by to bring the versions of a file
+/// together, made from the last sixteen characters of the path the object
+/// was found at, other than whitespace, with the last counting most.
+pub(crate) fn name_hash(name: &str) -> u32 {
+    name.bytes()
+        .filter(|b| !b.is_ascii_whitespace())
+        .fold(0u32, |hash, b| (hash >> 2).wrapping_add((b as u32) << 24))
+}
+
+/// Writes a pack of `objects` to `out`. Each object comes with the name
+/// hash of its path, and with a window, is stored as an offset delta
+/// against a similar object if that is smaller, which the threads of
+/// `options` look for among runs of the objects sorted by type, name
+/// hash and size. Returns each object with its offset and the CRC-32 of
+/// its entry, in the order written, with the checksum of the pack.
+pub(crate) fn write(
+    repository: &Repository,
+    objects: &[(ObjectId, u32)],
+    options: PackOptions,
+    progress: bool,
+    out: &mut Vec<u8>,
+) -> anyhow::Result<(Written, ObjectId)> {
+    let format = repository.object_format();
+    let mut prepared: Vec<Option<Prepared>> = (0..objects.len()).map(|_| None).collect();
+    if options.window == 0 {
+        for (position, (sha, _)) in objects.iter().enumerate() {
+            let (kind, content) = repository.find_object(sha)?;
+            prepared[position] = Some(whole(&kind, &content)?);
+        }
+    } else {
+        let mut sizes = Vec::with_capacity(objects.len());
+        for (sha, _) in objects {
+            let (kind, size) = repository.object_header(sha)?;
+            sizes.push((code(&kind)?, size));
+        }
+        // Like git, larger objects come first, so that smaller ones are
+        // deltas against them rather than the other way around.
+        let mut order: Vec<usize> = (0..objects.len()).collect();
+        order.sort_by_key(|&i| (sizes[i].0, objects[i].1, std::cmp::Reverse(sizes[i].1), i));
+
+        let threads = match options.threads {
+            0 => std::thread::available_parallelism().map_or(1, usize::from),
+            threads => threads,
+        };
+        let chunk = order.len().div_ceil(threads.clamp(1, order.len().max(1))).max(1);
+        let compressing = Progress::new("Compressing objects", objects.len(), progress);
+        let (sender, receiver) = mpsc::channel();
+        let results = std::thread::scope(|scope| {
+            let workers: Vec<_> = order.chunks(chunk)
+                .map(|run| {
+                    let sender = sender.clone();
+                    scope.spawn(move || find_deltas(repository, objects, run, options, sender))
+                })
+                .collect();
+            drop(sender);
+            for () in receiver {
+                compressing.tick();
+            }
+            workers.into_iter()
+                .map(|worker| worker.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic)))
+                .collect::<Vec<_>>()
+        });
+        compressing.finish();
+        for result in results {
+            for (position, object) in result? {
+                prepared[position] = Some(object);
+            }
+        }
+    }
+
+    // Objects go in the order given, each after the base of its delta.
+    let writing = Progress::new("Writing objects", objects.len(), progress);
+    let start = out.len();
+    out.extend_from_slice(b"PACK");
+    out.extend_from_slice(&2u32.to_be_bytes());
+    out.extend_from_slice(&(objects.len() as u32).to_be_bytes());
+    let mut offsets: Vec<Option<u64>> = vec![None; objects.len()];
+    let mut written = Vec::with_capacity(objects.len());
+    for position in 0..objects.len() {
+        let mut chain = vec![position];
+        while let Some(base) = prepared[*chain.last().unwrap_or(&position)].as_ref().and_then(|object| object.base) {
+            if offsets[base].is_some() {
+                break;
+            }
+            chain.push(base);
+        }
+        for &position in chain.iter().rev() {
+            if offsets[position].is_some() {
+                continue;
+            }
+            let object = prepared[position].as_ref().unwrap_or_else(|| unreachable!());
+            let offset = (out.len() - start) as u64;
+            let entry_start = out.len();
+            match object.base {
+                Some(base) => {
+                    out.extend(entry_header(OFS_DELTA, object.size));
+                    out.extend(base_distance(offset - offsets[base].unwrap_or_else(|| unreachable!())));
+                }
+                None => out.extend(entry_header(object.code, object.size)),
+            }
+            out.extend_from_slice(&object.deflated);
+            let mut crc = flate2::Crc::new();
+            crc.update(&out[entry_start..]);
+            offsets[position] = Some(offset);
+            written.push((objects[position].0, offset, crc.sum()));
+            writing.tick();
+        }
+    }
+    writing.finish();
+
+    let checksum = format.hash(&out[start..]);
+    out.extend_from_slice(checksum.as_ref());
+    Ok((written, checksum))
+}
+
+fn code(kind: &str) -> anyhow::Result<u8> {
+    (1..=4).find(|&code| kind_name(code) == Some(kind)).ok_or(anyhow!("Unknown object type {}", kind))
+}
+
+fn deflate(data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+fn whole(kind: &str, content: &[u8]) -> anyhow::Result<Prepared> {
+    Ok(Prepared { code: code(kind)?, size: content.len(), base: None, deflated: deflate(content)? })
+}
+
+/// Prepares the objects at the positions in `run`, trying each as a
+/// delta against the `window` objects of the same type before it whose
+/// chains are short enough, and sending a tick for each one done.
+fn find_deltas(
+    repository: &Repository,
+    objects: &[(ObjectId, u32)],
+    run: &[usize],
+    options: PackOptions,
+    ticks: mpsc::Sender<()>,
+) -> anyhow::Result<Vec<(usize, Prepared)>> {
+    let hash_len = repository.object_format().byte_len();
+    let mut window: VecDeque<(usize, u8, Vec<u8>, usize)> = VecDeque::with_capacity(options.window);
+    let mut prepared = Vec::with_capacity(run.len());
+    for &position in run {
+        let (kind, content) = repository.find_object(&objects[position].0)?;
+        let code = code(&kind)?;
+
+        let mut best: Option<(usize, Vec<u8>, usize)> = None;
+        for (base, base_code, base_content, depth) in window.iter().rev() {
+            if *base_code != code || *depth >= options.depth {
+                continue;
+            }
+            let limit = match &best {
+                Some((_, delta, _)) => delta.len(),
+                None => (content.len() / 2).saturating_sub(hash_len),
+            };
+            if base_content.len().abs_diff(content.len()) >= limit {
+                continue;
+            }
+            if let Some(delta) = create_delta(base_content, &content, limit) {
+                best = Some((*base, delta, depth + 1));
+            }
+        }
+
+        let (object, depth) = match best {
+            Some((base, delta, depth)) => {
+                (Prepared { code, size: delta.len(), base: Some(base), deflated: deflate(&delta)? }, depth)
+            }
+            None => (whole(&kind, &content)?, 0),
+        };
+        prepared.push((position, object));
+        if window.len() == options.window {
+            window.pop_front();
+        }
+        window.push_back((position, code, content, depth));
+        let _ = ticks.send(());
+    }
+    Ok(prepared)
+}
+
+/// The bytes of the entry header `parse_entry_header` reads.
+fn entry_header(code: u8, mut size: usize) -> Vec<u8> {
+    let mut header = Vec::new();
+    let mut byte = (code << 4) | (size & 0x0f) as u8;
+    size >>= 4;
+    while size > 0 {
+        header.push(byte | 0x80);
+        byte = (size & 0x7f) as u8;
+        size >>= 7;
+    }
+    header.push(byte);
+    header
+}
+
+/// The bytes of the distance `parse_base_distance` reads.
+fn base_distance(mut distance: u64) -> Vec<u8> {
+    let mut bytes = vec![(distance & 0x7f) as u8];
+    distance >>= 7;
+    while distance > 0 {
+        distance -= 1;
+        bytes.push(0x80 | (distance & 0x7f) as u8);
+        distance >>= 7;
+    }
+    bytes.reverse();
+    bytes
+}
+
+fn push_delta_size(out: &mut Vec<u8>, mut size: usize) {
+    while size >= 0x80 {
+        out.push(0x80 | (size & 0x7f) as u8);
+        size >>= 7;
+    }
+    out.push(size as u8);
+}
+
+/// How many bytes the blocks `create_delta` indexes its base by are.
+const BLOCK: usize = 16;
+
+/// A delta that makes `target` from `base`, as `apply_delta` reads it,
+/// if one shorter than `limit` can be found. The base is indexed by
+/// blocks of `BLOCK` bytes, and where a block of the target matches one,
+/// the match is grown both ways and copied; the rest is inserted.
+pub(crate) fn create_delta(base: &[u8], target: &[u8], limit: usize) -> Option<Vec<u8>> {
+    if base.len() < BLOCK || base.len() > u32::MAX as usize {
+        return None;
+    }
+    let mut blocks: HashMap<&[u8], usize> = HashMap::new();
+    for (i, block) in base.chunks_exact(BLOCK).enumerate() {
+        blocks.entry(block).or_insert(i * BLOCK);
+    }
+
+    let mut delta = Vec::new();
+    push_delta_size(&mut delta, base.len());
+    push_delta_size(&mut delta, target.len());
+    let mut inserted = 0;
+    let mut position = 0;
+    while position + BLOCK <= target.len() {
+        let Some(&found) = blocks.get(&target[position..position + BLOCK]) else {
+            position += 1;
+            continue;
+        };
+        let mut len = BLOCK;
+        while found + len < base.len() && position + len < target.len() && base[found + len] == target[position + len] {
+            len += 1;
+        }
+        let (mut from, mut to) = (found, position);
+        while to > inserted && from > 0 && base[from - 1] == target[to - 1] {
+            from -= 1;
+            to -= 1;
+            len += 1;
+        }
+
+        push_insert(&mut delta, &target[inserted..to]);
+        push_copy(&mut delta, from, len);
+        position = to + len;
+        inserted = position;
+        if delta.len() >= limit {
+            return None;
+        }
+    }
+    push_insert(&mut delta, &target[inserted..]);
+    (delta.len() < limit).then_some(delta)
+}
+
+/// Inserts of at most 127 bytes each, the most one instruction holds.
+fn push_insert(delta: &mut Vec<u8>, data: &[u8]) {
+    for chunk in data.chunks(0x7f) {
+        delta.push(chunk.len() as u8);
+        delta.extend_from_slice(chunk);
+    }
+}
+
+/// Copies of at most 64 KiB each, the most git's own deltas copy at once,
+/// with only the bytes of the offset and size that aren't zero.
+fn push_copy(delta: &mut Vec<u8>, mut offset: usize, mut len: usize) {
+    while len > 0 {
+        let size = len.min(0x10000);
+        let mut op = 0x80;
+        let mut bytes = Vec::with_capacity(7);
+        for i in 0..4 {
+            let byte = (offset >> (8 * i)) as u8;
+            if byte != 0 {
+                op |= 1 << i;
+                bytes.push(byte);
+            }
+        }
+        for i in 0..3 {
+            let byte = ((size & 0xffff) >> (8 * i)) as u8;
+            if byte != 0 {
+                op |= 0x10 << i;
+                bytes.push(byte);
+            }
+        }
+        delta.push(op);
+        delta.extend(bytes);
+        offset += size;
+        len -= size;
+    }
+}
+
+/// A version 2 index of the objects a pack holds, given with their
+/// offsets and CRCs, for the pack with `checksum`. Offsets past 2 GiB go
+/// in the table of large offsets.
+pub(crate) fn write_index(objects: &[(ObjectId, u64, u32)], checksum: &ObjectId, format: HashAlgorithm) -> Vec<u8> {
+    let mut objects = objects.to_vec();
+    objects.sort_by_key(|(sha, _, _)| *sha);
+    let mut index = INDEX_MAGIC.to_vec();
+    index.extend_from_slice(&2u32.to_be_bytes());
+    let mut count = 0;
+    for first in 0..=255u8 {
+        count += objects[count..].iter().take_while(|(sha, _, _)| sha.as_ref()[0] == first).count();
+        index.extend_from_slice(&(count as u32).to_be_bytes());
+    }
+    for (sha, _, _) in &objects {
+        index.extend_from_slice(sha.as_ref());
+    }
+    for (_, _, crc) in &objects {
+        index.extend_from_slice(&crc.to_be_bytes());
+    }
+    let mut large = Vec::new();
+    for (_, offset, _) in &objects {
+        let offset = match u32::try_from(*offset) {
+            Ok(offset) if offset & 0x8000_0000 == 0 => offset,
+            _ => {
+                large.extend_from_slice(&offset.to_be_bytes());
+                0x8000_0000 | (large.len() / 8 - 1) as u32
+            }
+        };
+        index.extend_from_slice(&offset.to_be_bytes());
+    }
+    index.extend(large);
+    index.extend_from_slice(checksum.as_ref());
+    let own = format.hash(&index);
+    index.extend_from_slice(own.as_ref());
+    index
+}
+
+/// Stores a pack and its index, and perhaps its bitmap, as
+/// `<prefix>-<checksum>.pack`, `.idx` and `.bitmap`, each written to a
+/// temporary file and renamed into place, the index last as git ignores
+/// a pack until it has one. Returns the path of the pack.
+pub(crate) fn store(
+    prefix: &Path,
+    pack: &[u8],
+    index: &[u8],
+    bitmap: Option<&[u8]>,
+    checksum: &ObjectId,
+) -> anyhow::Result<PathBuf> {
+    let directory = prefix.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    fs::create_dir_all(directory)?;
+    let name = format!("{}-{}", prefix.file_name().map(|name| name.to_string_lossy()).unwrap_or_default(), checksum);
+    let path = directory.join(format!("{}.pack", name));
+    let bitmap = bitmap.map(|bitmap| ("bitmap", bitmap));
+    for (extension, data) in std::iter::once(("pack", pack)).chain(bitmap).chain([("idx", index)]) {
+        let (temp_path, mut file) = create_temp_file(directory, "tmp_pack_")?;
+        let result = (|| -> anyhow::Result<()> {
+            file.write_all(data)?;
+            file.sync_all()?;
+            Ok(fs::rename(&temp_path, path.with_extension(extension))?)
+        })();
+        if result.is_err() {
+            let _ = fs::remove_file(&temp_path);
+        }
+        result?;
+    }
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FORMAT: HashAlgorithm = HashAlgorithm::Sha1;
+
+    fn zlib(data: &[u8]) -> Vec<u8> {
+        deflate(data).unwrap()
+    }
+
+    /// A pack of whole objects, with the ID and offset of each.
+    fn pack(objects: &[(&str, &[u8])]) -> (Vec<u8>, Vec<(ObjectId, u64)>) {
+        let mut pack = b"PACK\0\0\0\x02".to_vec();
+        pack.extend_from_slice(&(objects.len() as u32).to_be_bytes());
+        let mut entries = Vec::new();
+        for (kind, content) in objects {
+            let code = code(kind).unwrap();
+            let sha = FORMAT.hash(&[format!("{} {}\0", kind, content.len()).as_bytes(), content].concat());
+            entries.push((sha, pack.len() as u64));
+            pack.extend(entry_header(code, content.len()));
+            pack.extend(zlib(content));
+        }
+        let checksum = FORMAT.hash(&pack);
+        pack.extend_from_slice(checksum.as_ref());
+        (pack, entries)
+    }
+
+    /// A version 2 index of `objects` for the pack with `checksum`.
+    fn index(objects: &[(ObjectId, u64)], checksum: &[u8]) -> Vec<u8> {
+        let objects: Vec<_> = objects.iter().map(|(sha, offset)| (*sha, *offset, 0)).collect();
+        write_index(&objects, &FORMAT.read(checksum).unwrap(), FORMAT)
+    }
+
+    #[test]
+    fn indexes_are_checked() {
+        let (pack, objects) = pack(&[("blob", b"one"), ("blob", b"two"), ("tree", b"")]);
+        let checksum = &pack[pack.len() - 20..];
+        let data = index(&objects, checksum);
+
+        let parsed = PackIndex::parse(&data, FORMAT).unwrap();
+        assert_eq!(parsed.pack_checksum.as_ref(), checksum);
+        for (sha, offset) in &objects {
+            assert_eq!(parsed.find(sha), Some(*offset));
+        }
+        assert_eq!(parsed.find(&FORMAT.hash(b"missing")), None);
+
+        assert!(PackIndex::parse(&data, HashAlgorithm::Sha256).is_err());
+        let mut damaged = data.clone();
+        damaged[8 + 256 * 4] ^= 1;
+        assert!(PackIndex::parse(&damaged, FORMAT).is_err());
+        let mut version = data.clone();
+        version[7] = 3;
+        assert!(PackIndex::parse(&version, FORMAT).unwrap_err().to_string().contains("version 3"));
+
+        assert!(parse_header(&pack).is_ok());
+        assert!(parse_header(b"PACK\0\0\0\x04\0\0\0\0").unwrap_err().to_string().contains("version 4"));
+    }
+
+    #[test]
+    fn packs_must_match_their_index() {
+        let dir = std::env::temp_dir().join(format!("git-starter-rust-pack-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("pack-test.pack");
+        let (data, objects) = pack(&[("blob", b"one"), ("commit", b"two")]);
+        let (other, _) = pack(&[("blob", b"one"), ("commit", b"three")]);
+        let checksum = &data[data.len() - 20..];
+
+        fs::write(&path, &data).unwrap();
+        fs::write(path.with_extension("idx"), index(&objects, checksum)).unwrap();
+        let opened = Pack::open(&path, FORMAT).map(|pack| {
+            objects.iter().map(|(sha, _)| pack.read(sha).unwrap().unwrap()).collect::<Vec<_>>()
+        });
+        fs::write(path.with_extension("idx"), index(&objects[..1], checksum)).unwrap();
+        let short = Pack::open(&path, FORMAT);
+        fs::write(path.with_extension("idx"), index(&objects, &other[other.len() - 20..])).unwrap();
+        let mismatched = Pack::open(&path, FORMAT);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(opened.unwrap(), [("blob", b"one".to_vec()), ("commit", b"two".to_vec())]);
+        assert!(short.unwrap_err().to_string().contains("holds 2 objects"));
+        assert!(mismatched.unwrap_err().to_string().contains("does not match"));
+    }
+
+    /// A small xorshift generator, so the garbage is the same every run.
+    fn garbage(seed: u64, len: usize) -> Vec<u8> {
+        let mut state = seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn deltas() {
+        let base = b"0123456789abcdef";
+        // Copy 4 bytes from offset 10, insert "xy", copy the first 2.
+        let delta = [16, 8, 0x91, 10, 4, 2, b'x', b'y', 0x90, 2];
+        assert_eq!(apply_delta(base, &delta).unwrap(), b"abcdxy01");
+        // A copy of size 0 means 0x10000 bytes.
+        let big = vec![7; 0x10000];
+        assert_eq!(apply_delta(&big, &[0x80, 0x80, 0x04, 0x80, 0x80, 0x04, 0x80]).unwrap(), big);
+
+        let errors = [
+            &[][..],
+            &[16],
+            &[15, 8, 0x91, 10, 4, 2, b'x', b'y', 0x90, 2],
+            &[16, 9, 0x91, 10, 4, 2, b'x', b'y', 0x90, 2],
+            &[16, 7, 0x91, 10, 4, 2, b'x', b'y', 0x90, 2],
+            &[16, 8, 0x91, 10, 4, 2, b'x'],
+            &[16, 8, 0x91, 10],
+            &[16, 2, 0x91, 15, 2],
+            &[16, 4, 0x9f, 0xff, 0xff, 0xff, 0xff, 4],
+            &[16, 1, 0],
+            // Sizes past 64 bits, and a result size far past any memory.
+            &[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f, 0],
+            &[16, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f, 0x90, 16],
+        ];
+        for delta in errors {
+            assert!(apply_delta(base, delta).is_err(), "{:?}", delta);
+        }
+
+        for seed in 0..2000 {
+            let delta = garbage(seed, (seed % 64) as usize);
+            let _ = apply_delta(base, &delta);
+            let _ = apply_delta(&[16, 32].iter().chain(&delta).copied().collect::<Vec<_>>(), base);
+        }
+    }
+
+    #[test]
+    fn created_deltas() {
+        for seed in 0..200 {
+            let base = garbage(seed, 16 + (seed * 37 % 3000) as usize);
+            // The base with bytes changed, cut out and added at places
+            // the seed picks.
+            let at = |n: u64| (seed * n % base.len() as u64) as usize;
+            let (cut, len) = (at(13), (seed % 90) as usize);
+            let mut target = [&base[..cut], &garbage(seed + 1, len), &base[(cut + len).min(base.len())..]].concat();
+            for n in 0..seed % 5 {
+                let i = (seed * (n + 7) % target.len() as u64) as usize;
+                target[i] ^= 0x5a;
+            }
+            target.extend_from_slice(&base[..at(3)]);
+
+            let delta = create_delta(&base, &target, usize::MAX).unwrap();
+            assert_eq!(apply_delta(&base, &delta).unwrap(), target, "seed {}", seed);
+            assert!(create_delta(&base, &target, delta.len()).is_none());
+        }
+        let big = garbage(1, 200_000);
+        let delta = create_delta(&big, &big, usize::MAX).unwrap();
+        assert!(delta.len() < 32);
+        assert_eq!(apply_delta(&big, &delta).unwrap(), big);
+        // Nothing to index a base shorter than a block by.
+        assert!(create_delta(b"short", b"short", usize::MAX).is_none());
+    }
+
+    #[test]
+    fn written_packs_read_back() {
+        let dir = std::env::temp_dir().join(format!("git-starter-rust-pack-write-{}", std::process::id()));
+        let repository = Repository::init_module(&dir, FORMAT).unwrap();
+        let mut objects = Vec::new();
+        let mut content = Vec::new();
+        for version in 0..40u64 {
+            content.extend_from_slice(format!("line {}\n", version).as_bytes());
+            let sha = repository.write_object("blob", &content, true).unwrap();
+            objects.push((sha, name_hash("dir/file.txt")));
+        }
+        objects.push((repository.write_object("tree", b"", true).unwrap(), 0));
+        objects.push((repository.write_object("blob", &garbage(3, 5000), true).unwrap(), name_hash("other")));
+
+        for (options, deltas) in [
+            (PackOptions::WHOLE, false),
+            (PackOptions { window: 10, depth: 3, threads: 1 }, true),
+            (PackOptions { window: 4, depth: 50, threads: 3 }, true),
+        ] {
+            let mut data = Vec::new();
+            let (written, checksum) = write(&repository, &objects, options, false, &mut data).unwrap();
+            assert_eq!(written.len(), objects.len());
+            let index = write_index(&written, &checksum, FORMAT);
+            let path = store(&dir.join("out").join("pack"), &data, &index, None, &checksum).unwrap();
+            let pack = Pack::open(&path, FORMAT).unwrap();
+            let mut deepest = 0;
+            for (sha, _) in &objects {
+                let (kind, content) = pack.read(sha).unwrap().unwrap();
+                assert_eq!(repository.find_object(sha).unwrap(), (kind.to_string(), content));
+                deepest = deepest.max(pack.delta_depth(sha).unwrap().unwrap());
+            }
+            assert!(deepest <= options.depth);
+            assert_eq!(deepest > 0, deltas);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn entry_headers() {
+        assert_eq!(parse_entry_header(&mut &[0x35][..]).unwrap(), (3, 5));
+        assert_eq!(parse_entry_header(&mut &[0x9f, 0x01][..]).unwrap(), (1, 31));
+        assert!(parse_entry_header(&mut &[][..]).is_err());
+        assert!(parse_entry_header(&mut &[0x9f][..]).is_err());
+        assert!(parse_entry_header(&mut &[0xff; 11][..]).is_err());
+        assert!(parse_entry_header(&mut &[0x9f, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f][..]).is_err());
+
+        assert_eq!(parse_base_distance(&mut &[0x05][..]).unwrap(), 5);
+        assert_eq!(parse_base_distance(&mut &[0x80, 0x00][..]).unwrap(), 128);
+        assert!(parse_base_distance(&mut &[0x80][..]).is_err());
+        assert!(parse_base_distance(&mut &[0xff; 10][..]).is_err());
+
+        for size in [0, 15, 16, 2047, 2048, 1 << 40] {
+            assert_eq!(parse_entry_header(&mut &entry_header(2, size)[..]).unwrap(), (2, size as u64));
+        }
+        for distance in [0, 127, 128, 16511, 16512, u32::MAX as u64, 1 << 50] {
+            assert_eq!(parse_base_distance(&mut &base_distance(distance)[..]).unwrap(), distance);
+        }
+
+        for seed in 0..2000 {
+            let data = garbage(seed, (seed % 24) as usize);
+            let _ = parse_entry_header(&mut &data[..]);
+            let _ = parse_base_distance(&mut &data[..]);
+            let _ = PackIndex::parse(&data, FORMAT);
+            let _ = parse_header(&data);
+        }
+    }
+
+    type Read = anyhow::Result<Option<(&'static str, Vec<u8>)>>;
+
+    /// Writes a pack of raw `entries` with an index giving each the ID
+    /// of `names`, and reads every one.
+    fn read_raw(entries: &[Vec<u8>], names: &[&str]) -> Vec<Read> {
+        let dir = std::env::temp_dir()
+            .join(format!("git-starter-rust-raw-pack-{}-{}", std::process::id(), FORMAT.hash(&entries.concat())));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("pack-raw.pack");
+
+        let mut data = b"PACK\0\0\0\x02".to_vec();
+        data.extend_from_slice(&(entries.len() as u32).to_be_bytes());
+        let mut objects = Vec::new();
+        for (entry, name) in entries.iter().zip(names) {
+            objects.push((FORMAT.hash(name.as_bytes()), data.len() as u64));
+            data.extend_from_slice(entry);
+        }
+        let checksum = FORMAT.hash(&data);
+        data.extend_from_slice(checksum.as_ref());
+        fs::write(&path, &data).unwrap();
+        fs::write(path.with_extension("idx"), index(&objects, checksum.as_ref())).unwrap();
+
+        let results = match Pack::open(&path, FORMAT) {
+            Ok(pack) => objects.iter().map(|(sha, _)| pack.read(sha)).collect(),
+            Err(err) => vec![Err(err)],
+        };
+        fs::remove_dir_all(&dir).unwrap();
+        results
+    }
+
+    #[test]
+    fn malformed_entries_are_errors() {
+        let blob = [entry_header(3, 5), zlib(b"hello")].concat();
+        let delta = [5, 3, 0x90, 3];
+        let ofs_delta = [entry_header(OFS_DELTA, delta.len()), vec![blob.len() as u8], zlib(&delta)].concat();
+        let results = read_raw(&[blob.clone(), ofs_delta], &["blob", "delta"]);
+        assert_eq!(results[0].as_ref().unwrap(), &Some(("blob", b"hello".to_vec())));
+        assert_eq!(results[1].as_ref().unwrap(), &Some(("blob", b"hel".to_vec())));
+
+        // A ref delta against itself loops until the depth limit.
+        let itself = FORMAT.hash(b"loop");
+        let ref_delta = [entry_header(REF_DELTA, 4), itself.as_ref().to_vec(), zlib(&[3, 3, 0x90, 3])].concat();
+        let offset_zero = [entry_header(OFS_DELTA, 4), vec![0], zlib(&[3, 3, 0x90, 3])].concat();
+        let before_pack = [entry_header(OFS_DELTA, 4), vec![100], zlib(&[3, 3, 0x90, 3])].concat();
+        let missing_base = [entry_header(REF_DELTA, 4), FORMAT.hash(b"x").as_ref().to_vec(), zlib(&[3])].concat();
+        let cases = [
+            ("longer", [entry_header(3, 2), zlib(b"hello")].concat()),
+            ("shorter", [entry_header(3, 50), zlib(b"hello")].concat()),
+            ("huge", [vec![0xbf, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01], zlib(b"hello")].concat()),
+            ("bad type", [entry_header(5, 5), zlib(b"hello")].concat()),
+            ("not zlib", [entry_header(3, 5), b"hello".to_vec()].concat()),
+            ("truncated", blob[..blob.len() - 3].to_vec()),
+            ("loop", ref_delta),
+            ("zero", offset_zero),
+            ("before pack", before_pack),
+            ("missing base", missing_base),
+        ];
+        for (name, entry) in cases {
+            let results = read_raw(&[entry], &[name]);
+            assert!(results[0].is_err(), "{}: {:?}", name, results[0]);
+        }
+
+        for seed in 0..200 {
+            let results = read_raw(&[blob.clone(), garbage(seed, 1 + (seed % 40) as usize)], &["blob", "garbage"]);
+            assert!(results[0].is_ok());
+        }
+    }
+}
@@ -0,0 +1,144 @@
+//! Paging long output: the output of commands such as `log` and `diff`
+//! goes through `$GIT_PAGER`, `core.pager`, `$PAGER` or `less` when it
+//! is written to a terminal, as `pager.<command>`, `--paginate` and
+//! `--no-pager` allow.
+
+use std::io::{IsTerminal, Write};
+use std::process::{Child, Command, Stdio};
+use std::sync::Mutex;
+
+use anyhow::anyhow;
+
+use crate::config::{self, Config};
+
+/// Set for the pager and everything we run while it is in use, which
+/// then color their output and decorate commits as for a terminal.
+const IN_USE: &str = "GIT_PAGER_IN_USE";
+
+/// The running pager, waited for at exit.
+static PAGER: Mutex<Option<Child>> = Mutex::new(None);
+
+/// Whether output goes to a terminal, directly or through the pager.
+pub fn to_terminal() -> bool {
+    let in_use = std::env::var(IN_USE).ok().and_then(|value| config::parse_bool(&value));
+    std::io::stdout().is_terminal() || in_use == Some(true)
+}
+
+/// Whether `git <command>` pages its output: as `flag`, from `--paginate`
+/// or `--no-pager`, says, else as `pager.<command>` does, else when it
+/// does so `by_default`.
+pub fn wanted(config: &Config, command: &str, flag: Option<bool>, by_default: bool) -> bool {
+    let configured = || {
+        let value = config.get(&format!("pager.{}", command))?;
+        Some(config::parse_bool(value).unwrap_or(true))
+    };
+    flag.or_else(configured).unwrap_or(by_default)
+}
+
+/// The pager for `command`: `$GIT_PAGER`, else a `pager.<command>` that
+/// isn't a boolean, else `core.pager`, `$PAGER` and finally `less`.
+/// `None` when that is empty or `cat`, which pages nothing.
+pub fn program(config: &Config, command: &str) -> Option<String> {
+    let own = config.get(&format!("pager.{}", command)).filter(|value| config::parse_bool(value).is_none());
+    let program = std::env::var("GIT_PAGER")
+        .ok()
+        .or(own.map(str::to_string))
+        .or(config.get("core.pager").map(str::to_string))
+        .or(std::env::var("PAGER").ok())
+        .unwrap_or("less".to_string());
+    (!program.trim().is_empty() && program != "cat").then_some(program)
+}
+
+/// Starts `program` in the shell and sends our stdout, and stderr if that
+/// is a terminal too, through it. `less` is started with `LESS=FRX`
+/// unless that is set, so that it quits when the output fits on a screen
+/// and keeps colors. Does nothing unless stdout is a terminal.
+pub fn start(program: &str) -> anyhow::Result<()> {
+    if !std::io::stdout().is_terminal() {
+        return Ok(());
+    }
+    let mut command = Command::new("sh");
+    command.arg("-c").arg(program).stdin(Stdio::piped()).env(IN_USE, "true");
+    for (name, value) in [("LESS", "FRX"), ("LV", "-c")] {
+        if std::env::var_os(name).is_none() {
+            command.env(name, value);
+        }
+    }
+    let mut child = command.spawn().map_err(|err| anyhow!("unable to execute pager '{}': {}", program, err))?;
+    let input = child.stdin.take().expect("Stdin is piped");
+    redirect(&input, std::io::stderr().is_terminal());
+    drop(input);
+    std::env::set_var(IN_USE, "true");
+    *PAGER.lock().expect("Pager lock is not poisoned") = Some(child);
+    Ok(())
+}
+
+/// Ends our output to the pager and waits until the user quits it.
+pub fn finish() {
+    let Some(mut child) = PAGER.lock().expect("Pager lock is not poisoned").take() else {
+        return;
+    };
+    let _ = std::io::stdout().flush();
+    close_output();
+    let _ = child.wait();
+}
+
+/// Points stdout, and stderr with `stderr`, at the pager's input. Once the
+/// pager quits, the next write ends us quietly like it does git, rather
+/// than failing on the broken pipe.
+#[cfg(unix)]
+fn redirect(input: &std::process::ChildStdin, stderr: bool) {
+    use std::os::fd::AsRawFd;
+
+    unsafe {
+        libc::dup2(input.as_raw_fd(), libc::STDOUT_FILENO);
+        if stderr {
+            libc::dup2(input.as_raw_fd(), libc::STDERR_FILENO);
+        }
+        libc::signal(libc::SIGPIPE, libc::SIG_DFL);
+    }
+}
+
+#[cfg(not(unix))]
+fn redirect(_input: &std::process::ChildStdin, _stderr: bool) {}
+
+/// Closes stdout and stderr so that the pager sees the end of the output.
+#[cfg(unix)]
+fn close_output() {
+    unsafe {
+        libc::close(libc::STDOUT_FILENO);
+        libc::close(libc::STDERR_FILENO);
+    }
+}
+
+#[cfg(not(unix))]
+fn close_output() {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn when_to_page() {
+        let config = Config::parse("[pager]\n\tlog = false\n\tdiff = \"less -S\"\n").unwrap();
+        assert!(!wanted(&config, "log", None, true));
+        assert!(wanted(&config, "log", Some(true), true));
+        assert!(wanted(&config, "diff", None, false));
+        assert!(!wanted(&config, "diff", Some(false), true));
+        assert!(wanted(&config, "range-diff", None, true));
+        assert!(!wanted(&config, "branch", None, false));
+    }
+
+    #[test]
+    fn pager_programs() {
+        if std::env::var_os("GIT_PAGER").is_some() || std::env::var_os("PAGER").is_some() {
+            return;
+        }
+        let config = Config::parse("[core]\n\tpager = most\n[pager]\n\tdiff = \"less -S\"\n\tlog = true\n").unwrap();
+        assert_eq!(program(&config, "diff").as_deref(), Some("less -S"));
+        assert_eq!(program(&config, "log").as_deref(), Some("most"));
+        assert_eq!(program(&Config::parse("").unwrap(), "log").as_deref(), Some("less"));
+        assert_eq!(program(&Config::parse("[core]\n\tpager = cat\n").unwrap(), "log"), None);
+        assert_eq!(program(&Config::parse("[core]\n\tpager =\n").unwrap(), "log"), None);
+    }
+}
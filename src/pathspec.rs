@@ -0,0 +1,101 @@
+use anyhow::anyhow;
+
+use crate::wildmatch::wildmatch;
+
+/// One pattern of a pathspec, with the magic it was given.
+#[derive(Debug, Clone, Default)]
+struct Item {
+    pattern: String,
+    /// Wildcards don't match `/`, and `**` matches any directories.
+    glob: bool,
+    /// No wildcards at all.
+    literal: bool,
+    icase: bool,
+    exclude: bool,
+}
+
+impl Item {
+    /// Parses `arg` with its `:(magic)` or short `:!`, `:^` and `:/`
+    /// prefix, starting from the magic of the `GIT_*_PATHSPECS`
+    /// variables.
+    fn parse(arg: &str, defaults: &Item) -> anyhow::Result<Item> {
+        let mut item = Item { pattern: String::new(), ..defaults.clone() };
+        let pattern = if let Some(rest) = arg.strip_prefix(":(") {
+            let (magic, pattern) =
+                rest.split_once(')').ok_or(anyhow!("Missing ')' at the end of pathspec magic in '{}'", arg))?;
+            for word in magic.split(',').map(str::trim).filter(|word| !word.is_empty()) {
+                match word {
+                    "glob" => item.glob = true,
+                    "literal" => item.literal = true,
+                    "icase" => item.icase = true,
+                    "exclude" => item.exclude = true,
+                    // Commands always run from the top of the worktree.
+                    "top" => {}
+                    word if word.starts_with("attr:") => return Err(anyhow!("Unsupported pathspec magic 'attr'")),
+                    word => return Err(anyhow!("Invalid pathspec magic '{}' in '{}'", word, arg)),
+                }
+            }
+            pattern
+        } else if let Some(rest) = arg.strip_prefix(':') {
+            let end = rest.find(|c| !matches!(c, '!' | '^' | '/')).unwrap_or(rest.len());
+            item.exclude |= rest[..end].contains(['!', '^']);
+            let pattern = &rest[end..];
+            pattern.strip_prefix(':').unwrap_or(pattern)
+        } else {
+            arg
+        };
+
+        if item.literal && item.glob {
+            return Err(anyhow!("{}: 'literal' and 'glob' are incompatible", arg));
+        }
+        item.pattern = pattern.trim_end_matches('/').to_string();
+        Ok(item)
+    }
+
+    fn matches(&self, path: &str) -> bool {
+        let (pattern, path) = match self.icase {
+            true => (self.pattern.to_lowercase(), path.to_lowercase()),
+            false => (self.pattern.clone(), path.to_string()),
+        };
+        // A pattern matches what it names and everything below it.
+        if pattern.is_empty()
+            || path == pattern
+            || path.strip_prefix(&pattern).is_some_and(|rest| rest.starts_with('/'))
+        {
+            return true;
+        }
+        !self.literal && pattern.contains(['*', '?', '[', '\\']) && wildmatch(&pattern, &path, self.glob)
+    }
+}
+
+/// The paths a command is limited to, as patterns that git's pathspec
+/// magic applies to: `:(glob)`, `:(literal)`, `:(icase)`, and
+/// `:(exclude)` or `:!` for paths to leave out. Without magic, wildcards
+/// match across directories.
+#[derive(Debug, Clone, Default)]
+pub struct Pathspec {
+    items: Vec<Item>,
+}
+
+impl Pathspec {
+    pub fn parse(args: &[String]) -> anyhow::Result<Pathspec> {
+        let set = |name: &str| std::env::var(name).is_ok_and(|value| crate::config::parse_bool(&value) == Some(true));
+        let defaults = Item {
+            glob: set("GIT_GLOB_PATHSPECS"),
+            literal: set("GIT_LITERAL_PATHSPECS"),
+            icase: set("GIT_ICASE_PATHSPECS"),
+            ..Item::default()
+        };
+        let items = args.iter().map(|arg| Item::parse(arg, &defaults)).collect::<anyhow::Result<_>>()?;
+        Ok(Pathspec { items })
+    }
+
+    /// Whether `path`, relative to the top of the worktree, is selected:
+    /// matched by some pattern, or by none if every pattern excludes, and
+    /// not excluded.
+    pub fn matches(&self, path: &str) -> bool {
+        let mut includes = self.items.iter().filter(|item| !item.exclude).peekable();
+        let included = includes.peek().is_none() || includes.any(|item| item.matches(path));
+        included && !self.items.iter().any(|item| item.exclude && item.matches(path))
+    }
+}
@@ -0,0 +1,89 @@
+use std::io::{Read, Write};
+
+use anyhow::anyhow;
+
+use crate::trace;
+use crate::trace::TRACE_PACKET;
+
+/// Largest payload of a single pkt-line, git's `LARGE_PACKET_DATA_MAX`.
+pub(crate) const MAX_DATA_LENGTH: usize = 65516;
+
+pub(crate) fn write_packet(writer: &mut impl Write, data: &[u8]) -> anyhow::Result<()> {
+    if data.len() > MAX_DATA_LENGTH {
+        return Err(anyhow!("Packet too large: {} bytes", data.len()));
+    }
+    trace!(TRACE_PACKET, "packet: git> {}", trace::printable(data));
+    write!(writer, "{:04x}", data.len() + 4)?;
+    writer.write_all(data)?;
+
+    Ok(())
+}
+
+pub(crate) fn write_text(writer: &mut impl Write, text: &str) -> anyhow::Result<()> {
+    write_packet(writer, format!("{}\n", text).as_bytes())
+}
+
+pub(crate) fn write_flush(writer: &mut impl Write) -> anyhow::Result<()> {
+    trace!(TRACE_PACKET, "packet: git> 0000");
+    writer.write_all(b"0000")?;
+    Ok(())
+}
+
+/// Writes `data` split into as many packets as needed.
+pub(crate) fn write_data(writer: &mut impl Write, data: &[u8]) -> anyhow::Result<()> {
+    for chunk in data.chunks(MAX_DATA_LENGTH) {
+        write_packet(writer, chunk)?;
+    }
+
+    Ok(())
+}
+
+/// Reads one packet, returning `None` for a flush packet.
+pub(crate) fn read_packet(reader: &mut impl Read) -> anyhow::Result<Option<Vec<u8>>> {
+    let mut length = [0; 4];
+    reader.read_exact(&mut length)?;
+    let length = usize::from_str_radix(std::str::from_utf8(&length)?, 16)
+        .map_err(|_| anyhow!("Invalid pkt-line length: {}", String::from_utf8_lossy(&length)))?;
+
+    match length {
+        0 => {
+            trace!(TRACE_PACKET, "packet: git< 0000");
+            Ok(None)
+        }
+        1..=3 => Err(anyhow!("Invalid pkt-line length: {}", length)),
+        _ => {
+            let mut data = vec![0; length - 4];
+            reader.read_exact(&mut data)?;
+            trace!(TRACE_PACKET, "packet: git< {}", trace::printable(&data));
+            Ok(Some(data))
+        }
+    }
+}
+
+/// Reads a text packet with its trailing newline stripped.
+pub(crate) fn read_text(reader: &mut impl Read) -> anyhow::Result<Option<String>> {
+    Ok(read_packet(reader)?.map(|data| {
+        let text = String::from_utf8_lossy(&data);
+        text.strip_suffix('\n').unwrap_or(&text).to_string()
+    }))
+}
+
+/// Reads text packets up to the next flush.
+pub(crate) fn read_text_list(reader: &mut impl Read) -> anyhow::Result<Vec<String>> {
+    let mut lines = Vec::new();
+    while let Some(line) = read_text(reader)? {
+        lines.push(line);
+    }
+
+    Ok(lines)
+}
+
+/// Reads and concatenates data packets up to the next flush.
+pub(crate) fn read_data(reader: &mut impl Read) -> anyhow::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    while let Some(packet) = read_packet(reader)? {
+        data.extend_from_slice(&packet);
+    }
+
+    Ok(data)
+}
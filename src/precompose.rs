@@ -0,0 +1,22 @@
+use std::borrow::Cow;
+
+use unicode_normalization::{is_nfc, UnicodeNormalization};
+
+use crate::config::{self, Config};
+
+/// Whether `core.precomposeUnicode` is set, so that file names read from
+/// a directory are stored in precomposed form.
+pub fn enabled(config: &Config) -> bool {
+    config.get("core.precomposeunicode").and_then(config::parse_bool).unwrap_or(false)
+}
+
+/// `name` in precomposed (NFC) form. macOS hands back names decomposed,
+/// so `ä` would otherwise be stored as `a` followed by a combining mark
+/// and never match the tracked `ä`.
+pub fn precompose(name: &str) -> Cow<'_, str> {
+    if is_nfc(name) {
+        Cow::Borrowed(name)
+    } else {
+        Cow::Owned(name.nfc().collect())
+    }
+}
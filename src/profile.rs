@@ -0,0 +1,138 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Whether `--profile` was given. Counting is cheap, but phases are only
+/// timed when it is.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// A count of something a command did, reported by `--profile`.
+pub struct Counter {
+    name: &'static str,
+    value: AtomicU64,
+}
+
+impl Counter {
+    const fn new(name: &'static str) -> Counter {
+        Counter { name, value: AtomicU64::new(0) }
+    }
+
+    pub fn add(&self, amount: u64) {
+        self.value.fetch_add(amount, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.value.load(Ordering::Relaxed)
+    }
+}
+
+pub static OBJECT_READS: Counter = Counter::new("object reads");
+pub static BYTES_INFLATED: Counter = Counter::new("bytes inflated");
+pub static CACHE_HITS: Counter = Counter::new("cache hits");
+pub static CACHE_MISSES: Counter = Counter::new("cache misses");
+pub static OBJECT_WRITES: Counter = Counter::new("object writes");
+pub static BYTES_DEFLATED: Counter = Counter::new("bytes deflated");
+pub static FILES_OPENED: Counter = Counter::new("files opened");
+pub static FILES_WRITTEN: Counter = Counter::new("files written");
+
+const COUNTERS: [&Counter; 8] = [
+    &OBJECT_READS,
+    &BYTES_INFLATED,
+    &CACHE_HITS,
+    &CACHE_MISSES,
+    &OBJECT_WRITES,
+    &BYTES_DEFLATED,
+    &FILES_OPENED,
+    &FILES_WRITTEN,
+];
+
+/// What each phase took, in the order the phases first started.
+struct Phase {
+    name: &'static str,
+    time: Duration,
+    counts: [u64; COUNTERS.len()],
+}
+
+static PHASES: Mutex<Vec<Phase>> = Mutex::new(Vec::new());
+
+static START: Mutex<Option<Instant>> = Mutex::new(None);
+
+/// Starts collecting, from the global `--profile` flag.
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+    *START.lock().unwrap_or_else(|err| err.into_inner()) = Some(Instant::now());
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Times the phase `name` until the guard is dropped, attributing to it
+/// whatever the counters count meanwhile. A phase run several times is
+/// reported once, with the totals.
+pub fn phase(name: &'static str) -> PhaseGuard {
+    PhaseGuard { name, start: enabled().then(|| (Instant::now(), snapshot())) }
+}
+
+pub struct PhaseGuard {
+    name: &'static str,
+    start: Option<(Instant, [u64; COUNTERS.len()])>,
+}
+
+impl Drop for PhaseGuard {
+    fn drop(&mut self) {
+        let Some((started, start)) = self.start else {
+            return;
+        };
+        let end = snapshot();
+        let mut phases = PHASES.lock().unwrap_or_else(|err| err.into_inner());
+        let index = match phases.iter().position(|phase| phase.name == self.name) {
+            Some(index) => index,
+            None => {
+                phases.push(Phase { name: self.name, time: Duration::ZERO, counts: [0; COUNTERS.len()] });
+                phases.len() - 1
+            }
+        };
+        let phase = &mut phases[index];
+        phase.time += started.elapsed();
+        for (count, (end, start)) in phase.counts.iter_mut().zip(end.iter().zip(start)) {
+            *count += end - start;
+        }
+    }
+}
+
+fn snapshot() -> [u64; COUNTERS.len()] {
+    COUNTERS.map(Counter::get)
+}
+
+/// Prints what was collected to stderr, if `--profile` was given: the
+/// wall time, the counters, and what each phase took of both.
+pub fn report() {
+    let Some(start) = *START.lock().unwrap_or_else(|err| err.into_inner()) else {
+        return;
+    };
+    eprintln!("profile: {:<32} {:>12.3} s", "wall time", start.elapsed().as_secs_f64());
+    print_counts("", &snapshot());
+    let (hits, misses) = (CACHE_HITS.get(), CACHE_MISSES.get());
+    if hits + misses > 0 {
+        eprintln!("profile: {:<32} {:>12.1} %", "cache hit rate", hits as f64 * 100.0 / (hits + misses) as f64);
+    }
+
+    for phase in PHASES.lock().unwrap_or_else(|err| err.into_inner()).iter() {
+        eprintln!("profile: {:<32} {:>12.3} s", format!("{}: time", phase.name), phase.time.as_secs_f64());
+        print_counts(phase.name, &phase.counts);
+    }
+}
+
+fn print_counts(phase: &str, counts: &[u64; COUNTERS.len()]) {
+    for (counter, count) in COUNTERS.iter().zip(counts) {
+        if *count == 0 {
+            continue;
+        }
+        let label = match phase {
+            "" => counter.name.to_string(),
+            _ => format!("{}: {}", phase, counter.name),
+        };
+        eprintln!("profile: {:<32} {:>12}", label, count);
+    }
+}
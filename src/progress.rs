@@ -0,0 +1,60 @@
+use std::cell::Cell;
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+/// Like git, only show progress for operations that take a while.
+const DELAY: Duration = Duration::from_secs(2);
+
+/// A `Title:  42% (21/50)` counter on stderr, redrawn in place whenever the
+/// percentage changes.
+pub(crate) struct Progress {
+    title: &'static str,
+    total: usize,
+    enabled: bool,
+    start: Instant,
+    done: Cell<usize>,
+    shown_percent: Cell<Option<usize>>,
+}
+
+impl Progress {
+    pub(crate) fn new(title: &'static str, total: usize, enabled: bool) -> Progress {
+        Progress {
+            title,
+            total,
+            enabled: enabled && !crate::verbosity::quiet(),
+            start: Instant::now(),
+            done: Cell::new(0),
+            shown_percent: Cell::new(None),
+        }
+    }
+
+    pub(crate) fn tick(&self) {
+        self.done.set(self.done.get() + 1);
+        if !self.enabled || self.start.elapsed() < DELAY {
+            return;
+        }
+
+        let percent = self.percent();
+        if self.shown_percent.get() != Some(percent) {
+            self.shown_percent.set(Some(percent));
+            self.draw("\r");
+        }
+    }
+
+    /// Ends the counter with `, done.` if it was shown at all.
+    pub(crate) fn finish(&self) {
+        if self.shown_percent.get().is_some() {
+            self.draw(", done.\n");
+        }
+    }
+
+    fn percent(&self) -> usize {
+        (self.done.get() * 100).checked_div(self.total).unwrap_or(100)
+    }
+
+    fn draw(&self, end: &str) {
+        let mut stderr = std::io::stderr().lock();
+        let _ = write!(stderr, "{}: {:3}% ({}/{}){}", self.title, self.percent(), self.done.get(), self.total, end);
+        let _ = stderr.flush();
+    }
+}
@@ -0,0 +1,78 @@
+use std::borrow::Cow;
+use std::fmt::Write as _;
+
+/// Quotes `path` the way git's `quote_c_style` does when it contains
+/// control characters, `"` or `\`, or, with `quote_non_ascii` (the
+/// `core.quotePath` default), bytes outside ASCII.
+pub fn quote_path(path: &str, quote_non_ascii: bool) -> Cow<'_, str> {
+    let needs_quoting = path.bytes()
+        .any(|byte| byte < 0x20 || byte == 0x7f || byte == b'"' || byte == b'\\' || (quote_non_ascii && byte >= 0x80));
+    if !needs_quoting {
+        return Cow::Borrowed(path);
+    }
+
+    let mut quoted = String::with_capacity(path.len() + 2);
+    quoted.push('"');
+    for c in path.chars() {
+        match c {
+            '\x07' => quoted.push_str("\\a"),
+            '\x08' => quoted.push_str("\\b"),
+            '\t' => quoted.push_str("\\t"),
+            '\n' => quoted.push_str("\\n"),
+            '\x0b' => quoted.push_str("\\v"),
+            '\x0c' => quoted.push_str("\\f"),
+            '\r' => quoted.push_str("\\r"),
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if (c as u32) < 0x20 || c == '\x7f' || (quote_non_ascii && !c.is_ascii()) => {
+                let mut bytes = [0; 4];
+                for byte in c.encode_utf8(&mut bytes).bytes() {
+                    let _ = write!(quoted, "\\{:03o}", byte);
+                }
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+
+    Cow::Owned(quoted)
+}
+
+/// Reverses [`quote_path`] for a quoted path at the start of `text`,
+/// returning the path and what follows the closing quote. Returns `None`
+/// if `text` doesn't start with a well-formed quoted path.
+pub fn unquote_path(text: &str) -> Option<(String, &str)> {
+    let mut bytes = text.strip_prefix('"')?.bytes().enumerate();
+    let mut path = Vec::new();
+
+    while let Some((index, byte)) = bytes.next() {
+        match byte {
+            b'"' => {
+                let rest = &text[index + 2..];
+                return Some((String::from_utf8(path).ok()?, rest));
+            }
+            b'\\' => {
+                let (_, escaped) = bytes.next()?;
+                path.push(match escaped {
+                    b'a' => 0x07,
+                    b'b' => 0x08,
+                    b't' => b'\t',
+                    b'n' => b'\n',
+                    b'v' => 0x0b,
+                    b'f' => 0x0c,
+                    b'r' => b'\r',
+                    b'0'..=b'3' => {
+                        let (_, second) = bytes.next()?;
+                        let (_, third) = bytes.next()?;
+                        let digits = [escaped, second, third];
+                        u8::from_str_radix(std::str::from_utf8(&digits).ok()?, 8).ok()?
+                    }
+                    other => other,
+                });
+            }
+            byte => path.push(byte),
+        }
+    }
+
+    None
+}
@@ -0,0 +1,429 @@
+//! `range-diff`: compares two versions of a series of commits, as before
+//! and after a rebase. Each commit of one is paired with its counterpart
+//! in the other by how much their patches differ, and the differences
+//! between the patches of each pair are shown.
+
+use std::collections::HashMap;
+use std::io::Write;
+
+use anyhow::anyhow;
+
+use crate::diff::{self, Context};
+use crate::log;
+use crate::mailmap::{split_ident, Mailmap};
+use crate::object_id::ObjectId;
+use crate::repository::{self, Repository};
+use crate::rev_list::RevisionSet;
+use crate::userdiff::FuncName;
+
+/// How much a patch may change, in percent of its size, and still be
+/// paired with its old version rather than shown as new.
+pub const DEFAULT_CREATION_FACTOR: usize = 60;
+
+/// The cost of a pairing that must not be made.
+const COST_MAX: usize = 1 << 16;
+
+/// The hunk headers of the diff between two patches name the section of
+/// the patch, or the file and function of the hunk, they are in.
+const SECTION_HEADERS: &str = "^ ## (.*) ##$\n^.?@@ (.*)$";
+
+/// A commit of one of the ranges, and its patch as range-diff compares
+/// it.
+struct Patch {
+    sha: ObjectId,
+    subject: String,
+    /// The author and message of the commit, and its changes with a
+    /// ` ## <path> ##` line before each file and hunk headers without
+    /// line numbers, so that moving code around doesn't count.
+    text: String,
+    /// Where the changes start in `text`, or 0 if there are none.
+    diff_offset: usize,
+    /// How many lines the changes have.
+    size: usize,
+    /// The commit of the other range this one is paired with.
+    matching: Option<usize>,
+}
+
+impl Patch {
+    fn diff(&self) -> &str {
+        &self.text[self.diff_offset..]
+    }
+
+    /// The cost of showing this commit as added or removed rather than
+    /// paired with one of the other range.
+    fn creation_cost(&self, creation_factor: usize) -> usize {
+        match self.matching {
+            Some(_) => COST_MAX,
+            None => self.size * creation_factor / 100,
+        }
+    }
+}
+
+/// The commits of `range` other than merges, oldest first, with their
+/// patches.
+fn read_patches(repository: &Repository, range: &str, mailmap: &Mailmap) -> anyhow::Result<Vec<Patch>> {
+    let mut revisions = RevisionSet::parse(&[range.to_string()])?;
+    revisions.no_merges = true;
+    let mut patches = Vec::new();
+    for sha in revisions.walk()?.into_iter().rev() {
+        let commit = repository.read_commit(&sha)?;
+        let (name, email, _) = split_ident(&commit.author).ok_or(anyhow!("Invalid author in commit {}", sha))?;
+        let (name, email) = mailmap.lookup(name.unwrap_or_default(), email);
+        let mut text = format!(" ## Metadata ##\nAuthor: {} <{}>\n\n ## Commit message ##\n", name, email);
+        for line in commit.message.lines().map(str::trim_end).skip_while(|line| line.is_empty()) {
+            match line {
+                "" => text.push('\n'),
+                line => text.push_str(&format!("    {}\n", line)),
+            }
+        }
+
+        let mut patch = Patch { sha, subject: commit.subject(), text, diff_offset: 0, size: 0, matching: None };
+        if let Some(changes) = log::changes(repository, &commit)? {
+            let mut out = Vec::new();
+            changes.write_patch(diff::DEFAULT_CONTEXT, None, &mut out);
+            add_changes(&mut patch, &String::from_utf8_lossy(&out));
+        }
+        patches.push(patch);
+    }
+    Ok(patches)
+}
+
+/// Adds the changes of the commit, as `log -p` shows them, to the text
+/// of `patch`: the header of each file becomes one line naming it and
+/// whether it is new, deleted, renamed or changes mode, and the hunk
+/// headers lose their line numbers but gain the file name.
+fn add_changes(patch: &mut Patch, changes: &str) {
+    let mut lines = changes.lines().peekable();
+    let mut current = String::new();
+    while let Some(line) = lines.next() {
+        if let Some(names) = line.strip_prefix("diff --git a/") {
+            // Both names are the same unless the file is renamed.
+            let mut name = names[..names.len().saturating_sub(3) / 2].to_string();
+            let (mut new, mut deleted, mut renamed_from) = (false, false, None);
+            let (mut old_mode, mut new_mode) = (None, None);
+            let headers = ["new file mode ", "deleted file mode ", "old mode ", "new mode ", "similarity index ",
+                "rename from ", "rename to ", "index ", "--- ", "+++ "];
+            while let Some(line) = lines.next_if(|line| headers.iter().any(|header| line.starts_with(header))) {
+                let header = headers.iter().find(|header| line.starts_with(*header)).expect("Headers are known");
+                let value = &line[header.len()..];
+                match *header {
+                    "new file mode " => new = true,
+                    "deleted file mode " => deleted = true,
+                    "old mode " => old_mode = Some(value),
+                    "new mode " => new_mode = Some(value),
+                    "rename from " => renamed_from = Some(value.to_string()),
+                    "rename to " => name = value.to_string(),
+                    _ => {}
+                }
+            }
+
+            patch.text.push('\n');
+            if patch.diff_offset == 0 {
+                patch.diff_offset = patch.text.len();
+            }
+            let mut section = match renamed_from {
+                _ if new => format!(" ## {} (new)", name),
+                _ if deleted => format!(" ## {} (deleted)", name),
+                Some(from) => format!(" ## {} => {}", from, name),
+                None => format!(" ## {}", name),
+            };
+            if let (Some(old_mode), Some(new_mode)) = (old_mode, new_mode) {
+                section.push_str(&format!(" (mode change {} => {})", old_mode, new_mode));
+            }
+            patch.text.push_str(&section);
+            patch.text.push_str(" ##\n");
+            patch.size += 1;
+            current = name;
+            continue;
+        }
+
+        if let Some(header) = line.strip_prefix("@@ ") {
+            let function = header.find("@@").map_or("", |end| &header[end + 2..]);
+            patch.text.push_str("@@");
+            if !function.is_empty() {
+                patch.text.push_str(&format!(" {}:", current));
+            }
+            patch.text.push_str(function);
+        } else if line.starts_with(['+', '-', ' ']) {
+            patch.text.push_str(line);
+        } else if line.starts_with("Binary files ") {
+            // As without the a/ and b/ prefixes.
+            patch.text.push(' ');
+            patch.text.push_str(&line.replacen(" a/", " ", 1).replacen(" b/", " ", 1));
+        } else {
+            patch.text.push(' ');
+            patch.text.push_str(line);
+        }
+        patch.text.push('\n');
+        patch.size += 1;
+    }
+}
+
+/// Pairs the patches of `old` and `new` whose changes are exactly the
+/// same.
+fn find_exact_matches(old: &mut [Patch], new: &mut [Patch]) {
+    let mut by_diff: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (i, patch) in old.iter().enumerate() {
+        by_diff.entry(patch.diff()).or_default().push(i);
+    }
+    for patch in new.iter_mut() {
+        if let Some(i) = by_diff.get_mut(patch.diff()).filter(|same| !same.is_empty()).map(|same| same.remove(0)) {
+            patch.matching = Some(i);
+        }
+    }
+    for (j, patch) in new.iter().enumerate() {
+        if let Some(i) = patch.matching {
+            old[i].matching = Some(j);
+        }
+    }
+}
+
+/// How many lines the diff between two patches has, not counting its
+/// hunk headers.
+fn diff_size(old: &str, new: &str) -> usize {
+    let mut out = Vec::new();
+    diff::unified(old.as_bytes(), new.as_bytes(), &Context::default(), None, &mut out);
+    out.split(|&b| b == b'\n').filter(|line| !line.is_empty() && !line.starts_with(b"@@")).count()
+}
+
+/// Pairs the rest of the patches of `old` and `new` so that the pairs
+/// differ the least in total, where leaving a patch unpaired costs
+/// `creation_factor` percent of its size.
+fn find_correspondences(old: &mut [Patch], new: &mut [Patch], creation_factor: usize) {
+    let n = old.len() + new.len();
+    let mut cost = vec![vec![0; n]; n];
+    for (i, a) in old.iter().enumerate() {
+        for (j, b) in new.iter().enumerate() {
+            cost[i][j] = match (a.matching, b.matching) {
+                (Some(matching), _) if matching == j => 0,
+                (None, None) => diff_size(a.diff(), b.diff()),
+                _ => COST_MAX,
+            };
+        }
+        cost[i][new.len()..].fill(a.creation_cost(creation_factor));
+    }
+    for (j, b) in new.iter().enumerate() {
+        for row in &mut cost[old.len()..] {
+            row[j] = b.creation_cost(creation_factor);
+        }
+    }
+
+    for (i, j) in assign(&cost).into_iter().enumerate().take(old.len()) {
+        if j < new.len() {
+            old[i].matching = Some(j);
+            new[j].matching = Some(i);
+        }
+    }
+}
+
+/// The column each row of the square matrix `cost` is assigned to, so
+/// that the sum of the costs is the least: the Hungarian method.
+fn assign(cost: &[Vec<usize>]) -> Vec<usize> {
+    let n = cost.len();
+    // Potentials of the rows and columns, and the row of each column,
+    // counting from 1 so that 0 can stand for none.
+    let (mut row_potential, mut column_potential) = (vec![0i64; n + 1], vec![0i64; n + 1]);
+    let mut row_of = vec![0; n + 1];
+    let mut previous = vec![0; n + 1];
+    for row in 1..=n {
+        row_of[0] = row;
+        let mut column = 0;
+        let mut least = vec![i64::MAX; n + 1];
+        let mut used = vec![false; n + 1];
+        loop {
+            used[column] = true;
+            let current = row_of[column];
+            let (mut delta, mut next) = (i64::MAX, 0);
+            for j in 1..=n {
+                if !used[j] {
+                    let reduced = cost[current - 1][j - 1] as i64 - row_potential[current] - column_potential[j];
+                    if reduced < least[j] {
+                        least[j] = reduced;
+                        previous[j] = column;
+                    }
+                    if least[j] < delta {
+                        (delta, next) = (least[j], j);
+                    }
+                }
+            }
+            for j in 0..=n {
+                match used[j] {
+                    true => {
+                        row_potential[row_of[j]] += delta;
+                        column_potential[j] -= delta;
+                    }
+                    false => least[j] -= delta,
+                }
+            }
+            column = next;
+            if row_of[column] == 0 {
+                break;
+            }
+        }
+        while column != 0 {
+            let before = previous[column];
+            row_of[column] = row_of[before];
+            column = before;
+        }
+    }
+
+    let mut columns = vec![0; n];
+    for (column, &row) in row_of.iter().enumerate().skip(1) {
+        columns[row - 1] = column - 1;
+    }
+    columns
+}
+
+/// Writes the line that shows how the commits `old` and `new`, either of
+/// which may be missing, compare: `<` for one only in the old range, `>`
+/// for one only in the new, `=` for the same patch and `!` for a changed
+/// one.
+fn write_pair_header(old: Option<(usize, &Patch)>, new: Option<(usize, &Patch)>, width: usize, out: &mut Vec<u8>) {
+    let status = match (old, new) {
+        (_, None) => '<',
+        (None, _) => '>',
+        (Some((_, old)), Some((_, new))) if old.text != new.text => '!',
+        _ => '=',
+    };
+    let side = |patch: Option<(usize, &Patch)>| match patch {
+        Some((i, patch)) => format!("{:>width$}:  {}", i + 1, &patch.sha.to_string()[..7], width = width),
+        None => format!("{:>width$}:  -------", "-", width = width),
+    };
+    let subject = old.or(new).map_or("", |(_, patch)| &patch.subject);
+    out.extend_from_slice(format!("{} {} {} {}\n", side(old), status, side(new), subject).as_bytes());
+}
+
+/// Writes the diff between the texts of two patches, indented by four
+/// spaces, without line numbers in its hunk headers.
+fn write_patch_diff(old: &Patch, new: &Patch, sections: &FuncName, out: &mut Vec<u8>) {
+    let mut diff = Vec::new();
+    diff::unified(old.text.as_bytes(), new.text.as_bytes(), &Context::default(), Some(sections), &mut diff);
+    for line in diff.split_inclusive(|&b| b == b'\n') {
+        out.extend_from_slice(b"    ");
+        match line.strip_prefix(b"@@") {
+            Some(header) => {
+                let end = header.windows(2).position(|window| window == b"@@").map_or(header.len(), |end| end + 2);
+                out.extend_from_slice(b"@@");
+                out.extend_from_slice(&header[end..]);
+            }
+            None => out.extend_from_slice(line),
+        }
+    }
+}
+
+/// Shows how the commits of the ranges given by `args` compare: two
+/// ranges `A..B C..D`, the two sides of a symmetric range `A...B`, or
+/// `<base> <old> <new>` for `base..old` and `base..new`. The commits are
+/// listed in the order of the new range, each unpaired old commit after
+/// the ones before it; with `no_patch` the changes of changed patches
+/// are left out.
+pub fn run(args: &[String], creation_factor: usize, no_patch: bool) -> anyhow::Result<()> {
+    let head = |revision: &str| if revision.is_empty() { "HEAD".to_string() } else { revision.to_string() };
+    let (old_range, new_range) = match args {
+        [range] => match range.split_once("...") {
+            Some((a, b)) => (format!("{}..{}", head(b), head(a)), format!("{}..{}", head(a), head(b))),
+            None => return Err(anyhow!("need two commit ranges")),
+        },
+        [old, new] if old.contains("..") && new.contains("..") => (old.clone(), new.clone()),
+        [base, old, new] => (format!("{}..{}", base, old), format!("{}..{}", base, new)),
+        _ => return Err(anyhow!("need two commit ranges")),
+    };
+
+    let repository = repository::current()?;
+    let mailmap = Mailmap::load(None)?;
+    let mut old = read_patches(&repository, &old_range, &mailmap)?;
+    let mut new = read_patches(&repository, &new_range, &mailmap)?;
+    find_exact_matches(&mut old, &mut new);
+    find_correspondences(&mut old, &mut new, creation_factor);
+
+    let sections = FuncName::parse(SECTION_HEADERS, true)?;
+    let width = (1 + old.len().max(new.len())).to_string().len();
+    let mut shown = vec![false; old.len()];
+    let mut out = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        while i < old.len() && shown[i] {
+            i += 1;
+        }
+        // The old commits no longer in the new range come once the ones
+        // before them are shown.
+        if i < old.len() && old[i].matching.is_none() {
+            write_pair_header(Some((i, &old[i])), None, width, &mut out);
+            i += 1;
+            continue;
+        }
+        while j < new.len() && new[j].matching.is_none() {
+            write_pair_header(None, Some((j, &new[j])), width, &mut out);
+            j += 1;
+        }
+        if j < new.len() {
+            let matching = new[j].matching.expect("Unmatched commits are shown above");
+            write_pair_header(Some((matching, &old[matching])), Some((j, &new[j])), width, &mut out);
+            if !no_patch {
+                write_patch_diff(&old[matching], &new[j], &sections, &mut out);
+            }
+            shown[matching] = true;
+            j += 1;
+        }
+    }
+
+    match std::io::stdout().write_all(&out) {
+        Err(err) if err.kind() == std::io::ErrorKind::BrokenPipe => Ok(()),
+        result => Ok(result?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn patch(changes: &str) -> Patch {
+        let (sha, subject, text) = (ObjectId::Sha1([0; 20]), String::new(), String::new());
+        let mut patch = Patch { sha, subject, text, diff_offset: 0, size: 0, matching: None };
+        add_changes(&mut patch, changes);
+        patch
+    }
+
+    #[test]
+    fn changes_of_patches() {
+        let found = patch(concat!(
+            "diff --git a/src/main.rs b/src/main.rs\nindex 1234567..89abcde 100644\n",
+            "--- a/src/main.rs\n+++ b/src/main.rs\n@@ -1,2 +1,2 @@ fn main() {\n-    old();\n+    new();\n }\n",
+            "diff --git a/old name b/new name\nsimilarity index 100%\nrename from old name\nrename to new name\n",
+            "diff --git a/run.sh b/run.sh\nold mode 100644\nnew mode 100755\n",
+            "diff --git a/logo.png b/logo.png\nnew file mode 100644\nindex 0000000..1234567\n",
+            "Binary files /dev/null and b/logo.png differ\n",
+        ));
+        assert_eq!(
+            found.text,
+            concat!(
+                "\n ## src/main.rs ##\n@@ src/main.rs: fn main() {\n-    old();\n+    new();\n }\n",
+                "\n ## old name => new name ##\n",
+                "\n ## run.sh (mode change 100644 => 100755) ##\n",
+                "\n ## logo.png (new) ##\n Binary files /dev/null and logo.png differ\n",
+            )
+        );
+        assert_eq!((found.diff_offset, found.size), (1, 9));
+    }
+
+    #[test]
+    fn assignments() {
+        assert_eq!(assign(&[vec![4, 1, 3], vec![2, 0, 5], vec![3, 2, 2]]), [1, 0, 2]);
+        assert_eq!(assign(&[vec![0, 9], vec![9, 0]]), [0, 1]);
+        assert!(assign(&[]).is_empty());
+    }
+
+    #[test]
+    fn correspondences() {
+        let lines = |name: &str| (1..=10).map(|i| format!("+{} {}\n", name, i)).collect::<String>();
+        let edited = lines("one").replace("one 5", "five");
+        let mut old = vec![patch(&lines("one")), patch(&lines("two")), patch("+gone\n")];
+        let mut new = vec![patch(&lines("two")), patch(&edited), patch("+added\n+lines\n")];
+        find_exact_matches(&mut old, &mut new);
+        assert_eq!((old[1].matching, new[0].matching), (Some(0), Some(1)));
+        find_correspondences(&mut old, &mut new, DEFAULT_CREATION_FACTOR);
+        let matching: Vec<_> = old.iter().map(|patch| patch.matching).collect();
+        assert_eq!(matching, [Some(1), Some(0), None]);
+        assert_eq!(new[2].matching, None);
+    }
+}
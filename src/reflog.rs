@@ -0,0 +1,176 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::anyhow;
+
+use crate::date;
+use crate::ident::identity_time;
+use crate::lockfile;
+use crate::object_id::ObjectId;
+use crate::objects::ancestors;
+use crate::refs;
+use crate::repository::{self, Repository};
+
+const DAY: i64 = 24 * 60 * 60;
+
+/// Cutoffs for `reflog expire`, as seconds since the epoch.
+pub struct Expiry {
+    /// Entries older than this are removed.
+    pub expire: i64,
+    /// Entries older than this are removed if their commit is no longer
+    /// reachable from the ref.
+    pub expire_unreachable: i64,
+}
+
+impl Expiry {
+    /// Reads `gc.reflogExpire` and `gc.reflogExpireUnreachable`, which
+    /// default to 90 and 30 days.
+    pub fn from_config(repository: &Repository) -> anyhow::Result<Expiry> {
+        let config = repository.config()?;
+        let now = date::now().0;
+        let cutoff = |name: &str, days: i64| match config.get(name) {
+            Some(value) => date::parse_expiry(value),
+            None => Ok(now - days * DAY),
+        };
+
+        Ok(Expiry {
+            expire: cutoff("gc.reflogexpire", 90)?,
+            expire_unreachable: cutoff("gc.reflogexpireunreachable", 30)?,
+        })
+    }
+}
+
+/// Drops the entries of the reflogs of `refs`, or of every reflog with
+/// `all`, that are older than the cutoffs in `expiry`. With `dry_run`,
+/// only reports what would be pruned.
+pub fn expire(refs: &[String], all: bool, expiry: &Expiry, dry_run: bool, verbose: bool) -> anyhow::Result<()> {
+    let repository = repository::current()?;
+
+    let mut names = Vec::new();
+    for name in refs {
+        match refs::expand_ref(name)? {
+            Some((full_name, _)) => names.push(full_name),
+            None => names.push(name.clone()),
+        }
+    }
+    if all {
+        names.extend(reflogs(&repository)?);
+    }
+    names.sort();
+    names.dedup();
+
+    for name in names {
+        let path = log_path(&repository, &name);
+        if !path.is_file() {
+            return Err(anyhow!("Reflog could not be found: '{}'", name));
+        }
+
+        // An entry is reachable if the ref still contains its commit.
+        let reachable = match refs::read_ref(&name)? {
+            Some(tip) if expiry.expire_unreachable > expiry.expire => ancestors(&tip).unwrap_or_default(),
+            _ => Default::default(),
+        };
+
+        let content = fs::read_to_string(&path)?;
+        let mut kept = String::new();
+        for line in content.lines() {
+            let Some((new, time)) = parse_entry(line) else {
+                kept.push_str(line);
+                kept.push('\n');
+                continue;
+            };
+
+            let expired = time < expiry.expire
+                || (time < expiry.expire_unreachable && !reachable.contains(&new));
+            if expired {
+                if verbose || dry_run {
+                    println!("{}prune {}", if dry_run { "would " } else { "" }, new);
+                }
+            } else {
+                kept.push_str(line);
+                kept.push('\n');
+            }
+        }
+
+        if !dry_run && kept != content {
+            lockfile::write(&path, kept)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Where the reflog of `name` lives: `HEAD`'s and those of per-worktree
+/// refs belong to the worktree, the others to the common directory.
+fn log_path(repository: &Repository, name: &str) -> PathBuf {
+    if refs::is_shared(name) {
+        repository.common_dir().join("logs").join(name)
+    } else {
+        repository.git_dir().join("logs").join(name)
+    }
+}
+
+/// The reflogs of the repository, `HEAD`'s of every worktree among them.
+pub(crate) fn log_files(repository: &Repository) -> anyhow::Result<Vec<PathBuf>> {
+    let mut files: Vec<PathBuf> = reflogs(repository)?.iter().map(|name| log_path(repository, name)).collect();
+    if let Ok(entries) = fs::read_dir(repository.common_dir().join("worktrees")) {
+        files.extend(entries.flatten().map(|entry| entry.path().join("logs/HEAD")).filter(|path| path.is_file()));
+    }
+    files.push(repository.common_dir().join("logs/HEAD"));
+    files.sort();
+    files.dedup();
+    Ok(files)
+}
+
+/// Every object the entries of the reflog at `path` name, old and new.
+pub(crate) fn logged_objects(path: &Path) -> Vec<ObjectId> {
+    let content = fs::read_to_string(path).unwrap_or_default();
+    content.lines()
+        .flat_map(|line| line.split(' ').take(2))
+        .filter_map(|sha| sha.parse::<ObjectId>().ok())
+        .filter(|sha| *sha != sha.algorithm().null())
+        .collect()
+}
+
+/// The names of all reflogs: `HEAD` and every ref with one.
+fn reflogs(repository: &Repository) -> anyhow::Result<Vec<String>> {
+    let mut names = Vec::new();
+    if repository.git_dir().join("logs/HEAD").is_file() {
+        names.push("HEAD".to_string());
+    }
+
+    let logs_dir = repository.common_dir().join("logs");
+    let mut pending = vec![logs_dir.join("refs")];
+    while let Some(dir) = pending.pop() {
+        if !dir.is_dir() {
+            continue;
+        }
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                pending.push(path);
+            } else if path.extension() != Some("lock".as_ref()) {
+                names.push(log_name(&logs_dir, &path)?);
+            }
+        }
+    }
+
+    Ok(names)
+}
+
+fn log_name(logs_dir: &Path, path: &Path) -> anyhow::Result<String> {
+    Ok(path.strip_prefix(logs_dir)?
+        .to_string_lossy()
+        .replace(std::path::MAIN_SEPARATOR, "/"))
+}
+
+/// Reads the new ID and timestamp of a line such as
+/// `<old> <new> Name <email> 1709990458 +0200\tmessage`.
+fn parse_entry(line: &str) -> Option<(ObjectId, i64)> {
+    let (identity, _) = line.split_once('\t').unwrap_or((line, ""));
+    let mut fields = identity.splitn(3, ' ');
+    let _old = fields.next()?;
+    let new = fields.next()?.parse().ok()?;
+
+    Some((new, identity_time(fields.next()?)))
+}
@@ -0,0 +1,915 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+
+use anyhow::anyhow;
+
+use crate::config::Config;
+use crate::lockfile::{self, LockFile};
+use crate::object_id::{HashAlgorithm, ObjectId};
+use crate::objects::Object;
+use crate::reftable::{self, StackLock, Value};
+use crate::repository::{common_dir_of, current, git_dir, Repository};
+
+const MAX_SYMREF_DEPTH: usize = 5;
+
+/// How a repository stores its refs: `extensions.refStorage`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RefFormat {
+    /// A file per ref under `refs/`, and `packed-refs`.
+    #[default]
+    Files,
+    /// A stack of reftables in `reftable/`.
+    Reftable,
+}
+
+impl RefFormat {
+    pub fn from_name(name: &str) -> anyhow::Result<RefFormat> {
+        match name.to_lowercase().as_str() {
+            "files" => Ok(RefFormat::Files),
+            "reftable" => Ok(RefFormat::Reftable),
+            _ => Err(anyhow!("Unknown ref storage format: {}", name)),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            RefFormat::Files => "files",
+            RefFormat::Reftable => "reftable",
+        }
+    }
+
+    pub fn from_config(config: &Config) -> anyhow::Result<RefFormat> {
+        config.get("extensions.refstorage").map_or(Ok(RefFormat::Files), RefFormat::from_name)
+    }
+}
+
+/// Refs under `refs/` that each worktree has its own of, like git, so
+/// that bisecting in one worktree leaves the others alone.
+const PER_WORKTREE_PREFIXES: [&str; 3] = ["refs/bisect/", "refs/worktree/", "refs/rewritten/"];
+
+/// Whether `name` is shared by all worktrees rather than kept in each
+/// worktree's git directory.
+pub(crate) fn is_shared(name: &str) -> bool {
+    name.starts_with("refs/") && !PER_WORKTREE_PREFIXES.iter().any(|prefix| name.starts_with(prefix))
+}
+
+/// The reftable stack that holds `name` and the object format of its
+/// IDs, if the repository at `git_dir` stores refs in reftables: the
+/// common stack for shared refs, otherwise the worktree's own.
+/// `FETCH_HEAD` and `MERGE_HEAD` stay files, as they hold more than a ref.
+fn reftable_dir(git_dir: &Path, name: &str) -> anyhow::Result<Option<(PathBuf, HashAlgorithm)>> {
+    if name == "FETCH_HEAD" || name == "MERGE_HEAD" {
+        return Ok(None);
+    }
+    let common_dir = common_dir_of(git_dir)?;
+    let Some(format) = reftable_format(&common_dir)? else { return Ok(None) };
+    let dir = if is_shared(name) { common_dir } else { git_dir.to_path_buf() };
+    Ok(Some((dir.join("reftable"), format)))
+}
+
+/// The object format of the repository at `common_dir` if it stores refs
+/// in reftables. Repositories don't change how they store refs, so the
+/// config is read once per repository.
+fn reftable_format(common_dir: &Path) -> anyhow::Result<Option<HashAlgorithm>> {
+    static FORMATS: OnceLock<Mutex<HashMap<PathBuf, Option<HashAlgorithm>>>> = OnceLock::new();
+
+    let formats = FORMATS.get_or_init(Mutex::default);
+    if let Some(format) = formats.lock().expect("Ref format cache is not poisoned").get(common_dir) {
+        return Ok(*format);
+    }
+    let config = Config::from_file(&common_dir.join("config"))?;
+    let format = match RefFormat::from_config(&config)? {
+        RefFormat::Files => None,
+        RefFormat::Reftable => Some(
+            config.get("extensions.objectformat").map_or(Ok(HashAlgorithm::Sha1), HashAlgorithm::from_name)?,
+        ),
+    };
+    formats.lock().expect("Ref format cache is not poisoned").insert(common_dir.to_path_buf(), format);
+    Ok(format)
+}
+
+/// Returns the file backing a ref. `HEAD`, other pseudo-refs and refs such
+/// as `refs/bisect/` are per-worktree, the rest of `refs/` lives in the
+/// common directory.
+pub fn ref_path(name: &str) -> anyhow::Result<PathBuf> {
+    ref_path_in(&git_dir()?, name)
+}
+
+fn ref_path_in(git_dir: &Path, name: &str) -> anyhow::Result<PathBuf> {
+    if is_shared(name) {
+        Ok(common_dir_of(git_dir)?.join(name))
+    } else {
+        Ok(git_dir.join(name))
+    }
+}
+
+/// Resolves a ref to the object it points at, following symbolic refs
+/// and falling back to `packed-refs`. Returns `None` for missing and
+/// unborn refs.
+pub fn read_ref(name: &str) -> anyhow::Result<Option<ObjectId>> {
+    read_ref_in(&git_dir()?, name)
+}
+
+/// Like [`read_ref`], but for the repository at `git_dir`, such as a
+/// submodule.
+pub fn read_ref_in(git_dir: &Path, name: &str) -> anyhow::Result<Option<ObjectId>> {
+    follow(name, |name| raw_ref_in(git_dir, name))
+}
+
+/// Follows `name` through symbolic refs to the object it points at,
+/// reading what each ref holds with `raw`.
+pub(crate) fn follow(
+    name: &str,
+    raw: impl Fn(&str) -> anyhow::Result<Option<String>>,
+) -> anyhow::Result<Option<ObjectId>> {
+    let mut name = name.to_string();
+
+    for _ in 0..MAX_SYMREF_DEPTH {
+        let Some(content) = raw(&name)? else { return Ok(None) };
+        // These list an object per line, after the first of which git
+        // records what it was fetched from or merged as.
+        if name == "FETCH_HEAD" || name == "MERGE_HEAD" {
+            let Some(first) = content.split_whitespace().next() else { return Ok(None) };
+            let sha = first.parse().map_err(|_| anyhow!("Invalid ref {}: {}", name, first))?;
+            return Ok(Some(sha));
+        }
+        match content.strip_prefix("ref:") {
+            Some(target) => name = target.trim().to_string(),
+            None => {
+                let sha = content.parse()
+                    .map_err(|_| anyhow!("Invalid ref {}: {}", name, content))?;
+                return Ok(Some(sha));
+            }
+        }
+    }
+
+    Err(anyhow!("Symbolic ref nesting is too deep: {}", name))
+}
+
+/// What `name` holds, without following it: `ref: <target>` for a
+/// symbolic ref, otherwise an object ID. Returns `None` if it doesn't
+/// exist.
+pub fn raw_ref(name: &str) -> anyhow::Result<Option<String>> {
+    raw_ref_in(&git_dir()?, name)
+}
+
+/// Like [`raw_ref`], but for the repository or worktree at `git_dir`.
+pub fn raw_ref_in(git_dir: &Path, name: &str) -> anyhow::Result<Option<String>> {
+    if let Some((dir, format)) = reftable_dir(git_dir, name)? {
+        return Ok(match reftable::Stack::open(&dir, format)?.get(name) {
+            Some(Value::Symbolic(target)) => Some(format!("ref: {}", target)),
+            Some(Value::Object(sha) | Value::Peeled(sha, _)) => Some(sha.to_string()),
+            Some(Value::Deleted) | None => None,
+        });
+    }
+
+    let path = ref_path_in(git_dir, name)?;
+    if !path.is_file() {
+        return Ok(read_packed_ref(git_dir, name)?.map(|sha| sha.to_string()));
+    }
+    Ok(Some(fs::read_to_string(path)?.trim_end().to_string()))
+}
+
+fn read_packed_ref(git_dir: &Path, name: &str) -> anyhow::Result<Option<ObjectId>> {
+    let path = common_dir_of(git_dir)?.join("packed-refs");
+    if !is_shared(name) || !path.is_file() {
+        return Ok(None);
+    }
+
+    for line in fs::read_to_string(path)?.lines() {
+        if line.starts_with('#') || line.starts_with('^') {
+            continue;
+        }
+        if let Some((sha, ref_name)) = line.split_once(' ') {
+            if ref_name == name {
+                return Ok(Some(sha.parse()?));
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+/// Returns the branch HEAD points at, such as `main`, or `None` if it is
+/// detached.
+pub fn current_branch() -> anyhow::Result<Option<String>> {
+    let head = raw_ref("HEAD")?.unwrap_or_default();
+
+    Ok(head
+        .strip_prefix("ref:")
+        .and_then(|target| target.trim().strip_prefix("refs/heads/"))
+        .map(str::to_string))
+}
+
+/// Checks `name` against git's ref name rules. Names need at least two
+/// components unless `allow_onelevel`, and may contain a single `*` if
+/// `refspec_pattern`.
+pub fn is_valid_ref_name(name: &str, allow_onelevel: bool, refspec_pattern: bool) -> bool {
+    if name == "@" || name.contains("..") || name.contains("@{") || name.ends_with('.') {
+        return false;
+    }
+
+    let mut stars = 0;
+    for c in name.chars() {
+        match c {
+            '*' => stars += 1,
+            ' ' | '~' | '^' | ':' | '?' | '[' | '\\' => return false,
+            c if c.is_ascii_control() => return false,
+            _ => {}
+        }
+    }
+    if stars > usize::from(refspec_pattern) {
+        return false;
+    }
+
+    let components: Vec<&str> = name.split('/').collect();
+    if components.len() < 2 && !allow_onelevel {
+        return false;
+    }
+    components.iter().all(|component| {
+        !component.is_empty() && !component.starts_with('.') && !component.ends_with(".lock")
+    })
+}
+
+/// Drops a leading slash and collapses repeated ones, like
+/// `check-ref-format --normalize`.
+pub fn normalize_ref_name(name: &str) -> String {
+    let mut normalized = String::new();
+    for c in name.trim_start_matches('/').chars() {
+        if !(c == '/' && normalized.ends_with('/')) {
+            normalized.push(c);
+        }
+    }
+
+    normalized
+}
+
+pub fn write_ref(name: &str, sha: &ObjectId) -> anyhow::Result<()> {
+    write_ref_in(&git_dir()?, name, sha)
+}
+
+/// Like [`write_ref`], but for the repository at `git_dir`.
+pub fn write_ref_in(git_dir: &Path, name: &str, sha: &ObjectId) -> anyhow::Result<()> {
+    if !is_valid_ref_name(name, false, false) {
+        return Err(anyhow!("'{}' is not a valid ref name", name));
+    }
+    check_not_quarantined()?;
+
+    if let Some((dir, format)) = reftable_dir(git_dir, name)? {
+        let value = object_value(sha)?;
+        return StackLock::acquire(&dir, format)?.commit(BTreeMap::from([(name.to_string(), value)]));
+    }
+    let path = ref_path_in(git_dir, name)?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    lockfile::write(&path, format!("{}\n", sha))
+}
+
+/// Points the HEAD of the worktree whose git directory is `git_dir` at
+/// `head`: `ref: <ref>` or an object ID. With reftables, the `HEAD` file
+/// only names a branch that can't exist, for the versions of git that
+/// only recognize a git directory by it.
+pub(crate) fn write_head(git_dir: &Path, head: &str) -> anyhow::Result<()> {
+    let Some((dir, format)) = reftable_dir(git_dir, "HEAD")? else {
+        return lockfile::write(&git_dir.join("HEAD"), format!("{}\n", head));
+    };
+    let placeholder = git_dir.join("HEAD");
+    if !placeholder.is_file() {
+        fs::write(placeholder, "ref: refs/heads/.invalid\n")?;
+    }
+
+    let value = match head.strip_prefix("ref:") {
+        Some(target) => Value::Symbolic(target.trim().to_string()),
+        None => Value::Object(head.parse().map_err(|_| anyhow!("Invalid HEAD: {}", head))?),
+    };
+    StackLock::acquire(&dir, format)?.commit(BTreeMap::from([("HEAD".to_string(), value)]))
+}
+
+/// The reftable value of a ref at `sha`, which like `packed-refs` records
+/// what an annotated tag peels to.
+fn object_value(sha: &ObjectId) -> anyhow::Result<Value> {
+    let repository = current()?;
+    let mut peeled = *sha;
+    while let Ok(Object::Tag(tag)) = repository.read(&peeled) {
+        peeled = tag.object;
+    }
+    Ok(if peeled == *sha { Value::Object(*sha) } else { Value::Peeled(*sha, peeled) })
+}
+
+/// Lists the names of all refs under `prefix` (such as `refs/remotes/`),
+/// loose and packed, in sorted order.
+pub fn list_refs(prefix: &str) -> anyhow::Result<Vec<String>> {
+    list_refs_in(&git_dir()?, prefix)
+}
+
+/// Like [`list_refs`], but for the repository at `git_dir`.
+pub fn list_refs_in(git_dir: &Path, prefix: &str) -> anyhow::Result<Vec<String>> {
+    let common_dir = common_dir_of(git_dir)?;
+    // A linked worktree's per-worktree refs are in its own git directory,
+    // and those in the common directory are the main worktree's.
+    let linked = common_dir != git_dir;
+    let dirs = [Some((common_dir.clone(), true)), linked.then(|| (git_dir.to_path_buf(), false))];
+    let wanted = |name: &str, shared: bool| {
+        name.starts_with("refs/") && name.starts_with(prefix) && (!linked || is_shared(name) == shared)
+    };
+
+    if let Some((_, format)) = reftable_dir(git_dir, "refs/")? {
+        let mut names = BTreeSet::new();
+        for (dir, shared) in dirs.into_iter().flatten() {
+            let stack = reftable::Stack::open(&dir.join("reftable"), format)?;
+            names.extend(stack.refs().into_keys().filter(|name| wanted(name, shared)).map(String::from));
+        }
+        return Ok(names.into_iter().collect());
+    }
+
+    let mut names = BTreeSet::new();
+    for (base, shared) in dirs.into_iter().flatten() {
+        let mut pending = vec![base.join("refs")];
+        while let Some(dir) = pending.pop() {
+            if !dir.is_dir() {
+                continue;
+            }
+            for entry in fs::read_dir(dir)? {
+                let path = entry?.path();
+                if path.is_dir() {
+                    pending.push(path);
+                    continue;
+                }
+                // Locks held by writers in progress aren't refs.
+                if path.extension() == Some("lock".as_ref()) {
+                    continue;
+                }
+                let name = path.strip_prefix(&base)?
+                    .to_string_lossy()
+                    .replace(std::path::MAIN_SEPARATOR, "/");
+                if wanted(&name, shared) {
+                    names.insert(name);
+                }
+            }
+        }
+    }
+
+    let packed_path = common_dir.join("packed-refs");
+    if packed_path.is_file() {
+        for line in fs::read_to_string(packed_path)?.lines() {
+            if line.starts_with('#') || line.starts_with('^') {
+                continue;
+            }
+            if let Some((_, name)) = line.split_once(' ') {
+                if name.starts_with(prefix) && is_shared(name) {
+                    names.insert(name.to_string());
+                }
+            }
+        }
+    }
+
+    Ok(names.into_iter().collect())
+}
+
+/// Deletes the given refs, both their loose files and their `packed-refs`
+/// lines, and prunes the directories left empty.
+pub fn delete_refs(names: &[String]) -> anyhow::Result<()> {
+    check_not_quarantined()?;
+    let git_dir = git_dir()?;
+
+    let mut stacks: BTreeMap<PathBuf, (HashAlgorithm, Vec<&String>)> = BTreeMap::new();
+    let mut files = Vec::new();
+    for name in names {
+        match reftable_dir(&git_dir, name)? {
+            Some((dir, format)) => stacks.entry(dir).or_insert((format, Vec::new())).1.push(name),
+            None => files.push(name.clone()),
+        }
+    }
+    for (dir, (format, names)) in stacks {
+        let lock = StackLock::acquire(&dir, format)?;
+        let deleted = names
+            .into_iter()
+            .filter(|name| lock.stack().get(name).is_some())
+            .map(|name| (name.clone(), Value::Deleted))
+            .collect();
+        lock.commit(deleted)?;
+    }
+
+    // Packed first, so that no ref shows its packed value meanwhile.
+    if let Some(lock) = packed_refs_without(&git_dir, &files)? {
+        lock.rename()?;
+    }
+    remove_loose_refs(&git_dir, &files)
+}
+
+/// `packed-refs` without `names`, written and flushed under its lock but
+/// not yet in place, or `None` if there is no `packed-refs`.
+fn packed_refs_without(git_dir: &Path, names: &[String]) -> anyhow::Result<Option<LockFile>> {
+    let packed_path = common_dir_of(git_dir)?.join("packed-refs");
+    if names.is_empty() || !packed_path.is_file() {
+        return Ok(None);
+    }
+
+    let mut lock = LockFile::acquire(&packed_path)?;
+    let mut packed = String::new();
+    let mut deleted = false;
+    for line in fs::read_to_string(&packed_path)?.lines() {
+        if line.starts_with('^') && deleted {
+            continue;
+        }
+        deleted = !line.starts_with('#') && line.split_once(' ')
+            .is_some_and(|(_, name)| names.iter().any(|deleted| deleted == name));
+        if !deleted {
+            packed.push_str(line);
+            packed.push('\n');
+        }
+    }
+    lock.write(packed.as_bytes())?;
+    lock.flush()?;
+    Ok(Some(lock))
+}
+
+fn remove_loose_refs(git_dir: &Path, names: &[String]) -> anyhow::Result<()> {
+    let common_dir = common_dir_of(git_dir)?;
+    for name in names {
+        let base = if is_shared(name) { common_dir.as_path() } else { git_dir };
+        remove_loose_ref(base, &base.join("refs"), name)?;
+    }
+    Ok(())
+}
+
+/// One change to a ref in a [`Transaction`].
+#[derive(Debug, Clone)]
+pub struct RefUpdate {
+    pub name: String,
+    /// What the ref is set to, the null ID to delete it, or `None` to
+    /// only check `old`.
+    pub new: Option<ObjectId>,
+    /// What the ref must point at beforehand, the null ID if it must not
+    /// exist, or `None` for anything.
+    pub old: Option<ObjectId>,
+    /// Change a symbolic ref itself rather than the ref it points at.
+    pub no_deref: bool,
+}
+
+/// Changes several refs at once, git's ref transaction: [`prepare`] locks
+/// every ref and checks its old value, so that [`commit`] changes all of
+/// them or, if anything is wrong, none. Dropping a transaction releases
+/// its locks.
+///
+/// [`prepare`]: Transaction::prepare
+/// [`commit`]: Transaction::commit
+#[derive(Default)]
+pub struct Transaction {
+    updates: Vec<RefUpdate>,
+    /// Once prepared, the ref each update resolved to and how it is held.
+    locks: Vec<(String, Held)>,
+    /// The reftable stacks that `Held::Stack` refs are in, each locked as
+    /// a whole.
+    stacks: BTreeMap<PathBuf, StackLock>,
+    /// The directories created to hold lock files, outermost first,
+    /// removed again if they end up empty.
+    created: Vec<PathBuf>,
+}
+
+/// How a prepared update keeps others from changing its ref.
+enum Held {
+    /// The lock of its file.
+    File(LockFile),
+    /// The lock of the reftable stack in this directory.
+    Stack(PathBuf),
+}
+
+impl Transaction {
+    pub fn add(&mut self, update: RefUpdate) -> anyhow::Result<()> {
+        if !is_valid_ref_name(&update.name, true, false) {
+            return Err(anyhow!("invalid ref format: {}", update.name));
+        }
+        self.updates.push(update);
+        Ok(())
+    }
+
+    /// Locks every ref and checks that it can be changed as asked.
+    pub fn prepare(&mut self) -> anyhow::Result<()> {
+        if !self.locks.is_empty() {
+            return Ok(());
+        }
+        check_not_quarantined()?;
+        let repository = current()?;
+        let git_dir = git_dir()?;
+
+        let mut names = BTreeSet::new();
+        let mut resolved = Vec::new();
+        for update in &self.updates {
+            let name = if update.no_deref { update.name.clone() } else { resolve_symref(&update.name)? };
+            if !names.insert(name.clone()) {
+                return Err(anyhow!("multiple updates for ref '{}' not allowed", name));
+            }
+            resolved.push(name);
+        }
+        check_conflicts(&self.updates, &resolved)?;
+
+        let result = self.lock_all(&repository, &git_dir, resolved);
+        if result.is_err() {
+            self.remove_created();
+        }
+        result
+    }
+
+    fn lock_all(&mut self, repository: &Repository, git_dir: &Path, resolved: Vec<String>) -> anyhow::Result<()> {
+        let mut locks = Vec::new();
+        let mut stacks = BTreeMap::new();
+        for (update, name) in self.updates.iter().zip(resolved) {
+            let cannot_lock = |reason: String| anyhow!("cannot lock ref '{}': {}", update.name, reason);
+            let held = match reftable_dir(git_dir, &name)? {
+                Some((dir, format)) => {
+                    if !stacks.contains_key(&dir) {
+                        let lock = StackLock::acquire(&dir, format).map_err(|error| cannot_lock(error.to_string()))?;
+                        stacks.insert(dir.clone(), lock);
+                    }
+                    Held::Stack(dir)
+                }
+                None => {
+                    let path = ref_path(&name)?;
+                    if let Some(parent) = path.parent() {
+                        let missing = parent.ancestors().take_while(|dir| !dir.exists()).count();
+                        let created: Vec<PathBuf> = parent.ancestors().take(missing).map(Path::to_path_buf).collect();
+                        self.created.extend(created.into_iter().rev());
+                        fs::create_dir_all(parent).map_err(|error| cannot_lock(error.to_string()))?;
+                    }
+                    Held::File(LockFile::acquire(&path).map_err(|error| cannot_lock(error.to_string()))?)
+                }
+            };
+            match (update.old, read_ref(&name)?) {
+                (Some(old), Some(_)) if old == old.algorithm().null() => {
+                    return Err(cannot_lock("reference already exists".to_string()))
+                }
+                (Some(old), None) if old != old.algorithm().null() => {
+                    return Err(cannot_lock(format!("unable to resolve reference '{}'", name)))
+                }
+                (Some(old), Some(sha)) if old != sha => {
+                    return Err(cannot_lock(format!("is at {} but expected {}", sha, old)))
+                }
+                _ => {}
+            }
+
+            if let Some(new) = update.new.filter(|new| *new != new.algorithm().null()) {
+                let cannot_update = |reason: String| anyhow!("cannot update ref '{}': {}", name, reason);
+                let (kind, _) = repository.object_header(&new).map_err(|_| {
+                    cannot_update(format!("trying to write ref '{}' with nonexistent object {}", name, new))
+                })?;
+                if kind != "commit" && name.starts_with("refs/heads/") {
+                    let reason = format!("trying to write non-commit object {} to branch '{}'", new, name);
+                    return Err(cannot_update(reason));
+                }
+            }
+            locks.push((name, held));
+        }
+
+        self.locks = locks;
+        self.stacks = stacks;
+        Ok(())
+    }
+
+    /// Removes the directories made for lock files that are empty now,
+    /// innermost first.
+    fn remove_created(&mut self) {
+        for dir in self.created.drain(..).rev() {
+            let _ = fs::remove_dir(dir);
+        }
+    }
+
+    /// Prepares the transaction if that wasn't done yet, then writes every
+    /// new value and deletes the refs being deleted. Everything that can
+    /// fail short of moving files into place comes first, so that a
+    /// failure leaves every ref as it was.
+    pub fn commit(mut self) -> anyhow::Result<()> {
+        self.prepare()?;
+
+        let mut changes: BTreeMap<PathBuf, BTreeMap<String, Value>> = BTreeMap::new();
+        let mut deleted = Vec::new();
+        // Held until the refs are gone, so that nothing recreates them.
+        let mut delete_locks = Vec::new();
+        let mut written = Vec::new();
+        for (update, (name, held)) in self.updates.iter().zip(std::mem::take(&mut self.locks)) {
+            let Some(new) = update.new else { continue };
+            let delete = new == new.algorithm().null();
+            match held {
+                Held::Stack(dir) => {
+                    let value = if delete { Value::Deleted } else { object_value(&new)? };
+                    changes.entry(dir).or_default().insert(name, value);
+                }
+                Held::File(lock) if delete => {
+                    deleted.push(name);
+                    delete_locks.push(lock);
+                }
+                Held::File(mut lock) => {
+                    lock.write(format!("{}\n", new).as_bytes())?;
+                    lock.flush()?;
+                    written.push(lock);
+                }
+            }
+        }
+        let mut staged = Vec::new();
+        for (dir, lock) in std::mem::take(&mut self.stacks) {
+            staged.push(lock.stage(changes.remove(&dir).unwrap_or_default())?);
+        }
+        let git_dir = git_dir()?;
+        let packed = packed_refs_without(&git_dir, &deleted)?;
+
+        for staged in staged {
+            staged.commit()?;
+        }
+        if let Some(lock) = packed {
+            lock.rename()?;
+        }
+        for lock in written {
+            lock.rename()?;
+        }
+        remove_loose_refs(&git_dir, &deleted)
+    }
+}
+
+impl Drop for Transaction {
+    fn drop(&mut self) {
+        // The locks go first, leaving the directories made for them empty.
+        self.locks.clear();
+        self.stacks.clear();
+        self.remove_created();
+    }
+}
+
+/// Fails if a ref being written would need a directory where a ref is,
+/// or be where refs are below it, on disk or in the same transaction.
+fn check_conflicts(updates: &[RefUpdate], resolved: &[String]) -> anyhow::Result<()> {
+    let writes = |update: &RefUpdate| update.new.is_some_and(|new| new != new.algorithm().null());
+    for (update, name) in updates.iter().zip(resolved) {
+        if !writes(update) {
+            continue;
+        }
+        let cannot_lock = |reason: String| anyhow!("cannot lock ref '{}': {}", update.name, reason);
+        let ancestors = name.match_indices('/').map(|(index, _)| &name[..index]).filter(|prefix| prefix.contains('/'));
+        for ancestor in ancestors {
+            if raw_ref(ancestor)?.is_some() {
+                return Err(cannot_lock(format!("'{}' exists; cannot create '{}'", ancestor, name)));
+            }
+        }
+        if let Some(descendant) = list_refs(&format!("{}/", name))?.first() {
+            return Err(cannot_lock(format!("'{}' exists; cannot create '{}'", descendant, name)));
+        }
+        let other = updates.iter().zip(resolved).find(|(other, other_name)| {
+            writes(other)
+                && (other_name.starts_with(&format!("{}/", name)) || name.starts_with(&format!("{}/", other_name)))
+        });
+        if let Some((_, other_name)) = other {
+            return Err(cannot_lock(format!("cannot process '{}' and '{}' at the same time", name, other_name)));
+        }
+    }
+    Ok(())
+}
+
+/// Follows the symbolic refs from `name` to the ref that holds an object
+/// ID, or would for an unborn branch.
+fn resolve_symref(name: &str) -> anyhow::Result<String> {
+    let mut name = name.to_string();
+    for _ in 0..MAX_SYMREF_DEPTH {
+        let Some(content) = raw_ref(&name)? else { return Ok(name) };
+        match content.strip_prefix("ref:") {
+            Some(target) => name = target.trim().to_string(),
+            None => return Ok(name),
+        }
+    }
+
+    Err(anyhow!("Symbolic ref nesting is too deep: {}", name))
+}
+
+/// Refs must not change while `GIT_QUARANTINE_PATH` is set: objects in
+/// the quarantine haven't been accepted yet, and a ref pointing at one
+/// would dangle if the quarantine is thrown away.
+fn check_not_quarantined() -> anyhow::Result<()> {
+    if std::env::var_os("GIT_QUARANTINE_PATH").is_some() {
+        return Err(anyhow!("Ref updates forbidden inside quarantine environment"));
+    }
+
+    Ok(())
+}
+
+/// Deletes the loose file of a ref, if any, and the directories it leaves
+/// empty. Like git, top-level directories such as `refs/heads` are kept.
+fn remove_loose_ref(base: &Path, refs_dir: &Path, name: &str) -> anyhow::Result<()> {
+    let path = base.join(name);
+    if !path.is_file() {
+        return Ok(());
+    }
+    fs::remove_file(&path)?;
+
+    let mut dir = path.parent();
+    while let Some(parent) = dir.filter(|parent| parent.parent().is_some_and(|up| up != refs_dir)) {
+        if fs::remove_dir(parent).is_err() {
+            break;
+        }
+        dir = parent.parent();
+    }
+
+    Ok(())
+}
+
+/// Moves every loose ref into `packed-refs`, recording what annotated
+/// tags peel to, and deletes the loose files. Symbolic refs and
+/// per-worktree refs stay loose.
+pub fn pack_refs() -> anyhow::Result<()> {
+    check_not_quarantined()?;
+    // Reftables are packed by merging them into one.
+    if let Some((dir, format)) = reftable_dir(&git_dir()?, "refs/")? {
+        return StackLock::acquire(&dir, format)?.compact();
+    }
+    let repository = current()?;
+    let common_dir = repository.common_dir().to_path_buf();
+    let refs_dir = common_dir.join("refs");
+    let mut lock = LockFile::acquire(&common_dir.join("packed-refs"))?;
+
+    let mut loose = Vec::new();
+    let mut packed = String::from("# pack-refs with: peeled fully-peeled sorted \n");
+    for name in list_refs("refs/")?.into_iter().filter(|name| is_shared(name)) {
+        let path = common_dir.join(&name);
+        if path.is_file() {
+            if fs::read_to_string(&path)?.starts_with("ref:") {
+                continue;
+            }
+            loose.push(name.clone());
+        }
+        let Some(sha) = read_ref(&name)? else { continue };
+
+        packed.push_str(&format!("{} {}\n", sha, name));
+        let mut peeled = sha;
+        while let Object::Tag(tag) = repository.read(&peeled)? {
+            peeled = tag.object;
+        }
+        if peeled != sha {
+            packed.push_str(&format!("^{}\n", peeled));
+        }
+    }
+
+    lock.write(packed.as_bytes())?;
+    lock.commit()?;
+    for name in &loose {
+        remove_loose_ref(&common_dir, &refs_dir, name)?;
+    }
+
+    Ok(())
+}
+
+/// Finds the ref a short name such as `main` or `v1.0` refers to, using
+/// git's lookup order, and returns its full name and target.
+pub fn expand_ref(name: &str) -> anyhow::Result<Option<(String, ObjectId)>> {
+    let candidates = [
+        name.to_string(),
+        format!("refs/{}", name),
+        format!("refs/tags/{}", name),
+        format!("refs/heads/{}", name),
+        format!("refs/remotes/{}", name),
+        format!("refs/remotes/{}/HEAD", name),
+    ];
+    for candidate in candidates {
+        if let Some(sha) = read_ref(&candidate)? {
+            return Ok(Some((candidate, sha)));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Turns a user-supplied revision (a full SHA, `HEAD`, a full ref name or
+/// a short branch/tag name) into an object id, using git's lookup order.
+pub fn resolve_revision(revision: &str) -> anyhow::Result<ObjectId> {
+    if let Some((_, sha)) = expand_ref(revision)? {
+        return Ok(sha);
+    }
+
+    if revision.len() == 40 || revision.len() == 64 {
+        if let Ok(sha) = revision.parse() {
+            return Ok(sha);
+        }
+    }
+
+    Err(anyhow!("Not a valid object name: {}", revision))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn update(name: &str, new: ObjectId, old: Option<ObjectId>) -> RefUpdate {
+        RefUpdate { name: name.to_string(), new: Some(new), old, no_deref: false }
+    }
+
+    fn transaction(updates: Vec<RefUpdate>) -> Transaction {
+        let mut transaction = Transaction::default();
+        for update in updates {
+            transaction.add(update).unwrap();
+        }
+        transaction
+    }
+
+    fn error(result: anyhow::Result<()>) -> String {
+        result.unwrap_err().to_string()
+    }
+
+    /// Refs are read through the repository in the current directory,
+    /// which is kept for the whole process, so every case runs in turn in
+    /// one temporary repository.
+    #[test]
+    fn transactions() {
+        let dir = std::env::temp_dir().join(format!("git-starter-rust-refs-{}", std::process::id()));
+        let (repository, _) = Repository::init(&dir, false, Some("main"), None, None, None).unwrap();
+        std::env::set_current_dir(&dir).unwrap();
+        let tree = repository.write_object("tree", b"", true).unwrap();
+        let body = format!("tree {}\nauthor A <a@a> 0 +0000\ncommitter A <a@a> 0 +0000\n\nOne\n", tree);
+        let one = repository.write_object("commit", body.as_bytes(), true).unwrap();
+        let two = repository.write_object("commit", body.replace("One", "Two").as_bytes(), true).unwrap();
+        let null = one.algorithm().null();
+
+        transaction(vec![update("refs/heads/main", one, Some(null)), update("refs/heads/topic", one, None)])
+            .commit()
+            .unwrap();
+        assert_eq!(read_ref("refs/heads/main").unwrap(), Some(one));
+        assert_eq!(read_ref("refs/heads/topic").unwrap(), Some(one));
+
+        conflicts(one);
+        aborts(one, two, null);
+        drop_releases_locks(one, two);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn conflicts(one: ObjectId) {
+        assert_eq!(
+            error(transaction(vec![update("refs/heads/main/sub", one, None)]).prepare()),
+            "cannot lock ref 'refs/heads/main/sub': 'refs/heads/main' exists; cannot create 'refs/heads/main/sub'"
+        );
+        assert_eq!(
+            error(transaction(vec![update("refs/heads", one, None)]).prepare()),
+            "cannot lock ref 'refs/heads': 'refs/heads/main' exists; cannot create 'refs/heads'"
+        );
+        let updates = vec![update("refs/heads/new", one, None), update("refs/heads/new/sub", one, None)];
+        assert_eq!(
+            error(transaction(updates).prepare()),
+            "cannot lock ref 'refs/heads/new': \
+             cannot process 'refs/heads/new' and 'refs/heads/new/sub' at the same time"
+        );
+        let updates = vec![update("refs/heads/main", one, None), update("HEAD", one, None)];
+        assert_eq!(error(transaction(updates).prepare()), "multiple updates for ref 'refs/heads/main' not allowed");
+        assert!(!Path::new(".git/refs/heads/new").exists());
+    }
+
+    fn aborts(one: ObjectId, two: ObjectId, null: ObjectId) {
+        // A failed check leaves every ref, even those checked before it,
+        // as it was, and removes the directories made for lock files.
+        let updates = vec![
+            update("refs/heads/deep/er/branch", two, Some(null)),
+            update("refs/heads/topic", two, Some(two)),
+        ];
+        assert_eq!(
+            error(transaction(updates).commit()),
+            format!("cannot lock ref 'refs/heads/topic': is at {} but expected {}", one, two)
+        );
+        assert_eq!(read_ref("refs/heads/topic").unwrap(), Some(one));
+        assert!(!Path::new(".git/refs/heads/deep").exists());
+
+        assert_eq!(
+            error(transaction(vec![update("refs/heads/main", two, Some(null))]).commit()),
+            "cannot lock ref 'refs/heads/main': reference already exists"
+        );
+        assert_eq!(
+            error(transaction(vec![update("refs/heads/gone", two, Some(one))]).commit()),
+            "cannot lock ref 'refs/heads/gone': unable to resolve reference 'refs/heads/gone'"
+        );
+        let missing = one.algorithm().hash(b"missing");
+        assert_eq!(
+            error(transaction(vec![update("refs/heads/main", missing, None)]).commit()),
+            format!(
+                "cannot update ref 'refs/heads/main': trying to write ref 'refs/heads/main' with nonexistent object {}",
+                missing
+            )
+        );
+        assert_eq!(read_ref("refs/heads/main").unwrap(), Some(one));
+
+        let updates = vec![update("refs/heads/topic", null, Some(one)), update("refs/heads/main", two, Some(one))];
+        transaction(updates).commit().unwrap();
+        assert_eq!(read_ref("refs/heads/topic").unwrap(), None);
+        assert_eq!(read_ref("refs/heads/main").unwrap(), Some(two));
+    }
+
+    fn drop_releases_locks(one: ObjectId, two: ObjectId) {
+        let mut prepared = transaction(vec![update("refs/heads/main", one, None), update("refs/tags/v1/x", one, None)]);
+        prepared.prepare().unwrap();
+        assert!(Path::new(".git/refs/heads/main.lock").exists());
+        let locked = error(transaction(vec![update("refs/heads/main", one, None)]).prepare());
+        assert!(locked.starts_with("cannot lock ref 'refs/heads/main': "), "{}", locked);
+
+        drop(prepared);
+        assert!(!Path::new(".git/refs/heads/main.lock").exists());
+        assert!(!Path::new(".git/refs/tags/v1").exists());
+        assert_eq!(read_ref("refs/heads/main").unwrap(), Some(two));
+        transaction(vec![update("refs/heads/main", one, None)]).commit().unwrap();
+        assert_eq!(read_ref("refs/heads/main").unwrap(), Some(one));
+    }
+}
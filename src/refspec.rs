@@ -0,0 +1,121 @@
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+use anyhow::anyhow;
+
+/// A refspec such as `+refs/heads/*:refs/remotes/origin/*`, mapping refs
+/// on one side of a fetch or push to refs on the other.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Refspec {
+    /// Whether the update is allowed even if it isn't a fast-forward.
+    pub force: bool,
+    pub source: String,
+    pub destination: Option<String>,
+}
+
+impl Refspec {
+    /// Whether both sides contain a `*` that matches any ref name suffix.
+    pub fn is_pattern(&self) -> bool {
+        self.source.contains('*')
+    }
+
+    /// Maps a ref on the source side to its destination, or `None` if
+    /// `name` doesn't match or the refspec has no destination.
+    pub fn map_source(&self, name: &str) -> Option<String> {
+        map(&self.source, self.destination.as_deref()?, name)
+    }
+
+    /// Maps a ref on the destination side back to its source, such as a
+    /// remote-tracking ref to the remote branch it mirrors.
+    pub fn map_destination(&self, name: &str) -> Option<String> {
+        map(self.destination.as_deref()?, &self.source, name)
+    }
+
+    /// Whether `name` is matched by the source side.
+    pub fn matches_source(&self, name: &str) -> bool {
+        match_pattern(&self.source, name).is_some()
+    }
+
+    /// Whether `name` is matched by the destination side.
+    pub fn matches_destination(&self, name: &str) -> bool {
+        self.destination.as_deref()
+            .is_some_and(|destination| match_pattern(destination, name).is_some())
+    }
+}
+
+/// Returns the part of `name` matched by the `*` in `pattern`, or the empty
+/// string for an exact match.
+fn match_pattern<'a>(pattern: &str, name: &'a str) -> Option<&'a str> {
+    match pattern.split_once('*') {
+        Some((prefix, suffix)) => name.strip_prefix(prefix)?
+            .strip_suffix(suffix)
+            .filter(|matched| !matched.is_empty()),
+        None => (pattern == name).then_some(""),
+    }
+}
+
+fn map(from: &str, to: &str, name: &str) -> Option<String> {
+    let matched = match_pattern(from, name)?;
+    Some(to.replacen('*', matched, 1))
+}
+
+/// Finds the remote-tracking refs that no longer have a counterpart among
+/// `remote_refs`, the ones `--prune` deletes.
+pub fn stale_refs<'a>(
+    refspecs: &[Refspec],
+    tracking_refs: &'a [String],
+    remote_refs: &[String],
+) -> Vec<&'a String> {
+    tracking_refs.iter()
+        .filter(|tracking| {
+            let sources: Vec<String> = refspecs.iter()
+                .filter_map(|refspec| refspec.map_destination(tracking))
+                .collect();
+            !sources.is_empty() && !sources.iter().any(|source| remote_refs.contains(source))
+        })
+        .collect()
+}
+
+impl FromStr for Refspec {
+    type Err = anyhow::Error;
+
+    fn from_str(refspec: &str) -> Result<Self, Self::Err> {
+        let invalid = || anyhow!("Invalid refspec '{}'", refspec);
+
+        let (force, rest) = match refspec.strip_prefix('+') {
+            Some(rest) => (true, rest),
+            None => (false, refspec),
+        };
+        let (source, destination) = match rest.split_once(':') {
+            Some((source, destination)) => (source, Some(destination)),
+            None => (rest, None),
+        };
+
+        let stars = |side: &str| side.matches('*').count();
+        if stars(source) > 1 || destination.is_some_and(|destination| stars(destination) != stars(source)) {
+            return Err(invalid());
+        }
+        if source.is_empty() && destination.is_none() {
+            return Err(invalid());
+        }
+
+        Ok(Refspec {
+            force,
+            source: source.to_string(),
+            destination: destination.filter(|destination| !destination.is_empty()).map(str::to_string),
+        })
+    }
+}
+
+impl Display for Refspec {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.force {
+            f.write_str("+")?;
+        }
+        f.write_str(&self.source)?;
+        if let Some(destination) = &self.destination {
+            write!(f, ":{}", destination)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,644 @@
+//! Ref storage in reftables, selected by `extensions.refStorage=reftable`: a stack of
+//! immutable tables listed oldest first in `reftable/tables.list`, whose sorted ref
+//! records override those of older tables. Every change adds a table, and small tables
+//! are merged as the stack grows. See gitformat-reftable(5); the optional index, object
+//! and log blocks are not written.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, OpenOptions};
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use anyhow::anyhow;
+use bytes::{BufMut, BytesMut};
+
+use crate::lockfile::LockFile;
+use crate::object_id::{HashAlgorithm, ObjectId};
+
+const BLOCK_SIZE: usize = 4096;
+
+/// How many records follow each restart point, the record whose name is
+/// stored in full that readers can start decoding at.
+const RESTART_INTERVAL: usize = 16;
+
+/// What a ref record holds.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Value {
+    /// The ref was deleted, which hides it in older tables.
+    Deleted,
+    Object(ObjectId),
+    /// An annotated tag and the object it peels to.
+    Peeled(ObjectId, ObjectId),
+    Symbolic(String),
+}
+
+/// The records of one table by name, each with the update index of the
+/// transaction that wrote it.
+struct Table {
+    min_update_index: u64,
+    max_update_index: u64,
+    refs: BTreeMap<String, (u64, Value)>,
+    size: u64,
+}
+
+/// The tables of a stack, oldest first.
+pub(crate) struct Stack {
+    dir: PathBuf,
+    format: HashAlgorithm,
+    names: Vec<String>,
+    tables: Vec<Arc<Table>>,
+}
+
+impl Stack {
+    /// Reads the stack in `dir`, which is empty if it doesn't exist yet.
+    pub(crate) fn open(dir: &Path, format: HashAlgorithm) -> anyhow::Result<Stack> {
+        // A table may be merged away between reading the list and opening
+        // it, which a fresh list no longer names.
+        let mut attempts = 0;
+        loop {
+            let list = match fs::read_to_string(dir.join("tables.list")) {
+                Ok(list) => list,
+                Err(error) if error.kind() == io::ErrorKind::NotFound => String::new(),
+                Err(error) => return Err(error.into()),
+            };
+            let names: Vec<String> = list.lines().filter(|name| !name.is_empty()).map(String::from).collect();
+            match names.iter().map(|name| load(&dir.join(name), format)).collect::<anyhow::Result<_>>() {
+                Ok(tables) => return Ok(Stack { dir: dir.to_path_buf(), format, names, tables }),
+                Err(error) if attempts < 3 && error.downcast_ref::<io::Error>().is_some() => attempts += 1,
+                Err(error) => return Err(error),
+            }
+        }
+    }
+
+    /// The newest record of `name`, unless it was deleted.
+    pub(crate) fn get(&self, name: &str) -> Option<&Value> {
+        match self.tables.iter().rev().find_map(|table| table.refs.get(name)) {
+            Some((_, Value::Deleted)) | None => None,
+            Some((_, value)) => Some(value),
+        }
+    }
+
+    /// Every ref in the stack with its newest value.
+    pub(crate) fn refs(&self) -> BTreeMap<&str, &Value> {
+        let mut refs = BTreeMap::new();
+        for (name, (_, value)) in self.merged(0) {
+            if *value != Value::Deleted {
+                refs.insert(name.as_str(), value);
+            }
+        }
+        refs
+    }
+
+    /// The newest record of each name in the tables from `from` on.
+    fn merged(&self, from: usize) -> BTreeMap<&String, &(u64, Value)> {
+        let mut merged = BTreeMap::new();
+        for table in &self.tables[from..] {
+            merged.extend(table.refs.iter());
+        }
+        merged
+    }
+}
+
+/// Parsed tables by path. Tables never change once written, so each is
+/// read once per process.
+fn load(path: &Path, format: HashAlgorithm) -> anyhow::Result<Arc<Table>> {
+    static TABLES: OnceLock<Mutex<HashMap<PathBuf, Arc<Table>>>> = OnceLock::new();
+
+    let tables = TABLES.get_or_init(Mutex::default);
+    if let Some(table) = tables.lock().expect("Reftable cache is not poisoned").get(path) {
+        return Ok(table.clone());
+    }
+    let table = Arc::new(decode(&fs::read(path)?, format).map_err(|error| anyhow!("{}: {}", path.display(), error))?);
+    tables.lock().expect("Reftable cache is not poisoned").insert(path.to_path_buf(), table.clone());
+    Ok(table)
+}
+
+/// Exclusive access to a stack while it changes: `tables.list.lock`,
+/// taken before the stack is read so that what is checked against it
+/// stays true until the new tables are listed.
+pub(crate) struct StackLock {
+    lock: LockFile,
+    stack: Stack,
+}
+
+impl StackLock {
+    pub(crate) fn acquire(dir: &Path, format: HashAlgorithm) -> anyhow::Result<StackLock> {
+        fs::create_dir_all(dir)?;
+        let lock = LockFile::acquire(&dir.join("tables.list"))?;
+        Ok(StackLock { lock, stack: Stack::open(dir, format)? })
+    }
+
+    pub(crate) fn stack(&self) -> &Stack {
+        &self.stack
+    }
+
+    /// Adds a table with `changes` in a new transaction, then merges the
+    /// tables at the top of the stack that have grown close in size, so
+    /// that each is at least twice as large as those above it.
+    pub(crate) fn commit(self, changes: BTreeMap<String, Value>) -> anyhow::Result<()> {
+        self.stage(changes)?.commit()
+    }
+
+    /// Writes what [`StackLock::commit`] would, short of listing the new
+    /// tables in place of the old.
+    pub(crate) fn stage(self, changes: BTreeMap<String, Value>) -> anyhow::Result<Staged> {
+        let StackLock { lock, mut stack } = self;
+        if changes.is_empty() {
+            return Ok(Staged { lock: None, dir: stack.dir, added: Vec::new(), obsolete: Vec::new() });
+        }
+
+        let index = stack.tables.last().map_or(1, |table| table.max_update_index + 1);
+        let records = changes.into_iter().map(|(name, value)| (name, (index, value))).collect();
+        let (name, table) = write_table(&stack.dir, stack.format, index, index, records)?;
+        let added = vec![name.clone()];
+        stack.names.push(name);
+        stack.tables.push(table);
+
+        let sizes: Vec<u64> = stack.tables.iter().map(|table| table.size).collect();
+        let mut from = sizes.len() - 1;
+        let mut above = sizes[from];
+        while from > 0 && sizes[from - 1] <= 2 * above {
+            from -= 1;
+            above += sizes[from];
+        }
+        finish(lock, stack, from, added)
+    }
+
+    /// Merges the whole stack into one table, dropping deleted refs.
+    pub(crate) fn compact(self) -> anyhow::Result<()> {
+        finish(self.lock, self.stack, 0, Vec::new())?.commit()
+    }
+}
+
+/// New tables for a stack, written and listed under its lock, waiting
+/// for [`Staged::commit`] to replace the old list. Dropped instead, the
+/// new tables are removed and the stack stays as it was.
+pub(crate) struct Staged {
+    /// The new list, or `None` if nothing changed.
+    lock: Option<LockFile>,
+    dir: PathBuf,
+    added: Vec<String>,
+    /// Tables merged into new ones, removed once no longer listed.
+    obsolete: Vec<String>,
+}
+
+impl Staged {
+    pub(crate) fn commit(mut self) -> anyhow::Result<()> {
+        if let Some(lock) = self.lock.take() {
+            lock.rename()?;
+        }
+        self.added.clear();
+        for name in &self.obsolete {
+            let _ = fs::remove_file(self.dir.join(name));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Staged {
+    fn drop(&mut self) {
+        for name in &self.added {
+            let _ = fs::remove_file(self.dir.join(name));
+        }
+    }
+}
+
+/// Merges the tables of `stack` from `from` on into one, unless that is a
+/// single table already, and lists the result under `lock`. `added` are
+/// the tables new to the stack so far.
+fn finish(mut lock: LockFile, mut stack: Stack, from: usize, added: Vec<String>) -> anyhow::Result<Staged> {
+    // Built first, so that tables already written go if anything fails.
+    let mut staged = Staged { lock: None, dir: stack.dir.clone(), added, obsolete: Vec::new() };
+    if stack.tables.len() > from + 1 || from == 0 && stack.tables.first().is_some_and(|table| has_deletions(table)) {
+        // Deletions only matter while an older table could hold the ref.
+        let records: BTreeMap<String, (u64, Value)> = stack
+            .merged(from)
+            .into_iter()
+            .filter(|(_, (_, value))| from > 0 || *value != Value::Deleted)
+            .map(|(name, record)| (name.clone(), record.clone()))
+            .collect();
+        let min = stack.tables[from].min_update_index;
+        let max = stack.tables.last().map_or(min, |table| table.max_update_index);
+        let (name, table) = write_table(&stack.dir, stack.format, min, max, records)?;
+        // A table both added and merged away stays in `added` as well, so
+        // that it goes whether or not the change is committed.
+        staged.obsolete.extend(stack.names.drain(from..));
+        staged.added.push(name.clone());
+        stack.tables.truncate(from);
+        stack.names.push(name);
+        stack.tables.push(table);
+    }
+
+    let list: String = stack.names.iter().map(|name| format!("{}\n", name)).collect();
+    lock.write(list.as_bytes())?;
+    lock.flush()?;
+    staged.lock = Some(lock);
+    Ok(staged)
+}
+
+fn has_deletions(table: &Table) -> bool {
+    table.refs.values().any(|(_, value)| *value == Value::Deleted)
+}
+
+/// Writes a table of `records` to `dir` under a new unique name, which is
+/// returned with the table.
+fn write_table(
+    dir: &Path,
+    format: HashAlgorithm,
+    min_update_index: u64,
+    max_update_index: u64,
+    refs: BTreeMap<String, (u64, Value)>,
+) -> anyhow::Result<(String, Arc<Table>)> {
+    let data = encode(format, min_update_index, max_update_index, &refs)?;
+    loop {
+        let suffix = std::collections::hash_map::RandomState::new().build_hasher().finish() as u32;
+        let name = format!("0x{:012x}-0x{:012x}-{:08x}.ref", min_update_index, max_update_index, suffix);
+        let mut file = match OpenOptions::new().write(true).create_new(true).open(dir.join(&name)) {
+            Ok(file) => file,
+            Err(error) if error.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(error) => return Err(error.into()),
+        };
+        file.write_all(&data)?;
+        file.sync_all()?;
+
+        let table = Table { min_update_index, max_update_index, refs, size: data.len() as u64 };
+        return Ok((name, Arc::new(table)));
+    }
+}
+
+/// The header that starts and, repeated, ends every table: version 1 for
+/// SHA-1 or 2 with the ID of the hash, the block size and the range of
+/// update indexes.
+fn header(format: HashAlgorithm, min_update_index: u64, max_update_index: u64) -> BytesMut {
+    let mut header = BytesMut::new();
+    header.put_slice(b"REFT");
+    header.put_u8(if format == HashAlgorithm::Sha1 { 1 } else { 2 });
+    header.put_uint(BLOCK_SIZE as u64, 3);
+    header.put_u64(min_update_index);
+    header.put_u64(max_update_index);
+    if format != HashAlgorithm::Sha1 {
+        header.put_slice(b"s256");
+    }
+    header
+}
+
+fn encode(
+    format: HashAlgorithm,
+    min_update_index: u64,
+    max_update_index: u64,
+    refs: &BTreeMap<String, (u64, Value)>,
+) -> anyhow::Result<Vec<u8>> {
+    let header = header(format, min_update_index, max_update_index);
+    let mut data = BytesMut::new();
+    data.put_slice(&header);
+
+    // The first block shares its space with the file header, and offsets
+    // within it count from the start of the file.
+    let mut block = BlockWriter::new(header.len());
+    for (name, (update_index, value)) in refs {
+        let delta = update_index - min_update_index;
+        if !block.add(name, delta, value) {
+            block.finish(&mut data);
+            block = BlockWriter::new(0);
+            if !block.add(name, delta, value) {
+                return Err(anyhow!("Ref '{}' is too long for a reftable block", name));
+            }
+        }
+    }
+    if block.count > 0 {
+        block.finish(&mut data);
+    }
+
+    let footer_start = data.len();
+    data.put_slice(&header);
+    // The ref index, object and log sections, none of which are written.
+    data.put_bytes(0, 5 * 8);
+    let mut crc = flate2::Crc::new();
+    crc.update(&data[footer_start..]);
+    data.put_u32(crc.sum());
+    Ok(data.to_vec())
+}
+
+/// A ref block being filled: `r`, its length, the records, and the
+/// offsets of its restart points followed by their count.
+struct BlockWriter {
+    /// Where the block starts within its share of the file, past the file
+    /// header in the first block.
+    offset: usize,
+    records: Vec<u8>,
+    restarts: Vec<usize>,
+    count: usize,
+    last_name: Vec<u8>,
+}
+
+impl BlockWriter {
+    fn new(offset: usize) -> BlockWriter {
+        BlockWriter { offset, records: Vec::new(), restarts: Vec::new(), count: 0, last_name: Vec::new() }
+    }
+
+    /// Appends a record unless the block is too full for it.
+    fn add(&mut self, name: &str, update_index_delta: u64, value: &Value) -> bool {
+        let name = name.as_bytes();
+        let restart = self.count.is_multiple_of(RESTART_INTERVAL);
+        let prefix = match restart {
+            true => 0,
+            false => name.iter().zip(&self.last_name).take_while(|(a, b)| a == b).count(),
+        };
+        let suffix = &name[prefix..];
+        let kind = match value {
+            Value::Deleted => 0,
+            Value::Object(_) => 1,
+            Value::Peeled(..) => 2,
+            Value::Symbolic(_) => 3,
+        };
+
+        let mut record = Vec::new();
+        put_varint(&mut record, prefix as u64);
+        put_varint(&mut record, (suffix.len() as u64) << 3 | kind);
+        record.extend_from_slice(suffix);
+        put_varint(&mut record, update_index_delta);
+        match value {
+            Value::Deleted => {}
+            Value::Object(sha) => record.extend_from_slice(sha.as_ref()),
+            Value::Peeled(sha, peeled) => {
+                record.extend_from_slice(sha.as_ref());
+                record.extend_from_slice(peeled.as_ref());
+            }
+            Value::Symbolic(target) => {
+                put_varint(&mut record, target.len() as u64);
+                record.extend_from_slice(target.as_bytes());
+            }
+        }
+
+        let restarts = self.restarts.len() + usize::from(restart);
+        if self.offset + 4 + self.records.len() + record.len() + 3 * restarts + 2 > BLOCK_SIZE {
+            return false;
+        }
+        if restart {
+            self.restarts.push(self.offset + 4 + self.records.len());
+        }
+        self.records.extend_from_slice(&record);
+        self.last_name = name.to_vec();
+        self.count += 1;
+        true
+    }
+
+    /// Appends the block to `data`, padded to the block size.
+    fn finish(self, data: &mut BytesMut) {
+        let len = self.offset + 4 + self.records.len() + 3 * self.restarts.len() + 2;
+        data.put_u8(b'r');
+        data.put_uint(len as u64, 3);
+        data.put_slice(&self.records);
+        for restart in &self.restarts {
+            data.put_uint(*restart as u64, 3);
+        }
+        data.put_u16(self.restarts.len() as u16);
+        data.put_bytes(0, BLOCK_SIZE - len);
+    }
+}
+
+fn decode(data: &[u8], format: HashAlgorithm) -> anyhow::Result<Table> {
+    let bad = |what: &str| anyhow!("Bad reftable: {}", what);
+    let (header_len, footer_len) = match data.get(4) {
+        Some(1) => (24, 68),
+        Some(2) => (28, 72),
+        _ => return Err(bad("unknown version")),
+    };
+    if data.len() < header_len + footer_len || &data[..4] != b"REFT" {
+        return Err(bad("too short"));
+    }
+    let hash = match header_len {
+        24 => HashAlgorithm::Sha1,
+        _ if &data[24..28] == b"s256" => HashAlgorithm::Sha256,
+        _ if &data[24..28] == b"sha1" => HashAlgorithm::Sha1,
+        _ => return Err(bad("unknown hash")),
+    };
+    if hash != format {
+        return Err(anyhow!("Reftable uses {}, but the repository uses {}", hash.name(), format.name()));
+    }
+
+    let footer_start = data.len() - footer_len;
+    let footer = &data[footer_start..];
+    let mut crc = flate2::Crc::new();
+    crc.update(&footer[..footer_len - 4]);
+    if footer[..header_len] != data[..header_len] || crc.sum().to_be_bytes() != footer[footer_len - 4..] {
+        return Err(bad("corrupt footer"));
+    }
+    let u64_at = |bytes: &[u8], offset: usize| read_uint(&bytes[offset..offset + 8]);
+    let min_update_index = u64_at(data, 8);
+    let max_update_index = u64_at(data, 16);
+    // Ref blocks come first and end where the next section starts.
+    let end = [u64_at(footer, header_len), u64_at(footer, header_len + 8) >> 5, u64_at(footer, header_len + 24)]
+        .into_iter()
+        .filter(|&position| position > 0)
+        .fold(footer_start, |end, position| end.min(position as usize));
+
+    let mut refs = BTreeMap::new();
+    let mut position = 0;
+    loop {
+        let start = position + if position == 0 { header_len } else { 0 };
+        if start + 4 > end || data[start] != b'r' {
+            break;
+        }
+        let block_end = position + read_uint(&data[start + 1..start + 4]) as usize;
+        if block_end > end || block_end < start + 6 {
+            return Err(bad("block overflows"));
+        }
+        let restarts = read_uint(&data[block_end - 2..block_end]) as usize;
+        let records_end = block_end.checked_sub(2 + 3 * restarts).filter(|&records_end| records_end >= start + 4);
+        let records_end = records_end.ok_or_else(|| bad("restart table overflows"))?;
+
+        let mut pos = start + 4;
+        let mut name: Vec<u8> = Vec::new();
+        while pos < records_end {
+            let (record_name, update_index, value) =
+                decode_record(&data[..records_end], &mut pos, &name, hash).ok_or_else(|| bad("corrupt record"))?;
+            name = record_name;
+            let name = String::from_utf8(name.clone()).map_err(|_| bad("ref name is not UTF-8"))?;
+            refs.insert(name, (min_update_index + update_index, value));
+        }
+
+        // Blocks are padded to the block size unless the table was
+        // written unaligned, and no block starts with a zero.
+        position = block_end;
+        while position < end && data[position] == 0 {
+            position += 1;
+        }
+    }
+
+    Ok(Table { min_update_index, max_update_index, refs, size: data.len() as u64 })
+}
+
+/// Decodes the ref record at `pos`, whose name shares a prefix with
+/// `last_name`, returning its name, update index delta and value.
+fn decode_record(data: &[u8], pos: &mut usize, last_name: &[u8], hash: HashAlgorithm) -> Option<(Vec<u8>, u64, Value)> {
+    let prefix = get_varint(data, pos)? as usize;
+    let suffix_and_kind = get_varint(data, pos)?;
+    let suffix_len = (suffix_and_kind >> 3) as usize;
+    let mut name = last_name.get(..prefix)?.to_vec();
+    name.extend_from_slice(data.get(*pos..*pos + suffix_len)?);
+    *pos += suffix_len;
+    let update_index = get_varint(data, pos)?;
+
+    let mut object = || {
+        let sha = hash.read(data.get(*pos..)?)?;
+        *pos += hash.byte_len();
+        Some(sha)
+    };
+    let value = match suffix_and_kind & 0b111 {
+        0 => Value::Deleted,
+        1 => Value::Object(object()?),
+        2 => Value::Peeled(object()?, object()?),
+        3 => {
+            let len = get_varint(data, pos)? as usize;
+            let target = String::from_utf8(data.get(*pos..*pos + len)?.to_vec()).ok()?;
+            *pos += len;
+            Value::Symbolic(target)
+        }
+        _ => return None,
+    };
+    Some((name, update_index, value))
+}
+
+fn read_uint(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0, |value, &byte| value << 8 | u64::from(byte))
+}
+
+/// Appends `value` as a varint in git's encoding, which adds one to each
+/// continued group so that every number has a single encoding.
+fn put_varint(out: &mut Vec<u8>, mut value: u64) {
+    let mut bytes = vec![(value & 0x7f) as u8];
+    value >>= 7;
+    while value > 0 {
+        value -= 1;
+        bytes.push(0x80 | (value & 0x7f) as u8);
+        value >>= 7;
+    }
+    out.extend(bytes.iter().rev());
+}
+
+fn get_varint(data: &[u8], pos: &mut usize) -> Option<u64> {
+    let mut byte = *data.get(*pos)?;
+    *pos += 1;
+    let mut value = u64::from(byte & 0x7f);
+    while byte & 0x80 != 0 {
+        byte = *data.get(*pos)?;
+        *pos += 1;
+        value = (value + 1).checked_mul(128)? | u64::from(byte & 0x7f);
+    }
+    Some(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(format: HashAlgorithm, n: u32) -> ObjectId {
+        format.hash(&n.to_be_bytes())
+    }
+
+    fn records(format: HashAlgorithm, count: u32) -> BTreeMap<String, (u64, Value)> {
+        (0..count)
+            .map(|n| {
+                let value = match n % 4 {
+                    0 => Value::Deleted,
+                    1 => Value::Object(id(format, n)),
+                    2 => Value::Peeled(id(format, n), id(format, n + 1)),
+                    _ => Value::Symbolic(format!("refs/heads/target-{}", n)),
+                };
+                (format!("refs/heads/topic/{:05}", n), (10 + u64::from(n % 3), value))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn tables_round_trip() {
+        for format in [HashAlgorithm::Sha1, HashAlgorithm::Sha256] {
+            // Enough records for several blocks and restart points.
+            for count in [0, 1, 17, 500] {
+                let refs = records(format, count);
+                let data = encode(format, 10, 12, &refs).unwrap();
+                let table = decode(&data, format).unwrap();
+                assert_eq!((table.min_update_index, table.max_update_index), (10, 12));
+                assert_eq!(table.refs, refs);
+            }
+        }
+    }
+
+    #[test]
+    fn damaged_tables_are_errors() {
+        let data = encode(HashAlgorithm::Sha1, 1, 1, &records(HashAlgorithm::Sha1, 40)).unwrap();
+        assert!(decode(&data, HashAlgorithm::Sha256).is_err());
+        for len in [0, 4, 5, 24, 91, data.len() - 1] {
+            assert!(decode(&data[..len], HashAlgorithm::Sha1).is_err(), "truncated to {}", len);
+        }
+        let mut bad_magic = data.clone();
+        bad_magic[0] = b'X';
+        assert!(decode(&bad_magic, HashAlgorithm::Sha1).is_err());
+        let mut bad_footer = data.clone();
+        let last = bad_footer.len() - 10;
+        bad_footer[last] ^= 1;
+        assert!(decode(&bad_footer, HashAlgorithm::Sha1).is_err());
+        // Garbage records fail to decode rather than panic.
+        for offset in [24, 25, 30, 100, 500] {
+            let mut garbage = data.clone();
+            garbage[offset] = 0xff;
+            let _ = decode(&garbage, HashAlgorithm::Sha1);
+        }
+    }
+
+    #[test]
+    fn varints_round_trip() {
+        for value in [0, 1, 127, 128, 16511, 16512, u64::from(u32::MAX), u64::MAX] {
+            let mut data = Vec::new();
+            put_varint(&mut data, value);
+            let mut pos = 0;
+            assert_eq!(get_varint(&data, &mut pos), Some(value));
+            assert_eq!(pos, data.len());
+        }
+        assert_eq!(get_varint(&[0x80], &mut 0), None);
+        assert_eq!(get_varint(&[0xff; 11], &mut 0), None);
+    }
+
+    #[test]
+    fn stacks_merge_and_compact() {
+        let dir = std::env::temp_dir().join(format!("git-starter-rust-reftable-{}", std::process::id()));
+        let format = HashAlgorithm::Sha1;
+        let change = |name: &str, value: Value| {
+            let lock = StackLock::acquire(&dir, format).unwrap();
+            lock.commit(BTreeMap::from([(name.to_string(), value)])).unwrap();
+        };
+        change("refs/heads/main", Value::Object(id(format, 1)));
+        change("refs/heads/topic", Value::Object(id(format, 2)));
+        change("HEAD", Value::Symbolic("refs/heads/main".to_string()));
+        change("refs/heads/main", Value::Object(id(format, 3)));
+        change("refs/heads/topic", Value::Deleted);
+
+        let stack = Stack::open(&dir, format).unwrap();
+        assert_eq!(stack.get("refs/heads/main"), Some(&Value::Object(id(format, 3))));
+        assert_eq!(stack.get("refs/heads/topic"), None);
+        assert_eq!(stack.refs().keys().copied().collect::<Vec<_>>(), ["HEAD", "refs/heads/main"]);
+        // Tables of similar size were merged as the stack grew.
+        assert!(stack.tables.len() < 5);
+        let max_update_index = stack.tables.last().unwrap().max_update_index;
+        assert_eq!(max_update_index, 5);
+
+        // A staged change that is dropped leaves the stack as it was.
+        let lock = StackLock::acquire(&dir, format).unwrap();
+        drop(lock.stage(BTreeMap::from([("refs/heads/gone".to_string(), Value::Object(id(format, 4)))])).unwrap());
+        assert_eq!(Stack::open(&dir, format).unwrap().get("refs/heads/gone"), None);
+
+        StackLock::acquire(&dir, format).unwrap().compact().unwrap();
+        let stack = Stack::open(&dir, format).unwrap();
+        assert_eq!(stack.names.len(), 1);
+        assert!(!has_deletions(&stack.tables[0]));
+        assert_eq!(stack.tables[0].max_update_index, max_update_index);
+        assert_eq!(stack.refs().len(), 2);
+        // Only the listed table and the list itself are left.
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
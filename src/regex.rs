@@ -0,0 +1,455 @@
+use std::cell::Cell;
+
+/// A POSIX regular expression, extended or basic with the GNU additions
+/// git's patterns rely on (`\w`, `\s`, `\|`, `\+`, `\?`), matched against
+/// bytes by backtracking, leftmost first. Used for the patterns that
+/// find hunk headers, for `--grep` and `--author`, and for the pickaxe
+/// of `log -G`, which only ever see one line.
+#[derive(Debug)]
+pub(crate) struct Regex {
+    node: Node,
+    groups: usize,
+}
+
+/// How many steps a match may take before it is given up as not matching,
+/// so that a pattern such as `(a*)*b` can't take exponential time.
+const MAX_STEPS: usize = 1 << 20;
+
+/// The text being matched and the steps taken so far.
+struct Search<'a> {
+    text: &'a [u8],
+    steps: Cell<usize>,
+}
+
+#[derive(Debug)]
+enum Node {
+    Empty,
+    Byte(Box<[bool; 256]>),
+    Start,
+    End,
+    Group(Box<Node>, usize),
+    Concat(Vec<Node>),
+    Alternate(Vec<Node>),
+    Repeat(Box<Node>, usize, Option<usize>),
+}
+
+/// Where a group matched, by byte offsets.
+type Captures = Vec<Option<(usize, usize)>>;
+
+impl Regex {
+    /// Compiles `pattern`, which is a basic expression unless `extended`.
+    /// With `ignore_case`, ASCII letters match either case.
+    pub fn new(pattern: &str, extended: bool, ignore_case: bool) -> Option<Regex> {
+        let mut parser = Parser { pattern: pattern.as_bytes(), pos: 0, extended, ignore_case, groups: 0 };
+        let node = parser.alternation()?;
+        if parser.pos < parser.pattern.len() {
+            return None;
+        }
+        Some(Regex { node, groups: parser.groups })
+    }
+
+    /// The leftmost match in `text` and where each group in it matched,
+    /// the whole match first.
+    pub fn captures(&self, text: &[u8]) -> Option<Captures> {
+        let search = Search { text, steps: Cell::new(0) };
+        (0..=text.len()).find_map(|start| {
+            let mut captures = vec![None; self.groups + 1];
+            let mut end = None;
+            let matched = self.matches(&self.node, &search, start, &mut captures, &mut |pos, _| {
+                end = Some(pos);
+                true
+            });
+            matched.then(|| {
+                captures[0] = end.map(|end| (start, end));
+                captures
+            })
+        })
+    }
+
+    /// Whether the expression matches anywhere in `text`.
+    pub fn is_match(&self, text: &[u8]) -> bool {
+        self.captures(text).is_some()
+    }
+
+    /// Whether `node` matches `text` at `pos` in a way that `then`
+    /// accepts, given where that match ends.
+    fn matches(
+        &self,
+        node: &Node,
+        search: &Search,
+        pos: usize,
+        captures: &mut Captures,
+        then: &mut dyn FnMut(usize, &mut Captures) -> bool,
+    ) -> bool {
+        let steps = search.steps.get() + 1;
+        if steps > MAX_STEPS {
+            return false;
+        }
+        search.steps.set(steps);
+
+        let text = search.text;
+        match node {
+            Node::Empty => then(pos, captures),
+            Node::Byte(set) => pos < text.len() && set[text[pos] as usize] && then(pos + 1, captures),
+            Node::Start => pos == 0 && then(pos, captures),
+            Node::End => pos == text.len() && then(pos, captures),
+            Node::Group(inner, index) => self.matches(inner, search, pos, captures, &mut |end, captures| {
+                let previous = captures[*index];
+                captures[*index] = Some((pos, end));
+                if then(end, captures) {
+                    return true;
+                }
+                captures[*index] = previous;
+                false
+            }),
+            Node::Concat(nodes) => self.concat(nodes, search, pos, captures, then),
+            Node::Alternate(branches) => {
+                branches.iter().any(|branch| self.matches(branch, search, pos, captures, then))
+            }
+            Node::Repeat(inner, min, max) => self.repeat(inner, (*min, *max), 0, search, pos, captures, then),
+        }
+    }
+
+    fn concat(
+        &self,
+        nodes: &[Node],
+        search: &Search,
+        pos: usize,
+        captures: &mut Captures,
+        then: &mut dyn FnMut(usize, &mut Captures) -> bool,
+    ) -> bool {
+        match nodes.split_first() {
+            None => then(pos, captures),
+            Some((first, rest)) => self.matches(first, search, pos, captures, &mut |pos, captures| {
+                self.concat(rest, search, pos, captures, then)
+            }),
+        }
+    }
+
+    /// Matches `inner` as many more times as it can, then backs off.
+    #[allow(clippy::too_many_arguments)]
+    fn repeat(
+        &self,
+        inner: &Node,
+        (min, max): (usize, Option<usize>),
+        count: usize,
+        search: &Search,
+        pos: usize,
+        captures: &mut Captures,
+        then: &mut dyn FnMut(usize, &mut Captures) -> bool,
+    ) -> bool {
+        if max.is_none_or(|max| count < max) {
+            let more = self.matches(inner, search, pos, captures, &mut |end, captures| {
+                // Another empty match would never end.
+                if end == pos && count >= min {
+                    return false;
+                }
+                self.repeat(inner, (min, max), count + 1, search, end, captures, then)
+            });
+            if more {
+                return true;
+            }
+        }
+        count >= min && then(pos, captures)
+    }
+}
+
+struct Parser<'a> {
+    pattern: &'a [u8],
+    pos: usize,
+    extended: bool,
+    ignore_case: bool,
+    groups: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.pattern.get(self.pos).copied()
+    }
+
+    /// Whether the pattern continues with `token`, an operator that basic
+    /// expressions spell with a backslash.
+    fn at(&self, token: u8) -> bool {
+        match self.extended {
+            true => self.peek() == Some(token),
+            false => self.peek() == Some(b'\\') && self.pattern.get(self.pos + 1) == Some(&token),
+        }
+    }
+
+    fn skip(&mut self, token: u8) -> bool {
+        if !self.at(token) {
+            return false;
+        }
+        self.pos += if self.extended { 1 } else { 2 };
+        true
+    }
+
+    fn alternation(&mut self) -> Option<Node> {
+        let mut branches = vec![self.branch()?];
+        while self.skip(b'|') {
+            branches.push(self.branch()?);
+        }
+        Some(match branches.len() {
+            1 => branches.pop()?,
+            _ => Node::Alternate(branches),
+        })
+    }
+
+    fn branch(&mut self) -> Option<Node> {
+        let mut nodes = Vec::new();
+        while self.peek().is_some() && !self.at(b'|') && !self.at(b')') {
+            let atom = self.atom(nodes.is_empty())?;
+            nodes.push(self.repetitions(atom)?);
+        }
+        Some(match nodes.len() {
+            0 => Node::Empty,
+            1 => nodes.pop()?,
+            _ => Node::Concat(nodes),
+        })
+    }
+
+    fn repetitions(&mut self, mut atom: Node) -> Option<Node> {
+        loop {
+            let (min, max) = match self.peek() {
+                Some(b'*') => {
+                    self.pos += 1;
+                    (0, None)
+                }
+                _ if self.skip(b'+') => (1, None),
+                _ if self.skip(b'?') => (0, Some(1)),
+                _ if self.skip(b'{') => {
+                    let number = |parser: &mut Parser| {
+                        let start = parser.pos;
+                        while parser.peek().is_some_and(|b| b.is_ascii_digit()) {
+                            parser.pos += 1;
+                        }
+                        std::str::from_utf8(&parser.pattern[start..parser.pos]).ok()?.parse::<usize>().ok()
+                    };
+                    let min = number(self)?;
+                    let max = match self.peek() {
+                        Some(b',') => {
+                            self.pos += 1;
+                            number(self)
+                        }
+                        _ => Some(min),
+                    };
+                    if !self.skip(b'}') || max.is_some_and(|max| max < min) {
+                        return None;
+                    }
+                    (min, max)
+                }
+                _ => return Some(atom),
+            };
+            atom = Node::Repeat(Box::new(atom), min, max);
+        }
+    }
+
+    fn atom(&mut self, first: bool) -> Option<Node> {
+        if self.skip(b'(') {
+            self.groups += 1;
+            let index = self.groups;
+            let inner = self.alternation()?;
+            if !self.skip(b')') {
+                return None;
+            }
+            return Some(Node::Group(Box::new(inner), index));
+        }
+
+        let byte = self.peek()?;
+        self.pos += 1;
+        let ignore_case = self.ignore_case;
+        let set = |test: &dyn Fn(u8) -> bool| {
+            let mut set = Box::new([false; 256]);
+            for (b, member) in set.iter_mut().enumerate() {
+                *member = test(b as u8);
+            }
+            Node::Byte(fold_case(set, ignore_case))
+        };
+        Some(match byte {
+            b'.' => set(&|_| true),
+            b'[' => self.bracket()?,
+            // Basic expressions only anchor at their ends.
+            b'^' if self.extended || first => Node::Start,
+            b'$' if self.extended || self.peek().is_none() || self.at(b')') || self.at(b'|') => Node::End,
+            b'*' if self.extended && first => return None,
+            b'\\' => {
+                let escaped = self.peek()?;
+                self.pos += 1;
+                match escaped {
+                    b'w' => set(&|b| b.is_ascii_alphanumeric() || b == b'_'),
+                    b'W' => set(&|b| !(b.is_ascii_alphanumeric() || b == b'_')),
+                    b's' => set(&|b| b.is_ascii_whitespace()),
+                    b'S' => set(&|b| !b.is_ascii_whitespace()),
+                    escaped => set(&|b| b == escaped),
+                }
+            }
+            byte => set(&|b| b == byte),
+        })
+    }
+
+    /// A bracket expression, after its `[`, in which a backslash is an
+    /// ordinary character.
+    fn bracket(&mut self) -> Option<Node> {
+        let mut set = Box::new([false; 256]);
+        let negated = self.peek() == Some(b'^');
+        if negated {
+            self.pos += 1;
+        }
+        let mut first = true;
+        loop {
+            let byte = self.peek()?;
+            self.pos += 1;
+            match byte {
+                b']' if !first => break,
+                b'[' if self.peek() == Some(b':') => {
+                    let rest = &self.pattern[self.pos + 1..];
+                    let end = rest.windows(2).position(|window| window == b":]")?;
+                    let test: fn(&u8) -> bool = match &rest[..end] {
+                        b"alpha" => u8::is_ascii_alphabetic,
+                        b"digit" => u8::is_ascii_digit,
+                        b"alnum" => u8::is_ascii_alphanumeric,
+                        b"upper" => u8::is_ascii_uppercase,
+                        b"lower" => u8::is_ascii_lowercase,
+                        b"space" => |b| b.is_ascii_whitespace() || *b == 0x0b,
+                        b"blank" => |b| *b == b' ' || *b == b'\t',
+                        b"punct" => u8::is_ascii_punctuation,
+                        b"print" => |b| (0x20..0x7f).contains(b),
+                        b"graph" => u8::is_ascii_graphic,
+                        b"cntrl" => u8::is_ascii_control,
+                        b"xdigit" => u8::is_ascii_hexdigit,
+                        _ => return None,
+                    };
+                    for b in 0..=255u8 {
+                        set[b as usize] |= test(&b);
+                    }
+                    self.pos += 1 + end + 2;
+                }
+                low => {
+                    let high = match (self.peek(), self.pattern.get(self.pos + 1)) {
+                        (Some(b'-'), Some(&high)) if high != b']' => {
+                            self.pos += 2;
+                            high
+                        }
+                        _ => low,
+                    };
+                    for b in low..=high {
+                        set[b as usize] = true;
+                    }
+                }
+            }
+            first = false;
+        }
+        let mut set = fold_case(set, self.ignore_case);
+        if negated {
+            for member in set.iter_mut() {
+                *member = !*member;
+            }
+        }
+        Some(Node::Byte(set))
+    }
+}
+
+/// Adds the other case of every ASCII letter in `set`, if `ignore_case`.
+fn fold_case(mut set: Box<[bool; 256]>, ignore_case: bool) -> Box<[bool; 256]> {
+    if ignore_case {
+        for b in b'A'..=b'Z' {
+            let either = set[b as usize] || set[b.to_ascii_lowercase() as usize];
+            set[b as usize] = either;
+            set[b.to_ascii_lowercase() as usize] = either;
+        }
+    }
+    set
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn basic(pattern: &str, text: &str) -> bool {
+        Regex::new(pattern, false, false).unwrap().is_match(text.as_bytes())
+    }
+
+    fn extended(pattern: &str, text: &str) -> bool {
+        Regex::new(pattern, true, false).unwrap().is_match(text.as_bytes())
+    }
+
+    /// The whole match and the first group, as text.
+    fn groups(pattern: &str, text: &str) -> (String, Option<String>) {
+        let captures = Regex::new(pattern, true, false).unwrap().captures(text.as_bytes()).unwrap();
+        let group = |index: usize| captures.get(index).copied().flatten().map(|(start, end)| text[start..end].to_string());
+        (group(0).unwrap(), group(1))
+    }
+
+    #[test]
+    fn anchors() {
+        assert!(basic("^fix", "fix: typo"));
+        assert!(!basic("^fix", "prefix"));
+        assert!(basic("typo$", "fix: typo"));
+        assert!(!basic("typo$", "typos"));
+        assert!(basic("^$", ""));
+        // In basic expressions `^` and `$` are only anchors at the ends.
+        assert!(basic("a^b$c", "a^b$c"));
+        assert!(extended("(^| )x", "a x"));
+        assert!(!extended("a^b", "a^b"));
+    }
+
+    #[test]
+    fn alternation_and_groups() {
+        assert!(extended("cat|dog", "hotdog"));
+        assert!(basic("cat\\|dog", "hotdog"));
+        assert!(!basic("cat|dog", "hotdog"));
+        assert!(basic("cat|dog", "cat|dog"));
+        assert_eq!(groups("(fn|struct) +([a-z]+)", "pub fn main()"), ("fn main".into(), Some("fn".into())));
+        // Leftmost first, not longest.
+        assert_eq!(groups("a|ab", "ab"), ("a".into(), None));
+        assert!(Regex::new("(a", true, false).is_none());
+        assert!(Regex::new("a)", true, false).is_none());
+    }
+
+    #[test]
+    fn repetition() {
+        assert!(basic("ab*c", "ac"));
+        assert!(basic("ab*c", "abbbc"));
+        assert!(basic("ab\\+c", "abc") && !basic("ab\\+c", "ac"));
+        assert!(extended("ab?c", "ac") && extended("ab?c", "abc") && !extended("ab?c", "abbc"));
+        assert!(extended("^a{2,3}$", "aaa") && !extended("^a{2,3}$", "aaaa"));
+        assert!(basic("^a\\{2\\}$", "aa"));
+        assert!(Regex::new("a{3,2}", true, false).is_none());
+        assert!(Regex::new("*a", true, false).is_none());
+        assert_eq!(groups("<.*>", "<a> <b>").0, "<a> <b>");
+    }
+
+    #[test]
+    fn classes() {
+        assert!(basic("[0-9][0-9]*", "v42"));
+        assert!(!basic("^[^0-9]*$", "v42"));
+        assert!(basic("[]a]", "]"));
+        assert!(basic("[a-]", "-"));
+        assert!(basic("[[:digit:]]", "7"));
+        assert!(basic("^[[:alpha:]_][[:alnum:]_]*$", "snake_case1"));
+        assert!(!basic("[[:space:]]", "nospace"));
+        assert!(Regex::new("[[:nope:]]", false, false).is_none());
+        // A backslash is ordinary inside brackets.
+        assert!(basic("[\\]", "\\"));
+        assert!(basic("\\w\\s\\S", "a b"));
+        assert!(basic("a\\.b", "a.b") && !basic("a\\.b", "axb"));
+    }
+
+    #[test]
+    fn ignore_case() {
+        let regex = Regex::new("^fix[A-Z]", false, true).unwrap();
+        assert!(regex.is_match(b"FIXa"));
+        assert!(regex.is_match(b"fixB"));
+        assert!(!Regex::new("[^a]", false, true).unwrap().is_match(b"A"));
+    }
+
+    #[test]
+    fn backtracking_is_bounded() {
+        // Exponential for a naive backtracker; the step limit gives up.
+        let text = "a".repeat(64);
+        assert!(!extended("(a*)*b", &text));
+        assert!(!extended("(a|aa)*c", &text));
+        assert!(extended("(a*)*", &text));
+    }
+
+}
@@ -0,0 +1,105 @@
+use anyhow::anyhow;
+
+use crate::config::Config;
+use crate::refs;
+use crate::refspec::Refspec;
+use crate::repository::common_dir;
+
+fn open_config() -> anyhow::Result<(std::path::PathBuf, Config)> {
+    let path = common_dir()?.join("config");
+    let config = Config::from_file(&path)?;
+    Ok((path, config))
+}
+
+fn remotes(config: &Config) -> Vec<String> {
+    config.subsections("remote")
+}
+
+/// Adds a remote that fetches all of its branches into
+/// `refs/remotes/<name>/`.
+pub fn add(name: &str, url: &str) -> anyhow::Result<()> {
+    let (path, mut config) = open_config()?;
+    if remotes(&config).iter().any(|remote| remote == name) {
+        return Err(anyhow!("remote {} already exists.", name));
+    }
+    if !refs::is_valid_ref_name(&format!("refs/remotes/{}/test", name), false, false) {
+        return Err(anyhow!("'{}' is not a valid remote name", name));
+    }
+
+    let refspec = Refspec {
+        force: true,
+        source: "refs/heads/*".to_string(),
+        destination: Some(format!("refs/remotes/{}/*", name)),
+    };
+    config.set(&format!("remote.{}.url", name), url)?;
+    config.add(&format!("remote.{}.fetch", name), &refspec.to_string())?;
+    config.write(&path)
+}
+
+/// Removes a remote along with its remote-tracking branches and the
+/// upstream settings of branches that track it.
+pub fn remove(name: &str) -> anyhow::Result<()> {
+    let (path, mut config) = open_config()?;
+    if !remotes(&config).iter().any(|remote| remote == name) {
+        return Err(anyhow!("No such remote: '{}'", name));
+    }
+
+    for branch in config.subsections("branch") {
+        if config.get(&format!("branch.{}.remote", branch)) == Some(name) {
+            config.unset(&format!("branch.{}.remote", branch))?;
+            config.unset(&format!("branch.{}.merge", branch))?;
+        }
+    }
+    let refspecs = fetch_refspecs(&config, name)?;
+    config.remove_section(&format!("remote.{}", name));
+    config.write(&path)?;
+
+    let tracking: Vec<String> = refs::list_refs("refs/")?
+        .into_iter()
+        .filter(|tracking| refspecs.iter().any(|refspec| refspec.matches_destination(tracking)))
+        .collect();
+    refs::delete_refs(&tracking)
+}
+
+/// Returns the `remote.<name>.fetch` refspecs of a remote.
+pub fn fetch_refspecs(config: &Config, name: &str) -> anyhow::Result<Vec<Refspec>> {
+    config.get_all(&format!("remote.{}.fetch", name))
+        .into_iter()
+        .map(str::parse)
+        .collect()
+}
+
+/// Replaces the fetch URL of a remote.
+pub fn set_url(name: &str, url: &str) -> anyhow::Result<()> {
+    let (path, mut config) = open_config()?;
+    if !remotes(&config).iter().any(|remote| remote == name) {
+        return Err(anyhow!("No such remote '{}'", name));
+    }
+
+    config.set(&format!("remote.{}.url", name), url)?;
+    config.write(&path)
+}
+
+/// Prints the configured remotes, with their fetch and push URLs if
+/// `verbose`.
+pub fn list(verbose: bool) -> anyhow::Result<()> {
+    let (_, config) = open_config()?;
+
+    for name in remotes(&config) {
+        if !verbose {
+            println!("{}", name);
+            continue;
+        }
+
+        let urls = config.get_all(&format!("remote.{}.url", name));
+        let push_urls = config.get_all(&format!("remote.{}.pushurl", name));
+        for url in &urls {
+            println!("{}\t{} (fetch)", name, url);
+        }
+        for url in if push_urls.is_empty() { &urls } else { &push_urls } {
+            println!("{}\t{} (push)", name, url);
+        }
+    }
+
+    Ok(())
+}
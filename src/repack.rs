@@ -0,0 +1,486 @@
+//! `pack-objects` and `repack`: write objects into a pack, each stored as
+//! a delta against a similar one where that is smaller.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{BufRead, Write};
+use std::path::Path;
+
+use anyhow::anyhow;
+
+use crate::bitmap;
+use crate::config::{self, Config};
+use crate::index::{CacheTree, Index};
+use crate::info;
+use crate::midx;
+use crate::object_id::ObjectId;
+use crate::objects::Object;
+use crate::pack::{self, Pack, PackOptions, MAX_DELTA_DEPTH};
+use crate::reflog;
+use crate::refs;
+use crate::repository::{self, Repository};
+
+/// Settings that override `pack.window`, `pack.depth` and `pack.threads`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Tuning {
+    pub window: Option<usize>,
+    pub depth: Option<usize>,
+    /// 0 means one thread per CPU.
+    pub threads: Option<usize>,
+}
+
+impl Tuning {
+    fn options(self, config: &Config) -> anyhow::Result<PackOptions> {
+        let configured = PackOptions::from_config(config)?;
+        Ok(PackOptions {
+            window: self.window.unwrap_or(configured.window),
+            depth: self.depth.map_or(configured.depth, |depth| depth.min(MAX_DELTA_DEPTH)),
+            threads: self.threads.unwrap_or(configured.threads),
+        })
+    }
+}
+
+/// `pack-objects`: packs the objects listed on `input`, one per line,
+/// each optionally followed by the path it was found at as `rev-list
+/// --objects` prints it. The pack goes to stdout without a `base_name`,
+/// or else is stored with its index as `<base_name>-<checksum>.pack`,
+/// and the checksum printed.
+pub fn pack_objects(
+    input: impl BufRead,
+    base_name: Option<&Path>,
+    tuning: Tuning,
+    progress: bool,
+) -> anyhow::Result<()> {
+    let repository = repository::current()?.without_replacements();
+    let mut seen = HashSet::new();
+    let mut objects = Vec::new();
+    for line in input.lines() {
+        let line = line?;
+        let (sha, name) = line.split_once(' ').unwrap_or((&line, ""));
+        let sha: ObjectId = sha.parse().map_err(|_| anyhow!("expected object ID, got garbage:\n {}", line))?;
+        if seen.insert(sha) {
+            objects.push((sha, pack::name_hash(name)));
+        }
+    }
+
+    let options = tuning.options(&repository.config()?)?;
+    let mut data = Vec::new();
+    let (written, checksum) = pack::write(&repository, &objects, options, progress, &mut data)?;
+    match base_name {
+        Some(base_name) => {
+            let index = pack::write_index(&written, &checksum, repository.object_format());
+            pack::store(base_name, &data, &index, None, &checksum)?;
+            println!("{}", checksum);
+        }
+        None => {
+            let mut stdout = std::io::stdout().lock();
+            stdout.write_all(&data)?;
+            stdout.flush()?;
+        }
+    }
+    Ok(())
+}
+
+/// How `repack` runs.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Options {
+    /// Pack every reachable object into one pack, not just loose ones.
+    pub all: bool,
+    /// Roll up the smallest packs, and the loose objects, so that the
+    /// object counts of the packs left grow by at least this factor.
+    pub geometric: Option<usize>,
+    /// Remove the packs the new one replaces and the loose objects it
+    /// holds.
+    pub delete: bool,
+    /// Write a multi-pack-index of the packs there are afterwards.
+    pub write_midx: bool,
+    /// Write reachability bitmaps for the new pack, which `all` must be
+    /// set for. By default `repack.writeBitmaps`, or else whether the
+    /// repository is bare and `all` is set.
+    pub write_bitmaps: Option<bool>,
+    pub tuning: Tuning,
+    pub progress: bool,
+}
+
+/// `repack`: packs the loose objects that are reachable, or with `all`
+/// every reachable object, into a new pack; or with `geometric`, the
+/// objects of the packs `geometric_split` picks and every loose object.
+/// Objects in packs kept with a `.keep` file, or only in alternates, stay
+/// where they are. With `delete`, the packs replaced go, and so do loose
+/// objects that are now packed.
+pub fn run(options: Options) -> anyhow::Result<()> {
+    if options.all && options.geometric.is_some() {
+        return Err(anyhow!("options '--geometric' and '-a' cannot be used together"));
+    }
+    let repository = repository::current()?.without_replacements();
+    let config = repository.config()?;
+    let mut write_bitmaps = match (options.write_bitmaps, config.get("repack.writebitmaps")) {
+        (Some(write), _) => write,
+        (None, Some(value)) => config::parse_bool(value).ok_or(anyhow!("Bad repack.writeBitmaps value: {}", value))?,
+        (None, None) => options.all && config.get("core.bare") == Some("true"),
+    };
+    if write_bitmaps && !options.all {
+        return Err(anyhow!(
+            "Incremental repacks are incompatible with bitmap indexes.  Use\n\
+             --no-write-bitmap-index or disable the repack.writeBitmaps configuration."
+        ));
+    }
+    let pack_dir = repository.object_dir().join("pack");
+    let packs = repository.packs();
+    let (kept, old): (Vec<_>, Vec<_>) = midx::local_packs(&repository, &packs)
+        .into_iter()
+        .partition(|pack| pack.path().with_extension("keep").exists());
+
+    // As in git, a geometric repack prefers the copies of objects in the
+    // biggest pack that stays, so that the fewest are found elsewhere.
+    let (replaced, objects, mut preferred) = match options.geometric {
+        Some(factor) => {
+            let mut old = old;
+            old.sort_by_key(|pack| (pack.index().objects.len(), pack.path().to_path_buf()));
+            let counts: Vec<usize> = old.iter().map(|pack| pack.index().objects.len()).collect();
+            let split = geometric_split(&counts, factor);
+            let (replaced, staying) = old.split_at(split);
+            let names: HashMap<ObjectId, u32> = reachable(&repository)?.into_iter().collect();
+            let mut seen = HashSet::new();
+            let loose = repository.loose_objects()?
+                .into_iter()
+                .filter(|sha| repository.object_path(sha).is_file() && !packs.iter().any(|pack| pack.contains(sha)));
+            let objects: Vec<(ObjectId, u32)> = replaced.iter()
+                .flat_map(|pack| pack.index().objects.iter().map(|(sha, _)| *sha))
+                .filter(|sha| !staying.iter().chain(&kept).any(|pack| pack.contains(sha)))
+                .chain(loose)
+                .filter(|sha| seen.insert(*sha))
+                .map(|sha| (sha, names.get(&sha).copied().unwrap_or(0)))
+                .collect();
+            (replaced.to_vec(), objects, staying.last().map(|pack| pack.path().to_path_buf()))
+        }
+        None => {
+            let reachable = reachable(&repository)?;
+            let count = reachable.len();
+            let objects: Vec<_> = reachable.into_iter()
+                .filter(|(sha, _)| !kept.iter().any(|pack| pack.contains(sha)))
+                .filter(|(sha, _)| match options.all {
+                    true => old.iter().any(|pack| pack.contains(sha)) || repository.object_path(sha).is_file(),
+                    false => !packs.iter().any(|pack| pack.contains(sha)) && repository.object_path(sha).is_file(),
+                })
+                .collect();
+            if write_bitmaps && objects.len() < count {
+                // Bitmaps must cover everything reachable from the pack.
+                eprintln!("warning: disabling bitmap writing, as some objects are not being packed");
+                write_bitmaps = false;
+            }
+            (if options.all { old.clone() } else { Vec::new() }, objects, None)
+        }
+    };
+
+    let mut new = None;
+    if objects.is_empty() {
+        info!("Nothing new to pack.");
+    } else {
+        let pack_options = options.tuning.options(&config)?;
+        let mut data = Vec::new();
+        let (written, checksum) = pack::write(&repository, &objects, pack_options, options.progress, &mut data)?;
+        let index = pack::write_index(&written, &checksum, repository.object_format());
+        let bitmap = match write_bitmaps {
+            true => {
+                let names = objects.iter().copied().collect();
+                Some(bitmap::write(&repository, &written, &names, &tips(&repository)?, &checksum)?)
+            }
+            false => None,
+        };
+        new = Some(pack::store(&pack_dir.join("pack"), &data, &index, bitmap.as_deref(), &checksum)?);
+        repository.reload_packs();
+    }
+    if options.geometric.is_some() && preferred.is_none() {
+        preferred = new.clone();
+    }
+
+    let removed: Vec<_> = replaced.iter().filter(|pack| Some(pack.path()) != new.as_deref()).collect();
+    if options.delete {
+        if !removed.is_empty() && !options.write_midx {
+            // A multi-pack-index listing a removed pack is of no use.
+            midx::clear(&repository)?;
+        }
+        for pack in &removed {
+            // The index goes first, so git never sees a pack without one.
+            for extension in ["idx", "bitmap", "rev", "pack"] {
+                match fs::remove_file(pack.path().with_extension(extension)) {
+                    Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+                    _ => {}
+                }
+            }
+        }
+        repository.reload_packs();
+        prune_packed(&repository)?;
+    }
+
+    if options.write_midx {
+        midx::write(&repository, preferred.as_deref())?;
+    }
+    Ok(())
+}
+
+/// How many of the packs with object `counts`, sorted, a geometric
+/// repack by `factor` rolls up into one: those below the first pair,
+/// counting down from the biggest, where a pack has fewer than `factor`
+/// times the objects of the one below it; and then as many of the packs
+/// above as have fewer than `factor` times the objects of all those.
+fn geometric_split(counts: &[usize], factor: usize) -> usize {
+    let mut split = (1..counts.len())
+        .rev()
+        .find(|&i| counts[i] < counts[i - 1].saturating_mul(factor))
+        .map_or(0, |i| i + 1);
+    let mut total: usize = counts[..split].iter().sum();
+    while split < counts.len() && counts[split] < total.saturating_mul(factor) {
+        total += counts[split];
+        split += 1;
+    }
+    split
+}
+
+/// The loose-objects task of `maintenance run`: removes the loose objects
+/// a pack already has, then packs up to `maintenance.loose-objects.batchSize`
+/// of the rest, 50000 by default or all of them with 0, into a new pack,
+/// leaving them for the next run to remove. Returns how many were packed.
+pub(crate) fn pack_loose(repository: &Repository, config: &Config) -> anyhow::Result<usize> {
+    prune_packed(repository)?;
+    let batch_size = match config.get("maintenance.loose-objects.batchsize") {
+        Some(value) => value.parse::<usize>()
+            .map_err(|_| anyhow!("Bad maintenance.loose-objects.batchSize value: {}", value))?,
+        None => 50000,
+    };
+    let objects: Vec<(ObjectId, u32)> = repository.loose_objects()?
+        .into_iter()
+        .filter(|sha| repository.object_path(sha).is_file())
+        .take(if batch_size == 0 { usize::MAX } else { batch_size })
+        .map(|sha| (sha, 0))
+        .collect();
+    if objects.is_empty() {
+        return Ok(0);
+    }
+
+    let mut data = Vec::new();
+    let (written, checksum) = pack::write(repository, &objects, PackOptions::from_config(config)?, false, &mut data)?;
+    let index = pack::write_index(&written, &checksum, repository.object_format());
+    pack::store(&repository.object_dir().join("pack").join("loose"), &data, &index, None, &checksum)?;
+    repository.reload_packs();
+    Ok(objects.len())
+}
+
+/// The incremental-repack task of `maintenance run`: writes the
+/// multi-pack-index, removes the packs none of whose objects it points
+/// to, then rolls up the oldest packs that are small enough into a new
+/// one, leaving those for the next run to remove. Packs join the batch
+/// until it is a byte bigger than the second-biggest pack, each counted
+/// by the share of its objects the index points to, and it is only
+/// written if it holds at least two.
+pub(crate) fn incremental(repository: &Repository, config: &Config) -> anyhow::Result<()> {
+    midx::write(repository, None)?;
+
+    let packs = repository.packs();
+    let local = midx::local_packs(repository, &packs);
+    let entries = midx::select(&local, None)?;
+    let mut referenced = vec![0; local.len()];
+    for (_, position, _) in &entries {
+        referenced[*position] += 1;
+    }
+    let kept = |pack: &Pack| pack.path().with_extension("keep").exists();
+    let expired: Vec<_> = local.iter().zip(&referenced).filter(|(pack, count)| **count == 0 && !kept(pack)).collect();
+    if !expired.is_empty() {
+        midx::clear(repository)?;
+    }
+    for (pack, _) in &expired {
+        for extension in ["idx", "bitmap", "rev", "pack"] {
+            match fs::remove_file(pack.path().with_extension(extension)) {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+                _ => {}
+            }
+        }
+    }
+    if !expired.is_empty() {
+        // Start over with an index of the packs that are left.
+        repository.reload_packs();
+        return incremental(repository, config);
+    }
+
+    let mut sizes = Vec::new();
+    for pack in &local {
+        sizes.push(fs::metadata(pack.path())?.len());
+    }
+    let mut sorted = sizes.clone();
+    sorted.sort_unstable();
+    let batch_size = sorted.iter().rev().nth(1).map_or(0, |size| size + 1);
+    let mut by_age = Vec::new();
+    for (position, pack) in local.iter().enumerate() {
+        by_age.push((fs::metadata(pack.path())?.modified()?, position));
+    }
+    by_age.sort();
+
+    let mut included = vec![false; local.len()];
+    let mut total = 0;
+    for (_, position) in by_age {
+        if total >= batch_size {
+            break;
+        }
+        let pack = local[position];
+        let expected = sizes[position] * referenced[position] / pack.index().objects.len().max(1) as u64;
+        if kept(pack) || expected >= batch_size {
+            continue;
+        }
+        total += expected;
+        included[position] = true;
+    }
+    if total < batch_size || included.iter().filter(|&&included| included).count() < 2 {
+        return Ok(());
+    }
+
+    let objects: Vec<(ObjectId, u32)> = entries.iter()
+        .filter(|(_, position, _)| included[*position])
+        .map(|(sha, _, _)| (*sha, 0))
+        .collect();
+    let mut data = Vec::new();
+    let (written, checksum) = pack::write(repository, &objects, PackOptions::from_config(config)?, false, &mut data)?;
+    let index = pack::write_index(&written, &checksum, repository.object_format());
+    pack::store(&repository.object_dir().join("pack").join("pack"), &data, &index, None, &checksum)?;
+    repository.reload_packs();
+    midx::write(repository, None)?;
+    Ok(())
+}
+
+/// Removes the loose objects of the object directory that a pack there
+/// holds, and the fan-out directories that leaves empty.
+fn prune_packed(repository: &Repository) -> anyhow::Result<()> {
+    let packs = repository.packs();
+    let local: Vec<_> = packs.iter().filter(|pack| pack.path().starts_with(repository.object_dir())).collect();
+    for sha in repository.loose_objects()? {
+        let path = repository.object_path(&sha);
+        if path.is_file() && local.iter().any(|pack| pack.contains(&sha)) {
+            fs::remove_file(&path)?;
+            if let Some(directory) = path.parent() {
+                let _ = fs::remove_dir(directory);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// What the refs and the `HEAD` of each worktree point to.
+fn tips(repository: &Repository) -> anyhow::Result<Vec<ObjectId>> {
+    let mut tips = Vec::new();
+    for name in refs::list_refs("refs/")? {
+        tips.extend(refs::read_ref(&name)?);
+    }
+    let mut git_dirs = vec![repository.common_dir().to_path_buf()];
+    if let Ok(entries) = fs::read_dir(repository.common_dir().join("worktrees")) {
+        git_dirs.extend(entries.flatten().map(|entry| entry.path()));
+    }
+    for git_dir in &git_dirs {
+        tips.extend(refs::read_ref_in(git_dir, "HEAD").ok().flatten());
+    }
+    Ok(tips)
+}
+
+/// Every object reachable from the refs, the `HEAD` and index of every
+/// worktree, and the reflogs, each with the name hash of the path it was
+/// found at. Commits and tags come first, then trees and blobs.
+fn reachable(repository: &Repository) -> anyhow::Result<Vec<(ObjectId, u32)>> {
+    let mut tips = tips(repository)?;
+    let mut git_dirs = vec![repository.common_dir().to_path_buf()];
+    if let Ok(entries) = fs::read_dir(repository.common_dir().join("worktrees")) {
+        git_dirs.extend(entries.flatten().map(|entry| entry.path()));
+    }
+    let mut named = Vec::new();
+    for git_dir in &git_dirs {
+        let index = Index::read(&git_dir.join("index"), repository.object_format())?;
+        let entries = index.entries.into_iter().filter(|entry| entry.mode != 0o160000);
+        named.extend(entries.map(|entry| (entry.sha, entry.path)));
+        let mut trees: Vec<&CacheTree> = index.cache_tree.iter().collect();
+        while let Some(tree) = trees.pop() {
+            tips.extend(tree.sha.filter(|sha| tree.entry_count.is_some() && repository.has_object(sha)));
+            trees.extend(&tree.subtrees);
+        }
+    }
+    for log in reflog::log_files(repository)? {
+        tips.extend(reflog::logged_objects(&log).into_iter().filter(|sha| repository.has_object(sha)));
+    }
+
+    let mut seen = HashSet::new();
+    let mut objects = Vec::new();
+    let mut trees = Vec::new();
+    let mut blobs = Vec::new();
+    while let Some(sha) = tips.pop() {
+        if seen.contains(&sha) {
+            continue;
+        }
+        let (kind, _) = repository.object_header(&sha)?;
+        match kind.as_str() {
+            "tree" => trees.push((sha, String::new())),
+            "blob" => blobs.push((sha, String::new())),
+            _ => {
+                seen.insert(sha);
+                objects.push((sha, 0));
+                match repository.read(&sha)? {
+                    Object::Commit(commit) => {
+                        trees.push((commit.tree, String::new()));
+                        tips.extend(commit.parents);
+                    }
+                    Object::Tag(tag) => tips.push(tag.object),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    // Trees are walked from the last found, so the newest commit's come
+    // first, as in git.
+    while let Some((tree, path)) = trees.pop() {
+        if !seen.insert(tree) {
+            continue;
+        }
+        objects.push((tree, pack::name_hash(&path)));
+        let Object::Tree(entries) = repository.read(&tree)? else {
+            return Err(anyhow!("Object {} is not a tree", tree));
+        };
+        for entry in entries.entries.into_iter().rev() {
+            let path = if path.is_empty() { entry.name.clone() } else { format!("{}/{}", path, entry.name) };
+            match entry.kind() {
+                "tree" => trees.push((entry.sha, path)),
+                // Submodule commits live in another repository.
+                "commit" => {}
+                _ => blobs.push((entry.sha, path)),
+            }
+        }
+        while let Some((blob, path)) = blobs.pop() {
+            if seen.insert(blob) {
+                objects.push((blob, pack::name_hash(&path)));
+            }
+        }
+    }
+    for (blob, path) in named.into_iter().chain(blobs) {
+        if seen.insert(blob) && repository.has_object(&blob) {
+            objects.push((blob, pack::name_hash(&path)));
+        }
+    }
+    Ok(objects)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn geometric_splits() {
+        assert_eq!(geometric_split(&[], 2), 0);
+        assert_eq!(geometric_split(&[5], 2), 0);
+        assert_eq!(geometric_split(&[1, 2, 4, 8], 2), 0);
+        assert_eq!(geometric_split(&[1, 1, 4, 8], 2), 2);
+        // The third 3 breaks the progression, and rolling up the three
+        // makes 9, which 6 and then 15 are less than twice.
+        assert_eq!(geometric_split(&[3, 3, 3, 6, 15, 60], 2), 5);
+        assert_eq!(geometric_split(&[3, 3, 3, 6, 15, 60], 3), 6);
+        assert_eq!(geometric_split(&[1, 3, 9, 27], 3), 0);
+        assert_eq!(geometric_split(&[1, 3, 9, 28], 3), 0);
+        assert_eq!(geometric_split(&[2, 3, 9, 28], 3), 4);
+        assert_eq!(geometric_split(&[usize::MAX / 2, usize::MAX], 4), 0);
+    }
+}
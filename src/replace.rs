@@ -0,0 +1,57 @@
+use anyhow::anyhow;
+
+use crate::refs;
+use crate::repository;
+
+/// Makes reads of `object` return `replacement` instead. Both must be of
+/// the same type, and an existing replacement is only overwritten if
+/// `force`.
+pub fn create(object: &str, replacement: &str, force: bool) -> anyhow::Result<()> {
+    let repository = repository::current()?;
+    let object = refs::resolve_revision(object)?;
+    let replacement = refs::resolve_revision(replacement)?;
+
+    let name = format!("refs/replace/{}", object);
+    if refs::read_ref(&name)?.is_some() && !force {
+        return Err(anyhow!("Replace ref '{}' already exists", name));
+    }
+    if object == replacement {
+        return Err(anyhow!("New object is the same as the old one: '{}'", object));
+    }
+
+    let (object_kind, _) = repository.find_object(&object)?;
+    let (replacement_kind, _) = repository.find_object(&replacement)?;
+    if object_kind != replacement_kind {
+        return Err(anyhow!(
+            "Objects must be of the same type.\n'{}' points to a replaced object of type '{}'\n\
+             while '{}' points to a replacement object of type '{}'.",
+            object, object_kind, replacement, replacement_kind
+        ));
+    }
+
+    refs::write_ref(&name, &replacement)
+}
+
+/// Prints the replaced objects.
+pub fn list() -> anyhow::Result<()> {
+    for name in refs::list_refs("refs/replace/")? {
+        println!("{}", &name["refs/replace/".len()..]);
+    }
+
+    Ok(())
+}
+
+/// Removes the replacements of `objects`.
+pub fn delete(objects: &[String]) -> anyhow::Result<()> {
+    for object in objects {
+        let name = format!("refs/replace/{}", object);
+        if refs::read_ref(&name)?.is_none() {
+            return Err(anyhow!("Replace ref '{}' not found", object));
+        }
+
+        refs::delete_refs(&[name])?;
+        println!("Deleted replace ref '{}'", object);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,1382 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+
+use anyhow::anyhow;
+use bytes::{BufMut, BytesMut};
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+
+use crate::cache::ObjectCache;
+use crate::config::{self, Config};
+use crate::ident::{self, Role};
+use crate::object_id::{HashAlgorithm, ObjectId};
+use crate::objects::{Commit, Object, Tree, TreeEntry};
+use crate::pack::Pack;
+use crate::profile;
+use crate::refs::{self, RefFormat};
+use crate::trace;
+use crate::trace::TRACE;
+
+/// A git repository, located by its git directory and the common
+/// directory holding the objects and refs it shares with other worktrees.
+#[derive(Debug, Clone)]
+pub struct Repository {
+    git_dir: PathBuf,
+    common_dir: PathBuf,
+    /// Where new objects are written: `GIT_OBJECT_DIRECTORY`, or
+    /// `objects` in the common directory.
+    object_dir: PathBuf,
+    /// Further directories searched for objects, from
+    /// `GIT_ALTERNATE_OBJECT_DIRECTORIES` and `info/alternates`.
+    alternates: Arc<Vec<PathBuf>>,
+    format: HashAlgorithm,
+    compression: Compression,
+    /// How loose objects are flushed to disk, or `None` if they aren't.
+    fsync: Option<FsyncMethod>,
+    /// Loose objects written during a [`Repository::batch`], by the
+    /// temporary file each is in and where it goes. Shared by clones.
+    batch: Arc<Mutex<Option<Batch>>>,
+    // Shared by clones, so every handle on the repository benefits.
+    cache: Arc<Mutex<ObjectCache>>,
+    /// The packs of the object directory and the alternates, once read.
+    packs: Arc<Mutex<Option<Arc<Vec<Pack>>>>>,
+    /// Objects to read in place of others, from `refs/replace/<sha>`.
+    replacements: Arc<HashMap<ObjectId, ObjectId>>,
+    /// Whether objects read whole are hashed to check they are what was
+    /// asked for: `core.checksumObjects`, and always when serving them.
+    checksum_objects: bool,
+    /// Where a repository made by [`Repository::in_memory`] keeps
+    /// everything, in place of its directories. Shared by clones.
+    memory: Option<Arc<Mutex<Memory>>>,
+}
+
+/// The temporary file and final path of each loose object in a batch.
+type Batch = HashMap<ObjectId, (PathBuf, PathBuf)>;
+
+/// The objects, refs and config of an in-memory repository.
+#[derive(Debug, Default)]
+struct Memory {
+    objects: HashMap<ObjectId, (String, Vec<u8>)>,
+    /// What each ref holds, as [`Repository::raw_ref`] returns it.
+    refs: BTreeMap<String, String>,
+    config: Config,
+}
+
+/// How many replacements of replacements are followed, like git.
+const MAX_REPLACE_DEPTH: usize = 5;
+
+/// How deeply alternates of alternates are followed, like git.
+const MAX_ALTERNATE_DEPTH: usize = 5;
+
+/// The default of `core.bigFileThreshold`.
+pub const DEFAULT_BIG_FILE_THRESHOLD: u64 = 512 << 20;
+
+impl Repository {
+    /// Opens the repository whose worktree is `worktree`, or the bare
+    /// repository at `worktree`.
+    pub fn open(worktree: &Path) -> anyhow::Result<Repository> {
+        Repository::open_with(worktree, false)
+    }
+
+    /// Opens a repository, taking its object directories from the
+    /// environment if `environment`. Only the repository the command runs
+    /// in does, so that other repositories it opens keep their own.
+    fn open_with(worktree: &Path, environment: bool) -> anyhow::Result<Repository> {
+        let git_dir = git_dir_of(worktree)?;
+        let common_dir = common_dir_of(&git_dir)?;
+        let config = Config::from_file(&common_dir.join("config"))?;
+        let format = object_format(&config)?;
+        let compression = loose_compression(&config)?;
+        let fsync = loose_fsync(&config);
+        let checksum_objects = checksum_objects(&config)?;
+        let replacements = Arc::new(replacements(&git_dir)?);
+        let (object_dir, alternates) = object_dirs(&common_dir, environment)?;
+
+        Ok(Repository {
+            git_dir,
+            common_dir,
+            object_dir,
+            alternates: Arc::new(alternates),
+            format,
+            compression,
+            fsync,
+            batch: Arc::default(),
+            cache: Arc::default(),
+            packs: Arc::default(),
+            replacements,
+            checksum_objects,
+            memory: None,
+        })
+    }
+
+    /// Creates a repository in `directory`, or reinitializes an existing
+    /// one. Returns the repository and whether it already existed. Like
+    /// `git init`, it takes its object directory from the environment.
+    pub fn init(
+        directory: &Path,
+        bare: bool,
+        initial_branch: Option<&str>,
+        format: Option<HashAlgorithm>,
+        ref_format: Option<RefFormat>,
+        template: Option<&Path>,
+    ) -> anyhow::Result<(Repository, bool)> {
+        Repository::create(directory, bare, initial_branch, format, ref_format, template, true)
+    }
+
+    /// Creates the bare git directory of a submodule, which never uses
+    /// the object directories of the superproject's environment.
+    pub(crate) fn init_module(directory: &Path, format: HashAlgorithm) -> anyhow::Result<Repository> {
+        Ok(Repository::create(directory, true, None, Some(format), None, None, false)?.0)
+    }
+
+    /// An empty repository that keeps its objects, refs and config in
+    /// memory, so that history can be built and read without touching the
+    /// filesystem, as tests do. HEAD points at `main`, and the config
+    /// starts empty rather than with the global one. It has no
+    /// directories: only the methods of the repository itself see it, not
+    /// the commands that use the repository they run in.
+    pub fn in_memory() -> Repository {
+        let memory = Memory {
+            refs: BTreeMap::from([("HEAD".to_string(), "ref: refs/heads/main".to_string())]),
+            ..Memory::default()
+        };
+        Repository {
+            git_dir: PathBuf::new(),
+            common_dir: PathBuf::new(),
+            object_dir: PathBuf::new(),
+            alternates: Arc::default(),
+            format: HashAlgorithm::default(),
+            compression: Compression::default(),
+            fsync: None,
+            batch: Arc::default(),
+            cache: Arc::default(),
+            packs: Arc::default(),
+            replacements: Arc::default(),
+            checksum_objects: false,
+            memory: Some(Arc::new(Mutex::new(memory))),
+        }
+    }
+
+    /// Whether this is a repository made by [`Repository::in_memory`].
+    pub fn is_in_memory(&self) -> bool {
+        self.memory.is_some()
+    }
+
+    fn lock_memory(&self) -> Option<MutexGuard<'_, Memory>> {
+        // Every change is a single insert, so nothing is left half done
+        // by a panic elsewhere.
+        self.memory.as_ref().map(|memory| memory.lock().unwrap_or_else(|err| err.into_inner()))
+    }
+
+    fn create(
+        directory: &Path,
+        bare: bool,
+        initial_branch: Option<&str>,
+        format: Option<HashAlgorithm>,
+        ref_format: Option<RefFormat>,
+        template: Option<&Path>,
+        environment: bool,
+    ) -> anyhow::Result<(Repository, bool)> {
+        let git_dir = if bare { directory.to_path_buf() } else { directory.join(".git") };
+        let reinit = git_dir.join("HEAD").is_file();
+
+        let config_path = git_dir.join("config");
+        // Copied first, so that the template's `config` is the base of the
+        // new one.
+        if let Some(template) = template_dir(template, &config_path)? {
+            copy_template(&template, &git_dir)?;
+        }
+        let mut config = Config::from_file(&config_path)?;
+        let existing_format = object_format(&config)?;
+        if reinit && format.is_some_and(|format| format != existing_format) {
+            return Err(anyhow!("Attempt to reinitialize repository with different hash"));
+        }
+        let format = if reinit { existing_format } else { format.unwrap_or_default() };
+        let existing_ref_format = RefFormat::from_config(&config)?;
+        if reinit && ref_format.is_some_and(|ref_format| ref_format != existing_ref_format) {
+            return Err(anyhow!("Attempt to reinitialize repository with different reference storage format"));
+        }
+        let ref_format = if reinit { existing_ref_format } else { ref_format.unwrap_or_default() };
+
+        let (object_dir, alternates) = object_dirs(&git_dir, environment)?;
+        fs::create_dir_all(&object_dir)?;
+        match ref_format {
+            RefFormat::Files => {
+                fs::create_dir_all(git_dir.join("refs/heads"))?;
+                fs::create_dir_all(git_dir.join("refs/tags"))?;
+            }
+            // Older versions of git still need a `refs` directory to
+            // recognize the repository, but fail to use it.
+            RefFormat::Reftable => {
+                fs::create_dir_all(git_dir.join("refs"))?;
+                fs::write(git_dir.join("refs/heads"), "this repository uses the reftable format\n")?;
+            }
+        }
+
+        if reinit {
+            if let Some(branch) = initial_branch {
+                eprintln!("warning: re-init: ignored --initial-branch={}", branch);
+            }
+        }
+
+        // Extensions such as `objectFormat` need format version 1.
+        if format != HashAlgorithm::Sha1 || ref_format != RefFormat::Files {
+            config.set("core.repositoryformatversion", "1")?;
+        }
+        if format != HashAlgorithm::Sha1 {
+            config.set("extensions.objectformat", format.name())?;
+        }
+        if ref_format != RefFormat::Files {
+            config.set("extensions.refstorage", ref_format.name())?;
+        }
+        if config.get("core.repositoryformatversion").is_none() {
+            config.set("core.repositoryformatversion", "0")?;
+        }
+        config.set("core.filemode", if probe_file_mode(&git_dir)? { "true" } else { "false" })?;
+        config.set("core.bare", if bare { "true" } else { "false" })?;
+        // Like git, only the unusual case is recorded.
+        if probe_ignore_case(&git_dir)? {
+            config.set("core.ignorecase", "true")?;
+        }
+        if probe_precompose_unicode(&git_dir)? {
+            config.set("core.precomposeunicode", "true")?;
+        }
+        config.write(&config_path)?;
+        // Written once the config says how refs are stored.
+        if !reinit {
+            refs::write_head(&git_dir, &format!("ref: refs/heads/{}", initial_branch.unwrap_or("main")))?;
+        }
+
+        let common_dir = common_dir_of(&git_dir)?;
+        let compression = loose_compression(&config)?;
+        let fsync = loose_fsync(&config);
+        let checksum_objects = checksum_objects(&config)?;
+        let replacements = Arc::new(replacements(&git_dir)?);
+        let repository = Repository {
+            git_dir,
+            common_dir,
+            object_dir,
+            alternates: Arc::new(alternates),
+            format,
+            compression,
+            fsync,
+            batch: Arc::default(),
+            cache: Arc::default(),
+            packs: Arc::default(),
+            replacements,
+            checksum_objects,
+            memory: None,
+        };
+        Ok((repository, reinit))
+    }
+
+    pub fn git_dir(&self) -> &Path {
+        &self.git_dir
+    }
+
+    /// The directory shared by all worktrees. For the main worktree this
+    /// is the git directory itself.
+    pub fn common_dir(&self) -> &Path {
+        &self.common_dir
+    }
+
+    pub fn object_format(&self) -> HashAlgorithm {
+        self.format
+    }
+
+    /// The configuration in effect: the repository's config file on top of
+    /// the system and global ones.
+    pub fn config(&self) -> anyhow::Result<Config> {
+        match self.lock_memory() {
+            Some(memory) => Ok(memory.config.clone()),
+            None => Config::layered(&self.common_dir.join("config")),
+        }
+    }
+
+    /// Sets `name` to `value` in the config of an in-memory repository.
+    pub fn set_config(&self, name: &str, value: &str) -> anyhow::Result<()> {
+        let mut memory = self.lock_memory().ok_or(anyhow!("Only in-memory repositories are configured in place"))?;
+        memory.config.set(name, value)
+    }
+
+    /// The directory new objects are written to.
+    pub fn object_dir(&self) -> &Path {
+        &self.object_dir
+    }
+
+    /// Path of the loose object file for `sha` in the object directory.
+    pub fn object_path(&self, sha: &ObjectId) -> PathBuf {
+        loose_path(&self.object_dir, sha)
+    }
+
+    /// Finds the loose object file for `sha` in the object directory or
+    /// one of the alternates.
+    fn find_loose(&self, sha: &ObjectId) -> Option<PathBuf> {
+        if self.memory.is_some() {
+            return None;
+        }
+        if let Some((temp_path, _)) = self.lock_batch().as_ref().and_then(|batch| batch.get(sha)) {
+            return Some(temp_path.clone());
+        }
+        std::iter::once(&self.object_dir)
+            .chain(self.alternates.iter())
+            .map(|dir| loose_path(dir, sha))
+            .find(|path| path.is_file())
+    }
+
+    /// Every loose object in the object directory and the alternates, in
+    /// the order the directories list them, each once.
+    pub fn loose_objects(&self) -> anyhow::Result<Vec<ObjectId>> {
+        if let Some(memory) = self.lock_memory() {
+            let mut objects: Vec<ObjectId> = memory.objects.keys().copied().collect();
+            objects.sort();
+            return Ok(objects);
+        }
+        let mut seen = HashSet::new();
+        let mut objects = Vec::new();
+        for dir in std::iter::once(&self.object_dir).chain(self.alternates.iter()) {
+            let Ok(fanout) = fs::read_dir(dir) else { continue };
+            for entry in fanout {
+                let entry = entry?;
+                let prefix = entry.file_name().to_string_lossy().into_owned();
+                if prefix.len() != 2 || !entry.file_type()?.is_dir() {
+                    continue;
+                }
+                for file in fs::read_dir(entry.path())? {
+                    let rest = file?.file_name().to_string_lossy().into_owned();
+                    // Temporary files and anything else that isn't named
+                    // like an object are skipped.
+                    let Ok(sha) = format!("{}{}", prefix, rest).parse::<ObjectId>() else { continue };
+                    if sha.algorithm() == self.format && seen.insert(sha) {
+                        objects.push(sha);
+                    }
+                }
+            }
+        }
+        Ok(objects)
+    }
+
+    /// Every packed object in the object directory and the alternates,
+    /// each once.
+    pub fn packed_objects(&self) -> Vec<ObjectId> {
+        let mut seen = HashSet::new();
+        self.packs()
+            .iter()
+            .flat_map(|pack| pack.index().objects.iter().map(|(sha, _)| *sha))
+            .filter(|sha| seen.insert(*sha))
+            .collect()
+    }
+
+    /// Whether the object is here, loose or packed, without reading it.
+    pub fn has_object(&self, sha: &ObjectId) -> bool {
+        if let Some(memory) = self.lock_memory() {
+            return memory.objects.contains_key(sha);
+        }
+        self.find_loose(sha).is_some() || self.packs().iter().any(|pack| pack.contains(sha))
+    }
+
+    /// The packs of the object directory and the alternates, read the
+    /// first time they are needed. Like git, a pack that can't be opened
+    /// is left out with a warning.
+    pub(crate) fn packs(&self) -> Arc<Vec<Pack>> {
+        let mut packs = self.packs.lock().unwrap_or_else(|err| err.into_inner());
+        packs.get_or_insert_with(|| {
+            let mut found = Vec::new();
+            if self.memory.is_some() {
+                return Arc::new(found);
+            }
+            for dir in std::iter::once(&self.object_dir).chain(self.alternates.iter()) {
+                let Ok(entries) = fs::read_dir(dir.join("pack")) else { continue };
+                let mut paths: Vec<PathBuf> = entries.flatten()
+                    .map(|entry| entry.path())
+                    .filter(|path| path.extension().is_some_and(|ext| ext == "pack"))
+                    .filter(|path| path.with_extension("idx").is_file())
+                    .collect();
+                paths.sort();
+                for path in paths {
+                    match Pack::open(&path, self.format) {
+                        Ok(pack) => found.push(pack),
+                        Err(err) => eprintln!("warning: ignoring pack {}: {}", path.display(), err),
+                    }
+                }
+            }
+            Arc::new(found)
+        }).clone()
+    }
+
+    /// Forgets the packs read so far, so that packs added since are seen.
+    pub(crate) fn reload_packs(&self) {
+        *self.packs.lock().unwrap_or_else(|err| err.into_inner()) = None;
+    }
+
+    fn read_packed(&self, sha: &ObjectId) -> anyhow::Result<Option<(String, Vec<u8>)>> {
+        for pack in self.packs().iter() {
+            if let Some((kind, content)) = pack.read(sha)? {
+                return Ok(Some((kind.to_string(), content)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Opens the loose object `sha` and reads its header, leaving the
+    /// decoder at the start of the content.
+    fn open_loose(&self, sha: &ObjectId) -> anyhow::Result<(String, u64, ZlibDecoder<BufReader<fs::File>>)> {
+        let file = self.find_loose(sha)
+            .and_then(|path| fs::File::open(path).ok())
+            .ok_or(anyhow!("Object not found: {}", sha))?;
+        profile::FILES_OPENED.add(1);
+        let mut decoder = ZlibDecoder::new(BufReader::new(file));
+
+        let mut header = Vec::new();
+        let mut byte = [0u8];
+        loop {
+            decoder.read_exact(&mut byte).map_err(|_| anyhow!("Invalid object header: {}", sha))?;
+            if byte[0] == 0 {
+                break;
+            }
+            header.push(byte[0]);
+            if header.len() > 64 {
+                return Err(anyhow!("Invalid object header: {}", sha));
+            }
+        }
+        let (kind, size) = parse_loose_header(&header).ok_or(anyhow!("Invalid object header: {}", sha))?;
+        Ok((kind.to_string(), size, decoder))
+    }
+
+    /// Reads the `size` bytes of content left in `decoder`, failing
+    /// rather than inflating any further if the object holds more, so
+    /// that a small object can't expand to fill memory.
+    fn read_loose_content(sha: &ObjectId, size: u64, decoder: impl Read, out: &mut Vec<u8>) -> anyhow::Result<()> {
+        let start = out.len();
+        decoder.take(size.saturating_add(1))
+            .read_to_end(out)
+            .map_err(|err| anyhow!("Corrupt object {}: {}", sha, err))?;
+        let read = (out.len() - start) as u64;
+        profile::BYTES_INFLATED.add(read);
+        match read.cmp(&size) {
+            std::cmp::Ordering::Equal => Ok(()),
+            std::cmp::Ordering::Greater => Err(anyhow!("Object {} is longer than the {} bytes its header says", sha, size)),
+            std::cmp::Ordering::Less => Err(anyhow!("Object {} has {} bytes, but its header says {}", sha, read, size)),
+        }
+    }
+
+    /// The type and size of an object, without reading its content.
+    pub fn object_header(&self, sha: &ObjectId) -> anyhow::Result<(String, u64)> {
+        let sha = &self.replacement(sha)?;
+        if let Some((kind, content)) = self.lock_cache().get(sha) {
+            return Ok((kind, content.len() as u64));
+        }
+        if let Some(memory) = self.lock_memory() {
+            let (kind, content) = memory.objects.get(sha).ok_or(anyhow!("Object not found: {}", sha))?;
+            return Ok((kind.clone(), content.len() as u64));
+        }
+        if self.find_loose(sha).is_none() {
+            for pack in self.packs().iter() {
+                if let Some((kind, size)) = pack.header(sha)? {
+                    return Ok((kind.to_string(), size));
+                }
+            }
+        }
+        let (kind, size, _) = self.open_loose(sha)?;
+        Ok((kind, size))
+    }
+
+    /// Re-reads the loose object `sha` and checks that it decompresses,
+    /// has the size its header gives, and hashes to its name.
+    pub fn verify_loose(&self, sha: &ObjectId) -> anyhow::Result<()> {
+        let (kind, size, decoder) = self.open_loose(sha)?;
+        let mut data = format!("{} {}\0", kind, size).into_bytes();
+        Self::read_loose_content(sha, size, decoder, &mut data)?;
+        let actual = self.format.hash(&data);
+        if actual != *sha {
+            return Err(anyhow!("Object {} hashes to {}", sha, actual));
+        }
+        Ok(())
+    }
+
+    /// How many bytes the object takes up on disk, compressed, and in a
+    /// pack perhaps as a delta.
+    pub fn stored_size(&self, sha: &ObjectId) -> anyhow::Result<u64> {
+        if self.memory.is_some() {
+            return Ok(self.object_header(sha)?.1);
+        }
+        match self.find_loose(sha) {
+            Some(path) => Ok(fs::metadata(path)?.len()),
+            None => self.packs()
+                .iter()
+                .find_map(|pack| pack.stored_size(sha))
+                .ok_or(anyhow!("Object not found: {}", sha)),
+        }
+    }
+
+    /// How many deltas `sha` is stored as a chain of, or `None` if it is
+    /// loose.
+    pub fn delta_depth(&self, sha: &ObjectId) -> anyhow::Result<Option<usize>> {
+        if self.find_loose(sha).is_some() || self.memory.is_some() && self.has_object(sha) {
+            return Ok(None);
+        }
+        for pack in self.packs().iter() {
+            if let Some(depth) = pack.delta_depth(sha)? {
+                return Ok(Some(depth));
+            }
+        }
+        Err(anyhow!("Object not found: {}", sha))
+    }
+
+    /// Copies the content of an object to `out` as it is decompressed,
+    /// so that even huge loose blobs are never held in memory. Packed
+    /// objects are read whole, as their deltas need.
+    pub fn stream_object(&self, sha: &ObjectId, out: &mut impl Write) -> anyhow::Result<()> {
+        let sha = &self.replacement(sha)?;
+        if self.memory.is_some() {
+            return Ok(out.write_all(&self.find_object(sha)?.1)?);
+        }
+        if self.find_loose(sha).is_none() {
+            if let Some((_, content)) = self.read_packed(sha)? {
+                return Ok(out.write_all(&content)?);
+            }
+        }
+        let (_, size, decoder) = self.open_loose(sha)?;
+        let copied = std::io::copy(&mut decoder.take(size), out)?;
+        profile::OBJECT_READS.add(1);
+        profile::BYTES_INFLATED.add(copied);
+        if copied != size {
+            return Err(anyhow!("Object size mismatch: {}", sha));
+        }
+        Ok(())
+    }
+
+    /// `core.bigFileThreshold`: blobs larger than this are treated as
+    /// binary and streamed rather than read whole. 512 MiB by default.
+    pub fn big_file_threshold(&self) -> anyhow::Result<u64> {
+        match self.config()?.get("core.bigfilethreshold") {
+            Some(value) => config::parse_size(value)
+                .map_err(|_| anyhow!("Bad core.bigFileThreshold value: {}", value)),
+            None => Ok(DEFAULT_BIG_FILE_THRESHOLD),
+        }
+    }
+
+    /// Reads an object, returning its type and content. With
+    /// `core.checksumObjects`, fails unless the object hashes to `sha`.
+    pub fn find_object(&self, sha: &ObjectId) -> anyhow::Result<(String, Vec<u8>)> {
+        let sha = &self.replacement(sha)?;
+        if let Some((kind, content)) = self.lock_cache().get(sha) {
+            profile::CACHE_HITS.add(1);
+            // Other handles on the repository may have cached it unchecked.
+            self.check_object(sha, &kind, &content)?;
+            return Ok((kind, content));
+        }
+        profile::CACHE_MISSES.add(1);
+        if let Some(memory) = self.lock_memory() {
+            return memory.objects.get(sha).cloned().ok_or(anyhow!("Object not found: {}", sha));
+        }
+
+        let packed = match self.find_loose(sha) {
+            Some(_) => None,
+            None => self.read_packed(sha)?,
+        };
+        let (kind, content) = match packed {
+            Some(object) => object,
+            None => {
+                let (kind, size, decoder) = self.open_loose(sha)?;
+                let mut content = Vec::new();
+                Self::read_loose_content(sha, size, decoder, &mut content)?;
+                profile::OBJECT_READS.add(1);
+                (kind, content)
+            }
+        };
+        trace!(TRACE, "read_object: {} {} {}", sha, kind, content.len());
+        self.check_object(sha, &kind, &content)?;
+
+        if ObjectCache::caches(&kind) {
+            self.lock_cache().insert(*sha, &kind, &content);
+        }
+        Ok((kind, content))
+    }
+
+    /// Fails if checking objects and `kind` and `content` don't hash to
+    /// `sha`, so that corruption on disk is caught where the object is
+    /// used rather than copied on.
+    fn check_object(&self, sha: &ObjectId, kind: &str, content: &[u8]) -> anyhow::Result<()> {
+        if !self.checksum_objects {
+            return Ok(());
+        }
+        let header = format!("{} {}\0", kind, content.len());
+        if self.format.hash(&[header.as_bytes(), content].concat()) != *sha {
+            return Err(anyhow!("hash mismatch {}", sha));
+        }
+        Ok(())
+    }
+
+    /// This repository with every object read whole checked against its
+    /// ID, whatever `core.checksumObjects` says, for sending objects to
+    /// other repositories. Streamed blobs are still not checked.
+    pub fn checking_objects(&self) -> Repository {
+        Repository { checksum_objects: true, ..self.clone() }
+    }
+
+    /// This repository with every object read as it is stored, as if
+    /// `GIT_NO_REPLACE_OBJECTS` were set.
+    pub fn without_replacements(&self) -> Repository {
+        Repository { replacements: Arc::default(), ..self.clone() }
+    }
+
+    /// Returns the object to read in place of `sha`, which is `sha` itself
+    /// unless it has been replaced.
+    pub fn replacement(&self, sha: &ObjectId) -> anyhow::Result<ObjectId> {
+        let mut sha = *sha;
+        for _ in 0..MAX_REPLACE_DEPTH {
+            match self.replacements.get(&sha) {
+                Some(replacement) => sha = *replacement,
+                None => return Ok(sha),
+            }
+        }
+
+        Err(anyhow!("Replace depth too high for object {}", sha))
+    }
+
+    fn lock_cache(&self) -> MutexGuard<'_, ObjectCache> {
+        // The cache is only ever a copy of what is on disk, so it is safe
+        // to keep using after a panic elsewhere.
+        self.cache.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Hashes an object of type `kind`, storing it when `write_to_file`
+    /// is set.
+    pub fn write_object(&self, kind: &str, content: &[u8], write_to_file: bool) -> anyhow::Result<ObjectId> {
+        let mut buf = BytesMut::new();
+        buf.write_fmt(format_args!("{} {}", kind, content.len()))?;
+        buf.put_u8(0);
+        buf.put_slice(content);
+
+        let sha1 = self.format.hash(&buf);
+        if let Some(mut memory) = self.lock_memory() {
+            if write_to_file {
+                memory.objects.entry(sha1).or_insert_with(|| (kind.to_string(), content.to_vec()));
+            }
+            return Ok(sha1);
+        }
+
+        // Objects are immutable, so an existing one never needs rewriting.
+        let filename = self.object_path(&sha1);
+        if write_to_file && !self.has_object(&sha1) {
+            trace!(TRACE, "write_object: {} {} {}", sha1, kind, content.len());
+            let directory = filename.parent().expect("Object path has a directory");
+            fs::create_dir_all(directory)?;
+
+            // Write to a temporary file next to the object and rename it into
+            // place, so readers never see a partial object.
+            let (temp_path, file) = create_temp_file(directory, "tmp_obj_")?;
+            let result = (|| -> anyhow::Result<()> {
+                let mut encoder = ZlibEncoder::new(file, self.compression);
+                encoder.write_all(&buf)?;
+                let file = encoder.finish()?;
+                profile::OBJECT_WRITES.add(1);
+                profile::BYTES_DEFLATED.add(buf.len() as u64);
+                profile::FILES_WRITTEN.add(1);
+                self.store_loose(sha1, file, &temp_path, &filename)
+            })();
+            if result.is_err() {
+                let _ = fs::remove_file(&temp_path);
+            }
+            result?;
+        }
+
+        Ok(sha1)
+    }
+
+    /// Copies the loose object `sha` of `source` into the object
+    /// directory as it is stored, after checking it. Returns false if the
+    /// object is here already.
+    pub fn copy_loose_from(&self, source: &Repository, sha: &ObjectId) -> anyhow::Result<bool> {
+        if self.has_object(sha) {
+            return Ok(false);
+        }
+        if self.memory.is_some() {
+            let (kind, content) = source.find_object(sha)?;
+            self.write_object(&kind, &content, true)?;
+            return Ok(true);
+        }
+        source.verify_loose(sha)?;
+        let data = fs::read(source.find_loose(sha).ok_or(anyhow!("Object not found: {}", sha))?)?;
+
+        let filename = self.object_path(sha);
+        let directory = filename.parent().expect("Object path has a directory");
+        fs::create_dir_all(directory)?;
+        let (temp_path, mut file) = create_temp_file(directory, "tmp_obj_")?;
+        let result = (|| -> anyhow::Result<()> {
+            file.write_all(&data)?;
+            self.store_loose(*sha, file, &temp_path, &filename)
+        })();
+        if result.is_err() {
+            let _ = fs::remove_file(&temp_path);
+        }
+        result?;
+        Ok(true)
+    }
+
+    /// Flushes the new loose object `sha`, written to `temp_path`, as
+    /// `core.fsyncMethod` says and moves it to `filename`, or leaves the
+    /// move to the end of the batch being written.
+    fn store_loose(&self, sha: ObjectId, file: fs::File, temp_path: &Path, filename: &Path) -> anyhow::Result<()> {
+        let batched = self.fsync == Some(FsyncMethod::Batch) && self.lock_batch().is_some();
+        match self.fsync {
+            None => {}
+            Some(FsyncMethod::WriteoutOnly) => writeout(&file)?,
+            Some(FsyncMethod::Batch) if batched => writeout(&file)?,
+            Some(_) => file.sync_all()?,
+        }
+        set_read_only(&file)?;
+        drop(file);
+
+        if batched {
+            if let Some(batch) = self.lock_batch().as_mut() {
+                batch.insert(sha, (temp_path.to_path_buf(), filename.to_path_buf()));
+                return Ok(());
+            }
+        }
+        fs::rename(temp_path, filename)?;
+        Ok(())
+    }
+
+    /// Runs `write`, batching the loose objects it writes if
+    /// `core.fsyncMethod` is `batch`. They are written out without
+    /// waiting for the disk to keep them, then flushed with a single
+    /// fsync and moved into place together, with one fsync per object
+    /// directory, so that a crash never leaves a named but empty object.
+    pub fn batch<T>(&self, write: impl FnOnce() -> anyhow::Result<T>) -> anyhow::Result<T> {
+        if self.fsync != Some(FsyncMethod::Batch) || self.lock_batch().is_some() {
+            return write();
+        }
+        *self.lock_batch() = Some(HashMap::new());
+        let result = write();
+        let objects = self.lock_batch().take().unwrap_or_default();
+        if result.is_err() {
+            for (temp_path, _) in objects.values() {
+                let _ = fs::remove_file(temp_path);
+            }
+            return result;
+        }
+        if objects.is_empty() {
+            return result;
+        }
+
+        // On the filesystems batching is for, one fsync flushes the disk
+        // cache of everything already written out.
+        let (flush_path, flush) = create_temp_file(&self.object_dir, "bulk_fsync_")?;
+        flush.sync_all()?;
+        drop(flush);
+        fs::remove_file(&flush_path)?;
+
+        let mut directories = HashSet::new();
+        for (temp_path, filename) in objects.values() {
+            fs::rename(temp_path, filename)?;
+            directories.extend(filename.parent().map(Path::to_path_buf));
+        }
+        #[cfg(unix)]
+        for directory in directories {
+            fs::File::open(directory)?.sync_all()?;
+        }
+        result
+    }
+
+    fn lock_batch(&self) -> MutexGuard<'_, Option<Batch>> {
+        // Like the cache, the pending objects are still accurate after a
+        // panic elsewhere.
+        self.batch.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    pub fn read(&self, sha: &ObjectId) -> anyhow::Result<Object> {
+        let (kind, data) = self.find_object(sha)?;
+        Object::parse(&kind, &data, self.format)
+    }
+
+    /// Reads an object that must be a commit.
+    pub fn read_commit(&self, sha: &ObjectId) -> anyhow::Result<Commit> {
+        match self.read(sha)? {
+            Object::Commit(commit) => Ok(commit),
+            object => Err(anyhow!("Object {} is a {}, not a commit", sha, object.kind())),
+        }
+    }
+
+    /// Reads an object that must be a tree.
+    pub fn read_tree(&self, sha: &ObjectId) -> anyhow::Result<Tree> {
+        match self.read(sha)? {
+            Object::Tree(tree) => Ok(tree),
+            object => Err(anyhow!("Object {} is a {}, not a tree", sha, object.kind())),
+        }
+    }
+
+    /// Maps the path of every file below `tree` to its mode and ID.
+    pub fn tree_files(&self, tree: &ObjectId) -> anyhow::Result<BTreeMap<String, (u32, ObjectId)>> {
+        fn collect(
+            repository: &Repository,
+            tree: &ObjectId,
+            prefix: &str,
+            files: &mut BTreeMap<String, (u32, ObjectId)>,
+        ) -> anyhow::Result<()> {
+            for TreeEntry { mode, name, sha } in repository.read_tree(tree)?.entries {
+                let path = format!("{}{}", prefix, name);
+                if mode == 0o40000 {
+                    collect(repository, &sha, &format!("{}/", path), files)?;
+                } else {
+                    files.insert(path, (mode, sha));
+                }
+            }
+
+            Ok(())
+        }
+
+        let mut files = BTreeMap::new();
+        collect(self, tree, "", &mut files)?;
+        Ok(files)
+    }
+
+    pub fn write(&self, object: &Object) -> anyhow::Result<ObjectId> {
+        self.write_object(object.kind(), &object.serialize(), true)
+    }
+
+    /// Writes a commit of `tree` with `parents`, none for a root commit.
+    pub fn commit(&self, tree: &ObjectId, parents: &[ObjectId], message: &str) -> anyhow::Result<ObjectId> {
+        let config = self.config()?;
+        let commit = Commit {
+            tree: *tree,
+            parents: parents.to_vec(),
+            author: ident::resolve(Role::Author, &config)?.to_string(),
+            committer: ident::resolve(Role::Committer, &config)?.to_string(),
+            extra_headers: Vec::new(),
+            message: format!("{}\n", message),
+        };
+
+        self.write(&Object::Commit(commit))
+    }
+
+    /// What the ref `name` holds, without following it, as
+    /// [`refs::raw_ref_in`] reads it.
+    pub fn raw_ref(&self, name: &str) -> anyhow::Result<Option<String>> {
+        match self.lock_memory() {
+            Some(memory) => Ok(memory.refs.get(name).cloned()),
+            None => refs::raw_ref_in(&self.git_dir, name),
+        }
+    }
+
+    /// Resolves the ref `name` to the object it points at, following
+    /// symbolic refs. Returns `None` for missing and unborn refs.
+    pub fn read_ref(&self, name: &str) -> anyhow::Result<Option<ObjectId>> {
+        refs::follow(name, |name| self.raw_ref(name))
+    }
+
+    /// Points the ref `name` at `sha`.
+    pub fn write_ref(&self, name: &str, sha: &ObjectId) -> anyhow::Result<()> {
+        let Some(mut memory) = self.lock_memory() else {
+            return refs::write_ref_in(&self.git_dir, name, sha);
+        };
+        if !refs::is_valid_ref_name(name, false, false) {
+            return Err(anyhow!("'{}' is not a valid ref name", name));
+        }
+        memory.refs.insert(name.to_string(), sha.to_string());
+        Ok(())
+    }
+
+    /// The names of the refs under `prefix`, such as `refs/heads/`, in
+    /// sorted order.
+    pub fn list_refs(&self, prefix: &str) -> anyhow::Result<Vec<String>> {
+        match self.lock_memory() {
+            Some(memory) => Ok(memory.refs
+                .keys()
+                .filter(|name| name.starts_with("refs/") && name.starts_with(prefix))
+                .cloned()
+                .collect()),
+            None => refs::list_refs_in(&self.git_dir, prefix),
+        }
+    }
+}
+
+/// Loads the replace refs, unless `GIT_NO_REPLACE_OBJECTS` is set.
+fn replacements(git_dir: &Path) -> anyhow::Result<HashMap<ObjectId, ObjectId>> {
+    if std::env::var_os("GIT_NO_REPLACE_OBJECTS").is_some() {
+        return Ok(HashMap::new());
+    }
+
+    let mut replacements = HashMap::new();
+    for name in refs::list_refs_in(git_dir, "refs/replace/")? {
+        let (Ok(original), Some(replacement)) = (
+            name["refs/replace/".len()..].parse(),
+            refs::read_ref_in(git_dir, &name)?,
+        ) else {
+            continue;
+        };
+        replacements.insert(original, replacement);
+    }
+
+    Ok(replacements)
+}
+
+/// Splits the header of a loose object into its type and size, as git
+/// parses it: a known type, and a size in plain decimal without leading
+/// zeros that fits in 64 bits.
+fn parse_loose_header(header: &[u8]) -> Option<(&str, u64)> {
+    let (kind, size) = std::str::from_utf8(header).ok()?.split_once(' ')?;
+    if !matches!(kind, "blob" | "tree" | "commit" | "tag")
+        || size.is_empty()
+        || !size.bytes().all(|b| b.is_ascii_digit())
+        || size.len() > 1 && size.starts_with('0')
+    {
+        return None;
+    }
+    Some((kind, size.parse().ok()?))
+}
+
+fn loose_path(object_dir: &Path, sha: &ObjectId) -> PathBuf {
+    let sha = sha.to_string();
+    object_dir.join(&sha[..2]).join(&sha[2..])
+}
+
+/// The object directory and its alternates. With `environment`,
+/// `GIT_OBJECT_DIRECTORY` overrides the directory, as in a quarantine where
+/// incoming objects are kept apart until they are accepted, and
+/// `GIT_ALTERNATE_OBJECT_DIRECTORIES` adds alternates ahead of those listed
+/// in `info/alternates`.
+fn object_dirs(common_dir: &Path, environment: bool) -> anyhow::Result<(PathBuf, Vec<PathBuf>)> {
+    let from_environment = |name| if environment { std::env::var_os(name) } else { None };
+    let object_dir = match from_environment("GIT_OBJECT_DIRECTORY") {
+        Some(dir) => PathBuf::from(dir),
+        None => common_dir.join("objects"),
+    };
+
+    let mut alternates: Vec<PathBuf> = match from_environment("GIT_ALTERNATE_OBJECT_DIRECTORIES") {
+        Some(dirs) => std::env::split_paths(&dirs).filter(|dir| !dir.as_os_str().is_empty()).collect(),
+        None => Vec::new(),
+    };
+    let mut pending = vec![(object_dir.clone(), 0)];
+    pending.extend(alternates.iter().map(|dir| (dir.clone(), 1)));
+    while let Some((dir, depth)) = pending.pop() {
+        let Ok(content) = fs::read_to_string(dir.join("info/alternates")) else { continue };
+        if depth >= MAX_ALTERNATE_DEPTH {
+            return Err(anyhow!("Alternate object directories nested too deeply: {}", dir.display()));
+        }
+
+        for line in content.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            // Relative alternates are relative to the directory listing them.
+            let alternate = dir.join(line);
+            if alternate != object_dir && !alternates.contains(&alternate) {
+                alternates.push(alternate.clone());
+                pending.push((alternate, depth + 1));
+            }
+        }
+    }
+
+    Ok((object_dir, alternates))
+}
+
+fn object_format(config: &Config) -> anyhow::Result<HashAlgorithm> {
+    match config.get("extensions.objectformat") {
+        Some(name) => HashAlgorithm::from_name(name),
+        None => Ok(HashAlgorithm::Sha1),
+    }
+}
+
+/// The zlib level for loose objects: `core.looseCompression`, falling
+/// back to `core.compression`. Both range from -1 (zlib's default) to 9.
+fn loose_compression(config: &Config) -> anyhow::Result<Compression> {
+    let Some((name, value)) = ["core.loosecompression", "core.compression"]
+        .into_iter()
+        .find_map(|name| config.get(name).map(|value| (name, value))) else {
+        return Ok(Compression::default());
+    };
+
+    match value.trim().parse::<i32>() {
+        Ok(-1) => Ok(Compression::default()),
+        Ok(level @ 0..=9) => Ok(Compression::new(level as u32)),
+        _ => Err(anyhow!("Bad zlib compression level {} for '{}'", value, name)),
+    }
+}
+
+/// `core.checksumObjects`, off by default, as hashing each object read
+/// costs about as much again as inflating it.
+fn checksum_objects(config: &Config) -> anyhow::Result<bool> {
+    match config.get("core.checksumobjects") {
+        Some(value) => config::parse_bool(value).ok_or(anyhow!("Bad core.checksumObjects value: {}", value)),
+        None => Ok(false),
+    }
+}
+
+/// `core.fsyncMethod`: how files are flushed to disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FsyncMethod {
+    /// An fsync for every file, waiting for the disk to keep it.
+    Fsync,
+    /// Only hand the file to the disk, trusting it to keep what it has.
+    WriteoutOnly,
+    /// Hand each file to the disk and flush them together, see
+    /// [`Repository::batch`].
+    Batch,
+}
+
+/// How loose objects are flushed: by `core.fsyncMethod`, `fsync` by
+/// default, or not at all if `core.fsync` leaves them out. Like git,
+/// components listed in `core.fsync` add to the default, which here is
+/// everything, `-component` takes one away and `none` clears the list.
+/// Only `loose-object` and the groups holding it are looked at; every
+/// other file is always synced.
+fn loose_fsync(config: &Config) -> Option<FsyncMethod> {
+    let mut loose = true;
+    for value in config.get_all("core.fsync") {
+        for component in value.split(',').map(str::trim).filter(|component| !component.is_empty()) {
+            let (negated, name) = match component.strip_prefix('-') {
+                Some(name) => (true, name),
+                None => (false, component),
+            };
+            match name {
+                "none" => loose = false,
+                "loose-object" | "objects" | "added" | "committed" | "all" => loose = !negated,
+                "pack" | "pack-metadata" | "commit-graph" | "index" | "reference" | "derived-metadata" => {}
+                _ => eprintln!("warning: ignoring unknown core.fsync component '{}'", component),
+            }
+        }
+    }
+    if !loose {
+        return None;
+    }
+
+    match config.get("core.fsyncmethod") {
+        None | Some("fsync") => Some(FsyncMethod::Fsync),
+        Some("writeout-only") => Some(FsyncMethod::WriteoutOnly),
+        Some("batch") => Some(FsyncMethod::Batch),
+        Some(value) => {
+            eprintln!("warning: ignoring unknown core.fsyncMethod value '{}'", value);
+            Some(FsyncMethod::Fsync)
+        }
+    }
+}
+
+/// Starts writing `file` out to the disk and waits for that, without
+/// asking the disk to flush its cache, where the platform can.
+fn writeout(file: &fs::File) -> anyhow::Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::unix::io::AsRawFd;
+
+        let flags = libc::SYNC_FILE_RANGE_WAIT_BEFORE | libc::SYNC_FILE_RANGE_WRITE | libc::SYNC_FILE_RANGE_WAIT_AFTER;
+        // SAFETY: the descriptor belongs to `file`, which outlives the call.
+        if unsafe { libc::sync_file_range(file.as_raw_fd(), 0, 0, flags) } == 0 {
+            return Ok(());
+        }
+    }
+    Ok(file.sync_data()?)
+}
+
+/// Creates a new file named `prefix` plus a unique suffix in `directory`.
+pub(crate) fn create_temp_file(directory: &Path, prefix: &str) -> anyhow::Result<(PathBuf, fs::File)> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    loop {
+        let name = format!("{}{}_{}", prefix, std::process::id(), COUNTER.fetch_add(1, Ordering::Relaxed));
+        let path = directory.join(name);
+        match fs::OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err.into()),
+        }
+    }
+}
+
+/// Loose objects are read-only, like git makes them.
+fn set_read_only(file: &fs::File) -> anyhow::Result<()> {
+    let mut permissions = file.metadata()?.permissions();
+    permissions.set_readonly(true);
+    file.set_permissions(permissions)?;
+
+    Ok(())
+}
+
+/// Checks whether the filesystem holding `git_dir` keeps the executable
+/// bit, by flipping it on a scratch file like git does at init.
+#[cfg(unix)]
+fn probe_file_mode(git_dir: &Path) -> anyhow::Result<bool> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let probe = git_dir.join("config.probe");
+    fs::write(&probe, "")?;
+    let result = fs::set_permissions(&probe, fs::Permissions::from_mode(0o755))
+        .and_then(|_| fs::metadata(&probe))
+        .map(|metadata| metadata.permissions().mode() & 0o100 != 0);
+    fs::remove_file(&probe)?;
+
+    Ok(result?)
+}
+
+#[cfg(not(unix))]
+fn probe_file_mode(_git_dir: &Path) -> anyhow::Result<bool> {
+    Ok(false)
+}
+
+/// Whether the filesystem holding `git_dir` treats names that differ only
+/// in case as the same file.
+fn probe_ignore_case(git_dir: &Path) -> anyhow::Result<bool> {
+    let probe = git_dir.join("case.probe");
+    fs::write(&probe, "")?;
+    let ignore_case = git_dir.join("CASE.PROBE").exists();
+    fs::remove_file(&probe)?;
+
+    Ok(ignore_case)
+}
+
+/// The template directory `init` copies from: `--template`,
+/// `GIT_TEMPLATE_DIR` or `init.templateDir`, unless it is empty.
+fn template_dir(template: Option<&Path>, config_path: &Path) -> anyhow::Result<Option<PathBuf>> {
+    let template = match template {
+        Some(template) => template.to_path_buf(),
+        None => match std::env::var_os("GIT_TEMPLATE_DIR") {
+            Some(template) => PathBuf::from(template),
+            None => match Config::layered(config_path)?.get("init.templatedir") {
+                Some(template) => match (template.strip_prefix("~/"), std::env::var_os("HOME")) {
+                    (Some(rest), Some(home)) => PathBuf::from(home).join(rest),
+                    _ => PathBuf::from(template),
+                },
+                None => return Ok(None),
+            },
+        },
+    };
+    Ok(Some(template).filter(|template| !template.as_os_str().is_empty()))
+}
+
+/// Copies what `template` holds into `git_dir`, keeping files that are
+/// already there. Like git, names starting with `.` are skipped, and so
+/// is a template whose `config` needs a newer repository format.
+fn copy_template(template: &Path, git_dir: &Path) -> anyhow::Result<()> {
+    if !template.is_dir() {
+        eprintln!("warning: templates not found in {}", template.display());
+        return Ok(());
+    }
+    let version = Config::from_file(&template.join("config"))?
+        .get("core.repositoryformatversion")
+        .map_or(Ok(0), |version| version.parse::<u32>())
+        .unwrap_or(u32::MAX);
+    if version > 1 {
+        eprintln!("warning: not copying templates from '{}': bad config version", template.display());
+        return Ok(());
+    }
+    copy_template_dir(template, git_dir)
+}
+
+fn copy_template_dir(from: &Path, to: &Path) -> anyhow::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        let (source, target) = (entry.path(), to.join(entry.file_name()));
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            copy_template_dir(&source, &target)?;
+        } else if fs::symlink_metadata(&target).is_ok() {
+            continue;
+        } else if file_type.is_symlink() {
+            copy_symlink(&source, &target)?;
+        } else {
+            fs::copy(&source, &target)
+                .map_err(|err| anyhow!("cannot copy '{}' to '{}': {}", source.display(), target.display(), err))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn copy_symlink(source: &Path, target: &Path) -> anyhow::Result<()> {
+    std::os::unix::fs::symlink(fs::read_link(source)?, target)?;
+    Ok(())
+}
+
+/// Without symlinks, what the link points at is copied.
+#[cfg(not(unix))]
+fn copy_symlink(source: &Path, target: &Path) -> anyhow::Result<()> {
+    fs::copy(source, target)?;
+    Ok(())
+}
+
+/// Whether the filesystem holding `git_dir` finds a file created with a
+/// precomposed name under its decomposed one, as on macOS, where names
+/// are read back decomposed.
+fn probe_precompose_unicode(git_dir: &Path) -> anyhow::Result<bool> {
+    let probe = git_dir.join("\u{e4}.probe");
+    fs::write(&probe, "")?;
+    let decomposed = git_dir.join("a\u{308}.probe").exists();
+    fs::remove_file(&probe)?;
+
+    Ok(decomposed)
+}
+
+/// The repository in the current directory. It is opened once per
+/// process, so its object cache is shared by every caller.
+pub fn current() -> anyhow::Result<Repository> {
+    static CURRENT: OnceLock<Repository> = OnceLock::new();
+
+    if let Some(repository) = CURRENT.get() {
+        return Ok(repository.clone());
+    }
+    let repository = Repository::open_with(Path::new("."), true)?;
+    Ok(CURRENT.get_or_init(|| repository).clone())
+}
+
+pub(crate) fn git_dir() -> anyhow::Result<PathBuf> {
+    git_dir_of(Path::new("."))
+}
+
+/// Finds the git directory of the repository whose worktree is `worktree`.
+pub(crate) fn git_dir_of(worktree: &Path) -> anyhow::Result<PathBuf> {
+    let dot_git = worktree.join(".git");
+    if dot_git.is_dir() {
+        return Ok(dot_git);
+    }
+    if !dot_git.exists() {
+        // Inside a bare repository the current directory is the git directory.
+        if worktree.join("HEAD").is_file() && worktree.join("objects").is_dir() {
+            return Ok(worktree.to_path_buf());
+        }
+        return Ok(dot_git);
+    }
+
+    // A `.git` file is a pointer to the real git directory, as used by
+    // linked worktrees and submodules. Relative paths are relative to the
+    // directory containing the `.git` file.
+    let content = fs::read_to_string(&dot_git)?;
+    let gitdir = content.lines()
+        .next()
+        .and_then(|line| line.strip_prefix("gitdir:"))
+        .map(str::trim)
+        .filter(|path| !path.is_empty())
+        .ok_or(anyhow!("Invalid gitfile format: {}", dot_git.display()))?;
+
+    let gitdir = worktree.join(gitdir);
+    if !gitdir.is_dir() {
+        return Err(anyhow!("Not a git repository: {}", gitdir.display()));
+    }
+
+    Ok(gitdir)
+}
+
+pub(crate) fn common_dir() -> anyhow::Result<PathBuf> {
+    common_dir_of(&git_dir()?)
+}
+
+pub(crate) fn common_dir_of(git_dir: &Path) -> anyhow::Result<PathBuf> {
+    let commondir = git_dir.join("commondir");
+    if !commondir.is_file() {
+        return Ok(git_dir.to_path_buf());
+    }
+
+    let content = fs::read_to_string(commondir)?;
+    Ok(git_dir.join(content.trim_end()))
+}
+
+pub(crate) fn repo_config() -> anyhow::Result<Config> {
+    current()?.config()
+}
+
+/// Turns a filesystem path relative to the worktree root into the
+/// slash-separated form used in trees and attribute patterns.
+pub fn worktree_path(path: &Path) -> String {
+    let path = path.strip_prefix(".").unwrap_or(path);
+    path.to_string_lossy().replace('\\', "/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loose_headers() {
+        assert_eq!(parse_loose_header(b"blob 12"), Some(("blob", 12)));
+        assert_eq!(parse_loose_header(b"tree 0"), Some(("tree", 0)));
+        for header in [&b"blob"[..], b"blob ", b"blob 012", b"blob -1", b"blob 1 ", b"blob 0x10", b"tre 1", b"\xff 1"] {
+            assert_eq!(parse_loose_header(header), None, "{:?}", String::from_utf8_lossy(header));
+        }
+        // One past the largest 64-bit number.
+        assert_eq!(parse_loose_header(b"blob 18446744073709551616"), None);
+    }
+
+    #[test]
+    fn malformed_loose_objects_are_errors() {
+        let dir = std::env::temp_dir().join(format!("git-starter-rust-loose-{}", std::process::id()));
+        let repository = Repository::init_module(&dir, HashAlgorithm::Sha1).unwrap();
+        let deflate = |data: &[u8]| {
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::fast());
+            encoder.write_all(data).unwrap();
+            encoder.finish().unwrap()
+        };
+        let store = |name: &str, data: &[u8]| {
+            let sha = HashAlgorithm::Sha1.hash(name.as_bytes());
+            let path = repository.object_path(&sha);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, data).unwrap();
+            sha
+        };
+
+        let good = store("good", &deflate(b"blob 5\0hello"));
+        // An object inflating to far more than its header says is cut
+        // off there rather than inflated whole.
+        let bomb = store("bomb", &deflate(&[b"blob 1\0".as_slice(), &vec![0; 8 << 20]].concat()));
+        let short = store("short", &deflate(b"blob 10\0hello"));
+        let huge = store("huge", &deflate(b"blob 18446744073709551615\0hello"));
+        let no_nul = store("no nul", &deflate(&[b'a'; 1000]));
+        let full = deflate(b"blob 5\0hello");
+        let truncated = store("truncated", &full[..full.len() - 6]);
+        let garbage = store("garbage", b"this is not zlib at all");
+        let empty = store("empty", b"");
+
+        let read = |sha: &ObjectId| repository.find_object(sha).map_err(|err| err.to_string());
+        let results: Vec<_> = [&bomb, &short, &huge, &no_nul, &truncated, &garbage, &empty].map(read).into();
+        let good = read(&good);
+        let headers = [&huge, &no_nul, &garbage].map(|sha| repository.object_header(sha).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(good, Ok(("blob".to_string(), b"hello".to_vec())));
+        for result in &results {
+            assert!(result.is_err(), "{:?}", result);
+        }
+        assert!(results[0].as_ref().unwrap_err().contains("longer than the 1 bytes"));
+        assert!(results[1].as_ref().unwrap_err().contains("has 5 bytes"));
+        assert_eq!(headers, [false, true, true]);
+    }
+
+    #[test]
+    fn in_memory_repositories() {
+        let repository = Repository::in_memory();
+        let blob = repository.write(&Object::Blob(b"hello\n".to_vec())).unwrap();
+        assert_eq!(blob.to_string(), "ce013625030ba8dba906f756967f9e9ca394464a");
+        let entry = TreeEntry { mode: 0o100644, name: "hello.txt".to_string(), sha: blob };
+        let tree = repository.write(&Object::Tree(Tree { entries: vec![entry] })).unwrap();
+        let commit = repository.commit(&tree, &[], "Add hello").unwrap();
+
+        assert_eq!(repository.read_ref("HEAD").unwrap(), None);
+        repository.write_ref("refs/heads/main", &commit).unwrap();
+        assert!(repository.write_ref("main", &commit).is_err());
+        assert_eq!(repository.read_ref("HEAD").unwrap(), Some(commit));
+        assert_eq!(repository.raw_ref("HEAD").unwrap().as_deref(), Some("ref: refs/heads/main"));
+        assert_eq!(repository.list_refs("refs/heads/").unwrap(), ["refs/heads/main"]);
+
+        assert_eq!(repository.read_commit(&commit).unwrap().tree, tree);
+        assert_eq!(repository.tree_files(&tree).unwrap().into_keys().collect::<Vec<_>>(), ["hello.txt"]);
+        assert_eq!(repository.object_header(&blob).unwrap(), ("blob".to_string(), 6));
+        assert_eq!(repository.loose_objects().unwrap().len(), 3);
+        assert!(repository.without_replacements().has_object(&tree));
+        assert!(repository.packs().is_empty());
+
+        repository.set_config("user.name", "A U Thor").unwrap();
+        assert_eq!(repository.config().unwrap().get("user.name"), Some("A U Thor"));
+        let other = Repository::in_memory();
+        assert!(!other.has_object(&blob) && other.read(&blob).is_err());
+        assert_eq!(other.config().unwrap().get("user.name"), None);
+    }
+}
@@ -0,0 +1,252 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::anyhow;
+
+use crate::date;
+use crate::diff::{self, TreeDiff};
+use crate::error::Error;
+use crate::mailmap::{split_ident, Mailmap};
+use crate::object_id::ObjectId;
+use crate::objects::{ancestors, peel_to_tree, Commit, Object};
+use crate::refs;
+use crate::repository::{self, git_dir_of, Repository};
+use crate::rev_list::RevisionSet;
+
+/// A stand-in for git's ls-remote: the refs of the repository at `url`,
+/// which has to be local, with annotated tags also listed peeled as
+/// `<name>^{}`.
+fn remote_refs(url: &str) -> anyhow::Result<Vec<(ObjectId, String)>> {
+    let path = Path::new(url.strip_prefix("file://").unwrap_or(url));
+    let git_dir = git_dir_of(path)?;
+    if !git_dir.is_dir() {
+        return Err(anyhow!("'{}' does not appear to be a git repository", url));
+    }
+    let remote = Repository::open(path)?;
+
+    let mut listed = Vec::new();
+    for name in std::iter::once("HEAD".to_string()).chain(refs::list_refs_in(&git_dir, "refs/")?) {
+        let Some(mut sha) = refs::read_ref_in(&git_dir, &name)? else {
+            continue;
+        };
+        listed.push((sha, name.clone()));
+        let mut peeled = false;
+        while let Ok(Object::Tag(tag)) = remote.read(&sha) {
+            sha = tag.object;
+            peeled = true;
+        }
+        if peeled {
+            listed.push((sha, format!("{}^{{}}", name)));
+        }
+    }
+
+    Ok(listed)
+}
+
+/// The ref at the remote named like `remote` that points at `head`
+/// (peeled to `head_commit`), and the object the remote has for that
+/// name, as git's request-pull looks them up.
+fn find_remote_ref(
+    refs: &[(ObjectId, String)],
+    remote: &str,
+    head: &ObjectId,
+    head_commit: &ObjectId,
+) -> Option<(ObjectId, String)> {
+    let mut remote_sha = None;
+    for (sha, name) in refs {
+        let (name, peeled) = match name.strip_suffix("^{}") {
+            Some(name) => (name, true),
+            None => (name.as_str(), false),
+        };
+        if name == remote || name.ends_with(&format!("/{}", remote)) {
+            if !peeled {
+                remote_sha = Some(*sha);
+            }
+            if sha == head_commit {
+                return Some((remote_sha.unwrap_or(*head), name.to_string()));
+            }
+        }
+    }
+    None
+}
+
+/// The newest commit reachable from both `a` and `b`.
+fn merge_base(a: &ObjectId, b: &ObjectId) -> anyhow::Result<Option<ObjectId>> {
+    let reachable = ancestors(a)?;
+    let history = RevisionSet { include: vec![*b], ..RevisionSet::default() }.walk()?;
+    Ok(history.into_iter().find(|commit| reachable.contains(commit)))
+}
+
+
+/// `<subject> (<committer date>)`, as git's `%s (%ci)`.
+fn describe(commit: &Commit) -> String {
+    let date = split_ident(&commit.committer)
+        .and_then(|(_, _, time)| date::parse(time).ok())
+        .map(|(time, offset)| date::format_iso(time, offset))
+        .unwrap_or_default();
+    format!("{} ({})", commit.subject(), date)
+}
+
+/// The commits in `commits`, listed oldest first under their authors'
+/// names, like `git shortlog`.
+fn shortlog(repository: &Repository, commits: &[ObjectId], out: &mut Vec<u8>) -> anyhow::Result<()> {
+    let mailmap = Mailmap::load(None)?;
+    let mut authors: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for sha in commits.iter().rev() {
+        let commit = repository.read_commit(sha)?;
+        let (name, email, _) = split_ident(&commit.author).ok_or(anyhow!("Invalid author in commit {}", sha))?;
+        let (name, _) = mailmap.lookup(name.unwrap_or_default(), email);
+        authors.entry(name.to_string()).or_default().push(commit.subject());
+    }
+
+    for (author, subjects) in authors {
+        writeln!(out, "{} ({}):", author, subjects.len())?;
+        for subject in subjects {
+            writeln!(out, "      {}", subject)?;
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+/// Finds what `local`, a branch, tag or other revision, names: the full
+/// ref name where there is one.
+fn resolve_head(local: &str) -> anyhow::Result<(String, ObjectId)> {
+    let symbolic = refs::raw_ref(local)
+        .ok()
+        .flatten()
+        .and_then(|content| content.strip_prefix("ref:").map(|target| target.trim().to_string()));
+    if let Some(target) = symbolic {
+        if let Some(sha) = refs::read_ref(&target)? {
+            return Ok((target, sha));
+        }
+    }
+
+    for prefix in ["refs/heads/", "refs/tags/"] {
+        let matching: Vec<String> = refs::list_refs(prefix)?
+            .into_iter()
+            .filter(|name| name == &format!("{}{}", prefix, local) || name.ends_with(&format!("/{}", local)))
+            .collect();
+        if let [name] = matching.as_slice() {
+            if let Some(sha) = refs::read_ref(name)? {
+                return Ok((name.clone(), sha));
+            }
+        }
+    }
+
+    let sha = refs::resolve_revision(local).map_err(|_| anyhow!("Not a valid revision: {}", local))?;
+    Ok((local.to_string(), sha))
+}
+
+#[derive(Debug, Default)]
+pub struct RequestOptions {
+    /// Include the patch after the diffstat (`-p`).
+    pub patch: bool,
+}
+
+/// Prints a request to pull the commits from `base` up to `end` from
+/// `url`, for mailing to a maintainer: where they start and end, the
+/// tag message or branch description, a shortlog and a diffstat. `end`
+/// is `<local>[:<remote>]` and defaults to `HEAD`. Warns, and fails with
+/// status 1, if the remote doesn't have the commits.
+pub fn run(base: &str, url: &str, end: Option<&str>, options: &RequestOptions) -> anyhow::Result<()> {
+    let repository = repository::current()?;
+    let base_commit = refs::resolve_revision(base)
+        .and_then(|sha| peel_to_tree(&sha))
+        .map_err(|_| anyhow!("Not a valid revision: {}", base))?
+        .0;
+
+    let end = end.unwrap_or_default();
+    let local = match end.rsplit_once(':') {
+        Some((local, _)) => local,
+        None => end,
+    };
+    let local = if local.is_empty() { "HEAD" } else { local };
+    let remote = end.split_once(':').map_or(end, |(_, remote)| remote);
+    let mut pretty_remote = remote.strip_prefix("refs/").unwrap_or(remote);
+    pretty_remote = pretty_remote.strip_prefix("heads/").unwrap_or(pretty_remote);
+
+    let (head, head_sha) = resolve_head(local)?;
+    let head_commit = peel_to_tree(&head_sha).map_err(|_| anyhow!("Ambiguous revision: {}", local))?.0;
+    let config = repository.config()?;
+    let description = head
+        .strip_prefix("refs/heads/")
+        .and_then(|branch| Some((branch, config.get(&format!("branch.{}.description", branch))?)));
+
+    let merge_base = merge_base(&base_commit, &head_commit)?
+        .ok_or(anyhow!("No commits in common between {} and {}", base, head))?;
+
+    let mut succeeded = true;
+    let remote_name = if remote.is_empty() { "HEAD" } else { remote };
+    let found = match remote_refs(url) {
+        Ok(refs) => find_remote_ref(&refs, remote_name, &head_sha, &head_commit),
+        Err(err) => {
+            eprintln!("fatal: {}", err);
+            None
+        }
+    };
+    match &found {
+        None => {
+            eprintln!("warn: No match for commit {} found at {}", head_commit, url);
+            eprintln!("warn: Are you sure you pushed '{}' there?", remote_name);
+            succeeded = false;
+        }
+        Some((remote_sha, _)) if *remote_sha != head_sha => {
+            eprintln!("warn: {} found at {} but points to a different object", head, url);
+            eprintln!("warn: Are you sure you pushed '{}' there?", remote_name);
+            succeeded = false;
+        }
+        Some(_) => {}
+    }
+    let tag_remote;
+    if found.as_ref().is_some_and(|(_, name)| *name == format!("refs/tags/{}", pretty_remote)) {
+        tag_remote = format!("tags/{}", pretty_remote);
+        pretty_remote = &tag_remote;
+    }
+    let url = config.get(&format!("remote.{}.url", url)).unwrap_or(url);
+
+    let mut out = Vec::new();
+    let base = repository.read_commit(&merge_base)?;
+    let tip = repository.read_commit(&head_commit)?;
+    writeln!(out, "The following changes since commit {}:\n\n  {}\n", merge_base, describe(&base))?;
+    writeln!(out, "are available in the Git repository at:\n\n  {} {}\n", url, pretty_remote)?;
+    writeln!(out, "for you to fetch changes up to {}:\n\n  {}\n", head_commit, describe(&tip))?;
+    writeln!(out, "{}", "-".repeat(64))?;
+
+    if let Object::Tag(tag) = repository.read(&head_sha)? {
+        for line in tag.message.lines() {
+            if ["-----BEGIN PGP ", "-----BEGIN SSH ", "-----BEGIN SIGNED "].iter().any(|start| line.starts_with(start)) {
+                break;
+            }
+            writeln!(out, "{}", line)?;
+        }
+        writeln!(out, "\n{}", "-".repeat(64))?;
+    }
+    if let Some((branch, description)) = description {
+        writeln!(out, "(from the branch description for {} local branch)\n", branch)?;
+        write!(out, "{}", description)?;
+        if !description.ends_with('\n') {
+            writeln!(out)?;
+        }
+        writeln!(out, "{}", "-".repeat(64))?;
+    }
+
+    let commits = RevisionSet { include: vec![head_commit], exclude: vec![base_commit], ..RevisionSet::default() }.walk()?;
+    shortlog(&repository, &commits, &mut out)?;
+
+    let changes = TreeDiff::new(&repository, &base.tree, &tip.tree, true)?;
+    let width = std::env::var("COLUMNS").ok().and_then(|columns| columns.parse().ok()).unwrap_or(80);
+    changes.write_stat(width, &mut out);
+    changes.write_summary(&mut out);
+    if options.patch && !changes.is_empty() {
+        out.push(b'\n');
+        changes.write_patch(diff::DEFAULT_CONTEXT, None, &mut out);
+    }
+    std::io::stdout().lock().write_all(&out)?;
+
+    if !succeeded {
+        return Err(Error::Status(1).into());
+    }
+    Ok(())
+}
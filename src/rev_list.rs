@@ -0,0 +1,365 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+use anyhow::anyhow;
+
+use crate::ident::identity_time;
+use crate::object_id::ObjectId;
+use crate::date;
+use crate::diff::TreeDiff;
+use crate::objects::{ancestors, peel_to_tree, Commit};
+use crate::refs;
+use crate::regex::Regex;
+use crate::repository::{self, Repository};
+
+/// The commits selected by rev-list style arguments: those reachable
+/// from an included revision but not from an excluded one.
+#[derive(Debug, Default)]
+pub struct RevisionSet {
+    pub include: Vec<ObjectId>,
+    pub exclude: Vec<ObjectId>,
+    /// The left sides of `A...B` ranges, for telling which side a commit
+    /// came from.
+    pub left: Vec<ObjectId>,
+    /// Mark commits with `<` or `>` for the side of a symmetric range.
+    pub left_right: bool,
+    /// Only the commits on the left, or the right, side of a symmetric
+    /// range.
+    pub left_only: bool,
+    pub right_only: bool,
+    /// Leave out the commits of a symmetric range whose change a commit
+    /// on the other side makes too, judged by patch ID.
+    pub cherry_pick: bool,
+    /// Mark those commits with `=` instead, and the others with `+`.
+    pub cherry_mark: bool,
+    pub no_merges: bool,
+    /// Print the number of commits instead of listing them.
+    pub count: bool,
+    /// Only commits made at or after this time.
+    pub since: Option<i64>,
+    /// Only commits made at or before this time.
+    pub until: Option<i64>,
+    /// Only commits whose author line matches one of these patterns.
+    pub author: Vec<String>,
+    /// Only commits whose message matches one of these patterns.
+    pub grep: Vec<String>,
+    pub ignore_case: bool,
+}
+
+impl RevisionSet {
+    /// Parses revisions such as `main`, `^old`, `A..B` and `A...B`, and the
+    /// options `--not`, which flips whether the following revisions are
+    /// included, `--all`, `--branches`, `--tags`, `--remotes`,
+    /// `--left-right`, `--left-only`, `--right-only`, `--cherry-pick`,
+    /// `--cherry-mark`, `--cherry` (for `--right-only --cherry-mark
+    /// --no-merges`), `--count`, and the filters `--no-merges`,
+    /// `--since`/`--after`, `--until`/`--before`, `--author`, `--grep` and
+    /// `-i`. Patterns are basic regular expressions.
+    pub fn parse(args: &[String]) -> anyhow::Result<RevisionSet> {
+        let mut revisions = RevisionSet::default();
+        let mut not = false;
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if let Some((option, value)) = split_value(arg, &mut args)? {
+                match option {
+                    "--since" | "--after" => revisions.since = Some(date::approxidate(&value)?),
+                    "--until" | "--before" => revisions.until = Some(date::approxidate(&value)?),
+                    "--author" => revisions.author.push(value),
+                    _ => revisions.grep.push(value),
+                }
+                continue;
+            }
+
+            match arg.as_str() {
+                "--not" => not = !not,
+                "--all" => {
+                    revisions.add_refs("refs/", not)?;
+                    if let Some(head) = refs::read_ref("HEAD")? {
+                        revisions.add(head, not)?;
+                    }
+                }
+                "--branches" => revisions.add_refs("refs/heads/", not)?,
+                "--tags" => revisions.add_refs("refs/tags/", not)?,
+                "--remotes" => revisions.add_refs("refs/remotes/", not)?,
+                "--left-right" => revisions.left_right = true,
+                "--left-only" => revisions.left_only = true,
+                "--right-only" => revisions.right_only = true,
+                "--cherry-pick" => revisions.cherry_pick = true,
+                "--cherry-mark" => revisions.cherry_mark = true,
+                "--cherry" => {
+                    revisions.right_only = true;
+                    revisions.cherry_mark = true;
+                    revisions.no_merges = true;
+                }
+                "--no-merges" => revisions.no_merges = true,
+                "--count" => revisions.count = true,
+                "-i" | "--regexp-ignore-case" => revisions.ignore_case = true,
+                arg if arg.starts_with("--") => return Err(anyhow!("Unrecognized argument: {}", arg)),
+                arg => revisions.add_revision(arg, not)?,
+            }
+        }
+
+        Ok(revisions)
+    }
+
+    fn add_revision(&mut self, arg: &str, not: bool) -> anyhow::Result<()> {
+        if let Some((left, right)) = arg.split_once("...") {
+            let left = resolve(left)?;
+            let right = resolve(right)?;
+            // Everything reachable from both sides, and so from their
+            // merge bases, is left out.
+            let left_commits = ancestors(&left)?;
+            let right_commits = ancestors(&right)?;
+            self.add(left, not)?;
+            self.add(right, not)?;
+            self.exclude.extend(left_commits.intersection(&right_commits).copied());
+            self.left.push(left);
+        } else if let Some((from, to)) = arg.split_once("..") {
+            let from = resolve(from)?;
+            let to = resolve(to)?;
+            self.add(from, !not)?;
+            self.add(to, not)?;
+        } else if let Some(revision) = arg.strip_prefix('^') {
+            self.add(resolve(revision)?, !not)?;
+        } else {
+            self.add(resolve(arg)?, not)?;
+        }
+
+        Ok(())
+    }
+
+    fn add_refs(&mut self, prefix: &str, not: bool) -> anyhow::Result<()> {
+        for name in refs::list_refs(prefix)? {
+            if let Some(sha) = refs::read_ref(&name)? {
+                // Refs to trees or blobs have no history.
+                if let Ok((commit, _)) = peel_to_tree(&sha) {
+                    self.add(commit, not)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn add(&mut self, sha: ObjectId, exclude: bool) -> anyhow::Result<()> {
+        let (commit, _) = peel_to_tree(&sha)?;
+        if exclude {
+            self.exclude.push(commit);
+        } else {
+            self.include.push(commit);
+        }
+
+        Ok(())
+    }
+
+    /// Lists the selected commits newest first by committer date, the way
+    /// git walks history by default.
+    pub fn walk(&self) -> anyhow::Result<Vec<ObjectId>> {
+        Ok(self.walk_marked()?.into_iter().map(|(sha, _)| sha).collect())
+    }
+
+    /// Lists the selected commits like [`walk`], each with the mark git
+    /// shows before it: `=` with `--cherry-mark` for a change the other
+    /// side of a symmetric range makes too, else `<` or `>` for its side
+    /// with `--left-right`, else `+` with `--cherry-mark`.
+    ///
+    /// [`walk`]: RevisionSet::walk
+    pub fn walk_marked(&self) -> anyhow::Result<Vec<(ObjectId, &'static str)>> {
+        let commits = self.walk_all()?;
+        let symmetric = !self.left.is_empty();
+        let mut left = HashSet::new();
+        if symmetric && (self.left_right || self.left_only || self.right_only || self.cherry_pick || self.cherry_mark) {
+            for sha in &self.left {
+                left.extend(ancestors(sha)?);
+            }
+        }
+        let same = match symmetric && (self.cherry_pick || self.cherry_mark) {
+            true => patch_same(&commits.iter().map(|(sha, _)| *sha).collect::<Vec<_>>(), &left)?,
+            false => HashSet::new(),
+        };
+
+        let marked = commits
+            .into_iter()
+            .filter(|(sha, selected)| {
+                *selected
+                    && !(self.cherry_pick && !self.cherry_mark && same.contains(sha))
+                    && !(symmetric && self.left_only && !left.contains(sha))
+                    && !(symmetric && self.right_only && left.contains(sha))
+            })
+            .map(|(sha, _)| {
+                let mark = match (same.contains(&sha), self.left_right && symmetric, left.contains(&sha)) {
+                    (true, _, _) => "=",
+                    (false, true, true) => "<",
+                    (false, true, false) => ">",
+                    (false, false, _) if self.cherry_mark => "+",
+                    (false, false, _) => "",
+                };
+                (sha, mark)
+            })
+            .collect();
+        Ok(marked)
+    }
+
+    /// Every commit of the walk, with whether the filters select it. The
+    /// others still count for `--cherry-pick`, as in git.
+    fn walk_all(&self) -> anyhow::Result<Vec<(ObjectId, bool)>> {
+        let repository = repository::current()?;
+        let patterns = |patterns: &[String]| {
+            patterns.iter()
+                .map(|pattern| {
+                    Regex::new(pattern, false, self.ignore_case)
+                        .ok_or(anyhow!("Invalid regular expression: {}", pattern))
+                })
+                .collect::<anyhow::Result<Vec<Regex>>>()
+        };
+        let (author, grep) = (patterns(&self.author)?, patterns(&self.grep)?);
+
+        let mut excluded = HashSet::new();
+        for sha in &self.exclude {
+            if !excluded.contains(sha) {
+                excluded.extend(ancestors(sha)?);
+            }
+        }
+
+        let mut seen = HashSet::new();
+        let mut queue = BinaryHeap::new();
+        let mut order = 0;
+        for sha in &self.include {
+            if seen.insert(*sha) {
+                queue.push(Queued::new(&repository, *sha, &mut order)?);
+            }
+        }
+
+        let mut commits = Vec::new();
+        while let Some(Queued { sha, commit, .. }) = queue.pop() {
+            if excluded.contains(&sha) {
+                continue;
+            }
+            commits.push((sha, self.selects(&commit, &author, &grep)));
+            for parent in commit.parents {
+                if seen.insert(parent) && !excluded.contains(&parent) {
+                    queue.push(Queued::new(&repository, parent, &mut order)?);
+                }
+            }
+        }
+
+        Ok(commits)
+    }
+
+    /// Whether `commit` passes the date filters and the compiled `author`
+    /// and `grep` patterns. Filtered-out commits are still walked through.
+    fn selects(&self, commit: &Commit, author: &[Regex], grep: &[Regex]) -> bool {
+        let time = identity_time(&commit.committer);
+        let matches = |patterns: &[Regex], text: &str| patterns.iter().any(|regex| regex.is_match(text.as_bytes()));
+
+        self.since.unwrap_or(i64::MIN) <= time
+            && time <= self.until.unwrap_or(i64::MAX)
+            && !(self.no_merges && commit.parents.len() > 1)
+            && (author.is_empty() || matches(author, &commit.author))
+            && (grep.is_empty() || commit.message.lines().any(|line| matches(grep, line)))
+    }
+}
+
+/// Those of `commits` whose patch ID that of a commit on the other side
+/// matches, the sides being the commits in `left` and the rest. Merges
+/// have no patch ID.
+fn patch_same(commits: &[ObjectId], left: &HashSet<ObjectId>) -> anyhow::Result<HashSet<ObjectId>> {
+    let repository = repository::current()?;
+    let mut ids = Vec::new();
+    let mut sides: HashMap<ObjectId, [bool; 2]> = HashMap::new();
+    for sha in commits {
+        let commit = repository.read_commit(sha)?;
+        let changes = match commit.parents[..] {
+            [] => TreeDiff::root(&repository, &commit.tree)?,
+            [parent] => TreeDiff::new(&repository, &repository.read_commit(&parent)?.tree, &commit.tree, false)?,
+            _ => continue,
+        };
+        let id = changes.patch_id(repository.object_format());
+        let side = left.contains(sha) as usize;
+        sides.entry(id).or_default()[side] = true;
+        ids.push((*sha, id, side));
+    }
+    Ok(ids.into_iter().filter(|(_, id, side)| sides[id][1 - side]).map(|(sha, _, _)| sha).collect())
+}
+
+/// Splits a filter option from its value, given either as `--since=<date>`
+/// or as the following argument.
+fn split_value<'a>(
+    arg: &'a str,
+    args: &mut std::slice::Iter<String>,
+) -> anyhow::Result<Option<(&'a str, String)>> {
+    const OPTIONS: [&str; 6] = ["--since", "--after", "--until", "--before", "--author", "--grep"];
+
+    if let Some((option, value)) = arg.split_once('=') {
+        if OPTIONS.contains(&option) {
+            return Ok(Some((option, value.to_string())));
+        }
+    } else if OPTIONS.contains(&arg) {
+        let value = args.next().ok_or(anyhow!("Option {} requires a value", arg))?;
+        return Ok(Some((arg, value.clone())));
+    }
+
+    Ok(None)
+}
+
+/// A commit waiting in the walk, ordered by date and, among commits with
+/// the same date, by when it was queued.
+struct Queued {
+    sha: ObjectId,
+    commit: Commit,
+    time: i64,
+    order: usize,
+}
+
+impl Queued {
+    fn new(repository: &Repository, sha: ObjectId, order: &mut usize) -> anyhow::Result<Queued> {
+        let commit = repository.read_commit(&sha)?;
+        *order += 1;
+
+        let time = identity_time(&commit.committer);
+        Ok(Queued { sha, commit, time, order: *order })
+    }
+}
+
+impl Ord for Queued {
+    fn cmp(&self, other: &Queued) -> Ordering {
+        self.time.cmp(&other.time).then(other.order.cmp(&self.order))
+    }
+}
+
+impl PartialOrd for Queued {
+    fn partial_cmp(&self, other: &Queued) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Queued {
+    fn eq(&self, other: &Queued) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Queued {}
+
+/// An empty side of a range, as in `main..`, stands for `HEAD`.
+fn resolve(revision: &str) -> anyhow::Result<ObjectId> {
+    refs::resolve_revision(if revision.is_empty() { "HEAD" } else { revision })
+}
+
+/// Prints the commits selected by `args`, one per line.
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    let revisions = RevisionSet::parse(args)?;
+    let commits = revisions.walk_marked()?;
+
+    if revisions.count {
+        println!("{}", commits.len());
+        return Ok(());
+    }
+
+    for (sha, mark) in commits {
+        println!("{}{}", mark, sha);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,317 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use anyhow::anyhow;
+
+use crate::fast_export::signature_start;
+use crate::info;
+use crate::maintenance;
+use crate::object_id::ObjectId;
+use crate::objects::{tree_files, write_tree_files, Commit, Object, Tag};
+use crate::operation::Operation;
+use crate::refs;
+use crate::repository::{self, Repository};
+use crate::worktree;
+
+/// Namespaces whose refs are rewritten. Notes and replacements describe
+/// the old commits and are left alone.
+const REWRITTEN_REFS: [&str; 3] = ["refs/heads/", "refs/tags/", "refs/remotes/"];
+
+/// Headers dropped from rewritten commits, since the signatures in them
+/// no longer match.
+const SIGNATURE_HEADERS: [&str; 2] = ["gpgsig", "gpgsig-sha256"];
+
+pub struct RewriteOptions {
+    /// Only these paths (or everything below these directories) are kept.
+    pub paths: Vec<String>,
+    /// `(old, new)` path prefixes, applied after `paths`.
+    pub renames: Vec<(String, String)>,
+    /// Blobs larger than this many bytes are removed.
+    pub max_blob_size: Option<u64>,
+    /// A shell command that gets each commit message on stdin and prints
+    /// the message to use instead.
+    pub message_callback: Option<String>,
+}
+
+/// Rewrites every commit reachable from the branches, tags and
+/// remote-tracking refs according to `options`, then points the refs at
+/// the new commits and updates the worktree if HEAD was rewritten.
+/// Commits that end up changing nothing are dropped, unless they were
+/// empty to begin with. The old and new ID of each commit is saved in
+/// `rewrite-history/commit-map`.
+pub fn run(options: &RewriteOptions) -> anyhow::Result<()> {
+    let repository = repository::current()?;
+    // Rewriting under an operation would leave it pointing at old commits.
+    Operation::ensure_none(repository.git_dir(), None)?;
+    let mut rewriter = Rewriter {
+        repository,
+        options,
+        commits: HashMap::new(),
+        blob_sizes: HashMap::new(),
+    };
+
+    let mut tips = BTreeMap::new();
+    for name in refs::list_refs("refs/")? {
+        if !REWRITTEN_REFS.iter().any(|prefix| name.starts_with(prefix)) {
+            continue;
+        }
+        if let Some(sha) = refs::read_ref(&name)? {
+            tips.insert(name, sha);
+        }
+    }
+
+    let mut order = Vec::new();
+    rewriter.walk(tips.values(), &mut order)?;
+    let mut commit_map = String::new();
+    for sha in &order {
+        let new = rewriter.rewrite(sha)?;
+        let new = new.unwrap_or(rewriter.repository.object_format().null());
+        commit_map.push_str(&format!("{} {}\n", sha, new));
+    }
+
+    let head = refs::read_ref("HEAD")?;
+    let mut deleted = Vec::new();
+    for (name, sha) in &tips {
+        match rewriter.rewrite_ref(sha)? {
+            Some(new) if new == *sha => {}
+            Some(new) => {
+                refs::write_ref(name, &new)?;
+                info!("Ref '{}' was rewritten", name);
+            }
+            None => {
+                deleted.push(name.clone());
+                info!("Ref '{}' was deleted", name);
+            }
+        }
+    }
+    refs::delete_refs(&deleted)?;
+
+    let map_dir = rewriter.repository.git_dir().join("rewrite-history");
+    fs::create_dir_all(&map_dir)?;
+    fs::write(map_dir.join("commit-map"), commit_map)?;
+    maintenance::run_auto(false)?;
+
+    // Leave the worktree matching the rewritten HEAD, with ORIG_HEAD to
+    // go back to.
+    let Some(head) = head else { return Ok(()) };
+    let branch = refs::current_branch()?;
+    let new = match &branch {
+        Some(branch) => refs::read_ref(&format!("refs/heads/{}", branch))?,
+        None => rewriter.commits.get(&head).copied().flatten(),
+    };
+    match new {
+        Some(new) if new != head => {
+            let mut transaction = refs::Transaction::default();
+            transaction.add(refs::RefUpdate {
+                name: "ORIG_HEAD".to_string(),
+                new: Some(head),
+                old: None,
+                no_deref: true,
+            })?;
+            transaction.commit()?;
+            worktree::checkout(&new, branch.as_deref())
+        }
+        _ => Ok(()),
+    }
+}
+
+struct Rewriter<'a> {
+    repository: Repository,
+    options: &'a RewriteOptions,
+    /// The new ID of each rewritten commit, or the new ID of its nearest
+    /// kept ancestor if it was dropped, or `None` if none is left.
+    commits: HashMap<ObjectId, Option<ObjectId>>,
+    blob_sizes: HashMap<ObjectId, u64>,
+}
+
+impl Rewriter<'_> {
+    /// Appends the commits reachable from `tips` to `order`, parents first.
+    fn walk<'t>(&self, tips: impl Iterator<Item = &'t ObjectId>, order: &mut Vec<ObjectId>) -> anyhow::Result<()> {
+        let mut seen = HashSet::new();
+        let mut pending: Vec<(ObjectId, bool)> = Vec::new();
+
+        for tip in tips {
+            let Some(commit) = self.peel(tip)? else { continue };
+            pending.push((commit, false));
+
+            while let Some((sha, parents_done)) = pending.pop() {
+                if parents_done {
+                    order.push(sha);
+                    continue;
+                }
+                if !seen.insert(sha) {
+                    continue;
+                }
+                pending.push((sha, true));
+                let commit = self.repository.read_commit(&sha)?;
+                pending.extend(commit.parents.iter().rev().map(|parent| (*parent, false)));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Follows tags down to a commit, if there is one.
+    fn peel(&self, sha: &ObjectId) -> anyhow::Result<Option<ObjectId>> {
+        let mut sha = *sha;
+        loop {
+            match self.repository.read(&sha)? {
+                Object::Tag(tag) => sha = tag.object,
+                Object::Commit(_) => return Ok(Some(sha)),
+                _ => return Ok(None),
+            }
+        }
+    }
+
+    /// Writes the rewritten version of `sha`, whose parents have already
+    /// been rewritten, and returns what it maps to.
+    fn rewrite(&mut self, sha: &ObjectId) -> anyhow::Result<Option<ObjectId>> {
+        let commit = self.repository.read_commit(sha)?;
+
+        let mut parents: Vec<ObjectId> = Vec::new();
+        for parent in &commit.parents {
+            if let Some(parent) = self.commits[parent] {
+                if !parents.contains(&parent) {
+                    parents.push(parent);
+                }
+            }
+        }
+
+        let tree = self.rewrite_tree(&commit.tree)?;
+        let was_empty = match commit.parents.first() {
+            Some(parent) => self.repository.read_commit(parent)?.tree == commit.tree,
+            None => tree_files(&commit.tree)?.is_empty(),
+        };
+        let parent_tree = match parents.first() {
+            Some(parent) => Some(self.repository.read_commit(parent)?.tree),
+            None => None,
+        };
+        let is_empty = match parent_tree {
+            Some(parent_tree) => parent_tree == tree,
+            None => tree_files(&tree)?.is_empty(),
+        };
+        if is_empty && !was_empty && parents.len() < 2 {
+            let kept = parents.first().copied();
+            self.commits.insert(*sha, kept);
+            return Ok(kept);
+        }
+
+        let message = match &self.options.message_callback {
+            Some(callback) => run_callback(callback, &commit.message)
+                .map_err(|error| anyhow!("Message callback failed for {}: {}", sha, error))?,
+            None => commit.message.clone(),
+        };
+        let rewritten = Commit {
+            tree,
+            parents,
+            extra_headers: commit.extra_headers.iter()
+                .filter(|(header, _)| !SIGNATURE_HEADERS.contains(&header.as_str()))
+                .cloned()
+                .collect(),
+            message,
+            ..commit.clone()
+        };
+        let new = if rewritten == commit { *sha } else { self.repository.write(&Object::Commit(rewritten))? };
+
+        self.commits.insert(*sha, Some(new));
+        Ok(Some(new))
+    }
+
+    fn rewrite_tree(&mut self, tree: &ObjectId) -> anyhow::Result<ObjectId> {
+        let paths = &self.options.paths;
+        let mut files = BTreeMap::new();
+
+        for (path, (mode, sha)) in tree_files(tree)? {
+            if !paths.is_empty() && !paths.iter().any(|prefix| strip_path(&path, prefix).is_some()) {
+                continue;
+            }
+            if let Some(max) = self.options.max_blob_size {
+                if mode != 0o160000 && self.blob_size(&sha)? > max {
+                    continue;
+                }
+            }
+
+            let path = self.options.renames.iter()
+                .find_map(|(old, new)| strip_path(&path, old).map(|rest| join_path(new, rest)))
+                .unwrap_or(path);
+            files.insert(path, (mode, sha));
+        }
+
+        write_tree_files(&files)
+    }
+
+    fn blob_size(&mut self, sha: &ObjectId) -> anyhow::Result<u64> {
+        if let Some(size) = self.blob_sizes.get(sha) {
+            return Ok(*size);
+        }
+
+        let (_, data) = self.repository.find_object(sha)?;
+        let size = data.len() as u64;
+        self.blob_sizes.insert(*sha, size);
+        Ok(size)
+    }
+
+    /// Maps the target of a ref. Annotated tags are rewritten to point at
+    /// the new commit, losing their signature. Returns `None` if the
+    /// ref's history was dropped entirely.
+    fn rewrite_ref(&mut self, sha: &ObjectId) -> anyhow::Result<Option<ObjectId>> {
+        match self.repository.read(sha)? {
+            Object::Commit(_) => Ok(self.commits[sha]),
+            Object::Tag(tag) => {
+                let Some(object) = self.rewrite_ref(&tag.object)? else { return Ok(None) };
+                if object == tag.object {
+                    return Ok(Some(*sha));
+                }
+
+                let message = match signature_start(&tag.message) {
+                    Some(signature) => tag.message[..signature].to_string(),
+                    None => tag.message.clone(),
+                };
+                let tag = Tag { object, message, ..tag };
+                Ok(Some(self.repository.write(&Object::Tag(tag))?))
+            }
+            _ => Ok(Some(*sha)),
+        }
+    }
+}
+
+/// Returns what follows `prefix` in `path` if `path` is `prefix` or lies
+/// below the directory `prefix`.
+fn strip_path<'p>(path: &'p str, prefix: &str) -> Option<&'p str> {
+    let prefix = prefix.trim_end_matches('/');
+    if prefix.is_empty() {
+        return Some(path);
+    }
+    match path.strip_prefix(prefix)? {
+        "" => Some(""),
+        rest => rest.strip_prefix('/'),
+    }
+}
+
+fn join_path(directory: &str, path: &str) -> String {
+    let directory = directory.trim_end_matches('/');
+    match (directory, path) {
+        ("", path) => path.to_string(),
+        (directory, "") => directory.to_string(),
+        (directory, path) => format!("{}/{}", directory, path),
+    }
+}
+
+fn run_callback(callback: &str, message: &str) -> anyhow::Result<String> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(callback)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    child.stdin.take().unwrap().write_all(message.as_bytes())?;
+
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(anyhow!("{}", output.status));
+    }
+
+    String::from_utf8(output.stdout).map_err(|_| anyhow!("Message is not valid UTF-8"))
+}
@@ -0,0 +1,45 @@
+//! Running async code, such as HTTP requests, from the synchronous
+//! commands.
+
+use std::future::Future;
+
+use tokio::runtime::{Builder, Handle, RuntimeFlavor};
+
+/// Runs `future` to completion: on the runtime the command already runs
+/// in if that has threads to spare, otherwise on a runtime of its own, as
+/// when the library is used without one.
+pub(crate) fn block_on<F>(future: F) -> F::Output
+where
+    F: Future + Send,
+    F::Output: Send,
+{
+    match Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => {
+            tokio::task::block_in_place(|| handle.block_on(future))
+        }
+        // A current-thread runtime can't be blocked by a task it runs, nor
+        // can another runtime start on its thread, so use a new thread.
+        Ok(_) => std::thread::scope(|scope| {
+            scope.spawn(|| local_runtime().block_on(future)).join().expect("Runtime thread panicked")
+        }),
+        Err(_) => local_runtime().block_on(future),
+    }
+}
+
+fn local_runtime() -> tokio::runtime::Runtime {
+    Builder::new_current_thread().enable_all().build().expect("Failed to start an async runtime")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runs_with_any_runtime_or_none() {
+        assert_eq!(block_on(async { 1 }), 1);
+        let current_thread = Builder::new_current_thread().build().unwrap();
+        assert_eq!(current_thread.block_on(async { block_on(async { 2 }) }), 2);
+        let multi_thread = Builder::new_multi_thread().build().unwrap();
+        assert_eq!(multi_thread.block_on(async { block_on(async { 3 }) }), 3);
+    }
+}
@@ -0,0 +1,399 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::anyhow;
+
+use crate::ident::identity_time;
+use crate::object_id::ObjectId;
+use crate::objects::{peel_to_tree, Commit};
+use crate::refs;
+use crate::repository::{self, Repository};
+
+/// Each revision takes one bit of a commit's flags, as in git.
+const MAX_REVISIONS: usize = 26;
+
+#[derive(Debug, Default)]
+pub struct ShowOptions {
+    /// Show remote-tracking branches, alone or with local ones.
+    pub remotes: bool,
+    pub all: bool,
+    /// Commits to show past the common ancestor, or `-1` for only the
+    /// tips.
+    pub more: i64,
+    /// Name commits by abbreviated ID instead of `branch~N`.
+    pub sha1_name: bool,
+    pub no_name: bool,
+    /// Leave out commits on the first branch.
+    pub topics: bool,
+    /// Keep merges reachable from only one branch.
+    pub sparse: bool,
+}
+
+/// A commit's name relative to a branch: `generation` first-parent steps
+/// back from `head`.
+struct Name {
+    head: String,
+    generation: usize,
+}
+
+impl Name {
+    fn base(&self) -> String {
+        match self.generation {
+            0 => self.head.clone(),
+            1 => format!("{}^", self.head),
+            generation => format!("{}~{}", self.head, generation),
+        }
+    }
+}
+
+/// State for walking back from the branches together, following git's
+/// show-branch closely so its output matches.
+struct Walk {
+    repository: Repository,
+    commits: HashMap<ObjectId, Commit>,
+    /// Which revisions reach each commit, one bit per revision.
+    flags: HashMap<ObjectId, u64>,
+    uninteresting: HashSet<ObjectId>,
+    /// Seen commits, in the reverse of the order they were seen.
+    seen: Vec<ObjectId>,
+    marked: HashSet<ObjectId>,
+    names: HashMap<ObjectId, Name>,
+}
+
+impl Walk {
+    fn commit(&mut self, sha: &ObjectId) -> anyhow::Result<&Commit> {
+        if !self.commits.contains_key(sha) {
+            let commit = self.repository.read_commit(sha)?;
+            self.commits.insert(*sha, commit);
+        }
+
+        Ok(&self.commits[sha])
+    }
+
+    fn time(&self, sha: &ObjectId) -> i64 {
+        identity_time(&self.commits[sha].committer)
+    }
+
+    fn mark_seen(&mut self, sha: ObjectId) -> bool {
+        if !self.marked.insert(sha) {
+            return false;
+        }
+        self.seen.push(sha);
+        true
+    }
+
+    /// Inserts `sha` before the first older commit in `list`.
+    fn insert_by_date(&self, list: &mut Vec<ObjectId>, sha: ObjectId) {
+        let time = self.time(&sha);
+        let position = list.iter().position(|other| self.time(other) < time).unwrap_or(list.len());
+        list.insert(position, sha);
+    }
+
+    /// Walks back from the tips in `list` until every commit left to
+    /// visit is reachable from all revisions, then `extra` more.
+    fn join(&mut self, mut list: Vec<ObjectId>, revisions: usize, mut extra: i64) -> anyhow::Result<()> {
+        let all: u64 = (1 << revisions) - 1;
+
+        while !list.is_empty() {
+            let still_interesting = list.iter().any(|sha| !self.uninteresting.contains(sha));
+            let sha = list.remove(0);
+            if !still_interesting && extra <= 0 {
+                break;
+            }
+
+            self.mark_seen(sha);
+            let flags = self.flags[&sha];
+            let uninteresting = flags & all == all || self.uninteresting.contains(&sha);
+
+            for parent in self.commit(&sha)?.parents.clone() {
+                let parent_flags = self.flags.get(&parent).copied().unwrap_or(0);
+                let parent_uninteresting = self.uninteresting.contains(&parent);
+                if parent_flags & flags == flags && (parent_uninteresting || !uninteresting) {
+                    continue;
+                }
+                self.commit(&parent)?;
+                if self.mark_seen(parent) && !still_interesting {
+                    extra -= 1;
+                }
+                *self.flags.entry(parent).or_default() |= flags;
+                if uninteresting {
+                    self.uninteresting.insert(parent);
+                }
+                self.insert_by_date(&mut list, parent);
+            }
+        }
+
+        // Anything reachable from a common commit isn't interesting, but
+        // only among the commits already seen.
+        loop {
+            let mut changed = false;
+            for sha in self.seen.clone().iter().rev() {
+                if self.flags[sha] & all != all && !self.uninteresting.contains(sha) {
+                    continue;
+                }
+                for parent in &self.commits[sha].parents {
+                    changed |= self.uninteresting.insert(*parent);
+                }
+            }
+            if !changed {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Orders `list` so commits come before their parents, keeping
+    /// branches together the way `git log --graph` does.
+    fn sort_topologically(&self, list: &[ObjectId]) -> Vec<ObjectId> {
+        let mut indegree: HashMap<ObjectId, usize> = list.iter().map(|sha| (*sha, 1)).collect();
+        for sha in list {
+            for parent in &self.commits[sha].parents {
+                if let Some(degree) = indegree.get_mut(parent) {
+                    *degree += 1;
+                }
+            }
+        }
+
+        let mut stack: Vec<ObjectId> = list.iter().filter(|sha| indegree[sha] == 1).rev().copied().collect();
+        let mut sorted = Vec::new();
+        while let Some(sha) = stack.pop() {
+            for parent in &self.commits[&sha].parents {
+                match indegree.get_mut(parent) {
+                    Some(degree) if *degree > 0 => {
+                        *degree -= 1;
+                        if *degree == 1 {
+                            stack.push(*parent);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            indegree.insert(sha, 0);
+            sorted.push(sha);
+        }
+
+        sorted
+    }
+
+    /// Names commits from the tips, along first-parent chains and then
+    /// through merge parents as `name^2`.
+    fn name_commits(&mut self, list: &[ObjectId], tips: &[(String, ObjectId)]) {
+        for sha in list {
+            if let Some((name, _)) = tips.iter().find(|(_, tip)| tip == sha) {
+                self.names.entry(*sha).or_insert(Name { head: name.clone(), generation: 0 });
+            }
+        }
+
+        while list.iter().map(|sha| self.name_first_parent_chain(*sha)).sum::<usize>() > 0 {}
+
+        loop {
+            let mut named = 0;
+            for sha in list {
+                let Some(name) = self.names.get(sha) else {
+                    continue;
+                };
+                let base = name.base();
+                let parents = self.commits[sha].parents.clone();
+                for (nth, parent) in parents.into_iter().enumerate() {
+                    if self.names.contains_key(&parent) {
+                        continue;
+                    }
+                    let head = match nth {
+                        0 => format!("{}^", base),
+                        nth => format!("{}^{}", base, nth + 1),
+                    };
+                    self.names.insert(parent, Name { head, generation: 0 });
+                    named += 1;
+                    self.name_first_parent_chain(parent);
+                }
+            }
+            if named == 0 {
+                break;
+            }
+        }
+    }
+
+    fn name_first_parent_chain(&mut self, mut sha: ObjectId) -> usize {
+        let mut named = 0;
+        while let Some(name) = self.names.get(&sha) {
+            let Some(parent) = self.commits.get(&sha).and_then(|commit| commit.parents.first()).copied() else {
+                break;
+            };
+            if self.names.contains_key(&parent) {
+                break;
+            }
+            let name = Name { head: name.head.clone(), generation: name.generation + 1 };
+            self.names.insert(parent, name);
+            named += 1;
+            sha = parent;
+        }
+
+        named
+    }
+
+    /// The commit's bracketed name, if it should have one, and subject.
+    fn describe(&self, sha: &ObjectId, no_name: bool) -> String {
+        let subject = oneline(&self.commits[sha].message);
+        if no_name {
+            return subject;
+        }
+        match self.names.get(sha) {
+            Some(name) => format!("[{}] {}", name.base(), subject),
+            None => format!("[{}] {}", &sha.to_string()[..7], subject),
+        }
+    }
+}
+
+/// The first paragraph of a message on one line.
+fn oneline(message: &str) -> String {
+    let subject: Vec<&str> = message.lines()
+        .skip_while(|line| line.trim().is_empty())
+        .take_while(|line| !line.trim().is_empty())
+        .map(str::trim_end)
+        .collect();
+    let subject = subject.join(" ");
+
+    subject.strip_prefix("[PATCH] ").map(str::to_string).unwrap_or(subject)
+}
+
+/// The branches to show when none are named: local ones, remote ones or
+/// both.
+fn default_revisions(options: &ShowOptions) -> anyhow::Result<Vec<String>> {
+    let mut names = Vec::new();
+    if options.all || !options.remotes {
+        names.extend(refs::list_refs("refs/heads/")?.iter().map(|name| name["refs/heads/".len()..].to_string()));
+    }
+    if options.all || options.remotes {
+        names.extend(refs::list_refs("refs/remotes/")?.iter().map(|name| name["refs/remotes/".len()..].to_string()));
+    }
+
+    if names.len() > MAX_REVISIONS {
+        for name in &names[MAX_REVISIONS..] {
+            eprintln!("warning: ignoring {}; cannot handle more than {} refs", name, MAX_REVISIONS);
+        }
+        names.truncate(MAX_REVISIONS);
+    }
+
+    Ok(names)
+}
+
+/// Whether `name` as given on the command line is the branch `HEAD` is on.
+fn is_head(head: Option<&str>, name: &str) -> bool {
+    let name = name.strip_prefix("refs/heads/").or_else(|| name.strip_prefix("heads/")).unwrap_or(name);
+    head == Some(name)
+}
+
+/// Shows the branches in `revisions`, or all local branches, with their
+/// recent commits and which branches contain each.
+pub fn run(revisions: &[String], options: &ShowOptions) -> anyhow::Result<()> {
+    let names = if revisions.is_empty() { default_revisions(options)? } else { revisions.to_vec() };
+    if names.len() > MAX_REVISIONS {
+        return Err(anyhow!("Cannot handle more than {} revs.", MAX_REVISIONS));
+    }
+    if names.is_empty() {
+        return Ok(());
+    }
+
+    let mut walk = Walk {
+        repository: repository::current()?,
+        commits: HashMap::new(),
+        flags: HashMap::new(),
+        uninteresting: HashSet::new(),
+        seen: Vec::new(),
+        marked: HashSet::new(),
+        names: HashMap::new(),
+    };
+
+    let mut tips = Vec::new();
+    let mut list = Vec::new();
+    for (i, name) in names.iter().enumerate() {
+        let sha = refs::resolve_revision(name)
+            .map_err(|_| anyhow!("Bad sha1 reference {}", name))?;
+        let (commit, _) = peel_to_tree(&sha)?;
+        walk.commit(&commit)?;
+        walk.mark_seen(commit);
+        let flags = walk.flags.entry(commit).or_default();
+        let first = *flags == 0;
+        *flags |= 1 << i;
+        if first {
+            walk.insert_by_date(&mut list, commit);
+        }
+        tips.push((name.clone(), commit));
+    }
+
+    let branch = refs::current_branch()?;
+    let head_sha = refs::read_ref("HEAD")?;
+    let head_at = tips.iter()
+        .position(|(name, sha)| is_head(branch.as_deref(), name) && Some(*sha) == head_sha);
+
+    if options.more >= 0 {
+        walk.join(list, tips.len(), options.more)?;
+    }
+
+    // Newest first, with ties in the order seen.
+    let mut seen: Vec<ObjectId> = walk.seen.iter().rev().copied().collect();
+    seen.sort_by_key(|sha| std::cmp::Reverse(walk.time(sha)));
+
+    if tips.len() > 1 || options.more < 0 {
+        for (i, (name, sha)) in tips.iter().enumerate() {
+            let marker = if head_at == Some(i) { '*' } else { '!' };
+            if options.more < 0 {
+                let marker = if head_at == Some(i) { '*' } else { ' ' };
+                println!("{} [{}] {}", marker, name, walk.describe(sha, true));
+            } else {
+                println!("{}{} [{}] {}", " ".repeat(i), marker, name, walk.describe(sha, true));
+            }
+        }
+        if options.more >= 0 {
+            println!("{}", "-".repeat(tips.len()));
+        }
+    }
+    if options.more < 0 {
+        return Ok(());
+    }
+
+    let sorted = walk.sort_topologically(&seen);
+    if !options.sha1_name && !options.no_name {
+        walk.name_commits(&sorted, &tips);
+    }
+
+    let all: u64 = (1 << tips.len()) - 1;
+    let mut extra = options.more;
+    let mut shown_merge_point = false;
+    for sha in &sorted {
+        let flags = walk.flags.get(sha).copied().unwrap_or(0);
+        let is_merge_point = flags & all == all;
+        shown_merge_point |= is_merge_point;
+
+        let mut line = String::new();
+        if tips.len() > 1 {
+            let is_merge = walk.commits[sha].parents.len() > 1;
+            if options.topics && !is_merge_point && flags & 1 != 0 {
+                continue;
+            }
+            // A merge within a single branch says little about how the
+            // branches relate.
+            let is_tip = tips.iter().any(|(_, tip)| tip == sha);
+            if !options.sparse && is_merge && !is_tip && flags.count_ones() == 1 {
+                continue;
+            }
+            for i in 0..tips.len() {
+                line.push(match (flags & (1 << i) != 0, is_merge) {
+                    (false, _) => ' ',
+                    (true, true) => '-',
+                    (true, false) if head_at == Some(i) => '*',
+                    (true, false) => '+',
+                });
+            }
+            line.push(' ');
+        }
+        println!("{}{}", line, walk.describe(sha, options.no_name));
+
+        if shown_merge_point {
+            extra -= 1;
+            if extra < 0 {
+                break;
+            }
+        }
+    }
+
+    Ok(())
+}
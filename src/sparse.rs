@@ -0,0 +1,293 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+use anyhow::anyhow;
+
+use crate::attributes::Attributes;
+use crate::config::{self, Config};
+use crate::convert::Convert;
+use crate::index::{Index, IndexEntry, SKIP_WORKTREE};
+use crate::repository::{self, git_dir};
+use crate::verify_path::Protection;
+use crate::wildmatch::wildmatch;
+use crate::worktree;
+
+/// The patterns of `$GIT_DIR/info/sparse-checkout`, see
+/// git-sparse-checkout(1).
+#[derive(Debug)]
+pub(crate) enum Sparse {
+    /// Cone mode: everything at the top level, everything below the
+    /// `recursive` directories, and the files directly inside `parents`.
+    Cone { recursive: Vec<String>, parents: HashSet<String> },
+    /// Full gitignore-style patterns, the last match winning.
+    Patterns(Vec<String>),
+}
+
+impl Sparse {
+    fn parse(content: &str, cone: bool) -> Sparse {
+        let lines: Vec<&str> = content.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .collect();
+        if !cone {
+            return Sparse::Patterns(lines.iter().map(|line| line.to_string()).collect());
+        }
+
+        // A directory followed by its own `!/dir/*/` only includes its
+        // direct children.
+        let mut recursive = Vec::new();
+        let mut parents = HashSet::new();
+        for line in &lines {
+            let Some(directory) = line.strip_prefix('/').and_then(|line| line.strip_suffix('/')) else {
+                continue;
+            };
+            if directory == "*" || directory.is_empty() || line.starts_with('!') {
+                continue;
+            }
+            if lines.contains(&format!("!/{}/*/", directory).as_str()) {
+                parents.insert(directory.to_string());
+            } else {
+                recursive.push(directory.to_string());
+            }
+        }
+
+        Sparse::Cone { recursive, parents }
+    }
+
+    /// Whether the file at `path` belongs in the worktree.
+    pub fn includes(&self, path: &str) -> bool {
+        match self {
+            Sparse::Cone { recursive, parents } => {
+                let directory = path.rsplit_once('/').map(|(directory, _)| directory).unwrap_or("");
+                directory.is_empty()
+                    || parents.contains(directory)
+                    || recursive.iter().any(|recursive| {
+                        path.strip_prefix(recursive.as_str()).is_some_and(|rest| rest.starts_with('/'))
+                    })
+            }
+            Sparse::Patterns(patterns) => {
+                // The file itself decides first, then each of its leading
+                // directories from the innermost outwards.
+                let mut candidates = vec![(path, false)];
+                let mut rest = path;
+                while let Some((directory, _)) = rest.rsplit_once('/') {
+                    candidates.push((directory, true));
+                    rest = directory;
+                }
+
+                candidates.into_iter()
+                    .find_map(|(candidate, is_dir)| last_match(patterns, candidate, is_dir))
+                    .unwrap_or(false)
+            }
+        }
+    }
+
+    /// The lines written to the sparse-checkout file for `directories` in
+    /// cone mode.
+    fn cone_patterns(directories: &[String]) -> String {
+        let mut recursive: Vec<&str> = directories.iter()
+            .map(|directory| directory.trim_matches('/'))
+            .filter(|directory| !directory.is_empty())
+            .collect();
+        recursive.sort();
+        recursive.dedup();
+
+        let mut parents: Vec<&str> = recursive.iter()
+            .flat_map(|directory| directory.match_indices('/').map(|(end, _)| &directory[..end]))
+            .collect();
+        parents.sort();
+        parents.dedup();
+
+        let mut content = String::from("/*\n!/*/\n");
+        for parent in parents {
+            content.push_str(&format!("/{}/\n!/{}/*/\n", parent, parent));
+        }
+        for directory in recursive {
+            content.push_str(&format!("/{}/\n", directory));
+        }
+
+        content
+    }
+}
+
+/// Finds the last pattern matching `path` and whether it includes it.
+fn last_match(patterns: &[String], path: &str, is_dir: bool) -> Option<bool> {
+    patterns.iter().rev().find_map(|pattern| {
+        let (included, pattern) = match pattern.strip_prefix('!') {
+            Some(pattern) => (false, pattern),
+            None => (true, pattern.as_str()),
+        };
+        let (pattern, dir_only) = match pattern.strip_suffix('/') {
+            Some(pattern) => (pattern, true),
+            None => (pattern, false),
+        };
+        if dir_only && !is_dir {
+            return None;
+        }
+
+        let matched = match pattern.strip_prefix('/') {
+            Some(anchored) => wildmatch(anchored, path, true),
+            None if pattern.contains('/') => wildmatch(pattern, path, true),
+            None => wildmatch(pattern, path.rsplit('/').next().unwrap_or(path), true),
+        };
+        matched.then_some(included)
+    })
+}
+
+/// Loads the sparse patterns of the worktree with git directory
+/// `git_dir`, or `None` if it isn't a sparse checkout.
+pub(crate) fn load(git_dir: &Path) -> anyhow::Result<Option<Sparse>> {
+    let config = repository::repo_config()?;
+    if !config_flag(&config, "core.sparsecheckout", false)? {
+        return Ok(None);
+    }
+
+    match fs::read_to_string(git_dir.join("info/sparse-checkout")) {
+        Ok(content) => Ok(Some(Sparse::parse(&content, config_flag(&config, "core.sparsecheckoutcone", true)?))),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Copies the sparse patterns of one worktree to a new one, returning
+/// them if there are any.
+pub(crate) fn copy_patterns(from_git_dir: &Path, to_git_dir: &Path) -> anyhow::Result<Option<Sparse>> {
+    if load(from_git_dir)?.is_none() {
+        return Ok(None);
+    }
+
+    fs::create_dir_all(to_git_dir.join("info"))?;
+    fs::copy(from_git_dir.join("info/sparse-checkout"), to_git_dir.join("info/sparse-checkout"))?;
+    load(to_git_dir)
+}
+
+fn config_flag(config: &Config, name: &str, default: bool) -> anyhow::Result<bool> {
+    match config.get(name) {
+        Some(value) => config::parse_bool(value).ok_or(anyhow!("Bad {} value: {}", name, value)),
+        None => Ok(default),
+    }
+}
+
+/// Turns on sparse checkout, keeping only the top-level files unless
+/// patterns were already set.
+pub fn init(cone: bool) -> anyhow::Result<()> {
+    let git_dir = git_dir()?;
+    let sparse_file = git_dir.join("info/sparse-checkout");
+    if !sparse_file.exists() {
+        fs::create_dir_all(git_dir.join("info"))?;
+        fs::write(&sparse_file, if cone { Sparse::cone_patterns(&[]) } else { "/*\n!/*/\n".to_string() })?;
+    }
+
+    enable(cone)?;
+    update_worktree()
+}
+
+/// Replaces the sparse patterns, which are directories in cone mode,
+/// and updates the worktree to match.
+pub fn set(patterns: &[String], cone: bool) -> anyhow::Result<()> {
+    let git_dir = git_dir()?;
+    let content = if cone {
+        Sparse::cone_patterns(patterns)
+    } else {
+        patterns.iter().map(|pattern| format!("{}\n", pattern)).collect()
+    };
+    fs::create_dir_all(git_dir.join("info"))?;
+    fs::write(git_dir.join("info/sparse-checkout"), content)?;
+
+    enable(cone)?;
+    update_worktree()
+}
+
+/// Prints the directories of a cone mode checkout, or the raw patterns.
+pub fn list() -> anyhow::Result<()> {
+    match load(&git_dir()?)? {
+        Some(Sparse::Cone { recursive, .. }) => {
+            for directory in recursive {
+                println!("{}", directory);
+            }
+        }
+        Some(Sparse::Patterns(patterns)) => {
+            for pattern in patterns {
+                println!("{}", pattern);
+            }
+        }
+        None => return Err(anyhow!("This worktree is not sparse")),
+    }
+
+    Ok(())
+}
+
+fn enable(cone: bool) -> anyhow::Result<()> {
+    let config_path = repository::current()?.common_dir().join("config");
+    let mut config = Config::from_file(&config_path)?;
+    config.set("core.sparsecheckout", "true")?;
+    config.set("core.sparsecheckoutcone", if cone { "true" } else { "false" })?;
+    config.write(&config_path)
+}
+
+/// Removes unmodified files that fell outside the sparse patterns and
+/// restores those that are back inside, updating their skip-worktree bits.
+fn update_worktree() -> anyhow::Result<()> {
+    let repository = repository::current()?;
+    let Some(sparse) = load(repository.git_dir())? else {
+        return Ok(());
+    };
+    let index_path = repository.git_dir().join("index");
+    let mut index = Index::read(&index_path, repository.object_format())?;
+    let config = repository.config()?;
+    let convert = Convert::from_config(&config)?;
+    let protection = Protection::load(&config)?;
+
+    for entry in &mut index.entries {
+        let file = Path::new(".").join(&entry.path);
+        let directory = entry.path.rsplit_once('/').map(|(directory, _)| directory).unwrap_or("");
+        let skipped = entry.extended_flags & SKIP_WORKTREE != 0;
+
+        match (sparse.includes(&entry.path), skipped) {
+            (true, true) => {
+                protection.verify(&entry.path, entry.mode)?;
+                let attributes = Attributes::load_for(repository.git_dir(), directory)?;
+                if let Some(parent) = file.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                worktree::write_entry(&file, &entry.path, entry.mode, &entry.sha, &attributes, &convert)?;
+
+                let restored = IndexEntry::from_file(&file, entry.path.clone(), entry.mode, entry.sha)?;
+                *entry = IndexEntry { extended_flags: entry.extended_flags & !SKIP_WORKTREE, ..restored };
+            }
+            (false, false) => {
+                let attributes = Attributes::load_for(repository.git_dir(), directory)?;
+                let exists = fs::symlink_metadata(&file).is_ok();
+                if exists && worktree::is_entry_modified(&file, &entry.path, entry.mode, &entry.sha, &attributes, &convert)? {
+                    eprintln!("warning: not removing modified file '{}' outside the sparse checkout", entry.path);
+                    continue;
+                }
+                if exists {
+                    if entry.mode == 0o160000 {
+                        fs::remove_dir(&file)?;
+                    } else {
+                        fs::remove_file(&file)?;
+                    }
+                    remove_empty_parents(&file);
+                }
+                entry.extended_flags |= SKIP_WORKTREE;
+            }
+            _ => {}
+        }
+    }
+
+    index.write(&index_path, repository.object_format())
+}
+
+/// Removes the directories above `file` that are now empty, stopping at
+/// the worktree root.
+pub(crate) fn remove_empty_parents(file: &Path) {
+    let mut directory = file.parent();
+    while let Some(path) = directory {
+        if path == Path::new(".") || fs::remove_dir(path).is_err() {
+            break;
+        }
+        directory = path.parent();
+    }
+}
@@ -0,0 +1,181 @@
+use std::collections::{BTreeMap, HashSet};
+
+use crate::object_id::ObjectId;
+use crate::objects::Object;
+use crate::refs;
+use crate::repository::{self, Repository};
+
+/// Object counts and sizes of one type.
+#[derive(Debug, Default, Clone, Copy)]
+struct Totals {
+    count: u64,
+    size: u64,
+    stored: u64,
+}
+
+impl Totals {
+    fn add(&mut self, size: u64, stored: u64) {
+        self.count += 1;
+        self.size += size;
+        self.stored += stored;
+    }
+}
+
+/// What a walk of the object graph found.
+#[derive(Debug, Default)]
+struct Walk {
+    by_type: BTreeMap<&'static str, Totals>,
+    by_extension: BTreeMap<String, Totals>,
+    /// Every blob with its size and the first path it was seen at.
+    blobs: Vec<(u64, ObjectId, String)>,
+    /// Every tree with its depth below the root and its path.
+    trees: Vec<(usize, ObjectId, String)>,
+    /// How many objects are loose, and how many packed at each delta
+    /// chain depth.
+    loose: u64,
+    depths: BTreeMap<usize, u64>,
+    seen: HashSet<ObjectId>,
+}
+
+impl Walk {
+    fn object(&mut self, repository: &Repository, sha: &ObjectId, kind: &'static str) -> anyhow::Result<Option<u64>> {
+        if !self.seen.insert(*sha) {
+            return Ok(None);
+        }
+        let (_, size) = repository.object_header(sha)?;
+        self.by_type.entry(kind).or_default().add(size, repository.stored_size(sha)?);
+        match repository.delta_depth(sha)? {
+            Some(depth) => *self.depths.entry(depth).or_default() += 1,
+            None => self.loose += 1,
+        }
+        Ok(Some(size))
+    }
+
+    fn tree(&mut self, repository: &Repository, sha: &ObjectId, path: &str, depth: usize) -> anyhow::Result<()> {
+        if self.object(repository, sha, "tree")?.is_none() {
+            return Ok(());
+        }
+        self.trees.push((depth, *sha, path.to_string()));
+        let Object::Tree(tree) = repository.read(sha)? else { return Ok(()) };
+        for entry in tree.entries {
+            let path = if path.is_empty() { entry.name.clone() } else { format!("{}/{}", path, entry.name) };
+            match entry.kind() {
+                "tree" => self.tree(repository, &entry.sha, &path, depth + 1)?,
+                // Submodule commits are in another repository.
+                "commit" => {}
+                _ => {
+                    let Some(size) = self.object(repository, &entry.sha, "blob")? else { continue };
+                    let stored = repository.stored_size(&entry.sha)?;
+                    self.by_extension.entry(extension(&entry.name)).or_default().add(size, stored);
+                    self.blobs.push((size, entry.sha, path));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The extension of `name` with its dot, or `(none)`.
+fn extension(name: &str) -> String {
+    match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => format!(".{}", extension.to_lowercase()),
+        _ => "(none)".to_string(),
+    }
+}
+
+/// `bytes` in the largest binary unit that keeps it at least 1, as
+/// `count-objects -H` prints sizes.
+fn human(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["bytes", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{} bytes", bytes),
+        unit => format!("{:.2} {}", value, UNITS[unit]),
+    }
+}
+
+/// Walks every object reachable from the refs and `HEAD` and prints
+/// counts and sizes by type and by file extension, the `top` largest
+/// blobs and deepest trees, and how objects are stored.
+pub fn run(top: usize) -> anyhow::Result<()> {
+    let repository = repository::current()?;
+    let mut starts = Vec::new();
+    for name in refs::list_refs("refs/")?.into_iter().chain(["HEAD".to_string()]) {
+        starts.extend(refs::read_ref(&name)?);
+    }
+
+    let mut walk = Walk::default();
+    let mut pending = starts;
+    while let Some(sha) = pending.pop() {
+        if walk.seen.contains(&sha) {
+            continue;
+        }
+        match repository.read(&sha)? {
+            Object::Commit(commit) => {
+                walk.object(&repository, &sha, "commit")?;
+                walk.tree(&repository, &commit.tree, "", 0)?;
+                pending.extend(commit.parents);
+            }
+            Object::Tag(tag) => {
+                walk.object(&repository, &sha, "tag")?;
+                pending.push(tag.object);
+            }
+            Object::Tree(_) => walk.tree(&repository, &sha, "", 0)?,
+            Object::Blob(_) => {
+                if let Some(size) = walk.object(&repository, &sha, "blob")? {
+                    walk.blobs.push((size, sha, String::new()));
+                }
+            }
+        }
+    }
+
+    println!("{:<10} {:>10} {:>14} {:>14}", "type", "count", "size", "on disk");
+    let mut total = Totals::default();
+    for kind in ["commit", "tree", "blob", "tag"] {
+        let totals = walk.by_type.get(kind).copied().unwrap_or_default();
+        println!("{:<10} {:>10} {:>14} {:>14}", kind, totals.count, human(totals.size), human(totals.stored));
+        total.count += totals.count;
+        total.size += totals.size;
+        total.stored += totals.stored;
+    }
+    println!("{:<10} {:>10} {:>14} {:>14}", "total", total.count, human(total.size), human(total.stored));
+
+    walk.blobs.sort_by(|a, b| b.0.cmp(&a.0).then(a.2.cmp(&b.2)));
+    if !walk.blobs.is_empty() {
+        println!("\nLargest blobs:");
+        for (size, sha, path) in walk.blobs.iter().take(top) {
+            println!("  {:>12}  {}  {}", human(*size), sha, path);
+        }
+    }
+
+    walk.trees.sort_by(|a, b| b.0.cmp(&a.0).then(a.2.cmp(&b.2)));
+    if !walk.trees.is_empty() {
+        println!("\nDeepest trees:");
+        for (depth, sha, path) in walk.trees.iter().take(top) {
+            println!("  {:>3}  {}  {}", depth, sha, if path.is_empty() { "(root)" } else { path });
+        }
+    }
+
+    println!("\nDelta chain depths:");
+    if walk.loose > 0 {
+        println!("  {:>3}  {} objects (loose)", 0, walk.loose);
+    }
+    for (depth, count) in &walk.depths {
+        println!("  {:>3}  {} objects", depth, count);
+    }
+
+    let mut extensions: Vec<(&String, &Totals)> = walk.by_extension.iter().collect();
+    extensions.sort_by(|a, b| b.1.stored.cmp(&a.1.stored).then(a.0.cmp(b.0)));
+    if !extensions.is_empty() {
+        println!("\nBy extension:");
+        for (extension, totals) in extensions.into_iter().take(top) {
+            println!("  {:<12} {:>8} {:>14} {:>14}", extension, totals.count, human(totals.size), human(totals.stored));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,320 @@
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
+
+use anyhow::anyhow;
+
+use crate::config::Config;
+use crate::info;
+use crate::object_id::ObjectId;
+use crate::objects::{peel_to_tree, read_tree};
+use crate::refs;
+use crate::repository::{common_dir, git_dir_of, repo_config, Repository};
+use crate::verify_path::Protection;
+use crate::worktree;
+
+/// A submodule as declared in `.gitmodules`.
+struct Submodule {
+    name: String,
+    path: String,
+    url: Option<String>,
+}
+
+fn submodules() -> anyhow::Result<Vec<Submodule>> {
+    let gitmodules = Config::from_file(Path::new(".gitmodules"))?;
+
+    gitmodules.subsections("submodule")
+        .into_iter()
+        .filter(|name| {
+            // Like git, refuse names that would put the submodule's git
+            // directory outside `.git/modules`.
+            let valid = is_valid_name(name);
+            if !valid {
+                eprintln!("warning: ignoring suspicious submodule name: {}", name);
+            }
+            valid
+        })
+        .map(|name| {
+            let path = gitmodules.get(&format!("submodule.{}.path", name))
+                .ok_or(anyhow!("No submodule path configured for '{}' in .gitmodules", name))?
+                .to_string();
+            let url = gitmodules.get(&format!("submodule.{}.url", name))
+                .map(str::to_string);
+
+            Ok(Submodule { name, path, url })
+        })
+        .collect()
+}
+
+/// Whether `name` is safe to name a directory below `.git/modules`, as
+/// git's `check_submodule_name` decides: not empty and without `..`
+/// components, whichever slash separates them.
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.split(['/', '\\']).all(|component| component != "..")
+}
+
+/// The git directory of the submodule `name` below `modules`. Empty
+/// components are dropped, so that a leading slash stays inside as it
+/// does in git.
+fn module_dir(modules: &Path, name: &str) -> PathBuf {
+    let mut dir = modules.to_path_buf();
+    dir.extend(name.split(['/', '\\']).filter(|component| !component.is_empty()));
+    dir
+}
+
+/// The path of `submodule`, which comes from `.gitmodules` too, once it
+/// is checked like a tree entry so that nothing is created outside the
+/// worktree or inside `.git`.
+fn checked_path<'a>(submodule: &'a Submodule, protection: &Protection) -> anyhow::Result<&'a Path> {
+    protection.verify(&submodule.path, 0o160000)?;
+    Ok(Path::new(&submodule.path))
+}
+
+/// Registers the URLs from `.gitmodules` in the repository config, the
+/// first step before a submodule can be cloned.
+pub fn init() -> anyhow::Result<()> {
+    let config_path = common_dir()?.join("config");
+    let mut config = Config::from_file(&config_path)?;
+
+    for submodule in submodules()? {
+        let key = format!("submodule.{}.url", submodule.name);
+        if config.get(&key).is_some() {
+            continue;
+        }
+
+        let url = submodule.url
+            .ok_or(anyhow!("No url found for submodule path '{}' in .gitmodules", submodule.path))?;
+        let url = resolve_relative_url(&url, &config)?;
+
+        config.set(&format!("submodule.{}.active", submodule.name), "true")?;
+        config.set(&key, &url)?;
+        info!("Submodule '{}' ({}) registered for path '{}'", submodule.name, url, submodule.path);
+    }
+
+    config.write(&config_path)?;
+    Ok(())
+}
+
+/// Checks out the commit recorded for each registered submodule, first
+/// cloning it into `.git/modules/<name>` from its URL, which must be a
+/// repository on this machine. Its HEAD is left detached at the commit.
+pub fn update() -> anyhow::Result<()> {
+    let config = Config::from_file(&common_dir()?.join("config"))?;
+    let Some(tree) = recorded_tree()? else {
+        return Ok(());
+    };
+    let protection = Protection::load(&repo_config()?)?;
+
+    for submodule in submodules()? {
+        let Some(url) = config.get(&format!("submodule.{}.url", submodule.name)) else {
+            continue;
+        };
+        let path = checked_path(&submodule, &protection)?;
+        let Some(recorded) = find_gitlink(&tree, &submodule.path)? else {
+            continue;
+        };
+
+        let source = local_url(url)
+            .ok_or(anyhow!("clone of '{}' into submodule path '{}' failed", url, submodule.path))?;
+        if !path.join(".git").exists() {
+            clone(&submodule, url, &source)?;
+        }
+        if refs::read_ref_in(&git_dir_of(path)?, "HEAD")? == Some(recorded) {
+            continue;
+        }
+
+        // The submodule's objects can only be read by a process running
+        // inside it.
+        run_in(path, &["-q", "copy-objects", "--from", &source.display().to_string()])?;
+        run_in(path, &["submodule--checkout", &recorded.to_string()])?;
+        info!("Submodule path '{}': checked out '{}'", submodule.path, recorded);
+    }
+
+    Ok(())
+}
+
+/// `submodule--checkout`: detaches HEAD at `commit` and checks it out,
+/// run by `update` inside a submodule.
+pub fn checkout(commit: &ObjectId) -> anyhow::Result<()> {
+    worktree::checkout(commit, None)
+}
+
+/// Sets up the git directory of `submodule` in `.git/modules/<name>`,
+/// with `url` as its `origin`, and points the submodule's `.git` file at
+/// it. Objects are copied and files checked out afterwards.
+fn clone(submodule: &Submodule, url: &str, source: &Path) -> anyhow::Result<()> {
+    let format = Repository::open(source)
+        .ok()
+        .filter(|repository| repository.git_dir().is_dir())
+        .ok_or(anyhow!("clone of '{}' into submodule path '{}' failed", url, submodule.path))?
+        .object_format();
+
+    let path = Path::new(&submodule.path);
+    fs::create_dir_all(path)?;
+    let worktree = fs::canonicalize(path)?;
+    let module_dir = module_dir(&fs::canonicalize(common_dir()?)?.join("modules"), &submodule.name);
+    if !module_dir.join("HEAD").is_file() {
+        Repository::init_module(&module_dir, format)?;
+        let config_path = module_dir.join("config");
+        let mut config = Config::from_file(&config_path)?;
+        config.set("core.bare", "false")?;
+        config.set("core.worktree", &relative_path(&worktree, &module_dir).display().to_string())?;
+        config.set("remote.origin.url", url)?;
+        config.set("remote.origin.fetch", "+refs/heads/*:refs/remotes/origin/*")?;
+        config.write(&config_path)?;
+    }
+    fs::write(path.join(".git"), format!("gitdir: {}\n", relative_path(&module_dir, &worktree).display()))?;
+    Ok(())
+}
+
+/// The repository `url` names if it is on this machine, as a path that
+/// holds from any directory.
+fn local_url(url: &str) -> Option<PathBuf> {
+    let path = url.strip_prefix("file://").unwrap_or(url);
+    if path.contains("://") {
+        return None;
+    }
+    fs::canonicalize(path).ok()
+}
+
+/// `path` relative to the directory `base`, both absolute.
+fn relative_path(path: &Path, base: &Path) -> PathBuf {
+    let path: Vec<Component> = path.components().collect();
+    let base: Vec<Component> = base.components().collect();
+    let common = path.iter().zip(&base).take_while(|(a, b)| a == b).count();
+
+    let mut relative: PathBuf = base[common..].iter().map(|_| "..").collect();
+    relative.extend(&path[common..]);
+    relative
+}
+
+/// Runs this program with `args` inside the submodule checked out at
+/// `path`, away from the superproject's object directory.
+fn run_in(path: &Path, args: &[&str]) -> anyhow::Result<()> {
+    let status = Command::new(std::env::current_exe()?)
+        .args(args)
+        .current_dir(path)
+        .env_remove("GIT_OBJECT_DIRECTORY")
+        .env_remove("GIT_ALTERNATE_OBJECT_DIRECTORIES")
+        .env_remove("GIT_QUARANTINE_PATH")
+        .status()?;
+    if !status.success() {
+        return Err(anyhow!("Unable to checkout submodule '{}'", path.display()));
+    }
+    Ok(())
+}
+
+/// The tree HEAD records the submodules in, or `None` on an unborn branch.
+fn recorded_tree() -> anyhow::Result<Option<ObjectId>> {
+    let head = refs::read_ref("HEAD")?;
+    Ok(head.map(|head| peel_to_tree(&head)).transpose()?.map(|(_, tree)| tree))
+}
+
+/// Prints the commit recorded for each submodule, prefixed with `-` if it
+/// isn't checked out and `+` if its HEAD doesn't match the recorded commit.
+pub fn status() -> anyhow::Result<()> {
+    let tree = recorded_tree()?;
+
+    for submodule in submodules()? {
+        let recorded = match &tree {
+            Some(tree) => find_gitlink(tree, &submodule.path)?,
+            None => None,
+        };
+        let Some(recorded) = recorded else {
+            continue;
+        };
+
+        let path = Path::new(&submodule.path);
+        let checked_out = if path.join(".git").exists() {
+            refs::read_ref_in(&git_dir_of(path)?, "HEAD")?
+        } else {
+            None
+        };
+
+        match checked_out {
+            None => println!("-{} {}", recorded, submodule.path),
+            Some(sha) if sha != recorded => println!("+{} {}", sha, submodule.path),
+            Some(_) => println!(" {} {}", recorded, submodule.path),
+        }
+    }
+
+    Ok(())
+}
+
+/// Looks up the commit recorded for the gitlink at `path` inside `tree`.
+fn find_gitlink(tree: &ObjectId, path: &str) -> anyhow::Result<Option<ObjectId>> {
+    let mut tree = *tree;
+    let mut components = path.split('/').filter(|component| !component.is_empty()).peekable();
+
+    while let Some(component) = components.next() {
+        let Some(entry) = read_tree(&tree)?
+            .entries
+            .into_iter()
+            .find(|entry| entry.name == component) else {
+            return Ok(None);
+        };
+
+        match (entry.mode, components.peek()) {
+            (0o160000, None) => return Ok(Some(entry.sha)),
+            (0o40000, Some(_)) => tree = entry.sha,
+            _ => return Ok(None),
+        }
+    }
+
+    Ok(None)
+}
+
+/// Resolves `./` and `../` submodule URLs against the superproject's
+/// `origin` remote, or its own location if it has no remote.
+fn resolve_relative_url(url: &str, config: &Config) -> anyhow::Result<String> {
+    if !url.starts_with("./") && !url.starts_with("../") {
+        return Ok(url.to_string());
+    }
+
+    let mut base = match config.get("remote.origin.url") {
+        Some(remote) => remote.trim_end_matches('/').to_string(),
+        None => std::fs::canonicalize(".")?.display().to_string(),
+    };
+    let mut rest = url;
+    loop {
+        if let Some(stripped) = rest.strip_prefix("./") {
+            rest = stripped;
+        } else if let Some(stripped) = rest.strip_prefix("../") {
+            rest = stripped;
+            let parent = base.rfind(['/', ':'])
+                .ok_or(anyhow!("Cannot strip one component off url '{}'", base))?;
+            base.truncate(parent);
+        } else {
+            break;
+        }
+    }
+
+    Ok(PathBuf::from(base).join(rest).display().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_stay_inside_modules() {
+        assert!(is_valid_name("vendor/lib"));
+        assert!(is_valid_name("..lib"));
+        for name in ["", "..", "../../../../tmp/sm/EVIL", "vendor/../../EVIL", "vendor\\..\\EVIL"] {
+            assert!(!is_valid_name(name), "{}", name);
+        }
+        let modules = Path::new("/repo/.git/modules");
+        assert_eq!(module_dir(modules, "vendor/lib"), Path::new("/repo/.git/modules/vendor/lib"));
+        assert_eq!(module_dir(modules, "/tmp/EVIL"), Path::new("/repo/.git/modules/tmp/EVIL"));
+    }
+
+    #[test]
+    fn paths_are_checked_like_tree_entries() {
+        let protection = Protection::load(&Config::default()).unwrap();
+        let submodule = |path: &str| Submodule { name: "lib".to_string(), path: path.to_string(), url: None };
+        assert_eq!(checked_path(&submodule("vendor/lib"), &protection).unwrap(), Path::new("vendor/lib"));
+        for path in ["../EVIL", "/tmp/EVIL", "vendor/../../EVIL", ".git/modules/lib", "vendor/.GIT", "vendor/lib/"] {
+            assert!(checked_path(&submodule(path), &protection).is_err(), "{}", path);
+        }
+    }
+}
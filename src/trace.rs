@@ -0,0 +1,110 @@
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Where a trace key sends its output, parsed from its environment
+/// variable like git does: `1`, `2` or `true` mean stderr, an absolute
+/// path appends to that file, anything else disables tracing.
+#[derive(Debug)]
+enum Target {
+    Stderr,
+    File(PathBuf),
+}
+
+/// A tracing channel enabled by an environment variable such as
+/// `GIT_TRACE`.
+pub struct TraceKey {
+    name: &'static str,
+    target: OnceLock<Option<Target>>,
+}
+
+/// General tracing: commands, child processes and object access.
+pub static TRACE: TraceKey = TraceKey::new("GIT_TRACE");
+/// Every pkt-line sent or received.
+pub static TRACE_PACKET: TraceKey = TraceKey::new("GIT_TRACE_PACKET");
+
+impl TraceKey {
+    const fn new(name: &'static str) -> TraceKey {
+        TraceKey { name, target: OnceLock::new() }
+    }
+
+    fn target(&self) -> Option<&Target> {
+        self.target
+            .get_or_init(|| {
+                let value = std::env::var(self.name).ok()?;
+                match value.to_lowercase().as_str() {
+                    "" | "0" | "false" => None,
+                    "1" | "2" | "true" => Some(Target::Stderr),
+                    _ if PathBuf::from(&value).is_absolute() => Some(Target::File(PathBuf::from(value))),
+                    _ => {
+                        eprintln!("warning: unknown trace value for '{}': {}", self.name, value);
+                        None
+                    }
+                }
+            })
+            .as_ref()
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.target().is_some()
+    }
+
+    /// Writes one trace line prefixed with the time and source location.
+    /// Tracing must never break a command, so write errors are ignored.
+    pub fn log(&self, file: &str, line: u32, args: fmt::Arguments) {
+        let Some(target) = self.target() else {
+            return;
+        };
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let seconds = now.as_secs() % 86400;
+        let message = format!(
+            "{:02}:{:02}:{:02}.{:06} {}:{:<4} trace: {}\n",
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60,
+            now.subsec_micros(),
+            file,
+            line,
+            args,
+        );
+
+        let _ = match target {
+            Target::Stderr => std::io::stderr().write_all(message.as_bytes()),
+            Target::File(path) => OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .and_then(|mut file| file.write_all(message.as_bytes())),
+        };
+    }
+}
+
+/// Logs a formatted message to a [`TraceKey`] if it is enabled, without
+/// formatting anything otherwise.
+#[macro_export]
+macro_rules! trace {
+    ($key:expr, $($arg:tt)*) => {
+        if $key.enabled() {
+            $key.log(file!(), line!(), format_args!($($arg)*));
+        }
+    };
+}
+
+/// Renders pkt-line payloads for `GIT_TRACE_PACKET`, escaping
+/// non-printable bytes.
+pub fn printable(data: &[u8]) -> String {
+    let data = data.strip_suffix(b"\n").unwrap_or(data);
+
+    data.iter()
+        .map(|&byte| match byte {
+            0x20..=0x7e => (byte as char).to_string(),
+            _ => format!("\\{:o}", byte),
+        })
+        .collect()
+}
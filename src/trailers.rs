@@ -0,0 +1,134 @@
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+use anyhow::anyhow;
+
+/// Trailers git itself adds, which mark a paragraph as a trailer block
+/// even if it holds other lines too.
+const GIT_GENERATED_PREFIXES: [&str; 2] = ["Signed-off-by: ", "(cherry picked from commit "];
+
+/// A `Key: value` line at the end of a commit message, such as
+/// `Signed-off-by: Name <email>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trailer {
+    pub key: String,
+    pub value: String,
+}
+
+impl Trailer {
+    /// Parses `Key: value`; continuation lines are not part of `line`.
+    fn parse_line(line: &str) -> Option<Trailer> {
+        let (key, value) = line.split_once(':')?;
+        let key = key.trim_end();
+        if key.is_empty() || !key.chars().all(|c| c.is_alphanumeric() || c == '-') {
+            return None;
+        }
+
+        Some(Trailer { key: key.to_string(), value: value.trim().to_string() })
+    }
+
+    fn same_key(&self, other: &Trailer) -> bool {
+        self.key.eq_ignore_ascii_case(&other.key)
+    }
+}
+
+/// Parses a `--trailer` argument, `Key: value` or `Key=value`.
+impl FromStr for Trailer {
+    type Err = anyhow::Error;
+
+    fn from_str(argument: &str) -> Result<Self, Self::Err> {
+        let (key, value) = argument.split_once([':', '='])
+            .unwrap_or((argument, ""));
+        let key = key.trim();
+        if key.is_empty() {
+            return Err(anyhow!("Empty trailer token in trailer '{}'", argument));
+        }
+
+        Ok(Trailer { key: key.to_string(), value: value.trim().to_string() })
+    }
+}
+
+impl Display for Trailer {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.key, self.value)
+    }
+}
+
+/// Finds where the trailer block of `message` starts: the last paragraph,
+/// unless it is the subject, if all its lines are trailers or at least a
+/// quarter are and one was added by git.
+fn trailer_block_start(message: &str) -> Option<usize> {
+    let body = message.trim_end_matches('\n');
+    let start = body.rfind("\n\n")? + 2;
+    let block = &body[start..];
+
+    let (mut trailers, mut others, mut git_generated) = (0, 0, false);
+    for line in block.lines() {
+        if line.starts_with([' ', '\t']) {
+            continue;
+        }
+        if Trailer::parse_line(line).is_some() {
+            trailers += 1;
+            git_generated |= GIT_GENERATED_PREFIXES.iter().any(|prefix| line.starts_with(prefix));
+        } else {
+            others += 1;
+        }
+    }
+
+    let is_block = trailers > 0 && (others == 0 || (git_generated && trailers * 3 >= others));
+    is_block.then_some(start)
+}
+
+/// Returns the trailers of `message`, with continuation lines unfolded.
+pub fn parse(message: &str) -> Vec<Trailer> {
+    let Some(start) = trailer_block_start(message) else {
+        return Vec::new();
+    };
+
+    let mut trailers: Vec<Trailer> = Vec::new();
+    let mut continues = false;
+    for line in message[start..].lines() {
+        match (line.strip_prefix([' ', '\t']), trailers.last_mut()) {
+            (Some(continuation), Some(trailer)) if continues => {
+                trailer.value.push(' ');
+                trailer.value.push_str(continuation.trim());
+            }
+            _ => match Trailer::parse_line(line) {
+                Some(trailer) => {
+                    trailers.push(trailer);
+                    continues = true;
+                }
+                None => continues = false,
+            },
+        }
+    }
+
+    trailers
+}
+
+/// Appends `trailers` to the trailer block of `message`, starting one if
+/// needed. Like git's default, a trailer is skipped if the one before it
+/// is identical.
+pub fn add(message: &str, trailers: &[Trailer]) -> String {
+    if trailers.is_empty() {
+        return message.to_string();
+    }
+
+    let mut existing = parse(message);
+    let mut result = message.trim_end_matches('\n').to_string();
+    if !result.is_empty() && trailer_block_start(message).is_none() {
+        result.push('\n');
+    }
+    result.push('\n');
+
+    for trailer in trailers {
+        let duplicate = existing.last()
+            .is_some_and(|last| last.same_key(trailer) && last.value == trailer.value);
+        if !duplicate {
+            result.push_str(&format!("{}\n", trailer));
+            existing.push(trailer.clone());
+        }
+    }
+
+    result
+}
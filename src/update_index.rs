@@ -0,0 +1,32 @@
+use anyhow::anyhow;
+
+use crate::index::{Index, ASSUME_VALID, SKIP_WORKTREE};
+use crate::repository;
+
+/// Sets (`Some(true)`) or clears (`Some(false)`) the assume-unchanged and
+/// skip-worktree bits of the index entries for `paths`.
+pub fn mark(paths: &[String], assume_unchanged: Option<bool>, skip_worktree: Option<bool>) -> anyhow::Result<()> {
+    let repository = repository::current()?;
+    let index_path = repository.git_dir().join("index");
+    let mut index = Index::read(&index_path, repository.object_format())?;
+
+    for path in paths {
+        let path = path.trim_start_matches("./");
+        let entry = index.entries.iter_mut()
+            .find(|entry| entry.path == path)
+            .ok_or(anyhow!("Unable to mark file {}", path))?;
+
+        match assume_unchanged {
+            Some(true) => entry.flags |= ASSUME_VALID,
+            Some(false) => entry.flags &= !ASSUME_VALID,
+            None => {}
+        }
+        match skip_worktree {
+            Some(true) => entry.extended_flags |= SKIP_WORKTREE,
+            Some(false) => entry.extended_flags &= !SKIP_WORKTREE,
+            None => {}
+        }
+    }
+
+    index.write(&index_path, repository.object_format())
+}
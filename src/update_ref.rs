@@ -0,0 +1,209 @@
+use std::io::BufRead;
+
+use anyhow::anyhow;
+
+use crate::error::Error;
+use crate::object_id::ObjectId;
+use crate::refs::{self, RefUpdate, Transaction};
+use crate::repository;
+
+/// Sets `name` to `new`, if it is at `old` when given, or doesn't exist
+/// if `old` is empty.
+pub fn update(name: &str, new: &str, old: Option<&str>, no_deref: bool) -> anyhow::Result<()> {
+    let new = parse_value(new)?;
+    let old = old.map(|old| if old.is_empty() { null() } else { parse_value(old) }).transpose()?;
+
+    let mut transaction = Transaction::default();
+    transaction.add(RefUpdate { name: name.to_string(), new: Some(new), old, no_deref })?;
+    transaction.commit().map_err(|error| anyhow!("update_ref failed for ref '{}': {}", name, error))
+}
+
+/// Deletes `name`, if it is at `old` when given.
+pub fn delete(name: &str, old: Option<&str>, no_deref: bool) -> anyhow::Result<()> {
+    let old = old.filter(|old| !old.is_empty()).map(parse_value).transpose()?;
+
+    let mut transaction = Transaction::default();
+    transaction.add(RefUpdate { name: name.to_string(), new: Some(null()?), old, no_deref })?;
+    transaction.commit().map_err(|error| {
+        eprintln!("error: {}", error);
+        Error::Status(1).into()
+    })
+}
+
+fn null() -> anyhow::Result<ObjectId> {
+    Ok(repository::current()?.object_format().null())
+}
+
+fn parse_value(value: &str) -> anyhow::Result<ObjectId> {
+    refs::resolve_revision(value).map_err(|_| anyhow!("{}: not a valid SHA1", value))
+}
+
+/// Where a `--stdin` session is. Updates are queued while open or
+/// started; a prepared transaction can only be committed or aborted, and
+/// once closed only `start` begins another.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum State {
+    Open,
+    Started,
+    Prepared,
+    Closed,
+}
+
+impl State {
+    /// The state after a command that moves the session to `next`.
+    fn advance(self, next: State) -> anyhow::Result<State> {
+        match self {
+            State::Started if next == State::Started => Err(anyhow!("cannot restart ongoing transaction")),
+            // An explicit transaction never falls back to an implicit one.
+            State::Open | State::Started => Ok(self.max(next)),
+            State::Prepared if next != State::Closed => Err(anyhow!("prepared transactions can only be closed")),
+            State::Closed if next != State::Started => Err(anyhow!("transaction is closed")),
+            State::Prepared | State::Closed => Ok(next),
+        }
+    }
+}
+
+/// Runs `update-ref --stdin`: reads `update`, `create`, `delete` and
+/// `verify` commands and applies them in one transaction, or in the
+/// transactions that `start`, `prepare`, `commit` and `abort` delimit.
+/// With `null_terminated`, every field ends in NUL instead of commands
+/// ending in a newline.
+pub fn run_stdin(null_terminated: bool) -> anyhow::Result<()> {
+    let null = null()?;
+    let terminator = if null_terminated { b'\0' } else { b'\n' };
+    let mut input = std::io::stdin().lock();
+
+    let mut state = State::Open;
+    let mut transaction = Transaction::default();
+    let mut no_deref = false;
+    while let Some(line) = read_field(&mut input, terminator)? {
+        let (command, rest) = line.split_once(' ').unwrap_or((&line, ""));
+        let next = match command {
+            "update" | "create" | "delete" | "verify" | "option" => State::Open,
+            "start" => State::Started,
+            "prepare" => State::Prepared,
+            "commit" | "abort" => State::Closed,
+            _ => return Err(anyhow!("unknown command: {}", line)),
+        };
+        if next != State::Open && !rest.is_empty() {
+            return Err(anyhow!("{}: extra input: {}", command, rest));
+        }
+        let previous = state;
+        state = state.advance(next)?;
+        if previous == State::Closed {
+            transaction = Transaction::default();
+        }
+
+        match command {
+            "option" => match rest {
+                "no-deref" => no_deref = true,
+                _ => return Err(anyhow!("option unknown: {}", rest)),
+            },
+            "start" => println!("start: ok"),
+            "prepare" => {
+                transaction.prepare()?;
+                println!("prepare: ok");
+            }
+            "commit" => {
+                std::mem::take(&mut transaction).commit()?;
+                println!("commit: ok");
+            }
+            "abort" => {
+                transaction = Transaction::default();
+                println!("abort: ok");
+            }
+            _ => {
+                let update = parse_update(command, rest, &mut input, null_terminated, null)?;
+                transaction.add(RefUpdate { no_deref: std::mem::take(&mut no_deref), ..update })?;
+            }
+        }
+    }
+
+    // Input that ends inside an explicit transaction aborts it.
+    match state {
+        State::Open => transaction.commit(),
+        _ => Ok(()),
+    }
+}
+
+/// Reads up to the next `terminator`, or `None` at the end of the input.
+fn read_field(input: &mut impl BufRead, terminator: u8) -> anyhow::Result<Option<String>> {
+    let mut field = Vec::new();
+    if input.read_until(terminator, &mut field)? == 0 {
+        return Ok(None);
+    }
+    if field.last() == Some(&terminator) {
+        field.pop();
+    }
+    Ok(Some(String::from_utf8(field).map_err(|_| anyhow!("Input is not valid UTF-8"))?))
+}
+
+/// Parses the ref and values of an `update`, `create`, `delete` or
+/// `verify` command: the rest of the line split at spaces, or with
+/// `null_terminated` the ref and then a field per value, where an empty
+/// field is a missing value.
+fn parse_update(
+    command: &str,
+    rest: &str,
+    input: &mut impl BufRead,
+    null_terminated: bool,
+    null: ObjectId,
+) -> anyhow::Result<RefUpdate> {
+    let expected: &[&str] = match command {
+        "update" => &["<newvalue>", "<oldvalue>"],
+        "create" => &["<newvalue>"],
+        _ => &["<oldvalue>"],
+    };
+    let (name, values) = if null_terminated {
+        let mut values = Vec::new();
+        for what in expected {
+            match read_field(input, b'\0')? {
+                Some(value) => values.push(value),
+                None => return Err(anyhow!("{} {}: unexpected end of input when reading {}", command, rest, what)),
+            }
+        }
+        (rest, values)
+    } else {
+        let mut words = rest.split(' ');
+        let name = words.next().unwrap_or_default();
+        (name, words.map(String::from).collect())
+    };
+    if name.is_empty() {
+        return Err(anyhow!("{}: missing <ref>", command));
+    }
+    if values.len() > expected.len() {
+        return Err(anyhow!("{} {}: extra input: {}", command, name, values[expected.len()..].join(" ")));
+    }
+
+    let value = |index: usize| -> anyhow::Result<Option<ObjectId>> {
+        match values.get(index).filter(|value| !value.is_empty()) {
+            Some(value) => refs::resolve_revision(value)
+                .map(Some)
+                .map_err(|_| anyhow!("{} {}: invalid {}: {}", command, name, expected[index], value)),
+            None => Ok(None),
+        }
+    };
+    let fail = |what: &str| Err(anyhow!("{} {}: {}", command, name, what));
+    let (new, old) = match command {
+        "update" => match value(0)? {
+            Some(new) => (Some(new), value(1)?),
+            None if null_terminated => {
+                eprintln!("warning: {} {}: missing <newvalue>, treating as zero", command, name);
+                (Some(null), value(1)?)
+            }
+            None => return fail("missing <newvalue>"),
+        },
+        "create" => match value(0)? {
+            Some(new) if new == null => return fail("zero <newvalue>"),
+            Some(new) => (Some(new), Some(null)),
+            None => return fail("missing <newvalue>"),
+        },
+        "delete" => match value(0)? {
+            Some(old) if old == null => return fail("zero <oldvalue>"),
+            old => (Some(null), old),
+        },
+        _ => (None, Some(value(0)?.unwrap_or(null))),
+    };
+
+    Ok(RefUpdate { name: name.to_string(), new, old, no_deref: false })
+}
@@ -0,0 +1,224 @@
+use std::collections::HashSet;
+use std::io::{ErrorKind, Read, Write};
+
+use anyhow::anyhow;
+
+use crate::bundle::{add_tree, write_pack};
+use crate::object_id::ObjectId;
+use crate::objects::Object;
+use crate::pktline;
+use crate::refs;
+use crate::repository::Repository;
+
+/// Serves clones and fetches of a repository over version 0 of git's
+/// pack protocol, the other end of `git fetch-pack`, independent of how
+/// the bytes travel. A server calls [`UploadPack::advertise_refs`] for
+/// the first response and [`UploadPack::handle`] for what the client
+/// sends next; over smart HTTP, the advertisement is preceded by the
+/// `# service=git-upload-pack` packet and a flush, which are left to the
+/// caller. Packs are undeltified, and only the capabilities that need no
+/// more than that are offered.
+pub struct UploadPack {
+    repository: Repository,
+    stateless_rpc: bool,
+}
+
+impl UploadPack {
+    /// Serves `repository`, as it is stored: like git, replacements are
+    /// not applied, and every object sent is checked against its ID.
+    /// With `stateless_rpc`, each request is answered on its own, as over
+    /// smart HTTP, rather than as one conversation.
+    pub fn new(repository: Repository, stateless_rpc: bool) -> UploadPack {
+        UploadPack { repository: repository.without_replacements().checking_objects(), stateless_rpc }
+    }
+
+    /// The refs a client may ask for, HEAD first, each with its object
+    /// and, for annotated tags, what it peels to.
+    fn tips(&self) -> anyhow::Result<Vec<(String, ObjectId, Option<ObjectId>)>> {
+        let git_dir = self.repository.git_dir();
+        let names = std::iter::once("HEAD".to_string()).chain(refs::list_refs_in(git_dir, "refs/")?);
+        let mut tips = Vec::new();
+        for name in names {
+            let Some(sha) = refs::read_ref_in(git_dir, &name)? else { continue };
+            let mut peeled = None;
+            let mut object = sha;
+            while let Ok(Object::Tag(tag)) = self.repository.read(&object) {
+                object = tag.object;
+                peeled = Some(object);
+            }
+            tips.push((name, sha, peeled));
+        }
+        Ok(tips)
+    }
+
+    /// Writes the first response: every ref, with the capabilities on
+    /// the first line, then a flush.
+    pub fn advertise_refs(&self, response: &mut impl Write) -> anyhow::Result<()> {
+        let git_dir = self.repository.git_dir();
+        let format = self.repository.object_format();
+        let mut capabilities = Vec::new();
+        let head = refs::raw_ref_in(git_dir, "HEAD")?;
+        if let Some(target) = head.as_deref().and_then(|head| head.strip_prefix("ref: ")) {
+            if refs::read_ref_in(git_dir, target)?.is_some() {
+                capabilities.push(format!("symref=HEAD:{}", target));
+            }
+        }
+        capabilities.push(format!("object-format={}", format.name()));
+        capabilities.push(format!("agent=git-starter-rust/{}", env!("CARGO_PKG_VERSION")));
+        let mut capabilities = Some(capabilities.join(" "));
+
+        let tips = self.tips()?;
+        if tips.is_empty() {
+            // An empty repository still has capabilities to announce.
+            let line = format!("{} capabilities^{{}}\0{}\n", format.null(), capabilities.take().unwrap_or_default());
+            pktline::write_packet(response, line.as_bytes())?;
+        }
+        for (name, sha, peeled) in tips {
+            let line = match capabilities.take() {
+                Some(capabilities) => format!("{} {}\0{}\n", sha, name, capabilities),
+                None => format!("{} {}\n", sha, name),
+            };
+            pktline::write_packet(response, line.as_bytes())?;
+            if let Some(peeled) = peeled {
+                pktline::write_text(response, &format!("{} {}^{{}}", peeled, name))?;
+            }
+        }
+        pktline::write_flush(response)?;
+        response.flush()?;
+        Ok(())
+    }
+
+    /// Answers what the client sends after the advertisement: the
+    /// objects it wants, then what it has, and once it is `done`, a
+    /// pack of what it lacks. Without `stateless_rpc`, reads until then,
+    /// answering each batch of haves; otherwise reads one request and
+    /// answers just that.
+    pub fn handle(&self, request: &mut impl Read, response: &mut impl Write) -> anyhow::Result<()> {
+        let tips: HashSet<ObjectId> = self.tips()?.into_iter().map(|(_, sha, _)| sha).collect();
+        let mut wants = Vec::new();
+        loop {
+            let line = match pktline::read_text(request) {
+                Ok(Some(line)) => line,
+                Ok(None) => break,
+                // Clients that only wanted the refs may just hang up.
+                Err(err) if wants.is_empty() && is_eof(&err) => return Ok(()),
+                Err(err) => return Err(err),
+            };
+            let Some(want) = line.strip_prefix("want ") else {
+                return Err(anyhow!("git upload-pack: protocol error, expected to get object ID, not '{}'", line));
+            };
+            // The first want lists the capabilities the client picked.
+            let want = want.split(' ').next().unwrap_or_default();
+            let sha: ObjectId = want.parse()
+                .map_err(|_| anyhow!("git upload-pack: protocol error, expected to get object ID, not '{}'", line))?;
+            if !tips.contains(&sha) {
+                pktline::write_text(response, &format!("ERR upload-pack: not our ref {}", sha))?;
+                return Err(anyhow!("git upload-pack: not our ref {}", sha));
+            }
+            wants.push(sha);
+        }
+        if wants.is_empty() {
+            return Ok(());
+        }
+
+        // Without multi_ack, only the first object in common is
+        // acknowledged, and a flush only answered until there is one.
+        let mut common = Vec::new();
+        loop {
+            let Some(line) = pktline::read_text(request)? else {
+                if common.is_empty() {
+                    pktline::write_text(response, "NAK")?;
+                }
+                response.flush()?;
+                if self.stateless_rpc {
+                    return Ok(());
+                }
+                continue;
+            };
+            if line == "done" {
+                break;
+            }
+            let Some(sha) = line.strip_prefix("have ").and_then(|have| have.parse::<ObjectId>().ok()) else {
+                return Err(anyhow!("git upload-pack: expected SHA1 list, got '{}'", line));
+            };
+            if !common.contains(&sha) && self.repository.object_header(&sha).is_ok() {
+                common.push(sha);
+                if common.len() == 1 {
+                    pktline::write_text(response, &format!("ACK {}", sha))?;
+                }
+            }
+        }
+        if common.is_empty() {
+            pktline::write_text(response, "NAK")?;
+        }
+
+        let mut pack = Vec::new();
+        write_pack(&self.repository, &self.objects(&wants, &common)?, &mut pack)?;
+        response.write_all(&pack)?;
+        response.flush()?;
+        Ok(())
+    }
+
+    /// Everything reachable from `wants` that isn't reachable from
+    /// `common`, as far as the commits `common` leads to tell.
+    fn objects(&self, wants: &[ObjectId], common: &[ObjectId]) -> anyhow::Result<Vec<ObjectId>> {
+        let repository = &self.repository;
+        let mut excluded = HashSet::new();
+        let mut pending: Vec<ObjectId> = common.to_vec();
+        while let Some(sha) = pending.pop() {
+            if excluded.contains(&sha) {
+                continue;
+            }
+            match repository.read(&sha) {
+                Ok(Object::Tag(tag)) => pending.push(tag.object),
+                Ok(Object::Commit(commit)) => {
+                    excluded.insert(sha);
+                    pending.extend(commit.parents);
+                }
+                _ => {}
+            }
+        }
+
+        let mut objects = Vec::new();
+        let mut visited = HashSet::new();
+        let mut trees = Vec::new();
+        let mut boundary = HashSet::new();
+        let mut pending: Vec<ObjectId> = wants.to_vec();
+        while let Some(sha) = pending.pop() {
+            if excluded.contains(&sha) || !visited.insert(sha) {
+                continue;
+            }
+            match repository.read(&sha)? {
+                Object::Tag(tag) => {
+                    objects.push(sha);
+                    pending.push(tag.object);
+                }
+                Object::Commit(commit) => {
+                    objects.push(sha);
+                    trees.push(commit.tree);
+                    boundary.extend(commit.parents.iter().filter(|parent| excluded.contains(*parent)).copied());
+                    pending.extend(commit.parents);
+                }
+                Object::Tree(_) => trees.push(sha),
+                Object::Blob(_) => objects.push(sha),
+            }
+        }
+
+        // Whatever the commits at the edge of what the client has hold,
+        // it has too.
+        let mut skip = HashSet::new();
+        for sha in boundary {
+            let commit = repository.read_commit(&sha)?;
+            add_tree(repository, commit.tree, &HashSet::new(), &mut skip, &mut Vec::new())?;
+        }
+        let mut seen = HashSet::new();
+        for tree in trees {
+            add_tree(repository, tree, &skip, &mut seen, &mut objects)?;
+        }
+        Ok(objects)
+    }
+}
+
+fn is_eof(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<std::io::Error>().is_some_and(|err| err.kind() == ErrorKind::UnexpectedEof)
+}
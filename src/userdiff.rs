@@ -0,0 +1,210 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::anyhow;
+
+use crate::attributes::{AttrValue, Attributes};
+use crate::config::{self, Config};
+use crate::regex::Regex;
+
+/// The hunk header patterns git has built in for `diff=rust`, `diff=cpp`
+/// and `diff=python`, used unless the config sets its own.
+const BUILTIN_FUNCNAMES: &[(&str, &str)] = &[
+    (
+        "cpp",
+        "!^[ \t]*[A-Za-z_][A-Za-z_0-9]*:[[:space:]]*($|/[/*])\n\
+         ^((::[[:space:]]*)?[A-Za-z_].*)$",
+    ),
+    ("python", "^[ \t]*((class|(async[ \t]+)?def)[ \t].*)$"),
+    (
+        "rust",
+        "^[\t ]*((pub(\\([^\\)]+\\))?[\t ]+)?((async|const|unsafe|extern([\t ]+\"[^\"]+\"))[\t ]+)?\
+         (struct|enum|union|mod|trait|fn|impl|macro_rules!)[< \t]+[^;]*)$",
+    ),
+];
+
+/// How to find the line a hunk header shows: `diff.<driver>.xfuncname`,
+/// a list of extended regular expressions one per line, or `funcname`
+/// with basic ones. The first to match a line decides, and one starting
+/// with `!` rejects it. The header is the first group, or the whole
+/// match if there is none.
+#[derive(Debug)]
+pub(crate) struct FuncName {
+    patterns: Vec<(bool, Regex)>,
+}
+
+impl FuncName {
+    pub(crate) fn parse(patterns: &str, extended: bool) -> anyhow::Result<FuncName> {
+        let patterns = patterns.split('\n').map(|pattern| {
+            let (negated, pattern) = match pattern.strip_prefix('!') {
+                Some(pattern) => (true, pattern),
+                None => (false, pattern),
+            };
+            let regex = Regex::new(pattern, extended, false)
+                .ok_or(anyhow!("Invalid regexp to look for hunk header: {}", pattern))?;
+            Ok((negated, regex))
+        });
+        Ok(FuncName { patterns: patterns.collect::<anyhow::Result<_>>()? })
+    }
+
+    /// The part of `line` to show, or `None` if it isn't a function line.
+    pub fn find<'a>(&self, line: &'a [u8]) -> Option<&'a [u8]> {
+        let line = line.strip_suffix(b"\n").unwrap_or(line);
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        let (negated, captures) = self.patterns.iter()
+            .find_map(|(negated, regex)| regex.captures(line).map(|captures| (*negated, captures)))?;
+        if negated {
+            return None;
+        }
+        let (start, end) = captures.get(1).copied().flatten().or(captures[0])?;
+        Some(&line[start..end])
+    }
+}
+
+/// What a `diff=<driver>` attribute selects, from `diff.<driver>.*`.
+#[derive(Debug, Default)]
+pub(crate) struct Driver {
+    /// Whether files are always binary or always text, rather than
+    /// judged by their content.
+    pub binary: Option<bool>,
+    pub funcname: Option<FuncName>,
+    /// `diff.<driver>.command`: a program that shows the changes.
+    pub command: Option<String>,
+}
+
+/// Finds the driver for each path from its `diff` attribute: `-diff`
+/// makes a file binary, `diff` makes it text, and `diff=<driver>` picks
+/// the settings of `diff.<driver>`, or a built-in driver.
+pub(crate) struct Drivers {
+    config: Config,
+    /// The git directory whose attributes apply, if in a repository.
+    git_dir: Option<PathBuf>,
+    /// The attribute rules for each directory looked up so far.
+    attributes: RefCell<HashMap<String, Attributes>>,
+    drivers: RefCell<HashMap<String, Arc<Driver>>>,
+    /// `GIT_EXTERNAL_DIFF` or `diff.external`, if external programs
+    /// may be run at all.
+    external: Option<String>,
+    allow_external: bool,
+}
+
+impl Drivers {
+    /// Drivers for the repository at `git_dir`, or without attributes
+    /// outside one. With `allow_external`, changes are shown by an
+    /// external program if one is configured.
+    pub fn new(config: Config, git_dir: Option<&Path>, allow_external: bool) -> Drivers {
+        let external = std::env::var("GIT_EXTERNAL_DIFF")
+            .ok()
+            .or_else(|| config.get("diff.external").map(String::from))
+            .filter(|program| allow_external && !program.is_empty());
+        Drivers {
+            config,
+            git_dir: git_dir.map(Path::to_path_buf),
+            attributes: RefCell::default(),
+            drivers: RefCell::default(),
+            external,
+            allow_external,
+        }
+    }
+
+    /// The driver for `path`, relative to the worktree root.
+    pub fn get(&self, path: &str) -> anyhow::Result<Option<Arc<Driver>>> {
+        let Some(git_dir) = &self.git_dir else {
+            return Ok(None);
+        };
+        let directory = path.rsplit_once('/').map_or("", |(directory, _)| directory);
+        let mut attributes = self.attributes.borrow_mut();
+        if !attributes.contains_key(directory) {
+            attributes.insert(directory.to_string(), Attributes::load_for(git_dir, directory)?);
+        }
+        let name = match attributes[directory].get(path, "diff") {
+            None => return Ok(None),
+            Some(AttrValue::Set) => "",
+            Some(AttrValue::Unset) => "-",
+            Some(AttrValue::Value(name)) => name,
+        };
+
+        let mut drivers = self.drivers.borrow_mut();
+        if let Some(driver) = drivers.get(name) {
+            return Ok(Some(driver.clone()));
+        }
+        let driver = Arc::new(match name {
+            "" => Driver { binary: Some(false), ..Driver::default() },
+            "-" => Driver { binary: Some(true), ..Driver::default() },
+            name => self.load(name)?,
+        });
+        drivers.insert(name.to_string(), driver.clone());
+        Ok(Some(driver))
+    }
+
+    fn load(&self, name: &str) -> anyhow::Result<Driver> {
+        let get = |key: &str| self.config.get(&format!("diff.{}.{}", name, key));
+        let funcname = match (get("xfuncname"), get("funcname")) {
+            (Some(patterns), _) => Some(FuncName::parse(patterns, true)?),
+            (None, Some(patterns)) => Some(FuncName::parse(patterns, false)?),
+            (None, None) => match BUILTIN_FUNCNAMES.iter().find(|(builtin, _)| *builtin == name) {
+                Some((_, patterns)) => Some(FuncName::parse(patterns, true)?),
+                None => None,
+            },
+        };
+        Ok(Driver {
+            binary: get("binary").and_then(config::parse_bool),
+            funcname,
+            command: get("command").map(String::from).filter(|_| self.allow_external),
+        })
+    }
+
+    /// The program to show the changes to a file with `driver` with, if
+    /// any: the driver's command before `diff.external`.
+    pub fn external<'a>(&'a self, driver: Option<&'a Driver>) -> Option<&'a str> {
+        driver.and_then(|driver| driver.command.as_deref()).or(self.external.as_deref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn builtin(name: &str) -> FuncName {
+        let (_, patterns) = BUILTIN_FUNCNAMES.iter().find(|(builtin, _)| *builtin == name).unwrap();
+        FuncName::parse(patterns, true).unwrap()
+    }
+
+    fn header<'a>(funcname: &FuncName, line: &'a str) -> Option<&'a str> {
+        funcname.find(line.as_bytes()).map(|header| std::str::from_utf8(header).unwrap())
+    }
+
+    #[test]
+    fn builtin_drivers() {
+        let rust = builtin("rust");
+        assert_eq!(header(&rust, "    pub(crate) fn find(&self) {\n"), Some("pub(crate) fn find(&self) {"));
+        assert_eq!(header(&rust, "impl<T> Drop for Guard<T> {\r\n"), Some("impl<T> Drop for Guard<T> {"));
+        assert_eq!(header(&rust, "pub async fn run() -> Result<()> {"), Some("pub async fn run() -> Result<()> {"));
+        assert_eq!(header(&rust, "    let fn_name = 1;"), None);
+        assert_eq!(header(&rust, "fn declared();"), None);
+
+        let python = builtin("python");
+        assert_eq!(header(&python, "    async def fetch(url):"), Some("async def fetch(url):"));
+        assert_eq!(header(&python, "class Repo:"), Some("class Repo:"));
+        assert_eq!(header(&python, "    return define"), None);
+
+        let cpp = builtin("cpp");
+        assert_eq!(header(&cpp, "int main(int argc, char **argv)"), Some("int main(int argc, char **argv)"));
+        // Labels are rejected by the negated first pattern.
+        assert_eq!(header(&cpp, "cleanup:"), None);
+        assert_eq!(header(&cpp, "    return 0;"), None);
+    }
+
+    #[test]
+    fn config_patterns() {
+        let funcname = FuncName::parse("!^static\n^([a-z]+) =", true).unwrap();
+        assert_eq!(header(&funcname, "name = value"), Some("name"));
+        assert_eq!(header(&funcname, "static x = 1"), None);
+        // Basic `funcname` patterns spell groups with backslashes.
+        let funcname = FuncName::parse("^\\(sub [a-z]*\\)", false).unwrap();
+        assert_eq!(header(&funcname, "sub parse {"), Some("sub parse"));
+        assert!(FuncName::parse("^(unclosed", true).is_err());
+    }
+}
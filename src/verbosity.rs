@@ -0,0 +1,44 @@
+use std::sync::atomic::{AtomicI8, Ordering};
+
+/// How much commands report beyond their results: -1 with `--quiet`,
+/// otherwise how many times `--verbose` was given.
+static LEVEL: AtomicI8 = AtomicI8::new(0);
+
+/// Sets the verbosity from the global `-q` and `-v` flags, once, before
+/// any command runs.
+pub fn set(quiet: bool, verbose: u8) {
+    let level = if quiet { -1 } else { verbose.min(i8::MAX as u8) as i8 };
+    LEVEL.store(level, Ordering::Relaxed);
+}
+
+/// Whether informational messages and progress are suppressed.
+pub fn quiet() -> bool {
+    LEVEL.load(Ordering::Relaxed) < 0
+}
+
+/// How many times `--verbose` was given.
+pub fn verbose() -> u8 {
+    LEVEL.load(Ordering::Relaxed).max(0) as u8
+}
+
+/// Reports what a command did on stderr, unless `--quiet` was given.
+/// Results a script might read go to stdout instead, with `println!`.
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)*) => {
+        if !$crate::verbosity::quiet() {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+/// Reports detail on stderr if `--verbose` was given at least `$level`
+/// times.
+#[macro_export]
+macro_rules! verbose {
+    ($level:expr, $($arg:tt)*) => {
+        if $crate::verbosity::verbose() >= $level {
+            eprintln!($($arg)*);
+        }
+    };
+}
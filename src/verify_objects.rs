@@ -0,0 +1,52 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+
+use crate::error::Error;
+use crate::progress::Progress;
+use crate::repository;
+
+/// Re-hashes every loose object of the repository on `threads` threads,
+/// or one per CPU, reporting each that is corrupt or misnamed, with a
+/// progress counter if `progress`. Exits with status 1 if any is bad.
+pub fn run(threads: Option<usize>, progress: bool) -> anyhow::Result<()> {
+    let repository = repository::current()?;
+    let objects = repository.loose_objects()?;
+    let threads = threads
+        .or_else(|| std::thread::available_parallelism().ok().map(usize::from))
+        .unwrap_or(1)
+        .clamp(1, objects.len().max(1));
+
+    let progress = Progress::new("Verifying objects", objects.len(), progress);
+    let next = AtomicUsize::new(0);
+    let (sender, receiver) = mpsc::channel();
+    let mut failures = Vec::new();
+    std::thread::scope(|scope| {
+        for _ in 0..threads {
+            let sender = sender.clone();
+            let (repository, objects, next) = (&repository, &objects, &next);
+            scope.spawn(move || {
+                // Threads take the next object until none are left.
+                while let Some(sha) = objects.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    if sender.send(repository.verify_loose(sha).err()).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(sender);
+        for result in receiver {
+            progress.tick();
+            failures.extend(result);
+        }
+    });
+    progress.finish();
+
+    if failures.is_empty() {
+        return Ok(());
+    }
+    failures.sort_by_key(|err| err.to_string());
+    for err in &failures {
+        eprintln!("error: {:#}", err);
+    }
+    Err(Error::Status(1).into())
+}
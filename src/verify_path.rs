@@ -0,0 +1,170 @@
+use std::collections::HashSet;
+
+use anyhow::anyhow;
+
+use crate::config::{self, Config};
+use crate::objects::TreeEntry;
+
+/// What is refused when paths from a tree or an index are checked out:
+/// anything outside the worktree or inside `.git`, and the other
+/// spellings of `.git` that some filesystems open as it.
+#[derive(Debug, Clone, Copy)]
+pub struct Protection {
+    /// `core.protectNTFS`, on by default as in git, since a repository
+    /// checked out here may later be checked out on Windows.
+    ntfs: bool,
+    /// `core.protectHFS`, on by default on macOS.
+    hfs: bool,
+    /// `core.ignoreCase`, under which names that differ only in case are
+    /// the same file.
+    ignore_case: bool,
+}
+
+impl Protection {
+    pub fn load(config: &Config) -> anyhow::Result<Protection> {
+        let flag = |name: &str, default: bool| match config.get(&format!("core.{}", name.to_lowercase())) {
+            Some(value) => config::parse_bool(value).ok_or(anyhow!("Bad core.{} value: {}", name, value)),
+            None => Ok(default),
+        };
+        Ok(Protection {
+            ntfs: flag("protectNTFS", true)?,
+            hfs: flag("protectHFS", cfg!(target_os = "macos"))?,
+            ignore_case: flag("ignoreCase", false)?,
+        })
+    }
+
+    /// Fails with git's `invalid path` message if checking out `path`,
+    /// recorded with `mode`, could write outside the worktree, into the
+    /// repository or, on Windows, to a name the filesystem can't hold.
+    pub fn verify(&self, path: &str, mode: u32) -> anyhow::Result<()> {
+        // A symlink named `.gitmodules` could point anywhere.
+        let symlink = mode == 0o120000;
+        let valid = path.split('/').all(|name| {
+            let dotgit = is_special(name)
+                || symlink && name.eq_ignore_ascii_case(".gitmodules")
+                || self.hfs && (is_hfs_dot(name, "git") || symlink && is_hfs_dot(name, "gitmodules"))
+                || self.ntfs && (is_ntfs_dotgit(name) || symlink && is_ntfs_dot_generic(name, "gitmodules", "gi7eba"));
+            !dotgit && (!self.ntfs || is_valid_win32_name(name))
+        });
+        match valid {
+            true => Ok(()),
+            false => Err(anyhow!("invalid path '{}'", path)),
+        }
+    }
+
+    /// Like `verify` for the `entries` of the tree at `prefix`, whose names
+    /// must also be single path components, and distinct so that no
+    /// symlink is written where a directory of the same name is filled.
+    pub fn verify_entries(&self, prefix: &str, entries: &[TreeEntry]) -> anyhow::Result<()> {
+        let mut names = HashSet::new();
+        for TreeEntry { mode, name, .. } in entries {
+            let path = format!("{}{}", prefix, name);
+            if name.contains('/') || cfg!(windows) && name.contains('\\') {
+                return Err(anyhow!("invalid path '{}'", path));
+            }
+            self.verify(&path, *mode)?;
+            if !names.insert(if self.ignore_case { name.to_lowercase() } else { name.clone() }) {
+                return Err(anyhow!("More than one tree entry is named '{}'", path));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether `name` leaves its directory, is empty as in an absolute path,
+/// or is `.git` in any case.
+fn is_special(name: &str) -> bool {
+    name.is_empty() || name == "." || name == ".." || name.eq_ignore_ascii_case(".git")
+}
+
+/// Whether HFS+ opens `name` as `.<long>`: it ignores case and
+/// zero-width and direction marks.
+pub(crate) fn is_hfs_dot(name: &str, long: &str) -> bool {
+    let ignored = |c: &char| matches!(c, '\u{200c}'..='\u{200f}' | '\u{202a}'..='\u{202e}' | '\u{206a}'..='\u{206f}' | '\u{feff}');
+    let folded: String = name.chars().filter(|c| !ignored(c)).collect();
+    folded.strip_prefix('.').is_some_and(|rest| rest.eq_ignore_ascii_case(long))
+}
+
+/// Whether NTFS opens `name` as `.git`: `.git` or its short name `git~1`
+/// with trailing dots and spaces, which NTFS drops, or an alternate data
+/// stream after `:`.
+pub(crate) fn is_ntfs_dotgit(name: &str) -> bool {
+    let lower = name.to_ascii_lowercase();
+    let rest = lower.strip_prefix(".git").or_else(|| lower.strip_prefix("git~1"));
+    rest.is_some_and(only_spaces_and_periods)
+}
+
+/// Whether NTFS opens `name` as `.<long>`, by its own name or by an 8.3
+/// short name: the first six letters of `long` or the hashed `short`
+/// prefix, with `~` and a number.
+fn is_ntfs_dot_generic(name: &str, long: &str, short: &str) -> bool {
+    let bytes = name.as_bytes();
+    if bytes.first() == Some(&b'.')
+        && bytes.len() > long.len()
+        && bytes[1..=long.len()].eq_ignore_ascii_case(long.as_bytes())
+    {
+        return only_spaces_and_periods(&name[long.len() + 1..]);
+    }
+    if bytes.len() >= 8
+        && bytes[..6].eq_ignore_ascii_case(&long.as_bytes()[..6])
+        && bytes[6] == b'~'
+        && (b'1'..=b'4').contains(&bytes[7])
+    {
+        return only_spaces_and_periods(&name[8..]);
+    }
+
+    // A fall-back short name: up to six letters of `short`, `~`, and a
+    // number from 1 that fills the name out to eight characters.
+    let mut saw_tilde = false;
+    let mut i = 0;
+    while i < 8 {
+        let Some(&c) = bytes.get(i) else { return false };
+        if saw_tilde {
+            if !c.is_ascii_digit() {
+                return false;
+            }
+        } else if c == b'~' {
+            i += 1;
+            if !bytes.get(i).is_some_and(|c| (b'1'..=b'9').contains(c)) {
+                return false;
+            }
+            saw_tilde = true;
+        } else if i >= 6 || !c.is_ascii() || c.to_ascii_lowercase() != short.as_bytes()[i] {
+            return false;
+        }
+        i += 1;
+    }
+    only_spaces_and_periods(&name[8..])
+}
+
+/// Whether what follows a name is only what NTFS ignores.
+fn only_spaces_and_periods(rest: &str) -> bool {
+    rest.split(':').next().is_some_and(|rest| rest.bytes().all(|c| c == b' ' || c == b'.'))
+}
+
+/// Whether Windows can create a file called `name`: no reserved device
+/// name such as `CON` or `LPT1`, with or without an extension, none of
+/// `<>:"\|?*` or control characters, and no trailing dot or space.
+#[cfg(windows)]
+fn is_valid_win32_name(name: &str) -> bool {
+    const RESERVED: [&str; 6] = ["aux", "con", "nul", "prn", "conin$", "conout$"];
+
+    if name.is_empty() || name == "." || name == ".." {
+        return true;
+    }
+    if name.ends_with([' ', '.']) || name.chars().any(|c| c < ' ' || "<>:\"\\|?*".contains(c)) {
+        return false;
+    }
+    let stem = name.split('.').next().unwrap_or_default().trim_end_matches(' ').to_ascii_lowercase();
+    let numbered = |prefix: &str| {
+        stem.strip_prefix(prefix)
+            .is_some_and(|n| matches!(n, "1" | "2" | "3" | "4" | "5" | "6" | "7" | "8" | "9" | "¹" | "²" | "³"))
+    };
+    !(RESERVED.contains(&stem.as_str()) || numbered("com") || numbered("lpt"))
+}
+
+/// Outside Windows any name can be created.
+#[cfg(not(windows))]
+fn is_valid_win32_name(_name: &str) -> bool {
+    true
+}
@@ -0,0 +1,210 @@
+/// Matches `text` against a shell glob the way git's `wildmatch` does.
+///
+/// Supports `?`, `*`, `[...]` character classes (with `!`/`^` negation and
+/// ranges) and backslash escapes. With `pathname` set, wildcards don't
+/// match `/` and `**` between slashes matches any number of directories.
+pub(crate) fn wildmatch(pattern: &str, text: &str, pathname: bool) -> bool {
+    matches(pattern.as_bytes(), text.as_bytes(), pathname)
+}
+
+fn matches(pattern: &[u8], text: &[u8], pathname: bool) -> bool {
+    let (mut p, mut t) = (0, 0);
+
+    while p < pattern.len() {
+        match pattern[p] {
+            b'?' => {
+                if t >= text.len() || (pathname && text[t] == b'/') {
+                    return false;
+                }
+                p += 1;
+                t += 1;
+            }
+            b'*' => {
+                let mut end = p;
+                while end < pattern.len() && pattern[end] == b'*' {
+                    end += 1;
+                }
+                let double = end - p >= 2;
+                let rest = &pattern[end..];
+
+                if pathname && double {
+                    let at_start = p == 0 || pattern[p - 1] == b'/';
+                    let at_end = end == pattern.len() || pattern[end] == b'/';
+                    if at_start && at_end {
+                        if rest.is_empty() {
+                            return true;
+                        }
+                        // `**/` matches zero or more leading directories.
+                        let rest = &rest[1..];
+                        if matches(rest, &text[t..], pathname) {
+                            return true;
+                        }
+                        return (t..text.len())
+                            .any(|i| text[i] == b'/' && matches(rest, &text[i + 1..], pathname));
+                    }
+                }
+
+                // Anywhere else `**` is no different from `*`.
+                for i in t..=text.len() {
+                    if matches(rest, &text[i..], pathname) {
+                        return true;
+                    }
+                    if i < text.len() && pathname && text[i] == b'/' {
+                        break;
+                    }
+                }
+                return false;
+            }
+            b'[' => {
+                if t >= text.len() || (pathname && text[t] == b'/') {
+                    return false;
+                }
+                match match_class(&pattern[p..], text[t]) {
+                    Some((true, length)) => {
+                        p += length;
+                        t += 1;
+                    }
+                    Some((false, _)) => return false,
+                    // An unterminated class is matched literally.
+                    None => {
+                        if text[t] != b'[' {
+                            return false;
+                        }
+                        p += 1;
+                        t += 1;
+                    }
+                }
+            }
+            b'\\' if p + 1 < pattern.len() => {
+                if t >= text.len() || text[t] != pattern[p + 1] {
+                    return false;
+                }
+                p += 2;
+                t += 1;
+            }
+            c => {
+                if t >= text.len() || text[t] != c {
+                    return false;
+                }
+                p += 1;
+                t += 1;
+            }
+        }
+    }
+
+    t == text.len()
+}
+
+/// Matches `c` against the class at the start of `pattern`, returning
+/// whether it matched and the length of the class, or `None` if the class
+/// isn't terminated.
+fn match_class(pattern: &[u8], c: u8) -> Option<(bool, usize)> {
+    let mut i = 1;
+    let negated = matches!(pattern.get(i), Some(b'!') | Some(b'^'));
+    if negated {
+        i += 1;
+    }
+
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        let mut start = *pattern.get(i)?;
+        if start == b']' && !first {
+            break;
+        }
+        first = false;
+
+        if start == b'\\' {
+            i += 1;
+            start = *pattern.get(i)?;
+        }
+
+        if pattern.get(i + 1) == Some(&b'-') && pattern.get(i + 2).is_some_and(|&end| end != b']') {
+            let mut end = pattern[i + 2];
+            i += 2;
+            if end == b'\\' {
+                i += 1;
+                end = *pattern.get(i)?;
+            }
+            matched |= start <= c && c <= end;
+        } else {
+            matched |= start == c;
+        }
+        i += 1;
+    }
+
+    Some((matched != negated, i + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn literals_and_wildcards() {
+        assert!(wildmatch("foo", "foo", false));
+        assert!(!wildmatch("foo", "bar", false));
+        assert!(wildmatch("", "", false));
+        assert!(wildmatch("???", "foo", false));
+        assert!(!wildmatch("??", "foo", false));
+        assert!(wildmatch("*", "foo", false));
+        assert!(wildmatch("f*", "foo", false));
+        assert!(!wildmatch("*f", "foo", false));
+        assert!(wildmatch("*foo*", "foo", false));
+        assert!(wildmatch("*ob*a*r*", "foobar", false));
+        assert!(wildmatch("*ab", "aaaaaaabababab", false));
+    }
+
+    #[test]
+    fn escapes() {
+        assert!(wildmatch("foo\\*", "foo*", false));
+        assert!(!wildmatch("foo\\*bar", "foobar", false));
+        assert!(wildmatch("f\\\\oo", "f\\oo", false));
+    }
+
+    #[test]
+    fn classes() {
+        assert!(wildmatch("*[al]?", "ball", false));
+        assert!(!wildmatch("[ten]", "ten", false));
+        assert!(wildmatch("**[!te]", "ten", false));
+        assert!(!wildmatch("**[!ten]", "ten", false));
+        assert!(wildmatch("t[a-g]n", "ten", false));
+        assert!(!wildmatch("t[!a-g]n", "ten", false));
+        assert!(wildmatch("t[!a-g]n", "ton", false));
+        assert!(wildmatch("t[^a-g]n", "ton", false));
+        // A `]` first in the class, or before the closing one, is literal.
+        assert!(wildmatch("a[]]b", "a]b", false));
+        assert!(wildmatch("a[]-]b", "a-b", false));
+        assert!(wildmatch("a[]-]b", "a]b", false));
+        assert!(!wildmatch("a[]-]b", "aab", false));
+        assert!(wildmatch("a[]a-]b", "aab", false));
+        assert!(!wildmatch("[!]-]", "]", false));
+        assert!(wildmatch("[!]-]", "a", false));
+        // An unterminated class matches itself.
+        assert!(wildmatch("[ab", "[ab", false));
+    }
+
+    #[test]
+    fn pathnames() {
+        assert!(wildmatch("foo*bar", "foo/baz/bar", false));
+        assert!(!wildmatch("foo*bar", "foo/baz/bar", true));
+        assert!(!wildmatch("foo?bar", "foo/bar", true));
+        assert!(!wildmatch("foo[/]bar", "foo/bar", true));
+        assert!(!wildmatch("foo[^a-z]bar", "foo/bar", true));
+        assert!(wildmatch("foo/*", "foo/bar", true));
+        assert!(!wildmatch("foo/*", "foo/bar/baz", true));
+    }
+
+    #[test]
+    fn double_stars() {
+        assert!(wildmatch("**/foo", "foo", true));
+        assert!(wildmatch("**/foo", "x/y/foo", true));
+        assert!(!wildmatch("**/foo", "x/yfoo", true));
+        assert!(wildmatch("foo/**", "foo/a/b", true));
+        assert!(wildmatch("a/**/b", "a/b", true));
+        assert!(wildmatch("a/**/b", "a/x/y/b", true));
+        assert!(!wildmatch("a/**/b", "a/xb", true));
+        // Not between slashes, `**` is just `*`.
+        assert!(!wildmatch("a**b", "a/x/b", true));
+    }
+}
@@ -0,0 +1,792 @@
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+
+use anyhow::anyhow;
+
+use crate::attributes::Attributes;
+use crate::config::Config;
+use crate::convert::Convert;
+use crate::error::Error;
+use crate::ignore::Ignore;
+use crate::index::{CacheTree, Index, IndexEntry, ASSUME_VALID, SKIP_WORKTREE};
+use crate::object_id::ObjectId;
+use crate::objects::{peel_to_tree, read_object, read_tree, tree_files, write_object, Commit, TreeEntry};
+use crate::repository::{self, common_dir, git_dir, repo_config};
+use crate::profile;
+use crate::progress::Progress;
+use crate::sparse::{self, Sparse};
+use crate::verify_path::Protection;
+use crate::{hooks, ls_files, refs};
+
+/// What the HEAD of a worktree points at.
+enum Head {
+    Branch(String),
+    Detached,
+}
+
+struct Worktree {
+    path: PathBuf,
+    head: Option<ObjectId>,
+    branch: Option<String>,
+    bare: bool,
+    locked: bool,
+}
+
+pub fn add(
+    path: &Path,
+    commitish: Option<&str>,
+    new_branch: Option<&str>,
+    detach: bool,
+    force: bool,
+    quiet: bool,
+    progress: bool,
+) -> anyhow::Result<()> {
+    if path.exists() && fs::read_dir(path)?.next().is_some() {
+        return Err(anyhow!("'{}' already exists", path.display()));
+    }
+
+    let mut create_branch = None;
+    let (head, base) = match (new_branch, commitish) {
+        (Some(branch), _) => {
+            if refs::read_ref(&format!("refs/heads/{}", branch))?.is_some() && !force {
+                return Err(anyhow!("A branch named '{}' already exists", branch));
+            }
+            create_branch = Some(branch.to_string());
+            (Head::Branch(branch.to_string()), refs::resolve_revision(commitish.unwrap_or("HEAD"))?)
+        }
+        (None, Some(commitish)) if !detach => {
+            match refs::read_ref(&format!("refs/heads/{}", commitish))? {
+                Some(sha) => (Head::Branch(commitish.to_string()), sha),
+                None => (Head::Detached, refs::resolve_revision(commitish)?),
+            }
+        }
+        (None, Some(commitish)) => (Head::Detached, refs::resolve_revision(commitish)?),
+        (None, None) if detach => (Head::Detached, refs::resolve_revision("HEAD")?),
+        (None, None) => {
+            // Like git, default to a branch named after the new directory.
+            let branch = path.file_name()
+                .and_then(|name| name.to_str())
+                .ok_or(anyhow!("Invalid worktree path: {}", path.display()))?
+                .to_string();
+            match refs::read_ref(&format!("refs/heads/{}", branch))? {
+                Some(sha) => (Head::Branch(branch), sha),
+                None => {
+                    create_branch = Some(branch.clone());
+                    (Head::Branch(branch), refs::resolve_revision("HEAD")?)
+                }
+            }
+        }
+    };
+
+    if let Some(branch) = &create_branch {
+        if !refs::is_valid_ref_name(&format!("refs/heads/{}", branch), false, false) {
+            return Err(anyhow!("'{}' is not a valid branch name", branch));
+        }
+    }
+    if let (Head::Branch(branch), None) = (&head, &create_branch) {
+        if !force {
+            ensure_not_checked_out(branch)?;
+        }
+    }
+
+    let (commit, tree) = peel_to_tree(&base)?;
+    verify_tree(&tree, "", &Protection::load(&repo_config()?)?)?;
+
+    let common_dir = fs::canonicalize(common_dir()?)?;
+    let worktrees_dir = common_dir.join("worktrees");
+    let name = unique_name(&worktrees_dir, path)?;
+    let worktree_git_dir = worktrees_dir.join(&name);
+    fs::create_dir_all(&worktree_git_dir)?;
+
+    // Keep the worktree locked while it is being populated so that a
+    // half-created worktree can't be pruned or removed from under us.
+    let lock_file = worktree_git_dir.join("locked");
+    fs::write(&lock_file, "initializing\n")?;
+
+    fs::create_dir_all(path)?;
+    let path = fs::canonicalize(path)?;
+    fs::write(path.join(".git"), format!("gitdir: {}\n", worktree_git_dir.display()))?;
+    fs::write(worktree_git_dir.join("gitdir"), format!("{}\n", path.join(".git").display()))?;
+    fs::write(worktree_git_dir.join("commondir"), "../..\n")?;
+
+    if let Some(branch) = &create_branch {
+        refs::write_ref(&format!("refs/heads/{}", branch), &commit)?;
+    }
+    match &head {
+        Head::Branch(branch) => {
+            if !quiet {
+                eprintln!("Preparing worktree ({} '{}')",
+                          if create_branch.is_some() { "new branch" } else { "checking out" }, branch);
+            }
+            refs::write_head(&worktree_git_dir, &format!("ref: refs/heads/{}", branch))?;
+        }
+        Head::Detached => {
+            if !quiet {
+                eprintln!("Preparing worktree (detached HEAD {})", &commit.to_string()[..7]);
+            }
+            refs::write_head(&worktree_git_dir, &commit.to_string())?;
+        }
+    }
+
+    let attributes = Attributes::load(&common_dir)?;
+    let convert = Convert::from_config(&repo_config()?)?;
+    // Like git, a new worktree starts with the sparse patterns of the one
+    // it was created from.
+    let sparse = sparse::copy_patterns(&git_dir()?, &worktree_git_dir)?;
+    let mut index = Index::default();
+    let progress = Progress::new("Updating files", tree_files(&tree)?.len(), progress);
+    let kept = HashMap::new();
+    let workers = workers(&repo_config()?)?;
+    let checkout = Checkout { convert: &convert, sparse: sparse.as_ref(), progress: &progress, kept: &kept, workers };
+    index.cache_tree = Some(checkout_files(&tree, &path, &mut index, attributes, &checkout)?);
+    progress.finish();
+    index.write(&worktree_git_dir.join("index"), commit.algorithm())?;
+
+    fs::remove_file(lock_file)?;
+    if !quiet {
+        println!("HEAD is now at {} {}", &commit.to_string()[..7], commit_subject(&commit)?);
+    }
+
+    // Like for a fresh clone, the previous HEAD is the null SHA and the
+    // third argument flags a branch checkout.
+    hooks::run_checked(
+        "post-checkout",
+        &[&commit.algorithm().null().to_string(), &commit.to_string(), "1"],
+        None,
+        &path,
+        &worktree_git_dir,
+    )?;
+
+    Ok(())
+}
+
+pub fn list() -> anyhow::Result<()> {
+    let worktrees = worktrees()?;
+    // Like git, leave at least two spaces between the path and the SHA.
+    let width = worktrees.iter()
+        .map(|worktree| worktree.path.display().to_string().len() + 1)
+        .max()
+        .unwrap_or(0);
+
+    for worktree in worktrees {
+        let path = worktree.path.display().to_string();
+        if worktree.bare {
+            println!("{:width$} (bare)", path, width = width);
+            continue;
+        }
+
+        let sha = worktree.head
+            .map(|sha| sha.to_string()[..7].to_string())
+            .unwrap_or_else(|| "0000000".to_string());
+        let branch = match &worktree.branch {
+            Some(branch) => format!("[{}]", branch),
+            None => "(detached HEAD)".to_string(),
+        };
+        let locked = if worktree.locked { " locked" } else { "" };
+        let prunable = if worktree.path.exists() { "" } else { " prunable" };
+
+        println!("{:width$} {} {}{}{}", path, sha, branch, locked, prunable, width = width);
+    }
+
+    Ok(())
+}
+
+pub fn remove(worktree: &str, force: u8) -> anyhow::Result<()> {
+    let worktrees_dir = common_dir()?.join("worktrees");
+    let target = fs::canonicalize(worktree).ok();
+
+    let name = fs::read_dir(&worktrees_dir)
+        .map(|entries| entries.filter_map(Result::ok).collect::<Vec<_>>())
+        .unwrap_or_default()
+        .into_iter()
+        .find(|entry| {
+            let Ok(path) = linked_worktree_path(&entry.path()) else {
+                return false;
+            };
+            entry.file_name().to_str() == Some(worktree)
+                || target.as_ref().is_some_and(|target| fs::canonicalize(&path).ok().as_ref() == Some(target))
+        })
+        .map(|entry| entry.file_name())
+        .ok_or(anyhow!("'{}' is not a working tree", worktree))?;
+
+    let worktree_git_dir = worktrees_dir.join(&name);
+    let path = linked_worktree_path(&worktree_git_dir)?;
+
+    let lock_file = worktree_git_dir.join("locked");
+    if lock_file.is_file() && force < 2 {
+        let reason = fs::read_to_string(&lock_file)?;
+        let reason = reason.trim();
+        return Err(anyhow!(
+            "Cannot remove a locked working tree{}; use 'remove -f -f' to override or unlock first",
+            if reason.is_empty() { String::new() } else { format!(", lock reason: {}", reason) }
+        ));
+    }
+
+    if force == 0 && path.exists() {
+        let head = refs::raw_ref_in(&worktree_git_dir, "HEAD")?.unwrap_or_default();
+        let head = match head.strip_prefix("ref:") {
+            Some(branch) => refs::read_ref(branch.trim())?,
+            None => Some(head.parse()?),
+        };
+        let config = repo_config()?;
+        let modified = match head {
+            Some(head) => {
+                let (_, tree) = peel_to_tree(&head)?;
+                let attributes = Attributes::load(&common_dir()?)?;
+                is_modified(&tree, &path, "", &attributes, &Convert::from_config(&config)?)?
+            }
+            None => false,
+        };
+        // Ignored files don't count, as in git.
+        let index = Index::read(&worktree_git_dir.join("index"), repository::current()?.object_format())?;
+        let ignore = Ignore::load(&common_dir()?, &config);
+        if modified || !ls_files::untracked_files(&path, &index, &config, Some(ignore))?.is_empty() {
+            return Err(anyhow!("'{}' contains modified or untracked files, use --force to delete it", path.display()));
+        }
+    }
+
+    if path.exists() {
+        fs::remove_dir_all(&path)?;
+    }
+    fs::remove_dir_all(&worktree_git_dir)?;
+
+    Ok(())
+}
+
+/// Replaces the files of the current worktree with those of `commit` and
+/// points HEAD at `branch`, or detaches it. Refuses to overwrite local
+/// changes or untracked files.
+pub(crate) fn checkout(commit: &ObjectId, branch: Option<&str>) -> anyhow::Result<()> {
+    let repository = repository::current()?;
+    let git_dir = repository.git_dir();
+    let (commit, tree) = peel_to_tree(commit)?;
+    let index_path = git_dir.join("index");
+    let old_index = Index::read(&index_path, repository.object_format())?;
+    let config = repository.config()?;
+    let convert = Convert::from_config(&config)?;
+
+    verify_tree(&tree, "", &Protection::load(&config)?)?;
+    let target = tree_files(&tree)?;
+
+    let mut modified = Vec::new();
+    for entry in &old_index.entries {
+        // Assume-unchanged files are taken to be clean without looking.
+        if entry.extended_flags & SKIP_WORKTREE != 0
+            || entry.flags & ASSUME_VALID != 0
+            || target.get(&entry.path) == Some(&(entry.mode, entry.sha)) {
+            continue;
+        }
+        let file = Path::new(".").join(&entry.path);
+        let directory = entry.path.rsplit_once('/').map(|(directory, _)| directory).unwrap_or("");
+        let attributes = Attributes::load_for(git_dir, directory)?;
+        if fs::symlink_metadata(&file).is_ok()
+            && is_entry_modified(&file, &entry.path, entry.mode, &entry.sha, &attributes, &convert)? {
+            modified.push(entry.path.as_str());
+        }
+    }
+    if !modified.is_empty() {
+        return Err(anyhow!(
+            "Your local changes to the following files would be overwritten by checkout:\n\t{}\n\
+             Please commit your changes or stash them before you switch branches.",
+            modified.join("\n\t")
+        ));
+    }
+
+    let tracked: HashSet<&str> = old_index.entries.iter().map(|entry| entry.path.as_str()).collect();
+    let untracked: Vec<&str> = target.keys()
+        .map(String::as_str)
+        .filter(|path| !tracked.contains(path) && fs::symlink_metadata(path).is_ok())
+        .collect();
+    if !untracked.is_empty() {
+        return Err(anyhow!(
+            "The following untracked working tree files would be overwritten by checkout:\n\t{}\n\
+             Please move or remove them before you switch branches.",
+            untracked.join("\n\t")
+        ));
+    }
+
+    for entry in &old_index.entries {
+        let file = Path::new(".").join(&entry.path);
+        if target.contains_key(&entry.path) || fs::symlink_metadata(&file).is_err() {
+            continue;
+        }
+        if entry.mode == 0o160000 {
+            fs::remove_dir(&file)?;
+        } else {
+            fs::remove_file(&file)?;
+        }
+        sparse::remove_empty_parents(&file);
+    }
+
+    let attributes = Attributes::load(git_dir)?;
+    let sparse = sparse::load(git_dir)?;
+    let progress = Progress::new("Updating files", target.len(), false);
+    // Files marked assume-unchanged, or skip-worktree outside a sparse
+    // checkout, keep their contents and flags if the target doesn't
+    // change them.
+    let kept: HashMap<&str, &IndexEntry> = old_index.entries.iter()
+        .filter(|entry| {
+            entry.flags & ASSUME_VALID != 0 || (sparse.is_none() && entry.extended_flags & SKIP_WORKTREE != 0)
+        })
+        .filter(|entry| target.get(&entry.path) == Some(&(entry.mode, entry.sha)))
+        .map(|entry| (entry.path.as_str(), entry))
+        .collect();
+    let workers = workers(&config)?;
+    let checkout = Checkout { convert: &convert, sparse: sparse.as_ref(), progress: &progress, kept: &kept, workers };
+    let mut index = Index::default();
+    index.cache_tree = Some(checkout_files(&tree, Path::new("."), &mut index, attributes, &checkout)?);
+    index.write(&index_path, repository.object_format())?;
+
+    let head = match branch {
+        Some(branch) => format!("ref: refs/heads/{}", branch),
+        None => commit.to_string(),
+    };
+    refs::write_head(git_dir, &head)
+}
+
+/// Copies `paths`, or every file if `all`, from the index to the
+/// worktree, or below `prefix` if given. Files that differ from the index
+/// are only overwritten if `force`.
+pub fn checkout_index(paths: &[String], all: bool, prefix: &str, force: bool) -> anyhow::Result<()> {
+    let repository = repository::current()?;
+    let git_dir = repository.git_dir();
+    let index = Index::read(&git_dir.join("index"), repository.object_format())?;
+    let config = repository.config()?;
+    let convert = Convert::from_config(&config)?;
+    let protection = Protection::load(&config)?;
+
+    let mut entries = Vec::new();
+    let mut failed = false;
+    if all {
+        entries.extend(index.entries.iter().filter(|entry| entry.extended_flags & SKIP_WORKTREE == 0));
+    }
+    for path in paths {
+        match index.entries.iter().find(|entry| entry.path == *path) {
+            Some(entry) => entries.push(entry),
+            None => {
+                eprintln!("git checkout-index: {} is not in the cache", path);
+                failed = true;
+            }
+        }
+    }
+
+    for entry in entries {
+        if let Err(err) = protection.verify(&entry.path, entry.mode) {
+            eprintln!("error: {}", err);
+            failed = true;
+            continue;
+        }
+        let file = PathBuf::from(format!("{}{}", prefix, entry.path));
+        let directory = entry.path.rsplit_once('/').map(|(directory, _)| directory).unwrap_or("");
+        let attributes = Attributes::load_for(git_dir, directory)?;
+
+        if !force && fs::symlink_metadata(&file).is_ok() {
+            if is_entry_modified(&file, &entry.path, entry.mode, &entry.sha, &attributes, &convert)? {
+                eprintln!("{} already exists, no checkout", file.display());
+                failed = true;
+            }
+            continue;
+        }
+
+        if let Some(parent) = file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        if fs::symlink_metadata(&file).is_ok_and(|metadata| !metadata.is_dir()) {
+            fs::remove_file(&file)?;
+        }
+        write_entry(&file, &entry.path, entry.mode, &entry.sha, &attributes, &convert)?;
+    }
+
+    if failed {
+        return Err(Error::Status(1).into());
+    }
+    Ok(())
+}
+
+/// What stays the same while checking out every directory of a tree.
+struct Checkout<'a> {
+    convert: &'a Convert,
+    sparse: Option<&'a Sparse>,
+    progress: &'a Progress,
+    /// Index entries to carry over without touching their files.
+    kept: &'a HashMap<&'a str, &'a IndexEntry>,
+    /// How many threads write files, and the fewest files to start them
+    /// for, see [`workers`].
+    workers: (usize, usize),
+}
+
+/// `checkout.workers` and `checkout.thresholdForParallelism`: how many
+/// threads write files, one per CPU unless set, where values below one
+/// also mean one per CPU, and the fewest files worth starting them for,
+/// 100 by default.
+fn workers(config: &Config) -> anyhow::Result<(usize, usize)> {
+    let get = |name: &str, key: &str, default: i64| match config.get(key) {
+        Some(value) => value.parse::<i64>().map_err(|_| anyhow!("Bad {} value: {}", name, value)),
+        None => Ok(default),
+    };
+    let workers = match get("checkout.workers", "checkout.workers", 0)? {
+        workers if workers < 1 => std::thread::available_parallelism().map_or(1, usize::from),
+        workers => workers as usize,
+    };
+    let threshold = get("checkout.thresholdForParallelism", "checkout.thresholdforparallelism", 100)?;
+    Ok((workers, threshold.max(0) as usize))
+}
+
+/// A file found while walking the tree, to be written once the walk is
+/// done, into the index entry at `position`.
+struct Pending {
+    file: PathBuf,
+    path: String,
+    mode: u32,
+    sha: ObjectId,
+    attributes: Arc<Attributes>,
+    position: usize,
+}
+
+/// Checks every entry below `tree`, whose path is `prefix`, before any
+/// file is checked out.
+fn verify_tree(tree: &ObjectId, prefix: &str, protection: &Protection) -> anyhow::Result<()> {
+    let entries = read_tree(tree)?.entries;
+    protection.verify_entries(prefix, &entries)?;
+    for entry in entries.iter().filter(|entry| entry.mode == 0o40000) {
+        verify_tree(&entry.sha, &format!("{}{}/", prefix, entry.name), protection)?;
+    }
+    Ok(())
+}
+
+/// Writes the contents of `tree` below `dir`, recording every file in
+/// `index`. Directories are created while walking the tree and files
+/// written afterwards, on several threads if there are enough of them.
+fn checkout_files(
+    tree: &ObjectId,
+    dir: &Path,
+    index: &mut Index,
+    attributes: Attributes,
+    checkout: &Checkout,
+) -> anyhow::Result<CacheTree> {
+    let _phase = profile::phase("checkout");
+    let mut pending = Vec::new();
+    let cache_tree = checkout_tree(tree, dir, "", index, &Arc::new(attributes), checkout, &mut pending)?;
+
+    let workers = match pending.len() < checkout.workers.1 {
+        true => 1,
+        false => checkout.workers.0.clamp(1, pending.len().max(1)),
+    };
+    if workers == 1 {
+        for pending in pending {
+            index.entries[pending.position] = write_pending(&pending, checkout.convert)?;
+            checkout.progress.tick();
+        }
+        return Ok(cache_tree);
+    }
+
+    let next = AtomicUsize::new(0);
+    let (sender, receiver) = mpsc::channel();
+    std::thread::scope(|scope| {
+        for _ in 0..workers {
+            let sender = sender.clone();
+            let (pending, next, convert) = (&pending, &next, checkout.convert);
+            scope.spawn(move || {
+                // Threads take the next file until none are left.
+                while let Some(pending) = pending.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    if sender.send((pending.position, write_pending(pending, convert))).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(sender);
+        for (position, entry) in receiver {
+            match entry {
+                Ok(entry) => index.entries[position] = entry,
+                Err(err) => {
+                    // Stop the others from starting on more files.
+                    next.store(pending.len(), Ordering::Relaxed);
+                    return Err(err);
+                }
+            }
+            checkout.progress.tick();
+        }
+        Ok(())
+    })?;
+    Ok(cache_tree)
+}
+
+fn write_pending(pending: &Pending, convert: &Convert) -> anyhow::Result<IndexEntry> {
+    let Pending { file, path, mode, sha, attributes, .. } = pending;
+    write_entry(file, path, *mode, sha, attributes, convert)?;
+    IndexEntry::from_file(file, path.clone(), *mode, *sha)
+}
+
+/// Walks `tree`, whose path is `prefix`, creating its directories below
+/// `dir`, and records every file in `index`, leaving those to write in
+/// `pending`.
+fn checkout_tree(
+    tree: &ObjectId,
+    dir: &Path,
+    prefix: &str,
+    index: &mut Index,
+    attributes: &Arc<Attributes>,
+    checkout: &Checkout,
+    pending: &mut Vec<Pending>,
+) -> anyhow::Result<CacheTree> {
+    let entries = read_tree(tree)?.entries;
+    let attributes = match with_tree_attributes(attributes, &entries, prefix)? {
+        Cow::Borrowed(_) => attributes.clone(),
+        Cow::Owned(attributes) => Arc::new(attributes),
+    };
+
+    // The checked out tree is exactly what the index holds, so record it
+    // in the cache tree to save the next write-tree from rehashing it.
+    let mut entry_count = 0;
+    let mut subtrees = Vec::new();
+
+    for TreeEntry { mode, name, sha } in entries {
+        let file = dir.join(&name);
+        let path = format!("{}{}", prefix, name);
+
+        if mode == 0o40000 {
+            let subtree = checkout_tree(&sha, &file, &format!("{}/", path), index, &attributes, checkout, pending)?;
+            entry_count += subtree.entry_count.unwrap_or(0);
+            subtrees.push(subtree);
+            continue;
+        }
+
+        entry_count += 1;
+        if let Some(entry) = checkout.kept.get(path.as_str()) {
+            index.entries.push((*entry).clone());
+        } else if checkout.sparse.is_some_and(|sparse| !sparse.includes(&path)) {
+            // Paths outside the sparse checkout are only recorded in the index.
+            let mut entry = IndexEntry::new(path, mode, sha);
+            entry.extended_flags |= SKIP_WORKTREE;
+            index.entries.push(entry);
+        } else {
+            fs::create_dir_all(dir)?;
+            let position = index.entries.len();
+            index.entries.push(IndexEntry::new(path.clone(), mode, sha));
+            pending.push(Pending { file, path, mode, sha, attributes: attributes.clone(), position });
+            continue;
+        }
+        checkout.progress.tick();
+    }
+
+    let name = prefix.trim_end_matches('/').rsplit('/').next().unwrap_or("").to_string();
+    Ok(CacheTree { name, entry_count: Some(entry_count), sha: Some(*tree), subtrees })
+}
+
+/// Writes the blob, symlink or submodule directory recorded for `path`
+/// to `file`.
+pub(crate) fn write_entry(
+    file: &Path,
+    path: &str,
+    mode: u32,
+    sha: &ObjectId,
+    attributes: &Attributes,
+    convert: &Convert,
+) -> anyhow::Result<()> {
+    match mode {
+        // Submodules are checked out as empty directories.
+        0o160000 => fs::create_dir_all(file)?,
+        0o120000 => {
+            let (_, target) = read_object(sha)?;
+            write_symlink(file, &target)?;
+        }
+        _ => {
+            let (_, content) = read_object(sha)?;
+            fs::write(file, convert.to_worktree(path, attributes, &content)?)?;
+            profile::FILES_WRITTEN.add(1);
+            set_executable(file, mode == 0o100755)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks whether the file at `file` differs from what is recorded for
+/// `path`, or is missing.
+pub(crate) fn is_entry_modified(
+    file: &Path,
+    path: &str,
+    mode: u32,
+    sha: &ObjectId,
+    attributes: &Attributes,
+    convert: &Convert,
+) -> anyhow::Result<bool> {
+    Ok(match mode {
+        0o160000 => !file.is_dir(),
+        0o120000 => match fs::read_link(file) {
+            Ok(target) => write_object("blob", target.to_string_lossy().as_bytes(), false)? != *sha,
+            Err(_) => true,
+        },
+        _ => match fs::read(file) {
+            Ok(content) => write_object("blob", &convert.to_git(path, attributes, &content)?, false)? != *sha,
+            Err(_) => true,
+        },
+    })
+}
+
+#[cfg(unix)]
+fn write_symlink(file: &Path, target: &[u8]) -> anyhow::Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+
+    std::os::unix::fs::symlink(std::ffi::OsStr::from_bytes(target), file)?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn write_symlink(file: &Path, target: &[u8]) -> anyhow::Result<()> {
+    // Without symlink support git writes the link target as a plain file.
+    fs::write(file, target)?;
+    Ok(())
+}
+
+#[cfg(unix)]
+fn set_executable(file: &Path, executable: bool) -> anyhow::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    if executable {
+        fs::set_permissions(file, fs::Permissions::from_mode(0o755))?;
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn set_executable(_file: &Path, _executable: bool) -> anyhow::Result<()> {
+    Ok(())
+}
+
+/// Adds the rules of the `.gitattributes` blob in a tree's `entries`, if
+/// there is one.
+fn with_tree_attributes<'a>(
+    attributes: &'a Attributes,
+    entries: &[TreeEntry],
+    prefix: &str,
+) -> anyhow::Result<Cow<'a, Attributes>> {
+    let mut attributes = Cow::Borrowed(attributes);
+    if let Some(entry) = entries.iter().find(|entry| entry.name == ".gitattributes" && entry.mode != 0o40000) {
+        let (_, content) = read_object(&entry.sha)?;
+        attributes.to_mut().push_file(prefix, &String::from_utf8_lossy(&content));
+    }
+
+    Ok(attributes)
+}
+
+/// Checks whether any file recorded in `tree` was changed or deleted.
+fn is_modified(tree: &ObjectId, dir: &Path, prefix: &str, attributes: &Attributes, convert: &Convert) -> anyhow::Result<bool> {
+    let entries = read_tree(tree)?.entries;
+    let attributes = with_tree_attributes(attributes, &entries, prefix)?;
+
+    for TreeEntry { mode, name, sha } in entries {
+        let file = dir.join(&name);
+        let path = format!("{}{}", prefix, name);
+        let modified = match mode {
+            0o40000 => is_modified(&sha, &file, &format!("{}/", path), &attributes, convert)?,
+            _ => is_entry_modified(&file, &path, mode, &sha, &attributes, convert)?,
+        };
+
+        if modified {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+fn commit_subject(commit: &ObjectId) -> anyhow::Result<String> {
+    let (_, data) = read_object(commit)?;
+    Ok(Commit::parse(&data)?.subject())
+}
+
+fn ensure_not_checked_out(branch: &str) -> anyhow::Result<()> {
+    for worktree in worktrees()? {
+        if worktree.branch.as_deref() == Some(branch) {
+            return Err(anyhow!("'{}' is already checked out at '{}'", branch, worktree.path.display()));
+        }
+    }
+
+    Ok(())
+}
+
+/// Picks the admin directory name for a new worktree: the basename of its
+/// path, with a numeric suffix if that name is taken.
+fn unique_name(worktrees_dir: &Path, path: &Path) -> anyhow::Result<String> {
+    let base = path.file_name()
+        .and_then(|name| name.to_str())
+        .ok_or(anyhow!("Invalid worktree path: {}", path.display()))?;
+
+    let mut name = base.to_string();
+    let mut counter = 1;
+    while worktrees_dir.join(&name).exists() {
+        name = format!("{}{}", base, counter);
+        counter += 1;
+    }
+
+    Ok(name)
+}
+
+fn linked_worktree_path(worktree_git_dir: &Path) -> anyhow::Result<PathBuf> {
+    let gitdir = fs::read_to_string(worktree_git_dir.join("gitdir"))?;
+    let gitdir = PathBuf::from(gitdir.trim_end());
+
+    Ok(gitdir.parent().map(Path::to_path_buf).unwrap_or(gitdir))
+}
+
+/// The commit HEAD of the worktree whose git directory is `git_dir` is
+/// at, and the branch it is on unless detached.
+fn read_head(git_dir: &Path) -> anyhow::Result<(Option<ObjectId>, Option<String>)> {
+    let head = refs::raw_ref_in(git_dir, "HEAD")?.ok_or(anyhow!("No HEAD in {}", git_dir.display()))?;
+    let head = head.as_str();
+
+    match head.strip_prefix("ref:") {
+        Some(target) => {
+            let target = target.trim();
+            let branch = target.strip_prefix("refs/heads/").unwrap_or(target);
+            Ok((refs::read_ref(target)?, Some(branch.to_string())))
+        }
+        None => Ok((Some(head.parse()?), None)),
+    }
+}
+
+/// Lists the main worktree followed by all linked worktrees.
+fn worktrees() -> anyhow::Result<Vec<Worktree>> {
+    let common_dir = fs::canonicalize(common_dir()?)?;
+    let config = Config::from_file(&common_dir.join("config"))?;
+    let bare = config.get("core.bare") == Some("true");
+
+    let (head, branch) = read_head(&common_dir)?;
+    let mut worktrees = vec![Worktree {
+        path: if bare { common_dir.clone() } else { common_dir.parent().unwrap_or(&common_dir).to_path_buf() },
+        head,
+        branch,
+        bare,
+        locked: false,
+    }];
+
+    let Ok(entries) = fs::read_dir(common_dir.join("worktrees")) else {
+        return Ok(worktrees);
+    };
+    let mut entries: Vec<_> = entries.collect::<Result<_, _>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        let worktree_git_dir = entry.path();
+        let Ok(path) = linked_worktree_path(&worktree_git_dir) else {
+            continue;
+        };
+        let (head, branch) = read_head(&worktree_git_dir)?;
+
+        worktrees.push(Worktree {
+            path,
+            head,
+            branch,
+            bare: false,
+            locked: worktree_git_dir.join("locked").is_file(),
+        });
+    }
+
+    Ok(worktrees)
+}